@@ -17,32 +17,86 @@
  * @typedef {typeof Utilities} Utilities
  * @typedef {typeof ScriptApp} ScriptApp
  * @typedef {typeof Session} Session
+ * @typedef {typeof CacheService} CacheService
+ * @typedef {typeof LockService} LockService
  */
 
+/**
+ * Refuses to overwrite cells holding a formula, unless the service sent overwriteFormulas
+ * @param {GoogleAppsScript.Spreadsheet.Range} range - The cells about to be written or cleared
+ * @param {boolean} [overwriteFormulas] - [google_sheets] overwrite_formulas from the service config
+ *
+ * A mis-typed cell_address otherwise replaces a dashboard formula with a number without a trace.
+ */
+function GUARD_FORMULAS(range, overwriteFormulas) {
+    if (overwriteFormulas) {
+        return;
+    }
+    const formulas = range.getFormulas();
+    for (let r = 0; r < formulas.length; r++) {
+        for (let c = 0; c < formulas[r].length; c++) {
+            if (formulas[r][c]) {
+                const cell = range.getCell(r + 1, c + 1).getA1Notation();
+                const message = `${range.getSheet().getName()}!${cell} contains the formula ${formulas[r][c]}; not overwriting it (set overwrite_formulas = true to allow)`;
+                console.warn(`[GUARD_FORMULAS] ${message}`);
+                const error = new Error(message);
+                error.name = 'FormulaProtected';
+                throw error;
+            }
+        }
+    }
+}
+
+/**
+ * The cell a single-cell write goes to: cellAddress on the sheet, or for "named:MonthlyCash" the
+ * top-left cell of that named range, wherever the dashboard has moved it
+ * @param {GoogleAppsScript.Spreadsheet.Spreadsheet} spreadsheet - The spreadsheet being written
+ * @param {GoogleAppsScript.Spreadsheet.Sheet} sheet - The sheet from sheetName (unused for named ranges)
+ * @param {string} cellAddress - "B2", or "named:" and a range name
+ * @return {GoogleAppsScript.Spreadsheet.Range} The cell
+ */
+function TARGET_CELL(spreadsheet, sheet, cellAddress) {
+    const named = /^named:\s*(.+)$/i.exec(String(cellAddress).trim());
+    if (named) {
+        const range = spreadsheet.getRangeByName(named[1].trim());
+        if (!range) {
+            throw new Error(`Named range "${named[1].trim()}" not found.`);
+        }
+        return range.getCell(1, 1);
+    }
+    return sheet.getRange(cellAddress);
+}
+
 /**
  * Updates a specific cell with a float
  * @param {string} spreadsheetId - The spreadsheet ID (optional, uses active if not provided)
  * @param {string} sheetName - The name of the sheet (optional)
- * @param {string} cellAddress - The cell address (e.g., "A1", "B2")
+ * @param {string} cellAddress - The cell address (e.g., "A1", "B2"), or "named:" and a range name
  * @param {float} [floatValue] - Float to assign to cell)
+ * @param {boolean} [overwriteFormulas] - Write even when the cell holds a formula
+ * @param {string} [note] - Also set the cell's note, e.g. where the value came from
  * @return {string} Success message
  * @customfunction
  *
  * Note: Account selection is handled by the Windows service, not this script.
  */
-function UPDATE_SHEET_CELL_FLOAT(spreadsheetId, sheetName, cellAddress, floatValue) {
+function UPDATE_SHEET_CELL_FLOAT(spreadsheetId, sheetName, cellAddress, floatValue, overwriteFormulas, note) {
     try {
         const spreadsheet = spreadsheetId ?
             SpreadsheetApp.openById(spreadsheetId) :
             SpreadsheetApp.getActiveSpreadsheet();
         const sheet = sheetName ? spreadsheet.getSheetByName(sheetName) : spreadsheet.getActiveSheet();
-        if (!sheet) {
+        if (!sheet && !/^named:/i.test(String(cellAddress).trim())) {
             console.error(`[UPDATE_SHEET_CELL_FLOAT] Sheet not found: ${sheetName}`);
             throw new Error(`Sheet "${sheetName}" not found.`);
         }
-        const range = sheet.getRange(cellAddress);
+        const range = TARGET_CELL(spreadsheet, sheet, cellAddress);
         if (floatValue !== undefined && floatValue !== null) {
+            GUARD_FORMULAS(range, overwriteFormulas);
             range.setValue(floatValue);
+            if (note) {
+                range.setNote(note);
+            }
         }
         const msg = floatValue !== undefined && floatValue !== null
             ? `Cell ${cellAddress} updated with float: ${floatValue} at ${new Date().toLocaleString()}`
@@ -59,27 +113,37 @@ function UPDATE_SHEET_CELL_FLOAT(spreadsheetId, sheetName, cellAddress, floatVal
  * Updates a specific cell with a string
  * @param {string} spreadsheetId - The spreadsheet ID (optional, uses active if not provided)
  * @param {string} sheetName - The name of the sheet (optional)
- * @param {string} cellAddress - The cell address (e.g., "A1", "B2")
+ * @param {string} cellAddress - The cell address (e.g., "A1", "B2"), or "named:" and a range name
  * @param {string} [stringValue] - Float to assign to cell)
+ * @param {boolean} [overwriteFormulas] - Write even when the cell holds a formula
+ * @param {string} [background] - Also set the cell's background color, e.g. "#f4cccc"
+ * @param {string} [note] - Also set the cell's note, e.g. where the value came from
  * @return {string} Success message
  * @customfunction
  *
  * Note: Account selection is handled by the Windows service, not this script.
  */
 
-function UPDATE_SHEET_CELL_STRING(spreadsheetId, sheetName, cellAddress,  stringValue) {
+function UPDATE_SHEET_CELL_STRING(spreadsheetId, sheetName, cellAddress,  stringValue, overwriteFormulas, background, note) {
     try {
         const spreadsheet = spreadsheetId ?
             SpreadsheetApp.openById(spreadsheetId) :
             SpreadsheetApp.getActiveSpreadsheet();
         const sheet = sheetName ? spreadsheet.getSheetByName(sheetName) : spreadsheet.getActiveSheet();
-        if (!sheet) {
+        if (!sheet && !/^named:/i.test(String(cellAddress).trim())) {
             console.error(`[UPDATE_SHEET_CELL_STRING] Sheet not found: ${sheetName}`);
             throw new Error(`Sheet "${sheetName}" not found.`);
         }
-        const range = sheet.getRange(cellAddress);
+        const range = TARGET_CELL(spreadsheet, sheet, cellAddress);
         if (stringValue !== undefined && stringValue !== null) {
+            GUARD_FORMULAS(range, overwriteFormulas);
             range.setValue(stringValue);
+            if (background) {
+                range.setBackground(background);
+            }
+            if (note) {
+                range.setNote(note);
+            }
         }
         const msg = stringValue !== undefined && stringValue !== null
             ? `Cell ${cellAddress} updated with string: ${stringValue} at ${new Date().toLocaleString()}`
@@ -92,6 +156,315 @@ function UPDATE_SHEET_CELL_STRING(spreadsheetId, sheetName, cellAddress,  string
     }
 }
 
+/**
+ * Appends a row after the last non-empty row of a sheet
+ * @param {string} spreadsheetId - The spreadsheet ID (optional, uses active if not provided)
+ * @param {string} sheetName - The name of the sheet (optional)
+ * @param {Array} rowValues - Values for the new row, left to right starting in column A
+ * @param {string} [appendColumn] - Column letters ("B"): the row starts in this column, below its
+ *   last filled cell, so a history table can sit beside other content
+ * @param {boolean} [overwriteFormulas] - Write even when the target cells hold a formula
+ * @return {string} Success message
+ *
+ * Used by append-mode sync blocks to build a time series (date, balance) on a history tab.
+ */
+function APPEND_SHEET_ROW(spreadsheetId, sheetName, rowValues, appendColumn, overwriteFormulas) {
+    try {
+        const spreadsheet = spreadsheetId ?
+            SpreadsheetApp.openById(spreadsheetId) :
+            SpreadsheetApp.getActiveSpreadsheet();
+        const sheet = sheetName ? spreadsheet.getSheetByName(sheetName) : spreadsheet.getActiveSheet();
+        if (!sheet) {
+            console.error(`[APPEND_SHEET_ROW] Sheet not found: ${sheetName}`);
+            throw new Error(`Sheet "${sheetName}" not found.`);
+        }
+        if (appendColumn) {
+            const column = sheet.getRange(`${appendColumn}1`).getColumn();
+            // the column's last filled cell, which may be well above the sheet's last row
+            const lastRow = sheet.getLastRow();
+            const values = lastRow > 0 ? sheet.getRange(1, column, lastRow, 1).getValues() : [];
+            let row = values.length;
+            while (row > 0 && values[row - 1][0] === '') {
+                row--;
+            }
+            const target = sheet.getRange(row + 1, column, 1, rowValues.length);
+            GUARD_FORMULAS(target, overwriteFormulas);
+            target.setValues([rowValues]);
+            return `Row ${row + 1} from column ${appendColumn} appended with: ${JSON.stringify(rowValues)} at ${new Date().toLocaleString()}`;
+        }
+        // appendRow writes to the first row after the last row with content
+        sheet.appendRow(rowValues);
+        return `Row ${sheet.getLastRow()} appended with: ${JSON.stringify(rowValues)} at ${new Date().toLocaleString()}`;
+    }
+    catch (error) {
+        console.error('[APPEND_SHEET_ROW] Error:', error);
+        throw error;
+    }
+}
+
+/**
+ * Writes a 2D block of values whose top-left corner is cellAddress
+ * @param {string} spreadsheetId - The spreadsheet ID (optional, uses active if not provided)
+ * @param {string} sheetName - The name of the sheet (optional)
+ * @param {string} cellAddress - Top-left cell of the block (e.g., "A1")
+ * @param {Array<Array>} rows - Rows of values; shorter rows are padded with blanks
+ * @param {boolean} [clearBelow] - Clear the block's columns from cellAddress down before writing
+ * @param {string} [namedRange] - Named range to point at the written rows
+ * @param {boolean} [overwriteFormulas] - Write / clear even where cells hold formulas
+ * @param {number} [namedRangeRows] - Rows the named range spans from cellAddress, when the service
+ *   splits a large block into several writes and this is the first
+ * @param {Object} [preview] - Instead of clearing, set preview.rows to how many filled rows are
+ *   below the written ones in the block's columns and preview.sample to the first of them
+ * @return {string} Success message
+ *
+ * Used by report and row-export blocks.
+ */
+function WRITE_SHEET_RANGE(spreadsheetId, sheetName, cellAddress, rows, clearBelow, namedRange, overwriteFormulas, namedRangeRows, preview) {
+    try {
+        const spreadsheet = spreadsheetId ?
+            SpreadsheetApp.openById(spreadsheetId) :
+            SpreadsheetApp.getActiveSpreadsheet();
+        const sheet = sheetName ? spreadsheet.getSheetByName(sheetName) : spreadsheet.getActiveSheet();
+        if (!sheet) {
+            console.error(`[WRITE_SHEET_RANGE] Sheet not found: ${sheetName}`);
+            throw new Error(`Sheet "${sheetName}" not found.`);
+        }
+        const anchor = sheet.getRange(cellAddress);
+        const width = rows.reduce((max, row) => Math.max(max, row.length), 1);
+        if (preview) {
+            // safe mode: the rows clearBelow would remove, read before the write moves the last row
+            const below = sheet.getLastRow() - anchor.getRow() + 1 - rows.length;
+            const left = below > 0 ?
+                anchor.offset(rows.length, 0, below, width).getDisplayValues().filter(row => row.some(cell => cell !== '')) :
+                [];
+            preview.rows = left.length;
+            preview.sample = left.slice(0, 3);
+        }
+        // everything this call changes: the written rows, and with clearBelow the rest of the columns
+        const cleared = clearBelow ? Math.max(sheet.getLastRow() - anchor.getRow() + 1, 1) : 0;
+        GUARD_FORMULAS(anchor.offset(0, 0, Math.max(rows.length, cleared, 1), width), overwriteFormulas);
+        if (clearBelow) {
+            const height = Math.max(sheet.getLastRow() - anchor.getRow() + 1, 1);
+            anchor.offset(0, 0, height, width).clearContent();
+        }
+        if (rows.length === 0) {
+            if (namedRange) {
+                SET_NAMED_RANGE(spreadsheet, namedRange, anchor);
+            }
+            return `Range at ${cellAddress} cleared; no rows to write`;
+        }
+        const padded = rows.map(row => row.concat(new Array(width - row.length).fill('')));
+        const written = anchor.offset(0, 0, padded.length, width);
+        written.setValues(padded);
+        if (namedRange) {
+            // charts bound to the name follow the data as it grows or shrinks
+            SET_NAMED_RANGE(spreadsheet, namedRange, namedRangeRows ? anchor.offset(0, 0, namedRangeRows, width) : written);
+        }
+        return `Range at ${cellAddress} updated with ${padded.length} rows at ${new Date().toLocaleString()}`;
+    }
+    catch (error) {
+        console.error('[WRITE_SHEET_RANGE] Error:', error);
+        throw error;
+    }
+}
+
+/**
+ * Creates the named range or points an existing one at the new range
+ */
+function SET_NAMED_RANGE(spreadsheet, name, range) {
+    const existing = spreadsheet.getNamedRanges().find(n => n.getName() === name);
+    if (existing) {
+        existing.setRange(range);
+    } else {
+        spreadsheet.setNamedRange(name, range);
+    }
+}
+
+/**
+ * Adds what a previewClear write left in place to its reply, as wouldClear and clearPreview
+ */
+function CLEAR_PREVIEW(reply, preview) {
+    if (preview) {
+        reply.wouldClear = preview.rows;
+        reply.clearPreview = preview.sample;
+    }
+    return reply;
+}
+
+/**
+ * Web App endpoint to write a block of rows
+ * This function handles POST requests with mode "range" from the Rust service.
+ */
+function doWriteRange(data) {
+    try {
+        const preview = data.previewClear ? {} : null;
+        const result = WRITE_SHEET_RANGE(
+            data.spreadsheetId,
+            data.sheetName,
+            data.cellAddress,
+            data.rows,
+            data.clearBelow,
+            data.namedRange,
+            data.overwriteFormulas,
+            data.namedRangeRows,
+            preview
+        );
+        return ContentService
+            .createTextOutput(JSON.stringify(CLEAR_PREVIEW({ success: true, message: result }, preview)))
+            .setMimeType(ContentService.MimeType.JSON);
+    }
+    catch (error) {
+        console.error('[doWriteRange] Error:', error);
+        return ContentService
+            .createTextOutput(JSON.stringify(WRITE_FAILURE(error)))
+            .setMimeType(ContentService.MimeType.JSON);
+    }
+}
+
+/**
+ * Copies a block staged on a hidden tab onto the sheet viewers look at, as one range write
+ * @param {string} spreadsheetId - The spreadsheet ID
+ * @param {string} sheetName - The sheet the block belongs on
+ * @param {string} stagingSheet - The hidden tab the service wrote the block to
+ * @param {string} cellAddress - Top-left cell of the block, the same on both tabs
+ * @param {number} rowCount - Rows staged from cellAddress down
+ * @param {number} width - Columns staged from cellAddress across
+ * @param {boolean} [clearBelow] - As for WRITE_SHEET_RANGE
+ * @param {string} [namedRange] - As for WRITE_SHEET_RANGE
+ * @param {boolean} [overwriteFormulas] - As for WRITE_SHEET_RANGE
+ * @param {Object} [preview] - As for WRITE_SHEET_RANGE
+ * @return {string} Success message
+ *
+ * Used by blocks with staged = true, so dashboards never show a table half written mid-cycle.
+ */
+function PROMOTE_STAGED_RANGE(spreadsheetId, sheetName, stagingSheet, cellAddress, rowCount, width, clearBelow, namedRange, overwriteFormulas, preview) {
+    try {
+        const spreadsheet = SpreadsheetApp.openById(spreadsheetId);
+        const staging = spreadsheet.getSheetByName(stagingSheet);
+        if (!staging) {
+            console.error(`[PROMOTE_STAGED_RANGE] Staging sheet not found: ${stagingSheet}`);
+            throw new Error(`Staging sheet "${stagingSheet}" not found.`);
+        }
+        // only what this cycle staged; anything further out is left over from an earlier one
+        const rows = rowCount > 0 ?
+            staging.getRange(cellAddress).offset(0, 0, rowCount, Math.max(width, 1)).getValues() :
+            [];
+        if (!staging.isSheetHidden()) {
+            staging.hideSheet();
+        }
+        return WRITE_SHEET_RANGE(spreadsheetId, sheetName, cellAddress, rows, clearBelow, namedRange, overwriteFormulas, undefined, preview);
+    }
+    catch (error) {
+        console.error('[PROMOTE_STAGED_RANGE] Error:', error);
+        throw error;
+    }
+}
+
+/**
+ * Web App endpoint to promote a staged block
+ * This function handles POST requests with mode "promote" from the Rust service.
+ */
+function doPromoteRange(data) {
+    try {
+        const preview = data.previewClear ? {} : null;
+        const result = PROMOTE_STAGED_RANGE(
+            data.spreadsheetId,
+            data.sheetName,
+            data.stagingSheet,
+            data.cellAddress,
+            data.rowCount,
+            data.width,
+            data.clearBelow,
+            data.namedRange,
+            data.overwriteFormulas,
+            preview
+        );
+        return ContentService
+            .createTextOutput(JSON.stringify(CLEAR_PREVIEW({ success: true, message: result }, preview)))
+            .setMimeType(ContentService.MimeType.JSON);
+    }
+    catch (error) {
+        console.error('[doPromoteRange] Error:', error);
+        return ContentService
+            .createTextOutput(JSON.stringify(WRITE_FAILURE(error)))
+            .setMimeType(ContentService.MimeType.JSON);
+    }
+}
+
+/**
+ * Reads cells as displayed: a range, or without one every row of the sheet up to its last filled
+ * row and column
+ * @param {string} spreadsheetId - The ID of the spreadsheet
+ * @param {string} sheetName - The name of the sheet to read
+ * @param {string} cellAddress - "A2:D50", "named:" and a range name, or empty for the whole sheet
+ * @return {string[][]} The rows, empty cells as ""
+ *
+ * The service's import-blocks command reads sync block definitions from a "config" tab this way.
+ */
+function READ_SHEET_VALUES(spreadsheetId, sheetName, cellAddress) {
+    const spreadsheet = spreadsheetId ?
+        SpreadsheetApp.openById(spreadsheetId) :
+        SpreadsheetApp.getActiveSpreadsheet();
+    // a named range is read whole, wherever it is, so sheetName isn't needed for it
+    const named = /^named:\s*(.+)$/i.exec(String(cellAddress || '').trim());
+    if (named) {
+        const range = spreadsheet.getRangeByName(named[1].trim());
+        if (!range) {
+            throw new Error(`Named range "${named[1].trim()}" not found.`);
+        }
+        return range.getDisplayValues();
+    }
+    const sheet = spreadsheet.getSheetByName(sheetName);
+    if (!sheet) {
+        console.error(`[READ_SHEET_VALUES] Sheet not found: ${sheetName}`);
+        throw new Error(`Sheet "${sheetName}" not found.`);
+    }
+    return cellAddress ? sheet.getRange(cellAddress).getDisplayValues() : sheet.getDataRange().getDisplayValues();
+}
+
+/**
+ * Web App endpoint to read a sheet
+ * This function handles POST requests with mode "read" from the Rust service.
+ */
+function doReadSheet(data) {
+    try {
+        const rows = READ_SHEET_VALUES(data.spreadsheetId, data.sheetName, data.cellAddress);
+        return ContentService
+            .createTextOutput(JSON.stringify({ success: true, rows: rows }))
+            .setMimeType(ContentService.MimeType.JSON);
+    }
+    catch (error) {
+        console.error('[doReadSheet] Error:', error);
+        return ContentService
+            .createTextOutput(JSON.stringify(WRITE_FAILURE(error)))
+            .setMimeType(ContentService.MimeType.JSON);
+    }
+}
+
+/**
+ * Web App endpoint to append a row to a sheet
+ * This function handles POST requests with mode "append" from the Rust service.
+ */
+function doAppendRow(data) {
+    try {
+        const result = APPEND_SHEET_ROW(
+            data.spreadsheetId,
+            data.sheetName,
+            data.rowValues,
+            data.appendColumn,
+            data.overwriteFormulas
+        );
+        return ContentService
+            .createTextOutput(JSON.stringify({ success: true, message: result }))
+            .setMimeType(ContentService.MimeType.JSON);
+    }
+    catch (error) {
+        console.error('[doAppendRow] Error:', error);
+        return ContentService
+            .createTextOutput(JSON.stringify(WRITE_FAILURE(error)))
+            .setMimeType(ContentService.MimeType.JSON);
+    }
+}
 
 /**
  * Web App endpoint to receive float to post to sheet
@@ -106,7 +479,9 @@ function doUpdateFloat(data) {
             data.spreadsheetId,
             data.sheetName,
             data.cellAddress,
-            data.floatValue
+            data.floatValue,
+            data.overwriteFormulas,
+            data.note
         );
         return ContentService;
             //.createTextOutput(JSON.stringify({ success: true, message: result }))
@@ -115,7 +490,7 @@ function doUpdateFloat(data) {
     catch (error) {
         console.error('[doUpdateFloat] Error:', error);
         return ContentService
-            .createTextOutput(JSON.stringify({ success: false, error: error instanceof Error ? error.message : String(error) }))
+            .createTextOutput(JSON.stringify(WRITE_FAILURE(error)))
             .setMimeType(ContentService.MimeType.JSON);
     }
 }
@@ -133,7 +508,10 @@ function doUpdateString(data) {
             data.spreadsheetId,
             data.sheetName,
             data.cellAddress,
-            data.stringValue
+            data.stringValue,
+            data.overwriteFormulas,
+            data.background,
+            data.note
         );
         return ContentService;
             // .createTextOutput(JSON.stringify({ success: true, message: result }))
@@ -142,15 +520,218 @@ function doUpdateString(data) {
     catch (error) {
         console.error('[doUpdateString] Error:', error);
         return ContentService
-            .createTextOutput(JSON.stringify({ success: false, error: error instanceof Error ? error.message : String(error) }))
+            .createTextOutput(JSON.stringify(WRITE_FAILURE(error)))
             .setMimeType(ContentService.MimeType.JSON);
     }
 }
 
 /** Web App endpoint */
+/**
+ * Checks the optional HMAC-SHA256 signature sent by the Rust service.
+ * Only enforced when the SIGNING_SECRET script property is set. doPost can't read request headers,
+ * so the service repeats X-Signature / X-Timestamp / X-Nonce as query parameters.
+ */
+function VERIFY_SIGNATURE(e) {
+    const secret = PropertiesService.getScriptProperties().getProperty('SIGNING_SECRET');
+    if (!secret) {
+        return;
+    }
+    const params = e.parameter || {};
+    if (!params.signature || !params.timestamp || !params.nonce) {
+        throw new Error('Missing request signature');
+    }
+    // reject anything older (or newer) than 5 minutes
+    const age = Math.abs(Date.now() / 1000 - Number(params.timestamp));
+    if (!(age <= 300)) {
+        throw new Error('Request timestamp outside the allowed window');
+    }
+    const message = `${params.timestamp}.${params.nonce}.${e.postData.contents}`;
+    const bytes = Utilities.computeHmacSha256Signature(message, secret, Utilities.Charset.UTF_8);
+    const expected = bytes.map(b => ((b + 256) % 256).toString(16).padStart(2, '0')).join('');
+    if (expected !== String(params.signature).toLowerCase()) {
+        throw new Error('Invalid request signature');
+    }
+    // each nonce is accepted once within the timestamp window
+    const cache = CacheService.getScriptCache();
+    if (cache.get(`nonce:${params.nonce}`)) {
+        throw new Error('Replayed request');
+    }
+    cache.put(`nonce:${params.nonce}`, '1', 600);
+}
+
+/**
+ * Adds a tab to the spreadsheet when it has none of that name, for blocks with create_sheet_if_missing
+ * @param {string} spreadsheetId - The ID of the spreadsheet
+ * @param {string} sheetName - The tab the write is for
+ * @param {Object<string, string>} [headers] - Column letter -> title, written to the first row of a new tab
+ * @param {boolean} [hidden] - Hide a new tab, as for the staging tabs of staged blocks
+ * @return {boolean} Whether the tab was created
+ */
+function ENSURE_SHEET(spreadsheetId, sheetName, headers, hidden) {
+    const spreadsheet = SpreadsheetApp.openById(spreadsheetId);
+    if (spreadsheet.getSheetByName(sheetName)) {
+        return false;
+    }
+    // two writes for the same new tab can arrive together; only one may add it
+    const lock = LockService.getDocumentLock() || LockService.getScriptLock();
+    lock.waitLock(30000);
+    try {
+        if (spreadsheet.getSheetByName(sheetName)) {
+            return false;
+        }
+        const sheet = spreadsheet.insertSheet(sheetName);
+        Object.keys(headers || {}).forEach(column => {
+            sheet.getRange(`${column}1`).setValue(headers[column]).setFontWeight('bold');
+        });
+        if (hidden) {
+            sheet.hideSheet();
+        }
+        console.log(`[ENSURE_SHEET] Created sheet "${sheetName}"`);
+        return true;
+    }
+    finally {
+        lock.releaseLock();
+    }
+}
+
+/**
+ * Routes a verified payload to the handler for its mode
+ */
+function doWrite(data) {
+    // create_sheet_if_missing: the tab is added before the write looks for it
+    if (data.createSheet && data.mode !== 'read' && data.spreadsheetId && data.sheetName) {
+        ENSURE_SHEET(data.spreadsheetId, data.sheetName, data.newSheetHeaders);
+    }
+    // staged range writes go to the block's own staging tab, which is added hidden
+    if (data.staged && data.mode === 'range' && data.spreadsheetId && data.sheetName) {
+        if (!ENSURE_SHEET(data.spreadsheetId, data.sheetName, null, true) && !data.confirmDestructive) {
+            // a visible tab of that name is someone's, not one the service staged in and hid
+            const existing = SpreadsheetApp.openById(data.spreadsheetId).getSheetByName(data.sheetName);
+            if (existing && !existing.isSheetHidden()) {
+                throw new Error(`"${data.sheetName}" is a visible tab with ${existing.getLastRow()} row(s); not staging over it without confirm_destructive`);
+            }
+        }
+    }
+    // Append mode adds a new row instead of writing a single cell
+    if (data.mode === 'append') {
+        if (!data.spreadsheetId || !data.sheetName || !Array.isArray(data.rowValues)) {
+            console.error('[doPost] Missing required fields for append:', data);
+            throw new Error('Missing required field: rowValues');
+        }
+        return doAppendRow(data);
+    }
+    // Range mode writes a block of rows starting at cellAddress
+    if (data.mode === 'range') {
+        if (!data.spreadsheetId || !data.sheetName || !data.cellAddress || !Array.isArray(data.rows)) {
+            console.error('[doPost] Missing required fields for range:', data);
+            throw new Error('Missing required field: rows');
+        }
+        return doWriteRange(data);
+    }
+    // Promote mode copies a staged block onto sheetName in one write
+    if (data.mode === 'promote') {
+        if (!data.spreadsheetId || !data.sheetName || !data.cellAddress || !data.stagingSheet) {
+            console.error('[doPost] Missing required fields for promote:', data);
+            throw new Error('Missing required field: stagingSheet');
+        }
+        return doPromoteRange(data);
+    }
+    // Read mode returns the sheet's rows, or those of cellAddress when given, and changes nothing
+    if (data.mode === 'read') {
+        if (!data.spreadsheetId || !data.sheetName) {
+            console.error('[doPost] Missing required fields for read:', data);
+            throw new Error('Missing required field: sheetName');
+        }
+        return doReadSheet(data);
+    }
+    // Validate required fields
+    if (!data.spreadsheetId || !data.sheetName || !data.cellAddress) {
+        console.error('[doPost] Missing required fields:', data);
+        throw new Error('Missing required field: cellAddress');
+    }
+    // an empty string is a value too: the service sends one to clear a cell (on_missing = "clear")
+    if (typeof data.stringValue === 'string') {
+        return doUpdateString(data);
+    }
+    return doUpdateFloat(data);
+}
+
+/**
+ * Idempotency keys of writes applied in the last 6 hours (the longest CacheService keeps anything).
+ * The service sends the same key when it retries a POST, so an append that timed out on its side
+ * but landed here isn't added twice.
+ *
+ * Appends are also kept in script properties for IDEMPOTENCY_KEEP_DAYS, because the service's
+ * offline queue can resend one after an outage much longer than 6 hours. Only the newest
+ * IDEMPOTENCY_KEEP_MAX are kept (script properties hold 500 KB in all); a row resent after its key
+ * was pruned can be added twice.
+ */
+const IDEMPOTENCY_KEEP_DAYS = 7;
+const IDEMPOTENCY_KEEP_MAX = 3000;
+
+function IDEMPOTENCY_SEEN(key) {
+    if (!key) {
+        return false;
+    }
+    return !!CacheService.getScriptCache().get(`idem:${key}`)
+        || !!PropertiesService.getScriptProperties().getProperty(`idem:${key}`);
+}
+
+function REMEMBER_IDEMPOTENCY_KEY(key, persist) {
+    if (!key) {
+        return;
+    }
+    CacheService.getScriptCache().put(`idem:${key}`, '1', 21600);
+    if (!persist) {
+        return;
+    }
+    try {
+        const properties = PropertiesService.getScriptProperties();
+        const now = Date.now();
+        const kept = Object.entries(properties.getProperties())
+            .filter(([name]) => name.startsWith('idem:'))
+            .sort((a, b) => Number(b[1]) - Number(a[1]));
+        kept.forEach(([name, appliedAt], i) => {
+            if (i >= IDEMPOTENCY_KEEP_MAX - 1 || now - Number(appliedAt) > IDEMPOTENCY_KEEP_DAYS * 86400000) {
+                properties.deleteProperty(name);
+            }
+        });
+        properties.setProperty(`idem:${key}`, String(now));
+    } catch (error) {
+        // the cache entry still covers the usual retry
+        console.error('[idempotency] Failed to keep the key past 6 hours:', error);
+    }
+}
+
+// The JSON reply for a failed write; formulaProtected tells the service a formula was left alone
+function WRITE_FAILURE(error) {
+    const reply = { success: false, error: error instanceof Error ? error.message : String(error) };
+    if (error instanceof Error && error.name === 'FormulaProtected') {
+        reply.formulaProtected = true;
+    }
+    return reply;
+}
+
+// range / append handlers report failures in their JSON reply; cell writes return no content
+function WRITE_SUCCEEDED(output) {
+    if (!output || typeof output.getContent !== 'function') {
+        return true;
+    }
+    try {
+        return JSON.parse(output.getContent()).success !== false;
+    } catch (_) {
+        return true;
+    }
+}
+
 function doPost(e) {
+    // correlation ids from the Rust service, so these logs can be matched to its run summary
+    let ids = 'run=- request=-';
     try {
+        VERIFY_SIGNATURE(e);
         const data = JSON.parse(e.postData.contents);
+        ids = `run=${data.runId || '-'} request=${data.requestId || '-'}`;
+        console.log(`[doPost] ${data.mode || 'cell'} ${data.sheetName}!${data.cellAddress || ''} ${ids}`);
         // console.log('Received payload:', JSON.stringify(e, null, 2));
         // Validate API key for security
         const scriptApiKey = PropertiesService.getScriptProperties().getProperty('QB_API_KEY');
@@ -158,21 +739,24 @@ function doPost(e) {
             console.error('[doPost] Invalid API key:', data.apiKey);
             throw new Error('Invalid API key');
         }
-        // Validate required fields
-        if (!data.spreadsheetId || !data.sheetName || !data.cellAddress) {
-            console.error('[doPost] Missing required fields:', data);
-            throw new Error('Missing required field: cellAddress');
+        // a retry of a write that already went through (the service timed out waiting for the reply)
+        if (IDEMPOTENCY_SEEN(data.idempotencyKey)) {
+            console.log(`[doPost] Duplicate write ignored ${ids}`);
+            return ContentService
+                .createTextOutput(JSON.stringify({ success: true, duplicate: true }))
+                .setMimeType(ContentService.MimeType.JSON);
         }
-       if (data.stringValue && data.stringValue !== "") {
-        return doUpdateString(data);
-        } else {
-        return doUpdateFloat(data);
+        const output = doWrite(data);
+        if (WRITE_SUCCEEDED(output)) {
+            // a cell or range write sent again only rewrites the same values, so only appends are kept past 6 hours
+            REMEMBER_IDEMPOTENCY_KEY(data.idempotencyKey, data.mode === 'append');
         }
+        return output;
     }
     catch (error) {
-        console.error('[doPost] Error:', error);
+        console.error(`[doPost] Error (${ids}):`, error);
         return ContentService
-            .createTextOutput(JSON.stringify({ success: false, error: error instanceof Error ? error.message : String(error) }))
+            .createTextOutput(JSON.stringify(WRITE_FAILURE(error)))
             .setMimeType(ContentService.MimeType.JSON);
     }
 }
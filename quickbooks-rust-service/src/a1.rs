@@ -0,0 +1,295 @@
+// A1 notation: parsing and validating cell addresses before anything is sent
+//
+// Accepts a cell ("B2", "$B$2") or a range ("A1:C10"), optionally after a sheet name
+// ("Summary!B2", "'Cash Flow'!B2", quotes doubled inside: "'Bob''s'!A1"). R1C1 references and
+// whole-column / whole-row ranges are rejected up front with a message that says so, rather than
+// failing later inside the webapp.
+
+use anyhow::{bail, Result};
+use std::fmt;
+
+// Google Sheets allows 18,278 columns (ZZZ) and 10 million cells, so no sheet has more rows than that
+pub const MAX_COLUMN: u32 = 18_278;
+pub const MAX_ROW: u32 = 10_000_000;
+
+/// One cell; column and row both start at 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub column: u32,
+    pub row: u32,
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", column_name(self.column), self.row)
+    }
+}
+
+/// A parsed reference: a single cell when `end` is None
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub sheet: Option<String>,
+    pub start: Cell,
+    pub end: Option<Cell>,
+}
+
+impl Reference {
+    pub fn is_range(&self) -> bool {
+        self.end.is_some_and(|end| end != self.start)
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(sheet) = &self.sheet {
+            write!(f, "{}!", quote_sheet_name(sheet))?;
+        }
+        write!(f, "{}", self.start)?;
+        if let Some(end) = self.end {
+            write!(f, ":{}", end)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a cell or range, with or without a sheet name
+pub fn parse(address: &str) -> Result<Reference> {
+    let address = address.trim();
+    if address.is_empty() {
+        bail!("cell address is empty");
+    }
+    let (sheet, cells) = split_sheet(address)?;
+    let (start, end) = match cells.split_once(':') {
+        Some((start, end)) => {
+            let (start, end) = (parse_part(start, address)?, parse_part(end, address)?);
+            if end.column < start.column || end.row < start.row {
+                bail!("'{}': the range has to run from its top-left cell to its bottom-right cell", address);
+            }
+            (start, Some(end))
+        }
+        None => (parse_part(cells, address)?, None),
+    };
+    Ok(Reference { sheet, start, end })
+}
+
+/// Parses a `cell_address` setting: one cell, with the sheet given separately in sheet_name
+pub fn parse_cell(address: &str) -> Result<Cell> {
+    let reference = parse(address)?;
+    if reference.sheet.is_some() {
+        bail!("'{}' includes a sheet name; put the sheet in sheet_name and only the cell here", address.trim());
+    }
+    if reference.is_range() {
+        bail!("'{}' is a range; give only its top-left cell ({})", address.trim(), reference.start);
+    }
+    Ok(reference.start)
+}
+
+/// "APPEND:B" => Some("B"): rather than a fixed cell, the next empty row of column B. The letters
+/// are returned as written; check them with `column_number`.
+pub fn append_column(address: &str) -> Option<&str> {
+    let address = address.trim();
+    let prefix = address.get(..7)?;
+    prefix.eq_ignore_ascii_case("APPEND:").then(|| address[7..].trim())
+}
+
+/// "named:MonthlyCash" => Some("MonthlyCash"): the top-left cell of a named range in the
+/// spreadsheet, looked up by the webapp at write time. The name is returned as written; check it
+/// with `parse_range_name`.
+pub fn named_range(address: &str) -> Option<&str> {
+    let address = address.trim();
+    let prefix = address.get(..6)?;
+    prefix.eq_ignore_ascii_case("named:").then(|| address[6..].trim())
+}
+
+/// Checks a name the way Sheets does: letters, digits and underscores, not starting with a digit,
+/// and not something Sheets would read as a cell ("B2") or R1C1 reference
+pub fn parse_range_name(name: &str) -> Result<&str> {
+    let Some(first) = name.chars().next() else {
+        bail!("a named range needs a name after 'named:'");
+    };
+    if first.is_ascii_digit() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        bail!("'{}' isn't a valid range name; use letters, digits and underscores, starting with a letter or underscore", name);
+    }
+    if parse(name).is_ok() || r1c1_hint(name).is_some() {
+        bail!("'{}' reads as a cell reference; Sheets doesn't allow it as a range name", name);
+    }
+    Ok(name)
+}
+
+/// "A" => 1, "Z" => 26, "AA" => 27; None for anything that isn't one to three letters
+pub fn column_number(letters: &str) -> Option<u32> {
+    if letters.is_empty() || letters.len() > 3 || !letters.bytes().all(|b| b.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some(letters.bytes().fold(0, |n, b| n * 26 + u32::from(b.to_ascii_uppercase() - b'A' + 1)))
+}
+
+/// 1 => "A", 27 => "AA"
+pub fn column_name(mut column: u32) -> String {
+    let mut letters = Vec::new();
+    while column > 0 {
+        let rem = (column - 1) % 26;
+        letters.push(b'A' + rem as u8);
+        column = (column - 1) / 26;
+    }
+    letters.reverse();
+    String::from_utf8(letters).unwrap_or_default()
+}
+
+/// The sheet name as it has to appear before '!': quoted unless it is a plain word
+pub fn quote_sheet_name(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        // a name like "Q1" or "FY2024" would read as a cell reference
+        && parse_part(name, name).is_err();
+    if plain {
+        name.to_string()
+    } else {
+        format!("'{}'", name.replace('\'', "''"))
+    }
+}
+
+// "'Cash Flow'!A1" => (Some("Cash Flow"), "A1")
+fn split_sheet(address: &str) -> Result<(Option<String>, &str)> {
+    if let Some(quoted) = address.strip_prefix('\'') {
+        // the sheet name ends at the first single quote that isn't doubled
+        let mut name = String::new();
+        let mut chars = quoted.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if c != '\'' {
+                name.push(c);
+                continue;
+            }
+            if let Some((_, '\'')) = chars.peek() {
+                chars.next();
+                name.push('\'');
+                continue;
+            }
+            let Some(cells) = quoted[i + 1..].strip_prefix('!') else {
+                bail!("'{}': a quoted sheet name has to be followed by '!' and a cell", address);
+            };
+            if name.is_empty() {
+                bail!("'{}': the sheet name is empty", address);
+            }
+            return Ok((Some(name), cells));
+        }
+        bail!("'{}': the sheet name's opening quote is never closed", address);
+    }
+    match address.rsplit_once('!') {
+        Some((sheet, _)) if sheet.contains(' ') => {
+            bail!("'{}': sheet names with spaces have to be quoted, as in '{}'!A1", address, sheet.replace('\'', "''"))
+        }
+        Some(("", _)) => bail!("'{}': the sheet name is empty", address),
+        Some((sheet, cells)) => Ok((Some(sheet.to_string()), cells)),
+        None => Ok((None, address)),
+    }
+}
+
+// One side of a reference: "B2" or "$B$2"
+fn parse_part(part: &str, address: &str) -> Result<Cell> {
+    let unanchored: String = part.chars().filter(|c| *c != '$').collect();
+    let letters_end = unanchored.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(unanchored.len());
+    let (letters, digits) = unanchored.split_at(letters_end);
+
+    let is_a1 = !letters.is_empty() && !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit());
+    if !is_a1 {
+        // checked only now because "RC1" is a perfectly good A1 cell (column RC)
+        if let Some(hint) = r1c1_hint(part) {
+            bail!("'{}' is R1C1 notation, which is not supported; use A1 notation{}", address, hint);
+        }
+        if letters.is_empty() && !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+            bail!("'{}' is a whole-row reference; give cells, e.g. A{}", address, digits);
+        }
+        if digits.is_empty() && !letters.is_empty() {
+            bail!("'{}' is a whole-column reference; give cells, e.g. {}1", address, letters.to_ascii_uppercase());
+        }
+        bail!("'{}' is not an A1 cell reference such as B2", address);
+    }
+    let column = match column_number(letters) {
+        Some(column) if column <= MAX_COLUMN => column,
+        _ => bail!("'{}': column {} is past the last column (ZZZ)", address, letters.to_ascii_uppercase()),
+    };
+    let row = match digits.parse::<u32>() {
+        Ok(0) => bail!("'{}': rows are numbered from 1", address),
+        Ok(row) if row <= MAX_ROW => row,
+        _ => bail!("'{}': row {} is past the last row a sheet can have", address, digits),
+    };
+    Ok(Cell { column, row })
+}
+
+// Some(" such as C2") for "R2C3", Some("") for relative forms like "R[1]C[-1]"; None if it isn't R1C1
+fn r1c1_hint(part: &str) -> Option<String> {
+    let upper = part.to_ascii_uppercase();
+    let rest = upper.strip_prefix('R')?;
+    let (row, rest) = rest.split_once('C')?;
+    let numbered = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let relative = |s: &str| s.is_empty() || (s.starts_with('[') && s.ends_with(']'));
+    if numbered(row) && numbered(rest) {
+        let cell = Cell { column: rest.parse().ok()?, row: row.parse().ok()? };
+        return Some(format!(" such as {}", cell));
+    }
+    if (numbered(row) || relative(row)) && (numbered(rest) || relative(rest)) {
+        return Some(String::new());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn column_letters_round_trip() {
+        for (letters, number) in [("A", 1), ("Z", 26), ("AA", 27), ("AZ", 52), ("ZZ", 702), ("AAA", 703), ("ZZZ", MAX_COLUMN)] {
+            assert_eq!(column_number(letters), Some(number), "{}", letters);
+            assert_eq!(column_name(number), letters);
+        }
+        assert_eq!(column_number("aa"), Some(27));
+        for bad in ["", "A1", "AAAA", "Ä"] {
+            assert_eq!(column_number(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn cells_and_ranges_parse() {
+        assert_eq!(parse_cell("$B$2").unwrap(), Cell { column: 2, row: 2 });
+        assert_eq!(parse_cell(" AA10 ").unwrap().to_string(), "AA10");
+        let range = parse("'Bob''s'!A1:C10").unwrap();
+        assert_eq!(range.sheet.as_deref(), Some("Bob's"));
+        assert_eq!(range.to_string(), "'Bob''s'!A1:C10");
+        assert!(range.is_range());
+        assert!(!parse("B2:B2").unwrap().is_range());
+    }
+
+    #[test]
+    fn bad_references_say_what_is_wrong() {
+        let error = |address: &str| parse(address).unwrap_err().to_string();
+        assert!(error("").contains("empty"));
+        assert!(error("R2C3").contains("R1C1 notation") && error("R2C3").contains("such as C2"));
+        assert!(error("R[1]C[-1]").contains("R1C1 notation"));
+        assert!(error("A:B").contains("whole-column"));
+        assert!(error("2:5").contains("whole-row"));
+        assert!(error("A0").contains("numbered from 1"));
+        assert!(error("AAAA1").contains("past the last column"));
+        assert!(error("A10000001").contains("past the last row"));
+        assert!(error("C3:A1").contains("top-left"));
+        assert!(error("Cash Flow!A1").contains("quoted"));
+        assert!(error("'Cash Flow!A1").contains("never closed"));
+        assert!(parse_cell("Summary!B2").unwrap_err().to_string().contains("sheet_name"));
+        assert!(parse_cell("A1:C3").unwrap_err().to_string().contains("top-left cell (A1)"));
+    }
+
+    #[test]
+    fn special_addresses_and_range_names() {
+        assert_eq!(append_column(" append:d "), Some("d"));
+        assert_eq!(append_column("A1"), None);
+        assert_eq!(named_range("named:MonthlyCash"), Some("MonthlyCash"));
+        assert!(parse_range_name("Monthly_Cash2").is_ok());
+        assert!(parse_range_name("B2").is_err());
+        assert!(parse_range_name("R1C1").is_err());
+        assert!(parse_range_name("2024Cash").is_err());
+        assert!(parse_range_name("Cash Flow").is_err());
+        assert_eq!(quote_sheet_name("Summary"), "Summary");
+        assert_eq!(quote_sheet_name("Q1"), "'Q1'");
+    }
+}
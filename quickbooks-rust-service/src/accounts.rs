@@ -0,0 +1,148 @@
+// Account data parsed from AccountQueryRs
+
+use anyhow::Result;
+use rayon::prelude::*;
+use roxmltree::Node;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::amounts::{self, NumberFormat};
+use crate::config::{AccountClassificationConfig, AccountSyncConfig, Config};
+use crate::qbxml;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AccountData {
+    // stays the same when the account is renamed; empty for category totals
+    pub list_id: String,
+    pub account_full_name: String,
+    pub number: String,
+    pub account_type: String,
+    #[serde(serialize_with = "amounts::serialize")]
+    pub balance: Decimal,
+    // CurrencyRef FullName; empty unless multicurrency is turned on in the company file
+    pub currency: String,
+    // AccountRet Desc and BankNumber, for treasury sheets
+    pub description: String,
+    pub bank_number: String,
+    // from [account_classification], filled in by `classify`; blank without one
+    pub category: String,
+}
+
+pub type AccountIndex = HashMap<String, AccountData>;
+
+const PARALLEL_THRESHOLD: usize = 1000;
+
+// AccountType values whose balances QuickBooks reports with the credit sign
+const CREDIT_NORMAL_TYPES: [&str; 7] = [
+    "AccountsPayable",
+    "CreditCard",
+    "Equity",
+    "Income",
+    "LongTermLiability",
+    "OtherCurrentLiability",
+    "OtherIncome",
+];
+
+impl AccountData {
+    /// The balance with income, liability and equity accounts flipped, so every account type reads
+    /// positive in its normal state (normalize_signs)
+    pub fn normalized_balance(&self) -> Decimal {
+        if CREDIT_NORMAL_TYPES.contains(&self.account_type.as_str()) {
+            -self.balance
+        } else {
+            self.balance
+        }
+    }
+}
+
+fn account_data(ret: Node, format: NumberFormat) -> AccountData {
+    AccountData {
+        list_id: qbxml::child_text(ret, "ListID").unwrap_or_default(),
+        account_full_name: qbxml::child_text(ret, "FullName").unwrap_or_default(),
+        number: qbxml::child_text(ret, "AccountNumber").unwrap_or_default(),
+        account_type: qbxml::child_text(ret, "AccountType").unwrap_or_default(),
+        balance: qbxml::child_text(ret, "Balance")
+            .and_then(|s| format.parse(&s))
+            .unwrap_or_default(),
+        currency: qbxml::ref_full_name(ret, "CurrencyRef").unwrap_or_default(),
+        description: qbxml::child_text(ret, "Desc").unwrap_or_default(),
+        bank_number: qbxml::child_text(ret, "BankNumber").unwrap_or_default(),
+        category: String::new(),
+    }
+}
+
+/// Every account in an AccountQueryRs response keyed by FullName. The response is parsed once per
+/// cycle and shared by all sync blocks rather than re-parsed for each one.
+pub fn index_accounts(response_xml: &str, format: NumberFormat) -> Result<AccountIndex> {
+    let doc = qbxml::parse_document(response_xml)?;
+    let rs = qbxml::find_response(&doc, "AccountQueryRs")?;
+    let rets: Vec<Node> = rs.children().filter(|n| n.has_tag_name("AccountRet")).collect();
+    // building thousands of AccountData is worth spreading over cores; a few dozen isn't
+    let accounts: Vec<AccountData> = if rets.len() >= PARALLEL_THRESHOLD {
+        rets.par_iter().map(|ret| account_data(*ret, format)).collect()
+    } else {
+        rets.iter().map(|ret| account_data(*ret, format)).collect()
+    };
+    Ok(accounts.into_iter().map(|a| (a.account_full_name.clone(), a)).collect())
+}
+
+/// Looks up a sync block's account by FullName
+pub fn find_account<'a>(accounts: &'a AccountIndex, account_full_name: &str) -> Option<&'a AccountData> {
+    let found = accounts.get(account_full_name);
+    if found.is_none() {
+        log::warn!("No accounts found with the specified criteria");
+    }
+    found
+}
+
+/// Looks up a sync block's account by the ListID it matched before, so a rename in QuickBooks
+/// doesn't lose it, and by FullName when there is none or that account is gone. The caller only
+/// passes a ListID found for the block's current account_full_name (History::account_id).
+pub fn find_known_account<'a>(accounts: &'a AccountIndex, account_full_name: &str, list_id: Option<&str>) -> Option<&'a AccountData> {
+    let known = list_id.and_then(|id| accounts.values().find(|a| a.list_id == id));
+    match known {
+        Some(account) => {
+            if account.account_full_name != account_full_name {
+                log::warn!("[QBXML] Account '{}' is now '{}' in QuickBooks (ListID {}); using it, but update account_full_name to match",
+                    account_full_name, account.account_full_name, account.list_id);
+            }
+            Some(account)
+        }
+        None => find_account(accounts, account_full_name),
+    }
+}
+
+/// Gives every account its [account_classification] category
+pub fn classify(accounts: &mut AccountIndex, classification: Option<&AccountClassificationConfig>) {
+    let Some(classification) = classification else { return };
+    for account in accounts.values_mut() {
+        account.category = classification.category(&account.account_full_name, &account.account_type).unwrap_or_default().to_string();
+    }
+}
+
+/// Leaves out the accounts allowed_accounts / denied_accounts keep off every destination, so no
+/// category total, KPI or computed value can include them either
+pub fn restrict(accounts: &mut AccountIndex, config: &Config) {
+    let before = accounts.len();
+    accounts.retain(|name, _| config.account_permitted(name));
+    if accounts.len() < before {
+        log::debug!("[QBXML] {} account(s) left out by allowed_accounts / denied_accounts", before - accounts.len());
+    }
+}
+
+/// What a sync block reports on: its account, or for a `category` block an account named after the
+/// category whose balance is the total of every account in it, each sign-normalized first when the
+/// block normalizes signs. None when there is no such account, or nothing in the category.
+/// `list_id` is the ListID the block's account had when last found (see find_known_account).
+pub fn for_sync_block(accounts: &AccountIndex, block: &AccountSyncConfig, config: &Config, list_id: Option<&str>) -> Option<AccountData> {
+    let Some(category) = &block.category else {
+        return find_known_account(accounts, &block.account_full_name, list_id).cloned();
+    };
+    let mut members = accounts.values().filter(|a| a.category.eq_ignore_ascii_case(category)).peekable();
+    members.peek()?;
+    let normalize = block.normalizes_signs(config);
+    let balance = members.map(|a| if normalize { a.normalized_balance() } else { a.balance }).sum();
+    // no account_type, so sync_value doesn't flip the total again
+    Some(AccountData { account_full_name: category.clone(), balance, category: category.clone(), ..Default::default() })
+}
@@ -0,0 +1,128 @@
+// [aging]: open invoices (InvoiceQueryRq, unpaid only) totalled per customer into age buckets,
+// 0-30 / 31-60 / 61-90 / 90+ days by default, and written as one table
+//
+// Worked out here rather than in the sheet, where an ARRAYFORMULA over an invoice export
+// recalculates the whole matrix on every edit. Credit memos and unapplied payments aren't
+// included, so a customer's total can be above their A/R balance in QuickBooks.
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::amounts::{self, NumberFormat};
+use crate::config::AgingConfig;
+use crate::qbxml;
+
+#[derive(Debug, Clone)]
+pub struct OpenInvoice {
+    // Customer:Job FullName
+    pub customer: String,
+    pub txn_date: NaiveDate,
+    pub due_date: Option<NaiveDate>,
+    pub balance: Decimal,
+}
+
+/// One customer's open balance in each bucket, oldest last
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgingRow {
+    pub customer: String,
+    pub buckets: Vec<Decimal>,
+}
+
+impl AgingRow {
+    pub fn total(&self) -> Decimal {
+        self.buckets.iter().sum()
+    }
+}
+
+/// Every unpaid invoice, with only the elements aging needs
+pub fn request() -> String {
+    qbxml::envelope(
+        "      <InvoiceQueryRq>
+        <PaidStatus>NotPaidOnly</PaidStatus>
+        <IncludeRetElement>CustomerRef</IncludeRetElement>
+        <IncludeRetElement>TxnDate</IncludeRetElement>
+        <IncludeRetElement>DueDate</IncludeRetElement>
+        <IncludeRetElement>BalanceRemaining</IncludeRetElement>
+      </InvoiceQueryRq>",
+    )
+}
+
+pub fn parse(response_xml: &str, format: NumberFormat) -> Result<Vec<OpenInvoice>> {
+    let doc = qbxml::parse_document(response_xml)?;
+    let rs = qbxml::find_response(&doc, "InvoiceQueryRs")?;
+    let mut invoices = Vec::new();
+    for ret in rs.children().filter(|n| n.has_tag_name("InvoiceRet")) {
+        let txn_date = qbxml::child_text(ret, "TxnDate").unwrap_or_default();
+        invoices.push(OpenInvoice {
+            customer: qbxml::ref_full_name(ret, "CustomerRef").unwrap_or_default(),
+            txn_date: parse_date(&txn_date).with_context(|| format!("Invoice with TxnDate '{}'", txn_date))?,
+            due_date: qbxml::child_text(ret, "DueDate").and_then(|d| parse_date(&d).ok()),
+            balance: qbxml::child_text(ret, "BalanceRemaining").and_then(|b| format.parse(&b)).unwrap_or_default(),
+        });
+    }
+    Ok(invoices)
+}
+
+fn parse_date(text: &str) -> Result<NaiveDate> {
+    Ok(NaiveDate::parse_from_str(text.trim(), "%Y-%m-%d")?)
+}
+
+/// Balances per customer (or per job with `jobs = true`) and bucket as of `today`, by customer name
+pub fn buckets(invoices: &[OpenInvoice], aging: &AgingConfig, today: NaiveDate) -> Vec<AgingRow> {
+    let days = aging.bucket_days();
+    let from_due_date = aging.from_due_date.unwrap_or(false);
+    let mut rows: BTreeMap<String, Vec<Decimal>> = BTreeMap::new();
+    for invoice in invoices.iter().filter(|i| !i.balance.is_zero()) {
+        let customer = match aging.jobs.unwrap_or(false) {
+            true => invoice.customer.as_str(),
+            false => invoice.customer.split(':').next().unwrap_or_default(),
+        };
+        let since = if from_due_date { invoice.due_date.unwrap_or(invoice.txn_date) } else { invoice.txn_date };
+        let age = (today - since).num_days().max(0);
+        let bucket = days.iter().position(|&last| age <= i64::from(last)).unwrap_or(days.len());
+        rows.entry(customer.to_string()).or_insert_with(|| vec![Decimal::ZERO; days.len() + 1])[bucket] += invoice.balance;
+    }
+    rows.into_iter().map(|(customer, buckets)| AgingRow { customer, buckets }).collect()
+}
+
+/// "Customer", a title per bucket ("0-30", "31-60", ..., "90+") and "Total"
+pub fn headers(aging: &AgingConfig) -> Vec<String> {
+    let days = aging.bucket_days();
+    let mut headers = vec!["Customer".to_string()];
+    let mut first = 0;
+    for last in &days {
+        headers.push(format!("{}-{}", first, last));
+        first = last + 1;
+    }
+    headers.push(format!("{}+", days.last().copied().unwrap_or_default()));
+    headers.push("Total".to_string());
+    headers
+}
+
+/// The table for a range write: optional header, a row per customer and an optional total row
+pub fn to_rows(rows: &[AgingRow], aging: &AgingConfig) -> Vec<Vec<Value>> {
+    let mut table = Vec::with_capacity(rows.len() + 2);
+    if aging.include_header.unwrap_or(true) {
+        table.push(headers(aging).into_iter().map(Value::from).collect());
+    }
+    for row in rows {
+        table.push(row_values(&row.customer, &row.buckets, row.total()));
+    }
+    if aging.include_total.unwrap_or(true) {
+        let columns = aging.bucket_days().len() + 1;
+        let totals: Vec<Decimal> = (0..columns).map(|i| rows.iter().map(|r| r.buckets[i]).sum()).collect();
+        let total = totals.iter().sum();
+        table.push(row_values("Total", &totals, total));
+    }
+    table
+}
+
+fn row_values(customer: &str, buckets: &[Decimal], total: Decimal) -> Vec<Value> {
+    let mut values = vec![Value::from(customer)];
+    values.extend(buckets.iter().map(|b| amounts::json(*b)));
+    values.push(amounts::json(total));
+    values
+}
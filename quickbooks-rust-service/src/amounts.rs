@@ -0,0 +1,212 @@
+// Amounts in qbXML responses (balances, report columns, transaction totals, list quantities) as
+// exact decimals
+//
+// Amounts stay Decimal through computed blocks, templates and balance diffs, so no float artifacts
+// (0.30000000000000004) reach a cell. They are sent as JSON numbers, which every receiver reads as
+// a double, or as strings when a double can't hold them to the cent (beyond about 90 trillion).
+//
+// qbXML itself writes "1234.56", but report columns and some company files set up for another
+// locale come back as "1.234,56" or "1 234,56". [quickbooks] number_locale says which separators to
+// expect; without it each value's separators are guessed, which a single separator followed by
+// exactly three digits can get wrong: "1,234" is taken for a thousand and "1.234" for one and a bit.
+
+use anyhow::{bail, Result};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Deserializer, Serializer};
+use serde_json::Value;
+use std::str::FromStr;
+
+// spaces used to group digits: plain, no-break and narrow no-break
+const SPACES: [char; 3] = [' ', '\u{00A0}', '\u{202F}'];
+
+/// The decimal and digit grouping separators amounts are written with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberFormat {
+    /// Guess per value: the last of '.' and ',' is the decimal separator when both appear. With only
+    /// one separator followed by exactly three digits it can't tell, and reads "1,234" as 1234 but
+    /// "1.234" as 1.234, as qbXML writes rates and quantities. A company file that writes "1.234"
+    /// for a thousand needs number_locale, or its amounts come out a thousand times too small.
+    #[default]
+    Auto,
+    Separators { decimal: char, group: char },
+}
+
+impl NumberFormat {
+    /// The format for a locale name such as "en-US", "de-DE" or "fr-CA"; "auto" guesses
+    pub fn for_locale(locale: &str) -> Result<Self> {
+        let locale = locale.trim().replace('_', "-").to_ascii_lowercase();
+        let language = locale.split('-').next().unwrap_or_default();
+        let separators = |decimal, group| Ok(NumberFormat::Separators { decimal, group });
+        match (language, locale.as_str()) {
+            (_, "auto") => Ok(NumberFormat::Auto),
+            (_, "de-ch" | "de-li" | "fr-ch" | "it-ch") => separators('.', '\''),
+            (_, "es-mx" | "es-us") => separators('.', ','),
+            ("en" | "ja" | "zh" | "ko" | "he" | "th" | "hi" | "ms" | "fil", _) => separators('.', ','),
+            ("de" | "es" | "it" | "nl" | "pt" | "id" | "tr" | "da" | "el" | "ro" | "hr" | "sl" | "sr" | "vi", _) => separators(',', '.'),
+            ("fr" | "sv" | "nb" | "no" | "fi" | "pl" | "cs" | "sk" | "ru" | "uk" | "hu" | "bg" | "lt" | "lv" | "et", _) => separators(',', ' '),
+            _ => bail!("Unknown number_locale '{}'; use a locale such as \"en-US\" or \"de-DE\", or \"auto\"", locale),
+        }
+    }
+
+    /// Parses an amount: separators as configured, a leading or trailing '-' or accounting
+    /// parentheses for negatives. None for anything else, such as text in a number column.
+    pub fn parse(&self, text: &str) -> Option<Decimal> {
+        let mut text = text.trim();
+        let mut negative = false;
+        if let Some(inner) = text.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+            text = inner.trim();
+            negative = true;
+        }
+        if let Some(rest) = text.strip_prefix('-').or_else(|| text.strip_suffix('-')) {
+            text = rest.trim();
+            negative = !negative;
+        }
+        let (decimal, group) = match *self {
+            NumberFormat::Separators { decimal, group } => (decimal, Some(group)),
+            NumberFormat::Auto => guess(text),
+        };
+
+        let mut plain = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '0'..='9' => plain.push(c),
+                _ if c == decimal => plain.push('.'),
+                // spaces and apostrophes only ever group digits
+                _ if Some(c) == group || SPACES.contains(&c) || c == '\'' => {}
+                _ => return None,
+            }
+        }
+        if !plain.bytes().any(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let amount = Decimal::from_str(&plain).ok()?;
+        Some(if negative { -amount } else { amount })
+    }
+}
+
+// The decimal separator and the grouping one (if any) of a value with unknown separators
+fn guess(text: &str) -> (char, Option<char>) {
+    match (text.rfind('.'), text.rfind(',')) {
+        (Some(dot), Some(comma)) if comma > dot => (',', Some('.')),
+        (Some(_), Some(_)) => ('.', Some(',')),
+        // "1.234.567" groups, "1234.56" doesn't
+        (Some(_), None) if text.matches('.').count() > 1 => (',', Some('.')),
+        (Some(_), None) => ('.', None),
+        // "1,234" and "1,234,567" group; "1234,56" is a decimal comma
+        (None, Some(comma)) if text.matches(',').count() == 1 && text.len() - comma - 1 != 3 => (',', None),
+        _ => ('.', Some(',')),
+    }
+}
+
+/// The amount as a double, if that holds it to the cent. Digits past the cent (of a ratio, say)
+/// may be rounded off; cents never are.
+fn as_f64(amount: Decimal) -> Option<f64> {
+    // through text: the nearest double, which to_f64 doesn't always find
+    let float = f64::from_str(&amount.to_string()).ok()?;
+    let back = Decimal::from_str(&float.to_string()).ok()?;
+    (round(back, 2) == round(amount, 2)).then_some(float)
+}
+
+/// An amount as a JSON value: a number, or a string when a number would lose cents
+pub fn json(amount: Decimal) -> Value {
+    match as_f64(amount) {
+        Some(float) => Value::from(float),
+        None => Value::from(amount.to_string()),
+    }
+}
+
+/// `serialize_with` for Decimal fields, the same way as `json`
+pub fn serialize<S: Serializer>(amount: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+    match as_f64(*amount) {
+        Some(float) => serializer.serialize_f64(float),
+        None => serializer.collect_str(amount),
+    }
+}
+
+/// `deserialize_with` for Decimal fields written by `serialize`, or as plain numbers before they were
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+    let text = match Value::deserialize(deserializer)? {
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s,
+        other => return Err(serde::de::Error::custom(format!("expected an amount, found {}", other))),
+    };
+    Decimal::from_str(&text)
+        .or_else(|_| Decimal::from_scientific(&text))
+        .map_err(serde::de::Error::custom)
+}
+
+/// Rounds half away from zero, as people (and sheets) do; Decimal's own round_dp rounds half to even
+pub fn round(amount: Decimal, decimals: u32) -> Decimal {
+    amount.round_dp_with_strategy(decimals, RoundingStrategy::MidpointAwayFromZero)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(text: &str) -> Decimal {
+        text.parse().unwrap()
+    }
+
+    fn locale(name: &str) -> NumberFormat {
+        NumberFormat::for_locale(name).unwrap()
+    }
+
+    #[test]
+    fn each_locale_reads_its_own_separators() {
+        assert_eq!(locale("en-US").parse("1,234,567.89"), Some(dec("1234567.89")));
+        assert_eq!(locale("de_DE").parse("1.234.567,89"), Some(dec("1234567.89")));
+        assert_eq!(locale("fr-FR").parse("1 234 567,89"), Some(dec("1234567.89")));
+        assert_eq!(locale("fr-FR").parse("1\u{202F}234,5"), Some(dec("1234.5")));
+        assert_eq!(locale("de-CH").parse("1'234'567.89"), Some(dec("1234567.89")));
+        assert_eq!(locale("es-MX").parse("1,234.5"), Some(dec("1234.5")));
+        // a dot only groups digits here
+        assert_eq!(locale("de-DE").parse("1.234"), Some(dec("1234")));
+        assert_eq!(locale("en-US").parse("1234.56"), Some(dec("1234.56")));
+        assert_eq!(locale("auto"), NumberFormat::Auto);
+        assert!(NumberFormat::for_locale("xx-YY").is_err());
+    }
+
+    #[test]
+    fn auto_guesses_the_separators_per_value() {
+        let auto = NumberFormat::Auto;
+        assert_eq!(auto.parse("1234.56"), Some(dec("1234.56")));
+        assert_eq!(auto.parse("1,234.56"), Some(dec("1234.56")));
+        assert_eq!(auto.parse("1.234,56"), Some(dec("1234.56")));
+        assert_eq!(auto.parse("1.234.567"), Some(dec("1234567")));
+        assert_eq!(auto.parse("1,234,567"), Some(dec("1234567")));
+        assert_eq!(auto.parse("1234,56"), Some(dec("1234.56")));
+        assert_eq!(auto.parse("1 234,56"), Some(dec("1234.56")));
+        assert_eq!(auto.parse("1,234"), Some(dec("1234")));
+        // qbXML's own decimal point, not a grouped thousand: number_locale = "de-DE" reads that
+        assert_eq!(auto.parse("1.234"), Some(dec("1.234")));
+    }
+
+    #[test]
+    fn negatives_in_parentheses_or_with_a_minus() {
+        let auto = NumberFormat::Auto;
+        assert_eq!(auto.parse("(1,200.00)"), Some(dec("-1200.00")));
+        assert_eq!(auto.parse("( 45.10 )"), Some(dec("-45.10")));
+        assert_eq!(auto.parse("-45.10"), Some(dec("-45.10")));
+        assert_eq!(auto.parse("45.10-"), Some(dec("-45.10")));
+        assert_eq!(locale("de-DE").parse("(1.200,00)"), Some(dec("-1200.00")));
+        // both at once cancel out
+        assert_eq!(auto.parse("(-5)"), Some(dec("5")));
+    }
+
+    #[test]
+    fn text_is_not_an_amount() {
+        let auto = NumberFormat::Auto;
+        for text in ["", "-", "()", "N/A", "$12.00", "12 EUR", "1.2.3,4,5"] {
+            assert_eq!(auto.parse(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn json_keeps_cents_and_rounding_is_half_away_from_zero() {
+        assert_eq!(json(dec("45210.37")), Value::from(45210.37));
+        assert_eq!(json(dec("123456789012345678.91")), Value::from("123456789012345678.91"));
+        assert_eq!(round(dec("2.345"), 2), dec("2.35"));
+        assert_eq!(round(dec("-2.345"), 2), dec("-2.35"));
+    }
+}
@@ -0,0 +1,79 @@
+// Archiving of raw qbXML responses for after-the-fact debugging
+// Each response is written to a timestamped file and old files are pruned on every write
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::config::DebugConfig;
+
+const DEFAULT_ARCHIVE_DIR: &str = "logs/qbxml";
+const DEFAULT_MAX_FILES: usize = 50;
+const DEFAULT_MAX_AGE_DAYS: u64 = 14;
+
+/// Archives `response_xml` if `[debug] archive_responses = true`.
+/// Failures are logged and swallowed; archiving must never break a sync run.
+pub fn maybe_archive_response(debug: Option<&DebugConfig>, label: &str, response_xml: &str) {
+    let debug = match debug {
+        Some(debug) if debug.archive_responses.unwrap_or(false) => debug,
+        _ => return,
+    };
+    match archive_response(debug, label, response_xml) {
+        Ok(path) => log::debug!("[ARCHIVE] Saved qbXML response to {}", path.display()),
+        Err(e) => log::warn!("[ARCHIVE] Failed to archive qbXML response: {:#}", e),
+    }
+}
+
+pub fn archive_response(debug: &DebugConfig, label: &str, response_xml: &str) -> Result<PathBuf> {
+    let dir = Path::new(debug.archive_dir.as_deref().unwrap_or(DEFAULT_ARCHIVE_DIR));
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create archive directory {}", dir.display()))?;
+
+    let file_name = format!("{}-{}.xml", Local::now().format("%Y%m%d-%H%M%S%.3f"), sanitize(label));
+    let path = dir.join(file_name);
+    fs::write(&path, response_xml)
+        .with_context(|| format!("Failed to write archive file {}", path.display()))?;
+
+    let max_files = debug.archive_max_files.unwrap_or(DEFAULT_MAX_FILES);
+    let max_age = Duration::from_secs(debug.archive_max_age_days.unwrap_or(DEFAULT_MAX_AGE_DAYS) * 24 * 60 * 60);
+    if let Err(e) = prune(dir, max_files, max_age) {
+        log::warn!("[ARCHIVE] Failed to prune {}: {:#}", dir.display(), e);
+    }
+    Ok(path)
+}
+
+// Removes archived responses older than max_age, then the oldest files beyond max_files
+fn prune(dir: &Path, max_files: usize, max_age: Duration) -> Result<()> {
+    let now = SystemTime::now();
+    let mut files: Vec<(SystemTime, PathBuf)> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("xml") {
+            continue;
+        }
+        let modified = fs::metadata(&path)?.modified()?;
+        if now.duration_since(modified).unwrap_or_default() > max_age {
+            fs::remove_file(&path)?;
+        } else {
+            files.push((modified, path));
+        }
+    }
+    if files.len() > max_files {
+        // oldest first
+        files.sort();
+        let excess = files.len() - max_files;
+        for (_, path) in files.into_iter().take(excess) {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+// Keeps file names safe on Windows no matter what label is passed in
+fn sanitize(label: &str) -> String {
+    label.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
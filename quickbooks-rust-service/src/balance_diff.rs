@@ -0,0 +1,149 @@
+// Balance diffing: with [balance_diff], every account's balance is kept in the history file after
+// each run, and the accounts that moved since the previous run are logged (and optionally written
+// to a tab next to the status sheet). `qb_sync diff` prints the same list from the history file.
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use rust_decimal::Decimal;
+use serde_json::Value;
+
+use crate::accounts::AccountIndex;
+use crate::amounts;
+use crate::config::BalanceDiffConfig;
+use crate::context::AppContext;
+use crate::history::History;
+
+// a cent
+pub const DEFAULT_MIN_CHANGE: Decimal = Decimal::from_parts(1, 0, 0, false, 2);
+
+const HEADERS: [&str; 7] = ["Account", "Previous", "Current", "Change", "Change %", "Previous As Of", "As Of"];
+
+#[derive(Debug, Clone)]
+pub struct BalanceChange {
+    pub account: String,
+    pub previous: Decimal,
+    pub current: Decimal,
+    pub change: Decimal,
+    // to the hundredth of a percent; None when the previous balance was zero
+    pub percent: Option<Decimal>,
+    pub previous_at: DateTime<Local>,
+    pub at: DateTime<Local>,
+}
+
+/// Accounts whose latest balance differs from the previous run's by at least `min_change`,
+/// largest moves first
+pub fn changes(history: &History, min_change: Decimal) -> Vec<BalanceChange> {
+    let mut changes: Vec<BalanceChange> = history.accounts()
+        .filter_map(|(account, entry)| {
+            let previous = entry.previous?;
+            let change = entry.latest.balance - previous.balance;
+            if amounts::round(change.abs(), 2) < min_change {
+                return None;
+            }
+            Some(BalanceChange {
+                account: account.clone(),
+                previous: previous.balance,
+                current: entry.latest.balance,
+                change,
+                percent: (!previous.balance.is_zero())
+                    .then(|| amounts::round(change / previous.balance.abs() * Decimal::ONE_HUNDRED, 2)),
+                previous_at: previous.at,
+                at: entry.latest.at,
+            })
+        })
+        .collect();
+    changes.sort_by_key(|c| std::cmp::Reverse(c.change.abs()));
+    changes
+}
+
+// two decimals, rounded; Decimal's own {:.2} truncates
+fn cents(amount: Decimal) -> String {
+    format!("{:.2}", amounts::round(amount, 2))
+}
+
+fn format_percent(percent: Option<Decimal>) -> String {
+    percent.map(|p| format!("{:+.1}%", amounts::round(p, 1))).unwrap_or_else(|| "n/a".to_string())
+}
+
+/// One line per change, for the console / `qb_sync diff`
+pub fn lines(changes: &[BalanceChange]) -> Vec<String> {
+    changes.iter()
+        .map(|c| format!(
+            "{:<40} {:>14} -> {:>14} {:>14} ({})",
+            c.account, cents(c.previous), cents(c.current), format!("{:+}", cents(c.change)), format_percent(c.percent),
+        ))
+        .collect()
+}
+
+pub fn rows(changes: &[BalanceChange]) -> Vec<Vec<Value>> {
+    let mut rows = vec![HEADERS.iter().map(|h| Value::from(*h)).collect()];
+    for c in changes {
+        rows.push(vec![
+            Value::from(c.account.as_str()),
+            amounts::json(c.previous),
+            amounts::json(c.current),
+            amounts::json(c.change),
+            c.percent.map(amounts::json).unwrap_or(Value::Null),
+            Value::from(c.previous_at.format("%Y-%m-%d %H:%M:%S").to_string()),
+            Value::from(c.at.format("%Y-%m-%d %H:%M:%S").to_string()),
+        ]);
+    }
+    rows
+}
+
+/// Records this run's balances from the account query and reports what changed
+pub async fn after_cycle(accounts: &AccountIndex, ctx: &AppContext<'_>, diff_cfg: &BalanceDiffConfig) -> Result<()> {
+    let config = ctx.config;
+    let now = Local::now();
+    let changes = ctx.history.update(|history| {
+        for (name, account) in accounts {
+            history.record_balance(name, account.balance, now);
+        }
+        changes(history, diff_cfg.min_change.unwrap_or(DEFAULT_MIN_CHANGE))
+    })?;
+    if changes.is_empty() {
+        log::info!("[DIFF] No balance changes since the previous run");
+    } else {
+        log::info!("[DIFF] {} account balance(s) changed since the previous run", changes.len());
+        for line in lines(&changes) {
+            log::info!("[DIFF] {}", line);
+        }
+    }
+
+    if let Some(sheet_name) = &diff_cfg.sheet_name {
+        let Some(status_cfg) = &config.status_sheet else {
+            log::warn!("[DIFF] [balance_diff] sheet_name is set but there is no [status_sheet] to write it next to");
+            return Ok(());
+        };
+        let gs_client = ctx.sheets(&status_cfg.spreadsheet_id);
+        gs_client.write_range(&rows(&changes), sheet_name, "A1", true, None).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(text: &str) -> Decimal {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn changes_below_the_threshold_are_left_out() {
+        let mut history = History::default();
+        let (before, after) = (Local::now() - chrono::Duration::hours(1), Local::now());
+        for (account, previous, current) in [("Checking", "3000.00", "3100.00"), ("Savings", "0", "50"), ("Petty Cash", "100.00", "100.004")] {
+            history.record_balance(account, dec(previous), before);
+            history.record_balance(account, dec(current), after);
+        }
+
+        let found = changes(&history, DEFAULT_MIN_CHANGE);
+        let accounts: Vec<&str> = found.iter().map(|c| c.account.as_str()).collect();
+        assert_eq!(accounts, ["Checking", "Savings"]);
+        assert_eq!(found[0].percent, Some(dec("3.33")));
+        assert_eq!(found[1].percent, None);
+        assert!(lines(&found)[0].ends_with("(+3.3%)"));
+        assert_eq!(changes(&history, dec("75")).len(), 1);
+    }
+}
@@ -0,0 +1,378 @@
+// --record <dir> / --replay <dir>: one run's QuickBooks and HTTP traffic on disk, to rerun it later
+//
+// Recording writes every qbXML request and response (0001-AccountQueryRq.xml, 0001-AccountQueryRs.xml),
+// every outgoing HTTP payload with the answer it got (0002-webapp.json) and the rows of every QODBC
+// query (0003-odbc.json) into the directory, numbered in the order they happened. Replaying runs the same config against the capture: QuickBooks
+// isn't started or connected to and nothing goes over the network. Each qbXML request is answered
+// with the next recorded response to the same request elements, each webapp call with the recorded
+// answer to the same payload (else the next one of its kind), each ODBC query with the rows recorded
+// for the same SQL (else the next query's), and webhook, Airtable, Excel and telemetry sends count
+// as delivered with their recorded status. A payload that differs from the
+// recorded one is logged with both, which is usually the bug being reproduced.
+//
+// The webapp api_key is left out of the files, URLs are reduced to their host and GETs to host and
+// path (a query string can carry a token), but responses are the company's real data; treat a
+// capture like a backup of the company file.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use crate::file_mode::FileMode;
+use crate::qb_backend::RequestProcessor;
+
+// payload fields that differ from run to run (or are secret) and so are ignored when comparing
+const VOLATILE_FIELDS: &[&str] = &["apiKey", "runId", "requestId", "idempotencyKey"];
+const COMPANY_FILE: &str = "company-file.txt";
+// what ODBC queries are recorded as; the SQL is the request and the rows, as JSON, the response
+const ODBC: &str = "odbc";
+
+static CAPTURE: OnceLock<Capture> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Record,
+    Replay,
+}
+
+pub struct Capture {
+    mode: Mode,
+    dir: PathBuf,
+    // numbers the files in the order they are written
+    next: AtomicUsize,
+    // replay: the recorded qbXML responses per request key, and the HTTP exchanges not used yet
+    responses: Mutex<HashMap<String, VecDeque<PathBuf>>>,
+    exchanges: Mutex<Vec<Exchange>>,
+}
+
+// one HTTP call: which kind of destination, its host, what was sent and what came back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Exchange {
+    kind: String,
+    host: String,
+    request: Value,
+    status: u16,
+    response: String,
+}
+
+/// Starts recording into, or replaying from, `dir` for the rest of the process
+pub fn start(mode: Mode, dir: &Path) -> Result<()> {
+    if CAPTURE.set(open(mode, dir)?).is_err() {
+        bail!("A capture is already running");
+    }
+    match mode {
+        Mode::Record => log::warn!("[CAPTURE] Recording this run into {}", dir.display()),
+        Mode::Replay => log::warn!("[CAPTURE] Replaying the run recorded in {}; QuickBooks and the network are left alone", dir.display()),
+    }
+    Ok(())
+}
+
+/// A capture of `dir` on its own, outside this process's --record / --replay
+pub fn open(mode: Mode, dir: &Path) -> Result<Capture> {
+    Ok(match mode {
+        Mode::Record => {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+            if std::fs::read_dir(dir)?.next().is_some() {
+                bail!("--record directory {} isn't empty; record each run into a directory of its own", dir.display());
+            }
+            Capture::new(mode, dir)
+        }
+        Mode::Replay => {
+            let capture = Capture::new(mode, dir);
+            capture.load().with_context(|| format!("Failed to read the capture in {}", dir.display()))?;
+            capture
+        }
+    })
+}
+
+/// Whether this run is a --replay, which must not reach QuickBooks or the network
+pub fn replaying() -> bool {
+    replaying_capture().is_some()
+}
+
+/// Whether this run is a --record or --replay, whose exchanges are numbered in the order they happen
+pub fn active() -> bool {
+    CAPTURE.get().is_some()
+}
+
+fn replaying_capture() -> Option<&'static Capture> {
+    CAPTURE.get().filter(|c| c.mode == Mode::Replay)
+}
+
+fn recording() -> Option<&'static Capture> {
+    CAPTURE.get().filter(|c| c.mode == Mode::Record)
+}
+
+impl Capture {
+    fn new(mode: Mode, dir: &Path) -> Self {
+        Self { mode, dir: dir.to_path_buf(), next: AtomicUsize::new(1), responses: Default::default(), exchanges: Default::default() }
+    }
+
+    /// `processor`, recording what it is asked and answers into this (recording) capture
+    pub fn recorder(&'static self, processor: Box<dyn RequestProcessor>) -> Box<dyn RequestProcessor> {
+        Box::new(Recording { inner: processor, capture: self })
+    }
+
+    /// A processor answering from this (replaying) capture
+    pub fn replayer(&'static self) -> Box<dyn RequestProcessor> {
+        Box::new(Replaying { capture: self })
+    }
+
+    fn load(&self) -> Result<()> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&self.dir)?.map(|e| e.map(|e| e.path())).collect::<std::io::Result<_>>()?;
+        files.sort();
+        let mut responses = self.responses.lock().unwrap();
+        let mut exchanges = self.exchanges.lock().unwrap();
+        for path in files {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string();
+            if let Some(stem) = name.strip_suffix("Rq.xml") {
+                let request = std::fs::read_to_string(&path)?;
+                let response = path.with_file_name(format!("{}Rs.xml", stem));
+                if !response.is_file() {
+                    bail!("{} has no recorded response", name);
+                }
+                responses.entry(request_key(&request)?).or_default().push_back(response);
+            } else if name.ends_with(".json") {
+                let text = std::fs::read_to_string(&path)?;
+                exchanges.push(serde_json::from_str(&text).with_context(|| format!("{} isn't a recorded HTTP call", name))?);
+            }
+        }
+        log::info!("[CAPTURE] {} qbXML responses and {} HTTP calls to replay", responses.values().map(VecDeque::len).sum::<usize>(), exchanges.len());
+        Ok(())
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{:04}-{}", self.next.fetch_add(1, Ordering::SeqCst), name))
+    }
+
+    // recording must never break the run it records
+    fn write(&self, path: &Path, contents: &str) {
+        if let Err(e) = std::fs::write(path, contents) {
+            log::warn!("[CAPTURE] Failed to write {}: {}", path.display(), e);
+        }
+    }
+
+    fn record_qbxml(&self, request: &str, response: &str) {
+        let element = request_key(request).unwrap_or_else(|_| "Unknown".to_string());
+        let first = element.split('+').next().unwrap_or_default().trim_end_matches("Rq");
+        let request_path = self.path(&format!("{}Rq.xml", first));
+        let response_path = request_path.with_file_name(request_path.file_name().unwrap().to_string_lossy().replace("Rq.xml", "Rs.xml"));
+        self.write(&request_path, request);
+        self.write(&response_path, response);
+    }
+
+    fn replay_qbxml(&self, request: &str) -> Result<String> {
+        let key = request_key(request)?;
+        let path = self.responses.lock().unwrap().get_mut(&key).and_then(VecDeque::pop_front)
+            .with_context(|| format!("The capture has no more responses to {}", key))?;
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))
+    }
+
+    fn record_http(&self, exchange: &Exchange) {
+        let path = self.path(&format!("{}.json", exchange.kind));
+        match serde_json::to_string_pretty(exchange) {
+            Ok(text) => self.write(&path, &text),
+            Err(e) => log::warn!("[CAPTURE] Failed to record a {} call: {}", exchange.kind, e),
+        }
+    }
+
+    // the recorded exchange with the same payload, else the next one of the same kind
+    fn replay_http(&self, kind: &str, request: &Value) -> Result<(u16, String)> {
+        let mut exchanges = self.exchanges.lock().unwrap();
+        let same_kind = |e: &Exchange| e.kind == kind;
+        let index = exchanges.iter().position(|e| same_kind(e) && e.request == *request)
+            .or_else(|| exchanges.iter().position(same_kind))
+            .with_context(|| format!("The capture has no more {} calls", kind))?;
+        let exchange = exchanges.remove(index);
+        if exchange.request != *request {
+            log::warn!("[CAPTURE] This {} payload differs from the recorded one\n  recorded: {}\n  now:      {}", kind, exchange.request, request);
+        }
+        Ok((exchange.status, exchange.response))
+    }
+}
+
+// the request elements of a qbXML / qbposXML message, e.g. "AccountQueryRq" or
+// "InvoiceQueryRq+CurrencyQueryRq"; responses are matched on these, not on dates that move
+fn request_key(request: &str) -> Result<String> {
+    let doc = roxmltree::Document::parse(request).context("Failed to parse the qbXML request")?;
+    let msgs = doc.root_element().children().find(|n| n.is_element() && n.tag_name().name().ends_with("MsgsRq"))
+        .context("The qbXML request has no request elements")?;
+    Ok(msgs.children().filter(|n| n.is_element()).map(|n| n.tag_name().name()).collect::<Vec<_>>().join("+"))
+}
+
+// a payload as it is recorded and compared: JSON without its volatile fields, anything else as text
+fn normalize(body: &[u8]) -> Value {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(mut json) => {
+            if let Some(fields) = json.as_object_mut() {
+                for name in VOLATILE_FIELDS {
+                    fields.remove(*name);
+                }
+            }
+            json
+        }
+        Err(_) => Value::from(String::from_utf8_lossy(body).into_owned()),
+    }
+}
+
+fn host(url: &str) -> String {
+    reqwest::Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_string)).unwrap_or_default()
+}
+
+// what a GET is recorded and matched by: host and path, without the query or fragment
+fn host_and_path(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(u) => format!("{}{}", u.host_str().unwrap_or_default(), u.path()),
+        Err(_) => url.split(['?', '#']).next().unwrap_or_default().to_string(),
+    }
+}
+
+/// With --replay, the recorded answer to a `kind` call carrying `body` (instead of sending it)
+pub fn replayed_http(kind: &str, body: &[u8]) -> Option<Result<(u16, String)>> {
+    let capture = replaying_capture()?;
+    Some(capture.replay_http(kind, &normalize(body)))
+}
+
+/// With --record, notes a `kind` call to `url` carrying `body` and what it was answered
+pub fn record_http(kind: &str, url: &str, body: &[u8], status: u16, response: &str) {
+    if let Some(capture) = recording() {
+        capture.record_http(&Exchange { kind: kind.to_string(), host: host(url), request: normalize(body), status, response: response.to_string() });
+    }
+}
+
+/// Sends a JSON request for a destination or exporter, or with --replay answers it from the capture;
+/// the status and response text either way
+pub async fn send(kind: &str, url: &str, body: &Value, request: reqwest::RequestBuilder) -> Result<(reqwest::StatusCode, String)> {
+    send_bytes(kind, url, serde_json::to_vec(body)?, request.header(reqwest::header::CONTENT_TYPE, "application/json")).await
+}
+
+/// Like `send`, for a body that isn't a single JSON value, such as a batch of JSON Lines
+pub async fn send_bytes(kind: &str, url: &str, bytes: Vec<u8>, request: reqwest::RequestBuilder) -> Result<(reqwest::StatusCode, String)> {
+    if let Some(replayed) = replayed_http(kind, &bytes) {
+        let (status, text) = replayed?;
+        return Ok((reqwest::StatusCode::from_u16(status)?, text));
+    }
+    let res = request.body(bytes.clone()).send().await?;
+    let status = res.status();
+    let text = res.text().await.unwrap_or_default();
+    record_http(kind, url, &bytes, status.as_u16(), &text);
+    Ok((status, text))
+}
+
+/// Sends a GET, such as a reconciliation source's, or with --replay answers it from the capture;
+/// matched on the URL's host and path
+pub async fn get(kind: &str, url: &str, request: reqwest::RequestBuilder) -> Result<(reqwest::StatusCode, String)> {
+    let recorded = host_and_path(url);
+    if let Some(replayed) = replayed_http(kind, recorded.as_bytes()) {
+        let (status, text) = replayed?;
+        return Ok((reqwest::StatusCode::from_u16(status)?, text));
+    }
+    let res = request.send().await?;
+    let status = res.status();
+    let text = res.text().await.unwrap_or_default();
+    record_http(kind, url, recorded.as_bytes(), status.as_u16(), &text);
+    Ok((status, text))
+}
+
+/// With --replay, the rows recorded for an ODBC query (instead of running it)
+pub fn replayed_odbc(sql: &str) -> Option<Result<Vec<Vec<Option<String>>>>> {
+    let rows = replayed_http(ODBC, sql.as_bytes())?
+        .and_then(|(_, rows)| serde_json::from_str(&rows).context("The recorded ODBC rows aren't valid"));
+    Some(rows)
+}
+
+/// Whether ODBC rows should be kept for `record_odbc`
+pub fn recording_odbc() -> bool {
+    recording().is_some()
+}
+
+/// With --record, notes the rows an ODBC query returned
+pub fn record_odbc(sql: &str, rows: &[Vec<Option<String>>]) {
+    match serde_json::to_string(rows) {
+        // the connection string can hold a password, so no host is kept
+        Ok(rows) => record_http(ODBC, "", sql.as_bytes(), 200, &rows),
+        Err(e) => log::warn!("[CAPTURE] Failed to record an ODBC query: {}", e),
+    }
+}
+
+/// The backend's processor, recording with --record; with --replay the capture stands in for it
+pub fn wrap(processor: Box<dyn RequestProcessor>) -> Box<dyn RequestProcessor> {
+    match recording() {
+        Some(capture) => capture.recorder(processor),
+        None => processor,
+    }
+}
+
+/// With --replay, the processor answering from the capture, so no backend is connected at all
+pub fn replay_processor() -> Option<Box<dyn RequestProcessor>> {
+    Some(replaying_capture()?.replayer())
+}
+
+struct Recording {
+    inner: Box<dyn RequestProcessor>,
+    capture: &'static Capture,
+}
+
+impl RequestProcessor for Recording {
+    fn open_connection(&self, app_id: &str, app_name: &str) -> Result<()> {
+        self.inner.open_connection(app_id, app_name)
+    }
+
+    fn begin_session(&self, company_file: &str, file_mode: FileMode) -> Result<String> {
+        self.inner.begin_session(company_file, file_mode)
+    }
+
+    fn process_request(&self, ticket: &str, request: &str) -> Result<String> {
+        let response = self.inner.process_request(ticket, request)?;
+        self.capture.record_qbxml(request, &response);
+        Ok(response)
+    }
+
+    fn end_session(&self, ticket: &str) -> Result<()> {
+        self.inner.end_session(ticket)
+    }
+
+    fn close_connection(&self) -> Result<()> {
+        self.inner.close_connection()
+    }
+
+    fn get_current_company_file_name(&self, ticket: &str) -> Result<String> {
+        let name = self.inner.get_current_company_file_name(ticket)?;
+        self.capture.write(&self.capture.dir.join(COMPANY_FILE), &name);
+        Ok(name)
+    }
+}
+
+struct Replaying {
+    capture: &'static Capture,
+}
+
+impl RequestProcessor for Replaying {
+    fn open_connection(&self, _app_id: &str, _app_name: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn begin_session(&self, _company_file: &str, _file_mode: FileMode) -> Result<String> {
+        Ok("replay-session".to_string())
+    }
+
+    fn process_request(&self, _ticket: &str, request: &str) -> Result<String> {
+        self.capture.replay_qbxml(request)
+    }
+
+    fn end_session(&self, _ticket: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn close_connection(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_current_company_file_name(&self, _ticket: &str) -> Result<String> {
+        std::fs::read_to_string(self.capture.dir.join(COMPANY_FILE)).context("The capture has no company file name")
+    }
+}
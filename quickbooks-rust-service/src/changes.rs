@@ -0,0 +1,118 @@
+// [schedule] watch_changes_secs: start the next cycle soon after something changes in QuickBooks
+// instead of waiting out the interval
+//
+// The SDK can push data events, but only to an out-of-process COM server registered with QuickBooks
+// for the callback, which qb_sync is not. So while the daemon waits on its open session it asks
+// instead: any transaction or account modified since the last cycle started? One TransactionQuery
+// (at most one id back) and one AccountQuery on modified date answer that cheaply. Deleted
+// transactions don't show up there and wait for the scheduled cycle.
+//
+// [quickbooks] account_reuse_secs asks the same before the account query every sync block shares,
+// the heaviest request of a cycle on a large chart of accounts. Deleted transactions and accounts
+// are asked about too, as they move balances without modifying anything. When nothing changed since
+// the last account query was sent, its response is used again, for at most account_reuse_secs. It
+// is kept in memory per company file, so it pays off in daemon mode on short intervals.
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::deleted_txns;
+use crate::qb_session::QbSession;
+use crate::qbxml;
+use crate::response_cache;
+
+// the last account query: company file, when it was sent, when it was answered, the response
+struct KeptAccounts {
+    company: String,
+    sent: DateTime<Local>,
+    answered: Instant,
+    response: String,
+}
+
+static LAST_ACCOUNTS: Mutex<Option<KeptAccounts>> = Mutex::new(None);
+
+/// Whether any transaction or account was added or changed after `since`. Like keep_alive, an
+/// error means the session is no longer usable.
+pub fn any_since(session: &QbSession, since: &DateTime<Local>) -> Result<bool> {
+    let response = session.processor.process_request(&session.ticket, &request(since))?;
+    let doc = qbxml::parse_document(&response)?;
+    let transactions = qbxml::find_response(&doc, "TransactionQueryRs")?;
+    let accounts = qbxml::find_response(&doc, "AccountQueryRs")?;
+    Ok(transactions.children().any(|n| n.has_tag_name("TransactionRet"))
+        || accounts.children().any(|n| n.has_tag_name("AccountRet")))
+}
+
+/// The account query's response, or the last one when [quickbooks] account_reuse_secs is set and
+/// nothing that moves a balance happened since it was sent
+pub fn account_xml(session: &QbSession, config: &Config) -> Result<Option<String>> {
+    let Some(max_age) = config.quickbooks.account_reuse_secs.filter(|secs| *secs > 0).map(Duration::from_secs) else {
+        return session.processor.get_account_xml(&session.ticket);
+    };
+    let company = config.quickbooks.company_file.to_ascii_lowercase();
+    let kept = LAST_ACCOUNTS.lock().unwrap_or_else(|e| e.into_inner()).as_ref()
+        .filter(|kept| kept.company == company && kept.answered.elapsed() < max_age)
+        .map(|kept| (kept.sent, kept.response.clone()));
+    if let Some((sent, response)) = kept {
+        match balances_changed_since(session, &sent) {
+            Ok(false) => {
+                log::info!("[QBXML] Nothing changed in QuickBooks since {}; reusing that account query", sent.format("%H:%M:%S"));
+                return Ok(Some(response));
+            }
+            Ok(true) => log::debug!("[QBXML] QuickBooks data changed since {}; querying accounts", sent.format("%H:%M:%S")),
+            // the account query below reports a session that is really gone
+            Err(e) => log::debug!("[QBXML] Change check failed; querying accounts: {:#}", e),
+        }
+    }
+    let sent = Local::now();
+    let response = session.processor.get_account_xml(&session.ticket)?;
+    *LAST_ACCOUNTS.lock().unwrap_or_else(|e| e.into_inner()) = response.as_ref()
+        .filter(|response| !response_cache::has_error(response))
+        .map(|response| KeptAccounts { company, sent, answered: Instant::now(), response: response.clone() });
+    Ok(response)
+}
+
+// any_since, plus any transaction or account deleted after `since`
+fn balances_changed_since(session: &QbSession, since: &DateTime<Local>) -> Result<bool> {
+    let from = qbxml::format_datetime(since);
+    let txn_types: String = deleted_txns::TXN_DEL_TYPES.iter().map(|t| format!("\n        <TxnDelType>{}</TxnDelType>", t)).collect();
+    let deleted = format!(
+        r#"
+      <TxnDeletedQueryRq>{txn_types}
+        <DeletedDateRangeFilter><FromDeletedDate>{from}</FromDeletedDate></DeletedDateRangeFilter>
+      </TxnDeletedQueryRq>
+      <ListDeletedQueryRq>
+        <ListDelType>Account</ListDelType>
+        <DeletedDateRangeFilter><FromDeletedDate>{from}</FromDeletedDate></DeletedDateRangeFilter>
+      </ListDeletedQueryRq>"#);
+    let response = session.processor.process_request(&session.ticket, &qbxml::envelope(&format!("{}{}", messages(since), deleted)))?;
+    let doc = qbxml::parse_document(&response)?;
+    let found = |rs: &str, ret: &str| -> Result<bool> {
+        Ok(qbxml::find_response(&doc, rs)?.children().any(|n| n.has_tag_name(ret)))
+    };
+    Ok(found("TransactionQueryRs", "TransactionRet")?
+        || found("AccountQueryRs", "AccountRet")?
+        || found("TxnDeletedQueryRs", "TxnDeletedRet")?
+        || found("ListDeletedQueryRs", "ListDeletedRet")?)
+}
+
+fn request(since: &DateTime<Local>) -> String {
+    qbxml::envelope(&messages(since))
+}
+
+fn messages(since: &DateTime<Local>) -> String {
+    let from = qbxml::format_datetime(since);
+    format!(
+        r#"      <TransactionQueryRq>
+        <MaxReturned>1</MaxReturned>
+        <TransactionModifiedDateRangeFilter><FromModifiedDate>{from}</FromModifiedDate></TransactionModifiedDateRangeFilter>
+        <IncludeRetElement>TxnID</IncludeRetElement>
+      </TransactionQueryRq>
+      <AccountQueryRq>
+        <MaxReturned>1</MaxReturned>
+        <FromModifiedDate>{from}</FromModifiedDate>
+        <IncludeRetElement>ListID</IncludeRetElement>
+      </AccountQueryRq>"#)
+}
@@ -0,0 +1,173 @@
+// [quickbooks.circuit_breaker]: stop calling a QuickBooks that has stopped answering properly
+//
+// Every SDK call of a session goes through `Guarded`. After `failures` failed calls in a row, or a
+// single call slower than max_request_secs or answering with more than max_response_kb, the circuit
+// opens: for cooldown_secs every call fails straight away with the reason instead of waiting on
+// QuickBooks, so a wedged company file costs a daemon one short failed cycle per interval rather
+// than an hour of timeouts. The first call after the cooldown is a trial; it closes the circuit
+// when it succeeds and opens it again when it doesn't.
+//
+// QuickBooks being busy with another user isn't a fault and doesn't count, and neither does
+// BeginSession failing, since a QuickBooks that is still loading the company file fails it on
+// purpose. The state is kept per company file for the life of the process, as each cycle
+// connects afresh.
+
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::config::{CircuitBreakerConfig, Config};
+use crate::file_mode::FileMode;
+use crate::qb_backend::RequestProcessor;
+use crate::qbxml_safe::com_error;
+
+pub const DEFAULT_FAILURES: u32 = 3;
+pub const DEFAULT_COOLDOWN_SECS: u64 = 900;
+
+static CIRCUITS: OnceLock<Mutex<HashMap<String, State>>> = OnceLock::new();
+
+#[derive(Default)]
+struct State {
+    failures: u32,
+    open_until: Option<Instant>,
+    reason: String,
+    // opened since take_opened last looked
+    unannounced: bool,
+}
+
+fn with_state<T>(key: &str, f: impl FnOnce(&mut State) -> T) -> T {
+    let mut circuits = CIRCUITS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    f(circuits.entry(key.to_string()).or_default())
+}
+
+fn key(config: &Config) -> String {
+    config.quickbooks.company_file.to_ascii_lowercase()
+}
+
+/// `processor` behind the circuit breaker when [quickbooks.circuit_breaker] is set
+pub fn guard(processor: Box<dyn RequestProcessor>, config: &Config) -> Box<dyn RequestProcessor> {
+    match &config.quickbooks.circuit_breaker {
+        Some(limits) => Box::new(Guarded { inner: processor, key: key(config), limits: limits.clone() }),
+        None => processor,
+    }
+}
+
+/// Err with the reason while the circuit of the config's company file is open
+pub fn check(config: &Config) -> Result<()> {
+    check_key(&key(config))
+}
+
+fn check_key(key: &str) -> Result<()> {
+    with_state(key, |state| match state.open_until {
+        Some(until) if until > Instant::now() => {
+            let left = until - Instant::now();
+            bail!("QuickBooks circuit breaker is open for another {}s after: {}", left.as_secs().max(1), state.reason)
+        }
+        _ => Ok(()),
+    })
+}
+
+/// Why the circuit opened, once per opening, so the notify commands run once
+pub fn take_opened(config: &Config) -> Option<String> {
+    with_state(&key(config), |state| std::mem::take(&mut state.unannounced).then(|| state.reason.clone()))
+}
+
+pub struct Guarded {
+    inner: Box<dyn RequestProcessor>,
+    key: String,
+    limits: CircuitBreakerConfig,
+}
+
+impl Guarded {
+    fn call<T>(&self, method: &str, counts_failures: bool, size: impl Fn(&T) -> usize, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        check_key(&self.key)?;
+        let started = Instant::now();
+        let result = f();
+        let elapsed = started.elapsed();
+        let value = match result {
+            Err(e) => {
+                if counts_failures && !com_error::is_busy(&e) {
+                    self.failed(format!("{} failed: {:#}", method, e));
+                }
+                return Err(e);
+            }
+            Ok(value) => value,
+        };
+        if let Some(max_kb) = self.limits.max_response_kb {
+            let kb = size(&value).div_ceil(1024) as u64;
+            if kb > max_kb {
+                let reason = format!("{} answered with {} KB, more than max_response_kb = {}", method, kb, max_kb);
+                self.open(reason.clone());
+                bail!("{}", reason);
+            }
+        }
+        match self.limits.max_request_secs {
+            Some(max_secs) if elapsed > Duration::from_secs(max_secs) => {
+                self.open(format!("{} took {}s, more than max_request_secs = {}", method, elapsed.as_secs(), max_secs));
+            }
+            _ => with_state(&self.key, |state| {
+                if state.open_until.take().is_some() {
+                    log::info!("[CIRCUIT] QuickBooks answered again; circuit closed");
+                }
+                state.failures = 0;
+            }),
+        }
+        Ok(value)
+    }
+
+    fn failed(&self, reason: String) {
+        let threshold = self.limits.failures.unwrap_or(DEFAULT_FAILURES).max(1);
+        let failures = with_state(&self.key, |state| {
+            state.failures += 1;
+            state.failures
+        });
+        // after the cooldown one failure is enough, since the count was never reset
+        if failures >= threshold {
+            self.open(format!("{} failed calls in a row, the last: {}", failures, reason));
+        }
+    }
+
+    fn open(&self, reason: String) {
+        let cooldown = Duration::from_secs(self.limits.cooldown_secs.unwrap_or(DEFAULT_COOLDOWN_SECS));
+        log::error!("[CIRCUIT] Leaving QuickBooks alone for {}s: {}", cooldown.as_secs(), reason);
+        with_state(&self.key, |state| {
+            state.open_until = Some(Instant::now() + cooldown);
+            state.reason = reason;
+            state.unannounced = true;
+        });
+    }
+}
+
+impl RequestProcessor for Guarded {
+    fn open_connection(&self, app_id: &str, app_name: &str) -> Result<()> {
+        self.call("OpenConnection", true, |_| 0, || self.inner.open_connection(app_id, app_name))
+    }
+
+    fn begin_session(&self, company_file: &str, file_mode: FileMode) -> Result<String> {
+        self.call("BeginSession", false, |_| 0, || self.inner.begin_session(company_file, file_mode))
+    }
+
+    fn process_request(&self, ticket: &str, request: &str) -> Result<String> {
+        if let Some(max_kb) = self.limits.max_request_kb {
+            let kb = request.len().div_ceil(1024) as u64;
+            if kb > max_kb {
+                bail!("The request is {} KB, more than [quickbooks.circuit_breaker] max_request_kb = {}; it wasn't sent", kb, max_kb);
+            }
+        }
+        self.call("ProcessRequest", true, String::len, || self.inner.process_request(ticket, request))
+    }
+
+    // ending and closing always go through, so a session is cleaned up even with the circuit open
+    fn end_session(&self, ticket: &str) -> Result<()> {
+        self.inner.end_session(ticket)
+    }
+
+    fn close_connection(&self) -> Result<()> {
+        self.inner.close_connection()
+    }
+
+    fn get_current_company_file_name(&self, ticket: &str) -> Result<String> {
+        self.call("GetCurrentCompanyFileName", true, String::len, || self.inner.get_current_company_file_name(ticket))
+    }
+}
@@ -0,0 +1,189 @@
+// Command line interface for qb_sync
+
+use clap::{Parser, Subcommand};
+use rust_decimal::Decimal;
+use std::path::PathBuf;
+
+use crate::config;
+use crate::dump;
+use crate::secrets;
+
+#[derive(Debug, Parser)]
+#[command(name = "qb_sync", version, about = "Sync QuickBooks Desktop account balances to Google Sheets")]
+pub struct Cli {
+    /// Path to the base config file
+    #[arg(short, long, value_name = "PATH", default_value = "config/config.toml")]
+    pub config: PathBuf,
+
+    /// Run every tenant config (*.toml) in this directory once, one after another, instead of --config
+    #[arg(long, value_name = "DIR")]
+    pub config_dir: Option<PathBuf>,
+
+    /// Environment overlay to merge over the base config (config.<ENV>.toml next to it)
+    #[arg(long, value_name = "ENV", env = config::ENV_VAR)]
+    pub env: Option<String>,
+
+    /// Override a single config value, e.g. --set quickbooks.company_file=AUTO (repeatable)
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub overrides: Vec<(String, String)>,
+
+    /// Skip the blocks an interrupted cycle (crash, reboot, stop) already completed
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Let clear_below, history retention and staging tabs remove rows, as [google_sheets] confirm_destructive = true does
+    #[arg(long)]
+    pub yes: bool,
+
+    /// Run one cycle, saving every qbXML request / response and outgoing HTTP payload into this (empty) directory
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["replay", "config_dir"])]
+    pub record: Option<PathBuf>,
+
+    /// Rerun one cycle from a --record directory, without QuickBooks or the network
+    #[arg(long, value_name = "DIR", conflicts_with = "config_dir")]
+    pub replay: Option<PathBuf>,
+
+    /// Print setup instructions and enable debug logging
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Log only warnings, errors, progress lines and the run totals
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run one sync cycle and exit, even when the config has a [schedule]
+    Run,
+
+    /// Keep running cycles every [schedule] interval_secs until stopped (needs [schedule])
+    Daemon,
+
+    /// Load and check --config (or every tenant of --config-dir) without running anything
+    ValidateConfig,
+
+    /// Query the chart of accounts and print each account's full name, type and balance
+    ListAccounts {
+        /// Only accounts of this AccountType, e.g. Bank or CreditCard
+        #[arg(long = "type", value_name = "TYPE")]
+        account_type: Option<String>,
+    },
+
+    /// Check the setup end to end: the config loads, QuickBooks answers and the webapp can read every sheet the sync blocks write
+    Selftest,
+
+    /// Print a shell completion script, e.g. qb_sync completions powershell >> $PROFILE
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    /// Encrypt a secret (e.g. the webapp api_key) and print the enc:... value for config.toml
+    EncryptSecret {
+        /// dpapi ties the value to this Windows user; age works on any machine holding the identity file
+        #[arg(long, value_enum, default_value = "dpapi")]
+        method: secrets::Method,
+
+        /// age recipient (public key, age1...) to encrypt to; required with --method age
+        #[arg(long, value_name = "AGE1...")]
+        recipient: Option<String>,
+
+        /// DPAPI only: let any account on this machine decrypt, e.g. when the service runs as another user
+        #[arg(long)]
+        machine: bool,
+
+        /// The secret to encrypt; read from stdin when omitted so it stays out of shell history (not
+        /// echoed when typed at a terminal)
+        value: Option<String>,
+    },
+
+    /// List accounts whose balance changed between the last two runs (needs [balance_diff])
+    Diff {
+        /// Ignore changes smaller than this; defaults to [balance_diff] min_change or 0.01
+        #[arg(long, value_name = "AMOUNT")]
+        min_change: Option<Decimal>,
+    },
+
+    /// Query the chart of accounts and print every account (name, number, type, balance, ...) without syncing
+    Dump {
+        #[arg(long, value_enum, default_value = "json")]
+        format: dump::Format,
+
+        /// Write to this file instead of stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+
+    /// Add or update [[sync_blocks]] in --config from rows of account name, spreadsheet id, sheet and cell
+    ImportBlocks {
+        /// CSV file of the rows, with or without a header row
+        #[arg(value_name = "CSV", required_unless_present = "from_sheet", conflicts_with = "from_sheet")]
+        csv: Option<PathBuf>,
+
+        /// Read the rows from a tab of this spreadsheet instead, through the webapp
+        #[arg(long, value_name = "SPREADSHEET_ID")]
+        from_sheet: Option<String>,
+
+        /// The tab --from-sheet reads
+        #[arg(long, value_name = "NAME", default_value = "config", requires = "from_sheet")]
+        tab: String,
+
+        /// Print the merged config instead of saving it
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Upload the companion Apps Script as a web app and write its URL into --config
+    DeployWebapp {
+        /// OAuth client file (client_secret_....json) of a Desktop app client from the Google Cloud console
+        #[arg(long, value_name = "PATH")]
+        credentials: PathBuf,
+        /// Apps Script project to update; defaults to [google_sheets] script_id, else a new project
+        #[arg(long, value_name = "ID")]
+        script_id: Option<String>,
+        /// Title of a newly created project
+        #[arg(long, default_value = "QuickBooks Sheets Sync")]
+        title: String,
+        /// Print the planned project, content and deployment without signing in or changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Print a report from the history file, without connecting to QuickBooks
+    Report {
+        #[command(subcommand)]
+        report: Report,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Report {
+    /// Success rate, mean duration and most common errors of every block, least reliable first
+    Reliability {
+        /// How many days back to look, today included
+        #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..))]
+        days: u32,
+    },
+}
+
+impl Cli {
+    /// The --set values, with --yes as one more
+    pub fn config_overrides(&self) -> Vec<(String, String)> {
+        let mut overrides = self.overrides.clone();
+        if self.yes {
+            overrides.push(("google_sheets.confirm_destructive".to_string(), "true".to_string()));
+        }
+        overrides
+    }
+}
+
+fn parse_key_value(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", arg)),
+    }
+}
@@ -0,0 +1,120 @@
+// [[close_snapshots]]: account balances captured at period close, e.g. on the last day of the month
+// at 23:55, into a cell picked by the period such as "{month_name}!B{row}"
+//
+// A close is due once its time has passed; the first cycle after that takes it and the history
+// file remembers it, so each close is written once. A close missed by more than catch_up_hours (the
+// machine was off over month-end) is skipped with a warning rather than filled with balances from
+// days later. In daemon mode the next cycle is brought forward to the next close.
+
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, Months, NaiveDate, NaiveDateTime, NaiveTime};
+use rust_decimal::Decimal;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::a1;
+use crate::config::{CloseSnapshotConfig, Config};
+use crate::dates;
+use crate::template::{self, TemplateValue, TemplateVars};
+
+pub const DEFAULT_DATE: &str = "end-of-month";
+pub const DEFAULT_TIME: &str = "23:55";
+const DEFAULT_FIRST_ROW: u32 = 2;
+const DEFAULT_CATCH_UP_HOURS: u64 = 24;
+
+/// What a snapshot should do this cycle
+pub enum Due {
+    NotYet,
+    Take(NaiveDateTime),
+    // the close passed too long ago to be worth taking
+    Missed(NaiveDateTime),
+}
+
+/// Checks every snapshot's date, time and target at load, rather than at its first close
+pub fn validate(config: &Config) -> Result<()> {
+    let mut names = HashSet::new();
+    for snapshot in &config.close_snapshots {
+        if !names.insert(snapshot.label()) {
+            bail!("Two [[close_snapshots]] are named '{}'", snapshot.label());
+        }
+        if snapshot.accounts.is_empty() {
+            bail!("Close snapshot '{}' has no accounts", snapshot.label());
+        }
+        let close = closes(snapshot, dates::now(config)?)?[0];
+        for (i, account) in snapshot.accounts.iter().enumerate() {
+            target(snapshot, close, account, i)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `snapshot` has a close to take now, given the last close it took
+pub fn due(snapshot: &CloseSnapshotConfig, config: &Config, taken: Option<NaiveDate>) -> Result<Due> {
+    let now = dates::now(config)?;
+    let Some(close) = closes(snapshot, now)?.into_iter().rfind(|c| *c <= now) else {
+        return Ok(Due::NotYet);
+    };
+    if taken.is_some_and(|taken| taken >= close.date()) {
+        return Ok(Due::NotYet);
+    }
+    let catch_up = chrono::Duration::hours(snapshot.catch_up_hours.unwrap_or(DEFAULT_CATCH_UP_HOURS) as i64);
+    Ok(if now - close > catch_up { Due::Missed(close) } else { Due::Take(close) })
+}
+
+/// How long until the next close of any snapshot, for the daemon to wake up for
+pub fn until_next(config: &Config) -> Option<Duration> {
+    let now = dates::now(config).ok()?;
+    config.close_snapshots.iter()
+        .filter_map(|snapshot| closes(snapshot, now).ok()?.into_iter().find(|c| *c > now))
+        .min()
+        .and_then(|next| (next - now).to_std().ok())
+}
+
+/// The sheet and cell of the `index`th account's balance for `close`
+pub fn target(snapshot: &CloseSnapshotConfig, close: NaiveDateTime, account: &str, index: usize) -> Result<(String, String)> {
+    let row = snapshot.first_row.unwrap_or(DEFAULT_FIRST_ROW) + index as u32;
+    let date = close.date();
+    let vars = TemplateVars::from([
+        ("account_name", TemplateValue::Text(account.to_string())),
+        ("row", TemplateValue::Number(Decimal::from(row))),
+        ("year", TemplateValue::Text(date.format("%Y").to_string())),
+        ("month", TemplateValue::Text(date.format("%m").to_string())),
+        ("month_name", TemplateValue::Text(date.format("%B").to_string())),
+        ("month_abbr", TemplateValue::Text(date.format("%b").to_string())),
+        ("quarter", TemplateValue::Number(Decimal::from(date.month0() / 3 + 1))),
+        ("date", TemplateValue::Text(date.format("%Y-%m-%d").to_string())),
+    ]);
+    let context = || format!("Close snapshot '{}' target", snapshot.label());
+    let rendered = template::render(&snapshot.target, &vars).with_context(context)?;
+    let reference = a1::parse(&rendered).with_context(context)?;
+    let Some(sheet) = reference.sheet.clone() else {
+        bail!("Close snapshot '{}': target \"{}\" needs a sheet, e.g. \"{{month_name}}!B{{row}}\"", snapshot.label(), snapshot.target);
+    };
+    if reference.is_range() {
+        bail!("Close snapshot '{}': target \"{}\" is a range; give one cell", snapshot.label(), rendered);
+    }
+    Ok((sheet, reference.start.to_string()))
+}
+
+// The snapshot's closes from last month to next, in order: its date expression evaluated from the
+// first of each month, at its time of day
+fn closes(snapshot: &CloseSnapshotConfig, now: NaiveDateTime) -> Result<Vec<NaiveDateTime>> {
+    let expr = snapshot.date.as_deref().unwrap_or(DEFAULT_DATE);
+    let time_text = snapshot.time.as_deref().unwrap_or(DEFAULT_TIME);
+    let time = NaiveTime::parse_from_str(time_text.trim(), "%H:%M")
+        .with_context(|| format!("Close snapshot '{}': invalid time '{}', expected HH:MM", snapshot.label(), time_text))?;
+    let this_month = now.date().with_day(1).unwrap_or_default();
+    let months = [
+        this_month.checked_sub_months(Months::new(1)),
+        Some(this_month),
+        this_month.checked_add_months(Months::new(1)),
+    ];
+    let mut closes = Vec::new();
+    for first in months.into_iter().flatten() {
+        let date = dates::resolve(expr, first).with_context(|| format!("Close snapshot '{}'", snapshot.label()))?;
+        closes.push(date.and_time(time));
+    }
+    closes.sort();
+    closes.dedup();
+    Ok(closes)
+}
@@ -1,38 +1,1424 @@
-use anyhow::{Context, Result};
-use figment::{Figment, providers::{Format, Toml}};
+use anyhow::{anyhow, Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::Timelike;
+use figment::{Figment, providers::{Format, Serialized, Toml}, value::Value};
+use rust_decimal::{Decimal, RoundingStrategy};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::a1;
+use crate::amounts::{self, NumberFormat};
+use crate::expr;
+use crate::redact::Redaction;
+use crate::run_window::Window;
+use crate::secrets;
+
+/// Environment variable consulted when no `--env` argument is given
+pub const ENV_VAR: &str = "QB_SYNC_ENV";
+
+/// Current config layout. Bump it, and add a step to `migrate`, whenever a change would stop
+/// older config files from loading.
+pub const CONFIG_VERSION: u32 = 2;
+
+// the most [quickbooks] parallel_sessions; each is a connection QuickBooks has to serve
+const MAX_PARALLEL_SESSIONS: u32 = 8;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
+    // layout version the file was written for; files without one are detected in `migrate`
+    pub version: Option<u32>,
+    // a key no field reads (e.g. a misspelt cell_adress) fails the load instead of being dropped;
+    // defaults on for files that declare a version, off for older ones upgraded by `migrate`
+    pub strict: Option<bool>,
+    // two blocks writing the same cell only log a warning instead of failing the load
+    pub allow_collisions: Option<bool>,
+    // sync blocks flip the sign of income, liability and equity balances (default false)
+    pub normalize_signs: Option<bool>,
+    // how sync block values and report amounts are rounded before they're written; a block can
+    // set its own. Unrounded when neither is set
+    pub rounding: Option<RoundingConfig>,
+    // when blocks run, e.g. "07:00-19:00" and ["Mon-Fri"]; a block can set its own (see run_window)
+    pub active_hours: Option<String>,
+    #[serde(default)]
+    pub active_days: Vec<String>,
+    // accounts whose balances may be written anywhere: empty allows every account, and denied wins.
+    // An entry covers the account's subaccounts
+    #[serde(default)]
+    pub allowed_accounts: Vec<String>,
+    #[serde(default)]
+    pub denied_accounts: Vec<String>,
     pub quickbooks: QuickBooksConfig,
     pub google_sheets: GoogleSheetsConfig,
     pub sync_blocks: Vec<AccountSyncConfig>,
     pub timestamp_blocks: Vec<TimestampConfig>,
+    #[serde(default)]
+    pub report_blocks: Vec<ReportBlockConfig>,
+    #[serde(default)]
+    pub transaction_blocks: Vec<TransactionBlockConfig>,
+    #[serde(default)]
+    pub list_blocks: Vec<ListBlockConfig>,
+    #[serde(default)]
+    pub raw_qbxml_blocks: Vec<RawQbxmlBlockConfig>,
+    #[serde(default)]
+    pub close_snapshots: Vec<CloseSnapshotConfig>,
+    #[serde(default)]
+    pub reconciliation_blocks: Vec<ReconciliationConfig>,
+    pub debug: Option<DebugConfig>,
+    pub secrets: Option<SecretsConfig>,
+    pub schedule: Option<ScheduleConfig>,
+    pub timeouts: Option<TimeoutsConfig>,
+    pub local_api: Option<LocalApiConfig>,
+    pub hooks: Option<HooksConfig>,
+    pub telemetry: Option<TelemetryConfig>,
+    pub pipeline: Option<PipelineConfig>,
+    pub routing: Option<RoutingConfig>,
+    pub history: Option<HistoryConfig>,
+    pub logging: Option<LoggingConfig>,
+    pub status_sheet: Option<StatusSheetConfig>,
+    pub company_info: Option<CompanyInfoConfig>,
+    pub watchdog: Option<WatchdogConfig>,
+    pub redaction: Option<RedactionConfig>,
+    pub name_mapping: Option<NameMappingConfig>,
+    pub balance_diff: Option<BalanceDiffConfig>,
+    pub microsoft_graph: Option<MicrosoftGraphConfig>,
+    pub airtable: Option<AirtableConfig>,
+    pub s3: Option<S3Config>,
+    pub google_drive: Option<GoogleDriveConfig>,
+    pub point_of_sale: Option<PointOfSaleConfig>,
+    pub odbc: Option<OdbcConfig>,
+    pub deleted_transactions: Option<DeletedTransactionsConfig>,
+    pub aging: Option<AgingConfig>,
+    pub account_classification: Option<AccountClassificationConfig>,
+    pub kpis: Option<KpisConfig>,
+    pub tray: Option<TrayConfig>,
+    pub dashboard: Option<DashboardConfig>,
+}
+
+// QuickBooks AccountType values, as AccountQueryRs reports them
+pub const ACCOUNT_TYPES: &[&str] = &[
+    "AccountsPayable", "AccountsReceivable", "Bank", "CostOfGoodsSold", "CreditCard", "Equity", "Expense",
+    "FixedAsset", "Income", "LongTermLiability", "NonPosting", "OtherAsset", "OtherCurrentAsset",
+    "OtherCurrentLiability", "OtherExpense", "OtherIncome",
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccountClassificationConfig {
+    // AccountType -> category, e.g. Bank = "Cash", LongTermLiability = "Debt", Expense = "OpEx"
+    #[serde(default)]
+    pub types: HashMap<String, String>,
+    // account FullName -> category, over its type's; "Loans" also takes "Loans:Truck"
+    #[serde(default)]
+    pub accounts: HashMap<String, String>,
+    // category of accounts neither maps; blank when omitted
+    pub default: Option<String>,
+}
+
+impl AccountClassificationConfig {
+    /// The category of an account: the most specific `accounts` entry covering it, else its type's,
+    /// else `default`
+    pub fn category(&self, full_name: &str, account_type: &str) -> Option<&str> {
+        let by_account = self.accounts.iter()
+            .filter(|(name, _)| within(full_name, name))
+            .max_by_key(|(name, _)| name.len());
+        by_account
+            .or_else(|| self.types.iter().find(|(t, _)| t.eq_ignore_ascii_case(account_type)))
+            .map(|(_, category)| category.as_str())
+            .or(self.default.as_deref())
+    }
+}
+
+// "East" covers "East" itself and its sub-entries such as "East:Boston", in any case
+fn within(full_name: &str, parent: &str) -> bool {
+    full_name.eq_ignore_ascii_case(parent)
+        || full_name.get(..parent.len() + 1).is_some_and(|prefix| prefix.eq_ignore_ascii_case(&format!("{}:", parent)))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AirtableConfig {
+    // personal access token with data.records:write on the bases used
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    // an access key allowed s3:PutObject on the buckets of jsonl destinations with a url
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    // "auto" for Cloudflare R2 (default us-east-1)
+    pub region: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MicrosoftGraphConfig {
+    // Entra ID app registration with the Files.ReadWrite.All / Sites.ReadWrite.All application permission
+    pub tenant_id: String,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PointOfSaleConfig {
+    // the POS SDK connection string, e.g. "Computer Name=STORE-PC;Company Data=My Store;Version=12"
+    pub connection_string: String,
+    // name the service is registered under in Point of Sale (default "QuickBooks Sync Service")
+    pub application_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OdbcConfig {
+    // for list blocks with backend = "odbc", e.g. "DSN=QuickBooks Data" (the DSN QODBC installs)
+    pub connection_string: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GoogleDriveConfig {
+    // JSON key file of a service account the Drive folder is shared with
+    pub service_account_key: String,
+    // the folder id from its URL (.../folders/<id>); CSV destination files are uploaded here
+    pub folder_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BalanceDiffConfig {
+    // changes smaller than this are ignored (default 0.01)
+    pub min_change: Option<Decimal>,
+    // also write the changes to this tab of the [status_sheet] spreadsheet
+    pub sheet_name: Option<String>,
+}
+
+impl BalanceDiffConfig {
+    pub fn label(&self) -> &str {
+        "Balance diff"
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompanyInfoConfig {
+    // company name, legal name, fiscal year start, file path and product, as label / value rows
+    pub spreadsheet_id: String,
+    pub sheet_name: String,
+    // top-left corner (default A1)
+    pub cell_address: Option<String>,
+}
+
+impl CompanyInfoConfig {
+    pub fn label(&self) -> &str {
+        "Company info"
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeletedTransactionsConfig {
+    pub spreadsheet_id: String,
+    // the audit tab; a row per deleted transaction is appended below its last row
+    pub sheet_name: String,
+    // TxnDelType values such as "Invoice" or "Check"; every type when empty
+    #[serde(default)]
+    pub txn_types: Vec<String>,
+    // how far back the first run looks, as a date or relative expression (default "start-of-month")
+    pub since: Option<String>,
+    // add sheet_name, with the column titles in its first row, when the spreadsheet has no such tab
+    pub create_sheet_if_missing: Option<bool>,
+}
+
+impl DeletedTransactionsConfig {
+    pub fn label(&self) -> &str {
+        "Deleted transactions"
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgingConfig {
+    // open invoice balances per customer and age bucket, as a table
+    pub spreadsheet_id: String,
+    pub sheet_name: String,
+    // top-left corner (default A1)
+    pub cell_address: Option<String>,
+    // the last day of every bucket but the open-ended last one (default [30, 60, 90]: 0-30, 31-60,
+    // 61-90 and 90+ days)
+    #[serde(default)]
+    pub buckets: Vec<u32>,
+    // age invoices from their due date instead of their invoice date; not yet due counts as 0 days
+    pub from_due_date: Option<bool>,
+    // a row per job instead of each job's balance counted under its customer
+    pub jobs: Option<bool>,
+    pub include_header: Option<bool>,
+    // a last row with every column's total (default true)
+    pub include_total: Option<bool>,
+    pub create_sheet_if_missing: Option<bool>,
+}
+
+impl AgingConfig {
+    pub fn label(&self) -> &str {
+        "Invoice aging"
+    }
+
+    pub fn bucket_days(&self) -> Vec<u32> {
+        match self.buckets.is_empty() {
+            true => vec![30, 60, 90],
+            false => self.buckets.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloseSnapshotConfig {
+    pub name: String,
+    pub spreadsheet_id: String,
+    // the close date as a relative date expression, e.g. "end-of-quarter" (default "end-of-month")
+    pub date: Option<String>,
+    // time of day the balances are taken, HH:MM (default "23:55")
+    pub time: Option<String>,
+    // sheet and cell of each account's balance, e.g. "{month_name}!B{row}"
+    pub target: String,
+    pub accounts: Vec<String>,
+    // {row} of the first account; each next account is a row further down (default 2)
+    pub first_row: Option<u32>,
+    // a close missed by more than this many hours (machine off at month-end) is skipped (default 24)
+    pub catch_up_hours: Option<u64>,
+}
+
+impl CloseSnapshotConfig {
+    pub fn label(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconciliationConfig {
+    pub name: String,
+    pub account_full_name: String,
+    // what the balance is checked against
+    pub source: ReconciliationSource,
+    // the largest difference that still counts as a match (default 0)
+    pub tolerance: Option<f64>,
+    // overrides the top-level normalize_signs for the QuickBooks side
+    pub normalize_signs: Option<bool>,
+    // where MATCH / MISMATCH is written
+    pub spreadsheet_id: String,
+    pub sheet_name: String,
+    pub cell_address: String,
+    // also write the QuickBooks balance, the source's value and the difference in the three cells
+    // to the right
+    pub write_amounts: Option<bool>,
+    // defaults "MATCH" / "MISMATCH" on green / red
+    pub match_text: Option<String>,
+    pub mismatch_text: Option<String>,
+    pub match_color: Option<String>,
+    pub mismatch_color: Option<String>,
+}
+
+impl ReconciliationConfig {
+    pub fn label(&self) -> &str {
+        &self.name
+    }
+
+    pub fn normalizes_signs(&self, config: &Config) -> bool {
+        self.normalize_signs.or(config.normalize_signs).unwrap_or(false)
+    }
+
+    /// The cells right of cell_address the balance, source value and difference go in, with write_amounts
+    pub fn amount_cells(&self) -> Vec<a1::Cell> {
+        match a1::parse_cell(&self.cell_address) {
+            Ok(status) if self.write_amounts.unwrap_or(false) => {
+                (1..=3).map(|i| a1::Cell { column: status.column + i, row: status.row }).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Where a reconciliation block's comparison value comes from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ReconciliationSource {
+    /// A cell of a sheet, read through the webapp; spreadsheet_id defaults to the block's
+    Sheet {
+        spreadsheet_id: Option<String>,
+        sheet_name: String,
+        // a single cell such as "B2", or "named:BankBalance"
+        range: String,
+    },
+    /// The last row of a CSV file with a header row, such as a bank's balance export
+    Csv {
+        path: String,
+        // the header of the column holding the amount (default the last column)
+        column: Option<String>,
+    },
+    /// A JSON document fetched with GET
+    Http {
+        url: String,
+        // JSON pointer to the amount, e.g. "/accounts/0/balance" (default the whole document)
+        pointer: Option<String>,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KpisConfig {
+    pub spreadsheet_id: String,
+    pub sheet_name: String,
+    // [account_classification] categories totalled for each side, e.g. cash = ["Cash"] and
+    // current_assets = ["Cash", "Receivables", "Inventory"]
+    #[serde(default)]
+    pub current_assets: Vec<String>,
+    #[serde(default)]
+    pub quick_assets: Vec<String>,
+    #[serde(default)]
+    pub current_liabilities: Vec<String>,
+    #[serde(default)]
+    pub cash: Vec<String>,
+    // runway_months: the monthly spend, as an expression over published values, e.g. "expenses / 3"
+    pub monthly_burn: Option<String>,
+    // places the KPIs are rounded to (default 2)
+    pub decimals: Option<u32>,
+    // KPI -> cell on sheet_name, e.g. { current_ratio = "B2", quick_ratio = "B3", runway_months = "B4" }
+    pub cells: BTreeMap<String, String>,
+}
+
+impl KpisConfig {
+    pub fn label(&self) -> &str {
+        "KPIs"
+    }
+
+    /// The categories of one of the current_assets / quick_assets / current_liabilities / cash groups
+    pub fn group(&self, name: &str) -> &[String] {
+        match name {
+            "current_assets" => &self.current_assets,
+            "quick_assets" => &self.quick_assets,
+            "current_liabilities" => &self.current_liabilities,
+            "cash" => &self.cash,
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusSheetConfig {
+    // tab that gets a block / value / time / OK-ERROR table at the end of every cycle
+    pub spreadsheet_id: String,
+    pub sheet_name: String,
+    // top-left corner of the table (default A1)
+    pub cell_address: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchdogConfig {
+    // daemon mode: the cell that reads stale_text once any watched block's data is older than
+    // stale_after_mins, and fresh_text while all are newer
+    pub spreadsheet_id: String,
+    pub sheet_name: String,
+    pub cell_address: String,
+    pub stale_after_mins: u64,
+    // block names to watch (default every block)
+    #[serde(default)]
+    pub blocks: Vec<String>,
+    // defaults "STALE" and "OK"
+    pub stale_text: Option<String>,
+    pub fresh_text: Option<String>,
+    // cell background for each state, e.g. "#f4cccc"; left as it is when not set
+    pub stale_color: Option<String>,
+    pub fresh_color: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DashboardConfig {
+    // address the status page is served on (default 127.0.0.1:8470); daemon mode only. Anyone who
+    // can reach it can run sync blocks, so keep it on loopback
+    pub listen: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrayConfig {
+    // daemon mode: toast when syncing starts failing (default true) and when it works again (default false)
+    pub notify_failures: Option<bool>,
+    pub notify_recovery: Option<bool>,
+    // warn when QuickBooks is busy and the cycle is postponed, once until a cycle runs (default true)
+    pub notify_busy: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionConfig {
+    // key for redact = "hash" columns, so hashes can't be matched against hashes of guessed values;
+    // changing it changes every hash
+    pub hash_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NameMappingConfig {
+    // CSV of from,to names; an optional first line of from,to is skipped
+    pub file: String,
+    // headers of the columns to rename, in any export that has them (default: Name, Full Name,
+    // Parent, Customer, Vendor, Payee and Received From)
+    #[serde(default)]
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    // per-run / per-block ids in log lines, webapp payloads and the run summary (default true)
+    pub correlation_ids: Option<bool>,
+    // warn about blocks whose query, parse and writes add up to more than this (default 60)
+    pub slow_block_secs: Option<u64>,
+    // a [PROGRESS] line this often while a cycle runs (default 30, 0 = off)
+    pub progress_secs: Option<u64>,
+    // configs with more blocks log their per-block lines at debug level (default 50)
+    pub detail_max_blocks: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryConfig {
+    // JSON file with per-block state between runs (default state/history.json)
+    pub path: Option<String>,
+    // days of per-block outcome counts kept for `qb_sync report reliability` (default 90, 0 = off)
+    pub keep_days: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleConfig {
+    // run a sync cycle every interval_secs instead of once and exit
+    pub interval_secs: u64,
+    // keep the QuickBooks connection/session open between cycles rather than reconnecting each time
+    pub keep_session_open: Option<bool>,
+    // how often the open session is pinged between cycles (default 60)
+    pub keepalive_secs: Option<u64>,
+    // when another user holds the company file, try again after this long instead of a full interval (default 120)
+    pub busy_retry_secs: Option<u64>,
+    // each cycle starts up to this many seconds later than the interval, at random (default 0)
+    pub jitter_secs: Option<u64>,
+    // parallel sync and timestamp blocks start spread evenly over this many seconds (default 0)
+    pub spread_secs: Option<u64>,
+    // with keep_session_open, look for changed transactions / accounts this often between cycles and
+    // start the next cycle as soon as there are any
+    pub watch_changes_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeoutsConfig {
+    // longest any one block may spend on its network writes before it is failed
+    pub block_secs: Option<u64>,
+    // blocks still running this long after the cycle started are cancelled and reported as skipped
+    pub cycle_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineConfig {
+    // list block rows are parsed and written in batches of this many rows
+    pub batch_rows: Option<usize>,
+    // parsed batches allowed to wait for their write before the parser pauses
+    pub queue_batches: Option<usize>,
+    // webapp requests in flight at once across all blocks; the fetch phase's counterpart is
+    // [quickbooks] parallel_sessions (default 10)
+    pub write_concurrency: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingConfig {
+    // list blocks with route_by_class = true send each row to the first rule matching its Class
+    #[serde(default)]
+    pub classes: Vec<ClassRoute>,
+    // Account list blocks with route_by_category = true send each account to the rule of its category
+    #[serde(default)]
+    pub categories: Vec<CategoryRoute>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClassRoute {
+    // a class FullName; "East" also takes its subclasses, such as "East:Boston"
+    pub class: String,
+    pub spreadsheet_id: String,
+    // defaults to the block's own sheet_name
+    pub sheet_name: Option<String>,
+}
+
+impl ClassRoute {
+    pub fn matches(&self, class: &str) -> bool {
+        within(class, &self.class)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CategoryRoute {
+    // an [account_classification] category, matched without regard to case
+    pub category: String,
+    pub spreadsheet_id: String,
+    pub sheet_name: Option<String>,
+}
+
+impl CategoryRoute {
+    pub fn matches(&self, category: &str) -> bool {
+        category.eq_ignore_ascii_case(&self.category)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HooksConfig {
+    // shell commands run in order before the blocks / after the summary of every cycle
+    #[serde(default)]
+    pub pre_sync: Vec<String>,
+    #[serde(default)]
+    pub post_sync: Vec<String>,
+    // a command still running after this long is killed and counts as failed (default 300)
+    pub timeout_secs: Option<u64>,
+    // skip the cycle's blocks when a pre_sync command fails (default false: log it and carry on)
+    pub stop_on_pre_sync_failure: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    // OTLP/HTTP collector base URL, e.g. "http://tempo:4318"; traces go to <endpoint>/v1/traces
+    pub endpoint: String,
+    // service.name on the exported spans (default "qb_sync")
+    pub service_name: Option<String>,
+    // sent with every export, e.g. an Authorization header for a hosted collector
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    // give up on an export after this long (default 10)
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalApiConfig {
+    // named pipe other local programs send requests to (default \\.\pipe\qb_sync); daemon mode only
+    pub pipe_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretsConfig {
+    // age identity (private key) file for enc:age: values; QB_SYNC_AGE_IDENTITY takes precedence
+    pub age_identity_file: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebugConfig {
+    // save every raw qbXML response under archive_dir so parsing bugs can be replayed later
+    pub archive_responses: Option<bool>,
+    pub archive_dir: Option<String>,
+    // retention limits; whichever is hit first wins
+    pub archive_max_files: Option<usize>,
+    pub archive_max_age_days: Option<u64>,
+    // builds without qb-com: the directory of recorded responses the mock backend answers with (default fixtures)
+    pub mock_responses: Option<String>,
+}
+
+// on_missing, parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OnMissing {
+    Skip,
+    // a balance of 0, through value_template like any other
+    Zero,
+    // an empty value: cells are emptied, other destinations get a blank
+    Clear,
+    Text(String),
+}
+
+// When any sync, timestamp, report, transaction, list or raw qbXML block runs, written inline in
+// the block
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BlockSchedule {
+    // only within these hours and on these days, e.g. "07:00-19:00" and ["Mon-Fri"]; the
+    // top-level active_hours / active_days when neither is set
+    pub active_hours: Option<String>,
+    #[serde(default)]
+    pub active_days: Vec<String>,
+    // at most once a day or hour: a clean run in the current period makes later cycles skip it
+    pub max_frequency: Option<MaxFrequency>,
+}
+
+// create_sheet_if_missing, on every block and section writing to Google Sheets: add sheet_name to
+// the spreadsheet when it has no such tab, instead of failing the write. Where a block notes more
+// (a header row for the new tab, the tabs of other destinations) that is said on its field.
+
+// max_frequency; periods are calendar days and clock hours in the [quickbooks] timezone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaxFrequency {
+    Daily,
+    Hourly,
+}
+
+impl MaxFrequency {
+    /// Whether two wall-clock times fall in the same day, or hour
+    pub fn same_period(self, a: chrono::NaiveDateTime, b: chrono::NaiveDateTime) -> bool {
+        match self {
+            MaxFrequency::Daily => a.date() == b.date(),
+            MaxFrequency::Hourly => a.date() == b.date() && a.hour() == b.hour(),
+        }
+    }
+
+    pub fn period(self) -> &'static str {
+        match self {
+            MaxFrequency::Daily => "today",
+            MaxFrequency::Hourly => "this hour",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TimestampConfig {
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub schedule: BlockSchedule,
+    pub spreadsheet_id: String,
+    pub sheet_name: String,
+    pub cell_address: String,
+    // write a Sheets date-time serial number instead of text, so the cell works in date math
+    pub as_datetime: Option<bool>,
+    // chrono format of the text written, e.g. "%m/%d/%Y %I:%M %p" (default "%d-%m-%Y:%H:%M")
+    pub display_format: Option<String>,
+    // also write the time as ISO-8601 UTC ("2024-06-01T14:05:00Z") in the cell to the right, in the
+    // same write, for staleness formulas that can't rely on how the first cell is displayed
+    pub iso_utc: Option<bool>,
+    pub create_sheet_if_missing: Option<bool>,
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportBlockConfig {
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub schedule: BlockSchedule,
+    // a GeneralSummaryReportType such as "SalesTaxLiability", a GeneralDetailReportType such as
+    // "UnbilledCostsByJob", or with QuickBooks payroll a payroll one such as "PayrollSummary"
+    pub report_type: String,
+    pub spreadsheet_id: String,
+    pub sheet_name: String,
+    // top-left cell of the range the report is written into
+    pub cell_address: String,
+    // explicit period (YYYY-MM-DD) or a qbXML ReportDateMacro such as "LastMonth", not both
+    pub date_from: Option<String>,
+    pub date_to: Option<String>,
+    pub date_macro: Option<String>,
+    // summary reports: a column per period, e.g. "Month" or "Quarter" (qbXML SummarizeColumnsBy)
+    pub summarize_columns_by: Option<String>,
+    pub include_header: Option<bool>,
+    // clear the columns below the written rows so a shorter report doesn't leave stale rows behind
+    pub clear_below: Option<bool>,
+    // keep a named range (the block name) pointed at the written table, for charts
+    pub named_range: Option<bool>,
+    // write to a hidden staging tab first, then copy it over in one go, so viewers never see the
+    // table half written
+    pub staged: Option<bool>,
+    // values for computed blocks: variable name -> the row label whose total (its last amount) it
+    // takes, e.g. { net_income = "Net Income" }
+    #[serde(default)]
+    pub publish: BTreeMap<String, String>,
+    // overrides the top-level rounding for the written amounts; published totals aren't rounded
+    pub rounding: Option<RoundingConfig>,
+    pub create_sheet_if_missing: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionBlockConfig {
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub schedule: BlockSchedule,
+    pub txn_type: TxnType,
+    pub spreadsheet_id: String,
+    pub sheet_name: String,
+    // top-left cell of the range the transactions are written into
+    pub cell_address: String,
+    // transaction date filter (YYYY-MM-DD), both ends optional
+    pub date_from: Option<String>,
+    pub date_to: Option<String>,
+    pub include_header: Option<bool>,
+    pub clear_below: Option<bool>,
+    pub named_range: Option<bool>,
+    // stage the rows on a hidden tab, then copy them over in one go (see the report block's)
+    pub staged: Option<bool>,
+    // column header (e.g. "Payee") -> "hash", "mask" or "remove", applied before the rows are written
+    #[serde(default)]
+    pub redact: HashMap<String, Redaction>,
+    // Rhai scripts run on each row in turn, after renaming and redaction (see scripting.rs)
+    #[serde(default)]
+    pub scripts: Vec<String>,
+    pub create_sheet_if_missing: Option<bool>,
+    // also send every row written, after redaction and scripts, to JSON Lines as an object by header
+    pub jsonl: Option<JsonlExportConfig>,
+    // overrides the top-level rounding for the Amount column
+    pub rounding: Option<RoundingConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListBlockConfig {
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub schedule: BlockSchedule,
+    pub list_type: ListType,
+    // "qbxml" (default) through the SDK session, or "odbc" through QODBC ([odbc])
+    #[serde(default)]
+    pub backend: ListBackend,
     pub spreadsheet_id: String,
     pub sheet_name: String,
     pub cell_address: String,
+    // TimeModified filter (YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS), both ends optional
+    pub modified_from: Option<String>,
+    pub modified_to: Option<String>,
+    // only export records modified since the last successful run of this block (from the history
+    // store), merged into the table already on the sheet by the export's key column
+    pub incremental: Option<bool>,
+    // Estimate/SalesOrder/PurchaseOrder: skip inactive estimates, invoiced or closed orders and
+    // fully received PO lines (default true)
+    pub open_only: Option<bool>,
+    // Invoice: follow each invoice with its line items (item, qty, rate, amount), keyed by invoice number
+    pub include_line_items: Option<bool>,
+    // Account, multicurrency files: add each account's currency, its exchange rate and the balance
+    // in home currency after Balance (which is in the account's own currency)
+    pub include_currency: Option<bool>,
+    // Invoice/Estimate/SalesOrder/PurchaseOrder: add each row's class as a last "Class" column
+    pub include_class: Option<bool>,
+    // split the rows across spreadsheets by their Class column (added as with include_class), as
+    // set out in [routing]; rows of classes without a rule stay in this block's spreadsheet
+    pub route_by_class: Option<bool>,
+    // Account: add each account's [account_classification] category as a last "Category" column
+    pub include_category: Option<bool>,
+    // Account, with include_category: split the rows across spreadsheets by category, as set out
+    // in [[routing.categories]]
+    pub route_by_category: Option<bool>,
+    pub include_header: Option<bool>,
+    pub clear_below: Option<bool>,
+    pub named_range: Option<bool>,
+    // stage the rows on a hidden tab, then copy them over in one go (see the report block's)
+    pub staged: Option<bool>,
+    // write only these fields, each into its own sheet column: header (e.g. "Name", "Due Date") -> column letter.
+    // Empty means every column, side by side from cell_address
+    #[serde(default)]
+    pub columns: HashMap<String, String>,
+    // column header (e.g. "Email") -> "hash", "mask" or "remove", applied before the rows are written
+    #[serde(default)]
+    pub redact: HashMap<String, Redaction>,
+    // Rhai scripts run on each row in turn, after renaming and redaction (see scripting.rs)
+    #[serde(default)]
+    pub scripts: Vec<String>,
+    // add sheet_name (and the [routing] sheets) when missing, with the columns' headers in the
+    // first row of a new tab
+    pub create_sheet_if_missing: Option<bool>,
+    // also send every row written, after redaction and scripts, to JSON Lines as an object by header
+    pub jsonl: Option<JsonlExportConfig>,
+    // overrides the top-level rounding for the money columns (balances and amounts); quantities,
+    // rates and costs per unit aren't rounded
+    pub rounding: Option<RoundingConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ListType {
+    Customer,
+    // the same CustomerQuery with contact details and billing address, for a CRM-style sheet
+    CustomerContacts,
+    // and as the Customer:Job tree, indented, with each job's status and dates
+    CustomerJobs,
+    Invoice,
+    Estimate,
+    SalesOrder,
+    PurchaseOrder,
+    // the chart of accounts
+    Account,
+    Vendor,
+    OtherName,
+    // inventory part totals, and Enterprise Advanced Inventory quantities per site / bin
+    ItemInventory,
+    ItemSites,
+}
+
+impl ListType {
+    pub fn element(&self) -> &'static str {
+        match self {
+            ListType::Customer | ListType::CustomerContacts | ListType::CustomerJobs => "Customer",
+            ListType::Invoice => "Invoice",
+            ListType::Estimate => "Estimate",
+            ListType::SalesOrder => "SalesOrder",
+            ListType::PurchaseOrder => "PurchaseOrder",
+            ListType::Account => "Account",
+            ListType::Vendor => "Vendor",
+            ListType::OtherName => "OtherName",
+            ListType::ItemInventory => "ItemInventory",
+            ListType::ItemSites => "ItemSites",
+        }
+    }
+
+    // transaction queries wrap modified dates in ModifiedDateRangeFilter
+    pub fn is_transaction(&self) -> bool {
+        !matches!(self, ListType::Customer | ListType::CustomerContacts | ListType::CustomerJobs | ListType::Account | ListType::Vendor | ListType::OtherName
+            | ListType::ItemInventory | ListType::ItemSites)
+    }
+}
+
+impl ListBlockConfig {
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(self.list_type.element())
+    }
+
+    pub fn line_items(&self) -> bool {
+        self.include_line_items.unwrap_or(false)
+    }
+
+    pub fn currency(&self) -> bool {
+        self.include_currency.unwrap_or(false)
+    }
+
+    pub fn category(&self) -> bool {
+        self.include_category.unwrap_or(false)
+    }
+
+    pub fn class(&self) -> bool {
+        self.include_class.unwrap_or(false) || self.route_by_class.unwrap_or(false)
+    }
+
+    pub fn odbc(&self) -> bool {
+        self.backend == ListBackend::Odbc
+    }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListBackend {
+    #[default]
+    Qbxml,
+    /// SQL through the QODBC driver (see odbc)
+    Odbc,
+}
+
+// names match the qbXML request prefix, e.g. Check => CheckQueryRq
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxnType {
+    Check,
+    CreditCardCharge,
+    Deposit,
+    ReceivePayment,
+    // customer credits, written as negative amounts so they net against sales
+    CreditMemo,
+    ARRefundCreditCard,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GoogleSheetsConfig {
     pub webapp_url: String,
+    // Apps Script project behind webapp_url; written by deploy-webapp so the next one updates it
+    pub script_id: Option<String>,
     pub api_key: String,
+    // shared secret for HMAC-signing each request; set SIGNING_SECRET in the script properties to match
+    pub signing_secret: Option<String>,
+    // most writes any one spreadsheet gets per minute; extra writes wait their turn (default unlimited)
+    pub writes_per_minute: Option<u32>,
+    // a post that times out, can't connect or gets a 5xx/429 is retried this many times (default 2)
+    pub retries: Option<u32>,
+    // after this many failed writes in a row to one spreadsheet, its other writes that cycle fail
+    // straight away, so the rest aren't held up (default never)
+    pub isolate_after: Option<u32>,
+    pub request_timeout_secs: Option<u64>,
+    // the webapp refuses to write over (or clear) cells holding a formula unless this is true
+    pub overwrite_formulas: Option<bool>,
+    // clear_below, history retention and writes onto a visible tab with a staging tab's name only
+    // log what they would remove unless this is true (or --yes is passed); true for files from
+    // before version 2 that don't set it, see `migrate`
+    pub confirm_destructive: Option<bool>,
+    // range writes go out as several posts of at most this many rows each (default 2000)
+    pub max_rows_per_write: Option<usize>,
+    // largest request body sent, in KB; bigger range writes are split, other writes fail (default 8000)
+    pub max_request_kb: Option<usize>,
+    // what to do with a text value longer than a cell holds (50,000 characters)
+    #[serde(default)]
+    pub long_values: LongValues,
+    // sent with every post to the webapp, e.g. a Cloudflare Access service token in front of it
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    // journal file for append rows that couldn't reach Google at all; sent in order next cycle
+    pub offline_queue: Option<String>,
     // spreadsheet_id is now per sync_block, not required here
     // pub spreadsheet_id: Option<String>,
 }
 
+// where JSON Lines go: a local file (path) or an S3-compatible bucket (url), not both
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonlExportConfig {
+    pub path: Option<String>,
+    pub url: Option<String>,
+    // a file per day (the default) or hour, named after path with the date added
+    #[serde(default)]
+    pub rotate: JsonlRotation,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonlRotation {
+    #[default]
+    Daily,
+    Hourly,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LongValues {
+    /// Fail the write, naming the value's length
+    #[default]
+    Error,
+    /// Cut the value to what the cell holds, with a warning
+    Truncate,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccountSyncConfig {
+    // shown in logs and the run summary; defaults to account_full_name
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub schedule: BlockSchedule,
+    // spreadsheet_id and sheet_name may be left out when every destination sets its own
+    #[serde(default)]
     pub spreadsheet_id: String,
+    // empty for computed blocks
+    #[serde(default)]
     pub account_full_name: String,
+    // computed block: arithmetic over other sync blocks' balances and published values by name,
+    // e.g. "cash_total / current_liabilities", evaluated after every other block
+    #[serde(alias = "ratio", alias = "expr")]
+    pub expression: Option<String>,
+    // category block: the total of every account in this [account_classification] category
+    pub category: Option<String>,
+    #[serde(default)]
     pub sheet_name: String,
+    // not needed in append mode; "APPEND:A" appends like append mode with append_column = "A"
+    #[serde(default)]
     pub cell_address: String,
+    #[serde(default)]
+    pub mode: SyncMode,
+    // chrono format for the date column written in append mode
+    pub date_format: Option<String>,
+    // append mode: the row goes below the last filled cell of this column, starting in it,
+    // instead of below the last row with anything in it
+    pub append_column: Option<String>,
+    // append mode: trims old rows off the history tab or thins them to one a day
+    pub retention: Option<RetentionConfig>,
+    // renders the value as a string before posting, e.g. "{account_name}: {balance:.2} {currency}"
+    pub value_template: Option<String>,
+    // extra places to write the same value, in addition to cell_address / append mode above
+    #[serde(default)]
+    pub destinations: Vec<DestinationConfig>,
+    // overrides the top-level normalize_signs for this block
+    pub normalize_signs: Option<bool>,
+    // overrides the top-level rounding for this block's value
+    pub rounding: Option<RoundingConfig>,
+    // add the tab of any Sheets destination when it is missing, instead of failing the write
+    pub create_sheet_if_missing: Option<bool>,
+    // cell destinations also set the cell's note to where the value came from: query time, account
+    // and ListID, run id
+    pub cell_note: Option<bool>,
+    // what to write when the account isn't in QuickBooks (or the category has no accounts): "skip"
+    // (the default, leaving the old value), "zero", "clear" or "text:N/A"
+    pub on_missing: Option<String>,
+    // Rhai scripts run in turn on the value about to be written (see scripting.rs)
+    #[serde(default)]
+    pub scripts: Vec<String>,
+    // the value, account name and query time in adjacent cells, e.g. { name = "A2", value = "B2" };
+    // written in one post, in addition to cell_address / append mode
+    #[serde(alias = "offsets")]
+    pub cells: Option<CellMap>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DestinationConfig {
+    /// Overwrite one cell; spreadsheet_id and sheet_name default to the block's. A cell_address of
+    /// "APPEND:A" makes it an append destination with that append_column.
+    Cell {
+        spreadsheet_id: Option<String>,
+        sheet_name: Option<String>,
+        cell_address: String,
+        // added to [google_sheets] headers for this destination's posts; same name replaces
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    /// Add a date + value row to a history tab
+    Append {
+        spreadsheet_id: Option<String>,
+        sheet_name: Option<String>,
+        date_format: Option<String>,
+        // start the row in this column, below its last filled cell
+        append_column: Option<String>,
+        retention: Option<RetentionConfig>,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    /// Append a row to a local CSV file
+    Csv {
+        path: String,
+        date_format: Option<String>,
+    },
+    /// Write one cell of an Excel workbook on SharePoint / OneDrive through Microsoft Graph;
+    /// the workbook is item_id or path within drive_id, worksheet defaults to the block's sheet_name
+    Excel {
+        drive_id: String,
+        item_id: Option<String>,
+        path: Option<String>,
+        worksheet: Option<String>,
+        cell_address: String,
+    },
+    /// Upsert a record into an Airtable table, matched on key_field (default "Account")
+    Airtable {
+        base_id: String,
+        table: String,
+        key_field: Option<String>,
+        // field name -> value template; defaults to Account / Balance / Value / Updated
+        #[serde(default)]
+        fields: HashMap<String, String>,
+    },
+    /// Add the result as a JSON object on a line of its own to a local .jsonl file, or PUT it as an
+    /// object under an S3-compatible bucket url (signed with [s3] when there is one)
+    Jsonl(JsonlExportConfig),
+    /// POST a JSON body to any URL (Zapier, n8n, Power Automate, ...)
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        // strings in the body are value templates; defaults to account / value / balance / timestamp
+        body: Option<serde_json::Value>,
+    },
+    /// Several parts of the result in adjacent cells of one row or column, sent as a single write,
+    /// e.g. cells = { name = "A2", value = "B2", queried_at = "C2" }
+    Cells {
+        spreadsheet_id: Option<String>,
+        sheet_name: Option<String>,
+        #[serde(alias = "offsets")]
+        cells: CellMap,
+        // chrono format for queried_at; defaults to the block's date_format
+        date_format: Option<String>,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+}
+
+/// How long an append destination's rows stay on the history tab. Rows are dated by their first
+/// cell, read back in date_format or as YYYY-MM-DD; rows with no such date are left alone.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    // rows older than this many days are removed
+    pub keep_days: Option<u32>,
+    // rows older than this many days are thinned to the last one of each day
+    pub daily_after_days: Option<u32>,
+}
+
+/// Where a `cells` destination writes each part of the result; parts left out aren't written
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CellMap {
+    pub value: Option<String>,
+    // the account's full name, or the label of a computed block
+    pub name: Option<String>,
+    pub queried_at: Option<String>,
+}
+
+/// One part of a sync result that a `cells` destination can write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellPart {
+    Value,
+    Name,
+    QueriedAt,
+}
+
+impl std::fmt::Display for CellPart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CellPart::Value => "value",
+            CellPart::Name => "name",
+            CellPart::QueriedAt => "queried_at",
+        })
+    }
+}
+
+/// The cells of a CellMap in sheet order: the first is `anchor`, the others follow it along its row
+/// when `across`, down its column otherwise
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CellLayout {
+    pub anchor: a1::Cell,
+    pub across: bool,
+    pub parts: Vec<CellPart>,
+}
+
+impl CellMap {
+    pub fn entries(&self) -> Vec<(CellPart, &str)> {
+        [(CellPart::Value, &self.value), (CellPart::Name, &self.name), (CellPart::QueriedAt, &self.queried_at)]
+            .into_iter()
+            .filter_map(|(part, address)| address.as_deref().map(|address| (part, address)))
+            .collect()
+    }
+
+    /// Orders the cells and checks they sit side by side, so that one range write covers them all
+    pub fn layout(&self) -> Result<CellLayout> {
+        let mut cells = Vec::new();
+        for (part, address) in self.entries() {
+            let cell = a1::parse_cell(address).with_context(|| format!("Invalid {} cell", part))?;
+            if let Some((other, _)) = cells.iter().find(|(_, c)| *c == cell) {
+                anyhow::bail!("{} and {} are both {}", other, part, cell);
+            }
+            cells.push((part, cell));
+        }
+        cells.sort_by_key(|(_, c)| (c.row, c.column));
+        let Some(&(_, first)) = cells.first() else {
+            anyhow::bail!("cells needs at least one of value, name and queried_at");
+        };
+        let across = cells.iter().all(|(_, c)| c.row == first.row);
+        let adjacent = cells.iter().enumerate().all(|(i, (_, c))| match across {
+            true => c.column == first.column + i as u32,
+            false => c.column == first.column && c.row == first.row + i as u32,
+        });
+        if !adjacent {
+            let listed: Vec<String> = cells.iter().map(|(part, c)| format!("{} = {}", part, c)).collect();
+            anyhow::bail!("{} aren't next to each other; put them side by side in one row or one column so they go out as one write", listed.join(", "));
+        }
+        Ok(CellLayout { anchor: first, across, parts: cells.into_iter().map(|(part, _)| part).collect() })
+    }
+}
+
+impl AccountSyncConfig {
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.account_full_name)
+    }
+
+    pub fn is_computed(&self) -> bool {
+        self.expression.is_some()
+    }
+
+    /// Whether the block writes the balance sign-normalized by account type
+    pub fn normalizes_signs(&self, config: &Config) -> bool {
+        self.normalize_signs.or(config.normalize_signs).unwrap_or(false)
+    }
+
+    pub fn on_missing(&self) -> Result<OnMissing> {
+        match self.on_missing.as_deref().map(str::trim) {
+            None | Some("skip") => Ok(OnMissing::Skip),
+            Some("zero") => Ok(OnMissing::Zero),
+            Some("clear") => Ok(OnMissing::Clear),
+            Some(other) => match other.strip_prefix("text:") {
+                Some(text) => Ok(OnMissing::Text(text.to_string())),
+                None => anyhow::bail!("Sync block '{}': on_missing = \"{}\" should be \"skip\", \"zero\", \"clear\" or \"text:...\"", self.label(), other),
+            },
+        }
+    }
+
+    /// The destination described by the block's own fields (if any) followed by `destinations`, with
+    /// "APPEND:A" cells turned into append destinations
+    pub fn all_destinations(&self) -> Vec<DestinationConfig> {
+        let mut all = Vec::with_capacity(self.destinations.len() + 1);
+        match self.mode {
+            SyncMode::Cell if !self.cell_address.is_empty() => all.push(DestinationConfig::Cell {
+                spreadsheet_id: None,
+                sheet_name: None,
+                cell_address: self.cell_address.clone(),
+                headers: HashMap::new(),
+            }),
+            SyncMode::Append => all.push(DestinationConfig::Append {
+                spreadsheet_id: None,
+                sheet_name: None,
+                date_format: self.date_format.clone(),
+                append_column: self.append_column.clone(),
+                retention: self.retention.clone(),
+                headers: HashMap::new(),
+            }),
+            SyncMode::Cell => {}
+        }
+        if let Some(cells) = &self.cells {
+            all.push(DestinationConfig::Cells {
+                spreadsheet_id: None,
+                sheet_name: None,
+                cells: cells.clone(),
+                date_format: self.date_format.clone(),
+                headers: HashMap::new(),
+            });
+        }
+        all.extend(self.destinations.iter().cloned());
+        for dest in &mut all {
+            if let DestinationConfig::Cell { spreadsheet_id, sheet_name, cell_address, headers } = dest {
+                if let Some(column) = a1::append_column(cell_address) {
+                    *dest = DestinationConfig::Append {
+                        spreadsheet_id: spreadsheet_id.take(),
+                        sheet_name: sheet_name.take(),
+                        date_format: self.date_format.clone(),
+                        append_column: Some(column.to_ascii_uppercase()),
+                        retention: self.retention.clone(),
+                        headers: std::mem::take(headers),
+                    };
+                }
+            }
+        }
+        all
+    }
+}
+
+impl TimestampConfig {
+    pub fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| format!("timestamp {}!{}", self.sheet_name, self.cell_address))
+    }
+
+    /// The cell right of cell_address the ISO-8601 UTC time goes in, when iso_utc is set
+    pub fn iso_cell(&self) -> Option<a1::Cell> {
+        if !self.iso_utc.unwrap_or(false) {
+            return None;
+        }
+        let cell = a1::parse_cell(&self.cell_address).ok()?;
+        Some(a1::Cell { column: cell.column + 1, row: cell.row })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawQbxmlBlockConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub schedule: BlockSchedule,
+    // the request element(s), e.g. "<ItemQueryRq><FullName>Widget</FullName></ItemQueryRq>", or a
+    // whole qbXML document; inline or read from request_file, not both
+    pub request: Option<String>,
+    pub request_file: Option<String>,
+    pub spreadsheet_id: String,
+    pub sheet_name: String,
+    // shorthand for a single field: XPath evaluated inside QBXMLMsgsRs, e.g.
+    // "ItemInventoryQueryRs/ItemInventoryRet/QuantityOnHand", written to cell_address
+    pub extract: Option<String>,
+    #[serde(default)]
+    pub mode: ExtractMode,
+    pub cell_address: Option<String>,
+    // column mode: clear the column below the written values
+    pub clear_below: Option<bool>,
+    // publish the extract's value under this name for computed blocks
+    pub publish: Option<String>,
+    // any number of XPath -> cell mappings read from the same response
+    #[serde(default)]
+    pub fields: Vec<FieldMapping>,
+    pub create_sheet_if_missing: Option<bool>,
+    // send the request to QuickBooks Point of Sale ([point_of_sale]) as qbposXML instead
+    pub point_of_sale: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldMapping {
+    pub xpath: String,
+    // defaults to the block's sheet_name
+    pub sheet_name: Option<String>,
+    pub cell_address: String,
+    #[serde(default)]
+    pub mode: ExtractMode,
+    pub clear_below: Option<bool>,
+    // first mode: also publish the value, when it is a number, under this name for computed blocks
+    pub publish: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractMode {
+    /// Write the first match to `cell_address` (numbers stay numbers)
+    #[default]
+    First,
+    /// Write every match down the column starting at `cell_address`
+    Column,
+}
+
+impl RawQbxmlBlockConfig {
+    pub fn label(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_point_of_sale(&self) -> bool {
+        self.point_of_sale.unwrap_or(false)
+    }
+
+    /// The extract / cell_address shorthand (if set) followed by `fields`
+    pub fn field_mappings(&self) -> Result<Vec<FieldMapping>> {
+        let mut mappings = Vec::new();
+        match (&self.extract, &self.cell_address) {
+            (Some(xpath), Some(cell_address)) => mappings.push(FieldMapping {
+                xpath: xpath.clone(),
+                sheet_name: None,
+                cell_address: cell_address.clone(),
+                mode: self.mode,
+                clear_below: self.clear_below,
+                publish: self.publish.clone(),
+            }),
+            (None, None) => {}
+            _ => anyhow::bail!("Raw qbXML block '{}': extract and cell_address go together", self.name),
+        }
+        mappings.extend(self.fields.iter().cloned());
+        if mappings.is_empty() {
+            anyhow::bail!("Raw qbXML block '{}' has nothing to extract; set extract or add [[raw_qbxml_blocks.fields]]", self.name);
+        }
+        Ok(mappings)
+    }
+}
+
+impl ReportBlockConfig {
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.report_type)
+    }
+}
+
+impl TxnType {
+    // the qbXML element prefix: Check => CheckQueryRq / CheckQueryRs / CheckRet
+    pub fn element(&self) -> &'static str {
+        match self {
+            TxnType::Check => "Check",
+            TxnType::CreditCardCharge => "CreditCardCharge",
+            TxnType::Deposit => "Deposit",
+            TxnType::ReceivePayment => "ReceivePayment",
+            TxnType::CreditMemo => "CreditMemo",
+            TxnType::ARRefundCreditCard => "ARRefundCreditCard",
+        }
+    }
+}
+
+impl TransactionBlockConfig {
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(self.txn_type.element())
+    }
+}
+
+/// How amounts are rounded before they're written, e.g. { mode = "half_even", decimals = 2 }
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RoundingConfig {
+    #[serde(default)]
+    pub mode: RoundingMode,
+    // places kept (default 2)
+    pub decimals: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    /// Banker's rounding: a half goes to the even digit, so 2.345 -> 2.34 and 2.355 -> 2.36
+    #[default]
+    HalfEven,
+    /// A half goes away from zero, as sheets round: 2.345 -> 2.35
+    HalfUp,
+    /// The digits past `decimals` are dropped: 2.349 -> 2.34
+    Truncate,
+}
+
+impl RoundingConfig {
+    pub fn apply(&self, amount: Decimal) -> Decimal {
+        let strategy = match self.mode {
+            RoundingMode::HalfEven => RoundingStrategy::MidpointNearestEven,
+            RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::Truncate => RoundingStrategy::ToZero,
+        };
+        amount.round_dp_with_strategy(self.decimals.unwrap_or(2), strategy)
+    }
+
+    /// Rounds `cell` when it holds a number; text and blanks are left alone
+    pub fn apply_to_cell(&self, cell: &mut serde_json::Value) {
+        if let serde_json::Value::Number(n) = cell {
+            if let Ok(amount) = n.to_string().parse::<Decimal>() {
+                *cell = amounts::json(self.apply(amount));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    /// Overwrite `cell_address` with the balance (the original behaviour)
+    #[default]
+    Cell,
+    /// Add a `date, balance` row below the last non-empty row of `sheet_name`
+    Append,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,11 +1429,1179 @@ pub struct QuickBooksConfig {
     pub application_name: Option<String>,
     pub application_id: Option<String>,
     pub connection_timeout: Option<u32>,
+    // start QBW.exe with company_file when QuickBooks isn't running, then wait for it to be ready
+    pub auto_start_quickbooks: Option<bool>,
+    // full path to QBW.exe; searched for under Program Files\Intuit when omitted
+    pub quickbooks_exe: Option<String>,
+    pub startup_timeout_secs: Option<u64>,
+    // IANA name (e.g. "America/Chicago") used for relative dates such as "today"; machine local time when omitted
+    pub timezone: Option<String>,
+    // stop before writing anything unless the open QuickBooks product name contains this
+    // (e.g. "Enterprise") / the open company is this one
+    pub require_edition: Option<String>,
+    pub require_company: Option<String>,
+    // separators of amounts in responses, e.g. "de-DE" for "1.234,56"; guessed per value when omitted
+    pub number_locale: Option<String>,
+    // before each cycle, a small company + account query; blocks are skipped when QuickBooks'
+    // answer suggests a damaged company file
+    pub integrity_check: Option<bool>,
+    // what to do when the company file is an Accountant's Copy or restricted by one
+    #[serde(default)]
+    pub restricted_mode: RestrictedMode,
+    // stop calling QuickBooks for a while once it keeps failing or answering too slowly
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    // answer a query identical to one answered within this many seconds from its response
+    pub cache_secs: Option<u64>,
+    // reuse the last account query while no account or transaction has changed since it was sent,
+    // for at most this many seconds
+    pub account_reuse_secs: Option<u64>,
+    // extra QuickBooks sessions, each on a thread of its own, that report and transaction blocks
+    // send their queries through at the same time (default 1: everything on the one session, in order)
+    pub parallel_sessions: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    // failed QuickBooks calls in a row that open the circuit (default 3)
+    pub failures: Option<u32>,
+    // a call slower than this opens the circuit at once; its answer is still used
+    pub max_request_secs: Option<u64>,
+    // a response bigger than this is refused and opens the circuit at once
+    pub max_response_kb: Option<u64>,
+    // a request bigger than this isn't sent; that is the config's fault, so it doesn't count
+    pub max_request_kb: Option<u64>,
+    // how long every call fails straight away once the circuit is open (default 900)
+    pub cooldown_secs: Option<u64>,
+    // shell commands run when the circuit opens, with QB_SYNC_CIRCUIT_REASON set
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestrictedMode {
+    /// Log it and carry on as usual
+    #[default]
+    Warn,
+    /// Also skip raw qbXML blocks whose request adds, modifies, deletes or voids anything
+    SkipWriteBack,
+    /// Stop before anything is written, like require_company
+    Abort,
+}
+
+impl QuickBooksConfig {
+    pub fn number_format(&self) -> Result<NumberFormat> {
+        match &self.number_locale {
+            Some(locale) => NumberFormat::for_locale(locale),
+            None => Ok(NumberFormat::Auto),
+        }
+    }
 }
 
 impl Config {
-    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let figment = Figment::from(Toml::file(path));
-        figment.extract().context("Failed to parse config file")
+    /// Labels of every configured block, as they appear in the run summary
+    pub fn block_labels(&self) -> Vec<String> {
+        let mut labels: Vec<String> = Vec::new();
+        labels.extend(self.sync_blocks.iter().map(|b| b.label().to_string()));
+        labels.extend(self.timestamp_blocks.iter().map(|b| b.label()));
+        labels.extend(self.company_info.iter().map(|b| b.label().to_string()));
+        labels.extend(self.deleted_transactions.iter().map(|b| b.label().to_string()));
+        labels.extend(self.aging.iter().map(|a| a.label().to_string()));
+        labels.extend(self.kpis.iter().map(|k| k.label().to_string()));
+        labels.extend(self.report_blocks.iter().map(|b| b.label().to_string()));
+        labels.extend(self.transaction_blocks.iter().map(|b| b.label().to_string()));
+        labels.extend(self.list_blocks.iter().map(|b| b.label().to_string()));
+        labels.extend(self.raw_qbxml_blocks.iter().map(|b| b.label().to_string()));
+        labels.extend(self.reconciliation_blocks.iter().map(|b| b.label().to_string()));
+        labels
+    }
+
+    /// Whether any block needs a QuickBooks session; timestamp blocks alone don't
+    pub fn needs_quickbooks(&self) -> bool {
+        !self.sync_blocks.is_empty()
+            || !self.report_blocks.is_empty()
+            || !self.transaction_blocks.is_empty()
+            || self.list_blocks.iter().any(|b| !b.odbc())
+            || self.raw_qbxml_blocks.iter().any(|b| !b.is_point_of_sale())
+            || self.company_info.is_some()
+            || self.deleted_transactions.is_some()
+            || self.aging.is_some()
+            || self.kpis.is_some()
+            || self.balance_diff.is_some()
+            || !self.close_snapshots.is_empty()
+            || !self.reconciliation_blocks.is_empty()
+    }
+
+    /// Whether allowed_accounts / denied_accounts keep any account out
+    pub fn restricts_accounts(&self) -> bool {
+        !self.allowed_accounts.is_empty() || !self.denied_accounts.is_empty()
+    }
+
+    /// Whether allowed_accounts / denied_accounts let an account's balance be written
+    pub fn account_permitted(&self, full_name: &str) -> bool {
+        (self.allowed_accounts.is_empty() || self.allowed_accounts.iter().any(|a| within(full_name, a)))
+            && !self.denied_accounts.iter().any(|d| within(full_name, d))
+    }
+
+    /// Whether some block running at `at` needs a QuickBooks session: needs_quickbooks, leaving
+    /// out what is outside its active hours
+    pub fn needs_quickbooks_at(&self, at: chrono::NaiveDateTime) -> bool {
+        let inactive = self.inactive_blocks(at);
+        let active = |label: &str| !inactive.iter().any(|l| l == label);
+        self.sync_blocks.iter().any(|b| active(b.label()))
+            || self.report_blocks.iter().any(|b| active(b.label()))
+            || self.transaction_blocks.iter().any(|b| active(b.label()))
+            || self.list_blocks.iter().any(|b| !b.odbc() && active(b.label()))
+            || self.raw_qbxml_blocks.iter().any(|b| !b.is_point_of_sale() && active(b.label()))
+            || (self.window().is_none_or(|w| w.contains(at))
+                && (self.company_info.is_some()
+                    || self.deleted_transactions.is_some()
+                    || self.aging.is_some()
+                    || self.balance_diff.is_some()
+                    || !self.close_snapshots.is_empty()
+                    || !self.reconciliation_blocks.is_empty()
+                    || self.kpis.is_some()))
     }
+
+    /// Labels of the blocks and sections that sit out a cycle starting at `at`, being outside
+    /// their active hours or days
+    pub fn inactive_blocks(&self, at: chrono::NaiveDateTime) -> Vec<String> {
+        let global = self.window();
+        let mut inactive = Vec::new();
+        for (label, hours, days) in self.block_windows() {
+            // checked by validate_run_windows
+            let own = Window::parse(hours, days).ok().flatten();
+            if own.as_ref().or(global.as_ref()).is_some_and(|w| !w.contains(at)) {
+                inactive.push(label);
+            }
+        }
+        if global.is_some_and(|w| !w.contains(at)) {
+            inactive.extend(self.company_info.iter().map(|c| c.label().to_string()));
+            inactive.extend(self.deleted_transactions.iter().map(|d| d.label().to_string()));
+            inactive.extend(self.aging.iter().map(|a| a.label().to_string()));
+            inactive.extend(self.kpis.iter().map(|k| k.label().to_string()));
+            inactive.extend(self.close_snapshots.iter().map(|s| s.label().to_string()));
+            inactive.extend(self.reconciliation_blocks.iter().map(|b| b.label().to_string()));
+            inactive.extend(self.balance_diff.iter().map(|d| d.label().to_string()));
+        }
+        inactive
+    }
+
+    /// Every block with a max_frequency
+    pub fn block_frequencies(&self) -> Vec<(String, MaxFrequency)> {
+        let mut limits = Vec::new();
+        limits.extend(self.sync_blocks.iter().filter_map(|b| Some((b.label().to_string(), b.schedule.max_frequency?))));
+        limits.extend(self.timestamp_blocks.iter().filter_map(|b| Some((b.label(), b.schedule.max_frequency?))));
+        limits.extend(self.report_blocks.iter().filter_map(|b| Some((b.label().to_string(), b.schedule.max_frequency?))));
+        limits.extend(self.transaction_blocks.iter().filter_map(|b| Some((b.label().to_string(), b.schedule.max_frequency?))));
+        limits.extend(self.list_blocks.iter().filter_map(|b| Some((b.label().to_string(), b.schedule.max_frequency?))));
+        limits.extend(self.raw_qbxml_blocks.iter().filter_map(|b| Some((b.label().to_string(), b.schedule.max_frequency?))));
+        limits
+    }
+
+    // the top-level active_hours / active_days
+    fn window(&self) -> Option<Window> {
+        Window::parse(self.active_hours.as_deref(), &self.active_days).ok().flatten()
+    }
+
+    // every block's label with its own active_hours and active_days
+    fn block_windows(&self) -> Vec<(String, Option<&str>, &[String])> {
+        let mut windows = Vec::new();
+        windows.extend(self.sync_blocks.iter().map(|b| (b.label().to_string(), b.schedule.active_hours.as_deref(), b.schedule.active_days.as_slice())));
+        windows.extend(self.timestamp_blocks.iter().map(|b| (b.label(), b.schedule.active_hours.as_deref(), b.schedule.active_days.as_slice())));
+        windows.extend(self.report_blocks.iter().map(|b| (b.label().to_string(), b.schedule.active_hours.as_deref(), b.schedule.active_days.as_slice())));
+        windows.extend(self.transaction_blocks.iter().map(|b| (b.label().to_string(), b.schedule.active_hours.as_deref(), b.schedule.active_days.as_slice())));
+        windows.extend(self.list_blocks.iter().map(|b| (b.label().to_string(), b.schedule.active_hours.as_deref(), b.schedule.active_days.as_slice())));
+        windows.extend(self.raw_qbxml_blocks.iter().map(|b| (b.label().to_string(), b.schedule.active_hours.as_deref(), b.schedule.active_days.as_slice())));
+        windows
+    }
+
+    /// Loads the base config and, when `env` is given, merges `config.<env>.toml` from the same
+    /// directory on top of it. Values in the overlay win; tables are merged key by key, while
+    /// arrays such as `sync_blocks` are replaced wholesale when the overlay defines them.
+    ///
+    /// `overrides` are dotted `key=value` pairs from `--set` and are applied last.
+    pub fn load<P: AsRef<Path>>(path: P, env: Option<&str>, overrides: &[(String, String)]) -> Result<Self> {
+        let path = path.as_ref();
+        let mut figment = Figment::from(Toml::file(path));
+        if let Some(env) = env {
+            let overlay = overlay_path(path, env);
+            // Toml::file quietly ignores missing files, which would silently run prod against the base config
+            if !overlay.is_file() {
+                anyhow::bail!("Config overlay for environment '{}' not found: {}", env, overlay.display());
+            }
+            log::info!("Using config overlay {}", overlay.display());
+            figment = figment.merge(Toml::file(overlay));
+        }
+        for (key, raw) in overrides {
+            // "true", "30" and "C:\file.qbw" become a bool, an integer and a string respectively
+            let value: Value = raw.parse().unwrap_or_else(|e| match e {});
+            log::info!("Config override: {} = {}", key, raw);
+            figment = figment.merge(Serialized::default(key, value));
+        }
+        // upgrade older layouts before the typed extract, which would otherwise reject them;
+        // current files are extracted as loaded so errors still name the file they came from
+        let mut raw: serde_json::Value = figment.extract().context("Failed to parse config file")?;
+        let declares_version = raw.get("version").is_some();
+        let migrated = migrate(&mut raw)?;
+        if interpolate_vars(&mut raw)? || migrated {
+            figment = Figment::from(Serialized::defaults(&raw));
+        }
+        let mut config: Config = figment.extract().context("Failed to parse config file")?;
+        if config.strict.unwrap_or(declares_version) {
+            config.validate_known_keys(&raw)?;
+        }
+        config.version = Some(CONFIG_VERSION);
+        config.decrypt_secrets()?;
+        config.quickbooks.number_format()?;
+        config.validate_cell_addresses()?;
+        config.validate_collisions()?;
+        config.validate_computed_blocks()?;
+        config.validate_routing()?;
+        config.validate_account_classification()?;
+        config.validate_headers()?;
+        config.validate_watchdog()?;
+        config.validate_redaction()?;
+        config.validate_on_missing()?;
+        config.validate_scripts()?;
+        crate::names::NameMap::load(&config)?;
+        config.validate_point_of_sale()?;
+        config.validate_dates()?;
+        config.validate_deleted_transactions()?;
+        config.validate_aging()?;
+        config.validate_kpis()?;
+        config.validate_run_windows()?;
+        config.validate_account_lists()?;
+        config.validate_odbc()?;
+        config.validate_incremental()?;
+        config.validate_jsonl_exports()?;
+        config.validate_circuit_breaker()?;
+        config.validate_parallel_sessions()?;
+        config.validate_retention()?;
+        config.validate_rounding()?;
+        config.validate_reconciliation()?;
+        Ok(config)
+    }
+
+    /// Names report and raw qbXML blocks publish values under, with the block publishing each
+    pub fn published_values(&self) -> Vec<(String, String)> {
+        let mut published = Vec::new();
+        for block in &self.report_blocks {
+            published.extend(block.publish.keys().map(|name| (name.clone(), format!("report block '{}'", block.label()))));
+        }
+        for block in &self.raw_qbxml_blocks {
+            for field in block.field_mappings().unwrap_or_default() {
+                if let Some(name) = field.publish {
+                    published.push((name, format!("raw qbXML block '{}'", block.label())));
+                }
+            }
+        }
+        if let Some(kpis) = &self.kpis {
+            published.extend(kpis.cells.keys().map(|name| (name.clone(), "[kpis]".to_string())));
+        }
+        published
+    }
+
+    // A computed block names itself and refers to account blocks, published values or computed
+    // blocks defined before it, so one pass in config order can evaluate them all
+    fn validate_computed_blocks(&self) -> Result<()> {
+        let published = self.published_values();
+        for (i, (name, place)) in published.iter().enumerate() {
+            if published[..i].iter().any(|(earlier, _)| earlier == name) || self.sync_blocks.iter().any(|b| b.label() == name) {
+                anyhow::bail!("{} publishes '{}', which is already the name of a sync block or another published value", place, name);
+            }
+        }
+        for block in &self.raw_qbxml_blocks {
+            if block.field_mappings().unwrap_or_default().iter().any(|f| f.publish.is_some() && f.mode != ExtractMode::First) {
+                anyhow::bail!("Raw qbXML block '{}': only mode = \"first\" fields can publish a value", block.label());
+            }
+        }
+        for (i, block) in self.sync_blocks.iter().enumerate() {
+            let Some(expression) = &block.expression else { continue };
+            let Some(name) = &block.name else {
+                anyhow::bail!("Computed sync block '{}' needs a name", expression);
+            };
+            if !block.account_full_name.is_empty() {
+                anyhow::bail!("Sync block '{}' sets both account_full_name and expression", name);
+            }
+            for source in expr::names(expression).with_context(|| format!("Sync block '{}'", name))? {
+                let known = self.sync_blocks.iter().enumerate()
+                    .any(|(j, b)| b.label() == source && (!b.is_computed() || j < i))
+                    || published.iter().any(|(published, _)| *published == source);
+                if !known {
+                    anyhow::bail!("Sync block '{}': '{}' is not an account sync block, a published value or a computed block defined above it", name, source);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // route_by_class needs somewhere to route to, and one named range can't span several spreadsheets
+    fn validate_on_missing(&self) -> Result<()> {
+        for block in self.sync_blocks.iter().filter(|b| b.on_missing.is_some()) {
+            if block.is_computed() {
+                anyhow::bail!("Sync block '{}' sets on_missing, which only applies to account and category blocks", block.label());
+            }
+            block.on_missing()?;
+        }
+        Ok(())
+    }
+
+    // every script compiles, so a typo fails the load rather than the block at 3am
+    fn validate_scripts(&self) -> Result<()> {
+        let sync = self.sync_blocks.iter().map(|b| (b.label(), &b.scripts));
+        let transactions = self.transaction_blocks.iter().map(|b| (b.label(), &b.scripts));
+        let lists = self.list_blocks.iter().map(|b| (b.label(), &b.scripts));
+        for (label, scripts) in sync.chain(transactions).chain(lists) {
+            crate::scripting::Scripts::load(scripts).with_context(|| format!("Block '{}'", label))?;
+        }
+        Ok(())
+    }
+
+    fn validate_routing(&self) -> Result<()> {
+        let rules = self.routing.as_ref().map(|r| r.classes.as_slice()).unwrap_or_default();
+        for rule in rules {
+            if rule.class.trim().is_empty() || rule.spreadsheet_id.trim().is_empty() {
+                anyhow::bail!("Every [[routing.classes]] rule needs a class and a spreadsheet_id");
+            }
+        }
+        for block in self.list_blocks.iter().filter(|b| b.route_by_class.unwrap_or(false)) {
+            if rules.is_empty() {
+                anyhow::bail!("List block '{}' sets route_by_class but [routing] has no classes", block.label());
+            }
+            if block.named_range.unwrap_or(false) {
+                anyhow::bail!("List block '{}': named_range can't be used with route_by_class", block.label());
+            }
+        }
+        let rules = self.routing.as_ref().map(|r| r.categories.as_slice()).unwrap_or_default();
+        for rule in rules {
+            if rule.category.trim().is_empty() || rule.spreadsheet_id.trim().is_empty() {
+                anyhow::bail!("Every [[routing.categories]] rule needs a category and a spreadsheet_id");
+            }
+        }
+        for block in self.list_blocks.iter().filter(|b| b.route_by_category.unwrap_or(false)) {
+            if rules.is_empty() {
+                anyhow::bail!("List block '{}' sets route_by_category but [routing] has no categories", block.label());
+            }
+            if !block.category() {
+                anyhow::bail!("List block '{}': route_by_category needs include_category = true", block.label());
+            }
+            if block.route_by_class.unwrap_or(false) {
+                anyhow::bail!("List block '{}' can route by class or by category, not both", block.label());
+            }
+            if block.named_range.unwrap_or(false) {
+                anyhow::bail!("List block '{}': named_range can't be used with route_by_category", block.label());
+            }
+        }
+        Ok(())
+    }
+
+    // account types have to be real ones, and categories need a classification to come from
+    fn validate_account_classification(&self) -> Result<()> {
+        if let Some(classification) = &self.account_classification {
+            for account_type in classification.types.keys() {
+                if !ACCOUNT_TYPES.iter().any(|t| t.eq_ignore_ascii_case(account_type)) {
+                    anyhow::bail!("[account_classification] types: '{}' is not a QuickBooks account type; expected one of {}", account_type, ACCOUNT_TYPES.join(", "));
+                }
+            }
+        }
+        let classified = self.account_classification.is_some();
+        for block in &self.sync_blocks {
+            let Some(category) = &block.category else { continue };
+            let Some(name) = &block.name else {
+                anyhow::bail!("Sync block for category '{}' needs a name", category);
+            };
+            if !block.account_full_name.is_empty() || block.is_computed() {
+                anyhow::bail!("Sync block '{}' sets category and also account_full_name or expression; it can only have one", name);
+            }
+            if !classified {
+                anyhow::bail!("Sync block '{}' totals category '{}' but there is no [account_classification] section", name, category);
+            }
+        }
+        for block in self.list_blocks.iter().filter(|b| b.category()) {
+            if block.list_type != ListType::Account {
+                anyhow::bail!("List block '{}': include_category only applies to Account exports", block.label());
+            }
+            if !classified {
+                anyhow::bail!("List block '{}' sets include_category but there is no [account_classification] section", block.label());
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_point_of_sale(&self) -> Result<()> {
+        if let Some(block) = self.raw_qbxml_blocks.iter().find(|b| b.is_point_of_sale()) {
+            match &self.point_of_sale {
+                None => anyhow::bail!("Raw qbXML block '{}' sets point_of_sale but there is no [point_of_sale] section", block.name),
+                Some(pos) if pos.connection_string.trim().is_empty() => anyhow::bail!("[point_of_sale] connection_string is empty"),
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    // block dates are resolved when the block runs; a typo or an offset out of range fails the load
+    // instead. Only the form is checked, against the machine's today.
+    fn validate_dates(&self) -> Result<()> {
+        let today = chrono::Local::now().date_naive();
+        let reports = self.report_blocks.iter().map(|b| (format!("Report block '{}'", b.label()), [&b.date_from, &b.date_to]));
+        let transactions = self.transaction_blocks.iter().map(|b| (format!("Transaction block '{}'", b.label()), [&b.date_from, &b.date_to]));
+        for (block, dates) in reports.chain(transactions) {
+            for date in dates.into_iter().flatten() {
+                crate::dates::resolve(date, today).with_context(|| block.clone())?;
+            }
+        }
+        for block in &self.list_blocks {
+            for date in [&block.modified_from, &block.modified_to].into_iter().flatten() {
+                crate::dates::resolve_qbxml_datetime(date, today).with_context(|| format!("List block '{}'", block.label()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_deleted_transactions(&self) -> Result<()> {
+        let Some(audit) = &self.deleted_transactions else { return Ok(()) };
+        for txn_type in &audit.txn_types {
+            crate::deleted_txns::txn_del_type(txn_type).context("[deleted_transactions] txn_types")?;
+        }
+        if let Some(since) = &audit.since {
+            // only checked for form here; it is resolved against the [quickbooks] timezone's today
+            crate::dates::resolve(since, chrono::Local::now().date_naive()).context("[deleted_transactions] since")?;
+        }
+        Ok(())
+    }
+
+    fn validate_aging(&self) -> Result<()> {
+        let Some(aging) = &self.aging else { return Ok(()) };
+        let days = aging.bucket_days();
+        if days.windows(2).any(|pair| pair[0] >= pair[1]) || days.contains(&0) {
+            anyhow::bail!("[aging] buckets must be increasing numbers of days above 0, e.g. [30, 60, 90]; got {:?}", days);
+        }
+        Ok(())
+    }
+
+    // every KPI has the groups it is worked out from, named after categories the classification
+    // hands out, and runway's burn refers to values published before the KPIs are worked out
+    fn validate_kpis(&self) -> Result<()> {
+        let Some(kpis) = &self.kpis else { return Ok(()) };
+        let Some(classification) = &self.account_classification else {
+            anyhow::bail!("[kpis] needs an [account_classification] section to total categories from");
+        };
+        if kpis.cells.is_empty() {
+            anyhow::bail!("[kpis] cells is empty; give at least one KPI and its cell, e.g. current_ratio = \"B2\"");
+        }
+        let categories: Vec<&String> = classification.types.values()
+            .chain(classification.accounts.values())
+            .chain(classification.default.iter())
+            .collect();
+        for name in kpis.cells.keys() {
+            let kpi = crate::kpis::kpi(name).context("[kpis] cells")?;
+            for group in crate::kpis::groups(kpi) {
+                if kpis.group(group).is_empty() {
+                    anyhow::bail!("[kpis] {} needs {}, which lists no categories", kpi, group);
+                }
+            }
+        }
+        for group in ["current_assets", "quick_assets", "current_liabilities", "cash"] {
+            for category in kpis.group(group) {
+                if !categories.iter().any(|c| c.eq_ignore_ascii_case(category)) {
+                    anyhow::bail!("[kpis] {}: no account is classified as '{}' in [account_classification]", group, category);
+                }
+            }
+        }
+        if kpis.cells.contains_key("runway_months") {
+            let Some(burn) = &kpis.monthly_burn else {
+                anyhow::bail!("[kpis] runway_months needs monthly_burn");
+            };
+            let published = self.published_values();
+            for source in expr::names(burn).context("[kpis] monthly_burn")? {
+                let known = self.sync_blocks.iter().any(|b| b.label() == source && !b.is_computed())
+                    || published.iter().any(|(name, place)| *name == source && place != "[kpis]");
+                if !known {
+                    anyhow::bail!("[kpis] monthly_burn: '{}' is not an account sync block or a value a report or raw qbXML block publishes", source);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_run_windows(&self) -> Result<()> {
+        Window::parse(self.active_hours.as_deref(), &self.active_days).context("Top-level active hours")?;
+        for (label, hours, days) in self.block_windows() {
+            Window::parse(hours, days).with_context(|| format!("Block '{}'", label))?;
+        }
+        Ok(())
+    }
+
+    // a sync block for an account the lists keep out would never write; better to hear now
+    fn validate_account_lists(&self) -> Result<()> {
+        if self.allowed_accounts.iter().chain(&self.denied_accounts).any(|a| a.trim().is_empty()) {
+            anyhow::bail!("allowed_accounts and denied_accounts can't have empty entries");
+        }
+        for block in self.sync_blocks.iter().filter(|b| b.category.is_none() && !b.is_computed()) {
+            if !self.account_permitted(&block.account_full_name) {
+                anyhow::bail!("Sync block '{}': account '{}' isn't permitted by allowed_accounts / denied_accounts", block.label(), block.account_full_name);
+            }
+        }
+        Ok(())
+    }
+
+    // QODBC only stands in for the exports whose columns it has under the same names
+    fn validate_odbc(&self) -> Result<()> {
+        for block in self.list_blocks.iter().filter(|b| b.odbc()) {
+            if self.odbc.is_none() {
+                anyhow::bail!("List block '{}' has backend = \"odbc\" but there is no [odbc] section", block.label());
+            }
+            crate::lists::check_odbc(block).with_context(|| format!("List block '{}'", block.label()))?;
+        }
+        Ok(())
+    }
+
+    // an incremental run merges the changed records into the table it reads back from the sheet,
+    // which a block spreading its fields over mapped columns doesn't have
+    fn validate_incremental(&self) -> Result<()> {
+        for block in self.list_blocks.iter().filter(|b| b.incremental.unwrap_or(false)) {
+            if !block.columns.is_empty() {
+                anyhow::bail!("List block '{}': columns can't be used with incremental; the changed records are merged into the table read back from cell_address", block.label());
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_jsonl_exports(&self) -> Result<()> {
+        let lists = self.list_blocks.iter().filter_map(|b| Some((format!("List block '{}'", b.label()), b.jsonl.as_ref()?)));
+        let transactions = self.transaction_blocks.iter().filter_map(|b| Some((format!("Transaction block '{}'", b.label()), b.jsonl.as_ref()?)));
+        for (block, export) in lists.chain(transactions) {
+            if export.path.is_some() == export.url.is_some() {
+                anyhow::bail!("{}: jsonl needs either path or url", block);
+            }
+        }
+        Ok(())
+    }
+
+    // serde drops keys no field reads, so compare the file against what the typed config holds
+    fn validate_known_keys(&self, raw: &serde_json::Value) -> Result<()> {
+        let typed = serde_json::to_value(self).context("Failed to check config keys")?;
+        let mut unknown = Vec::new();
+        for (section, value) in raw.as_object().into_iter().flatten() {
+            let mut keys = Vec::new();
+            match (typed.get(section), value) {
+                (None, _) => unknown.push(format!("'{}' at the top level", section)),
+                (Some(serde_json::Value::Array(entries)), serde_json::Value::Array(raw_entries)) => {
+                    for (i, (raw_entry, entry)) in raw_entries.iter().zip(entries).enumerate() {
+                        unknown_keys(raw_entry, entry, "", &mut keys);
+                        unknown.extend(keys.drain(..).map(|key| format!("'{}' in {}", key, self.block_label(section, i))));
+                    }
+                }
+                (Some(entry), _) => {
+                    unknown_keys(value, entry, "", &mut keys);
+                    unknown.extend(keys.into_iter().map(|key| format!("'{}' in [{}]", key, section)));
+                }
+            }
+        }
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        anyhow::bail!("Unknown config key{} {}; check the spelling, or set strict = false to ignore unknown keys",
+            if unknown.len() == 1 { "" } else { "s" }, unknown.join(", "))
+    }
+
+    // how errors name the i-th entry of a block array
+    fn block_label(&self, section: &str, i: usize) -> String {
+        let label = match section {
+            "sync_blocks" => self.sync_blocks.get(i).map(|b| format!("sync block '{}'", b.label())),
+            "timestamp_blocks" => self.timestamp_blocks.get(i).map(|b| format!("timestamp block '{}'", b.label())),
+            "report_blocks" => self.report_blocks.get(i).map(|b| format!("report block '{}'", b.label())),
+            "transaction_blocks" => self.transaction_blocks.get(i).map(|b| format!("transaction block '{}'", b.label())),
+            "list_blocks" => self.list_blocks.get(i).map(|b| format!("list block '{}'", b.label())),
+            "raw_qbxml_blocks" => self.raw_qbxml_blocks.get(i).map(|b| format!("raw qbXML block '{}'", b.label())),
+            "close_snapshots" => self.close_snapshots.get(i).map(|b| format!("close snapshot '{}'", b.label())),
+            "reconciliation_blocks" => self.reconciliation_blocks.get(i).map(|b| format!("reconciliation block '{}'", b.label())),
+            _ => None,
+        };
+        label.unwrap_or_else(|| format!("{}[{}]", section, i))
+    }
+
+    fn validate_reconciliation(&self) -> Result<()> {
+        for block in &self.reconciliation_blocks {
+            if block.tolerance.is_some_and(|t| t < 0.0 || t.is_nan()) {
+                anyhow::bail!("Reconciliation block '{}' tolerance must be zero or more", block.label());
+            }
+            if let ReconciliationSource::Sheet { range, .. } = &block.source {
+                if a1::named_range(range).is_none() {
+                    a1::parse_cell(range).with_context(|| format!("Invalid source range in reconciliation block '{}'; expected one cell", block.label()))?;
+                }
+            }
+            if let ReconciliationSource::Http { pointer: Some(pointer), .. } = &block.source {
+                if !pointer.is_empty() && !pointer.starts_with('/') {
+                    anyhow::bail!("Reconciliation block '{}' pointer must start with '/', e.g. \"/balance\"", block.label());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_rounding(&self) -> Result<()> {
+        let mut policies = vec![("the top-level rounding".to_string(), self.rounding)];
+        policies.extend(self.sync_blocks.iter().map(|b| (format!("sync block '{}'", b.label()), b.rounding)));
+        policies.extend(self.report_blocks.iter().map(|b| (format!("report block '{}'", b.label()), b.rounding)));
+        policies.extend(self.transaction_blocks.iter().map(|b| (format!("transaction block '{}'", b.label()), b.rounding)));
+        policies.extend(self.list_blocks.iter().map(|b| (format!("list block '{}'", b.label()), b.rounding)));
+        for (place, rounding) in policies {
+            if rounding.and_then(|r| r.decimals).is_some_and(|places| places > 28) {
+                anyhow::bail!("Rounding decimals of {} must be from 0 to 28", place);
+            }
+        }
+        Ok(())
+    }
+
+    /// The rounding of a block setting `own`: its own, else the top-level one
+    pub fn rounding(&self, own: Option<RoundingConfig>) -> Option<RoundingConfig> {
+        own.or(self.rounding)
+    }
+
+    fn validate_retention(&self) -> Result<()> {
+        for block in &self.sync_blocks {
+            let appends = block.all_destinations().into_iter().any(|dest| matches!(dest, DestinationConfig::Append { .. }));
+            if block.retention.is_some() && !appends {
+                anyhow::bail!("Sync block '{}' sets retention but doesn't append; it only applies to append mode", block.label());
+            }
+            for dest in block.all_destinations() {
+                let DestinationConfig::Append { retention: Some(retention), .. } = dest else { continue };
+                match (retention.keep_days, retention.daily_after_days) {
+                    (None, None) => anyhow::bail!("Sync block '{}' retention needs keep_days or daily_after_days", block.label()),
+                    (Some(0), _) => anyhow::bail!("Sync block '{}' retention keep_days must be at least 1", block.label()),
+                    (Some(keep), Some(daily)) if daily >= keep => anyhow::bail!(
+                        "Sync block '{}' retention daily_after_days ({}) must be less than keep_days ({})", block.label(), daily, keep),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_parallel_sessions(&self) -> Result<()> {
+        match self.quickbooks.parallel_sessions {
+            Some(n) if !(1..=MAX_PARALLEL_SESSIONS).contains(&n) => {
+                anyhow::bail!("[quickbooks] parallel_sessions must be between 1 and {}, got {}", MAX_PARALLEL_SESSIONS, n)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn validate_circuit_breaker(&self) -> Result<()> {
+        let Some(breaker) = &self.quickbooks.circuit_breaker else { return Ok(()) };
+        if breaker.failures == Some(0) {
+            anyhow::bail!("[quickbooks.circuit_breaker] failures must be at least 1");
+        }
+        for (name, limit) in [("max_request_secs", breaker.max_request_secs), ("max_response_kb", breaker.max_response_kb), ("max_request_kb", breaker.max_request_kb)] {
+            if limit == Some(0) {
+                anyhow::bail!("[quickbooks.circuit_breaker] {} must be at least 1; leave it out for no limit", name);
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_watchdog(&self) -> Result<()> {
+        let Some(watchdog) = &self.watchdog else { return Ok(()) };
+        if watchdog.stale_after_mins == 0 {
+            anyhow::bail!("[watchdog] stale_after_mins must be at least 1");
+        }
+        let labels = self.block_labels();
+        if let Some(unknown) = watchdog.blocks.iter().find(|b| !labels.contains(b)) {
+            anyhow::bail!("[watchdog] watches '{}', which is not the name of any block", unknown);
+        }
+        Ok(())
+    }
+
+    // redact names columns by their header, so a typo would quietly leave the data in
+    fn validate_redaction(&self) -> Result<()> {
+        for block in &self.list_blocks {
+            crate::redact::for_list_block(block, self).with_context(|| format!("List block '{}'", block.label()))?;
+        }
+        for block in &self.transaction_blocks {
+            crate::redact::for_transaction_block(block, self).with_context(|| format!("Transaction block '{}'", block.label()))?;
+        }
+        Ok(())
+    }
+
+    // Webapp header names and values must be valid HTTP; otherwise every post would fail mid-run
+    fn validate_headers(&self) -> Result<()> {
+        let mut all = vec![("[google_sheets]".to_string(), &self.google_sheets.headers)];
+        for block in &self.sync_blocks {
+            for dest in &block.destinations {
+                if let DestinationConfig::Cell { headers, .. } | DestinationConfig::Append { headers, .. } | DestinationConfig::Cells { headers, .. } = dest {
+                    all.push((format!("sync block '{}' destination", block.label()), headers));
+                }
+            }
+        }
+        for (place, headers) in all {
+            for (name, value) in headers {
+                if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                    anyhow::bail!("Invalid header name '{}' in {}", name, place);
+                }
+                if reqwest::header::HeaderValue::from_str(value).is_err() {
+                    anyhow::bail!("Invalid value for header '{}' in {}", name, place);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Every cell_address must be a single A1 cell; a typo fails the load instead of a write mid-run
+    fn validate_cell_addresses(&self) -> Result<()> {
+        // (place, address, whether it may be "named:NAME"): only single cells written through the
+        // webapp can, since range anchors and Excel cells are positioned on this side
+        let mut addresses: Vec<(String, &str, bool)> = Vec::new();
+        for block in &self.sync_blocks {
+            for dest in &block.destinations {
+                match dest {
+                    // "APPEND:A" is checked below as an append column
+                    DestinationConfig::Cell { cell_address, .. } if a1::append_column(cell_address).is_some() => {}
+                    DestinationConfig::Cell { cell_address, .. } => addresses.push((format!("sync block '{}' destination", block.label()), cell_address, true)),
+                    DestinationConfig::Excel { cell_address, .. } => addresses.push((format!("sync block '{}' Excel destination", block.label()), cell_address, false)),
+                    _ => {}
+                }
+            }
+            if block.mode == SyncMode::Cell && !block.cell_address.is_empty() && a1::append_column(&block.cell_address).is_none() {
+                addresses.push((format!("sync block '{}'", block.label()), &block.cell_address, true));
+            }
+            for dest in block.all_destinations() {
+                match dest {
+                    DestinationConfig::Append { append_column: Some(column), .. } if a1::column_number(&column).is_none() => {
+                        anyhow::bail!("Invalid append column '{}' in sync block '{}'; expected column letters such as A", column, block.label());
+                    }
+                    DestinationConfig::Cells { cells, .. } => {
+                        cells.layout().with_context(|| format!("Invalid cells in sync block '{}'", block.label()))?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for block in &self.timestamp_blocks {
+            // both cells go out as one range write, which needs a real cell to anchor it
+            let single_cell = !block.iso_utc.unwrap_or(false);
+            addresses.push((format!("timestamp block '{}'", block.label()), &block.cell_address, single_cell));
+            if let Some(format) = &block.display_format {
+                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    anyhow::bail!("Invalid display_format '{}' in timestamp block '{}'", format, block.label());
+                }
+            }
+        }
+        for block in &self.reconciliation_blocks {
+            // the amounts are written to the right of the status, which needs a real cell
+            let single_cell = !block.write_amounts.unwrap_or(false);
+            addresses.push((format!("reconciliation block '{}'", block.label()), &block.cell_address, single_cell));
+        }
+        for block in &self.report_blocks {
+            addresses.push((format!("report block '{}'", block.label()), &block.cell_address, false));
+        }
+        for block in &self.transaction_blocks {
+            addresses.push((format!("transaction block '{}'", block.label()), &block.cell_address, false));
+        }
+        for block in &self.list_blocks {
+            addresses.push((format!("list block '{}'", block.label()), &block.cell_address, false));
+        }
+        for block in &self.raw_qbxml_blocks {
+            if let Some(cell_address) = &block.cell_address {
+                addresses.push((format!("raw qbXML block '{}'", block.label()), cell_address, false));
+            }
+            for field in &block.fields {
+                addresses.push((format!("raw qbXML block '{}' field '{}'", block.label(), field.xpath), &field.cell_address, true));
+            }
+        }
+        if let Some(cell_address) = self.company_info.as_ref().and_then(|c| c.cell_address.as_deref()) {
+            addresses.push(("[company_info]".to_string(), cell_address, false));
+        }
+        if let Some(cell_address) = self.aging.as_ref().and_then(|a| a.cell_address.as_deref()) {
+            addresses.push(("[aging]".to_string(), cell_address, false));
+        }
+        if let Some(cell_address) = self.status_sheet.as_ref().and_then(|c| c.cell_address.as_deref()) {
+            addresses.push(("[status_sheet]".to_string(), cell_address, false));
+        }
+        if let Some(watchdog) = &self.watchdog {
+            addresses.push(("[watchdog]".to_string(), &watchdog.cell_address, true));
+        }
+        if let Some(kpis) = &self.kpis {
+            for (kpi, cell_address) in &kpis.cells {
+                addresses.push((format!("[kpis] {}", kpi), cell_address, true));
+            }
+        }
+        for (place, address, named_ok) in addresses {
+            if a1::append_column(address).is_some() {
+                anyhow::bail!("Invalid cell_address in {}: '{}' appends a row, which only sync block cells can do; give the cell", place, address.trim());
+            }
+            match a1::named_range(address) {
+                Some(_) if !named_ok => anyhow::bail!("Invalid cell_address in {}: '{}' is a named range, which only single cells sent to the webapp can use; give the cell", place, address.trim()),
+                Some(name) => a1::parse_range_name(name).map(|_| ()),
+                None => a1::parse_cell(address).map(|_| ()),
+            }
+            .with_context(|| format!("Invalid cell_address in {}", place))?;
+        }
+        Ok(())
+    }
+
+    // Two blocks writing the same cell overwrite each other every cycle, and the sheet shows
+    // whichever finished last. Range blocks are compared by their top-left cell only, since how far
+    // they reach depends on the data.
+    fn validate_collisions(&self) -> Result<()> {
+        // (spreadsheet or workbook, sheet, cell) -> every place writing there
+        let mut writers: BTreeMap<(String, String, String), Vec<String>> = BTreeMap::new();
+        let mut add = |spreadsheet: &str, sheet: &str, cell_address: &str, place: String| {
+            // a named range is the same cell wherever it is written from, whatever the sheet_name
+            if let Some(name) = a1::named_range(cell_address) {
+                writers.entry((spreadsheet.to_string(), String::new(), format!("named:{}", name))).or_default().push(place);
+                return;
+            }
+            // addresses were checked by validate_cell_addresses; "$b$2" and "B2" are the same cell
+            if let Ok(cell) = a1::parse_cell(cell_address) {
+                writers.entry((spreadsheet.to_string(), sheet.to_string(), cell.to_string())).or_default().push(place);
+            }
+        };
+        for block in &self.sync_blocks {
+            for dest in block.all_destinations() {
+                match dest {
+                    DestinationConfig::Cell { spreadsheet_id, sheet_name, cell_address, .. } => add(
+                        spreadsheet_id.as_deref().unwrap_or(&block.spreadsheet_id),
+                        sheet_name.as_deref().unwrap_or(&block.sheet_name),
+                        &cell_address,
+                        format!("sync block '{}'", block.label()),
+                    ),
+                    DestinationConfig::Excel { drive_id, item_id, path, worksheet, cell_address } => add(
+                        &format!("{}/{}", drive_id, item_id.or(path).unwrap_or_default()),
+                        worksheet.as_deref().unwrap_or(&block.sheet_name),
+                        &cell_address,
+                        format!("sync block '{}' (Excel)", block.label()),
+                    ),
+                    DestinationConfig::Cells { spreadsheet_id, sheet_name, cells, .. } => {
+                        for (_, cell_address) in cells.entries() {
+                            add(
+                                spreadsheet_id.as_deref().unwrap_or(&block.spreadsheet_id),
+                                sheet_name.as_deref().unwrap_or(&block.sheet_name),
+                                cell_address,
+                                format!("sync block '{}'", block.label()),
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for block in &self.timestamp_blocks {
+            add(&block.spreadsheet_id, &block.sheet_name, &block.cell_address, format!("timestamp block '{}'", block.label()));
+            if let Some(cell) = block.iso_cell() {
+                add(&block.spreadsheet_id, &block.sheet_name, &cell.to_string(), format!("timestamp block '{}'", block.label()));
+            }
+        }
+        for block in &self.reconciliation_blocks {
+            let place = format!("reconciliation block '{}'", block.label());
+            add(&block.spreadsheet_id, &block.sheet_name, &block.cell_address, place.clone());
+            for cell in block.amount_cells() {
+                add(&block.spreadsheet_id, &block.sheet_name, &cell.to_string(), place.clone());
+            }
+        }
+        for block in &self.report_blocks {
+            add(&block.spreadsheet_id, &block.sheet_name, &block.cell_address, format!("report block '{}'", block.label()));
+        }
+        for block in &self.transaction_blocks {
+            add(&block.spreadsheet_id, &block.sheet_name, &block.cell_address, format!("transaction block '{}'", block.label()));
+        }
+        for block in &self.list_blocks {
+            add(&block.spreadsheet_id, &block.sheet_name, &block.cell_address, format!("list block '{}'", block.label()));
+        }
+        for block in &self.raw_qbxml_blocks {
+            for field in block.field_mappings().unwrap_or_default() {
+                let sheet_name = field.sheet_name.as_deref().unwrap_or(&block.sheet_name);
+                add(&block.spreadsheet_id, sheet_name, &field.cell_address, format!("raw qbXML block '{}'", block.label()));
+            }
+        }
+        if let Some(info) = &self.company_info {
+            add(&info.spreadsheet_id, &info.sheet_name, info.cell_address.as_deref().unwrap_or("A1"), "[company_info]".to_string());
+        }
+        if let Some(aging) = &self.aging {
+            add(&aging.spreadsheet_id, &aging.sheet_name, aging.cell_address.as_deref().unwrap_or("A1"), "[aging]".to_string());
+        }
+        if let Some(status) = &self.status_sheet {
+            add(&status.spreadsheet_id, &status.sheet_name, status.cell_address.as_deref().unwrap_or("A1"), "[status_sheet]".to_string());
+        }
+        if let Some(watchdog) = &self.watchdog {
+            add(&watchdog.spreadsheet_id, &watchdog.sheet_name, &watchdog.cell_address, "[watchdog]".to_string());
+        }
+        if let Some(kpis) = &self.kpis {
+            for (kpi, cell_address) in &kpis.cells {
+                add(&kpis.spreadsheet_id, &kpis.sheet_name, cell_address, format!("[kpis] {}", kpi));
+            }
+        }
+
+        let collisions: Vec<String> = writers.into_iter()
+            .filter(|(_, places)| places.len() > 1)
+            .map(|((_, sheet, cell), places)| match sheet.as_str() {
+                "" => format!("{} is written by {}", cell, places.join(" and ")),
+                _ => format!("{}!{} is written by {}", a1::quote_sheet_name(&sheet), cell, places.join(" and ")),
+            })
+            .collect();
+        if collisions.is_empty() {
+            return Ok(());
+        }
+        if self.allow_collisions.unwrap_or(false) {
+            for collision in &collisions {
+                log::warn!("[CONFIG] {}", collision);
+            }
+            return Ok(());
+        }
+        anyhow::bail!("Blocks overwrite each other's cells (set allow_collisions = true to allow it):\n  {}", collisions.join("\n  "))
+    }
+
+    // Replaces enc:dpapi:/enc:age: values with their plaintext; new sensitive fields belong here too
+    fn decrypt_secrets(&mut self) -> Result<()> {
+        let identity_file = self.secrets.as_ref().and_then(|s| s.age_identity_file.clone());
+        if secrets::is_encrypted(&self.google_sheets.api_key) {
+            self.google_sheets.api_key = secrets::reveal(&self.google_sheets.api_key, identity_file.as_deref())
+                .context("Failed to decrypt google_sheets.api_key")?;
+        }
+        if let Some(secret) = self.google_sheets.signing_secret.as_mut().filter(|s| secrets::is_encrypted(s)) {
+            *secret = secrets::reveal(secret, identity_file.as_deref())
+                .context("Failed to decrypt google_sheets.signing_secret")?;
+        }
+        if let Some(graph) = self.microsoft_graph.as_mut().filter(|g| secrets::is_encrypted(&g.client_secret)) {
+            graph.client_secret = secrets::reveal(&graph.client_secret, identity_file.as_deref())
+                .context("Failed to decrypt microsoft_graph.client_secret")?;
+        }
+        if let Some(airtable) = self.airtable.as_mut().filter(|a| secrets::is_encrypted(&a.token)) {
+            airtable.token = secrets::reveal(&airtable.token, identity_file.as_deref())
+                .context("Failed to decrypt airtable.token")?;
+        }
+        if let Some(s3) = self.s3.as_mut().filter(|s| secrets::is_encrypted(&s.secret_access_key)) {
+            s3.secret_access_key = secrets::reveal(&s3.secret_access_key, identity_file.as_deref())
+                .context("Failed to decrypt s3.secret_access_key")?;
+        }
+        // webhook and webapp headers usually carry a token
+        for (name, value) in self.google_sheets.headers.iter_mut().filter(|(_, v)| secrets::is_encrypted(v)) {
+            *value = secrets::reveal(value, identity_file.as_deref())
+                .with_context(|| format!("Failed to decrypt google_sheets header {}", name))?;
+        }
+        for block in &mut self.sync_blocks {
+            for dest in &mut block.destinations {
+                if let DestinationConfig::Webhook { headers, .. } | DestinationConfig::Cell { headers, .. } | DestinationConfig::Append { headers, .. }
+                    | DestinationConfig::Cells { headers, .. } = dest
+                {
+                    for (name, value) in headers.iter_mut().filter(|(_, v)| secrets::is_encrypted(v)) {
+                        *value = secrets::reveal(value, identity_file.as_deref())
+                            .with_context(|| format!("Failed to decrypt destination header {}", name))?;
+                    }
+                }
+            }
+        }
+        if let Some(key) = self.redaction.as_mut().and_then(|r| r.hash_key.as_mut()).filter(|k| secrets::is_encrypted(k)) {
+            *key = secrets::reveal(key, identity_file.as_deref())
+                .context("Failed to decrypt redaction.hash_key")?;
+        }
+        for block in &mut self.reconciliation_blocks {
+            if let ReconciliationSource::Http { headers, .. } = &mut block.source {
+                for (name, value) in headers.iter_mut().filter(|(_, v)| secrets::is_encrypted(v)) {
+                    *value = secrets::reveal(value, identity_file.as_deref())
+                        .with_context(|| format!("Failed to decrypt reconciliation source header {}", name))?;
+                }
+            }
+        }
+        if let Some(telemetry) = self.telemetry.as_mut() {
+            for (name, value) in telemetry.headers.iter_mut().filter(|(_, v)| secrets::is_encrypted(v)) {
+                *value = secrets::reveal(value, identity_file.as_deref())
+                    .with_context(|| format!("Failed to decrypt telemetry header {}", name))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// serde aliases, which the typed config serializes under the field's own name
+const KEY_ALIASES: &[(&str, &str)] = &[("ratio", "expression"), ("expr", "expression"), ("offsets", "cells")];
+
+// Collects the dotted paths of keys in `raw` that `typed` (the same tree after a serde round trip) lacks
+fn unknown_keys(raw: &serde_json::Value, typed: &serde_json::Value, path: &str, found: &mut Vec<String>) {
+    match (raw, typed) {
+        (serde_json::Value::Object(raw), serde_json::Value::Object(typed)) => {
+            for (key, value) in raw {
+                let name = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                let field = typed.get(key).or_else(|| {
+                    KEY_ALIASES.iter().find(|(alias, _)| alias == key).and_then(|(_, field)| typed.get(*field))
+                });
+                match field {
+                    Some(field) => unknown_keys(value, field, &name, found),
+                    None => found.push(name),
+                }
+            }
+        }
+        (serde_json::Value::Array(raw), serde_json::Value::Array(typed)) => {
+            for (i, (value, field)) in raw.iter().zip(typed).enumerate() {
+                unknown_keys(value, field, &format!("{}[{}]", path, i), found);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Brings a raw config tree up to CONFIG_VERSION, warning about each upgrade so the file gets fixed.
+// Returns whether anything had to change.
+fn migrate(raw: &mut serde_json::Value) -> Result<bool> {
+    let Some(root) = raw.as_object_mut() else { return Ok(false) };
+    let version = match root.get("version") {
+        Some(v) => v.as_u64().and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| anyhow!("Config version must be a whole number, got {}", v))?,
+        None if is_single_account_layout(root) => 0,
+        // written before versioning but already in the [[sync_blocks]] layout
+        None => 1,
+    };
+    if version > CONFIG_VERSION {
+        anyhow::bail!("Config version {} is newer than this qb_sync supports ({}); upgrade qb_sync", version, CONFIG_VERSION);
+    }
+    if version == CONFIG_VERSION {
+        return Ok(false);
+    }
+    if version < 1 {
+        migrate_single_account(root);
+    }
+    if version < 2 {
+        migrate_confirm_destructive(root);
+    }
+    root.insert("version".to_string(), CONFIG_VERSION.into());
+    Ok(true)
+}
+
+// the original layout: one account named in [quickbooks], its cell in [google_sheets]
+fn is_single_account_layout(root: &serde_json::Map<String, serde_json::Value>) -> bool {
+    let has = |table: &str, key: &str| root.get(table).and_then(|t| t.get(key)).is_some();
+    !root.contains_key("sync_blocks")
+        || has("quickbooks", "account_full_name")
+        || has("google_sheets", "spreadsheet_id")
+}
+
+// version 0 -> 1: the single account becomes the first [[sync_blocks]] entry, app_id / app_name
+// become application_id / application_name and missing block arrays default to empty
+fn migrate_single_account(root: &mut serde_json::Map<String, serde_json::Value>) {
+    log::warn!("[CONFIG] Config uses the single-account layout from before [[sync_blocks]]; upgrading it in memory. \
+        Move the account to a [[sync_blocks]] entry and add version = {} to the top of the file", CONFIG_VERSION);
+    let mut block = serde_json::Map::new();
+    if let Some(qb) = root.get_mut("quickbooks").and_then(|t| t.as_object_mut()) {
+        for (old, new) in [("app_id", "application_id"), ("app_name", "application_name")] {
+            if let Some(value) = qb.remove(old) {
+                log::warn!("[CONFIG] quickbooks.{} is now quickbooks.{}", old, new);
+                qb.entry(new).or_insert(value);
+            }
+        }
+        if let Some(value) = qb.remove("account_full_name") {
+            block.insert("account_full_name".to_string(), value);
+        }
+    }
+    if let Some(gs) = root.get_mut("google_sheets").and_then(|t| t.as_object_mut()) {
+        for key in ["spreadsheet_id", "sheet_name", "cell_address"] {
+            if let Some(value) = gs.remove(key) {
+                block.insert(key.to_string(), value);
+            }
+        }
+    }
+    let sync_blocks = root.entry("sync_blocks").or_insert_with(|| serde_json::Value::Array(Vec::new()));
+    if let (false, Some(blocks)) = (block.is_empty(), sync_blocks.as_array_mut()) {
+        log::warn!("[CONFIG] [quickbooks] account_full_name and [google_sheets] spreadsheet_id / sheet_name / cell_address \
+            are now a [[sync_blocks]] entry");
+        blocks.push(serde_json::Value::Object(block));
+    }
+    root.entry("timestamp_blocks").or_insert_with(|| serde_json::Value::Array(Vec::new()));
+}
+
+// version 1 -> 2: safe mode came with version 2, so older files keep removing rows as they did
+// unless they say otherwise
+fn migrate_confirm_destructive(root: &mut serde_json::Map<String, serde_json::Value>) {
+    let Some(gs) = root.get_mut("google_sheets").and_then(|t| t.as_object_mut()) else { return };
+    if !gs.contains_key("confirm_destructive") {
+        log::warn!("[CONFIG] Config predates safe mode, so clear_below and retention still remove rows; \
+            set version = {} to preview them instead, or [google_sheets] confirm_destructive = true to keep this", CONFIG_VERSION);
+        gs.insert("confirm_destructive".to_string(), true.into());
+    }
+}
+
+// [vars] name = "value", then "${name}" anywhere in a string value of the config is replaced, so
+// dozens of nearly identical blocks don't each repeat the spreadsheet id or account prefix. A
+// string that is only "${name}" takes the variable's own type, so numbers stay numbers. "$${" is a
+// literal "${". value_template keeps unknown names as they are, since "${balance}" there is a dollar
+// sign and a placeholder. Without a [vars] table nothing is touched. Returns whether anything was.
+fn interpolate_vars(raw: &mut serde_json::Value) -> Result<bool> {
+    let Some(root) = raw.as_object_mut() else { return Ok(false) };
+    let Some(vars) = root.remove("vars") else { return Ok(false) };
+    let serde_json::Value::Object(vars) = vars else {
+        anyhow::bail!("[vars] must be a table of name = value");
+    };
+    for (name, value) in &vars {
+        if !(value.is_string() || value.is_number() || value.is_boolean()) {
+            anyhow::bail!("[vars] {} must be a string, number or boolean", name);
+        }
+    }
+    for (key, value) in root.iter_mut() {
+        interpolate_value(value, key, &vars)?;
+    }
+    Ok(true)
+}
+
+fn interpolate_value(value: &mut serde_json::Value, path: &str, vars: &serde_json::Map<String, serde_json::Value>) -> Result<()> {
+    match value {
+        serde_json::Value::String(text) if text.contains("${") => {
+            let keep_unknown = path.ends_with("value_template");
+            *value = interpolate_text(text, vars, keep_unknown).with_context(|| format!("Config value {}", path))?;
+        }
+        serde_json::Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                interpolate_value(item, &format!("{}[{}]", path, i), vars)?;
+            }
+        }
+        serde_json::Value::Object(table) => {
+            for (key, item) in table.iter_mut() {
+                interpolate_value(item, &format!("{}.{}", path, key), vars)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// "${sheet} Balances" with sheet = "Ops" => "Ops Balances"
+fn interpolate_text(text: &str, vars: &serde_json::Map<String, serde_json::Value>, keep_unknown: bool) -> Result<serde_json::Value> {
+    let as_text = |value: &serde_json::Value| match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    if let Some(value) = text.strip_prefix("${").and_then(|t| t.strip_suffix('}')).and_then(|name| vars.get(name.trim())) {
+        return Ok(value.clone());
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start..];
+        if let Some(literal) = after.strip_prefix("$${") {
+            out.push_str("${");
+            rest = literal;
+        } else if let Some(open) = after.strip_prefix("${") {
+            let Some(end) = open.find('}') else {
+                anyhow::bail!("'${{' without a closing '}}' in \"{}\"", text);
+            };
+            match vars.get(open[..end].trim()) {
+                Some(value) => out.push_str(&as_text(value)),
+                None if keep_unknown => out.push_str(&after[..end + 3]),
+                None => anyhow::bail!("unknown variable '{}'; define it under [vars]", open[..end].trim()),
+            }
+            rest = &open[end + 1..];
+        } else {
+            out.push('$');
+            rest = &after[1..];
+        }
+    }
+    out.push_str(rest);
+    Ok(serde_json::Value::String(out))
+}
+
+// config/config.toml + "prod" => config/config.prod.toml
+fn overlay_path(base: &Path, env: &str) -> PathBuf {
+    let stem = base.file_stem().and_then(|s| s.to_str()).unwrap_or("config");
+    let ext = base.extension().and_then(|s| s.to_str()).unwrap_or("toml");
+    base.with_file_name(format!("{}.{}.{}", stem, env, ext))
 }
@@ -0,0 +1,67 @@
+// What the blocks of one config share: the config, one HTTP client, the Sheets write budget, the
+// history file, the offline queue and which spreadsheets are failing this cycle
+//
+// Built once per config (once per tenant with --config-dir, once for a whole daemon run) and handed
+// to the process_* functions, so none of them builds its own client or reloads the history. The
+// HTTP client is a pool of keep-alive connections shared by the webapp, every destination and the
+// exporters; each request sets its own timeout.
+//
+// However many blocks write at once, at most [pipeline] write_concurrency webapp requests are in
+// flight; the rest wait for a slot.
+
+use std::sync::{Arc, OnceLock};
+
+use tokio::sync::Semaphore;
+
+use crate::config::Config;
+use crate::google_sheets::GoogleSheetsClient;
+use crate::history::HistoryStore;
+use crate::offline_queue::OfflineQueue;
+use crate::quota::Quota;
+use crate::sheet_faults::SheetFaults;
+
+// one pool for the whole process; reqwest::Client is a cheap handle to it
+static HTTP: OnceLock<reqwest::Client> = OnceLock::new();
+
+// Apps Script runs up to 30 executions at once per user; this leaves room for everyone else's
+pub const DEFAULT_WRITE_CONCURRENCY: usize = 10;
+
+pub struct AppContext<'a> {
+    pub config: &'a Config,
+    pub http: reqwest::Client,
+    // [google_sheets] writes_per_minute windows per spreadsheet
+    pub quota: Arc<Quota>,
+    pub history: HistoryStore,
+    // [google_sheets] offline_queue
+    pub offline_queue: Option<Arc<OfflineQueue>>,
+    // cleared when a cycle starts
+    pub sheet_faults: Arc<SheetFaults>,
+    // a permit per webapp request in flight
+    pub webapp_slots: Arc<Semaphore>,
+}
+
+impl<'a> AppContext<'a> {
+    /// The context for `config`, on the process-wide HTTP client and Sheets write budget
+    pub fn new(config: &'a Config) -> Self {
+        let http = HTTP.get_or_init(reqwest::Client::new).clone();
+        let slots = config.pipeline.as_ref().and_then(|p| p.write_concurrency).unwrap_or(DEFAULT_WRITE_CONCURRENCY).max(1);
+        Self {
+            config,
+            http,
+            quota: Quota::shared(),
+            history: HistoryStore::open(config),
+            offline_queue: OfflineQueue::open(config),
+            sheet_faults: Default::default(),
+            webapp_slots: Arc::new(Semaphore::new(slots)),
+        }
+    }
+
+    /// A webapp client for one spreadsheet
+    pub fn sheets(&self, spreadsheet_id: impl Into<String>) -> GoogleSheetsClient {
+        let mut client = GoogleSheetsClient::new(&self.config.google_sheets, self.http.clone(), self.quota.clone(), spreadsheet_id.into());
+        client.offline_queue = self.offline_queue.clone();
+        client.faults = Some(self.sheet_faults.clone());
+        client.slots = Some(self.webapp_slots.clone());
+        client
+    }
+}
@@ -0,0 +1,72 @@
+// Correlation ids: one per run (sync cycle) and one per block
+//
+// They are sent with every webapp request (runId / requestId fields and X-Run-Id / X-Request-Id
+// headers), added to log lines and kept in the run summary, so an error in the Apps Script
+// executions log can be matched to the service log that caused it.
+
+use std::future::Future;
+
+tokio::task_local! {
+    static RUN_ID: String;
+    static BLOCK_ID: String;
+    // the same ids, but assigned with correlation ids off too: idempotency keys are made of them
+    static CYCLE_KEY: String;
+    static BLOCK_KEY: String;
+}
+
+pub fn new_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Runs `f` with a fresh run id; with `enabled = false` no ids are assigned anywhere in the run
+pub async fn in_run<F: Future>(enabled: bool, f: F) -> F::Output {
+    let id = new_id();
+    CYCLE_KEY.scope(id.clone(), async move {
+        if enabled {
+            RUN_ID.scope(id, f).await
+        } else {
+            f.await
+        }
+    }).await
+}
+
+/// Runs `f` with a fresh block id (only inside a run that has ids)
+pub async fn in_block<F: Future>(f: F) -> F::Output {
+    let id = new_id();
+    let enabled = run_id().is_some();
+    BLOCK_KEY.scope(id.clone(), async move {
+        if enabled {
+            BLOCK_ID.scope(id, f).await
+        } else {
+            f.await
+        }
+    }).await
+}
+
+pub fn run_id() -> Option<String> {
+    RUN_ID.try_with(|id| id.clone()).ok()
+}
+
+pub fn block_id() -> Option<String> {
+    BLOCK_ID.try_with(|id| id.clone()).ok()
+}
+
+/// "<cycle>:<block>" for the idempotency key of a write made here, whether or not correlation ids
+/// are on (they are the run and block ids when they are); None outside a cycle
+pub fn write_scope() -> Option<String> {
+    let cycle = CYCLE_KEY.try_with(|id| id.clone()).ok()?;
+    let block = BLOCK_KEY.try_with(|id| id.clone()).unwrap_or_default();
+    Some(format!("{}:{}", cycle, block))
+}
+
+/// " run=<id> block=<id>" for log lines, empty outside a run
+pub fn log_suffix() -> String {
+    let mut suffix = String::new();
+    if let Some(run) = run_id() {
+        suffix.push_str(&format!(" run={}", run));
+    }
+    if let Some(block) = block_id() {
+        suffix.push_str(&format!(" block={}", block));
+    }
+    suffix
+}
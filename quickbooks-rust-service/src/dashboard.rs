@@ -0,0 +1,261 @@
+// [dashboard]: a status page for the daemon at http://127.0.0.1:8470/, for whoever looks after the
+// sync without reading its logs
+//
+// One row per block: how it last went, the value it wrote, when it last succeeded and the error it
+// last failed with. Sync blocks get a Run button, which goes through the same queue as the local
+// API's RunSyncBlock, so a press mid-cycle waits for the cycle to finish. The page reloads itself
+// every 30 seconds. State is kept in memory since the daemon started; the status sheet and history
+// file are the record across restarts.
+//
+// Requests are only answered for a Host naming the listen address or loopback, so a hostile site
+// whose name is made to resolve to 127.0.0.1 (DNS rebinding) gets neither the page nor its token.
+// A Run post also has to come from the page itself: the browser's Origin must match that Host, and
+// the form carries a token made when the daemon started, which only the page has.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+use crate::config::{Config, DashboardConfig};
+use crate::local_api::{self, Call, Request};
+use crate::summary::{RunSummary, Status};
+
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8470";
+// a form post is one short field; anything bigger isn't from the page
+const MAX_BODY: usize = 4096;
+// the request line and headers a browser sends, with room to spare
+const MAX_HEADERS: u64 = 16 * 1024;
+// a client that stalls mid-request is dropped rather than holding its task
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const REFRESH_SECS: u32 = 30;
+
+static BLOCKS: OnceLock<Mutex<BTreeMap<String, BlockState>>> = OnceLock::new();
+
+#[derive(Debug, Clone, Default)]
+struct BlockState {
+    status: &'static str,
+    value: Option<String>,
+    last_run: Option<DateTime<Local>>,
+    last_success: Option<DateTime<Local>>,
+    // the last failure, kept after the block recovers
+    error: Option<(DateTime<Local>, String)>,
+}
+
+/// Serves the page and returns the address it is on; blocks show up as "not run yet" until the
+/// first cycle reports them
+pub async fn start(cfg: &DashboardConfig, config: &Config, calls: mpsc::Sender<Call>) -> Result<SocketAddr> {
+    let listen = cfg.listen.as_deref().unwrap_or(DEFAULT_LISTEN);
+    let listener = TcpListener::bind(listen).await
+        .with_context(|| format!("Failed to listen on {} for [dashboard] (is another qb_sync running?)", listen))?;
+    let address = listener.local_addr().context("Failed to read the [dashboard] address")?;
+    let hosts: Arc<Vec<String>> = Arc::new(allowed_hosts(listen, address));
+    let blocks = BLOCKS.get_or_init(Default::default);
+    {
+        let mut blocks = blocks.lock().unwrap_or_else(|e| e.into_inner());
+        for label in config.block_labels() {
+            blocks.entry(label).or_insert_with(|| BlockState { status: "not run yet", ..Default::default() });
+        }
+    }
+    let runnable: Vec<String> = config.sync_blocks.iter().map(|b| b.label().to_string()).collect();
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    log::info!("[DASHBOARD] Serving http://{}/", address);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve(stream, hosts.clone(), runnable.clone(), token.clone(), calls.clone()));
+                }
+                Err(e) => log::warn!("[DASHBOARD] Connection failed: {}", e),
+            }
+        }
+    });
+    Ok(address)
+}
+
+// The Host headers a browser sends for the page: the listen address as configured and as bound,
+// and the loopback names on its port, lowercased
+fn allowed_hosts(listen: &str, address: SocketAddr) -> Vec<String> {
+    let port = address.port();
+    let mut hosts = vec![listen.to_ascii_lowercase(), address.to_string()];
+    hosts.extend([format!("127.0.0.1:{}", port), format!("localhost:{}", port), format!("[::1]:{}", port)]);
+    hosts
+}
+
+/// Takes in a cycle's (or a Run button's) outcomes; does nothing without [dashboard]
+pub fn record(summary: &RunSummary) {
+    let Some(blocks) = BLOCKS.get() else { return };
+    let mut by_block: BTreeMap<String, Vec<_>> = BTreeMap::new();
+    for outcome in summary.outcomes() {
+        by_block.entry(outcome.block.clone()).or_default().push(outcome);
+    }
+    let mut blocks = blocks.lock().unwrap_or_else(|e| e.into_inner());
+    for (block, outcomes) in by_block {
+        let state = blocks.entry(block).or_default();
+        let at = outcomes.iter().map(|o| o.at).max();
+        let errors: Vec<String> = outcomes.iter()
+            .filter_map(|o| match &o.status {
+                Status::Error(e) => Some(format!("{}: {}", o.destination, e)),
+                _ => None,
+            })
+            .collect();
+        let any_ok = outcomes.iter().any(|o| matches!(o.status, Status::Ok));
+        state.last_run = at;
+        state.value = outcomes.iter().find_map(|o| o.value.clone()).or(state.value.take());
+        state.status = if !errors.is_empty() {
+            "error"
+        } else if any_ok {
+            "ok"
+        } else {
+            "skipped"
+        };
+        if let (false, Some(at)) = (errors.is_empty(), at) {
+            state.error = Some((at, errors.join("; ")));
+        }
+        if errors.is_empty() && any_ok {
+            state.last_success = at;
+        }
+    }
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    host: Option<String>,
+    origin: Option<String>,
+    body: String,
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<HttpRequest> {
+    tokio::time::timeout(READ_TIMEOUT, read_capped(stream)).await
+        .map_err(|_| anyhow::anyhow!("no complete request within {}s", READ_TIMEOUT.as_secs()))?
+}
+
+// at most MAX_HEADERS of request line and headers and MAX_BODY of body are ever buffered
+async fn read_capped(stream: &mut TcpStream) -> Result<HttpRequest> {
+    let mut reader = BufReader::new(stream.take(MAX_HEADERS + MAX_BODY as u64));
+    let mut line = String::new();
+    read_line(&mut reader, &mut line).await?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default().to_string(), parts.next().unwrap_or("/").to_string());
+    let (mut host, mut origin, mut length) = (None, None, 0);
+    loop {
+        let mut header = String::new();
+        if read_line(&mut reader, &mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else { continue };
+        let value = value.trim().to_string();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "host" => host = Some(value),
+            "origin" => origin = Some(value),
+            "content-length" => length = value.parse().unwrap_or(0),
+            _ => {}
+        }
+    }
+    let mut body = vec![0u8; length.min(MAX_BODY)];
+    reader.read_exact(&mut body).await?;
+    Ok(HttpRequest { method, path, host, origin, body: String::from_utf8_lossy(&body).into_owned() })
+}
+
+// a line cut off by the cap (no newline before it) is refused rather than read as complete
+async fn read_line<R: AsyncBufReadExt + Unpin>(reader: &mut R, line: &mut String) -> Result<usize> {
+    let read = reader.read_line(line).await?;
+    if read > 0 && !line.ends_with('\n') {
+        anyhow::bail!("request headers longer than {} bytes", MAX_HEADERS);
+    }
+    Ok(read)
+}
+
+async fn serve(mut stream: TcpStream, hosts: Arc<Vec<String>>, runnable: Vec<String>, token: String, calls: mpsc::Sender<Call>) {
+    let request = match read_request(&mut stream).await {
+        Ok(request) => request,
+        Err(e) => {
+            log::debug!("[DASHBOARD] Unreadable request: {}", e);
+            return;
+        }
+    };
+    // before anything else: a rebound name reaches this port with its own Host, even for a GET
+    let known_host = request.host.as_ref().is_some_and(|host| hosts.contains(&host.to_ascii_lowercase()));
+    let response = match (request.method.as_str(), request.path.split('?').next().unwrap_or_default()) {
+        _ if !known_host => respond("403 Forbidden", "text/plain", "Open the dashboard at its own address"),
+        ("GET", "/") => respond("200 OK", "text/html; charset=utf-8", &page(&runnable, &token)),
+        ("POST", "/run") => run(&request, &runnable, &token, &calls).await,
+        _ => respond("404 Not Found", "text/plain", "Not found"),
+    };
+    stream.write_all(response.as_bytes()).await.ok();
+}
+
+// a Run button: the block runs on the daemon, then the browser goes back to the page
+async fn run(request: &HttpRequest, runnable: &[String], token: &str, calls: &mpsc::Sender<Call>) -> String {
+    let form = reqwest::Url::parse(&format!("http://localhost/?{}", request.body)).ok();
+    let field = |name: &str| form.as_ref()
+        .and_then(|url| url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned()))
+        .unwrap_or_default();
+    // another site's page could post the form too; browsers say where a post came from, and only
+    // the page (served for a known Host, see `serve`) has the token
+    let same_origin = match (&request.origin, &request.host) {
+        (Some(origin), Some(host)) => origin.split_once("://").map(|(_, h)| h) == Some(host.as_str()),
+        _ => false,
+    };
+    if !same_origin || field("token") != token {
+        return respond("403 Forbidden", "text/plain", "Runs can only be started from the dashboard");
+    }
+    let name = field("block");
+    if !runnable.contains(&name) {
+        return respond("400 Bad Request", "text/plain", "Only sync blocks can be run from here");
+    }
+    log::info!("[DASHBOARD] Running '{}'", name);
+    // the outcome reaches the page through `record`
+    if let Err(e) = local_api::ask(calls, Request::RunSyncBlock { name: name.clone() }).await {
+        log::warn!("[DASHBOARD] Running '{}' failed: {:#}", name, e);
+    }
+    "HTTP/1.1 303 See Other\r\nLocation: /\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+}
+
+fn respond(status: &str, content_type: &str, body: &str) -> String {
+    format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}", status, content_type, body.len(), body)
+}
+
+fn page(runnable: &[String], token: &str) -> String {
+    let blocks = BLOCKS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let time = |at: &Option<DateTime<Local>>| at.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_default();
+    let mut rows = String::new();
+    for (block, state) in &blocks {
+        let button = match runnable.contains(block) {
+            true => format!(
+                r#"<form method="post" action="/run"><input type="hidden" name="token" value="{}"><input type="hidden" name="block" value="{}"><button>Run</button></form>"#,
+                token, html(block),
+            ),
+            false => String::new(),
+        };
+        let error = state.error.as_ref().map(|(at, e)| format!("{}: {}", at.format("%Y-%m-%d %H:%M:%S"), e)).unwrap_or_default();
+        rows.push_str(&format!(
+            r#"<tr class="{}"><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+            state.status.replace(' ', "-"), html(block), state.status, html(state.value.as_deref().unwrap_or_default()),
+            time(&state.last_success), time(&state.last_run), html(&error), button,
+        ));
+    }
+    format!(r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><meta http-equiv="refresh" content="{}"><title>qb_sync</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #ccc; padding: 4px 8px; text-align: left; vertical-align: top; }}
+tr.error td:nth-child(2) {{ color: #b00; font-weight: bold; }}
+tr.ok td:nth-child(2) {{ color: #070; }}
+</style></head>
+<body><h1>qb_sync</h1><p>As of {}</p>
+<table><tr><th>Block</th><th>Status</th><th>Value</th><th>Last success</th><th>Last run</th><th>Last error</th><th></th></tr>
+{}</table></body></html>
+"#, REFRESH_SECS, Local::now().format("%Y-%m-%d %H:%M:%S"), rows)
+}
+
+fn html(text: &str) -> String {
+    htmlescape::encode_minimal(text)
+}
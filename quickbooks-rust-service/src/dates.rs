@@ -0,0 +1,177 @@
+// Relative date expressions for date_from / date_to / modified_from / modified_to
+//
+// A value is either a literal YYYY-MM-DD or an anchor optionally followed by an offset:
+//   today, yesterday, tomorrow
+//   start-of-week / end-of-week (weeks start on Monday), start-of-last-week, end-of-last-week
+//   start-of-month, end-of-month, start-of-last-month, end-of-last-month
+//   start-of-quarter, end-of-quarter, start-of-last-quarter, end-of-last-quarter
+//   start-of-year, end-of-year, start-of-last-year, end-of-last-year
+//   start-of-fiscal-year, end-of-fiscal-year, start-of-last-fiscal-year, end-of-last-fiscal-year
+//     (the company file's first fiscal month, read when the session opens; January until then)
+// Offsets are +N or -N with a unit of d, w, m or y, e.g. "-7d", "start-of-month+14d",
+// "end-of-last-month-1y". An offset on its own counts from today.
+// Expressions are evaluated at the start of each run, in [quickbooks] timezone when set.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{Datelike, Days, Months, NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::amounts;
+use crate::config::Config;
+
+// first month of the company's fiscal year, 1 = January
+static FISCAL_YEAR_START: AtomicU32 = AtomicU32::new(1);
+
+/// Sets the month the *-fiscal-year anchors count from (from CompanyQuery's FirstMonthFiscalYear)
+pub fn set_fiscal_year_start(month: u32) {
+    if (1..=12).contains(&month) {
+        FISCAL_YEAR_START.store(month, Ordering::Relaxed);
+    }
+}
+
+/// A date and time as a spreadsheet serial number: days since 1899-12-30, the time of day as the
+/// fraction. Sheets and Excel show it as a date once the cell has a date format.
+pub fn sheets_serial(at: NaiveDateTime) -> Decimal {
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30).unwrap_or_default().and_hms_opt(0, 0, 0).unwrap_or_default();
+    // ten decimals are finer than a millisecond and still fit a double
+    amounts::round(Decimal::from((at - epoch).num_milliseconds()) / Decimal::from(86_400_000), 10)
+}
+
+/// Today's date in the configured timezone (the machine's local timezone when not set)
+pub fn today(config: &Config) -> Result<NaiveDate> {
+    Ok(now(config)?.date())
+}
+
+/// The wall-clock time in the configured timezone
+pub fn now(config: &Config) -> Result<NaiveDateTime> {
+    wall_clock(config, chrono::Utc::now())
+}
+
+/// `at` as wall-clock time in the configured timezone
+pub fn wall_clock<Tz: chrono::TimeZone>(config: &Config, at: chrono::DateTime<Tz>) -> Result<NaiveDateTime> {
+    match config.quickbooks.timezone.as_deref() {
+        Some(name) => {
+            let tz: chrono_tz::Tz = name.parse().map_err(|e| anyhow!("Invalid [quickbooks] timezone '{}': {}", name, e))?;
+            Ok(at.with_timezone(&tz).naive_local())
+        }
+        None => Ok(at.with_timezone(&chrono::Local).naive_local()),
+    }
+}
+
+/// Resolves a literal date or a relative expression to a date
+pub fn resolve(expr: &str, today: NaiveDate) -> Result<NaiveDate> {
+    let expr = expr.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(expr, "%Y-%m-%d") {
+        return Ok(date);
+    }
+    let lower = expr.to_ascii_lowercase();
+    resolve_relative(&lower, today).with_context(|| {
+        format!("Invalid date '{}', expected YYYY-MM-DD or an expression such as today, start-of-month or -7d", expr)
+    })
+}
+
+/// qbXML form (YYYY-MM-DD) of a date or relative expression
+pub fn resolve_qbxml_date(expr: &str, today: NaiveDate) -> Result<String> {
+    Ok(resolve(expr, today)?.format("%Y-%m-%d").to_string())
+}
+
+/// Like resolve_qbxml_date but for modified-date filters: explicit date-times pass through,
+/// dates and expressions mean midnight
+pub fn resolve_qbxml_datetime(expr: &str, today: NaiveDate) -> Result<String> {
+    if let Ok(at) = chrono::NaiveDateTime::parse_from_str(expr.trim(), "%Y-%m-%dT%H:%M:%S") {
+        return Ok(at.format("%Y-%m-%dT%H:%M:%S").to_string());
+    }
+    Ok(format!("{}T00:00:00", resolve(expr, today)?.format("%Y-%m-%d")))
+}
+
+fn resolve_relative(expr: &str, today: NaiveDate) -> Result<NaiveDate> {
+    // anchors contain '-' themselves, so take the longest one the expression starts with
+    let (base, rest) = match ANCHORS.iter().filter(|a| expr.starts_with(*a)).max_by_key(|a| a.len()) {
+        Some(anchor) => (anchor_date(anchor, today)?, &expr[anchor.len()..]),
+        None => (today, expr),
+    };
+    if rest.is_empty() {
+        return Ok(base);
+    }
+    apply_offset(base, rest)
+}
+
+const ANCHORS: &[&str] = &[
+    "today", "yesterday", "tomorrow",
+    "start-of-week", "end-of-week", "start-of-last-week", "end-of-last-week",
+    "start-of-month", "end-of-month", "start-of-last-month", "end-of-last-month",
+    "start-of-quarter", "end-of-quarter", "start-of-last-quarter", "end-of-last-quarter",
+    "start-of-year", "end-of-year", "start-of-last-year", "end-of-last-year",
+    "start-of-fiscal-year", "end-of-fiscal-year", "start-of-last-fiscal-year", "end-of-last-fiscal-year",
+];
+
+fn anchor_date(anchor: &str, today: NaiveDate) -> Result<NaiveDate> {
+    let week_start = today - Days::new(today.weekday().num_days_from_monday() as u64);
+    let month_start = today.with_day(1).unwrap();
+    let quarter_start = NaiveDate::from_ymd_opt(today.year(), (today.month0() / 3) * 3 + 1, 1).unwrap();
+    let year_start = NaiveDate::from_ymd_opt(today.year(), 1, 1).unwrap();
+    // the fiscal year that contains today starts this year or last
+    let fiscal_month = FISCAL_YEAR_START.load(Ordering::Relaxed);
+    let fiscal_start = NaiveDate::from_ymd_opt(today.year(), fiscal_month, 1).unwrap();
+    let fiscal_start = if fiscal_start > today { fiscal_start.with_year(today.year() - 1).unwrap() } else { fiscal_start };
+    let date = match anchor {
+        "today" => Some(today),
+        "yesterday" => today.checked_sub_days(Days::new(1)),
+        "tomorrow" => today.checked_add_days(Days::new(1)),
+        "start-of-week" => Some(week_start),
+        "end-of-week" => week_start.checked_add_days(Days::new(6)),
+        "start-of-last-week" => week_start.checked_sub_days(Days::new(7)),
+        "end-of-last-week" => week_start.checked_sub_days(Days::new(1)),
+        "start-of-month" => Some(month_start),
+        "end-of-month" => end_of(month_start, 1),
+        "start-of-last-month" => month_start.checked_sub_months(Months::new(1)),
+        "end-of-last-month" => month_start.checked_sub_days(Days::new(1)),
+        "start-of-quarter" => Some(quarter_start),
+        "end-of-quarter" => end_of(quarter_start, 3),
+        "start-of-last-quarter" => quarter_start.checked_sub_months(Months::new(3)),
+        "end-of-last-quarter" => quarter_start.checked_sub_days(Days::new(1)),
+        "start-of-year" => Some(year_start),
+        "end-of-year" => end_of(year_start, 12),
+        "start-of-last-year" => year_start.checked_sub_months(Months::new(12)),
+        "end-of-last-year" => year_start.checked_sub_days(Days::new(1)),
+        "start-of-fiscal-year" => Some(fiscal_start),
+        "end-of-fiscal-year" => end_of(fiscal_start, 12),
+        "start-of-last-fiscal-year" => fiscal_start.checked_sub_months(Months::new(12)),
+        "end-of-last-fiscal-year" => fiscal_start.checked_sub_days(Days::new(1)),
+        _ => bail!("Unknown date anchor '{}'", anchor),
+    };
+    date.ok_or_else(|| anyhow!("'{}' is out of range", anchor))
+}
+
+// last day of the period of `months` months starting at `start`
+fn end_of(start: NaiveDate, months: u32) -> Option<NaiveDate> {
+    start.checked_add_months(Months::new(months))?.checked_sub_days(Days::new(1))
+}
+
+fn apply_offset(base: NaiveDate, offset: &str) -> Result<NaiveDate> {
+    let (negative, rest) = if let Some(rest) = offset.strip_prefix('+') {
+        (false, rest)
+    } else if let Some(rest) = offset.strip_prefix('-') {
+        (true, rest)
+    } else {
+        bail!("Expected +N or -N followed by d, w, m or y, got '{}'", offset);
+    };
+    let unit_at = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    let (count, unit) = rest.split_at(unit_at);
+    let count: u32 = count.parse().map_err(|_| anyhow!("Missing number in offset '{}'", offset))?;
+    let out_of_range = || anyhow!("Offset '{}' is out of range", offset);
+    let date = match (unit, negative) {
+        ("d", false) => base.checked_add_days(Days::new(count as u64)),
+        ("d", true) => base.checked_sub_days(Days::new(count as u64)),
+        ("w", false) => base.checked_add_days(Days::new(count as u64 * 7)),
+        ("w", true) => base.checked_sub_days(Days::new(count as u64 * 7)),
+        // month arithmetic clamps to the end of shorter months (Jan 31 + 1m = Feb 28/29)
+        ("m", false) => base.checked_add_months(Months::new(count)),
+        ("m", true) => base.checked_sub_months(Months::new(count)),
+        ("y", false) => base.checked_add_months(Months::new(count.checked_mul(12).ok_or_else(out_of_range)?)),
+        ("y", true) => base.checked_sub_months(Months::new(count.checked_mul(12).ok_or_else(out_of_range)?)),
+        _ => bail!("Unknown offset unit in '{}', expected d, w, m or y", offset),
+    };
+    date.ok_or_else(out_of_range)
+}
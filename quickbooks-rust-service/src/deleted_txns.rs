@@ -0,0 +1,93 @@
+// [deleted_transactions]: an audit feed of transactions deleted in QuickBooks
+//
+// TxnDeletedQueryRq lists deleted transactions by type, with when each was created and deleted.
+// Each cycle asks for the ones deleted since the last successful run (from the history store; the
+// `since` date on the first run) and appends one row per transaction to the audit tab, oldest
+// first. Voided transactions aren't deleted: QuickBooks keeps them with a zero amount and the SDK
+// has no query for voids, so they don't show up here.
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::a1;
+use crate::qbxml;
+
+// how far back the first run looks
+pub const DEFAULT_SINCE: &str = "start-of-month";
+
+pub const HEADERS: [&str; 5] = ["Deleted", "Type", "Ref Number", "Txn ID", "Created"];
+
+// TxnDelType: every transaction type TxnDeletedQuery can report
+pub const TXN_DEL_TYPES: &[&str] = &[
+    "ARRefundCreditCard", "Bill", "BillPaymentCheck", "BillPaymentCreditCard", "BuildAssembly", "Charge",
+    "Check", "CreditCardCharge", "CreditCardCredit", "CreditMemo", "Deposit", "Estimate",
+    "InventoryAdjustment", "Invoice", "ItemReceipt", "JournalEntry", "PayrollLiabilityAdjustment",
+    "PayrollPriorPayment", "PayrollYearToDateAdjustment", "PurchaseOrder", "ReceivePayment", "SalesOrder",
+    "SalesReceipt", "SalesTaxPaymentCheck", "TimeTracking", "TransferInventory", "VehicleMileage", "VendorCredit",
+];
+
+/// The TxnDelType spelling of `name`, matched without regard to case
+pub fn txn_del_type(name: &str) -> Result<&'static str> {
+    match TXN_DEL_TYPES.iter().find(|t| t.eq_ignore_ascii_case(name.trim())) {
+        Some(t) => Ok(t),
+        None => bail!("Unknown transaction type '{}'; expected one of {}", name, TXN_DEL_TYPES.join(", ")),
+    }
+}
+
+/// The query for `types` (every type when empty) deleted at or after `from` (YYYY-MM-DDTHH:MM:SS)
+pub fn request(types: &[String], from: &str) -> Result<String> {
+    let types: Vec<&str> = if types.is_empty() {
+        TXN_DEL_TYPES.to_vec()
+    } else {
+        types.iter().map(|t| txn_del_type(t)).collect::<Result<_>>()?
+    };
+    let mut body = String::from("      <TxnDeletedQueryRq>\n");
+    for t in types {
+        body.push_str(&format!("        <TxnDelType>{}</TxnDelType>\n", t));
+    }
+    body.push_str(&format!(
+        "        <DeletedDateRangeFilter><FromDeletedDate>{}</FromDeletedDate></DeletedDateRangeFilter>\n",
+        from
+    ));
+    body.push_str("      </TxnDeletedQueryRq>");
+    Ok(qbxml::envelope(&body))
+}
+
+/// One row per TxnDeletedRet (Deleted, Type, Ref Number, Txn ID, Created), in the order they were
+/// deleted
+pub fn parse(response_xml: &str) -> Result<Vec<Vec<Value>>> {
+    let doc = qbxml::parse_document(response_xml)?;
+    let rs = qbxml::find_response(&doc, "TxnDeletedQueryRs")?;
+    let mut rows: Vec<(String, Vec<Value>)> = rs.children()
+        .filter(|n| n.has_tag_name("TxnDeletedRet"))
+        .map(|ret| {
+            let text = |name: &str| qbxml::child_text(ret, name).unwrap_or_default();
+            let deleted = text("TimeDeleted");
+            let row = vec![
+                Value::from(sheet_time(&deleted)),
+                Value::from(text("TxnDelType")),
+                Value::from(text("RefNumber")),
+                Value::from(text("TxnID")),
+                Value::from(sheet_time(&text("TimeCreated"))),
+            ];
+            (deleted, row)
+        })
+        .collect();
+    // QuickBooks groups them by type; the audit tab reads better in time order
+    rows.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(rows.into_iter().map(|(_, row)| row).collect())
+}
+
+/// Column letter -> title, for a tab created by create_sheet_if_missing
+pub fn sheet_headers() -> BTreeMap<String, String> {
+    HEADERS.iter().enumerate().map(|(i, h)| (a1::column_name(i as u32 + 1), h.to_string())).collect()
+}
+
+// "2024-05-02T10:15:00-07:00" => "2024-05-02 10:15:00", QuickBooks' own local time
+fn sheet_time(qbxml_time: &str) -> String {
+    match chrono::DateTime::parse_from_rfc3339(qbxml_time) {
+        Ok(at) => at.format("%Y-%m-%d %H:%M:%S").to_string(),
+        Err(_) => qbxml_time.to_string(),
+    }
+}
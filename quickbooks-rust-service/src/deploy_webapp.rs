@@ -0,0 +1,244 @@
+// `qb_sync deploy-webapp`: pushes the companion Apps Script (Google_Sheet_Function, built into the
+// binary) through the Apps Script API and writes the web app URL into the config file
+//
+// Signs in with a Google OAuth client of the "Desktop app" kind: the browser opens Google's consent
+// page and the answer comes back to a one-off listener on 127.0.0.1. Service accounts can't be used,
+// since the Apps Script API refuses them. The first run creates a project and a deployment; later
+// runs update both, so the URL already in config.toml keeps working. Google doesn't let a
+// deployment authorize itself, so a new project still has to be opened once in the editor to run
+// setupPermissions() and setupQuickBooksIntegration().
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use toml_edit::{value, DocumentMut, Item, Table};
+
+const SCRIPT_SOURCE: &str = include_str!("../../Google_Sheet_Function/src/Code.ts");
+const MANIFEST: &str = include_str!("../../Google_Sheet_Function/src/appsscript.json");
+const API_URL: &str = "https://script.googleapis.com/v1/projects";
+const SCOPES: &str = "https://www.googleapis.com/auth/script.projects https://www.googleapis.com/auth/script.deployments";
+// how long the browser sign-in may take
+const SIGN_IN_TIMEOUT: Duration = Duration::from_secs(300);
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+// the client file downloaded from the Cloud console: {"installed": {...}}
+#[derive(Deserialize)]
+struct ClientFile {
+    installed: Option<OAuthClient>,
+    web: Option<OAuthClient>,
+}
+
+#[derive(Deserialize)]
+struct OAuthClient {
+    client_id: String,
+    client_secret: String,
+    auth_uri: String,
+    token_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// The deployed web app
+#[derive(Debug)]
+pub struct Deployed {
+    pub script_id: String,
+    pub url: String,
+    // true when the project was created by this run and still needs its first authorization
+    pub created: bool,
+}
+
+/// Signs in with the OAuth client in `credentials` and returns an access token for the Apps Script API
+pub async fn sign_in(http: &reqwest::Client, credentials: &Path) -> Result<String> {
+    let text = std::fs::read_to_string(credentials).with_context(|| format!("Failed to read {}", credentials.display()))?;
+    let file: ClientFile = serde_json::from_str(&text).with_context(|| format!("{} is not an OAuth client file", credentials.display()))?;
+    let client = file.installed.or(file.web)
+        .with_context(|| format!("{} has no \"installed\" client; create an OAuth client of type Desktop app", credentials.display()))?;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.context("Failed to listen for the sign-in redirect")?;
+    let redirect_uri = format!("http://127.0.0.1:{}", listener.local_addr()?.port());
+    let state = uuid::Uuid::new_v4().simple().to_string();
+    // PKCE: the code is only worth anything together with this verifier
+    let verifier = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    let challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+    let url = reqwest::Url::parse_with_params(&client.auth_uri, [
+        ("client_id", client.client_id.as_str()),
+        ("redirect_uri", redirect_uri.as_str()),
+        ("response_type", "code"),
+        ("scope", SCOPES),
+        ("state", state.as_str()),
+        ("code_challenge", challenge.as_str()),
+        ("code_challenge_method", "S256"),
+    ]).context("Invalid auth_uri in the OAuth client file")?;
+    println!("Sign in to Google in the browser to let qb_sync manage the Apps Script project:\n\n  {}\n", url);
+    open_browser(url.as_str());
+
+    let code = tokio::time::timeout(SIGN_IN_TIMEOUT, receive_code(&listener, &state)).await
+        .map_err(|_| anyhow::anyhow!("No sign-in within {} minutes", SIGN_IN_TIMEOUT.as_secs() / 60))??;
+    let res = http.post(&client.token_uri)
+        .timeout(TIMEOUT)
+        .form(&[
+            ("code", code.as_str()),
+            ("client_id", client.client_id.as_str()),
+            ("client_secret", client.client_secret.as_str()),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("grant_type", "authorization_code"),
+            ("code_verifier", verifier.as_str()),
+        ])
+        .send()
+        .await
+        .context("Failed to reach Google's token endpoint")?;
+    let status = res.status();
+    if !status.is_success() {
+        bail!("Google refused the sign-in: {} - {}", status, res.text().await.unwrap_or_default());
+    }
+    let token: TokenResponse = res.json().await.context("Unexpected token response from Google")?;
+    Ok(token.access_token)
+}
+
+// the browser is sent back to http://127.0.0.1:port/?code=...&state=...
+async fn receive_code(listener: &tokio::net::TcpListener, state: &str) -> Result<String> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let mut buf = vec![0u8; 8192];
+        let n = stream.read(&mut buf).await?;
+        let request = String::from_utf8_lossy(&buf[..n]);
+        let Some(target) = request.lines().next().and_then(|line| line.split_whitespace().nth(1)) else { continue };
+        let url = reqwest::Url::parse(&format!("http://127.0.0.1{}", target))?;
+        let param = |name: &str| url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned());
+        let (code, error) = (param("code"), param("error"));
+        // browsers also ask for /favicon.ico
+        if code.is_none() && error.is_none() {
+            stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n").await.ok();
+            continue;
+        }
+        let result = match (code, error) {
+            (_, Some(error)) => Err(anyhow::anyhow!("Sign-in failed: {}", error)),
+            _ if param("state").as_deref() != Some(state) => Err(anyhow::anyhow!("Sign-in answer doesn't match the request (state mismatch)")),
+            (code, None) => Ok(code.unwrap_or_default()),
+        };
+        let page = match &result {
+            Ok(_) => "Signed in. You can close this tab and go back to qb_sync.",
+            Err(_) => "Sign-in failed; qb_sync shows why.",
+        };
+        let body = format!("<html><body><p>{}</p></body></html>", page);
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+        stream.write_all(response.as_bytes()).await.ok();
+        return result;
+    }
+}
+
+fn open_browser(url: &str) {
+    #[cfg(windows)]
+    let _ = std::process::Command::new("rundll32").args(["url.dll,FileProtocolHandler", url]).spawn();
+    #[cfg(not(windows))]
+    let _ = url;
+}
+
+/// Creates the project (when `script_id` is None) or replaces its code, then points the deployment
+/// behind `webapp_url` at the new version, or creates one when there is none yet
+pub async fn deploy(http: &reqwest::Client, token: &str, script_id: Option<&str>, webapp_url: Option<&str>, title: &str) -> Result<Deployed> {
+    let (script_id, created) = match script_id {
+        Some(id) => (id.to_string(), false),
+        None => {
+            let project = call(http.post(API_URL).json(&json!({ "title": title })), token).await.context("Failed to create the Apps Script project")?;
+            let id = project["scriptId"].as_str().context("Apps Script API returned no scriptId")?.to_string();
+            println!("Created Apps Script project {}", id);
+            (id, true)
+        }
+    };
+    let project_url = format!("{}/{}", API_URL, script_id);
+    call(http.put(format!("{}/content", project_url)).json(&content()?), token).await.context("Failed to upload the script")?;
+
+    let description = format!("qb_sync {} deploy-webapp", env!("CARGO_PKG_VERSION"));
+    let version = call(http.post(format!("{}/versions", project_url)).json(&json!({ "description": description })), token).await
+        .context("Failed to create a script version")?;
+    let version_number = version["versionNumber"].as_u64().context("Apps Script API returned no versionNumber")?;
+    let config = json!({ "scriptId": script_id, "versionNumber": version_number, "manifestFileName": "appsscript", "description": description });
+
+    let deployment = match webapp_url.and_then(deployment_id) {
+        Some(id) if !created => call(http.put(format!("{}/deployments/{}", project_url, id)).json(&json!({ "deploymentConfig": config })), token).await
+            .with_context(|| format!("Failed to update deployment {}; is webapp_url from this project?", id))?,
+        _ => call(http.post(format!("{}/deployments", project_url)).json(&config), token).await.context("Failed to create the deployment")?,
+    };
+    let url = deployment["entryPoints"].as_array()
+        .and_then(|points| points.iter().find_map(|p| p["webApp"]["url"].as_str()))
+        .context("The deployment has no web app entry point")?
+        .to_string();
+    Ok(Deployed { script_id, url, created })
+}
+
+/// What `deploy` would do, for --dry-run: nothing is signed in to, created or uploaded
+pub fn plan(script_id: Option<&str>, webapp_url: Option<&str>, title: &str) -> Result<String> {
+    let mut lines = Vec::new();
+    match script_id {
+        Some(id) => lines.push(format!("Project: update Apps Script project {}", id)),
+        None => lines.push(format!("Project: create a new Apps Script project \"{}\"", title)),
+    }
+    let content = content()?;
+    for file in content["files"].as_array().into_iter().flatten() {
+        lines.push(format!("Content: {} ({}, {} bytes)",
+            file["name"].as_str().unwrap_or_default(),
+            file["type"].as_str().unwrap_or_default(),
+            file["source"].as_str().map_or(0, str::len)));
+    }
+    lines.push(format!("Version: a new version described as \"qb_sync {} deploy-webapp\"", env!("CARGO_PKG_VERSION")));
+    match webapp_url.and_then(deployment_id) {
+        Some(id) if script_id.is_some() => lines.push(format!("Deployment: point deployment {} at the new version", id)),
+        _ => lines.push("Deployment: create a new web app deployment, then write its URL and the script id into the config".to_string()),
+    }
+    Ok(lines.join("\n"))
+}
+
+// Code plus the manifest, set up as a web app anyone can post to; requests are checked against the API key
+fn content() -> Result<Value> {
+    let mut manifest: Value = serde_json::from_str(MANIFEST).context("appsscript.json is not valid JSON")?;
+    manifest["webapp"] = json!({ "executeAs": "USER_DEPLOYING", "access": "ANYONE_ANONYMOUS" });
+    Ok(json!({ "files": [
+        { "name": "Code", "type": "SERVER_JS", "source": SCRIPT_SOURCE },
+        { "name": "appsscript", "type": "JSON", "source": serde_json::to_string_pretty(&manifest)? },
+    ]}))
+}
+
+async fn call(request: reqwest::RequestBuilder, token: &str) -> Result<Value> {
+    let res = request.bearer_auth(token).timeout(TIMEOUT).send().await.context("Failed to reach the Apps Script API")?;
+    let status = res.status();
+    let text = res.text().await.unwrap_or_default();
+    if !status.is_success() {
+        // a 403 here is usually the per-user switch at https://script.google.com/home/usersettings
+        bail!("Apps Script API returned {} - {}", status, text);
+    }
+    serde_json::from_str(&text).context("Unexpected response from the Apps Script API")
+}
+
+// https://script.google.com/macros/s/<deployment id>/exec => the deployment id
+fn deployment_id(webapp_url: &str) -> Option<&str> {
+    let rest = webapp_url.trim().split("/macros/s/").nth(1)?;
+    let id = rest.split('/').next()?;
+    (!id.is_empty()).then_some(id)
+}
+
+/// [google_sheets] script_id and webapp_url as written in the config text
+pub fn current(text: &str) -> Result<(Option<String>, Option<String>)> {
+    let doc: DocumentMut = text.parse().context("Failed to parse the config file")?;
+    let get = |key: &str| doc.get("google_sheets").and_then(|t| t.get(key)).and_then(|v| v.as_str()).map(str::to_string);
+    Ok((get("script_id"), get("webapp_url")))
+}
+
+/// The config text with [google_sheets] script_id and webapp_url set, everything else untouched
+pub fn update_config(text: &str, deployed: &Deployed) -> Result<String> {
+    let mut doc: DocumentMut = text.parse().context("Failed to parse the config file")?;
+    let table = doc.entry("google_sheets").or_insert_with(|| Item::Table(Table::new()))
+        .as_table_mut()
+        .context("google_sheets must be a [google_sheets] table")?;
+    table.insert("webapp_url", value(deployed.url.as_str()));
+    table.insert("script_id", value(deployed.script_id.as_str()));
+    Ok(doc.to_string())
+}
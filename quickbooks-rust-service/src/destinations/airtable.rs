@@ -0,0 +1,77 @@
+// Airtable destination: upserts one record per block into a table, keyed on key_field so each
+// account keeps a single row that is updated in place every run
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::capture;
+use crate::config::AirtableConfig;
+use crate::destinations::{Destination, SyncValue};
+use crate::template;
+
+const API_URL: &str = "https://api.airtable.com/v0";
+const TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_KEY_FIELD: &str = "Account";
+
+pub struct AirtableDestination {
+    http: reqwest::Client,
+    token: String,
+    base_id: String,
+    table: String,
+    key_field: String,
+    // field name -> value template, rendered like a webhook body
+    fields: Value,
+}
+
+impl AirtableDestination {
+    pub fn new(cfg: &AirtableConfig, http: reqwest::Client, base_id: String, table: String, key_field: Option<String>, fields: &HashMap<String, String>) -> Result<Self> {
+        let key_field = key_field.unwrap_or_else(|| DEFAULT_KEY_FIELD.to_string());
+        let fields = if fields.is_empty() {
+            json!({
+                "Account": "{account_name}",
+                "Balance": "{balance}",
+                "Value": "{value}",
+                "Updated": "{timestamp}",
+            })
+        } else {
+            Value::Object(fields.iter().map(|(k, v)| (k.clone(), Value::from(v.as_str()))).collect::<Map<_, _>>())
+        };
+        if fields.get(&key_field).is_none() {
+            bail!("Airtable destination for table '{}' upserts on '{}', which is not one of its fields", table, key_field);
+        }
+        Ok(Self { http, token: cfg.token.clone(), base_id, table, key_field, fields })
+    }
+}
+
+#[async_trait]
+impl Destination for AirtableDestination {
+    fn describe(&self) -> String {
+        format!("airtable {}/{}", self.base_id, self.table)
+    }
+
+    async fn write_value(&self, value: &SyncValue) -> Result<()> {
+        let fields = template::render_json(&self.fields, &value.template_vars())?;
+        // performUpsert updates the record whose key field matches, or creates it;
+        // typecast lets Airtable convert text into date / single-select fields
+        let body = json!({
+            "performUpsert": { "fieldsToMergeOn": [self.key_field] },
+            "records": [{ "fields": fields }],
+            "typecast": true,
+        });
+        let mut url = reqwest::Url::parse(API_URL)?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid Airtable URL"))?
+            .extend([self.base_id.as_str(), self.table.as_str()]);
+
+        let request = self.http.patch(url.clone()).timeout(TIMEOUT).bearer_auth(&self.token);
+        let (status, text) = capture::send("airtable", url.as_str(), &body, request).await
+            .with_context(|| format!("Failed to send request to {}", self.describe()))?;
+        if !status.is_success() {
+            bail!("{} returned {} - {}", self.describe(), status, text);
+        }
+        Ok(())
+    }
+}
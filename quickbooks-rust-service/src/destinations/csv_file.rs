@@ -0,0 +1,53 @@
+// Local CSV file destination: one "date, account, balance, value" row appended per run
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+
+use crate::destinations::{Destination, SyncValue};
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const HEADER: [&str; 4] = ["Date", "Account", "Balance", "Value"];
+
+pub struct CsvDestination {
+    path: PathBuf,
+    date_format: String,
+}
+
+impl CsvDestination {
+    pub fn new(path: PathBuf, date_format: Option<String>) -> Self {
+        let date_format = date_format.unwrap_or_else(|| DEFAULT_DATE_FORMAT.to_string());
+        Self { path, date_format }
+    }
+}
+
+#[async_trait]
+impl Destination for CsvDestination {
+    fn describe(&self) -> String {
+        format!("csv {}", self.path.display())
+    }
+
+    async fn write_value(&self, value: &SyncValue) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        }
+        let is_new = !self.path.exists();
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        let mut writer = csv::Writer::from_writer(file);
+        if is_new {
+            writer.write_record(HEADER)?;
+        }
+        let rendered = value.rendered.clone().unwrap_or_else(|| value.account.balance.to_string());
+        writer.write_record([
+            value.queried_at.format(&self.date_format).to_string(),
+            value.account.account_full_name.clone(),
+            value.account.balance.to_string(),
+            rendered,
+        ])?;
+        writer.flush().with_context(|| format!("Failed to write {}", self.path.display()))?;
+        Ok(())
+    }
+}
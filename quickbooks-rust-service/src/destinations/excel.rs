@@ -0,0 +1,33 @@
+// Excel Online destination: writes the value into a workbook cell on SharePoint / OneDrive
+// through Microsoft Graph (see graph.rs)
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::destinations::{Destination, SyncValue};
+use crate::graph::{GraphClient, Workbook};
+
+/// Overwrites a single worksheet cell with the value
+pub struct ExcelCellDestination {
+    client: GraphClient,
+    workbook: Workbook,
+    worksheet: String,
+    cell_address: String,
+}
+
+impl ExcelCellDestination {
+    pub fn new(client: GraphClient, workbook: Workbook, worksheet: String, cell_address: String) -> Self {
+        Self { client, workbook, worksheet, cell_address }
+    }
+}
+
+#[async_trait]
+impl Destination for ExcelCellDestination {
+    fn describe(&self) -> String {
+        format!("excel {}!{}", self.worksheet, self.cell_address)
+    }
+
+    async fn write_value(&self, value: &SyncValue) -> Result<()> {
+        self.client.write_cell(&self.workbook, &self.worksheet, &self.cell_address, value.cell_value()).await
+    }
+}
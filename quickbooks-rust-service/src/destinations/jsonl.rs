@@ -0,0 +1,198 @@
+// JSON Lines destination for data lake ingestion: one JSON object per synced record, with the run
+// id and entity type, appended to a local .jsonl file that starts afresh every day (or hour), or PUT
+// as an object of its own under an S3-compatible bucket URL. List and transaction blocks with
+// `jsonl` export their rows the same way, one object per row.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use serde_json::{json, Map, Value};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::amounts;
+use crate::capture;
+use crate::config::{JsonlExportConfig, JsonlRotation, S3Config};
+use crate::correlation;
+use crate::destinations::{Destination, SyncValue};
+use crate::google_sheets::named_range_name;
+use crate::s3;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+enum Target {
+    File { path: PathBuf, rotate: JsonlRotation },
+    // a bucket (and optionally a prefix) URL; without [s3] the PUTs go unsigned
+    Bucket { http: reqwest::Client, url: String, credentials: Option<S3Config> },
+}
+
+impl Target {
+    fn new(export: &JsonlExportConfig, http: reqwest::Client, credentials: Option<S3Config>) -> Result<Self> {
+        Ok(match (&export.path, &export.url) {
+            (Some(path), None) => Target::File { path: path.into(), rotate: export.rotate },
+            // objects go under <url>/<date>/, so a query string would end up in front of their names
+            (None, Some(url)) if url.contains('?') => anyhow::bail!("JSON Lines url can't have a query string"),
+            (None, Some(url)) => Target::Bucket { http, url: url.clone(), credentials },
+            _ => anyhow::bail!("JSON Lines export needs either path or url"),
+        })
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Target::File { path, .. } => format!("jsonl {}", path.display()),
+            // a URL can carry credentials (user:password@), so only the host goes in logs
+            Target::Bucket { url, .. } => {
+                let host = reqwest::Url::parse(url).ok()
+                    .and_then(|u| u.host_str().map(str::to_string))
+                    .unwrap_or_else(|| "<invalid url>".to_string());
+                format!("jsonl {}", host)
+            }
+        }
+    }
+
+    // `records` as lines, appended to the file for `at` or PUT as <url>/<date>/<name>.jsonl
+    async fn write(&self, records: &[Value], at: DateTime<Local>, name: &str) -> Result<()> {
+        let mut lines = Vec::new();
+        for record in records {
+            serde_json::to_writer(&mut lines, record)?;
+            lines.push(b'\n');
+        }
+        match self {
+            Target::File { path, rotate } => {
+                let path = rotated(path, *rotate, at);
+                if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(dir)
+                        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+                }
+                // one write per call, so records from blocks writing the same file don't interleave
+                let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&path)
+                    .with_context(|| format!("Failed to open {}", path.display()))?;
+                file.write_all(&lines).with_context(|| format!("Failed to write {}", path.display()))?;
+            }
+            Target::Bucket { http, url, credentials } => {
+                let object_url = format!("{}/{}/{}.jsonl", url.trim_end_matches('/'), at.format("%Y-%m-%d"), name);
+                let mut request = http.put(&object_url).timeout(TIMEOUT);
+                if let Some(credentials) = credentials {
+                    for (header, value) in s3::put_headers(credentials, &object_url, &lines, chrono::Utc::now())? {
+                        request = request.header(header, value);
+                    }
+                }
+                let (status, text) = capture::send_bytes("jsonl", &object_url, lines, request).await
+                    .with_context(|| format!("Failed to upload to {}", self.describe()))?;
+                if !status.is_success() {
+                    anyhow::bail!("{} returned {} - {}", self.describe(), status, text);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// the run id, or the time when there is none, naming the objects a run PUTs
+fn run_name(at: DateTime<Local>) -> String {
+    correlation::run_id().unwrap_or_else(|| at.format("%Y%m%dT%H%M%S%.3f").to_string())
+}
+
+pub struct JsonlDestination {
+    target: Target,
+    // the block's label and what it synced: "account", or "computed" for an expression block
+    block: String,
+    entity_type: &'static str,
+}
+
+impl JsonlDestination {
+    pub fn new(export: &JsonlExportConfig, http: reqwest::Client, credentials: Option<S3Config>, block: String, entity_type: &'static str) -> Result<Self> {
+        Ok(Self { target: Target::new(export, http, credentials)?, block, entity_type })
+    }
+
+    fn record(&self, value: &SyncValue) -> Value {
+        let account = &value.account;
+        json!({
+            "run_id": correlation::run_id(),
+            "entity_type": self.entity_type,
+            "block": self.block,
+            "account": account.account_full_name,
+            "account_number": account.number,
+            "account_type": account.account_type,
+            "currency": account.currency,
+            "balance": amounts::json(account.balance),
+            "value": value.cell_value(),
+            "queried_at": value.queried_at.to_rfc3339(),
+        })
+    }
+}
+
+// qb.jsonl is written as qb-2024-06-01.jsonl, or qb-2024-06-01-14.jsonl hourly
+fn rotated(path: &std::path::Path, rotate: JsonlRotation, at: DateTime<Local>) -> PathBuf {
+    let stamp = match rotate {
+        JsonlRotation::Never => return path.to_path_buf(),
+        JsonlRotation::Daily => at.format("%Y-%m-%d"),
+        JsonlRotation::Hourly => at.format("%Y-%m-%d-%H"),
+    };
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, stamp, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, stamp),
+    };
+    path.with_file_name(name)
+}
+
+#[async_trait]
+impl Destination for JsonlDestination {
+    fn describe(&self) -> String {
+        self.target.describe()
+    }
+
+    async fn write_value(&self, value: &SyncValue) -> Result<()> {
+        // an object per record: <url>/<date>/<run id>-<block>.jsonl
+        let name = format!("{}-{}", run_name(value.queried_at), named_range_name(&self.block));
+        self.target.write(&[self.record(value)], value.queried_at, &name).await
+    }
+}
+
+/// A list or transaction block's `jsonl` export: each batch of rows it writes also goes out as one
+/// object per row, its cells under `record` by header
+pub struct JsonlRows {
+    target: Target,
+    block: String,
+    // the QuickBooks element the rows are, e.g. "Customer" or "Check"
+    entity_type: &'static str,
+    // numbers the objects a bucket gets for one block run, a batch each
+    batches: AtomicUsize,
+}
+
+impl JsonlRows {
+    pub fn new(export: &JsonlExportConfig, http: reqwest::Client, credentials: Option<S3Config>, block: String, entity_type: &'static str) -> Result<Self> {
+        Ok(Self { target: Target::new(export, http, credentials)?, block, entity_type, batches: AtomicUsize::new(1) })
+    }
+
+    pub fn describe(&self) -> String {
+        self.target.describe()
+    }
+
+    /// Writes `rows` (without a header row), their cells named after `headers`
+    pub async fn write(&self, headers: &[&str], rows: &[Vec<Value>]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let at = Local::now();
+        let records: Vec<Value> = rows.iter()
+            .map(|row| {
+                let cells: Map<String, Value> = headers.iter().map(|h| h.to_string()).zip(row.iter().cloned()).collect();
+                json!({
+                    "run_id": correlation::run_id(),
+                    "entity_type": self.entity_type,
+                    "block": self.block,
+                    "record": cells,
+                    "queried_at": at.to_rfc3339(),
+                })
+            })
+            .collect();
+        // an object per batch: <url>/<date>/<run id>-<block>-<batch>.jsonl
+        let batch = self.batches.fetch_add(1, Ordering::SeqCst);
+        let name = format!("{}-{}-{}", run_name(at), named_range_name(&self.block), batch);
+        self.target.write(&records, at, &name).await
+    }
+}
@@ -0,0 +1,165 @@
+// Destinations a sync block's value can be written to
+// A block may fan out to several destinations; each write succeeds or fails on its own
+
+pub mod sheets;
+pub mod csv_file;
+pub mod airtable;
+pub mod excel;
+pub mod webhook;
+pub mod jsonl;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+
+use crate::accounts::AccountData;
+use crate::amounts;
+use crate::config::{AccountSyncConfig, Config, DestinationConfig, OnMissing};
+use crate::context::AppContext;
+use crate::graph;
+use crate::template::{self, TemplateValue, TemplateVars};
+
+/// Everything a destination may need to write one sync block result
+#[derive(Debug, Clone)]
+pub struct SyncValue {
+    pub account: AccountData,
+    // value_template output, when the block has one
+    pub rendered: Option<String>,
+    pub queried_at: DateTime<Local>,
+}
+
+impl SyncValue {
+    /// The value as it should appear in a sheet cell: the rendered template or the raw balance
+    pub fn cell_value(&self) -> serde_json::Value {
+        match &self.rendered {
+            Some(text) => serde_json::Value::from(text.as_str()),
+            None => amounts::json(self.account.balance),
+        }
+    }
+
+    /// Placeholders for webhook / Airtable templates: the value_template ones plus {value}
+    /// (rendered value or balance) and {timestamp} (RFC 3339)
+    pub fn template_vars(&self) -> TemplateVars {
+        let mut vars = template::account_vars(&self.account);
+        vars.insert("value", match &self.rendered {
+            Some(text) => TemplateValue::Text(text.clone()),
+            None => TemplateValue::Number(self.account.balance),
+        });
+        vars.insert("timestamp", TemplateValue::Text(self.queried_at.to_rfc3339()));
+        vars
+    }
+}
+
+/// What a sync block writes for `account`: its balance, sign-normalized when the block says so,
+/// rounded per its rounding policy and rendered through the block's value_template if it has one
+pub fn sync_value(block: &AccountSyncConfig, account: &AccountData, config: &Config) -> Result<SyncValue> {
+    let mut account = account.clone();
+    if block.normalizes_signs(config) {
+        account.balance = account.normalized_balance();
+    }
+    if let Some(rounding) = config.rounding(block.rounding) {
+        account.balance = rounding.apply(account.balance);
+    }
+    Ok(SyncValue { rendered: render(block, &account)?, account, queried_at: Local::now() })
+}
+
+/// A computed block's result, presented as an "account" named after the block so every destination
+/// and the {account_name} / {balance} placeholders work unchanged. Rounded like `sync_value`; the
+/// published result isn't
+pub fn computed_value(block: &AccountSyncConfig, result: rust_decimal::Decimal, config: &Config) -> Result<SyncValue> {
+    let balance = config.rounding(block.rounding).map_or(result, |rounding| rounding.apply(result));
+    let account = AccountData { account_full_name: block.label().to_string(), balance, ..Default::default() };
+    Ok(SyncValue { rendered: render(block, &account)?, account, queried_at: Local::now() })
+}
+
+/// What a sync block writes when its account (or category) isn't there, by on_missing; None when
+/// it writes nothing
+pub fn missing_value(block: &AccountSyncConfig, config: &Config) -> Result<Option<SyncValue>> {
+    let name = block.category.clone().unwrap_or_else(|| block.account_full_name.clone());
+    let account = AccountData { account_full_name: name, ..Default::default() };
+    let text = |text: String| SyncValue { account: account.clone(), rendered: Some(text), queried_at: Local::now() };
+    Ok(match block.on_missing()? {
+        OnMissing::Skip => None,
+        OnMissing::Zero => Some(sync_value(block, &account, config)?),
+        OnMissing::Clear => Some(text(String::new())),
+        OnMissing::Text(value) => Some(text(value)),
+    })
+}
+
+// value_template turns the balance into a labelled string, e.g. "{account_name}: {balance:.2}"
+fn render(block: &AccountSyncConfig, account: &AccountData) -> Result<Option<String>> {
+    match &block.value_template {
+        Some(value_template) => Ok(Some(template::render(value_template, &template::account_vars(account))?)),
+        None => Ok(None),
+    }
+}
+
+#[async_trait]
+pub trait Destination: Send + Sync {
+    /// Short human readable description used in logs and the run summary
+    fn describe(&self) -> String;
+
+    async fn write_value(&self, value: &SyncValue) -> Result<()>;
+}
+
+/// Builds every destination of a sync block: the legacy top-level spreadsheet/cell fields
+/// (when set) followed by the entries of `destinations`
+pub fn for_sync_block(block: &AccountSyncConfig, ctx: &AppContext<'_>) -> Result<Vec<Box<dyn Destination>>> {
+    let mut destinations: Vec<Box<dyn Destination>> = Vec::new();
+    for dest_cfg in block.all_destinations() {
+        destinations.push(build(&dest_cfg, block, ctx)?);
+    }
+    if destinations.is_empty() {
+        anyhow::bail!("Sync block for '{}' has no destination; set cell_address, cells, mode = \"append\" or destinations", block.label());
+    }
+    Ok(destinations)
+}
+
+fn build(dest_cfg: &DestinationConfig, block: &AccountSyncConfig, ctx: &AppContext<'_>) -> Result<Box<dyn Destination>> {
+    let config = ctx.config;
+    // a destination without its own spreadsheet_id / sheet_name inherits the block's
+    let spreadsheet_id = |id: &Option<String>| id.clone().unwrap_or_else(|| block.spreadsheet_id.clone());
+    let sheet_name = |name: &Option<String>| name.clone().unwrap_or_else(|| block.sheet_name.clone());
+    let create_sheet = block.create_sheet_if_missing.unwrap_or(false);
+    Ok(match dest_cfg {
+        DestinationConfig::Cell { spreadsheet_id: id, sheet_name: name, cell_address, headers } => {
+            Box::new(sheets::SheetsCellDestination::new(
+                ctx.sheets(spreadsheet_id(id)).with_headers(headers).creating_sheet(create_sheet, BTreeMap::new()), sheet_name(name), cell_address.clone(),
+            ).with_note(block.cell_note.unwrap_or(false)))
+        }
+        DestinationConfig::Append { spreadsheet_id: id, sheet_name: name, date_format, append_column, retention, headers } => {
+            Box::new(sheets::SheetsAppendDestination::new(
+                ctx.sheets(spreadsheet_id(id)).with_headers(headers).creating_sheet(create_sheet, BTreeMap::new()), sheet_name(name), date_format.clone(), append_column.clone(),
+            ).with_retention(retention.clone()))
+        }
+        DestinationConfig::Cells { spreadsheet_id: id, sheet_name: name, cells, date_format, headers } => {
+            Box::new(sheets::SheetsCellsDestination::new(
+                ctx.sheets(spreadsheet_id(id)).with_headers(headers).creating_sheet(create_sheet, BTreeMap::new()), sheet_name(name), cells.layout()?,
+                date_format.clone().or_else(|| block.date_format.clone()),
+            ))
+        }
+        DestinationConfig::Csv { path, date_format } => {
+            Box::new(csv_file::CsvDestination::new(path.into(), date_format.clone()))
+        }
+        DestinationConfig::Excel { drive_id, item_id, path, worksheet, cell_address } => {
+            let graph_cfg = config.microsoft_graph.as_ref()
+                .context("Excel destinations need a [microsoft_graph] section")?;
+            let workbook = graph::Workbook::new(drive_id.clone(), item_id.clone(), path.clone())?;
+            let client = graph::GraphClient::new(graph_cfg, ctx.http.clone());
+            Box::new(excel::ExcelCellDestination::new(client, workbook, sheet_name(worksheet), cell_address.clone()))
+        }
+        DestinationConfig::Airtable { base_id, table, key_field, fields } => {
+            let airtable_cfg = config.airtable.as_ref()
+                .context("Airtable destinations need an [airtable] section with a token")?;
+            Box::new(airtable::AirtableDestination::new(airtable_cfg, ctx.http.clone(), base_id.clone(), table.clone(), key_field.clone(), fields)?)
+        }
+        DestinationConfig::Jsonl(export) => {
+            let entity_type = if block.is_computed() { "computed" } else { "account" };
+            Box::new(jsonl::JsonlDestination::new(export, ctx.http.clone(), config.s3.clone(), block.label().to_string(), entity_type)?)
+        }
+        DestinationConfig::Webhook { url, headers, body } => {
+            Box::new(webhook::WebhookDestination::new(ctx.http.clone(), url.clone(), headers.clone(), body.clone()))
+        }
+    })
+}
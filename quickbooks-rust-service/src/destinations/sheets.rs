@@ -0,0 +1,223 @@
+// Google Sheets destinations, written through the Apps Script webapp
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{Days, NaiveDate, NaiveDateTime};
+use std::collections::HashMap;
+
+use crate::a1;
+use crate::config::{CellLayout, CellPart, RetentionConfig};
+use crate::correlation;
+use crate::destinations::{Destination, SyncValue};
+use crate::google_sheets::GoogleSheetsClient;
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// Overwrites a single cell with the value
+pub struct SheetsCellDestination {
+    client: GoogleSheetsClient,
+    sheet_name: String,
+    cell_address: String,
+    // cell_note
+    note: bool,
+}
+
+impl SheetsCellDestination {
+    pub fn new(client: GoogleSheetsClient, sheet_name: String, cell_address: String) -> Self {
+        Self { client, sheet_name, cell_address, note: false }
+    }
+
+    pub fn with_note(mut self, note: bool) -> Self {
+        self.note = note;
+        self
+    }
+}
+
+/// The cell_note text: when the value was queried, from which account and in which run
+pub fn provenance(value: &SyncValue) -> String {
+    let mut lines = vec![format!("From QuickBooks at {}", value.queried_at.format("%Y-%m-%d %H:%M:%S %:z"))];
+    let account = &value.account;
+    match account.list_id.is_empty() {
+        true => lines.push(format!("Block: {}", account.account_full_name)),
+        false => lines.push(format!("Account: {} (ListID {})", account.account_full_name, account.list_id)),
+    }
+    if let Some(run_id) = correlation::run_id() {
+        lines.push(format!("Run: {}", run_id));
+    }
+    lines.join("\n")
+}
+
+#[async_trait]
+impl Destination for SheetsCellDestination {
+    fn describe(&self) -> String {
+        format!("sheet {}!{}", self.sheet_name, self.cell_address)
+    }
+
+    async fn write_value(&self, value: &SyncValue) -> Result<()> {
+        if self.note {
+            return self.client.send_noted(value.account.balance, value.rendered.as_deref(), &provenance(value), &self.sheet_name, &self.cell_address).await;
+        }
+        match &value.rendered {
+            Some(text) => self.client.send_string(text, &self.sheet_name, &self.cell_address).await,
+            None => self.client.send_balance(value.account.balance, Some(&self.sheet_name), Some(&self.cell_address)).await,
+        }
+    }
+}
+
+/// Adds a `date, value` row below the last non-empty row of a history tab, or below the last
+/// filled cell of `append_column`
+pub struct SheetsAppendDestination {
+    client: GoogleSheetsClient,
+    sheet_name: String,
+    date_format: String,
+    append_column: Option<String>,
+    retention: Option<RetentionConfig>,
+}
+
+impl SheetsAppendDestination {
+    pub fn new(client: GoogleSheetsClient, sheet_name: String, date_format: Option<String>, append_column: Option<String>) -> Self {
+        let date_format = date_format.unwrap_or_else(|| DEFAULT_DATE_FORMAT.to_string());
+        Self { client, sheet_name, date_format, append_column, retention: None }
+    }
+
+    pub fn with_retention(mut self, retention: Option<RetentionConfig>) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    // Reads the tab back and, when rows have aged out, rewrites the date and value columns from the
+    // first dated row down without them
+    async fn apply_retention(&self, retention: &RetentionConfig, today: NaiveDate) -> Result<()> {
+        let column = self.append_column.as_deref().and_then(a1::column_number).unwrap_or(1);
+        let rows = self.client.read_sheet(&self.sheet_name).await?;
+        let Some((first, kept, dropped)) = retain(&rows, column as usize - 1, &self.date_format, retention, today) else {
+            return Ok(());
+        };
+        let anchor = a1::Cell { column, row: first as u32 + 1 };
+        if !self.client.confirm_destructive {
+            log::warn!(
+                "[SAFE] Retention would remove {} row(s) from {}, dated {} to {}; set [google_sheets] confirm_destructive = true or pass --yes to remove them",
+                dropped.len(), self.sheet_name, dropped[0], dropped[dropped.len() - 1],
+            );
+            return Ok(());
+        }
+        log::info!("[SHEETS] Retention on {}: keeping {} of {} rows from {}", self.sheet_name, kept.len(), rows.len() - first, anchor);
+        self.client.write_range(&kept, &self.sheet_name, &anchor.to_string(), true, None).await
+    }
+}
+
+// The index of the first dated row, the date / value pairs to keep from it down and the date cells
+// of the rows that go, or None when nothing has aged out
+fn retain(rows: &[Vec<String>], column: usize, date_format: &str, retention: &RetentionConfig, today: NaiveDate) -> Option<(usize, Vec<Vec<serde_json::Value>>, Vec<String>)> {
+    let dates: Vec<Option<NaiveDate>> = rows.iter().map(|row| row.get(column).and_then(|text| row_date(text, date_format))).collect();
+    let first = dates.iter().position(Option::is_some)?;
+    let last = dates.iter().rposition(Option::is_some)?;
+    let days_ago = |days: Option<u32>| days.and_then(|days| today.checked_sub_days(Days::new(days.into())));
+    let (keep_from, daily_before) = (days_ago(retention.keep_days), days_ago(retention.daily_after_days));
+    let mut last_of_day = HashMap::new();
+    for (i, date) in dates.iter().enumerate() {
+        if let Some(date) = date {
+            last_of_day.insert(*date, i);
+        }
+    }
+    let kept: Vec<usize> = (first..=last)
+        .filter(|&i| match dates[i] {
+            None => true,
+            Some(date) if keep_from.is_some_and(|from| date < from) => false,
+            Some(date) => daily_before.is_none_or(|before| date >= before) || last_of_day[&date] == i,
+        })
+        .collect();
+    if kept.len() == last - first + 1 {
+        return None;
+    }
+    let cell = |text: Option<&String>| {
+        let text = text.map_or("", String::as_str);
+        // numbers go back as numbers rather than text
+        serde_json::from_str::<serde_json::Number>(text).map_or_else(|_| serde_json::Value::from(text), serde_json::Value::Number)
+    };
+    let dropped = (first..=last).filter(|i| kept.binary_search(i).is_err()).map(|i| rows[i][column].clone()).collect();
+    Some((first, kept.into_iter().map(|i| vec![cell(rows[i].get(column)), cell(rows[i].get(column + 1))]).collect(), dropped))
+}
+
+// The day a history row was written, from its date cell as the sheet displays it
+fn row_date(text: &str, date_format: &str) -> Option<NaiveDate> {
+    let text = text.trim();
+    NaiveDateTime::parse_from_str(text, date_format).map(|at| at.date())
+        .or_else(|_| NaiveDate::parse_from_str(text, date_format))
+        .ok()
+        .or_else(|| NaiveDate::parse_from_str(text.split_whitespace().next()?, "%Y-%m-%d").ok())
+}
+
+#[async_trait]
+impl Destination for SheetsAppendDestination {
+    fn describe(&self) -> String {
+        match &self.append_column {
+            Some(column) => format!("append {}!{}", self.sheet_name, column),
+            None => format!("append {}", self.sheet_name),
+        }
+    }
+
+    async fn write_value(&self, value: &SyncValue) -> Result<()> {
+        // date + balance as a new row builds a time series on the history tab
+        let row = [
+            serde_json::Value::from(value.queried_at.format(&self.date_format).to_string()),
+            value.cell_value(),
+        ];
+        self.client.append_row(&row, &self.sheet_name, self.append_column.as_deref()).await?;
+        if let Some(retention) = &self.retention {
+            // the value is written; a tab that couldn't be trimmed this time is trimmed next run
+            if let Err(e) = self.apply_retention(retention, value.queried_at.date_naive()).await {
+                log::warn!("[SHEETS] Couldn't apply retention to {}: {:#}", self.sheet_name, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the value, account name and query time into adjacent cells with one range write, so the
+/// three never disagree on the sheet
+pub struct SheetsCellsDestination {
+    client: GoogleSheetsClient,
+    sheet_name: String,
+    layout: CellLayout,
+    date_format: String,
+}
+
+impl SheetsCellsDestination {
+    pub fn new(client: GoogleSheetsClient, sheet_name: String, layout: CellLayout, date_format: Option<String>) -> Self {
+        let date_format = date_format.unwrap_or_else(|| DEFAULT_DATE_FORMAT.to_string());
+        Self { client, sheet_name, layout, date_format }
+    }
+
+    fn last_cell(&self) -> a1::Cell {
+        let extra = self.layout.parts.len().saturating_sub(1) as u32;
+        let anchor = self.layout.anchor;
+        match self.layout.across {
+            true => a1::Cell { column: anchor.column + extra, row: anchor.row },
+            false => a1::Cell { column: anchor.column, row: anchor.row + extra },
+        }
+    }
+}
+
+#[async_trait]
+impl Destination for SheetsCellsDestination {
+    fn describe(&self) -> String {
+        match self.last_cell() {
+            last if last == self.layout.anchor => format!("sheet {}!{}", self.sheet_name, last),
+            last => format!("sheet {}!{}:{}", self.sheet_name, self.layout.anchor, last),
+        }
+    }
+
+    async fn write_value(&self, value: &SyncValue) -> Result<()> {
+        let values = self.layout.parts.iter().map(|part| match part {
+            CellPart::Value => value.cell_value(),
+            CellPart::Name => serde_json::Value::from(value.account.account_full_name.as_str()),
+            CellPart::QueriedAt => serde_json::Value::from(value.queried_at.format(&self.date_format).to_string()),
+        });
+        let rows: Vec<Vec<serde_json::Value>> = match self.layout.across {
+            true => vec![values.collect()],
+            false => values.map(|v| vec![v]).collect(),
+        };
+        self.client.write_range(&rows, &self.sheet_name, &self.layout.anchor.to_string(), false, None).await
+    }
+}
@@ -0,0 +1,61 @@
+// Generic webhook destination: POSTs a JSON body to any URL, so a block's value can feed
+// Zapier / n8n / Power Automate flows directly
+//
+// Every string in the body is a value template (SyncValue::template_vars); a string that is nothing
+// but one numeric placeholder, such as "{balance}", is sent as a JSON number.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::capture;
+use crate::destinations::{Destination, SyncValue};
+use crate::template;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct WebhookDestination {
+    http: reqwest::Client,
+    url: String,
+    headers: HashMap<String, String>,
+    body: Value,
+}
+
+impl WebhookDestination {
+    pub fn new(http: reqwest::Client, url: String, headers: HashMap<String, String>, body: Option<Value>) -> Self {
+        let body = body.unwrap_or_else(|| json!({
+            "account": "{account_name}",
+            "value": "{value}",
+            "balance": "{balance}",
+            "timestamp": "{timestamp}",
+        }));
+        Self { http, url, headers, body }
+    }
+}
+
+#[async_trait]
+impl Destination for WebhookDestination {
+    fn describe(&self) -> String {
+        // hook URLs often embed their secret in the path, so only the host goes in logs
+        let host = reqwest::Url::parse(&self.url).ok()
+            .and_then(|u| u.host_str().map(str::to_string))
+            .unwrap_or_else(|| "<invalid url>".to_string());
+        format!("webhook {}", host)
+    }
+
+    async fn write_value(&self, value: &SyncValue) -> Result<()> {
+        let body = template::render_json(&self.body, &value.template_vars())?;
+        let mut request = self.http.post(&self.url).timeout(TIMEOUT);
+        for (name, header_value) in &self.headers {
+            request = request.header(name.as_str(), header_value.as_str());
+        }
+        let (status, text) = capture::send("webhook", &self.url, &body, request).await
+            .with_context(|| format!("Failed to POST to {}", self.describe()))?;
+        if !status.is_success() {
+            anyhow::bail!("{} returned {} - {}", self.describe(), status, text);
+        }
+        Ok(())
+    }
+}
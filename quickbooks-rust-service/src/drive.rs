@@ -0,0 +1,231 @@
+// [google_drive]: copy the files CSV destinations write to a Google Drive folder after every cycle
+//
+// Authenticates as a Google Cloud service account (its JSON key file), so no user has to sign in;
+// share the folder with the service account's client_email as an editor. A service account has no
+// storage of its own, so the folder should be on a shared drive. Each file replaces the one with
+// the same name in the folder, keeping a single, current copy for whoever reads it there.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::capture;
+use crate::config::{DestinationConfig, GoogleDriveConfig};
+use crate::context::AppContext;
+
+const DRIVE_URL: &str = "https://www.googleapis.com/drive/v3/files";
+const UPLOAD_URL: &str = "https://www.googleapis.com/upload/drive/v3/files";
+const SCOPE: &str = "https://www.googleapis.com/auth/drive";
+const TIMEOUT: Duration = Duration::from_secs(120);
+// renew a token this long before Google says it expires
+const TOKEN_MARGIN: Duration = Duration::from_secs(120);
+
+// access tokens per service account, kept across daemon cycles
+static TOKENS: OnceLock<Mutex<HashMap<String, (String, Instant)>>> = OnceLock::new();
+
+// the fields of a service account key file we need
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+struct FileList {
+    files: Vec<DriveFile>,
+}
+
+#[derive(Deserialize)]
+struct DriveFile {
+    id: String,
+}
+
+/// Uploads the file of every CSV destination that exists; failures are only logged
+pub async fn upload_artifacts(ctx: &AppContext<'_>, drive: &GoogleDriveConfig) {
+    let config = ctx.config;
+    let paths: BTreeSet<String> = config.sync_blocks.iter()
+        .flat_map(|b| b.all_destinations())
+        .filter_map(|d| match d {
+            DestinationConfig::Csv { path, .. } => Some(path),
+            _ => None,
+        })
+        .collect();
+    if paths.is_empty() {
+        return;
+    }
+    if capture::replaying() {
+        log::info!("[DRIVE] Replaying a capture; leaving {} file(s) on this machine", paths.len());
+        return;
+    }
+    let client = match DriveClient::new(drive, ctx.http.clone()) {
+        Ok(client) => client,
+        Err(e) => {
+            log::warn!("[DRIVE] {:#}", e);
+            return;
+        }
+    };
+    for path in paths.iter().map(Path::new).filter(|p| p.exists()) {
+        match client.upload(path, &drive.folder_id).await {
+            Ok(()) => log::info!("[DRIVE] Uploaded {}", path.display()),
+            Err(e) => log::warn!("[DRIVE] Failed to upload {}: {:#}", path.display(), e),
+        }
+    }
+}
+
+struct DriveClient {
+    http: reqwest::Client,
+    key: ServiceAccountKey,
+}
+
+impl DriveClient {
+    fn new(cfg: &GoogleDriveConfig, http: reqwest::Client) -> Result<Self> {
+        let text = std::fs::read_to_string(&cfg.service_account_key)
+            .with_context(|| format!("Failed to read service account key {}", cfg.service_account_key))?;
+        let key = serde_json::from_str(&text)
+            .with_context(|| format!("{} is not a service account key file", cfg.service_account_key))?;
+        Ok(Self { http, key })
+    }
+
+
+    // OAuth for service accounts: a JWT signed with the key is exchanged for an access token
+    async fn token(&self) -> Result<String> {
+        let tokens = TOKENS.get_or_init(Default::default);
+        if let Some((token, expires)) = tokens.lock().unwrap().get(&self.key.client_email) {
+            if Instant::now() + TOKEN_MARGIN < *expires {
+                return Ok(token.clone());
+            }
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let claims = Claims { iss: &self.key.client_email, scope: SCOPE, aud: &self.key.token_uri, iat: now, exp: now + 3600 };
+        let signing_key = jsonwebtoken::EncodingKey::from_rsa_pem(self.key.private_key.as_bytes())
+            .context("Invalid private_key in the service account key file")?;
+        let assertion = jsonwebtoken::encode(&jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256), &claims, &signing_key)
+            .context("Failed to sign the service account token request")?;
+        let res = self.http
+            .post(&self.key.token_uri)
+            .timeout(TIMEOUT)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", assertion.as_str()),
+            ])
+            .send()
+            .await
+            .context("Failed to request a Google token")?;
+        let status = res.status();
+        if !status.is_success() {
+            let text = res.text().await.unwrap_or_default();
+            bail!("Google token request failed: {} - {}", status, text);
+        }
+        let token: TokenResponse = res.json().await.context("Unexpected Google token response")?;
+        let expires = Instant::now() + Duration::from_secs(token.expires_in);
+        tokens.lock().unwrap().insert(self.key.client_email.clone(), (token.access_token.clone(), expires));
+        Ok(token.access_token)
+    }
+
+    /// Replaces the folder's file of the same name with `path`, creating it the first time
+    async fn upload(&self, path: &Path, folder_id: &str) -> Result<()> {
+        let name = path.file_name().and_then(|n| n.to_str()).context("File has no name")?;
+        let content = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let token = self.token().await?;
+        let id = match self.find(&token, name, folder_id).await? {
+            Some(id) => id,
+            None => self.create(&token, name, folder_id).await?,
+        };
+
+        let res = self.http
+            .patch(format!("{}/{}", UPLOAD_URL, id))
+            .timeout(TIMEOUT)
+            .query(&[("uploadType", "media"), ("supportsAllDrives", "true")])
+            .bearer_auth(&token)
+            .header(reqwest::header::CONTENT_TYPE, mime_type(path))
+            .body(content)
+            .send()
+            .await
+            .context("Failed to send upload to Google Drive")?;
+        let status = res.status();
+        if !status.is_success() {
+            let text = res.text().await.unwrap_or_default();
+            bail!("Google Drive returned {} uploading {}: {}", status, name, text);
+        }
+        Ok(())
+    }
+
+    // the id of the file called `name` in the folder, if there is one
+    async fn find(&self, token: &str, name: &str, folder_id: &str) -> Result<Option<String>> {
+        let query = format!("name = '{}' and '{}' in parents and trashed = false", escape(name), escape(folder_id));
+        let res = self.http
+            .get(DRIVE_URL)
+            .timeout(TIMEOUT)
+            .query(&[
+                ("q", query.as_str()),
+                ("fields", "files(id)"),
+                ("supportsAllDrives", "true"),
+                ("includeItemsFromAllDrives", "true"),
+            ])
+            .bearer_auth(token)
+            .send()
+            .await
+            .context("Failed to send request to Google Drive")?;
+        let status = res.status();
+        if !status.is_success() {
+            let text = res.text().await.unwrap_or_default();
+            bail!("Google Drive returned {} looking up {}: {}", status, name, text);
+        }
+        let list: FileList = res.json().await.context("Unexpected Google Drive response")?;
+        Ok(list.files.into_iter().next().map(|f| f.id))
+    }
+
+    // an empty file called `name` in the folder, for the upload to fill
+    async fn create(&self, token: &str, name: &str, folder_id: &str) -> Result<String> {
+        let res = self.http
+            .post(DRIVE_URL)
+            .timeout(TIMEOUT)
+            .query(&[("supportsAllDrives", "true"), ("fields", "id")])
+            .bearer_auth(token)
+            .json(&serde_json::json!({ "name": name, "parents": [folder_id] }))
+            .send()
+            .await
+            .context("Failed to send request to Google Drive")?;
+        let status = res.status();
+        if !status.is_success() {
+            let text = res.text().await.unwrap_or_default();
+            bail!("Google Drive returned {} creating {}: {}", status, name, text);
+        }
+        let file: DriveFile = res.json().await.context("Unexpected Google Drive response")?;
+        Ok(file.id)
+    }
+}
+
+// Drive query strings are single-quoted
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+        Some("csv") => "text/csv",
+        Some("xlsx") => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    }
+}
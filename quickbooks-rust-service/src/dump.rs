@@ -0,0 +1,63 @@
+// `qb_sync dump`: the whole chart of accounts as parsed from AccountQueryRs, written as JSON or CSV
+// for scripts (or for generating [[sync_blocks]]) without writing to any destination; `qb_sync
+// list-accounts` prints the same accounts as a table to read
+
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::Path;
+
+use crate::accounts::{AccountData, AccountIndex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+/// Accounts sorted by full name, so parents come before their subaccounts and dumps diff cleanly
+pub fn sorted(index: &AccountIndex) -> Vec<&AccountData> {
+    let mut accounts: Vec<&AccountData> = index.values().collect();
+    accounts.sort_by(|a, b| a.account_full_name.cmp(&b.account_full_name));
+    accounts
+}
+
+pub fn write(accounts: &[&AccountData], format: Format, out: impl Write) -> Result<()> {
+    match format {
+        Format::Json => {
+            let mut out = out;
+            serde_json::to_writer_pretty(&mut out, accounts)?;
+            writeln!(out)?;
+            out.flush()?;
+        }
+        Format::Csv => {
+            // the header row comes from the AccountData field names
+            let mut writer = csv::Writer::from_writer(out);
+            for account in accounts {
+                writer.serialize(account)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// One line per account for list-accounts: full name, type and balance in columns
+pub fn lines(accounts: &[&AccountData]) -> Vec<String> {
+    let width = accounts.iter().map(|a| a.account_full_name.chars().count()).max().unwrap_or(0).max(40);
+    accounts.iter()
+        .map(|a| format!("{:<width$} {:<24} {:>14}", a.account_full_name, a.account_type, a.balance.round_dp(2), width = width))
+        .collect()
+}
+
+/// Writes to `output`, or to stdout when no file is given
+pub fn write_to(accounts: &[&AccountData], format: Format, output: Option<&Path>) -> Result<()> {
+    match output {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            write(accounts, format, std::io::BufWriter::new(file))
+                .with_context(|| format!("Failed to write {}", path.display()))
+        }
+        None => write(accounts, format, std::io::stdout().lock()),
+    }
+}
@@ -0,0 +1,283 @@
+// Arithmetic over the values blocks publish during a cycle, for computed sync blocks
+//
+// "cash_total / current_liabilities", "(ar_current + ar_30) / ar_total * 100": numbers, + - * /,
+// parentheses and unary minus. A name is another sync block's name or a value a report or raw
+// qbXML block publishes; names with spaces or other punctuation go in square brackets, as in
+// "[Operating cash] / [Total assets]". Evaluated in decimal, so "[a] + [b]" gives exactly the
+// cents of both.
+//
+// Functions: abs(x), round(x) or round(x, places), and min, max, sum and avg over any number of
+// arguments, e.g. "max(checking - 5000, 0)" or "avg(jan, feb, mar)".
+
+use anyhow::{bail, Result};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+
+use crate::amounts;
+
+/// Evaluates `expression`, looking names up with `value`
+pub fn evaluate(expression: &str, value: impl Fn(&str) -> Option<Decimal>) -> Result<Decimal> {
+    let tokens = tokenize(expression)?;
+    let mut parser = Parser { tokens: &tokens, pos: 0, expression, value: &value };
+    let result = parser.sum()?;
+    if parser.pos < tokens.len() {
+        bail!("Unexpected {} in expression \"{}\"", tokens[parser.pos], expression);
+    }
+    Ok(result)
+}
+
+/// The names an expression refers to, in order of appearance; function names aren't included
+pub fn names(expression: &str) -> Result<Vec<String>> {
+    let tokens = tokenize(expression)?;
+    Ok(tokens.iter()
+        .enumerate()
+        .filter_map(|(i, t)| match t {
+            Token::Name(name) if tokens.get(i + 1) != Some(&Token::Op('(')) => Some(name.clone()),
+            _ => None,
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Decimal),
+    Name(String),
+    Op(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "number {}", n),
+            Token::Name(name) => write!(f, "name '{}'", name),
+            Token::Op(op) => write!(f, "'{}'", op),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '+' | '-' | '*' | '/' | '(' | ')' | ',' => {
+                chars.next();
+                tokens.push(Token::Op(c));
+            }
+            '0'..='9' | '.' => {
+                let mut number = String::new();
+                while let Some(&d) = chars.peek().filter(|d| d.is_ascii_digit() || **d == '.') {
+                    number.push(d);
+                    chars.next();
+                }
+                match number.parse() {
+                    Ok(n) => tokens.push(Token::Number(n)),
+                    Err(_) => bail!("Invalid number '{}' in expression \"{}\"", number, expression),
+                }
+            }
+            '[' => {
+                chars.next();
+                let mut name = String::new();
+                let mut closed = false;
+                for d in chars.by_ref() {
+                    if d == ']' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(d);
+                }
+                if !closed || name.trim().is_empty() {
+                    bail!("Empty or unclosed [name] in expression \"{}\"", expression);
+                }
+                tokens.push(Token::Name(name.trim().to_string()));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut name = String::new();
+                while let Some(&d) = chars.peek().filter(|d| d.is_alphanumeric() || **d == '_') {
+                    name.push(d);
+                    chars.next();
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => bail!("Unexpected '{}' in expression \"{}\"", c, expression),
+        }
+    }
+    if tokens.is_empty() {
+        bail!("Expression is empty");
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a, F: Fn(&str) -> Option<Decimal>> {
+    tokens: &'a [Token],
+    pos: usize,
+    expression: &'a str,
+    value: &'a F,
+}
+
+impl<F: Fn(&str) -> Option<Decimal>> Parser<'_, F> {
+    fn next_op(&mut self, ops: &[char]) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                self.pos += 1;
+                Some(*op)
+            }
+            _ => None,
+        }
+    }
+
+    // sum := product (('+' | '-') product)*
+    fn sum(&mut self) -> Result<Decimal> {
+        let mut total = self.product()?;
+        while let Some(op) = self.next_op(&['+', '-']) {
+            let rhs = self.product()?;
+            let result = if op == '+' { total.checked_add(rhs) } else { total.checked_sub(rhs) };
+            total = self.in_range(result)?;
+        }
+        Ok(total)
+    }
+
+    // product := factor (('*' | '/') factor)*
+    fn product(&mut self) -> Result<Decimal> {
+        let mut total = self.factor()?;
+        while let Some(op) = self.next_op(&['*', '/']) {
+            let rhs = self.factor()?;
+            if op == '/' && rhs.is_zero() {
+                bail!("Division by zero in expression \"{}\"", self.expression);
+            }
+            let result = if op == '*' { total.checked_mul(rhs) } else { total.checked_div(rhs) };
+            total = self.in_range(result)?;
+        }
+        Ok(total)
+    }
+
+    fn call(&self, function: &str, args: &[Decimal]) -> Result<Decimal> {
+        let arity = |ok: bool| -> Result<()> {
+            if !ok {
+                bail!("Wrong number of arguments to {}() in expression \"{}\"", function, self.expression);
+            }
+            Ok(())
+        };
+        match function.to_ascii_lowercase().as_str() {
+            "abs" => {
+                arity(args.len() == 1)?;
+                Ok(args[0].abs())
+            }
+            "round" => {
+                arity(args.len() <= 2)?;
+                let places = args.get(1).copied().unwrap_or_default();
+                if places.is_sign_negative() || !places.fract().is_zero() || places > Decimal::from(28) {
+                    bail!("round() takes a whole number of places from 0 to 28 in expression \"{}\"", self.expression);
+                }
+                Ok(amounts::round(args[0], places.to_u32().unwrap_or_default()))
+            }
+            "min" => Ok(args.iter().copied().fold(args[0], Decimal::min)),
+            "max" => Ok(args.iter().copied().fold(args[0], Decimal::max)),
+            "sum" => self.in_range(args.iter().try_fold(Decimal::ZERO, |total, a| total.checked_add(*a))),
+            "avg" => {
+                let total = self.in_range(args.iter().try_fold(Decimal::ZERO, |total, a| total.checked_add(*a)))?;
+                self.in_range(total.checked_div(Decimal::from(args.len())))
+            }
+            _ => bail!("Unknown function {}() in expression \"{}\"; there are abs, round, min, max, sum and avg", function, self.expression),
+        }
+    }
+
+    fn in_range(&self, result: Option<Decimal>) -> Result<Decimal> {
+        result.ok_or_else(|| anyhow::anyhow!("Expression \"{}\" gives a number too large to hold", self.expression))
+    }
+
+    // factor := '-' factor | '(' sum ')' | number | name | name '(' sum (',' sum)* ')'
+    fn factor(&mut self) -> Result<Decimal> {
+        if self.next_op(&['-']).is_some() {
+            return Ok(-self.factor()?);
+        }
+        if self.next_op(&['(']).is_some() {
+            let inner = self.sum()?;
+            if self.next_op(&[')']).is_none() {
+                bail!("Missing ')' in expression \"{}\"", self.expression);
+            }
+            return Ok(inner);
+        }
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(n),
+            Some(Token::Name(name)) if self.next_op(&['(']).is_some() => {
+                let mut args = vec![self.sum()?];
+                while self.next_op(&[',']).is_some() {
+                    args.push(self.sum()?);
+                }
+                if self.next_op(&[')']).is_none() {
+                    bail!("Missing ')' after the arguments of {}() in expression \"{}\"", name, self.expression);
+                }
+                self.call(&name, &args)
+            }
+            Some(Token::Name(name)) => (self.value)(&name)
+                .ok_or_else(|| anyhow::anyhow!("No value for '{}' in expression \"{}\"", name, self.expression)),
+            Some(other) => bail!("Unexpected {} in expression \"{}\"", other, self.expression),
+            None => bail!("Expression \"{}\" ends too early", self.expression),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str) -> Result<Decimal> {
+        evaluate(expression, |name| match name {
+            "cash" => Some(Decimal::new(1250050, 2)),
+            "Operating cash" => Some(Decimal::new(300, 0)),
+            "zero" => Some(Decimal::ZERO),
+            _ => None,
+        })
+    }
+
+    fn dec(text: &str) -> Decimal {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), dec("7"));
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), dec("9"));
+        assert_eq!(eval("10 - 4 - 3").unwrap(), dec("3"));
+        assert_eq!(eval("12 / 3 / 2").unwrap(), dec("2"));
+        assert_eq!(eval("-2 * -3 + 1").unwrap(), dec("7"));
+        assert_eq!(eval("-(1 + 2) * 2").unwrap(), dec("-6"));
+        assert_eq!(eval("0.1 + 0.2").unwrap(), dec("0.3"));
+    }
+
+    #[test]
+    fn names_and_functions() {
+        assert_eq!(eval("cash - [Operating cash]").unwrap(), dec("12200.50"));
+        assert_eq!(eval("round(cash / 3, 2)").unwrap(), dec("4166.83"));
+        assert_eq!(eval("max(zero - 5, abs(-2), 1)").unwrap(), dec("2"));
+        assert_eq!(eval("avg(1, 2, 4)").unwrap().round_dp(4), dec("2.3333"));
+        assert_eq!(names("max(cash - 5000, 0) + [Operating cash]").unwrap(), ["cash", "Operating cash"]);
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        assert!(eval("cash / zero").unwrap_err().to_string().contains("Division by zero"));
+        assert!(eval("1 / (2 - 2)").unwrap_err().to_string().contains("Division by zero"));
+    }
+
+    #[test]
+    fn unknown_names_and_bad_syntax_are_errors() {
+        assert!(eval("cash + savings").unwrap_err().to_string().contains("No value for 'savings'"));
+        assert!(eval("median(1, 2)").unwrap_err().to_string().contains("Unknown function median()"));
+        assert!(eval("abs(1, 2)").unwrap_err().to_string().contains("Wrong number of arguments"));
+        assert!(eval("round(1, 0.5)").unwrap_err().to_string().contains("whole number of places"));
+        assert!(eval("(1 + 2").unwrap_err().to_string().contains("Missing ')'"));
+        assert!(eval("1 +").unwrap_err().to_string().contains("ends too early"));
+        assert!(eval("1 2").unwrap_err().to_string().contains("Unexpected number 2"));
+        assert!(eval("1.2.3").unwrap_err().to_string().contains("Invalid number"));
+        assert!(eval("[cash").unwrap_err().to_string().contains("unclosed"));
+        assert!(eval("cash % 2").unwrap_err().to_string().contains("Unexpected '%'"));
+        assert!(eval("  ").unwrap_err().to_string().contains("empty"));
+    }
+}
@@ -0,0 +1,115 @@
+// XPath 1.0 value extraction from qbXML responses (sxd-xpath)
+//
+// Expressions are evaluated with QBXMLMsgsRs (QBPOSXMLMsgsRs for Point of Sale) as the context
+// node, so a relative path such as "ItemInventoryQueryRs/ItemInventoryRet/QuantityOnHand" picks
+// straight into the response, while absolute paths ("/QBXML/..."), predicates
+// ("...ItemInventoryRet[Name='Widget']/QuantityOnHand") and functions ("sum(...)", "count(...)")
+// work as usual.
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value;
+use sxd_document::dom::{ChildOfElement, ChildOfRoot, Element};
+use sxd_document::Package;
+use sxd_xpath::{Context, Factory};
+
+pub struct Response {
+    package: Package,
+}
+
+impl Response {
+    pub fn parse(response_xml: &str) -> Result<Self> {
+        let package = sxd_document::parser::parse(response_xml)
+            .map_err(|e| anyhow!("Failed to parse qbXML response: {}", e))?;
+        Ok(Self { package })
+    }
+
+    /// Removes every record naming an account `permitted` rejects, before any XPath sees it: an
+    /// AccountRet, a transaction or line whose ...AccountRef is the account, a report row about it.
+    /// Returns how many were removed.
+    pub fn drop_accounts(&mut self, permitted: impl Fn(&str) -> bool) -> usize {
+        let document = self.package.as_document();
+        let mut denied = Vec::new();
+        for child in document.root().children() {
+            if let ChildOfRoot::Element(element) = child {
+                collect_denied(element, &permitted, &mut denied);
+            }
+        }
+        for element in &denied {
+            element.remove_from_parent();
+        }
+        denied.len()
+    }
+
+    /// Every value the expression yields: one per node for node-sets (their trimmed text), a single
+    /// value for numbers, strings and booleans
+    pub fn values(&self, expr: &str) -> Result<Vec<Value>> {
+        let factory = Factory::new();
+        let xpath = factory.build(expr)
+            .map_err(|e| anyhow!("Invalid XPath '{}': {}", expr, e))?
+            .ok_or_else(|| anyhow!("Empty XPath"))?;
+        let context = Context::new();
+        let document = self.package.as_document();
+
+        // relative expressions start inside QBXMLMsgsRs; fall back to the root for odd responses
+        let msgs = factory.build("/QBXML/QBXMLMsgsRs | /QBPOSXML/QBPOSXMLMsgsRs").ok().flatten()
+            .and_then(|p| p.evaluate(&context, document.root()).ok())
+            .and_then(|v| match v {
+                sxd_xpath::Value::Nodeset(nodes) => nodes.document_order_first(),
+                _ => None,
+            });
+        let result = match msgs {
+            Some(node) => xpath.evaluate(&context, node),
+            None => xpath.evaluate(&context, document.root()),
+        }
+        .map_err(|e| anyhow!("XPath '{}' failed: {}", expr, e))?;
+
+        Ok(match result {
+            sxd_xpath::Value::Nodeset(nodes) => nodes.document_order()
+                .iter()
+                .map(|n| Value::from(n.string_value().trim()))
+                .collect(),
+            sxd_xpath::Value::Number(n) if n.is_finite() => vec![Value::from(n)],
+            // sum() over text that isn't numeric, for instance
+            sxd_xpath::Value::Number(_) => bail!("XPath '{}' did not evaluate to a number", expr),
+            sxd_xpath::Value::String(s) => vec![Value::from(s)],
+            sxd_xpath::Value::Boolean(b) => vec![Value::from(b)],
+        })
+    }
+}
+
+fn child_elements<'d>(element: Element<'d>) -> impl Iterator<Item = Element<'d>> {
+    element.children().into_iter().filter_map(|c| match c {
+        ChildOfElement::Element(e) => Some(e),
+        _ => None,
+    })
+}
+
+fn text_of(element: Element) -> String {
+    element.children().iter().filter_map(|c| match c {
+        ChildOfElement::Text(t) => Some(t.text()),
+        _ => None,
+    }).collect::<String>().trim().to_string()
+}
+
+// the account an element is a record of or refers to directly, if any
+fn named_account(element: Element) -> Option<String> {
+    let full_name = |e: Element| child_elements(e).find(|c| c.name().local_part() == "FullName").map(text_of);
+    if element.name().local_part() == "AccountRet" {
+        return full_name(element);
+    }
+    child_elements(element).find_map(|child| match child.name().local_part() {
+        name if name.ends_with("AccountRef") => full_name(child),
+        "RowData" if child.attribute_value("rowType") == Some("account") => child.attribute_value("value").map(str::to_string),
+        _ => None,
+    })
+}
+
+fn collect_denied<'d>(element: Element<'d>, permitted: &impl Fn(&str) -> bool, denied: &mut Vec<Element<'d>>) {
+    if named_account(element).is_some_and(|account| !account.is_empty() && !permitted(&account)) {
+        denied.push(element);
+        return;
+    }
+    for child in child_elements(element) {
+        collect_denied(child, permitted, denied);
+    }
+}
@@ -1,5 +1,7 @@
 // FileMode enum for QuickBooks session modes
 #[derive(Debug, Clone, Copy)]
+// only the COM backend looks at the mode
+#[cfg_attr(not(feature = "qb-com"), allow(dead_code))]
 pub enum FileMode {
     SingleUser,
     MultiUser,
@@ -1,13 +1,87 @@
-use anyhow::{Result, Context};
+use anyhow::{bail, Result, Context};
+use rust_decimal::Decimal;
 use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+use crate::a1;
+use crate::amounts;
+use crate::capture;
+use crate::config::{GoogleSheetsConfig, LongValues};
+use crate::correlation;
+use crate::offline_queue::OfflineQueue;
+use crate::quota::Quota;
+use crate::sheet_faults::SheetFaults;
+use crate::signing;
 
 pub struct GoogleSheetsClient {
     pub webapp_url: String,
     pub api_key: String,
     pub spreadsheet_id: String,
+    // when set every request is signed (X-Signature / X-Timestamp / X-Nonce)
+    pub signing_secret: Option<String>,
+    pub writes_per_minute: Option<u32>,
+    pub retries: u32,
+    pub request_timeout: Duration,
+    pub overwrite_formulas: bool,
+    // without it clear_below leaves the rows it would clear and logs them instead
+    pub confirm_destructive: bool,
+    pub max_rows_per_write: usize,
+    pub max_request_bytes: usize,
+    pub long_values: LongValues,
+    // [google_sheets] headers plus the destination's own, sent with every post
+    pub headers: HashMap<String, String>,
+    // create_sheet_if_missing: the webapp adds a missing tab, with these headers (column letter ->
+    // title) in its first row
+    pub create_sheet: Option<BTreeMap<String, String>>,
+    // appends that can't reach Google go here (see offline_queue)
+    pub offline_queue: Option<Arc<OfflineQueue>>,
+    // failed writes per spreadsheet this cycle, shared with every other client (see sheet_faults)
+    pub faults: Option<Arc<SheetFaults>>,
+    pub isolate_after: u32,
+    // [pipeline] write_concurrency, shared with every other client; a permit is held per attempt
+    pub slots: Option<Arc<Semaphore>>,
+    // shared with every other client (see AppContext)
+    http: reqwest::Client,
+    quota: Arc<Quota>,
+}
+
+const DEFAULT_RETRIES: u32 = 2;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 120;
+const DEFAULT_MAX_ROWS_PER_WRITE: usize = 2000;
+const DEFAULT_MAX_REQUEST_KB: usize = 8000;
+// what webapp calls are filed under in a --record capture
+const WEBAPP: &str = "webapp";
+// the most characters a Sheets cell holds
+pub const CELL_MAX_CHARS: usize = 50_000;
+// room left in a request for everything but the rows of a range write
+const PAYLOAD_OVERHEAD_BYTES: usize = 4096;
+
+// why one attempt failed, and whether trying again could help
+struct PostError {
+    retryable: bool,
+    // no response at all: no connection, DNS failure or timeout
+    unreachable: bool,
+    // the webapp wouldn't write over a formula; about the cell, not the spreadsheet
+    formula_protected: bool,
+    error: anyhow::Error,
+}
+
+impl PostError {
+    fn retryable(error: anyhow::Error) -> Self {
+        Self { retryable: true, unreachable: false, formula_protected: false, error }
+    }
+
+    fn fatal(error: anyhow::Error) -> Self {
+        Self { retryable: false, unreachable: false, formula_protected: false, error }
+    }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 struct GoogleSheetsPayload<'a> {
     #[serde(rename = "apiKey")]
     api_key: &'a str,
@@ -17,60 +91,603 @@ struct GoogleSheetsPayload<'a> {
     sheet_name: Option<&'a str>,
     #[serde(rename = "cellAddress")]
     cell_address: &'a str,
-    #[serde(rename = "floatValue")]
-    account_value: f64,
+    #[serde(rename = "floatValue", serialize_with = "amounts::serialize")]
+    account_value: Decimal,
     #[serde(rename = "stringValue", skip_serializing_if = "Option::is_none")]
     string_value: Option<&'a str>,
+    // string writes: also set the cell's background color
+    #[serde(skip_serializing_if = "Option::is_none")]
+    background: Option<&'a str>,
+    // cell writes: also set the cell's note (the hover text), replacing any it had
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<&'a str>,
+    // "append" asks the webapp to add rowValues as a new row instead of writing cellAddress
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<&'a str>,
+    #[serde(rename = "rowValues", skip_serializing_if = "Option::is_none")]
+    row_values: Option<&'a [serde_json::Value]>,
+    // append mode: start the row in this column, below its last filled cell, rather than in
+    // column A below the sheet's last row
+    #[serde(rename = "appendColumn", skip_serializing_if = "Option::is_none")]
+    append_column: Option<&'a str>,
+    // "range" writes rows as a block whose top-left corner is cellAddress
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rows: Option<&'a [Vec<serde_json::Value>]>,
+    #[serde(rename = "clearBelow", skip_serializing_if = "Option::is_none")]
+    clear_below: Option<bool>,
+    // range and promote: instead of clearing, send back wouldClear, the filled rows below the
+    // block, and the first of them as clearPreview
+    #[serde(rename = "previewClear", skip_serializing_if = "Option::is_none")]
+    preview_clear: Option<bool>,
+    // range mode: (re)point this named range at the written rows
+    #[serde(rename = "namedRange", skip_serializing_if = "Option::is_none")]
+    named_range: Option<&'a str>,
+    // a range write split into several posts: the named range covers this many rows from the
+    // first post's anchor, not just the rows of that post
+    #[serde(rename = "namedRangeRows", skip_serializing_if = "Option::is_none")]
+    named_range_rows: Option<usize>,
+    // without it the webapp won't write over cells that hold a formula
+    #[serde(rename = "overwriteFormulas", skip_serializing_if = "Option::is_none")]
+    overwrite_formulas: Option<bool>,
+    // without it a staged write won't use a visible tab that has the staging tab's name
+    #[serde(rename = "confirmDestructive", skip_serializing_if = "Option::is_none")]
+    confirm_destructive: Option<bool>,
+    // writes: add sheetName when the spreadsheet has no such tab, putting newSheetHeaders
+    // ({"A": "Name", ...}) in its first row
+    #[serde(rename = "createSheet", skip_serializing_if = "Option::is_none")]
+    create_sheet: Option<bool>,
+    #[serde(rename = "newSheetHeaders", skip_serializing_if = "Option::is_none")]
+    new_sheet_headers: Option<&'a BTreeMap<String, String>>,
+    // range mode: sheetName is a staging tab, added hidden when missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    staged: Option<bool>,
+    // "promote" copies rowCount x width cells at cellAddress from stagingSheet onto sheetName
+    #[serde(rename = "stagingSheet", skip_serializing_if = "Option::is_none")]
+    staging_sheet: Option<&'a str>,
+    #[serde(rename = "rowCount", skip_serializing_if = "Option::is_none")]
+    row_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<usize>,
+}
+
+/// Sheets named ranges allow letters, digits and underscores and can't start with a digit
+pub fn named_range_name(label: &str) -> String {
+    let mut name: String = label.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// The hidden tab a staged block writes to before its rows are promoted onto `sheet_name`; one
+/// per block and sheet, so blocks sharing a sheet don't stage over each other
+pub fn staging_sheet_name(label: &str, sheet_name: &str) -> String {
+    format!("_staging {} {}", named_range_name(label), sheet_name)
 }
 
 impl GoogleSheetsClient {
-    pub fn new(webapp_url: String, api_key: String, spreadsheet_id: String) -> Self {
-        Self { webapp_url, api_key, spreadsheet_id }
+    /// Usually made with AppContext::sheets
+    pub fn new(gs_cfg: &GoogleSheetsConfig, http: reqwest::Client, quota: Arc<Quota>, spreadsheet_id: String) -> Self {
+        Self {
+            webapp_url: gs_cfg.webapp_url.clone(),
+            api_key: gs_cfg.api_key.clone(),
+            spreadsheet_id,
+            signing_secret: gs_cfg.signing_secret.clone(),
+            writes_per_minute: gs_cfg.writes_per_minute,
+            retries: gs_cfg.retries.unwrap_or(DEFAULT_RETRIES),
+            request_timeout: Duration::from_secs(gs_cfg.request_timeout_secs.unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS)),
+            overwrite_formulas: gs_cfg.overwrite_formulas.unwrap_or(false),
+            confirm_destructive: gs_cfg.confirm_destructive.unwrap_or(false),
+            max_rows_per_write: gs_cfg.max_rows_per_write.unwrap_or(DEFAULT_MAX_ROWS_PER_WRITE).max(1),
+            max_request_bytes: gs_cfg.max_request_kb.unwrap_or(DEFAULT_MAX_REQUEST_KB).max(1) * 1024,
+            long_values: gs_cfg.long_values,
+            headers: gs_cfg.headers.clone(),
+            create_sheet: None,
+            offline_queue: None,
+            faults: None,
+            isolate_after: gs_cfg.isolate_after.unwrap_or(0),
+            slots: None,
+            http,
+            quota,
+        }
+    }
+
+    /// Adds a destination's own headers to the [google_sheets] ones, replacing any of the same name
+    pub fn with_headers(mut self, headers: &HashMap<String, String>) -> Self {
+        for (name, value) in headers {
+            self.headers.retain(|existing, _| !existing.eq_ignore_ascii_case(name));
+            self.headers.insert(name.clone(), value.clone());
+        }
+        self
+    }
+
+    /// With `create` the webapp adds a missing tab instead of failing the write, with `headers`
+    /// (column letter -> title) in its first row
+    pub fn creating_sheet(mut self, create: bool, headers: BTreeMap<String, String>) -> Self {
+        self.create_sheet = create.then_some(headers);
+        self
     }
 
-    pub async fn send_balance(&self, current_account_value: f64, sheet_name: Option<&str>, cell_address: Option<&str>) -> Result<()> {
+    pub async fn send_balance(&self, current_account_value: Decimal, sheet_name: Option<&str>, cell_address: Option<&str>) -> Result<()> {
         let payload = GoogleSheetsPayload {
             api_key: &self.api_key,
             spreadsheet_id: &self.spreadsheet_id,
             sheet_name: sheet_name,
             cell_address: cell_address.unwrap_or(""),
             account_value: current_account_value,
-            string_value: None,
+            ..Default::default()
         };
-        let client = reqwest::Client::new();
-        let res = client.post(&self.webapp_url)
-            .json(&payload)
-            .send()
-            .await
-            .context("Failed to send POST to Google Sheets Web App")?;
-        if !res.status().is_success() {
-            let status = res.status();
-            let text = res.text().await.unwrap_or_default();
-            anyhow::bail!("Google Sheets Web App returned error: {} - {}", status, text);
-        }
-        Ok(())
+        self.post(&payload).await
     }
 
     pub async fn send_timestamp(&self, timestamp: Option<&str>, sheet_name: Option<&str>, cell_address: Option<&str>) -> Result<()> {
+        let timestamp = timestamp.map(|t| self.fit_text(t)).transpose()?;
         let payload = GoogleSheetsPayload {
             api_key: &self.api_key,
             spreadsheet_id: &self.spreadsheet_id,
             sheet_name: sheet_name,
             cell_address: cell_address.unwrap_or(""),
-            account_value: 0.0,
-            string_value: timestamp,
+            string_value: timestamp.as_deref(),
+            ..Default::default()
         };
-        let client = reqwest::Client::new();
-        let res = client.post(&self.webapp_url)
-            .json(&payload)
-            .send()
-            .await
-            .context("Failed to send POST to Google Sheets Web App")?;
-        if !res.status().is_success() {
-            let status = res.status();
-            let text = res.text().await.unwrap_or_default();
-            anyhow::bail!("Google Sheets Web App returned error: {} - {}", status, text);
+        self.post(&payload).await
+    }
+
+    /// Writes a pre-formatted string (e.g. a rendered value_template) instead of a number
+    pub async fn send_string(&self, value: &str, sheet_name: &str, cell_address: &str) -> Result<()> {
+        let value = self.fit_text(value)?;
+        let payload = GoogleSheetsPayload {
+            api_key: &self.api_key,
+            spreadsheet_id: &self.spreadsheet_id,
+            sheet_name: Some(sheet_name),
+            cell_address,
+            string_value: Some(&value),
+            ..Default::default()
+        };
+        self.post(&payload).await
+    }
+
+    /// Writes `value` like send_string and, when given, sets the cell's background ("#f4cccc", "red")
+    pub async fn send_flag(&self, value: &str, background: Option<&str>, sheet_name: &str, cell_address: &str) -> Result<()> {
+        let value = self.fit_text(value)?;
+        let payload = GoogleSheetsPayload {
+            api_key: &self.api_key,
+            spreadsheet_id: &self.spreadsheet_id,
+            sheet_name: Some(sheet_name),
+            cell_address,
+            string_value: Some(&value),
+            background,
+            ..Default::default()
+        };
+        self.post(&payload).await
+    }
+
+    /// Writes `text`, or the balance when there is none, like send_string / send_balance, and sets
+    /// the cell's note to `note`
+    pub async fn send_noted(&self, balance: Decimal, text: Option<&str>, note: &str, sheet_name: &str, cell_address: &str) -> Result<()> {
+        let text = text.map(|t| self.fit_text(t)).transpose()?;
+        let note = self.fit_text(note)?;
+        let payload = GoogleSheetsPayload {
+            api_key: &self.api_key,
+            spreadsheet_id: &self.spreadsheet_id,
+            sheet_name: Some(sheet_name),
+            cell_address,
+            account_value: balance,
+            string_value: text.as_deref(),
+            note: Some(&note),
+            ..Default::default()
+        };
+        self.post(&payload).await
+    }
+
+    /// Appends `row_values` as a new row after the last non-empty row of `sheet_name`, or with
+    /// `append_column` ("B") from that column, in the row below its last filled cell
+    pub async fn append_row(&self, row_values: &[serde_json::Value], sheet_name: &str, append_column: Option<&str>) -> Result<()> {
+        let row_values = &self.fit_row(row_values)?;
+        let payload = GoogleSheetsPayload {
+            api_key: &self.api_key,
+            spreadsheet_id: &self.spreadsheet_id,
+            sheet_name: Some(sheet_name),
+            cell_address: "",
+            mode: Some("append"),
+            row_values: Some(row_values),
+            append_column,
+            ..Default::default()
+        };
+        self.post(&payload).await
+    }
+
+    /// Writes `rows` as a block starting at `cell_address`; with `clear_below` the webapp first
+    /// clears the same columns from that row down so stale rows from a longer previous write go away.
+    /// `named_range` is created or moved to cover exactly the written rows.
+    ///
+    /// More rows than max_rows_per_write, or than fit in max_request_bytes, go out as several posts
+    /// one below the other. Those aren't one write: when a later post fails, the earlier ones stay.
+    pub async fn write_range(&self, rows: &[Vec<serde_json::Value>], sheet_name: &str, cell_address: &str, clear_below: bool, named_range: Option<&str>) -> Result<()> {
+        self.write_rows(rows, sheet_name, cell_address, clear_below, named_range, false).await
+    }
+
+    /// The first half of a two-phase write: `rows` go to the hidden `staging_sheet` at
+    /// `cell_address`, where viewers don't see them half written, until `promote` copies them over.
+    /// Later batches are staged below the earlier ones the same way.
+    pub async fn stage_range(&self, rows: &[Vec<serde_json::Value>], staging_sheet: &str, cell_address: &str) -> Result<()> {
+        self.write_rows(rows, staging_sheet, cell_address, false, None, true).await
+    }
+
+    /// The second half: the webapp copies the `row_count` x `width` block staged at `cell_address`
+    /// onto `sheet_name` in one write, with write_range's clear_below and named_range
+    #[allow(clippy::too_many_arguments)]
+    pub async fn promote(&self, staging_sheet: &str, sheet_name: &str, cell_address: &str, row_count: usize, width: usize, clear_below: bool, named_range: Option<&str>) -> Result<()> {
+        let payload = GoogleSheetsPayload {
+            api_key: &self.api_key,
+            spreadsheet_id: &self.spreadsheet_id,
+            sheet_name: Some(sheet_name),
+            cell_address,
+            mode: Some("promote"),
+            staging_sheet: Some(staging_sheet),
+            row_count: Some(row_count),
+            width: Some(width),
+            clear_below: Some(clear_below && self.confirm_destructive),
+            preview_clear: (clear_below && !self.confirm_destructive).then_some(true),
+            named_range,
+            ..Default::default()
+        };
+        let reply = self.send(&payload).await?;
+        if payload.preview_clear.is_some() {
+            self.log_clear_preview(reply.as_ref(), sheet_name, cell_address);
+        }
+        Ok(())
+    }
+
+    async fn write_rows(&self, rows: &[Vec<serde_json::Value>], sheet_name: &str, cell_address: &str, clear_below: bool, named_range: Option<&str>, staged: bool) -> Result<()> {
+        // the webapp anchors the block at this cell; a range or R1C1 reference would fail there less clearly
+        let anchor = a1::parse_cell(cell_address).context("Invalid range anchor")?;
+        let mut rows = rows.iter().map(|row| self.fit_row(row)).collect::<Result<Vec<_>>>()?;
+        let chunks = self.chunks(&rows)?;
+        let split = chunks.len() > 1;
+        if split {
+            // every post pads its rows to its own widest one; the block should be as wide as its widest row
+            let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+            for row in &mut rows {
+                row.resize(width, serde_json::Value::from(""));
+            }
+            log::info!("[SHEETS] Writing {} rows at {}!{} in {} posts", rows.len(), sheet_name, cell_address, chunks.len());
+        }
+        let mut offset = 0;
+        for (i, len) in chunks.into_iter().enumerate() {
+            let start = a1::Cell { column: anchor.column, row: anchor.row + offset as u32 }.to_string();
+            let (first, last) = (i == 0, offset + len == rows.len());
+            let payload = GoogleSheetsPayload {
+                api_key: &self.api_key,
+                spreadsheet_id: &self.spreadsheet_id,
+                sheet_name: Some(sheet_name),
+                cell_address: &start,
+                mode: Some("range"),
+                rows: Some(&rows[offset..offset + len]),
+                // the first post clears below the whole block; the later ones write into the cleared rows
+                clear_below: Some(clear_below && first && self.confirm_destructive),
+                // unconfirmed, the last post, which ends where the block does, asks what is left below it
+                preview_clear: (clear_below && last && !self.confirm_destructive).then_some(true),
+                named_range: named_range.filter(|_| first),
+                named_range_rows: named_range.filter(|_| first && split).map(|_| rows.len()),
+                staged: staged.then_some(true),
+                ..Default::default()
+            };
+            let result = self.send(&payload).await;
+            let reply = if split {
+                result.with_context(|| format!("Rows {}-{} of {}", offset + 1, offset + len, rows.len()))?
+            } else {
+                result?
+            };
+            if payload.preview_clear.is_some() {
+                self.log_clear_preview(reply.as_ref(), sheet_name, cell_address);
+            }
+            offset += len;
         }
         Ok(())
     }
+
+    // Safe mode: what clear_below left in place, from the webapp's reply to a previewClear write
+    fn log_clear_preview(&self, reply: Option<&serde_json::Value>, sheet_name: &str, cell_address: &str) {
+        let Some(rows) = reply.and_then(|reply| reply.get("wouldClear")).and_then(serde_json::Value::as_u64) else {
+            log::warn!("[SAFE] Didn't clear below {}!{}, and the webapp didn't say what is there; redeploy Code.ts for a preview. Set [google_sheets] confirm_destructive = true or pass --yes to clear", sheet_name, cell_address);
+            return;
+        };
+        if rows == 0 {
+            return;
+        }
+        let sample: Vec<String> = reply.and_then(|reply| reply.get("clearPreview")).and_then(serde_json::Value::as_array)
+            .map(|preview| preview.iter().map(|row| row.to_string()).collect())
+            .unwrap_or_default();
+        log::warn!(
+            "[SAFE] Left {} row(s) below {}!{} that clear_below would remove, starting with {}; set [google_sheets] confirm_destructive = true or pass --yes to clear them",
+            rows, sheet_name, cell_address, sample.join(", "),
+        );
+    }
+
+    // How many rows go in each post of a range write: at most max_rows_per_write, and no more than
+    // fit in max_request_bytes. One post, possibly of no rows, when everything fits.
+    fn chunks(&self, rows: &[Vec<serde_json::Value>]) -> Result<Vec<usize>> {
+        let budget = self.max_request_bytes.saturating_sub(PAYLOAD_OVERHEAD_BYTES).max(1);
+        let mut chunks = vec![0];
+        let mut bytes = 0;
+        for (i, row) in rows.iter().enumerate() {
+            // the comma between rows included
+            let size = serde_json::to_vec(row)?.len() + 1;
+            if size > budget {
+                bail!("Row {} of the range is {} KB, more than fits in one request ([google_sheets] max_request_kb = {})",
+                    i + 1, size.div_ceil(1024), self.max_request_bytes / 1024);
+            }
+            let last = chunks.last_mut().expect("chunks starts with one");
+            if *last > 0 && (*last >= self.max_rows_per_write || bytes + size > budget) {
+                chunks.push(1);
+                bytes = size;
+            } else {
+                *last += 1;
+                bytes += size;
+            }
+        }
+        Ok(chunks)
+    }
+
+    // A text value as it can be written: as is when a cell holds it, else cut or refused per long_values
+    fn fit_text<'v>(&self, text: &'v str) -> Result<Cow<'v, str>> {
+        let chars = text.chars().count();
+        if chars <= CELL_MAX_CHARS {
+            return Ok(Cow::Borrowed(text));
+        }
+        match self.long_values {
+            LongValues::Error => bail!("A value of {} characters is longer than the {} a cell holds; \
+                [google_sheets] long_values = \"truncate\" cuts such values instead", chars, CELL_MAX_CHARS),
+            LongValues::Truncate => {
+                log::warn!("[SHEETS] Truncating a value of {} characters to the {} a cell holds", chars, CELL_MAX_CHARS);
+                Ok(Cow::Owned(text.chars().take(CELL_MAX_CHARS).collect()))
+            }
+        }
+    }
+
+    fn fit_row(&self, row: &[serde_json::Value]) -> Result<Vec<serde_json::Value>> {
+        row.iter()
+            .map(|value| match value {
+                serde_json::Value::String(text) => Ok(serde_json::Value::from(self.fit_text(text)?.into_owned())),
+                other => Ok(other.clone()),
+            })
+            .collect()
+    }
+
+    /// Every row of `sheet_name` up to its last filled row and column, as the sheet displays the
+    /// values. Used by import-blocks and append retention.
+    pub async fn read_sheet(&self, sheet_name: &str) -> Result<Vec<Vec<String>>> {
+        self.read(sheet_name, "").await
+    }
+
+    /// The cells of `range` on `sheet_name`, as the sheet displays them: a cell or range such as
+    /// "A2:D50", or "named:Customers" for a named range wherever it is. For looking at what is
+    /// already in the sheet before writing over it, or reconciling against it.
+    pub async fn read_range(&self, sheet_name: &str, range: &str) -> Result<Vec<Vec<String>>> {
+        let range = range.trim();
+        match a1::named_range(range) {
+            Some(name) => a1::parse_range_name(name).map(|_| ())?,
+            None => {
+                let reference = a1::parse(range)?;
+                if reference.sheet.is_some() {
+                    bail!("'{}' includes a sheet name; give the sheet separately and only the range here", range);
+                }
+            }
+        }
+        self.read(sheet_name, range).await
+    }
+
+    // a whole sheet when `range` is empty
+    async fn read(&self, sheet_name: &str, range: &str) -> Result<Vec<Vec<String>>> {
+        let payload = GoogleSheetsPayload {
+            api_key: &self.api_key,
+            spreadsheet_id: &self.spreadsheet_id,
+            sheet_name: Some(sheet_name),
+            cell_address: range,
+            mode: Some("read"),
+            ..Default::default()
+        };
+        let rows = self.send(&payload).await?
+            .and_then(|reply| reply.get("rows").cloned())
+            .context("Google Sheets Web App sent no rows; redeploy it if it predates read mode")?;
+        serde_json::from_value(rows).context("Unexpected rows from Google Sheets Web App")
+    }
+
+    async fn post(&self, payload: &GoogleSheetsPayload<'_>) -> Result<()> {
+        self.send(payload).await.map(|_| ())
+    }
+
+    // Posts the payload, retrying what can be retried, and returns the webapp's JSON reply if it sent one
+    async fn send(&self, payload: &GoogleSheetsPayload<'_>) -> Result<Option<serde_json::Value>> {
+        // reads change nothing, so there's nothing to apply twice (or to create)
+        let writes = payload.mode != Some("read");
+        // the webapp adds a staging tab itself; create_sheet_if_missing is about the sheet it is promoted to
+        let create_sheet = self.create_sheet.as_ref().filter(|_| writes && payload.staged.is_none());
+        let payload = &GoogleSheetsPayload {
+            overwrite_formulas: self.overwrite_formulas.then_some(true),
+            confirm_destructive: (writes && self.confirm_destructive).then_some(true),
+            create_sheet: create_sheet.map(|_| true),
+            new_sheet_headers: create_sheet.filter(|h| !h.is_empty()),
+            ..*payload
+        };
+        let mut json = serde_json::to_value(payload).context("Failed to serialize payload")?;
+        let (run_id, request_id) = (correlation::run_id(), correlation::block_id());
+        if let Some(fields) = json.as_object_mut() {
+            // shows up in the webapp's logs next to the cell it wrote
+            if let Some(id) = &run_id {
+                fields.insert("runId".to_string(), serde_json::Value::from(id.as_str()));
+            }
+            if let Some(id) = &request_id {
+                fields.insert("requestId".to_string(), serde_json::Value::from(id.as_str()));
+            }
+            // the same write retried after a timeout carries the same key, so the webapp can tell it
+            // already applied it (an append would otherwise add the row twice); the payload hash keeps
+            // a block's different writes apart. Outside a cycle the key is only this write's own.
+            if writes {
+                let scope = correlation::write_scope().unwrap_or_else(correlation::new_id);
+                let digest = Sha256::digest(serde_json::to_vec(payload)?);
+                let hash: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+                fields.insert("idempotencyKey".to_string(), serde_json::Value::from(format!("{}:{}", scope, hash)));
+            }
+        }
+        // serialize once so the signature covers exactly the bytes that are sent
+        let body = serde_json::to_vec(&json)?;
+        if body.len() > self.max_request_bytes {
+            bail!("Request of {} KB is larger than [google_sheets] max_request_kb ({})", body.len().div_ceil(1024), self.max_request_bytes / 1024);
+        }
+
+        let queue = self.offline_queue.as_ref().filter(|_| payload.mode == Some("append"));
+        if let Some(queue) = queue.filter(|q| q.has_pending()) {
+            // behind the rows already waiting, so the history stays in order
+            let waiting = queue.push(&self.spreadsheet_id, &json)?;
+            log::warn!("[QUEUE] Earlier rows are still queued; this one waits behind them ({} queued)", waiting);
+            return Ok(None);
+        }
+        match self.deliver(&body, run_id.as_deref(), request_id.as_deref()).await {
+            Ok(reply) => Ok(reply),
+            Err(e) => match queue.filter(|_| e.unreachable) {
+                Some(queue) => {
+                    let waiting = queue.push(&self.spreadsheet_id, &json)?;
+                    log::warn!("[QUEUE] {:#}; row queued for the next cycle ({} queued)", e.error, waiting);
+                    Ok(None)
+                }
+                None => Err(e.error),
+            },
+        }
+    }
+
+    /// Sends a payload from the offline queue: Ok(true) once delivered, Ok(false) while Google
+    /// still can't be reached, Err when the webapp refuses it
+    pub async fn replay(&self, body: &serde_json::Value) -> Result<bool> {
+        let mut json = body.clone();
+        if let Some(fields) = json.as_object_mut() {
+            fields.insert("apiKey".to_string(), serde_json::Value::from(self.api_key.as_str()));
+        }
+        let id = |name: &str| json.get(name).and_then(|v| v.as_str()).map(str::to_string);
+        let (run_id, request_id) = (id("runId"), id("requestId"));
+        let body = serde_json::to_vec(&json)?;
+        match self.deliver(&body, run_id.as_deref(), request_id.as_deref()).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.unreachable => Ok(false),
+            Err(e) => Err(e.error),
+        }
+    }
+
+    // One payload, retried as [google_sheets] retries allows; a spreadsheet that keeps failing is
+    // isolated for the rest of the cycle (see sheet_faults)
+    async fn deliver(&self, body: &[u8], run_id: Option<&str>, request_id: Option<&str>) -> Result<Option<serde_json::Value>, PostError> {
+        let Some(faults) = &self.faults else {
+            return self.deliver_with_retries(body, run_id, request_id).await;
+        };
+        faults.check(&self.spreadsheet_id).map_err(PostError::fatal)?;
+        let result = self.deliver_with_retries(body, run_id, request_id).await;
+        match &result {
+            Ok(_) => faults.succeeded(&self.spreadsheet_id),
+            Err(e) if !e.unreachable && !e.formula_protected => faults.failed(&self.spreadsheet_id, &e.error, self.isolate_after),
+            Err(_) => {}
+        }
+        result
+    }
+
+    async fn deliver_with_retries(&self, body: &[u8], run_id: Option<&str>, request_id: Option<&str>) -> Result<Option<serde_json::Value>, PostError> {
+        let mut attempt = 0;
+        loop {
+            if let Some(per_minute) = self.writes_per_minute {
+                self.quota.acquire(&self.spreadsheet_id, per_minute).await;
+            }
+            // not held through the retry wait, so a failing spreadsheet doesn't hold up the others
+            let slot = match &self.slots {
+                Some(slots) => Some(slots.acquire().await.map_err(|e| PostError::fatal(e.into()))?),
+                None => None,
+            };
+            let result = self.send_once(body, run_id, request_id).await;
+            drop(slot);
+            match result {
+                Ok(reply) => return Ok(reply),
+                Err(e) if e.retryable && attempt < self.retries => {
+                    attempt += 1;
+                    let delay = Duration::from_secs(2u64.pow(attempt));
+                    log::warn!("[SHEETS] {:#}; retry {}/{} in {}s", e.error, attempt, self.retries, delay.as_secs());
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn send_once(&self, body: &[u8], run_id: Option<&str>, request_id: Option<&str>) -> Result<Option<serde_json::Value>, PostError> {
+        let (status, text) = match capture::replayed_http(WEBAPP, body) {
+            Some(replayed) => {
+                let (status, text) = replayed.map_err(PostError::fatal)?;
+                (reqwest::StatusCode::from_u16(status).map_err(|e| PostError::fatal(e.into()))?, text)
+            }
+            None => self.post_to_webapp(body, run_id, request_id).await?,
+        };
+        if !status.is_success() {
+            let error = anyhow::anyhow!("Google Sheets Web App returned error: {} - {}", status, text);
+            return Err(if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                PostError::retryable(error)
+            } else {
+                PostError::fatal(error)
+            });
+        }
+
+        // cell writes answer with an empty body; range / append writes, reads and errors with JSON
+        let Ok(reply) = serde_json::from_str::<serde_json::Value>(&text) else {
+            return Ok(None);
+        };
+        if reply.get("duplicate").and_then(|d| d.as_bool()) == Some(true) {
+            log::info!("[SHEETS] Webapp had already applied this write (retried request); treating it as done");
+            return Ok(None);
+        }
+        if reply.get("success").and_then(|s| s.as_bool()) == Some(false) {
+            let message = reply.get("error").and_then(|e| e.as_str()).unwrap_or("unknown error");
+            let formula_protected = reply.get("formulaProtected").and_then(|f| f.as_bool()) == Some(true);
+            if formula_protected {
+                // most likely a mis-typed cell_address pointing into the dashboard
+                log::warn!("[SHEETS] Write refused to protect a formula: {}", message);
+            }
+            return Err(PostError { formula_protected, ..PostError::fatal(anyhow::anyhow!("Google Sheets Web App reported: {}", message)) });
+        }
+        Ok(Some(reply))
+    }
+
+    // One POST over the network, recorded with --record; the webapp's status and answer
+    async fn post_to_webapp(&self, body: &[u8], run_id: Option<&str>, request_id: Option<&str>) -> Result<(reqwest::StatusCode, String), PostError> {
+        let mut request = self.http.post(&self.webapp_url)
+            .timeout(self.request_timeout)
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        if let Some(id) = run_id {
+            request = request.header("X-Run-Id", id);
+        }
+        if let Some(id) = request_id {
+            request = request.header("X-Request-Id", id);
+        }
+        if let Some(secret) = &self.signing_secret {
+            // signed per attempt: the webapp accepts each nonce only once
+            let sig = signing::sign(secret, body);
+            // Apps Script's doPost can't see request headers, so the same values ride along as query parameters
+            request = request
+                .header("X-Signature", &sig.signature)
+                .header("X-Timestamp", &sig.timestamp)
+                .header("X-Nonce", &sig.nonce)
+                .query(&[("signature", &sig.signature), ("timestamp", &sig.timestamp), ("nonce", &sig.nonce)]);
+        }
+        let res = request
+            .body(body.to_vec())
+            .send()
+            .await
+            .map_err(|e| {
+                let unreachable = e.is_timeout() || e.is_connect() || e.is_request();
+                PostError { retryable: unreachable, unreachable, formula_protected: false, error: anyhow::Error::new(e).context("Failed to send POST to Google Sheets Web App") }
+            })?;
+        let status = res.status();
+        let text = res.text().await.unwrap_or_default();
+        capture::record_http(WEBAPP, &self.webapp_url, body, status.as_u16(), &text);
+        Ok((status, text))
+    }
 }
@@ -0,0 +1,153 @@
+// Microsoft Graph client for Excel workbooks on SharePoint / OneDrive
+//
+// Authenticates as an app (OAuth client credentials against [microsoft_graph] tenant_id), so no
+// user has to sign in; the app registration needs an application permission that can write the
+// workbook, e.g. Files.ReadWrite.All or Sites.ReadWrite.All.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::a1;
+use crate::capture;
+use crate::config::MicrosoftGraphConfig;
+
+const GRAPH_URL: &str = "https://graph.microsoft.com/v1.0";
+const TIMEOUT: Duration = Duration::from_secs(60);
+// renew a token this long before Entra ID says it expires
+const TOKEN_MARGIN: Duration = Duration::from_secs(120);
+
+// access tokens per tenant / client id, shared by every destination in the process
+static TOKENS: OnceLock<Mutex<HashMap<String, (String, Instant)>>> = OnceLock::new();
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// A workbook addressed by drive item id or by its path in the drive
+#[derive(Debug, Clone)]
+pub struct Workbook {
+    drive_id: String,
+    item: WorkbookItem,
+}
+
+#[derive(Debug, Clone)]
+enum WorkbookItem {
+    Id(String),
+    Path(String),
+}
+
+impl Workbook {
+    pub fn new(drive_id: String, item_id: Option<String>, path: Option<String>) -> Result<Self> {
+        let item = match (item_id, path) {
+            (Some(id), None) => WorkbookItem::Id(id),
+            (None, Some(path)) => WorkbookItem::Path(path.trim_matches('/').to_string()),
+            (Some(_), Some(_)) => bail!("Excel destination sets both item_id and path"),
+            (None, None) => bail!("Excel destination needs item_id or path"),
+        };
+        Ok(Self { drive_id, item })
+    }
+
+    pub fn describe(&self) -> &str {
+        match &self.item {
+            WorkbookItem::Id(id) => id,
+            WorkbookItem::Path(path) => path,
+        }
+    }
+
+    // .../drives/{drive}/items/{id}/workbook/worksheets/{sheet}/range(address='B2')
+    fn range_url(&self, worksheet: &str, address: &str) -> Result<reqwest::Url> {
+        let mut url = reqwest::Url::parse(GRAPH_URL)?;
+        {
+            let mut segments = url.path_segments_mut().map_err(|_| anyhow::anyhow!("Invalid Graph URL"))?;
+            segments.extend(["drives", self.drive_id.as_str()]);
+            match &self.item {
+                WorkbookItem::Id(id) => {
+                    segments.extend(["items", id.as_str()]);
+                }
+                // path addressing: root:/Folder/Book.xlsx:/workbook
+                WorkbookItem::Path(path) => {
+                    segments.push("root:");
+                    let parts: Vec<&str> = path.split('/').collect();
+                    let (last, dirs) = parts.split_last().context("Empty workbook path")?;
+                    segments.extend(dirs);
+                    segments.push(&format!("{}:", last));
+                }
+            }
+            segments.extend(["workbook", "worksheets", worksheet]);
+            segments.push(&format!("range(address='{}')", address));
+        }
+        Ok(url)
+    }
+}
+
+pub struct GraphClient {
+    http: reqwest::Client,
+    tenant_id: String,
+    client_id: String,
+    client_secret: String,
+}
+
+impl GraphClient {
+    pub fn new(cfg: &MicrosoftGraphConfig, http: reqwest::Client) -> Self {
+        Self {
+            http,
+            tenant_id: cfg.tenant_id.clone(),
+            client_id: cfg.client_id.clone(),
+            client_secret: cfg.client_secret.clone(),
+        }
+    }
+
+
+    async fn token(&self) -> Result<String> {
+        let key = format!("{}/{}", self.tenant_id, self.client_id);
+        let tokens = TOKENS.get_or_init(Default::default);
+        if let Some((token, expires)) = tokens.lock().unwrap().get(&key) {
+            if Instant::now() + TOKEN_MARGIN < *expires {
+                return Ok(token.clone());
+            }
+        }
+
+        let url = format!("https://login.microsoftonline.com/{}/oauth2/v2.0/token", self.tenant_id);
+        let res = self.http
+            .post(&url)
+            .timeout(TIMEOUT)
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+                ("scope", "https://graph.microsoft.com/.default"),
+            ])
+            .send()
+            .await
+            .context("Failed to request a Microsoft Graph token")?;
+        let status = res.status();
+        if !status.is_success() {
+            let text = res.text().await.unwrap_or_default();
+            bail!("Microsoft Graph token request failed: {} - {}", status, text);
+        }
+        let token: TokenResponse = res.json().await.context("Unexpected Microsoft Graph token response")?;
+        let expires = Instant::now() + Duration::from_secs(token.expires_in);
+        tokens.lock().unwrap().insert(key, (token.access_token.clone(), expires));
+        Ok(token.access_token)
+    }
+
+    /// Writes one value into a worksheet cell
+    pub async fn write_cell(&self, workbook: &Workbook, worksheet: &str, address: &str, value: serde_json::Value) -> Result<()> {
+        a1::parse_cell(address)?;
+        // a --replay doesn't sign in either
+        let token = if capture::replaying() { String::new() } else { self.token().await? };
+        let url = workbook.range_url(worksheet, address)?;
+        let request = self.http.patch(url.clone()).timeout(TIMEOUT).bearer_auth(token);
+        let (status, text) = capture::send("excel", url.as_str(), &serde_json::json!({ "values": [[value]] }), request).await
+            .context("Failed to send request to Microsoft Graph")?;
+        if !status.is_success() {
+            bail!("Microsoft Graph returned {} writing {}!{} in {}: {}", status, worksheet, address, workbook.describe(), text);
+        }
+        Ok(())
+    }
+}
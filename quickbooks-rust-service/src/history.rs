@@ -0,0 +1,257 @@
+// Small JSON file remembering per-block state between runs (default state/history.json)
+// Incremental list blocks read their last successful run time from here; with [balance_diff]
+// the latest two balances of every account are kept too, and every block's outcomes are counted
+// per day for `qb_sync report reliability`. Each [[close_snapshots]] entry remembers its last close,
+// and with [watchdog] or max_frequency every block remembers when it last ran without an error.
+// Account sync blocks remember their account's ListID, so they keep finding it after a rename, until
+// account_full_name is changed to point them at another account.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::amounts;
+use crate::config::{Config, MaxFrequency};
+use crate::dates;
+
+pub const DEFAULT_PATH: &str = "state/history.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BlockHistory {
+    // incremental list blocks: when the last successful export started
+    pub last_success: Option<DateTime<Local>>,
+    // [watchdog] and max_frequency: when the block last finished with every destination written
+    #[serde(default)]
+    pub last_ok: Option<DateTime<Local>>,
+    // account sync blocks: the ListID of the account last found, which survives a rename, and the
+    // account_full_name it was found for; a block pointed at another account matches by name again
+    #[serde(default)]
+    pub account_id: Option<String>,
+    #[serde(default)]
+    pub account_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BalanceSnapshot {
+    #[serde(serialize_with = "amounts::serialize", deserialize_with = "amounts::deserialize")]
+    pub balance: Decimal,
+    pub at: DateTime<Local>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountHistory {
+    pub latest: BalanceSnapshot,
+    // the run before latest; None until an account has been seen twice
+    pub previous: Option<BalanceSnapshot>,
+}
+
+// One block's runs on one day
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DayStats {
+    pub ok: u32,
+    pub failed: u32,
+    pub skipped: u32,
+    // time summed over the runs that reported any, and how many did
+    pub secs: f64,
+    pub timed: u32,
+    // failed runs by kind of error
+    #[serde(default)]
+    pub errors: BTreeMap<String, u32>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    #[serde(default)]
+    blocks: BTreeMap<String, BlockHistory>,
+    #[serde(default)]
+    accounts: BTreeMap<String, AccountHistory>,
+    #[serde(default)]
+    runs: BTreeMap<String, BTreeMap<NaiveDate, DayStats>>,
+    // [[close_snapshots]] by name: the last close taken (or given up on)
+    #[serde(default)]
+    closes: BTreeMap<String, NaiveDate>,
+}
+
+impl History {
+    pub fn path(config: &Config) -> PathBuf {
+        let path = config.history.as_ref().and_then(|h| h.path.as_deref()).unwrap_or(DEFAULT_PATH);
+        PathBuf::from(path)
+    }
+
+    /// A missing file is an empty history (first run)
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read history file {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("History file {} is not valid JSON", path.display()))
+    }
+
+    // write to a temp file and rename so a crash mid-write can't leave half a file behind
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn last_success(&self, block: &str) -> Option<DateTime<Local>> {
+        self.blocks.get(block).and_then(|b| b.last_success)
+    }
+
+    pub fn mark_success(&mut self, block: &str, at: DateTime<Local>) {
+        self.blocks.entry(block.to_string()).or_default().last_success = Some(at);
+    }
+
+    pub fn last_ok(&self, block: &str) -> Option<DateTime<Local>> {
+        self.blocks.get(block).and_then(|b| b.last_ok)
+    }
+
+    pub fn mark_ok(&mut self, block: &str, at: DateTime<Local>) {
+        self.blocks.entry(block.to_string()).or_default().last_ok = Some(at);
+    }
+
+    /// The ListID the block's account had when last found, while `account_full_name` is still the
+    /// name it was found for. Histories from before the name was kept only have the id.
+    pub fn account_id(&self, block: &str, account_full_name: &str) -> Option<String> {
+        let block = self.blocks.get(block)?;
+        match block.account_name.as_deref() {
+            Some(name) if name != account_full_name => None,
+            _ => block.account_id.clone(),
+        }
+    }
+
+    /// Whether `list_id` is already remembered for the block under `account_full_name`
+    pub fn remembers_account(&self, block: &str, list_id: &str, account_full_name: &str) -> bool {
+        self.blocks.get(block).is_some_and(|b| {
+            b.account_id.as_deref() == Some(list_id) && b.account_name.as_deref() == Some(account_full_name)
+        })
+    }
+
+    pub fn set_account_id(&mut self, block: &str, list_id: &str, account_full_name: &str) {
+        let entry = self.blocks.entry(block.to_string()).or_default();
+        entry.account_id = Some(list_id.to_string());
+        entry.account_name = Some(account_full_name.to_string());
+    }
+
+    /// Stores this run's balance, keeping the one it replaces as `previous`
+    pub fn record_balance(&mut self, account: &str, balance: Decimal, at: DateTime<Local>) {
+        let snapshot = BalanceSnapshot { balance, at };
+        match self.accounts.get_mut(account) {
+            Some(entry) => {
+                entry.previous = Some(entry.latest);
+                entry.latest = snapshot;
+            }
+            None => {
+                self.accounts.insert(account.to_string(), AccountHistory { latest: snapshot, previous: None });
+            }
+        }
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = (&String, &AccountHistory)> {
+        self.accounts.iter()
+    }
+
+    /// The block's counts for `day`, to add a run to
+    pub fn day_stats(&mut self, block: &str, day: NaiveDate) -> &mut DayStats {
+        self.runs.entry(block.to_string()).or_default().entry(day).or_default()
+    }
+
+    /// Drops run counts from before `first_kept`
+    pub fn prune_runs(&mut self, first_kept: NaiveDate) {
+        for days in self.runs.values_mut() {
+            days.retain(|day, _| *day >= first_kept);
+        }
+        self.runs.retain(|_, days| !days.is_empty());
+    }
+
+    pub fn last_close(&self, snapshot: &str) -> Option<NaiveDate> {
+        self.closes.get(snapshot).copied()
+    }
+
+    pub fn mark_close(&mut self, snapshot: &str, close: NaiveDate) {
+        self.closes.insert(snapshot.to_string(), close);
+    }
+
+    pub fn runs(&self) -> impl Iterator<Item = (&String, &BTreeMap<NaiveDate, DayStats>)> {
+        self.runs.iter()
+    }
+}
+
+/// A config's history, loaded once and shared by its blocks (see AppContext); every update is
+/// saved straight away
+pub struct HistoryStore {
+    path: PathBuf,
+    history: Mutex<History>,
+    // why nothing may be saved: an unreadable file is still at `path` and saving would replace it
+    frozen: Option<String>,
+}
+
+impl HistoryStore {
+    /// An unreadable history file is moved aside to "<name>.corrupt-<time>" before starting with
+    /// an empty history, so the first save doesn't destroy what could still be recovered from it
+    pub fn open(config: &Config) -> Self {
+        let path = History::path(config);
+        let (history, frozen) = match History::load(&path) {
+            Ok(history) => (history, None),
+            Err(e) => match set_aside(&path) {
+                Ok(aside) => {
+                    // incremental blocks do a full export, max_frequency blocks run and the diff starts over
+                    log::warn!("[HISTORY] {:#}; moved it to {} and starting with an empty history", e, aside.display());
+                    (History::default(), None)
+                }
+                Err(move_error) => {
+                    log::error!("[HISTORY] {:#}, and it couldn't be moved aside ({:#}); nothing is saved to it this run", e, move_error);
+                    (History::default(), Some(format!("{} couldn't be read; move or fix it so history can be saved again", path.display())))
+                }
+            },
+        };
+        Self { path, history: Mutex::new(history), frozen }
+    }
+
+    pub fn read<T>(&self, read: impl FnOnce(&History) -> T) -> T {
+        read(&self.history.lock().unwrap())
+    }
+
+    /// The blocks whose max_frequency is used up: their last clean run is in the same period as `now`
+    pub fn ran_this_period(&self, config: &Config, now: NaiveDateTime) -> Vec<(String, MaxFrequency)> {
+        self.read(|history| {
+            config.block_frequencies().into_iter()
+                .filter(|(block, frequency)| {
+                    history.last_ok(block)
+                        .and_then(|at| dates::wall_clock(config, at).ok())
+                        .is_some_and(|at| frequency.same_period(at, now))
+                })
+                .collect()
+        })
+    }
+
+    /// Changes the history and saves it
+    pub fn update<T>(&self, update: impl FnOnce(&mut History) -> T) -> Result<T> {
+        let mut history = self.history.lock().unwrap();
+        let result = update(&mut history);
+        if let Some(reason) = &self.frozen {
+            anyhow::bail!("History not saved: {}", reason);
+        }
+        history.save(&self.path)?;
+        Ok(result)
+    }
+}
+
+// history.json -> history.json.corrupt-20240601-093000, next to it
+fn set_aside(path: &Path) -> Result<PathBuf> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".corrupt-{}", Local::now().format("%Y%m%d-%H%M%S")));
+    let aside = path.with_file_name(name);
+    std::fs::rename(path, &aside).with_context(|| format!("Failed to rename {} to {}", path.display(), aside.display()))?;
+    Ok(aside)
+}
@@ -0,0 +1,126 @@
+// [hooks]: external commands run before and after every cycle, e.g. a QuickBooks verify script
+// first or a Power BI dataset refresh afterwards
+//
+// Commands go through the shell (cmd /C on Windows) one at a time, with the cycle's details in
+// QB_SYNC_* environment variables. Their output is logged; a command that outlives timeout_secs is
+// killed.
+
+use anyhow::{bail, Context, Result};
+use std::time::Duration;
+use tokio::process::Command;
+
+use crate::config::HooksConfig;
+use crate::summary::RunSummary;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    PreSync,
+    PostSync,
+}
+
+impl Stage {
+    fn name(self) -> &'static str {
+        match self {
+            Stage::PreSync => "pre_sync",
+            Stage::PostSync => "post_sync",
+        }
+    }
+}
+
+/// Runs every pre_sync command; Err when any of them failed
+pub async fn pre_sync(hooks: &HooksConfig, summary: &RunSummary) -> Result<()> {
+    run_all(hooks, Stage::PreSync, &hooks.pre_sync, environment(Stage::PreSync, summary)).await
+}
+
+/// Runs every post_sync command with the cycle's outcome; failures are only logged
+pub async fn post_sync(hooks: &HooksConfig, summary: &RunSummary) {
+    if let Err(e) = run_all(hooks, Stage::PostSync, &hooks.post_sync, environment(Stage::PostSync, summary)).await {
+        log::error!("[HOOK] {:#}", e);
+    }
+}
+
+/// Runs [quickbooks.circuit_breaker] notify commands with the reason the circuit opened; failures
+/// are only logged
+pub async fn circuit_open(commands: &[String], reason: &str, cooldown_secs: u64) {
+    let env = vec![
+        ("QB_SYNC_STAGE", "circuit_open".to_string()),
+        ("QB_SYNC_CIRCUIT_REASON", reason.to_string()),
+        ("QB_SYNC_CIRCUIT_COOLDOWN_SECS", cooldown_secs.to_string()),
+    ];
+    for command in commands {
+        if let Err(e) = run(command, &env, Duration::from_secs(DEFAULT_TIMEOUT_SECS)).await {
+            log::error!("[HOOK] circuit_open '{}' failed: {:#}", command, e);
+        }
+    }
+}
+
+// QB_SYNC_STAGE, QB_SYNC_RUN_ID and, after the cycle, QB_SYNC_STATUS (ok / failed) and the counts
+fn environment(stage: Stage, summary: &RunSummary) -> Vec<(&'static str, String)> {
+    let mut env = vec![("QB_SYNC_STAGE", stage.name().to_string())];
+    if let Some(run_id) = &summary.run_id {
+        env.push(("QB_SYNC_RUN_ID", run_id.clone()));
+    }
+    if stage == Stage::PostSync {
+        let (ok, failed, skipped) = summary.counts();
+        env.push(("QB_SYNC_STATUS", if failed == 0 { "ok" } else { "failed" }.to_string()));
+        env.push(("QB_SYNC_OK", ok.to_string()));
+        env.push(("QB_SYNC_FAILED", failed.to_string()));
+        env.push(("QB_SYNC_SKIPPED", skipped.to_string()));
+    }
+    env
+}
+
+async fn run_all(hooks: &HooksConfig, stage: Stage, commands: &[String], env: Vec<(&'static str, String)>) -> Result<()> {
+    let timeout = Duration::from_secs(hooks.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS).max(1));
+    let mut failed = 0;
+    for command in commands {
+        if let Err(e) = run(command, &env, timeout).await {
+            log::error!("[HOOK] {} '{}' failed: {:#}", stage.name(), command, e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!("{} of {} {} command(s) failed", failed, commands.len(), stage.name());
+    }
+    Ok(())
+}
+
+async fn run(command: &str, env: &[(&'static str, String)], timeout: Duration) -> Result<()> {
+    log::info!("[HOOK] Running '{}'", command);
+    let mut shell = shell(command);
+    shell.envs(env.iter().map(|(k, v)| (*k, v.as_str())))
+        .stdin(std::process::Stdio::null())
+        // the child is killed if the timeout drops its future
+        .kill_on_drop(true);
+    let output = tokio::time::timeout(timeout, shell.output())
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out after {}s and was stopped", timeout.as_secs()))?
+        .context("could not be started")?;
+    for line in String::from_utf8_lossy(&output.stdout).lines().filter(|l| !l.trim().is_empty()) {
+        log::info!("[HOOK]   {}", line);
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines().filter(|l| !l.trim().is_empty()) {
+        log::warn!("[HOOK]   {}", line);
+    }
+    if !output.status.success() {
+        bail!("exited with {}", output.status);
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    // raw_arg keeps cmd's own quoting rules instead of Rust's argv escaping
+    cmd.arg("/C").raw_arg(command);
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
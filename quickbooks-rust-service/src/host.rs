@@ -0,0 +1,217 @@
+// Which QuickBooks product and company file a session is talking to
+//
+// Queried once when a session opens: the product, edition and country are logged, and
+// [quickbooks] require_edition / require_company stop the run before anything is written when
+// the wrong QuickBooks or the wrong company file is open. The company details can also be written
+// to the dashboard ([company_info]), and the fiscal year start feeds the *-fiscal-year dates.
+//
+// The company preferences come back in the same request: whether multicurrency is on (list blocks
+// leave out their currency columns when it isn't), the basis summary reports use, and class
+// tracking / account numbers. They are shown in the run summary and under [company_info].
+//
+// A company file can also be restricted: an Accountant's Copy working file (.qba), or the client's
+// own file while an Accountant's Copy is out, where nothing dated on or before the dividing date can
+// change. Queries still work, but some behave differently and add / modify requests are refused, so
+// the restriction is logged and [quickbooks] restricted_mode decides what else happens.
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+use crate::config::{QuickBooksConfig, RestrictedMode};
+use crate::qbxml;
+
+#[derive(Debug, Clone, Default)]
+pub struct HostInfo {
+    // e.g. "QuickBooks Enterprise Solutions: Manufacturing and Wholesale 23.0"
+    pub product_name: String,
+    pub major_version: String,
+    pub minor_version: String,
+    // US, CA or UK
+    pub country: String,
+    pub company_name: String,
+    pub legal_name: String,
+    // 1 = January; QuickBooks' FirstMonthFiscalYear
+    pub fiscal_year_start_month: u32,
+    // "SingleUser" or "MultiUser"; QuickBooks' QBFileMode
+    pub file_mode: String,
+    // CompanyRet's AccountantCopy: the dividing date of an Accountant's Copy made from this file
+    pub accountant_copy_dividing_date: Option<String>,
+    // filled in from the session, not the query
+    pub company_file: String,
+    // None when the preferences query failed, e.g. for lack of permission
+    pub preferences: Option<Preferences>,
+}
+
+/// The company preferences that change what the service writes or how it reads
+#[derive(Debug, Clone, Default)]
+pub struct Preferences {
+    pub multicurrency: bool,
+    // blank without multicurrency
+    pub home_currency: String,
+    // "Accrual" or "Cash": ReportsPreferences' SummaryReportBasis
+    pub report_basis: String,
+    pub class_tracking: bool,
+    pub account_numbers: bool,
+}
+
+/// Why the open company file can't be changed freely
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Restriction {
+    /// An Accountant's Copy working file, open in QuickBooks Accountant
+    AccountantCopy,
+    /// The client's file while an Accountant's Copy is out; holds the dividing date
+    DividingDate(String),
+}
+
+impl std::fmt::Display for Restriction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Restriction::AccountantCopy => write!(f, "the open company file is an Accountant's Copy working file"),
+            Restriction::DividingDate(date) => write!(
+                f, "an Accountant's Copy of the open company file is outstanding; transactions dated on or before {} can't be changed", date
+            ),
+        }
+    }
+}
+
+impl HostInfo {
+    /// How the open company file is restricted, if it is
+    pub fn restriction(&self) -> Option<Restriction> {
+        if self.company_file.to_ascii_lowercase().ends_with(".qba") {
+            return Some(Restriction::AccountantCopy);
+        }
+        self.accountant_copy_dividing_date.clone().map(Restriction::DividingDate)
+    }
+
+    /// Edition as named in the product name: Enterprise, Premier, Accountant or Pro
+    pub fn edition(&self) -> &'static str {
+        let product = self.product_name.to_ascii_lowercase();
+        ["Enterprise", "Premier", "Accountant", "Pro"].into_iter()
+            .find(|e| product.contains(&e.to_ascii_lowercase()))
+            .unwrap_or("Unknown")
+    }
+}
+
+pub fn request() -> String {
+    // preferences last: with continueOnError a failure there still leaves the host and company
+    qbxml::envelope("<HostQueryRq></HostQueryRq>\n<CompanyQueryRq></CompanyQueryRq>\n<PreferencesQueryRq></PreferencesQueryRq>")
+}
+
+pub fn parse(response_xml: &str) -> Result<HostInfo> {
+    let doc = qbxml::parse_document(response_xml)?;
+    let host = qbxml::find_response(&doc, "HostQueryRs")?
+        .children()
+        .find(|n| n.has_tag_name("HostRet"))
+        .context("HostQueryRs has no HostRet")?;
+    let company = qbxml::find_response(&doc, "CompanyQueryRs")?
+        .children()
+        .find(|n| n.has_tag_name("CompanyRet"));
+    Ok(HostInfo {
+        product_name: qbxml::child_text(host, "ProductName").unwrap_or_default(),
+        major_version: qbxml::child_text(host, "MajorVersion").unwrap_or_default(),
+        minor_version: qbxml::child_text(host, "MinorVersion").unwrap_or_default(),
+        country: qbxml::child_text(host, "Country").unwrap_or_default(),
+        company_name: company.and_then(|c| qbxml::child_text(c, "CompanyName")).unwrap_or_default(),
+        legal_name: company.and_then(|c| qbxml::child_text(c, "LegalCompanyName")).unwrap_or_default(),
+        fiscal_year_start_month: company
+            .and_then(|c| qbxml::child_text(c, "FirstMonthFiscalYear"))
+            .and_then(|m| m.parse::<chrono::Month>().ok())
+            .map(|m| m.number_from_month())
+            .unwrap_or(1),
+        file_mode: qbxml::child_text(host, "QBFileMode").unwrap_or_default(),
+        accountant_copy_dividing_date: company
+            .and_then(|c| c.children().find(|n| n.has_tag_name("AccountantCopy")))
+            .filter(|a| qbxml::child_text(*a, "AccountantCopyExists").is_some_and(|v| v.eq_ignore_ascii_case("true")))
+            .map(|a| qbxml::child_text(a, "DividingDate").unwrap_or_default()),
+        company_file: String::new(),
+        preferences: parse_preferences(&doc).unwrap_or_else(|e| {
+            log::warn!("[QBXML] Could not read the company preferences: {:#}", e);
+            None
+        }),
+    })
+}
+
+// An error status is an error; a response without PreferencesRet (older QuickBooks) is None
+fn parse_preferences(doc: &roxmltree::Document) -> Result<Option<Preferences>> {
+    let Some(prefs) = qbxml::find_response(doc, "PreferencesQueryRs")?
+        .children()
+        .find(|n| n.has_tag_name("PreferencesRet"))
+    else {
+        return Ok(None);
+    };
+    let section = |name: &str| prefs.children().find(|n| n.has_tag_name(name));
+    let flag = |section: Option<roxmltree::Node>, name: &str| {
+        section.and_then(|s| qbxml::child_text(s, name)).is_some_and(|v| v.eq_ignore_ascii_case("true"))
+    };
+    let (accounting, multicurrency) = (section("AccountingPreferences"), section("MultiCurrencyPreferences"));
+    Ok(Some(Preferences {
+        multicurrency: flag(multicurrency, "IsMultiCurrencyOn"),
+        home_currency: multicurrency.and_then(|m| qbxml::ref_full_name(m, "HomeCurrencyRef")).unwrap_or_default(),
+        report_basis: section("ReportsPreferences").and_then(|r| qbxml::child_text(r, "SummaryReportBasis")).unwrap_or_default(),
+        class_tracking: flag(accounting, "IsUsingClassTracking"),
+        account_numbers: flag(accounting, "IsUsingAccountNumbers"),
+    }))
+}
+
+/// The preferences as label / value pairs, for the run summary and [company_info]; empty when they
+/// couldn't be read
+pub fn settings(info: &HostInfo) -> Vec<(&'static str, String)> {
+    let Some(prefs) = &info.preferences else {
+        return Vec::new();
+    };
+    let on_off = |on: bool| if on { "On" } else { "Off" }.to_string();
+    let mut settings = vec![("Multicurrency", on_off(prefs.multicurrency))];
+    if prefs.multicurrency {
+        settings.push(("Home currency", prefs.home_currency.clone()));
+    }
+    settings.extend([
+        ("Report basis", prefs.report_basis.clone()),
+        ("Class tracking", on_off(prefs.class_tracking)),
+        ("Account numbers", on_off(prefs.account_numbers)),
+    ]);
+    settings
+}
+
+/// Fails when the open QuickBooks or company file isn't the one the config asks for, or is
+/// restricted and restricted_mode is "abort"
+pub fn check(info: &HostInfo, qb_cfg: &QuickBooksConfig) -> Result<()> {
+    if let Some(required) = &qb_cfg.require_edition {
+        // matched against the product name so "Enterprise Solutions" or "Premier Accountant" work too
+        if !info.product_name.to_ascii_lowercase().contains(&required.to_ascii_lowercase()) {
+            bail!(
+                "QuickBooks edition mismatch: require_edition is \"{}\" but the open QuickBooks is {} ({})",
+                required, info.edition(), info.product_name
+            );
+        }
+    }
+    if let Some(required) = &qb_cfg.require_company {
+        if !info.company_name.trim().eq_ignore_ascii_case(required.trim()) {
+            bail!(
+                "Wrong company file open: require_company is \"{}\" but QuickBooks has \"{}\" open",
+                required, info.company_name
+            );
+        }
+    }
+    if let Some(restriction) = info.restriction().filter(|_| qb_cfg.restricted_mode == RestrictedMode::Abort) {
+        bail!("Stopping because {} (restricted_mode = \"abort\")", restriction);
+    }
+    Ok(())
+}
+
+/// Label / value rows for the [company_info] header area
+pub fn rows(info: &HostInfo) -> Vec<Vec<Value>> {
+    let fiscal_start = chrono::Month::try_from(info.fiscal_year_start_month as u8)
+        .map(|m| m.name().to_string())
+        .unwrap_or_default();
+    [
+        ("Company", info.company_name.clone()),
+        ("Legal name", info.legal_name.clone()),
+        ("Fiscal year starts", fiscal_start),
+        ("Company file", info.company_file.clone()),
+        ("QuickBooks", info.product_name.clone()),
+    ]
+    .into_iter()
+    .chain(settings(info))
+    .map(|(label, value)| vec![Value::from(label), Value::from(value)])
+    .collect()
+}
@@ -0,0 +1,154 @@
+// `qb_sync import-blocks`: [[sync_blocks]] generated from a table of account name, spreadsheet id,
+// sheet and cell, read from a CSV file or from a tab of a spreadsheet (through the webapp)
+//
+// The rows are merged into the config file rather than replacing its blocks: a row whose cell some
+// block already writes updates that block's account, a new cell adds a block at the end, and every
+// other block, comment and setting in the file is left as it was.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::path::Path;
+use toml_edit::{value, ArrayOfTables, DocumentMut, Item, Table};
+
+use crate::a1;
+use crate::context::AppContext;
+
+// header names accepted for each column; without a header row the columns are in this order
+const ACCOUNT: &[&str] = &["account", "account_name", "account_full_name"];
+const SPREADSHEET: &[&str] = &["spreadsheet_id", "spreadsheet"];
+const SHEET: &[&str] = &["sheet", "sheet_name", "tab"];
+const CELL: &[&str] = &["cell", "cell_address"];
+const NAME: &[&str] = &["name", "block"];
+
+/// One sync block to create or update
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub account: String,
+    pub spreadsheet_id: String,
+    pub sheet: String,
+    pub cell: String,
+    pub name: Option<String>,
+}
+
+/// What a merge did to the config's blocks
+#[derive(Debug, Default)]
+pub struct Merged {
+    pub added: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+pub fn from_csv(path: &Path) -> Result<Vec<Row>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut records = Vec::new();
+    for record in reader.records() {
+        let record = record.with_context(|| format!("Failed to read {}", path.display()))?;
+        records.push(record.iter().map(str::to_string).collect());
+    }
+    rows(records).with_context(|| format!("In {}", path.display()))
+}
+
+/// The rows of the `tab` tab of a spreadsheet, as the sheet displays them
+pub async fn from_sheet(ctx: &AppContext<'_>, spreadsheet_id: &str, tab: &str) -> Result<Vec<Row>> {
+    let records = ctx.sheets(spreadsheet_id).read_sheet(tab).await
+        .with_context(|| format!("Failed to read tab '{}' of spreadsheet {}", tab, spreadsheet_id))?;
+    rows(records).with_context(|| format!("In tab '{}'", tab))
+}
+
+// Rows from a table whose first row may name the columns. Blank rows are skipped; a row missing its
+// account, spreadsheet, sheet or cell is an error, as are two rows for the same cell.
+fn rows(records: Vec<Vec<String>>) -> Result<Vec<Row>> {
+    let mut records = records.into_iter()
+        .map(|record| record.into_iter().map(|field| field.trim().to_string()).collect::<Vec<_>>())
+        .enumerate()
+        .filter(|(_, record)| record.iter().any(|field| !field.is_empty()));
+    let Some((first_index, first)) = records.next() else {
+        return Ok(Vec::new());
+    };
+    let column = |names: &[&str]| first.iter().position(|field| names.contains(&field.to_ascii_lowercase().as_str()));
+    let (columns, header) = match (column(ACCOUNT), column(SPREADSHEET), column(SHEET), column(CELL)) {
+        (Some(account), Some(spreadsheet), Some(sheet), Some(cell)) => ([account, spreadsheet, sheet, cell], true),
+        (None, None, None, None) => ([0, 1, 2, 3], false),
+        _ => bail!("The header row needs account, spreadsheet_id, sheet and cell columns"),
+    };
+    let name = if header { column(NAME) } else { None };
+
+    let mut rows = Vec::new();
+    let mut cells = HashSet::new();
+    let first = (!header).then(|| (first_index, first.clone()));
+    for (i, record) in first.into_iter().chain(records) {
+        let line = i + 1;
+        let field = |index: usize| record.get(index).cloned().unwrap_or_default();
+        let [account, spreadsheet_id, sheet, cell] = columns.map(field);
+        if account.is_empty() || spreadsheet_id.is_empty() || sheet.is_empty() || cell.is_empty() {
+            bail!("Row {}: account, spreadsheet id, sheet and cell are all required", line);
+        }
+        let cell = a1::parse_cell(&cell).with_context(|| format!("Row {}", line))?.to_string();
+        if !cells.insert((spreadsheet_id.clone(), sheet.clone(), cell.clone())) {
+            bail!("Row {}: {}!{} of spreadsheet {} appears twice", line, sheet, cell, spreadsheet_id);
+        }
+        let name = name.map(field).filter(|n| !n.is_empty());
+        rows.push(Row { account, spreadsheet_id, sheet, cell, name });
+    }
+    Ok(rows)
+}
+
+/// Merges `rows` into the config text: a row updates the [[sync_blocks]] entry writing its cell, or
+/// adds one at the end. Returns the new text.
+pub fn merge(text: &str, rows: &[Row]) -> Result<(String, Merged)> {
+    let mut doc: DocumentMut = text.parse().context("Failed to parse the config file")?;
+    let blocks = doc.entry("sync_blocks")
+        .or_insert_with(|| Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .context("sync_blocks must be written as [[sync_blocks]] tables to import into it")?;
+
+    let mut merged = Merged::default();
+    for row in rows {
+        let existing = blocks.iter_mut().find(|block| {
+            let text = |key: &str| block.get(key).and_then(|item| item.as_str()).unwrap_or_default().trim().to_string();
+            let cell = a1::parse_cell(&text("cell_address")).map(|c| c.to_string()).unwrap_or_default();
+            text("spreadsheet_id") == row.spreadsheet_id && text("sheet_name") == row.sheet && cell == row.cell
+        });
+        match existing {
+            Some(block) if block.get("account_full_name").and_then(|item| item.as_str()) == Some(row.account.as_str()) => {
+                merged.unchanged += 1;
+            }
+            Some(block) => {
+                block["account_full_name"] = value(row.account.as_str());
+                if let Some(name) = &row.name {
+                    block["name"] = value(name.as_str());
+                }
+                merged.updated += 1;
+            }
+            None => {
+                let mut block = Table::new();
+                block.decor_mut().set_prefix("\n");
+                if let Some(name) = &row.name {
+                    block["name"] = value(name.as_str());
+                }
+                block["spreadsheet_id"] = value(row.spreadsheet_id.as_str());
+                block["account_full_name"] = value(row.account.as_str());
+                block["sheet_name"] = value(row.sheet.as_str());
+                block["cell_address"] = value(row.cell.as_str());
+                blocks.push(block);
+                merged.added += 1;
+            }
+        }
+    }
+    Ok((doc.to_string(), merged))
+}
+
+/// Replaces the config file with `text`, keeping the previous version next to it as <file>.bak
+pub fn save(path: &Path, text: &str) -> Result<()> {
+    let backup = path.with_extension("toml.bak");
+    std::fs::copy(path, &backup).with_context(|| format!("Failed to back up {} to {}", path.display(), backup.display()))?;
+    let tmp = path.with_extension("toml.tmp");
+    std::fs::write(&tmp, text).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
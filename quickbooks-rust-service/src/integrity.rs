@@ -0,0 +1,66 @@
+// [quickbooks] integrity_check: a quick look at the company file before a cycle writes anything,
+// so numbers from a damaged file never reach the dashboard
+//
+// One small request (the company record and the first few accounts) whose answer should always be
+// clean. An error, a status QuickBooks uses when it can't read every record it matched, a company
+// without a name or an account whose balance isn't a number all stop the cycle's blocks. Verify
+// Data can't be run through the SDK; the message says to run it in QuickBooks.
+
+use anyhow::{bail, Result};
+
+use crate::amounts::NumberFormat;
+use crate::qb_errors;
+use crate::qbxml;
+
+// enough accounts to touch the account list without turning the check into a full query
+const SAMPLE_ACCOUNTS: u32 = 25;
+
+const VERIFY_HINT: &str = "run File > Utilities > Verify Data in QuickBooks (and Rebuild Data if it finds problems) before syncing again";
+
+pub fn request() -> String {
+    qbxml::envelope(&format!(
+        "<CompanyQueryRq></CompanyQueryRq>\n<AccountQueryRq>\n<MaxReturned>{}</MaxReturned>\n</AccountQueryRq>",
+        SAMPLE_ACCOUNTS
+    ))
+}
+
+// 500-599: the query wasn't fully completed, some matched records couldn't be returned;
+// 9000 and up: QuickBooks' internal errors
+fn suggests_damage(code: u32) -> bool {
+    (500..600).contains(&code) || code >= 9000
+}
+
+/// Checks the response to `request()`; the error says what looked wrong
+pub fn check(response_xml: &str, format: NumberFormat) -> Result<()> {
+    let doc = qbxml::parse_document(response_xml)?;
+    for rs_name in ["CompanyQueryRs", "AccountQueryRs"] {
+        let rs = qbxml::find_response(&doc, rs_name)?;
+        let code = rs.attribute("statusCode").unwrap_or("0");
+        if code.trim().parse().is_ok_and(suggests_damage) {
+            bail!(
+                "QuickBooks answered {} with status {}: {}{}; the company file may be damaged, {}",
+                rs_name,
+                code,
+                rs.attribute("statusMessage").unwrap_or("<no message>"),
+                qb_errors::status_code_suffix(code),
+                VERIFY_HINT
+            );
+        }
+    }
+
+    let company = doc.descendants().find(|n| n.has_tag_name("CompanyRet"));
+    if company.and_then(|c| qbxml::child_text(c, "CompanyName")).filter(|name| !name.is_empty()).is_none() {
+        bail!("QuickBooks returned no company name; the company file may be damaged, {}", VERIFY_HINT);
+    }
+    for account in doc.descendants().filter(|n| n.has_tag_name("AccountRet")) {
+        let Some(name) = qbxml::child_text(account, "FullName").filter(|n| !n.is_empty()) else {
+            bail!("QuickBooks returned an account without a name; the company file may be damaged, {}", VERIFY_HINT);
+        };
+        if let Some(balance) = qbxml::child_text(account, "Balance") {
+            if format.parse(&balance).is_none() {
+                bail!("Account '{}' has the balance '{}', which isn't an amount; the company file may be damaged, {}", name, balance, VERIFY_HINT);
+            }
+        }
+    }
+    Ok(())
+}
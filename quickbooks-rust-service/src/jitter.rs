@@ -0,0 +1,37 @@
+// [schedule] jitter_secs and spread_secs: keep QuickBooks and Sheets from being hit all at once
+//
+// jitter_secs pushes each daemon cycle back by a random delay up to that long, so machines (or
+// services) started on the same interval drift apart instead of all firing at the top of the hour.
+// spread_secs staggers the blocks that run in parallel within a cycle evenly over that window
+// rather than starting every one of them at the same instant.
+
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::random::Rng;
+
+/// A random delay between zero and `max_secs`, to the millisecond
+pub fn random_delay(max_secs: u64) -> Duration {
+    if max_secs == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_millis(Rng::from_clock().up_to(max_secs.saturating_mul(1000)))
+}
+
+/// How long the `index`th of `count` parallel blocks waits before it starts
+pub fn stagger(config: &Config, index: usize, count: usize) -> Duration {
+    let spread_secs = config.schedule.as_ref().and_then(|s| s.spread_secs).unwrap_or(0);
+    if spread_secs == 0 || count < 2 {
+        return Duration::ZERO;
+    }
+    // the first block starts straight away and the last at the end of the window
+    Duration::from_secs(spread_secs) * index as u32 / (count - 1) as u32
+}
+
+/// Waits out a block's stagger
+pub async fn stagger_start(config: &Config, index: usize, count: usize) {
+    let delay = stagger(config, index, count);
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+}
@@ -0,0 +1,74 @@
+// [kpis]: summary ratios worked out here from [account_classification] categories and written to
+// their own cells, instead of in-sheet formulas that break when someone inserts a row
+//
+// Each side of a ratio is the total of the accounts in the categories listed for it, every balance
+// sign-normalized first so assets and liabilities both read positive. Runway divides cash by
+// monthly_burn, an expression over the values other blocks publish (e.g. a Profit & Loss report
+// block publishing its expenses). A ratio whose divisor comes to zero is left out for the cycle
+// rather than written as an error value; every KPI worked out is published under its own name for
+// computed blocks.
+
+use anyhow::{bail, Result};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+use crate::accounts::AccountIndex;
+use crate::amounts;
+use crate::config::KpisConfig;
+
+pub const DEFAULT_DECIMALS: u32 = 2;
+
+/// Every KPI [kpis] cells can name
+pub const KPIS: &[&str] = &["current_ratio", "quick_ratio", "working_capital", "cash", "runway_months"];
+
+/// The category groups a KPI is worked out from, by their [kpis] key
+pub fn groups(kpi: &str) -> &'static [&'static str] {
+    match kpi {
+        "current_ratio" | "working_capital" => &["current_assets", "current_liabilities"],
+        "quick_ratio" => &["quick_assets", "current_liabilities"],
+        "cash" | "runway_months" => &["cash"],
+        _ => &[],
+    }
+}
+
+/// Checks a KPI name from [kpis] cells
+pub fn kpi(name: &str) -> Result<&'static str> {
+    match KPIS.iter().find(|k| **k == name.trim()) {
+        Some(k) => Ok(k),
+        None => bail!("Unknown KPI '{}'; expected one of {}", name, KPIS.join(", ")),
+    }
+}
+
+// the normalized total of every account in one of `categories`
+fn total(accounts: &AccountIndex, categories: &[String]) -> Decimal {
+    accounts.values()
+        .filter(|a| categories.iter().any(|c| c.eq_ignore_ascii_case(&a.category)))
+        .map(|a| a.normalized_balance())
+        .sum()
+}
+
+fn ratio(numerator: Decimal, divisor: Decimal) -> Option<Decimal> {
+    (!divisor.is_zero()).then(|| numerator / divisor)
+}
+
+/// Every KPI in `cfg.cells`, rounded; None for one that can't be worked out this cycle: a zero
+/// divisor, or runway without a positive burn
+pub fn compute(accounts: &AccountIndex, cfg: &KpisConfig, monthly_burn: Option<Decimal>) -> BTreeMap<String, Option<Decimal>> {
+    let decimals = cfg.decimals.unwrap_or(DEFAULT_DECIMALS);
+    let current_assets = total(accounts, &cfg.current_assets);
+    let current_liabilities = total(accounts, &cfg.current_liabilities);
+    let cash = total(accounts, &cfg.cash);
+    cfg.cells.keys()
+        .map(|name| {
+            let value = match name.as_str() {
+                "current_ratio" => ratio(current_assets, current_liabilities),
+                "quick_ratio" => ratio(total(accounts, &cfg.quick_assets), current_liabilities),
+                "working_capital" => Some(current_assets - current_liabilities),
+                "cash" => Some(cash),
+                "runway_months" => monthly_burn.filter(|burn| *burn > Decimal::ZERO).and_then(|burn| ratio(cash, burn)),
+                _ => None,
+            };
+            (name.clone(), value.map(|v| amounts::round(v, decimals)))
+        })
+        .collect()
+}
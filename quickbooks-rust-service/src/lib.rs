@@ -2,5 +2,54 @@
 // Using SafeVariant wrappers for robust VARIANT/COM handling
 
 pub mod file_mode;
+pub mod a1;
+pub mod amounts;
 pub mod config;
+pub mod expr;
+pub mod secrets;
 pub mod qbxml_safe;
+pub mod qb_backend;
+pub mod circuit;
+pub mod response_cache;
+pub mod qb_errors;
+pub mod integrity;
+pub mod redact;
+pub mod names;
+pub mod scripting;
+
+// parsing, transforms and destinations, for the fixture tests under tests/
+pub mod qbxml;
+pub mod extract;
+pub mod raw_qbxml;
+pub mod accounts;
+pub mod kpis;
+pub mod run_window;
+pub mod reports;
+pub mod transactions;
+pub mod deleted_txns;
+pub mod aging;
+pub mod lists;
+pub mod dates;
+pub mod template;
+pub mod destinations;
+pub mod reconcile;
+pub mod context;
+pub mod offline_queue;
+pub mod google_sheets;
+pub mod graph;
+pub mod history;
+pub mod quota;
+pub mod sheet_faults;
+pub mod odbc;
+pub mod capture;
+pub mod signing;
+pub mod s3;
+pub mod correlation;
+pub mod host;
+
+// the daemon's status page and the request queue behind its Run button
+pub mod summary;
+pub mod progress;
+pub mod resume;
+pub mod local_api;
+pub mod dashboard;
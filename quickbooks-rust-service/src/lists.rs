@@ -0,0 +1,725 @@
+// Customer (balances or contact details), vendor, other name, invoice, pipeline (estimate / sales
+// order), purchase order, inventory (per item and per site / bin) and chart-of-accounts exports,
+// optionally limited to records modified since a given time.
+// Incremental blocks pass the start of their last successful run so only changed records are
+// re-exported
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDate};
+use roxmltree::Node;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::a1;
+use crate::amounts::{self, NumberFormat};
+use crate::config::{AccountClassificationConfig, ListBlockConfig, ListType};
+use crate::dates;
+use crate::qbxml;
+
+// how a column is read from a <Type>Ret element, or from one of its lines for line-item exports
+enum Field {
+    Text(&'static str),
+    Ref(&'static str),
+    // a child of an aggregate, e.g. BillAddress / City
+    Part(&'static str, &'static str),
+    // the first of several alternative refs present, e.g. ItemInventoryRef or ItemInventoryAssemblyRef
+    AnyRef(&'static [&'static str]),
+    Number(&'static str),
+    // a number that is money, which a block's rounding applies to (quantities, rates and costs
+    // per unit are Number and keep their places)
+    Amount(&'static str),
+    // "true"/"false" elements such as IsVendorEligibleFor1099, written as a checkbox-friendly boolean
+    Flag(&'static str),
+    // a list element's name indented by its Sublevel, so a parent's children sit under it
+    Indented(&'static str),
+    // line-item exports: fields of the parent <Type>Ret repeated on every line
+    HeaderText(&'static str),
+    HeaderRef(&'static str),
+    // purchase order lines: Quantity - ReceivedQuantity
+    OpenQuantity,
+    // a line's own ref, else the parent's (a PO line's class defaults to the PO's)
+    LineOrHeaderRef(&'static str),
+}
+
+impl Field {
+    fn value(&self, header: Node, record: Node, format: NumberFormat) -> Value {
+        match *self {
+            Field::Text(name) => Value::from(qbxml::child_text(record, name).unwrap_or_default()),
+            Field::Ref(name) => Value::from(qbxml::ref_full_name(record, name).unwrap_or_default()),
+            Field::Part(parent, name) => Value::from(
+                record.children().find(|n| n.has_tag_name(parent)).and_then(|p| qbxml::child_text(p, name)).unwrap_or_default()
+            ),
+            Field::AnyRef(names) => Value::from(names.iter().find_map(|name| qbxml::ref_full_name(record, name)).unwrap_or_default()),
+            Field::Number(name) | Field::Amount(name) => amounts::json(number(record, name, format)),
+            Field::Flag(name) => Value::from(qbxml::child_text(record, name).as_deref() == Some("true")),
+            Field::Indented(name) => {
+                let level = qbxml::child_text(record, "Sublevel").and_then(|l| l.parse::<usize>().ok()).unwrap_or(0);
+                Value::from(format!("{}{}", INDENT.repeat(level), qbxml::child_text(record, name).unwrap_or_default()))
+            }
+            Field::HeaderText(name) => Value::from(qbxml::child_text(header, name).unwrap_or_default()),
+            Field::HeaderRef(name) => Value::from(qbxml::ref_full_name(header, name).unwrap_or_default()),
+            Field::OpenQuantity => amounts::json(open_quantity(record, format)),
+            Field::LineOrHeaderRef(name) => Value::from(
+                qbxml::ref_full_name(record, name).or_else(|| qbxml::ref_full_name(header, name)).unwrap_or_default()
+            ),
+        }
+    }
+}
+
+// per Sublevel, in front of Field::Indented names
+const INDENT: &str = "    ";
+
+fn number(node: Node, name: &str, format: NumberFormat) -> Decimal {
+    qbxml::child_text(node, name).and_then(|t| format.parse(&t)).unwrap_or_default()
+}
+
+fn open_quantity(line: Node, format: NumberFormat) -> Decimal {
+    (number(line, "Quantity", format) - number(line, "ReceivedQuantity", format)).max(Decimal::ZERO)
+}
+
+struct Column {
+    header: &'static str,
+    field: Field,
+}
+
+const fn col(header: &'static str, field: Field) -> Column {
+    Column { header, field }
+}
+
+const CUSTOMER_COLUMNS: &[Column] = &[
+    col("Name", Field::Text("FullName")),
+    col("Email", Field::Text("Email")),
+    col("Phone", Field::Text("Phone")),
+    col("Balance", Field::Amount("TotalBalance")),
+    col("Modified", Field::Text("TimeModified")),
+];
+
+// the Customer:Job tree in QuickBooks' order, which puts every job right under its customer (or
+// parent job); Full Name keeps the rows apart once the sheet is sorted or filtered
+const CUSTOMER_JOB_COLUMNS: &[Column] = &[
+    col("Name", Field::Indented("Name")),
+    col("Full Name", Field::Text("FullName")),
+    col("Parent", Field::Ref("ParentRef")),
+    col("Level", Field::Number("Sublevel")),
+    col("Job Status", Field::Text("JobStatus")),
+    col("Job Type", Field::Ref("JobTypeRef")),
+    col("Start Date", Field::Text("JobStartDate")),
+    col("Projected End", Field::Text("JobProjectedEndDate")),
+    col("End Date", Field::Text("JobEndDate")),
+    col("Balance", Field::Amount("TotalBalance")),
+];
+
+// jobs (Parent:Job) come back as customers too; Name is the full name so they stay apart
+const CUSTOMER_CONTACT_COLUMNS: &[Column] = &[
+    col("Name", Field::Text("FullName")),
+    col("Company", Field::Text("CompanyName")),
+    col("Contact", Field::Text("Contact")),
+    col("Email", Field::Text("Email")),
+    col("Cc", Field::Text("Cc")),
+    col("Phone", Field::Text("Phone")),
+    col("Alt Phone", Field::Text("AltPhone")),
+    col("Fax", Field::Text("Fax")),
+    col("Address 1", Field::Part("BillAddress", "Addr1")),
+    col("Address 2", Field::Part("BillAddress", "Addr2")),
+    col("Address 3", Field::Part("BillAddress", "Addr3")),
+    col("Address 4", Field::Part("BillAddress", "Addr4")),
+    col("Address 5", Field::Part("BillAddress", "Addr5")),
+    col("City", Field::Part("BillAddress", "City")),
+    col("State", Field::Part("BillAddress", "State")),
+    col("Postal Code", Field::Part("BillAddress", "PostalCode")),
+    col("Country", Field::Part("BillAddress", "Country")),
+    col("Modified", Field::Text("TimeModified")),
+];
+
+const INVOICE_COLUMNS: &[Column] = &[
+    col("Number", Field::Text("RefNumber")),
+    col("Customer", Field::Ref("CustomerRef")),
+    col("Date", Field::Text("TxnDate")),
+    col("Due Date", Field::Text("DueDate")),
+    col("Amount", Field::Amount("Subtotal")),
+    col("Balance Due", Field::Amount("BalanceRemaining")),
+    col("Modified", Field::Text("TimeModified")),
+];
+
+// include_line_items: each invoice row is followed by one row per line with these columns filled
+// in and only the invoice number (the key column) and class repeated, for margin analysis
+const INVOICE_LINE_COLUMNS: &[Column] = &[
+    col("Item", Field::Ref("ItemRef")),
+    col("Line Description", Field::Text("Desc")),
+    col("Quantity", Field::Number("Quantity")),
+    col("Rate", Field::Number("Rate")),
+    col("Line Amount", Field::Amount("Amount")),
+];
+
+// the sales pipeline: what's quoted / ordered, for whom, and when it should close
+const ESTIMATE_COLUMNS: &[Column] = &[
+    col("Number", Field::Text("RefNumber")),
+    col("Customer", Field::Ref("CustomerRef")),
+    col("Date", Field::Text("TxnDate")),
+    col("Amount", Field::Amount("TotalAmount")),
+    col("Expected Close", Field::Text("DueDate")),
+    col("Sales Rep", Field::Ref("SalesRepRef")),
+];
+
+const SALES_ORDER_COLUMNS: &[Column] = &[
+    col("Number", Field::Text("RefNumber")),
+    col("Customer", Field::Ref("CustomerRef")),
+    col("Date", Field::Text("TxnDate")),
+    col("Amount", Field::Amount("TotalAmount")),
+    col("Expected Close", Field::Text("ShipDate")),
+    col("Sales Rep", Field::Ref("SalesRepRef")),
+];
+
+// one row per PO line: what was ordered, what has arrived, what is still outstanding
+const PURCHASE_ORDER_COLUMNS: &[Column] = &[
+    col("PO Number", Field::HeaderText("RefNumber")),
+    col("Vendor", Field::HeaderRef("VendorRef")),
+    col("Date", Field::HeaderText("TxnDate")),
+    col("Expected", Field::HeaderText("ExpectedDate")),
+    col("Item", Field::Ref("ItemRef")),
+    col("Description", Field::Text("Desc")),
+    col("Ordered", Field::Number("Quantity")),
+    col("Received", Field::Number("ReceivedQuantity")),
+    col("Open", Field::OpenQuantity),
+    col("Rate", Field::Number("Rate")),
+    col("Amount", Field::Amount("Amount")),
+];
+
+// include_class / route_by_class: appended last, after any line-item columns, so sheets laid out
+// before the column existed keep their columns where they were
+const CLASS_COLUMN: Column = col("Class", Field::Ref("ClassRef"));
+const LINE_CLASS_COLUMN: Column = col("Class", Field::LineOrHeaderRef("ClassRef"));
+
+const ACCOUNT_COLUMNS: &[Column] = &[
+    col("Name", Field::Text("FullName")),
+    col("Number", Field::Text("AccountNumber")),
+    col("Type", Field::Text("AccountType")),
+    col("Description", Field::Text("Desc")),
+    col("Bank Number", Field::Text("BankNumber")),
+    col("Balance", Field::Amount("Balance")),
+];
+
+// include_currency: appended to ACCOUNT_COLUMNS, filled from the CurrencyQueryRs sent alongside
+const CURRENCY_HEADERS: &[&str] = &["Currency", "Exchange Rate", "Home Balance"];
+const HOME_BALANCE_HEADER: &str = "Home Balance";
+
+// include_category: the [account_classification] category, after everything else
+const CATEGORY_HEADER: &str = "Category";
+
+// year-end 1099 prep: who is eligible and the tax id to file under
+const VENDOR_COLUMNS: &[Column] = &[
+    col("Name", Field::Text("Name")),
+    col("Company", Field::Text("CompanyName")),
+    col("Email", Field::Text("Email")),
+    col("Phone", Field::Text("Phone")),
+    col("Tax ID", Field::Text("VendorTaxIdent")),
+    col("1099 Eligible", Field::Flag("IsVendorEligibleFor1099")),
+    col("Balance", Field::Amount("Balance")),
+];
+
+const OTHER_NAME_COLUMNS: &[Column] = &[
+    col("Name", Field::Text("Name")),
+    col("Company", Field::Text("CompanyName")),
+    col("Email", Field::Text("Email")),
+    col("Phone", Field::Text("Phone")),
+];
+
+const ITEM_INVENTORY_COLUMNS: &[Column] = &[
+    col("Name", Field::Text("FullName")),
+    col("Description", Field::Text("SalesDesc")),
+    col("On Hand", Field::Number("QuantityOnHand")),
+    col("On Order", Field::Number("QuantityOnOrder")),
+    col("On Sales Order", Field::Number("QuantityOnSalesOrder")),
+    col("Reorder Point", Field::Number("ReorderPoint")),
+    col("Average Cost", Field::Number("AverageCost")),
+    col("Modified", Field::Text("TimeModified")),
+];
+
+// Enterprise Advanced Inventory: one row per item per site, or per bin when bins are in use
+const ITEM_SITES_COLUMNS: &[Column] = &[
+    col("Item", Field::AnyRef(&["ItemInventoryRef", "ItemInventoryAssemblyRef"])),
+    col("Site", Field::Ref("InventorySiteRef")),
+    col("Bin", Field::Ref("InventorySiteLocationRef")),
+    col("On Hand", Field::Number("QuantityOnHand")),
+    col("On Purchase Orders", Field::Number("QuantityOnPurchaseOrders")),
+    col("On Sales Orders", Field::Number("QuantityOnSalesOrders")),
+    col("To Be Built", Field::Number("QuantityToBeBuilt")),
+    col("Reorder Level", Field::Number("ReorderLevel")),
+];
+
+impl ListType {
+    fn columns(&self) -> &'static [Column] {
+        match self {
+            ListType::Customer => CUSTOMER_COLUMNS,
+            ListType::CustomerContacts => CUSTOMER_CONTACT_COLUMNS,
+            ListType::CustomerJobs => CUSTOMER_JOB_COLUMNS,
+            ListType::Invoice => INVOICE_COLUMNS,
+            ListType::Estimate => ESTIMATE_COLUMNS,
+            ListType::SalesOrder => SALES_ORDER_COLUMNS,
+            ListType::PurchaseOrder => PURCHASE_ORDER_COLUMNS,
+            ListType::Account => ACCOUNT_COLUMNS,
+            ListType::Vendor => VENDOR_COLUMNS,
+            ListType::OtherName => OTHER_NAME_COLUMNS,
+            ListType::ItemInventory => ITEM_INVENTORY_COLUMNS,
+            ListType::ItemSites => ITEM_SITES_COLUMNS,
+        }
+    }
+
+    // ItemSitesQueryRq has no modified-date filter
+    fn has_modified_filter(&self) -> bool {
+        !matches!(self, ListType::ItemSites)
+    }
+
+    // exports with one row per line item instead of one per record
+    fn line_element(&self) -> Option<&'static str> {
+        match self {
+            ListType::PurchaseOrder => Some("PurchaseOrderLineRet"),
+            _ => None,
+        }
+    }
+
+    // record types that can be followed by their line items (include_line_items)
+    fn child_lines(&self) -> Option<(&'static str, &'static [Column])> {
+        match self {
+            ListType::Invoice => Some(("InvoiceLineRet", INVOICE_LINE_COLUMNS)),
+            _ => None,
+        }
+    }
+
+    // record types with a ClassRef; a PO line's class defaults to the PO's
+    fn class_column(&self) -> Option<&'static Column> {
+        match self {
+            ListType::Invoice | ListType::Estimate | ListType::SalesOrder => Some(&CLASS_COLUMN),
+            ListType::PurchaseOrder => Some(&LINE_CLASS_COLUMN),
+            _ => None,
+        }
+    }
+
+    // neither query has a status filter, so closed records are dropped while parsing
+    fn is_open(&self, ret: Node) -> bool {
+        let flag = |name| qbxml::child_text(ret, name).as_deref() == Some("true");
+        match self {
+            ListType::Estimate => qbxml::child_text(ret, "IsActive").as_deref() != Some("false"),
+            ListType::SalesOrder => !flag("IsFullyInvoiced") && !flag("IsManuallyClosed"),
+            ListType::PurchaseOrder => !flag("IsFullyReceived") && !flag("IsManuallyClosed"),
+            _ => true,
+        }
+    }
+
+    pub fn headers(&self, line_items: bool, currency: bool, category: bool, class: bool) -> Vec<&'static str> {
+        let child_columns = self.child_lines().filter(|_| line_items).map(|(_, columns)| columns).unwrap_or(&[]);
+        let mut headers: Vec<&'static str> = self.columns().iter().chain(child_columns).map(|c| c.header).collect();
+        if currency {
+            headers.extend(CURRENCY_HEADERS);
+        }
+        if category {
+            headers.push(CATEGORY_HEADER);
+        }
+        if let Some(column) = self.class_column().filter(|_| class) {
+            headers.push(column.header);
+        }
+        headers
+    }
+}
+
+/// Builds the query; `since` (last successful run) takes precedence over the block's modified_from.
+/// Relative dates resolve against `today`.
+pub fn query_request(block: &ListBlockConfig, since: Option<DateTime<Local>>, today: NaiveDate) -> Result<String> {
+    let resolve = |expr: &String| dates::resolve_qbxml_datetime(expr, today);
+    let from = match since {
+        Some(since) => Some(qbxml::format_datetime(&since)),
+        None => block.modified_from.as_ref().map(resolve).transpose()?,
+    };
+    let to = block.modified_to.as_ref().map(resolve).transpose()?;
+
+    let mut filter = String::new();
+    if let Some(from) = &from {
+        filter.push_str(&format!("<FromModifiedDate>{}</FromModifiedDate>", from));
+    }
+    if let Some(to) = &to {
+        filter.push_str(&format!("<ToModifiedDate>{}</ToModifiedDate>", to));
+    }
+
+    let element = block.list_type.element();
+    if block.line_items() && block.list_type.child_lines().is_none() {
+        bail!("include_line_items only applies to Invoice exports, not {}", element);
+    }
+    if block.currency() && block.list_type != ListType::Account {
+        bail!("include_currency only applies to Account exports, not {}", element);
+    }
+    if block.category() && block.list_type != ListType::Account {
+        bail!("include_category only applies to Account exports, not {}", element);
+    }
+    if block.class() && block.list_type.class_column().is_none() {
+        bail!("{} exports have no Class column", element);
+    }
+    if !block.list_type.has_modified_filter() && (!filter.is_empty() || block.incremental.unwrap_or(false)) {
+        bail!("{} exports can't be limited by modified date; remove modified_from / modified_to / incremental", element);
+    }
+    let mut body = format!("      <{}QueryRq>\n", element);
+    if !filter.is_empty() {
+        // list queries take the dates directly, transaction queries wrap them in a range filter
+        if block.list_type.is_transaction() {
+            body.push_str(&format!("        <ModifiedDateRangeFilter>{}</ModifiedDateRangeFilter>\n", filter));
+        } else {
+            body.push_str(&format!("        {}\n", filter));
+        }
+    }
+    if block.list_type.line_element().is_some() || block.line_items() {
+        body.push_str("        <IncludeLineItems>true</IncludeLineItems>\n");
+    }
+    body.push_str(&format!("      </{}QueryRq>", element));
+    if block.currency() {
+        // AccountRet has no exchange rate of its own; every currency's comes back in the same response
+        body.push_str("\n      <CurrencyQueryRq>\n        <ActiveStatus>All</ActiveStatus>\n      </CurrencyQueryRq>");
+    }
+    Ok(qbxml::envelope(&body))
+}
+
+// QODBC names a column after the qbXML element, a ref by its FullName and an aggregate's part with
+// the aggregate in front, e.g. CustomerRefFullName or BillAddressCity
+fn odbc_column(field: &Field) -> Option<String> {
+    match *field {
+        Field::Text(name) | Field::Number(name) | Field::Amount(name) | Field::Flag(name) => Some(name.to_string()),
+        Field::Ref(name) => Some(format!("{}FullName", name)),
+        Field::Part(parent, name) => Some(format!("{}{}", parent, name)),
+        _ => None,
+    }
+}
+
+/// Err when the block asks for something the ODBC backend can't produce the same way
+pub fn check_odbc(block: &ListBlockConfig) -> Result<()> {
+    let element = block.list_type.element();
+    if block.list_type.line_element().is_some() || block.list_type.columns().iter().any(|c| odbc_column(&c.field).is_none()) {
+        bail!("backend = \"odbc\" doesn't support {:?} exports", block.list_type);
+    }
+    if block.line_items() || block.currency() || block.category() || block.class() {
+        bail!("backend = \"odbc\" can't add line items, currency, category or class columns to {} exports", element);
+    }
+    Ok(())
+}
+
+/// The QODBC counterpart of query_request: the same columns in the same order, the same modified
+/// filter and, with open_only, the same closed records left out
+pub fn odbc_query(block: &ListBlockConfig, since: Option<DateTime<Local>>, today: NaiveDate) -> Result<String> {
+    check_odbc(block)?;
+    let resolve = |expr: &String| dates::resolve_qbxml_datetime(expr, today);
+    let from = match since {
+        Some(since) => Some(qbxml::format_datetime(&since)),
+        None => block.modified_from.as_ref().map(resolve).transpose()?,
+    };
+    let to = block.modified_to.as_ref().map(resolve).transpose()?;
+
+    let columns: Vec<String> = block.list_type.columns().iter().filter_map(|c| odbc_column(&c.field)).collect();
+    // ODBC timestamp escapes; the qbXML form only differs by the 'T'
+    let stamp = |at: &str| format!("{{ts '{}'}}", at.replace('T', " "));
+    let mut conditions = Vec::new();
+    if let Some(from) = &from {
+        conditions.push(format!("TimeModified >= {}", stamp(from)));
+    }
+    if let Some(to) = &to {
+        conditions.push(format!("TimeModified <= {}", stamp(to)));
+    }
+    if block.open_only.unwrap_or(true) {
+        match block.list_type {
+            ListType::Estimate => conditions.push("IsActive = 1".to_string()),
+            ListType::SalesOrder => conditions.push("IsFullyInvoiced = 0 AND IsManuallyClosed = 0".to_string()),
+            _ => {}
+        }
+    }
+    let mut sql = format!("SELECT {} FROM {}", columns.join(", "), block.list_type.element());
+    if !conditions.is_empty() {
+        sql.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+    }
+    Ok(sql)
+}
+
+/// One row of odbc_query's result as the cells for_each_row would write for the same record.
+/// Numbers come back as plain decimals ("1234.50") and flags as 1 / 0; NULL is an empty cell.
+pub fn odbc_row(list_type: ListType, values: Vec<Option<String>>) -> Vec<Value> {
+    list_type.columns().iter().zip(values).map(|(c, value)| {
+        let value = value.unwrap_or_default();
+        match c.field {
+            Field::Number(_) | Field::Amount(_) => amounts::json(value.trim().parse::<Decimal>().unwrap_or_default()),
+            Field::Flag(_) => Value::from(matches!(value.trim(), "1" | "true" | "True")),
+            _ => Value::from(value),
+        }
+    }).collect()
+}
+
+/// Hands `each` one row per `<Type>Ret` (or per line for line-item exports) as soon as it is read,
+/// columns in header order. `open_only` drops closed estimates / orders and fully received PO lines;
+/// `line_items` follows each invoice with its line rows; `currency` adds the currency columns to
+/// account rows and `categories` their category; `class` puts each row's class last. An error from
+/// `each` stops the parse.
+#[allow(clippy::too_many_arguments)]
+pub fn for_each_row(
+    list_type: ListType,
+    response_xml: &str,
+    format: NumberFormat,
+    open_only: bool,
+    line_items: bool,
+    currency: bool,
+    categories: Option<&AccountClassificationConfig>,
+    class: bool,
+    mut each: impl FnMut(Vec<Value>) -> Result<()>,
+) -> Result<()> {
+    let element = list_type.element();
+    let doc = qbxml::parse_document(response_xml)?;
+    let rs = qbxml::find_response(&doc, &format!("{}QueryRs", element))?;
+    let ret_name = format!("{}Ret", element);
+    let rates = if currency { exchange_rates(&doc, format)? } else { HashMap::new() };
+    let class_column = list_type.class_column().filter(|_| class);
+
+    for ret in rs.children().filter(|n| n.has_tag_name(ret_name.as_str())) {
+        if open_only && !list_type.is_open(ret) {
+            continue;
+        }
+        let to_row = |record: Node| -> Vec<Value> {
+            list_type.columns().iter().map(|c| c.field.value(ret, record, format)).collect()
+        };
+        let class_of = |record: Node| class_column.map(|c| c.field.value(ret, record, format));
+        match list_type.line_element() {
+            Some(line_name) => {
+                for line in ret.children().filter(|n| n.has_tag_name(line_name)) {
+                    if open_only && open_quantity(line, format) <= Decimal::ZERO {
+                        continue;
+                    }
+                    let mut row = to_row(line);
+                    row.extend(class_of(line));
+                    each(row)?;
+                }
+            }
+            None => match list_type.child_lines().filter(|_| line_items) {
+                Some((line_name, line_columns)) => {
+                    let width = list_type.columns().len();
+                    let mut row = to_row(ret);
+                    row.resize(width + line_columns.len(), Value::from(""));
+                    let key = row[0].clone();
+                    // line rows also repeat the class, so class routing keeps them with their invoice
+                    let class = class_of(ret);
+                    row.extend(class.clone());
+                    each(row)?;
+                    // lines inside an InvoiceLineGroupRet count too; description-only lines have no item
+                    let lines = ret.descendants()
+                        .filter(|n| n.has_tag_name(line_name))
+                        .filter(|n| n.children().any(|c| c.has_tag_name("ItemRef")));
+                    for line in lines {
+                        let mut row = vec![Value::from(""); width];
+                        row[0] = key.clone();
+                        row.extend(line_columns.iter().map(|c| c.field.value(ret, line, format)));
+                        row.extend(class.clone());
+                        each(row)?;
+                    }
+                }
+                None => {
+                    let mut row = to_row(ret);
+                    if currency {
+                        row.extend(currency_values(ret, &rates, format));
+                    }
+                    if let Some(classification) = categories {
+                        let text = |name| qbxml::child_text(ret, name).unwrap_or_default();
+                        row.push(Value::from(classification.category(&text("FullName"), &text("AccountType")).unwrap_or_default()));
+                    }
+                    row.extend(class_of(ret));
+                    each(row)?;
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+// Exchange rate by currency name from the CurrencyQueryRs. QuickBooks quotes it as home currency
+// per unit of the foreign currency; the home currency itself has none, which is a rate of 1.
+fn exchange_rates(doc: &roxmltree::Document, format: NumberFormat) -> Result<HashMap<String, Decimal>> {
+    let rs = qbxml::find_response(doc, "CurrencyQueryRs")?;
+    Ok(rs.children()
+        .filter(|n| n.has_tag_name("CurrencyRet"))
+        .filter_map(|ret| {
+            let name = qbxml::child_text(ret, "Name")?;
+            let rate = qbxml::child_text(ret, "ExchangeRate").and_then(|r| format.parse(&r)).unwrap_or(Decimal::ONE);
+            Some((name, rate))
+        })
+        .collect())
+}
+
+// Currency, Exchange Rate and Home Balance of one AccountRet. Without multicurrency accounts have no
+// CurrencyRef and everything is in home currency; an unknown currency leaves rate and balance blank.
+// The home balance is rounded to cents.
+fn currency_values(ret: Node, rates: &HashMap<String, Decimal>, format: NumberFormat) -> [Value; 3] {
+    let currency = qbxml::ref_full_name(ret, "CurrencyRef").unwrap_or_default();
+    let rate = if currency.is_empty() { Some(Decimal::ONE) } else { rates.get(&currency).copied() };
+    match rate {
+        Some(rate) => {
+            let home_balance = amounts::round(number(ret, "Balance", format) * rate, 2);
+            [Value::from(currency), amounts::json(rate), amounts::json(home_balance)]
+        }
+        None => [Value::from(currency), Value::from(""), Value::from("")],
+    }
+}
+
+/// Position of the Class column, for exports that have one and include it
+pub fn class_column(list_type: ListType, line_items: bool, class: bool) -> Option<usize> {
+    let column = list_type.class_column().filter(|_| class)?;
+    list_type.headers(line_items, false, false, class).iter().position(|h| *h == column.header)
+}
+
+/// Position of the Category column, for Account exports with include_category
+pub fn category_column(list_type: ListType, line_items: bool, currency: bool, category: bool) -> Option<usize> {
+    list_type.headers(line_items, currency, category, false).iter().position(|h| *h == CATEGORY_HEADER)
+}
+
+/// Positions of the money columns (balances and amounts, not quantities or rates), which a block's
+/// rounding applies to
+pub fn amount_columns(list_type: ListType, line_items: bool, currency: bool, category: bool, class: bool) -> Vec<usize> {
+    let child_columns = list_type.child_lines().filter(|_| line_items).map(|(_, columns)| columns).unwrap_or(&[]);
+    let mut columns: Vec<usize> = list_type.columns().iter().chain(child_columns).enumerate()
+        .filter(|(_, c)| matches!(c.field, Field::Amount(_)))
+        .map(|(i, _)| i)
+        .collect();
+    columns.extend(list_type.headers(line_items, currency, category, class).iter().position(|h| *h == HOME_BALANCE_HEADER));
+    columns
+}
+
+/// The column titles, for blocks with include_header
+pub fn header_row(list_type: ListType, line_items: bool, currency: bool, category: bool, class: bool) -> Vec<Value> {
+    list_type.headers(line_items, currency, category, class).into_iter().map(Value::from).collect()
+}
+
+/// Position of the column incremental exports match records on: the name, or the number for
+/// transactions (Full Name for the indented Customer:Job tree). Line items and PO lines repeat it,
+/// so a record is every row that has it.
+pub fn key_column(list_type: ListType) -> usize {
+    match list_type {
+        ListType::CustomerJobs => 1,
+        _ => 0,
+    }
+}
+
+/// An incremental export's `changed` rows merged into the table already on the sheet, for writing
+/// back at the block's anchor. `sheet` is read_sheet's rows; the table is the `width` columns from
+/// `anchor` down, after its header row when `header`. A record's rows take the place of the rows with
+/// its key, records not on the sheet yet go at the end, and the rest keep their place as the sheet
+/// shows them. Blank rows pad the result to the old length, so nothing is left below a record that
+/// lost lines. The header isn't part of the result.
+pub fn merge_changed(sheet: &[Vec<String>], anchor: a1::Cell, width: usize, header: bool, changed: Vec<Vec<Value>>, key: usize) -> Vec<Vec<Value>> {
+    let left = anchor.column as usize - 1;
+    let mut existing: Vec<&[String]> = sheet.iter()
+        .skip(anchor.row as usize - 1 + header as usize)
+        .map(|row| row.get(left..).map_or(&[][..], |cells| &cells[..width.min(cells.len())]))
+        .collect();
+    while existing.last().is_some_and(|row| row.iter().all(String::is_empty)) {
+        existing.pop();
+    }
+
+    let key_of = |row: &[Value]| match row.get(key) {
+        Some(Value::String(text)) => text.clone(),
+        Some(other) => other.to_string(),
+        None => String::new(),
+    };
+    // a record without a key can't be matched, so it is only ever added
+    let mut records: Vec<Vec<Vec<Value>>> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for row in changed {
+        let key = key_of(&row);
+        match index.get(&key).filter(|_| !key.is_empty()) {
+            Some(&i) => records[i].push(row),
+            None => {
+                index.insert(key, records.len());
+                records.push(vec![row]);
+            }
+        }
+    }
+
+    let mut merged = Vec::with_capacity(existing.len() + records.len());
+    let mut placed = vec![false; records.len()];
+    for row in &existing {
+        let key = row.get(key).map(String::as_str).unwrap_or_default();
+        match index.get(key).filter(|_| !key.is_empty()) {
+            // the first of its old rows is where the record goes; the others are dropped
+            Some(&i) if !placed[i] => {
+                placed[i] = true;
+                merged.extend(records[i].iter().cloned());
+            }
+            Some(_) => {}
+            None => merged.push(row.iter().map(|cell| Value::from(cell.as_str())).collect()),
+        }
+    }
+    for (rows, placed) in records.into_iter().zip(placed) {
+        if !placed {
+            merged.extend(rows);
+        }
+    }
+    while merged.len() < existing.len() {
+        merged.push(vec![Value::from(""); width]);
+    }
+    merged
+}
+
+/// "Due Date", "DueDate" and "due_date" all name the same field
+pub fn field_key(name: &str) -> String {
+    name.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_lowercase()).collect()
+}
+
+/// The header of every mapped column, by its column letter, for the first row of a new tab;
+/// a field the export doesn't have is left out (mapped_columns reports it)
+pub fn mapped_headers(list_type: ListType, line_items: bool, currency: bool, category: bool, class: bool, columns: &HashMap<String, String>) -> BTreeMap<String, String> {
+    let headers = list_type.headers(line_items, currency, category, class);
+    columns.iter()
+        .filter_map(|(field, column)| {
+            let header = headers.iter().find(|h| field_key(h) == field_key(field))?;
+            Some((column.trim().to_ascii_uppercase(), header.to_string()))
+        })
+        .collect()
+}
+
+/// Splits `rows` for a block with `columns` set: the mapped fields are reordered into their sheet
+/// columns and returned as (top-left cell, rows) blocks of adjacent columns, one write each, so the
+/// columns in between (formulas, notes) are left alone. `anchor` gives the first row.
+#[allow(clippy::too_many_arguments)]
+pub fn mapped_columns(
+    list_type: ListType,
+    line_items: bool,
+    currency: bool,
+    category: bool,
+    class: bool,
+    rows: &[Vec<Value>],
+    columns: &HashMap<String, String>,
+    anchor: a1::Cell,
+) -> Result<Vec<(String, Vec<Vec<Value>>)>> {
+    let headers = list_type.headers(line_items, currency, category, class);
+    let mut targets: Vec<(u32, usize)> = Vec::with_capacity(columns.len());
+    for (field, column) in columns {
+        let index = headers.iter()
+            .position(|h| field_key(h) == field_key(field))
+            .with_context(|| format!("{} exports have no '{}' column; they have {}", list_type.element(), field, headers.join(", ")))?;
+        let number = a1::column_number(column.trim())
+            .filter(|n| *n <= a1::MAX_COLUMN)
+            .with_context(|| format!("Column for '{}' must be a column letter such as C, got '{}'", field, column))?;
+        if let Some((_, other)) = targets.iter().find(|(n, _)| *n == number) {
+            bail!("'{}' and '{}' are both mapped to column {}", headers[*other], field, column.trim().to_ascii_uppercase());
+        }
+        targets.push((number, index));
+    }
+    targets.sort();
+
+    // adjacent columns go out together
+    let mut runs: Vec<Vec<(u32, usize)>> = Vec::new();
+    for target in targets {
+        match runs.last_mut() {
+            Some(run) if run.last().is_some_and(|(n, _)| n + 1 == target.0) => run.push(target),
+            _ => runs.push(vec![target]),
+        }
+    }
+    Ok(runs.into_iter()
+        .map(|run| {
+            let cell = a1::Cell { column: run[0].0, row: anchor.row };
+            let block = rows.iter()
+                .map(|row| run.iter().map(|(_, i)| row.get(*i).cloned().unwrap_or(Value::Null)).collect())
+                .collect();
+            (cell.to_string(), block)
+        })
+        .collect())
+}
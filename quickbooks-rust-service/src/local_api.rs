@@ -0,0 +1,163 @@
+// [local_api]: a named pipe other programs on this machine can query instead of each registering
+// with the QuickBooks SDK and opening their own session
+//
+// One JSON request per line, answered with one JSON line:
+//   {"method":"GetAccountBalance","account":"Checking"}  -> {"ok":true,"result":{"account_full_name":...}}
+//   {"method":"ListAccounts"}                             -> every account, as `qb_sync dump` prints them
+//   {"method":"RunSyncBlock","name":"Operating cash"}     -> one outcome per destination
+// Failures come back as {"ok":false,"error":"..."}. Requests are answered by the daemon between
+// cycles on its own session, so one that arrives mid-cycle waits for the cycle to finish.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::config::LocalApiConfig;
+use crate::summary::{Outcome, Status};
+
+pub const DEFAULT_PIPE_NAME: &str = r"\\.\pipe\qb_sync";
+// requests waiting for the daemon; more than this and clients wait to be read
+const QUEUE: usize = 16;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method")]
+pub enum Request {
+    GetAccountBalance { account: String },
+    ListAccounts,
+    RunSyncBlock { name: String },
+}
+
+/// A request waiting for the daemon, with the way back to the client that sent it
+pub struct Call {
+    pub request: Request,
+    reply: oneshot::Sender<Result<Value>>,
+}
+
+impl Call {
+    pub fn respond(self, result: Result<Value>) {
+        // the client may have hung up in the meantime
+        let _ = self.reply.send(result);
+    }
+}
+
+/// The queue the daemon answers requests from, and the way in for the pipe and [dashboard]
+pub fn queue() -> (mpsc::Sender<Call>, mpsc::Receiver<Call>) {
+    mpsc::channel(QUEUE)
+}
+
+/// Starts listening on the pipe, passing requests to `calls`
+pub fn start(cfg: &LocalApiConfig, calls: mpsc::Sender<Call>) -> Result<()> {
+    let pipe_name = cfg.pipe_name.as_deref().unwrap_or(DEFAULT_PIPE_NAME);
+    server::listen(pipe_name, calls)?;
+    log::info!("[API] Listening on {}", pipe_name);
+    Ok(())
+}
+
+/// Queues `request` for the daemon and waits for its answer
+pub async fn ask(calls: &mpsc::Sender<Call>, request: Request) -> Result<Value> {
+    let (reply, answered) = oneshot::channel();
+    if calls.send(Call { request, reply }).await.is_err() {
+        return Err(anyhow!("qb_sync is shutting down"));
+    }
+    answered.await.unwrap_or_else(|_| Err(anyhow!("qb_sync stopped before answering")))
+}
+
+/// Outcomes of a RunSyncBlock request
+pub fn outcomes_json(outcomes: &[Outcome]) -> Value {
+    outcomes.iter()
+        .map(|o| {
+            let (status, detail) = match &o.status {
+                Status::Ok => ("ok", None),
+                Status::Error(e) => ("error", Some(e.as_str())),
+                Status::Skipped(reason) => ("skipped", Some(reason.as_str())),
+            };
+            json!({ "destination": o.destination, "status": status, "detail": detail, "value": o.value })
+        })
+        .collect()
+}
+
+fn response(result: Result<Value>) -> Value {
+    match result {
+        Ok(result) => json!({ "ok": true, "result": result }),
+        Err(e) => json!({ "ok": false, "error": format!("{:#}", e) }),
+    }
+}
+
+async fn answer(line: &str, calls: &mpsc::Sender<Call>) -> Value {
+    let request = match serde_json::from_str::<Request>(line) {
+        Ok(request) => request,
+        Err(e) => return response(Err(anyhow!("Invalid request: {}", e))),
+    };
+    response(ask(calls, request).await)
+}
+
+// Answers requests from one connected client until it disconnects
+#[cfg_attr(not(windows), allow(dead_code))]
+async fn serve_client(stream: impl AsyncRead + AsyncWrite, calls: mpsc::Sender<Call>) {
+    let (read, mut write) = tokio::io::split(stream);
+    let mut lines = BufReader::new(read).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut out = answer(&line, &calls).await.to_string();
+        out.push('\n');
+        if write.write_all(out.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(windows)]
+mod server {
+    use anyhow::{Context, Result};
+    use tokio::net::windows::named_pipe::ServerOptions;
+    use tokio::sync::mpsc;
+
+    use super::Call;
+
+    pub fn listen(pipe_name: &str, calls: mpsc::Sender<Call>) -> Result<()> {
+        // the default pipe DACL lets only this account, SYSTEM and administrators send requests;
+        // remote clients are refused
+        let mut server = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(pipe_name)
+            .with_context(|| format!("Failed to create named pipe {} (is another qb_sync running?)", pipe_name))?;
+        let pipe_name = pipe_name.to_string();
+        tokio::spawn(async move {
+            loop {
+                let connected = server.connect().await;
+                // a fresh instance waits for the next client while this one is being served
+                let next = match ServerOptions::new().create(&pipe_name) {
+                    Ok(next) => next,
+                    Err(e) => {
+                        log::error!("[API] Failed to reopen {}, local API stopped: {}", pipe_name, e);
+                        return;
+                    }
+                };
+                let client = std::mem::replace(&mut server, next);
+                match connected {
+                    Ok(()) => {
+                        tokio::spawn(super::serve_client(client, calls.clone()));
+                    }
+                    Err(e) => log::warn!("[API] Client connection failed: {}", e),
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(not(windows))]
+mod server {
+    use anyhow::{bail, Result};
+    use tokio::sync::mpsc;
+
+    use super::Call;
+
+    pub fn listen(_pipe_name: &str, _calls: mpsc::Sender<Call>) -> Result<()> {
+        bail!("[local_api] needs Windows named pipes")
+    }
+}
@@ -1,32 +1,107 @@
 mod file_mode;
+mod a1;
+mod amounts;
 mod config;
+mod secrets;
 mod qbxml_safe;
+mod qb_errors;
+mod archive;
+mod cli;
+mod qbxml;
+mod reports;
+mod transactions;
+mod deleted_txns;
+mod aging;
+mod accounts;
+mod kpis;
+mod run_window;
+mod template;
+mod destinations;
+mod summary;
+mod quickbooks_process;
+mod qb_session;
+mod session_pool;
+mod qb_backend;
+mod circuit;
+mod response_cache;
+mod pos_session;
+mod history;
+mod lists;
+mod dates;
+mod balance_diff;
+mod raw_qbxml;
+mod extract;
+mod expr;
+mod status_sheet;
+mod host;
+mod integrity;
+mod redact;
+mod names;
+mod scripting;
+mod dump;
+mod local_api;
+mod dashboard;
+mod hooks;
+mod pipeline;
+mod reliability;
+mod telemetry;
+mod tenants;
+mod resume;
+mod context;
+mod offline_queue;
+mod close_snapshot;
+mod reconcile;
+mod changes;
+mod import_blocks;
+mod deploy_webapp;
+mod watchdog;
+mod progress;
+mod tray;
 
 use anyhow::{Result, Context};
 use log::info;
-use winapi::um::winnt::UpdateBlackBoxRecorder;
-use std::env;
+use clap::{CommandFactory, Parser};
 use futures::future::join_all; 
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
 
-use crate::config::{AccountSyncConfig, TimestampConfig, Config};
-use crate::file_mode::FileMode;
-use crate::qbxml_safe::qbxml_request_processor::QbxmlRequestProcessor;
+use crate::config::{AccountSyncConfig, AgingConfig, DeletedTransactionsConfig, ExtractMode, FieldMapping, ListBlockConfig, ListType, OnMissing, RawQbxmlBlockConfig, ReportBlockConfig, RestrictedMode, ScheduleConfig, TimestampConfig, TransactionBlockConfig, Config};
+use crate::qbxml_safe::com_error;
+use crate::qb_backend::RequestProcessor;
 mod google_sheets;
+mod signing;
+mod s3;
+mod quota;
+mod sheet_faults;
+mod odbc;
+mod capture;
+mod graph;
+mod drive;
+mod correlation;
+mod shutdown;
+mod jitter;
+mod random;
 use google_sheets::GoogleSheetsClient;
+use session_pool::{Querier, SessionPool};
 
-#[derive(Debug, Clone)]
-pub struct AccountData {
-    pub account_full_name: String,
-    pub number: String,
-    pub account_type: String,
-    pub balance: f64,
-}
+use crate::accounts::AccountIndex;
+use crate::context::AppContext;
+use crate::destinations::jsonl::JsonlRows;
+use crate::destinations::SyncValue;
+use crate::history::History;
+use crate::qb_session::QbSession;
+use crate::pos_session::PosSession;
+use crate::summary::{Phase, RunSummary};
 
 fn print_instructions() {
     println!("QuickBooks Account Query Service v5");
     println!("===================================");
     println!();
-    println!("This service reads configuration from config/config.toml and queries");
+    println!("This service reads configuration from config/config.toml (or --config <path>) and queries");
     println!("the specified account to retreive its balance from QuickBooks Desktop.");
     println!();
     println!("Prerequisites:");
@@ -34,196 +109,1874 @@ fn print_instructions() {
     println!("   2. A company file must be open in QuickBooks");
     println!("   3. The FullName of the account in config.toml must exist in QuickBooks");
     println!();
-    println!("Usage: main_account_query [--verbose]");
+    println!("Usage: qb_sync [--verbose | --quiet] [--config <path>] [--env <name>] [--set key=value]...");
+    println!("--env <name> (or QB_SYNC_ENV) merges config.<name>.toml over the base config.");
+    println!("--set overrides a single value, e.g. --set quickbooks.company_file=AUTO");
     println!("All account sync blocks are now read from config/config.toml; no account_full_name, sheet_name, or cell_address parameter is required.");
     println!();
 }
 
-async fn process_sync_blocks(processor: &QbxmlRequestProcessor, response_xml: &str, the_sync_block: &AccountSyncConfig, config: &Config) -> Result<()> {
-    let gs_cfg = &config.google_sheets;
-    match processor.get_account_balance(&response_xml, &the_sync_block.account_full_name) {
-    Ok(Some(account_balance)) => {
-        info!("[QBXML] Account '{}' balance is: {:?}", the_sync_block.account_full_name, account_balance);
-        let gs_client = GoogleSheetsClient::new(
-            gs_cfg.webapp_url.clone(),
-            gs_cfg.api_key.clone(),
-            the_sync_block.spreadsheet_id.clone(),
-            );
-        gs_client.send_balance(
-            account_balance,
-            Some(&the_sync_block.sheet_name),
-            Some(&the_sync_block.cell_address),
-            ).await?;
+// [timeouts] block_secs fails a block whose writes take too long. Only awaits can be interrupted:
+// a qbXML request blocks the thread until QuickBooks answers.
+async fn with_block_timeout<T>(config: &Config, block: impl std::future::Future<Output = Result<T>>) -> Result<T> {
+    match config.timeouts.as_ref().and_then(|t| t.block_secs) {
+        Some(secs) => tokio::time::timeout(Duration::from_secs(secs), block).await
+            .map_err(|_| anyhow::anyhow!("Block timed out after {}s", secs))?,
+        None => block.await,
+    }
+}
+
+async fn process_sync_blocks(accounts: &AccountIndex, the_sync_block: &AccountSyncConfig, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let config = ctx.config;
+    let label = the_sync_block.label();
+    let known_id = ctx.history.read(|h| h.account_id(label, &the_sync_block.account_full_name));
+    match accounts::for_sync_block(accounts, the_sync_block, config, known_id.as_deref()) {
+    Some(account) => {
+        log::log!(progress::detail(), "[QBXML] Account '{}' balance is: {:?}", account.account_full_name, account.balance);
+        // matched by name the first time and whenever account_full_name changes; by ListID otherwise
+        if !account.list_id.is_empty()
+            && !ctx.history.read(|h| h.remembers_account(label, &account.list_id, &the_sync_block.account_full_name)) {
+            if let Err(e) = ctx.history.update(|h| h.set_account_id(label, &account.list_id, &the_sync_block.account_full_name)) {
+                log::warn!("[HISTORY] Failed to remember the ListID of '{}': {:#}", account.account_full_name, e);
+            }
+        }
+        let value = destinations::sync_value(the_sync_block, &account, config)?;
+        write_sync_value(the_sync_block, &value, ctx, summary).await?;
             },
-        Ok(None) => {
-          info!("[QBXML] No valid balance for account '{}'.", the_sync_block.account_full_name);
+        None => {
+          let reason = match the_sync_block.category {
+              Some(_) => "no account in the category",
+              None => {
+                  log::log!(progress::detail(), "[QBXML] No valid balance for account '{}'.", the_sync_block.account_full_name);
+                  "account not found"
+              }
+          };
+          // on_missing: written explicitly, so the sheet doesn't keep showing the last balance
+          match destinations::missing_value(the_sync_block, config)? {
+              Some(value) => {
+                  log::warn!("[SYNC] '{}': {}; writing {:?} (on_missing)", label, reason, value.cell_value());
+                  write_sync_value(the_sync_block, &value, ctx, summary).await?;
+              }
+              None => summary.skipped(label, "-", reason),
+          }
             },
-        Err(e) => {
-            eprintln!("[QBXML] Error parsing balance for '{}': {:#}", the_sync_block.account_full_name, e);
+    }
+    Ok(())
+}
+
+// fans a sync block's value out to every destination; one failing doesn't stop the others
+async fn write_sync_value(the_sync_block: &AccountSyncConfig, value: &SyncValue, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let label = the_sync_block.label();
+    let scripts = scripting::Scripts::load(&the_sync_block.scripts)?;
+    let scripted;
+    let value = match scripts.is_empty() {
+        true => value,
+        false => match scripts.value(value.clone())? {
+            Some(value) => {
+                scripted = value;
+                &scripted
+            }
+            None => {
+                summary.skipped(label, "-", "a script returned ()");
+                return Ok(());
             }
+        },
+    };
+    summary.set_value(label, status_sheet::display_value(&value.cell_value()));
+    let targets = destinations::for_sync_block(the_sync_block, ctx)?;
+    let results = summary.timed_async(label, Phase::Write, join_all(targets.iter().map(|target| target.write_value(value)))).await;
+    for (target, result) in targets.iter().zip(results) {
+        if let Err(e) = &result {
+            eprintln!("[SYNC] '{}' -> {} failed: {:#}", label, target.describe(), e);
+        }
+        summary.record(label, &target.describe(), &result);
     }
     Ok(())
 }
 
-async fn process_timestamp_blocks(the_timestamp_block: &TimestampConfig, config: &Config, ) -> Result<()> {
-    use chrono::Local;
-    let gs_cfg = &config.google_sheets;
-    let now = Local::now();
-    let formatted_time = now.format("%d-%m-%Y:%H:%M").to_string();
-    let gs_client = GoogleSheetsClient::new(
-        gs_cfg.webapp_url.clone(),
-        gs_cfg.api_key.clone(),
-        the_timestamp_block.spreadsheet_id.clone(),
-        );
-    gs_client.send_timestamp(
+// Balances of the account sync blocks by block name, the variables computed blocks can use
+fn sync_block_values(accounts: &AccountIndex, ctx: &AppContext<'_>) -> HashMap<String, Decimal> {
+    let config = ctx.config;
+    config.sync_blocks.iter()
+        .filter(|b| !b.is_computed())
+        .filter_map(|b| match accounts::for_sync_block(accounts, b, config, ctx.history.read(|h| h.account_id(b.label(), &b.account_full_name)).as_deref()) {
+            // the same value the block itself writes
+            Some(a) => Some((b.label().to_string(), if b.normalizes_signs(config) { a.normalized_balance() } else { a.balance })),
+            // on_missing = "zero" counts as 0 in expressions too
+            None => (b.on_missing().ok() == Some(OnMissing::Zero)).then(|| (b.label().to_string(), Decimal::ZERO)),
+        })
+        .collect()
+}
+
+// A computed block: its expression over the values published so far, written like any sync block
+// value. The result is published under its name for the computed blocks after it.
+async fn process_computed_block(the_sync_block: &AccountSyncConfig, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let label = the_sync_block.label();
+    let expression = the_sync_block.expression.as_deref().unwrap_or_default();
+    let result = expr::evaluate(expression, |name| summary.variable(name))?;
+    log::log!(progress::detail(), "[SYNC] '{}' = {} = {}", label, expression, result);
+    summary.publish(label, result);
+
+    let value = destinations::computed_value(the_sync_block, result, ctx.config)?;
+    write_sync_value(the_sync_block, &value, ctx, summary).await
+}
+
+async fn process_timestamp_blocks(the_timestamp_block: &TimestampConfig, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let config = ctx.config;
+    let result = with_block_timeout(config, send_timestamp_block(the_timestamp_block, ctx, summary)).await;
+    summary.record(&the_timestamp_block.label(), &format!("sheet {}!{}", the_timestamp_block.sheet_name, the_timestamp_block.cell_address), &result);
+    result
+}
+
+async fn send_timestamp_block(the_timestamp_block: &TimestampConfig, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let now = chrono::Utc::now();
+    let local = dates::now(ctx.config)?;
+    let format = the_timestamp_block.display_format.as_deref().unwrap_or("%d-%m-%Y:%H:%M");
+    let formatted_time = local.format(format).to_string();
+    summary.set_value(&the_timestamp_block.label(), formatted_time.as_str());
+    let gs_client = ctx.sheets(&the_timestamp_block.spreadsheet_id)
+        .creating_sheet(the_timestamp_block.create_sheet_if_missing.unwrap_or(false), BTreeMap::new());
+    let as_datetime = the_timestamp_block.as_datetime.unwrap_or(false);
+    if the_timestamp_block.iso_utc.unwrap_or(false) {
+        // one write, so the two cells never come from different runs
+        let display = match as_datetime {
+            true => amounts::json(dates::sheets_serial(local)),
+            false => serde_json::Value::from(formatted_time),
+        };
+        let iso = now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        let row = vec![vec![display, serde_json::Value::from(iso)]];
+        summary.timed_async(&the_timestamp_block.label(), Phase::Write, gs_client.write_range(
+            &row, &the_timestamp_block.sheet_name, &the_timestamp_block.cell_address, false, None,
+            )).await?;
+        return Ok(());
+    }
+    if as_datetime {
+        // as a number the webapp writes it with setValue, so the sheet sees a real date-time
+        let serial = dates::sheets_serial(local);
+        summary.timed_async(&the_timestamp_block.label(), Phase::Write, gs_client.send_balance(
+            serial,
+            Some(&the_timestamp_block.sheet_name),
+            Some(&the_timestamp_block.cell_address),
+            )).await?;
+        return Ok(());
+    }
+    summary.timed_async(&the_timestamp_block.label(), Phase::Write, gs_client.send_timestamp(
         Some(&formatted_time), 
         Some(&the_timestamp_block.sheet_name),
         Some(&the_timestamp_block.cell_address),
-        ).await?;
+        )).await?;
     Ok(())
 }
 
-async fn process_qbxml(processor: &QbxmlRequestProcessor, response_xml: &str, config: &Config) -> Result<()> {
-    // Process sync blocks in parallel
-    let sync_futures = config.sync_blocks.iter().map(|sync_block| {
-        process_sync_blocks(processor, response_xml, sync_block, config)
+async fn process_report_block(qb: Querier<'_>, the_report_block: &ReportBlockConfig, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let config = ctx.config;
+    let label = the_report_block.label();
+    let request = reports::report_request(the_report_block, dates::today(config)?)?;
+    let response_xml = summary.timed_async(label, Phase::Query, qb.process_request(&request)).await?;
+    archive::maybe_archive_response(config.debug.as_ref(), &format!("{}Report", the_report_block.report_type), &response_xml);
+
+    let mut table = summary.timed(label, Phase::Parse, || reports::parse_for_block(the_report_block, &response_xml, config.quickbooks.number_format()?))?;
+    let dropped = table.retain_accounts(|account| config.account_permitted(account));
+    if dropped > 0 {
+        log::debug!("[QBXML] Report '{}': {} row(s) left out by allowed_accounts / denied_accounts", label, dropped);
+    }
+    log::log!(progress::detail(), "[QBXML] Report '{}' ({}) returned {} rows", the_report_block.report_type, table.title, table.rows.len());
+    for (name, row_label) in &the_report_block.publish {
+        match reports::row_total(&table, row_label) {
+            Some(total) => summary.publish(name, total),
+            // computed blocks using it fail with "no value", naming it
+            None => log::warn!("[QBXML] Report '{}' has no '{}' row with an amount to publish as '{}'", label, row_label, name),
+        }
+    }
+    // after publishing, so computed blocks work from the unrounded totals
+    if let Some(rounding) = config.rounding(the_report_block.rounding) {
+        table.round(rounding);
+    }
+    summary.set_value(the_report_block.label(), format!("{} rows", table.rows.len()));
+    let rows = table.to_rows(the_report_block.include_header.unwrap_or(true));
+    let named_range = the_report_block.named_range.unwrap_or(false).then(|| google_sheets::named_range_name(the_report_block.label()));
+    let gs_client = ctx.sheets(&the_report_block.spreadsheet_id)
+        .creating_sheet(the_report_block.create_sheet_if_missing.unwrap_or(false), BTreeMap::new());
+    let staging = the_report_block.staged.unwrap_or(false).then(|| google_sheets::staging_sheet_name(label, &the_report_block.sheet_name));
+    summary.timed_async(label, Phase::Write, write_table(
+        &gs_client,
+        &rows,
+        &the_report_block.sheet_name,
+        &the_report_block.cell_address,
+        the_report_block.clear_below.unwrap_or(false),
+        named_range.as_deref(),
+        staging.as_deref(),
+        )).await?;
+    Ok(())
+}
+
+// A report or transaction block's rows; with a staging tab (staged = true) they are written there
+// first and then promoted onto sheet_name in one write
+async fn write_table(gs_client: &GoogleSheetsClient, rows: &[Vec<serde_json::Value>], sheet_name: &str, cell_address: &str, clear_below: bool, named_range: Option<&str>, staging: Option<&str>) -> Result<()> {
+    let Some(staging) = staging else {
+        return gs_client.write_range(rows, sheet_name, cell_address, clear_below, named_range).await;
+    };
+    gs_client.stage_range(rows, staging, cell_address).await?;
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    gs_client.promote(staging, sheet_name, cell_address, rows.len(), width, clear_below, named_range).await
+}
+
+// Reports need one QuickBooks request each; an SDK session is single-threaded so they run in order,
+// unless they go through a pool of sessions
+async fn process_report_blocks(qb: Querier<'_>, ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    let runs = config.report_blocks.iter().filter(|b| !summary.sits_out(b.label())).map(|report_block| {
+        correlation::in_block(async move {
+            let result = with_block_timeout(config, process_report_block(qb, report_block, ctx, summary)).await;
+            if let Err(e) = &result {
+                eprintln!("[QBXML] Error processing report '{}': {:#}", report_block.report_type, e);
+            }
+            summary.record(report_block.label(), &format!("range {}!{}", report_block.sheet_name, report_block.cell_address), &result);
+            summary.complete(report_block.label());
+        })
     });
-    let sync_results = join_all(sync_futures).await;
-    for result in sync_results {
-        result?; // Propagate any error
+    run_blocks(qb, runs).await;
+}
+
+// Every block at once when `qb` is a pool, otherwise one after another
+async fn run_blocks<F: std::future::Future<Output = ()>>(qb: Querier<'_>, runs: impl Iterator<Item = F>) {
+    if qb.concurrent() {
+        join_all(runs).await;
+    } else {
+        for run in runs {
+            run.await;
+        }
+    }
+}
+
+async fn process_transaction_block(qb: Querier<'_>, the_txn_block: &TransactionBlockConfig, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let config = ctx.config;
+    let element = the_txn_block.txn_type.element();
+    let label = the_txn_block.label();
+    let request = transactions::query_request(the_txn_block, dates::today(config)?)?;
+    let response_xml = summary.timed_async(label, Phase::Query, qb.process_request(&request)).await?;
+    archive::maybe_archive_response(config.debug.as_ref(), &format!("{}QueryRs", element), &response_xml);
+
+    let mut txns = summary.timed(label, Phase::Parse, || transactions::parse_transactions(the_txn_block.txn_type, &response_xml, config.quickbooks.number_format()?))?;
+    let dropped = transactions::retain_accounts(&mut txns, |account| config.account_permitted(account));
+    if dropped > 0 {
+        log::debug!("[QBXML] {} query: {} transaction(s) left out by allowed_accounts / denied_accounts", element, dropped);
+    }
+    if let Some(rounding) = config.rounding(the_txn_block.rounding) {
+        for txn in &mut txns {
+            txn.amount = rounding.apply(txn.amount);
+        }
+    }
+    log::log!(progress::detail(), "[QBXML] {} query returned {} transactions", element, txns.len());
+    summary.set_value(the_txn_block.label(), format!("{} transactions", txns.len()));
+    let include_header = the_txn_block.include_header.unwrap_or(true);
+    let mut rows = transactions::to_rows(the_txn_block.txn_type, &txns, include_header);
+    let headers = the_txn_block.txn_type.headers();
+    let renamer = names::for_headers(&headers, config)?;
+    let redactor = redact::for_transaction_block(the_txn_block, config)?;
+    for row in rows.iter_mut().skip(include_header as usize) {
+        // renamed first, so a hashed payee hashes the merged name
+        renamer.apply(row);
+        redactor.apply(row);
+    }
+    let scripts = scripting::Scripts::load(&the_txn_block.scripts)?;
+    if !scripts.is_empty() {
+        let data = rows.split_off(include_header as usize);
+        for row in data {
+            rows.extend(scripts.row(&headers, row)?);
+        }
+    }
+    let named_range = the_txn_block.named_range.unwrap_or(false).then(|| google_sheets::named_range_name(the_txn_block.label()));
+    let gs_client = ctx.sheets(&the_txn_block.spreadsheet_id)
+        .creating_sheet(the_txn_block.create_sheet_if_missing.unwrap_or(false), BTreeMap::new());
+    let staging = the_txn_block.staged.unwrap_or(false).then(|| google_sheets::staging_sheet_name(label, &the_txn_block.sheet_name));
+    summary.timed_async(label, Phase::Write, write_table(
+        &gs_client,
+        &rows,
+        &the_txn_block.sheet_name,
+        &the_txn_block.cell_address,
+        the_txn_block.clear_below.unwrap_or(false),
+        named_range.as_deref(),
+        staging.as_deref(),
+        )).await?;
+    if let Some(export) = &the_txn_block.jsonl {
+        let jsonl = JsonlRows::new(export, ctx.http.clone(), config.s3.clone(), label.to_string(), element)?;
+        summary.timed_async(label, Phase::Write, jsonl.write(&headers, &rows[include_header as usize..])).await
+            .with_context(|| format!("Failed to write {}", jsonl.describe()))?;
     }
+    Ok(())
+}
 
-    // Process timestamp blocks in parallel
-    let timestamp_futures = config.timestamp_blocks.iter().map(|timestamp_block| {
-        process_timestamp_blocks(timestamp_block, config)
+async fn process_transaction_blocks(qb: Querier<'_>, ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    let runs = config.transaction_blocks.iter().filter(|b| !summary.sits_out(b.label())).map(|txn_block| {
+        correlation::in_block(async move {
+            let result = with_block_timeout(config, process_transaction_block(qb, txn_block, ctx, summary)).await;
+            if let Err(e) = &result {
+                eprintln!("[QBXML] Error processing {} transactions: {:#}", txn_block.txn_type.element(), e);
+            }
+            summary.record(txn_block.label(), &format!("range {}!{}", txn_block.sheet_name, txn_block.cell_address), &result);
+            summary.complete(txn_block.label());
+        })
+    });
+    run_blocks(qb, runs).await;
+}
+
+// `session` is the SDK session's processor and ticket; None for blocks with backend = "odbc"
+async fn process_list_block(session: Option<(&dyn RequestProcessor, &str)>, the_list_block: &ListBlockConfig, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let config = ctx.config;
+    let element = the_list_block.list_type.element();
+    // taken before the query so records changed while we run are picked up next time
+    let started = chrono::Local::now();
+    let since = if the_list_block.incremental.unwrap_or(false) {
+        ctx.history.read(|h| h.last_success(the_list_block.label()))
+    } else {
+        None
+    };
+    if let Some(since) = since {
+        log::log!(progress::detail(), "[QBXML] {} '{}': exporting records modified since {}", element, the_list_block.label(), since.format("%Y-%m-%d %H:%M:%S"));
+    }
+
+    let label = the_list_block.label();
+    let named_range = the_list_block.named_range.unwrap_or(false).then(|| google_sheets::named_range_name(the_list_block.label()));
+    if named_range.is_some() && !the_list_block.columns.is_empty() {
+        anyhow::bail!("named_range can't be used with columns; the data isn't one rectangle");
+    }
+    if the_list_block.staged.unwrap_or(false) && !the_list_block.columns.is_empty() {
+        anyhow::bail!("staged can't be used with columns; the data isn't one rectangle");
+    }
+    let settings = pipeline::Settings::from_config(config.pipeline.as_ref());
+    // a named range has to cover the whole export, and the changed records have to be merged into
+    // the table all together, so those are written in one go
+    let settings = if named_range.is_some() || since.is_some() { settings.unbatched() } else { settings };
+
+    // rows are parsed on their own thread and written batch by batch as they arrive
+    let (list_type, line_items, currency, category, class) = (the_list_block.list_type, the_list_block.line_items(), the_list_block.currency(), the_list_block.category(), the_list_block.class());
+    let open_only = the_list_block.open_only.unwrap_or(true);
+    let number_format = config.quickbooks.number_format()?;
+    let categories = config.account_classification.clone().filter(|_| category);
+    // the money columns, rounded as each row is read so every target, script and export sees it
+    let rounding = config.rounding(the_list_block.rounding)
+        .map(|rounding| (rounding, lists::amount_columns(list_type, line_items, currency, category, class)));
+    let round = move |mut row: Vec<serde_json::Value>| {
+        if let Some((rounding, columns)) = &rounding {
+            for &i in columns {
+                if let Some(cell) = row.get_mut(i) {
+                    rounding.apply_to_cell(cell);
+                }
+            }
+        }
+        row
+    };
+    let mut batches = if the_list_block.odbc() {
+        // the driver is read from the parser's thread too, so rows are written while it pages on
+        let odbc = config.odbc.clone().context("backend = \"odbc\" needs an [odbc] section")?;
+        let sql = lists::odbc_query(the_list_block, since, dates::today(config)?)?;
+        log::debug!("[ODBC] '{}': {}", label, sql);
+        pipeline::batches(settings, move |each| odbc::query(&odbc, &sql, |values| each(round(lists::odbc_row(list_type, values)))))
+    } else {
+        let (processor, ticket) = session.context("list blocks need a QuickBooks session")?;
+        let request = lists::query_request(the_list_block, since, dates::today(config)?)?;
+        let response_xml = summary.timed(label, Phase::Query, || processor.process_request(ticket, &request))?;
+        archive::maybe_archive_response(config.debug.as_ref(), &format!("{}QueryRs", element), &response_xml);
+        pipeline::batches(settings, move |each| {
+            lists::for_each_row(list_type, &response_xml, number_format, open_only, line_items, currency, categories.as_ref(), class, |row| each(round(row)))
+        })
+    };
+
+    // the block's own sheet first, then one per [routing] rule with route_by_class / route_by_category;
+    // a route gives the index of the rule a row's class or category matches
+    let mut targets = vec![ListTarget::new(ctx, the_list_block, &the_list_block.spreadsheet_id, &the_list_block.sheet_name)];
+    let routing = config.routing.as_ref();
+    let target_for = |spreadsheet_id: &str, sheet_name: &Option<String>| ListTarget::new(ctx, the_list_block, spreadsheet_id, sheet_name.as_deref().unwrap_or(&the_list_block.sheet_name));
+    let routes: Option<(usize, Route)> = if the_list_block.route_by_class.unwrap_or(false) {
+        let column = lists::class_column(list_type, line_items, class).with_context(|| format!("{} exports have no Class column to route by", element))?;
+        let rules = routing.map(|r| r.classes.as_slice()).unwrap_or_default();
+        targets.extend(rules.iter().map(|r| target_for(&r.spreadsheet_id, &r.sheet_name)));
+        Some((column, Box::new(|class| rules.iter().position(|r| r.matches(class)))))
+    } else if the_list_block.route_by_category.unwrap_or(false) {
+        let column = lists::category_column(list_type, line_items, currency, category).context("route_by_category needs include_category = true")?;
+        let rules = routing.map(|r| r.categories.as_slice()).unwrap_or_default();
+        targets.extend(rules.iter().map(|r| target_for(&r.spreadsheet_id, &r.sheet_name)));
+        Some((column, Box::new(|category| rules.iter().position(|r| r.matches(category)))))
+    } else {
+        None
+    };
+    let header = the_list_block.include_header.unwrap_or(true).then(|| lists::header_row(list_type, line_items, currency, category, class));
+    let anchor = a1::parse_cell(&the_list_block.cell_address)?;
+    let headers = list_type.headers(line_items, currency, category, class);
+    let renamer = names::for_headers(&headers, config)?;
+    let redactor = redact::for_list_block(the_list_block, config)?;
+    let scripts = scripting::Scripts::load(&the_list_block.scripts)?;
+    let jsonl = the_list_block.jsonl.as_ref()
+        .map(|export| JsonlRows::new(export, ctx.http.clone(), config.s3.clone(), label.to_string(), element))
+        .transpose()?;
+    let mut records = 0;
+    // time spent waiting for the parser; the rest of its work overlaps the writes
+    while let Some(batch) = summary.timed_async(label, Phase::Parse, batches.recv()).await {
+        let rows = batch?;
+        records += rows.len();
+        let mut split = vec![Vec::new(); targets.len()];
+        for mut row in rows {
+            // the first matching rule wins; classes or categories without one stay on the block's own sheet
+            let target = routes.as_ref().map_or(0, |(column, route)| {
+                let key = row.get(*column).and_then(|c| c.as_str()).unwrap_or_default();
+                route(key).map_or(0, |i| i + 1)
+            });
+            // allowed_accounts / denied_accounts: Account exports lose the rows they keep out
+            if list_type == ListType::Account && !row.first().and_then(|c| c.as_str()).is_some_and(|name| config.account_permitted(name)) {
+                continue;
+            }
+            // after routing, which still sees the class even if it is redacted
+            renamer.apply(&mut row);
+            redactor.apply(&mut row);
+            let Some(row) = (match scripts.is_empty() {
+                true => Some(row),
+                false => scripts.row(&headers, row)?,
+            }) else { continue };
+            split[target].push(row);
+        }
+        let exported: Vec<Vec<serde_json::Value>> = match &jsonl {
+            Some(_) => split.iter().flatten().cloned().collect(),
+            None => Vec::new(),
+        };
+        for (target, rows) in targets.iter_mut().zip(split).filter(|(_, rows)| !rows.is_empty()) {
+            // incremental: only the changed records came back, so they update the table already there
+            let rows = match since {
+                Some(_) => {
+                    let sheet = summary.timed_async(label, Phase::Write, target.client.read_sheet(&target.sheet_name)).await
+                        .with_context(|| format!("Failed to read {} to merge the changed records into", target.sheet_name))?;
+                    lists::merge_changed(&sheet, anchor, headers.len(), header.is_some(), rows, lists::key_column(list_type))
+                }
+                None => rows,
+            };
+            write_list_rows(target, the_list_block, rows, header.as_deref(), anchor, named_range.as_deref(), summary).await?;
+        }
+        if let Some(jsonl) = &jsonl {
+            summary.timed_async(label, Phase::Write, jsonl.write(&headers, &exported)).await
+                .with_context(|| format!("Failed to write {}", jsonl.describe()))?;
+        }
+    }
+    // a sheet that got no rows this time still gets its header, and clear_below empties it
+    for target in targets.iter_mut().filter(|t| t.written == 0) {
+        write_list_rows(target, the_list_block, Vec::new(), header.as_deref(), anchor, named_range.as_deref(), summary).await?;
+    }
+    // staged: every batch is on the staging tabs, so each sheet is switched over in one write
+    for target in &targets {
+        let Some(staging) = &target.staging else { continue };
+        summary.timed_async(label, Phase::Write, target.client.promote(
+            staging,
+            &target.sheet_name,
+            &anchor.to_string(),
+            target.written,
+            target.width,
+            the_list_block.clear_below.unwrap_or(false),
+            named_range.as_deref(),
+            )).await?;
+    }
+    log::log!(progress::detail(), "[QBXML] {} query returned {} records", element, records);
+    if routes.is_some() {
+        for target in &targets {
+            log::log!(progress::detail(), "[QBXML] '{}': {} rows to {}!{}", label, target.written, target.client.spreadsheet_id, target.sheet_name);
+        }
+    }
+    summary.set_value(the_list_block.label(), format!("{} records", records));
+
+    // the rows are written either way; at worst the next incremental export repeats them
+    if let Err(e) = ctx.history.update(|h| h.mark_success(the_list_block.label(), started)) {
+        eprintln!("[HISTORY] {:#}", e);
+    }
+    Ok(())
+}
+
+// Which [routing] rule a row's class or category goes to, if any
+type Route<'a> = Box<dyn Fn(&str) -> Option<usize> + 'a>;
+
+// A sheet a list block writes to, and how far down it has got
+struct ListTarget {
+    client: GoogleSheetsClient,
+    sheet_name: String,
+    // rows written so far, header included
+    written: usize,
+    // staged = true: the hidden tab the rows go to until they are promoted, and the widest row so far
+    staging: Option<String>,
+    width: usize,
+}
+
+impl ListTarget {
+    fn new(ctx: &AppContext<'_>, the_list_block: &ListBlockConfig, spreadsheet_id: &str, sheet_name: &str) -> Self {
+        // a new tab gets the mapped columns' headers even when the export's own header row is off
+        let client = ctx.sheets(spreadsheet_id).creating_sheet(
+            the_list_block.create_sheet_if_missing.unwrap_or(false),
+            lists::mapped_headers(the_list_block.list_type, the_list_block.line_items(), the_list_block.currency(), the_list_block.category(), the_list_block.class(), &the_list_block.columns),
+        );
+        let staging = the_list_block.staged.unwrap_or(false).then(|| google_sheets::staging_sheet_name(the_list_block.label(), sheet_name));
+        Self { client, sheet_name: sheet_name.to_string(), written: 0, staging, width: 0 }
+    }
+}
+
+// One batch of a list export, written below the target's earlier batches; the first one starts with
+// the header
+async fn write_list_rows(target: &mut ListTarget, the_list_block: &ListBlockConfig, mut rows: Vec<Vec<serde_json::Value>>, header: Option<&[serde_json::Value]>, anchor: a1::Cell, named_range: Option<&str>, summary: &RunSummary) -> Result<()> {
+    let label = the_list_block.label();
+    let first = target.written == 0;
+    if let Some(header) = header.filter(|_| first) {
+        rows.insert(0, header.to_vec());
+    }
+    let cell = a1::Cell { row: anchor.row + target.written as u32, ..anchor };
+    // clear_below clears from the anchor down, so only the first write may do it
+    let clear_below = first && the_list_block.clear_below.unwrap_or(false);
+    if let Some(staging) = &target.staging {
+        // clear_below and the named range wait for the promote
+        target.width = rows.iter().map(Vec::len).fold(target.width, usize::max);
+        summary.timed_async(label, Phase::Write, target.client.stage_range(&rows, staging, &cell.to_string())).await?;
+    } else if the_list_block.columns.is_empty() {
+        summary.timed_async(label, Phase::Write, target.client.write_range(
+            &rows,
+            &target.sheet_name,
+            &cell.to_string(),
+            clear_below,
+            named_range,
+            )).await?;
+    } else {
+        // columns = { ... }: each run of adjacent mapped columns is its own write
+        for (cell_address, block) in lists::mapped_columns(the_list_block.list_type, the_list_block.line_items(), the_list_block.currency(), the_list_block.category(), the_list_block.class(), &rows, &the_list_block.columns, cell)? {
+            summary.timed_async(label, Phase::Write, target.client.write_range(
+                &block,
+                &target.sheet_name,
+                &cell_address,
+                clear_below,
+                None,
+                )).await?;
+        }
+    }
+    target.written += rows.len();
+    Ok(())
+}
+
+async fn process_list_blocks(session: &QbSession, ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    if config.list_blocks.is_empty() {
+        return;
+    }
+    let (processor, ticket) = (session.processor.as_ref(), &session.ticket);
+    let multicurrency = session.host.preferences.as_ref().map(|p| p.multicurrency);
+    for list_block in config.list_blocks.iter().filter(|b| !b.odbc() && !summary.sits_out(b.label())) {
+        correlation::in_block(async {
+            // without multicurrency every account is in home currency, so the currency columns would
+            // only repeat it; unknown preferences keep the columns
+            let without_currency;
+            let list_block = if list_block.currency() && multicurrency == Some(false) {
+                info!("[QBXML] '{}': multicurrency is off in this company file; leaving out the currency columns", list_block.label());
+                without_currency = ListBlockConfig { include_currency: Some(false), ..list_block.clone() };
+                &without_currency
+            } else {
+                list_block
+            };
+            let result = with_block_timeout(config, process_list_block(Some((processor, ticket.as_str())), list_block, ctx, summary)).await;
+            if let Err(e) = &result {
+                eprintln!("[QBXML] Error processing {} list: {:#}", list_block.list_type.element(), e);
+            }
+            summary.record(list_block.label(), &format!("range {}!{}", list_block.sheet_name, list_block.cell_address), &result);
+            summary.complete(list_block.label());
+        }).await;
+    }
+}
+
+// List blocks with backend = "odbc", through QODBC's own connection; they run with or without a session
+async fn process_odbc_list_blocks(ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    for list_block in config.list_blocks.iter().filter(|b| b.odbc() && !summary.sits_out(b.label())) {
+        correlation::in_block(async {
+            let result = with_block_timeout(config, process_list_block(None, list_block, ctx, summary)).await;
+            if let Err(e) = &result {
+                eprintln!("[ODBC] Error processing {} list: {:#}", list_block.list_type.element(), e);
+            }
+            summary.record(list_block.label(), &format!("range {}!{}", list_block.sheet_name, list_block.cell_address), &result);
+            summary.complete(list_block.label());
+        }).await;
+    }
+}
+
+// Sends the block's request once, then writes each field; every field is recorded on its own
+async fn process_raw_qbxml_block(processor: &dyn RequestProcessor, ticket: &str, the_raw_block: &RawQbxmlBlockConfig, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let config = ctx.config;
+    let mappings = the_raw_block.field_mappings()?;
+    let label = the_raw_block.label();
+    let request = raw_qbxml::request(the_raw_block)?;
+    let response_xml = summary.timed(label, Phase::Query, || processor.process_request(ticket, &request))?;
+    archive::maybe_archive_response(config.debug.as_ref(), &format!("Raw_{}", the_raw_block.name), &response_xml);
+    raw_qbxml::check_status(&response_xml)?;
+
+    // evaluate everything up front; the parsed document isn't Send, so it can't live across the writes
+    let extracted: Vec<Result<Vec<serde_json::Value>>> = summary.timed(label, Phase::Parse, || -> Result<_> {
+        let mut response = extract::Response::parse(&response_xml)?;
+        if config.restricts_accounts() {
+            let dropped = response.drop_accounts(|account| config.account_permitted(account));
+            if dropped > 0 {
+                log::debug!("[QBXML] Raw block '{}': {} record(s) left out by allowed_accounts / denied_accounts", label, dropped);
+            }
+        }
+        Ok(mappings.iter().map(|m| response.values(&m.xpath)).collect())
+    })?;
+
+    let gs_client = ctx.sheets(&the_raw_block.spreadsheet_id)
+        .creating_sheet(the_raw_block.create_sheet_if_missing.unwrap_or(false), BTreeMap::new());
+    for (mapping, values) in mappings.iter().zip(extracted) {
+        let sheet_name = mapping.sheet_name.as_deref().unwrap_or(&the_raw_block.sheet_name);
+        let result = match values {
+            Ok(values) => summary.timed_async(label, Phase::Write, write_raw_field(&gs_client, the_raw_block, mapping, sheet_name, values, summary)).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            eprintln!("[QBXML] Raw block '{}' field '{}' failed: {:#}", the_raw_block.name, mapping.xpath, e);
+        }
+        summary.record(the_raw_block.label(), &format!("sheet {}!{}", sheet_name, mapping.cell_address), &result);
+    }
+    Ok(())
+}
+
+async fn write_raw_field(gs_client: &GoogleSheetsClient, the_raw_block: &RawQbxmlBlockConfig, mapping: &FieldMapping, sheet_name: &str, values: Vec<serde_json::Value>, summary: &RunSummary) -> Result<()> {
+    log::log!(progress::detail(), "[QBXML] Raw block '{}': '{}' gave {} value(s)", the_raw_block.name, mapping.xpath, values.len());
+    match mapping.mode {
+        ExtractMode::First => {
+            let Some(value) = values.first() else {
+                anyhow::bail!("XPath '{}' matched nothing", mapping.xpath);
+            };
+            let text = status_sheet::display_value(value);
+            summary.set_value(the_raw_block.label(), text.as_str());
+            let number = text.trim().parse::<Decimal>().ok();
+            if let (Some(name), Some(number)) = (&mapping.publish, number) {
+                summary.publish(name, number);
+            }
+            // numeric text goes over as a number so sheet formulas can use it
+            match number {
+                Some(number) => gs_client.send_balance(number, Some(sheet_name), Some(&mapping.cell_address)).await?,
+                None => gs_client.send_string(&text, sheet_name, &mapping.cell_address).await?,
+            }
+        }
+        ExtractMode::Column => {
+            summary.set_value(the_raw_block.label(), format!("{} values", values.len()));
+            let rows: Vec<Vec<serde_json::Value>> = values.into_iter().map(|v| vec![v]).collect();
+            gs_client.write_range(
+                &rows,
+                sheet_name,
+                &mapping.cell_address,
+                mapping.clear_below.unwrap_or(false),
+                None,
+                ).await?;
+        }
+    }
+    Ok(())
+}
+
+// the QuickBooks raw blocks, or with `point_of_sale` the Point of Sale ones
+// skip_write_back: the company file is restricted and restricted_mode = "skip_write_back", so blocks
+// whose request would change QuickBooks are left out
+async fn process_raw_qbxml_blocks(processor: &dyn RequestProcessor, ticket: &str, point_of_sale: bool, skip_write_back: bool, ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    let blocks = config.raw_qbxml_blocks.iter().filter(|b| b.is_point_of_sale() == point_of_sale);
+    for raw_block in blocks.filter(|b| !summary.sits_out(b.label())) {
+        // a request that can't be read fails in process_raw_qbxml_block with a better message
+        if skip_write_back && raw_qbxml::request(raw_block).and_then(|r| raw_qbxml::writes_to_quickbooks(&r)).unwrap_or(false) {
+            log::warn!("[QBXML] Skipping raw qbXML block '{}': its request changes QuickBooks and the company file is restricted", raw_block.name);
+            summary.skipped(raw_block.label(), "-", "company file is restricted");
+            continue;
+        }
+        correlation::in_block(async {
+            // field results are recorded as they are written; this is the request / response failing
+            if let Err(e) = with_block_timeout(config, process_raw_qbxml_block(processor, ticket, raw_block, ctx, summary)).await {
+                eprintln!("[QBXML] Error processing raw qbXML block '{}': {:#}", raw_block.name, e);
+                summary.record(raw_block.label(), "-", &Err(e));
+            }
+            // only now: each field is recorded as it is written
+            summary.complete(raw_block.label());
+        }).await;
+    }
+}
+
+async fn process_qbxml(accounts: &AccountIndex, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let config = ctx.config;
+    // computed blocks still see the values of account blocks skipped by --resume
+    for (name, value) in sync_block_values(accounts, ctx) {
+        summary.publish(&name, value);
+    }
+    // Process sync blocks in parallel; computed blocks wait for the report and raw qbXML blocks
+    let account_blocks = account_sync_blocks(ctx, summary);
+    let count = account_blocks.len();
+    let sync_futures = account_blocks.iter().enumerate().map(|(i, sync_block)| async move {
+        // the stagger is waited out before the block's own timeout starts
+        jitter::stagger_start(config, i, count).await;
+        correlation::in_block(async {
+            // a failing block is recorded and the rest of the run carries on; recorded in the block's
+            // scope so the failure keeps its block id
+            if let Err(e) = with_block_timeout(config, process_sync_blocks(accounts, sync_block, ctx, summary)).await {
+                eprintln!("[QBXML] Error processing sync block '{}': {:#}", sync_block.label(), e);
+                summary.record(sync_block.label(), "-", &Err(e));
+            }
+        }).await
+    });
+    join_all(sync_futures).await;
+    for sync_block in &account_blocks {
+        summary.complete(sync_block.label());
+    }
+    Ok(())
+}
+
+// The sync blocks that read an account balance and take part in this run
+fn account_sync_blocks<'a>(ctx: &AppContext<'a>, summary: &RunSummary) -> Vec<&'a AccountSyncConfig> {
+    ctx.config.sync_blocks.iter()
+        .filter(|b| !summary.sits_out(b.label()) && !b.is_computed())
+        .collect()
+}
+
+// Computed blocks go last, in config order, once every value they can refer to has been published
+async fn process_computed_blocks(ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    let computed = config.sync_blocks.iter().filter(|b| b.is_computed() && !summary.sits_out(b.label()));
+    for sync_block in computed {
+        correlation::in_block(async {
+            if let Err(e) = with_block_timeout(config, process_computed_block(sync_block, ctx, summary)).await {
+                eprintln!("[SYNC] Error computing '{}': {:#}", sync_block.label(), e);
+                summary.record(sync_block.label(), "-", &Err(e));
+            }
+        }).await;
+        summary.complete(sync_block.label());
+    }
+}
+
+// Timestamp blocks in parallel; they need nothing from QuickBooks
+async fn process_all_timestamp_blocks(ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    let timestamp_blocks: Vec<&TimestampConfig> = config.timestamp_blocks.iter().filter(|b| !summary.sits_out(&b.label())).collect();
+    let count = timestamp_blocks.len();
+    let timestamp_futures = timestamp_blocks.iter().enumerate().map(|(i, timestamp_block)| async move {
+        jitter::stagger_start(config, i, count).await;
+        correlation::in_block(process_timestamp_blocks(timestamp_block, ctx, summary)).await
     });
     let timestamp_results = join_all(timestamp_futures).await;
-    for result in timestamp_results {
-        result?; // Propagate any error
+    for (timestamp_block, result) in timestamp_blocks.iter().zip(timestamp_results) {
+        if let Err(e) = result {
+            eprintln!("[QBXML] Error processing timestamp block '{}': {:#}", timestamp_block.label(), e);
+        }
+        summary.complete(&timestamp_block.label());
     }
+}
 
+// [company_info]: the details read when the session opened, as a small label / value table
+async fn process_company_info(session: &QbSession, ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    let Some(info_cfg) = &config.company_info else { return };
+    if summary.sits_out(info_cfg.label()) {
+        return;
+    }
+    correlation::in_block(async {
+        let cell_address = info_cfg.cell_address.as_deref().unwrap_or("A1");
+        summary.set_value(info_cfg.label(), session.host.company_name.as_str());
+        let gs_client = ctx.sheets(&info_cfg.spreadsheet_id);
+        let rows = host::rows(&session.host);
+        let write = gs_client.write_range(&rows, &info_cfg.sheet_name, cell_address, false, None);
+        let result = with_block_timeout(config, summary.timed_async(info_cfg.label(), Phase::Write, write)).await;
+        if let Err(e) = &result {
+            eprintln!("[QBXML] Error writing company info: {:#}", e);
+        }
+        summary.record(info_cfg.label(), &format!("range {}!{}", info_cfg.sheet_name, cell_address), &result);
+        summary.complete(info_cfg.label());
+    }).await;
+}
+
+// [deleted_transactions]: what was deleted since the last successful run, appended to the audit tab
+async fn process_deleted_transactions(processor: &dyn RequestProcessor, ticket: &str, ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    let Some(audit) = &config.deleted_transactions else { return };
+    if summary.sits_out(audit.label()) {
+        return;
+    }
+    correlation::in_block(async {
+        let result = with_block_timeout(config, append_deleted_transactions(processor, ticket, audit, ctx, summary)).await;
+        if let Err(e) = &result {
+            eprintln!("[QBXML] Error exporting deleted transactions: {:#}", e);
+        }
+        summary.record(audit.label(), &format!("append {}", audit.sheet_name), &result);
+        summary.complete(audit.label());
+    }).await;
+}
+
+async fn append_deleted_transactions(processor: &dyn RequestProcessor, ticket: &str, audit: &DeletedTransactionsConfig, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let config = ctx.config;
+    let label = audit.label();
+    // taken before the query so deletions made while we run are picked up next time
+    let started = chrono::Local::now();
+    let from = match ctx.history.read(|h| h.last_success(label)) {
+        Some(since) => qbxml::format_datetime(&since),
+        None => dates::resolve_qbxml_datetime(audit.since.as_deref().unwrap_or(deleted_txns::DEFAULT_SINCE), dates::today(config)?)?,
+    };
+    log::log!(progress::detail(), "[QBXML] '{}': transactions deleted since {}", label, from);
+
+    let request = deleted_txns::request(&audit.txn_types, &from)?;
+    let response_xml = summary.timed(label, Phase::Query, || processor.process_request(ticket, &request))?;
+    archive::maybe_archive_response(config.debug.as_ref(), "TxnDeletedQueryRs", &response_xml);
+    let rows = summary.timed(label, Phase::Parse, || deleted_txns::parse(&response_xml))?;
+
+    let gs_client = ctx.sheets(&audit.spreadsheet_id)
+        .creating_sheet(audit.create_sheet_if_missing.unwrap_or(false), deleted_txns::sheet_headers());
+    for row in &rows {
+        summary.timed_async(label, Phase::Write, gs_client.append_row(row, &audit.sheet_name, None)).await?;
+    }
+    summary.set_value(label, format!("{} deleted", rows.len()));
+
+    // only after every row is in; when an append fails the next cycle asks for the same window again,
+    // so the rows before it can appear twice
+    if let Err(e) = ctx.history.update(|h| h.mark_success(label, started)) {
+        eprintln!("[HISTORY] {:#}", e);
+    }
     Ok(())
 }
 
-async fn run_qbxml(config: &Config) -> Result<()> {
-    unsafe {
-        let hr = winapi::um::combaseapi::CoInitializeEx(std::ptr::null_mut(), winapi::um::objbase::COINIT_APARTMENTTHREADED);
-        // We can bail out here if there is a failure because nothing will need to be cleaned up
-        if hr < 0 {
-            return Err(anyhow::anyhow!("Failed to initialize COM system: HRESULT=0x{:08X}", hr));
+// [aging]: open invoice balances per customer and age bucket, as one table
+async fn process_aging(processor: &dyn RequestProcessor, ticket: &str, ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    let Some(aging_cfg) = &config.aging else { return };
+    if summary.sits_out(aging_cfg.label()) {
+        return;
+    }
+    let cell_address = aging_cfg.cell_address.as_deref().unwrap_or("A1");
+    correlation::in_block(async {
+        let result = with_block_timeout(config, write_aging(processor, ticket, aging_cfg, cell_address, ctx, summary)).await;
+        if let Err(e) = &result {
+            eprintln!("[QBXML] Error writing invoice aging: {:#}", e);
+        }
+        summary.record(aging_cfg.label(), &format!("range {}!{}", aging_cfg.sheet_name, cell_address), &result);
+        summary.complete(aging_cfg.label());
+    }).await;
+}
+
+async fn write_aging(processor: &dyn RequestProcessor, ticket: &str, aging_cfg: &AgingConfig, cell_address: &str, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let config = ctx.config;
+    let label = aging_cfg.label();
+    let response_xml = summary.timed(label, Phase::Query, || processor.process_request(ticket, &aging::request()))?;
+    archive::maybe_archive_response(config.debug.as_ref(), "InvoiceQueryRs", &response_xml);
+    let mut invoices = summary.timed(label, Phase::Parse, || aging::parse(&response_xml, config.quickbooks.number_format()?))?;
+    // before bucketing, so customers mapped to one name are totalled together
+    let names = names::NameMap::load(config)?;
+    for invoice in &mut invoices {
+        if let Some(to) = names.rename(&invoice.customer) {
+            invoice.customer = to;
         }
     }
+    let rows = aging::buckets(&invoices, aging_cfg, dates::today(config)?);
+    log::log!(progress::detail(), "[QBXML] '{}': {} open invoices of {} customers", label, invoices.len(), rows.len());
+    summary.set_value(label, format!("{} customers", rows.len()));
+    let gs_client = ctx.sheets(&aging_cfg.spreadsheet_id)
+        .creating_sheet(aging_cfg.create_sheet_if_missing.unwrap_or(false), BTreeMap::new());
+    // the number of customers changes, so rows left from a longer table are cleared
+    summary.timed_async(label, Phase::Write, gs_client.write_range(&aging::to_rows(&rows, aging_cfg), &aging_cfg.sheet_name, cell_address, true, None)).await?;
+    Ok(())
+}
 
-    let processor = match QbxmlRequestProcessor::new() {
-        Ok(processor) => processor,
-        Err(e) => {
-            eprintln!("[QBXML]: Failed to create QBXML request processor: {:#}", e);
-            // YOLO - this is the only cleanup needed at this point in the function
-            unsafe { winapi::um::combaseapi::CoUninitialize();  }
-            return Err(e);
-            },
+// [kpis]: ratios from the classified accounts, each into its own cell
+async fn process_kpis(accounts: &AccountIndex, ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    let Some(kpis_cfg) = &config.kpis else { return };
+    if summary.sits_out(kpis_cfg.label()) {
+        return;
+    }
+    correlation::in_block(async {
+        let result = with_block_timeout(config, write_kpis(accounts, kpis_cfg, ctx, summary)).await;
+        if let Err(e) = &result {
+            eprintln!("[KPI] Error writing KPIs: {:#}", e);
+        }
+        summary.record(kpis_cfg.label(), &format!("sheet {}", kpis_cfg.sheet_name), &result);
+        summary.complete(kpis_cfg.label());
+    }).await;
+}
+
+async fn write_kpis(accounts: &AccountIndex, kpis_cfg: &config::KpisConfig, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let label = kpis_cfg.label();
+    let monthly_burn = match (&kpis_cfg.monthly_burn, kpis_cfg.cells.contains_key("runway_months")) {
+        (Some(burn), true) => Some(expr::evaluate(burn, |name| summary.variable(name)).context("[kpis] monthly_burn")?),
+        _ => None,
+    };
+    let values = kpis::compute(accounts, kpis_cfg, monthly_burn);
+    let gs_client = ctx.sheets(&kpis_cfg.spreadsheet_id);
+    let mut shown = Vec::new();
+    for (kpi, value) in &values {
+        let Some(value) = *value else {
+            // a zero divisor, or no burn: the cell keeps what it had rather than showing #DIV/0!
+            log::warn!("[KPI] {} can't be worked out this cycle (nothing to divide by); leaving its cell as it is", kpi);
+            continue;
+        };
+        summary.publish(kpi, value);
+        summary.timed_async(label, Phase::Write, gs_client.send_balance(value, Some(&kpis_cfg.sheet_name), Some(&kpis_cfg.cells[kpi]))).await?;
+        shown.push(format!("{} {}", kpi, value));
+    }
+    log::log!(progress::detail(), "[KPI] {}", shown.join(", "));
+    summary.set_value(label, shown.join(", "));
+    Ok(())
+}
+
+// [[close_snapshots]] whose close has come: every account's balance into that period's cells
+async fn process_close_snapshots(accounts: &AccountIndex, ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    for snapshot in config.close_snapshots.iter().filter(|s| !summary.sits_out(s.label())) {
+        let label = snapshot.label();
+        let taken = ctx.history.read(|h| h.last_close(label));
+        match close_snapshot::due(snapshot, config, taken) {
+            Ok(close_snapshot::Due::NotYet) => {}
+            Ok(close_snapshot::Due::Take(close)) => {
+                correlation::in_block(async {
+                    if let Err(e) = with_block_timeout(config, process_close_snapshot(accounts, snapshot, close, ctx, summary)).await {
+                        eprintln!("[SNAPSHOT] Error taking '{}': {:#}", label, e);
+                        summary.record(label, "-", &Err(e));
+                    }
+                    summary.complete(label);
+                }).await;
+            }
+            Ok(close_snapshot::Due::Missed(close)) => {
+                log::warn!("[SNAPSHOT] '{}' missed its {} close; skipping it", label, close.format("%Y-%m-%d %H:%M"));
+                summary.skipped(label, "-", &format!("missed the {} close", close.format("%Y-%m-%d")));
+                if let Err(e) = ctx.history.update(|h| h.mark_close(label, close.date())) {
+                    eprintln!("[HISTORY] {:#}", e);
+                }
+            }
+            Err(e) => summary.record(label, "-", &Err(e)),
+        }
+    }
+}
+
+// One close: each account's balance to its own cell, recorded on its own. The close counts as taken
+// once every cell is written; until then each cycle within catch_up_hours tries again.
+async fn process_close_snapshot(accounts: &AccountIndex, snapshot: &config::CloseSnapshotConfig, close: chrono::NaiveDateTime, ctx: &AppContext<'_>, summary: &RunSummary) -> Result<()> {
+    let config = ctx.config;
+    let label = snapshot.label();
+    info!("[SNAPSHOT] Taking '{}' for the {} close", label, close.format("%Y-%m-%d %H:%M"));
+    summary.set_value(label, close.format("%Y-%m-%d").to_string());
+    let gs_client = ctx.sheets(&snapshot.spreadsheet_id);
+    let mut all_written = true;
+    for (i, name) in snapshot.accounts.iter().enumerate() {
+        let (sheet_name, cell_address) = close_snapshot::target(snapshot, close, name, i)?;
+        let result = match accounts.get(name) {
+            Some(account) => {
+                let balance = if config.normalize_signs.unwrap_or(false) { account.normalized_balance() } else { account.balance };
+                summary.timed_async(label, Phase::Write, gs_client.send_balance(balance, Some(&sheet_name), Some(&cell_address))).await
+            }
+            None => Err(anyhow::anyhow!("No account named '{}'", name)),
+        };
+        if let Err(e) = &result {
+            eprintln!("[SNAPSHOT] '{}' -> {} failed: {:#}", label, name, e);
+        }
+        all_written &= result.is_ok();
+        summary.record(label, &format!("sheet {}!{}", sheet_name, cell_address), &result);
+    }
+    if all_written {
+        ctx.history.update(|h| h.mark_close(label, close.date()))?;
+    }
+    Ok(())
+}
+
+// Each reconciliation block's status (and amounts) written; a mismatch fails the block so it's
+// alerted on like any failed write
+async fn process_reconciliation_blocks(accounts: &AccountIndex, ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    for block in config.reconciliation_blocks.iter().filter(|b| !summary.sits_out(b.label())) {
+        let label = block.label();
+        correlation::in_block(async {
+            let result = with_block_timeout(config, async {
+                let books = reconcile::books_balance(block, accounts, config)?;
+                let reconciliation = summary.timed_async(label, Phase::Query, reconcile::reconcile(block, books, ctx)).await?;
+                summary.timed_async(label, Phase::Write, reconcile::write(block, &reconciliation, ctx)).await?;
+                let status = if reconciliation.matches() { "match" } else { "mismatch" };
+                summary.set_value(label, status.to_string());
+                if !reconciliation.matches() {
+                    anyhow::bail!("QuickBooks {} vs source {}, difference {} over tolerance {}",
+                        reconciliation.books, reconciliation.source, reconciliation.difference(), reconciliation.tolerance);
+                }
+                info!("[RECONCILE] '{}' matches ({} vs {})", label, reconciliation.books, reconciliation.source);
+                Ok(())
+            }).await;
+            if let Err(e) = &result {
+                eprintln!("[RECONCILE] '{}': {:#}", label, e);
+            }
+            summary.record(label, &format!("sheet {}!{}", block.sheet_name, block.cell_address), &result);
+            summary.complete(label);
+        }).await;
+    }
+}
+
+// timing label for the one account query every sync block shares
+const ACCOUNT_QUERY: &str = "Account query";
+
+// Every block of one cycle, in order: the account query and the sync / timestamp blocks, then each
+// block type that sends its own request
+async fn process_blocks(session: Option<&QbSession>, ctx: &AppContext<'_>, summary: &RunSummary, account_index: &mut Option<AccountIndex>) {
+    let config = ctx.config;
+    // no session when no block needs QuickBooks data (see Config::needs_quickbooks)
+    let Some(session) = session else {
+        process_all_timestamp_blocks(ctx, summary).await;
+        process_odbc_list_blocks(ctx, summary).await;
+        process_point_of_sale_blocks(ctx, summary).await;
+        return;
     };
-    
-    // AppID isn't used by the QBSDK, if a value is passed in config it is harmless but not used
-    let app_id = config.quickbooks.application_id.as_deref().unwrap_or(""); 
+    let (processor, ticket) = (session.processor.as_ref(), &session.ticket);
 
-    /*  If we ever change the name of the service we register with Quickbooks we'll have 
-    to change this default too in order to ensure the program will work even if the config.toml loses this setting
+    if config.quickbooks.integrity_check.unwrap_or(false) {
+        if let Err(e) = check_integrity(session, config) {
+            log::error!("[INTEGRITY] {:#}; skipping this cycle's blocks", e);
+            for label in config.block_labels() {
+                summary.skipped(&label, "-", "integrity check failed");
+            }
+            return;
+        }
+    }
+
+    /* 
+    ... we'll get the Err and Ok(None) match arms deal with it if the ticket is invalid
     */
-    let app_name = config.quickbooks.application_name.as_deref().unwrap_or("QuickBooks Sync Service"); 
-    
-    if let Ok(()) = processor.open_connection(app_id, app_name) {
-
-        // sets company_file to AUTO if blank, company file name if provided in config.toml
-        let company_file = match config.quickbooks.company_file.as_str() { 
-            "AUTO" => "",
-            path => {
-                println!("[DEBUG] Company file: {}", path);
-                path }
-            };
-    
-        // we could try to check to see if we have an apparenlty valid ticket here but ...
-        let ticket = processor.begin_session(company_file, crate::FileMode::DoNotCare)?;
-
-        /* 
-        ... we'll get the Err and Ok(None) match arms deal with it if the ticket is invalid
-        */
-        match processor.get_account_xml(&ticket) {
-            Ok(Some(response_xml)) => {
-                // for debugging this line shows us what we got from the API
-                // it outputs more lines than are saved in the console so the output has to be routed somewhere to read it
-                // info!("{}", response_xml);
-                
-                // this is it! This is where all the real processing starts!
-                match process_qbxml(&processor, &response_xml, &config).await {
+    match summary.timed(ACCOUNT_QUERY, Phase::Query, || changes::account_xml(session, config)) {
+        Ok(Some(response_xml)) => {
+            // [debug] archive_responses = true keeps a copy of every raw response under logs/qbxml
+            archive::maybe_archive_response(config.debug.as_ref(), "AccountQueryRs", &response_xml);
+
+            // for debugging this line shows us what we got from the API
+            // it outputs more lines than are saved in the console so the output has to be routed somewhere to read it
+            // info!("{}", response_xml);
+            
+            // parsed once here; every sync block looks its account up in the same index
+            let accounts: Result<AccountIndex> = summary.timed(ACCOUNT_QUERY, Phase::Parse, || {
+                let mut index = accounts::index_accounts(&response_xml, config.quickbooks.number_format()?)?;
+                accounts::classify(&mut index, config.account_classification.as_ref());
+                accounts::restrict(&mut index, config);
+                Ok(index)
+            });
+
+            // this is it! This is where all the real processing starts!
+            match &accounts {
+                Ok(index) => match process_qbxml(index, ctx, summary).await {
                     Err(e) => eprintln!("[QBXML] Error processing QBXML: {:#}", e),
                     Ok(()) => eprintln!("[QBXML] Processing succeeded")
-                };
-            },
-            Ok(None) => {
-                eprintln!("[QBXML] No response_xml received, ticket probably invalid");
-            },
-            Err(e) => {
-                /* 
-                we can't exit the function here because it is possible that we have an open connection or have
-                initialized the COM system and we need to try to clean Up before we exit
-                */
-                eprintln!("[QBXML] Error querying Quickbooks: {:#}", e);
+                },
+                // the one parse error is reported against every block that needed it
+                Err(e) => {
+                    eprintln!("[QBXML] Error parsing the account query: {:#}", e);
+                    for sync_block in account_sync_blocks(ctx, summary) {
+                        correlation::in_block(async {
+                            summary.record(sync_block.label(), "-", &Err(anyhow::anyhow!("{:#}", e)));
+                        }).await;
+                        summary.complete(sync_block.label());
+                    }
+                }
+            }
+            process_all_timestamp_blocks(ctx, summary).await;
+            // a close that comes up while the account query fails is taken by the next cycle
+            if let Ok(index) = &accounts {
+                process_close_snapshots(index, ctx, summary).await;
+                process_reconciliation_blocks(index, ctx, summary).await;
             }
+            *account_index = accounts.ok();
+        },
+        Ok(None) => {
+            eprintln!("[QBXML] No response_xml received, ticket probably invalid");
+        },
+        Err(e) => {
+            /* 
+            we can't exit the function here because it is possible that we have an open connection or have
+            initialized the COM system and we need to try to clean Up before we exit
+            */
+            eprintln!("[QBXML] Error querying Quickbooks: {:#}", e);
+        }
+    }
+
+    process_company_info(session, ctx, summary).await;
+    // report and transaction blocks each send their own request: on the same session in turn, or
+    // with [quickbooks] parallel_sessions through a pool of sessions all at once
+    let pending = config.report_blocks.iter().filter(|b| !summary.sits_out(b.label())).count()
+        + config.transaction_blocks.iter().filter(|b| !summary.sits_out(b.label())).count();
+    // boxed: every block's future at once is too big for the main thread's stack
+    match SessionPool::for_cycle(config, pending) {
+        Some(pool) => {
+            let qb = Querier::Pool(&pool);
+            futures::join!(Box::pin(process_report_blocks(qb, ctx, summary)), Box::pin(process_transaction_blocks(qb, ctx, summary)));
+            pool.close();
         }
-        /* 
-        The COM system has returned all sorts of values for tickets when the ticket fails to be created
-        so we can't just assume that we can detect an invalid ticket; we should attempt to close the
-        session regardless of what we got as a ticket.
+        None => {
+            let qb = Querier::Session(processor, ticket);
+            Box::pin(process_report_blocks(qb, ctx, summary)).await;
+            Box::pin(process_transaction_blocks(qb, ctx, summary)).await;
+        }
+    }
+    process_list_blocks(session, ctx, summary).await;
+    process_odbc_list_blocks(ctx, summary).await;
+    process_deleted_transactions(processor, ticket, ctx, summary).await;
+    process_aging(processor, ticket, ctx, summary).await;
+    let skip_write_back = config.quickbooks.restricted_mode == RestrictedMode::SkipWriteBack && session.host.restriction().is_some();
+    process_raw_qbxml_blocks(processor, ticket, false, skip_write_back, ctx, summary).await;
+    process_point_of_sale_blocks(ctx, summary).await;
+    // after the report blocks, whose published values monthly_burn can use, and before the computed
+    // blocks, which can use the KPIs
+    if let Some(index) = account_index.as_ref() {
+        process_kpis(index, ctx, summary).await;
+    }
+    process_computed_blocks(ctx, summary).await;
+}
 
-        We don't want to bail out here in the event of an error because there are still cleanup steps needed
-        */
-        if let Err(e) = processor.end_session(&ticket) {
-            eprintln!("[QBXML] end_session errored: {:#}", e)
+// Raw blocks with point_of_sale = true, in a Point of Sale session of their own
+async fn process_point_of_sale_blocks(ctx: &AppContext<'_>, summary: &RunSummary) {
+    let config = ctx.config;
+    let Some(pos) = &config.point_of_sale else { return };
+    let pending: Vec<&str> = config.raw_qbxml_blocks.iter()
+        .filter(|b| b.is_point_of_sale() && !summary.sits_out(b.label()))
+        .map(|b| b.label())
+        .collect();
+    if pending.is_empty() {
+        return;
+    }
+    let session = match PosSession::open(config, pos) {
+        Ok(session) => session,
+        Err(e) => {
+            log::error!("[POS] {:#}", e);
+            for label in pending {
+                summary.record(label, "-", &Err(anyhow::anyhow!("{:#}", e)));
+            }
+            return;
         }
+    };
+    process_raw_qbxml_blocks(session.processor.as_ref(), &session.ticket, true, false, ctx, summary).await;
+    session.close();
+}
+
+// [quickbooks] integrity_check: Err when QuickBooks' answer to a small query looks like a damaged file
+fn check_integrity(session: &QbSession, config: &Config) -> Result<()> {
+    let response_xml = session.processor.process_request(&session.ticket, &integrity::request())
+        .context("Failed to send the integrity check")?;
+    archive::maybe_archive_response(config.debug.as_ref(), "IntegrityCheckRs", &response_xml);
+    integrity::check(&response_xml, config.quickbooks.number_format()?)?;
+    info!("[INTEGRITY] Company file looks sound");
+    Ok(())
+}
+
+// blocks taking longer than this (query + parse + write) get a warning unless [logging] slow_block_secs says otherwise
+const DEFAULT_SLOW_BLOCK_SECS: u64 = 60;
 
+// One sync cycle against an open session (None when no block needs one), with its own run id;
+// returns its (ok, failed, skipped) outcome counts
+async fn run_cycle(session: Option<&QbSession>, ctx: &AppContext<'_>) -> (usize, usize, usize) {
+    let config = ctx.config;
+    let correlation_ids = config.logging.as_ref().and_then(|l| l.correlation_ids).unwrap_or(true);
+    correlation::in_run(correlation_ids, run_cycle_inner(session, ctx)).await
+}
+
+async fn run_cycle_inner(session: Option<&QbSession>, ctx: &AppContext<'_>) -> (usize, usize, usize) {
+    let config = ctx.config;
+    let started = std::time::SystemTime::now();
+    let summary = RunSummary::new();
+    let (checkpoint, completed_earlier) = resume::Checkpoint::start(config, summary.run_id.clone());
+    let summary = summary.with_checkpoint(checkpoint, completed_earlier)
+        .with_settings(session.map(|s| host::settings(&s.host)).unwrap_or_default())
+        .with_inactive(config.inactive_blocks(window_now(config)))
+        .with_ran_this_period(ctx.history.ran_this_period(config, window_now(config)));
+    if let Some(run_id) = &summary.run_id {
+        info!("[QBXML] Starting run {}", run_id);
+    }
+    let total_blocks = progress::start_cycle(config);
+    ctx.sheet_faults.reset();
+    // rows an earlier cycle couldn't deliver go first, so they land before this cycle's
+    offline_queue::flush(ctx).await;
+    let mut skip_blocks = false;
+    if let Some(hooks_cfg) = &config.hooks {
+        if let Err(e) = hooks::pre_sync(hooks_cfg, &summary).await {
+            skip_blocks = hooks_cfg.stop_on_pre_sync_failure.unwrap_or(false);
+            if skip_blocks {
+                log::error!("[HOOK] {:#}; skipping this cycle's blocks", e);
+            } else {
+                log::warn!("[HOOK] {:#}; running the cycle anyway", e);
+            }
+        }
+    }
+    let mut account_index = None;
+    if skip_blocks {
+        for label in config.block_labels().iter().filter(|label| !summary.sits_out(label)) {
+            summary.skipped(label, "-", "pre_sync hook failed");
+        }
+    } else {
+        let blocks = progress::with_ticks(process_blocks(session, ctx, &summary, &mut account_index), &summary, total_blocks, config);
+        match config.timeouts.as_ref().and_then(|t| t.cycle_secs) {
+            Some(secs) => {
+                // dropping the blocks future cancels whatever is still running; cleanup below still happens
+                if tokio::time::timeout(Duration::from_secs(secs), blocks).await.is_err() {
+                    log::warn!("[SYNC] Cycle deadline of {}s exceeded; cancelling the remaining blocks", secs);
+                    for label in config.block_labels() {
+                        if !summary.has_block(&label) {
+                            summary.skipped(&label, "-", "deadline exceeded");
+                        }
+                    }
+                }
+            }
+            None => blocks.await,
+        }
     }
 
+    // per-block / per-destination results for this run
+    summary.log();
+    dashboard::record(&summary);
+    summary.log_timings(Duration::from_secs(config.logging.as_ref().and_then(|l| l.slow_block_secs).unwrap_or(DEFAULT_SLOW_BLOCK_SECS)));
+    for (spreadsheet_id, reason) in ctx.sheet_faults.isolated() {
+        log::warn!("[SHEETS] Spreadsheet {} stopped being written to this cycle; check its id and sharing. It failed with: {}", spreadsheet_id, reason);
+    }
+    if let Err(e) = reliability::record(&summary, ctx) {
+        eprintln!("[HISTORY] Failed to record this run's outcomes: {:#}", e);
+    }
+    if config.watchdog.is_some() || !config.block_frequencies().is_empty() {
+        if let Err(e) = watchdog::record(&summary, ctx) {
+            eprintln!("[HISTORY] Failed to record this run's clean blocks: {:#}", e);
+        }
+    }
+    let diff_cfg = config.balance_diff.as_ref().filter(|d| !summary.sits_out(d.label()));
+    if let (Some(diff_cfg), Some(accounts)) = (diff_cfg, &account_index) {
+        if let Err(e) = balance_diff::after_cycle(accounts, ctx, diff_cfg).await {
+            eprintln!("[DIFF] Failed to record balances: {:#}", e);
+        }
+    }
+    if let Some(drive_cfg) = &config.google_drive {
+        drive::upload_artifacts(ctx, drive_cfg).await;
+    }
+    if let Some(status_cfg) = &config.status_sheet {
+        if let Err(e) = status_sheet::write(&summary, status_cfg, ctx).await {
+            eprintln!("[SUMMARY] Failed to write status sheet: {:#}", e);
+        }
+    }
+    if let Some(hooks_cfg) = &config.hooks {
+        hooks::post_sync(hooks_cfg, &summary).await;
+    }
+    if let Some(telemetry_cfg) = &config.telemetry {
+        telemetry::export(&ctx.http, &summary, telemetry_cfg, started).await;
+    }
+    summary.finish_checkpoint();
+    summary.counts()
+}
+
+// Runs a cycle unless a stop is requested first; dropping the cycle cancels its pending Sheets posts.
+// A qbXML request already in flight still completes, since the COM call blocks this thread.
+// None when the cycle was cancelled.
+async fn run_cycle_until_stopped(session: Option<&QbSession>, ctx: &AppContext<'_>) -> Option<(usize, usize, usize)> {
+    tokio::select! {
+        counts = run_cycle(session, ctx) => Some(counts),
+        _ = shutdown::requested() => {
+            log::warn!("[SHUTDOWN] Cycle cancelled");
+            None
+        }
+    }
+}
+
+// [quickbooks.circuit_breaker] notify, once each time the circuit has opened
+async fn announce_open_circuit(config: &Config) {
+    let Some(breaker) = &config.quickbooks.circuit_breaker else { return };
+    if let Some(reason) = circuit::take_opened(config) {
+        let cooldown_secs = breaker.cooldown_secs.unwrap_or(circuit::DEFAULT_COOLDOWN_SECS);
+        hooks::circuit_open(&breaker.notify, &reason, cooldown_secs).await;
+    }
+}
+
+// One cycle in its own QuickBooks session; the cycle's counts as in run_cycle_until_stopped
+async fn run_qbxml(ctx: &AppContext<'_>) -> Result<Option<(usize, usize, usize)>> {
+    let result = run_qbxml_session(ctx).await;
+    announce_open_circuit(ctx.config).await;
+    result
+}
+
+async fn run_qbxml_session(ctx: &AppContext<'_>) -> Result<Option<(usize, usize, usize)>> {
+    let config = ctx.config;
+    // a heartbeat-only config has no reason to start COM or wait on QuickBooks
+    if !config.needs_quickbooks() {
+        info!("[QBXML] No block needs QuickBooks data; running without a QuickBooks session");
+        return Ok(run_cycle_until_stopped(None, ctx).await);
+    }
+    // nor does one whose QuickBooks blocks are all outside their active hours
+    if !config.needs_quickbooks_at(window_now(config)) {
+        info!("[QBXML] Every block needing QuickBooks is outside its active hours; running without a QuickBooks session");
+        return Ok(run_cycle_until_stopped(None, ctx).await);
+    }
+    let session = QbSession::open(config)?;
+    let counts = run_cycle_until_stopped(Some(&session), ctx).await;
+
     /* 
     Begin cleanup. Because it is hard to test earlier to see if we have a valid state for COM 
     we have to try to clean up everything just in case something managed to open or initialize even if
     running process_qbxml() failed
     */
+    session.close();
 
     /* 
-    We want to try to continue clean up even if this fails
-    I think this could happen if the connection was not open but the COM system was initialized
+    THis is a pretty unhelpful Ok(()) tbh; it really just means the program didn't crash not that
+    it actually achieved its objectives
     */
-    if let Err(e) = processor.close_connection() {
-        eprintln!("[QBXML] close_connection errored: {:#}", e);
+    Ok(counts)
+}
+
+// The time active_hours are compared with: [quickbooks] timezone, or local time when that is invalid
+fn window_now(config: &Config) -> chrono::NaiveDateTime {
+    dates::now(config).unwrap_or_else(|_| chrono::Local::now().naive_local())
+}
+
+// how often an open session is poked between cycles when keepalive_secs isn't set
+const DEFAULT_KEEPALIVE_SECS: u64 = 60;
+const DEFAULT_BUSY_RETRY_SECS: u64 = 120;
+
+// Sleeps until `deadline`, answering [local_api] and [dashboard] requests in the meantime; false
+// means a stop was requested and the daemon should stop
+async fn wait_until(deadline: Instant, api: &mut Option<mpsc::Receiver<local_api::Call>>, session: Option<&QbSession>, ctx: &AppContext<'_>) -> bool {
+    loop {
+        let next_call = async {
+            match api {
+                Some(queue) => queue.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => return true,
+            _ = shutdown::requested() => return false,
+            Some(call) = next_call => serve_api_call(call, session, ctx).await,
+        }
     }
+}
 
-    /*
-    YOLO
-    */
-    unsafe { winapi::um::combaseapi::CoUninitialize(); }
+// A [local_api] request, answered on the daemon's open session or, without keep_session_open, on a
+// session opened just for it
+async fn serve_api_call(call: local_api::Call, session: Option<&QbSession>, ctx: &AppContext<'_>) {
+    let config = ctx.config;
+    let result = match session {
+        Some(open) => api_request(&call.request, open, ctx).await,
+        None => match QbSession::open(config) {
+            Ok(temporary) => {
+                let result = api_request(&call.request, &temporary, ctx).await;
+                temporary.close();
+                result
+            }
+            Err(e) => Err(e),
+        },
+    };
+    if let Err(e) = &result {
+        log::warn!("[API] {:?} failed: {:#}", call.request, e);
+    }
+    call.respond(result);
+}
 
-    /* 
-    THis is a pretty unhelpful Ok(()) tbh; it really just means the program didn't crash not that
-    it actually achieved its objectives
-    */
+async fn api_request(request: &local_api::Request, session: &QbSession, ctx: &AppContext<'_>) -> Result<serde_json::Value> {
+    let config = ctx.config;
+    let response_xml = session.processor.get_account_xml(&session.ticket)?
+        .context("No response to the account query, ticket probably invalid")?;
+    let mut index = accounts::index_accounts(&response_xml, config.quickbooks.number_format()?)?;
+    accounts::classify(&mut index, config.account_classification.as_ref());
+    match request {
+        local_api::Request::GetAccountBalance { account } => {
+            let found = index.get(account).with_context(|| format!("No account named '{}'", account))?;
+            Ok(serde_json::to_value(found)?)
+        }
+        local_api::Request::ListAccounts => Ok(serde_json::to_value(dump::sorted(&index))?),
+        local_api::Request::RunSyncBlock { name } => {
+            let block = config.sync_blocks.iter()
+                .find(|b| b.label() == name)
+                .with_context(|| format!("No sync block named '{}'", name))?;
+            // this writes to the block's destinations, so the same accounts are kept out as in a cycle
+            accounts::restrict(&mut index, config);
+            let summary = RunSummary::new();
+            if block.is_computed() {
+                // computed blocks can refer to earlier computed blocks, so those are evaluated (not written)
+                // first; values published by report and raw qbXML blocks aren't available here
+                for (name, value) in sync_block_values(&index, ctx) {
+                    summary.publish(&name, value);
+                }
+                for earlier in config.sync_blocks.iter().take_while(|b| b.label() != name).filter(|b| b.is_computed()) {
+                    let result = expr::evaluate(earlier.expression.as_deref().unwrap_or_default(), |n| summary.variable(n))?;
+                    summary.publish(earlier.label(), result);
+                }
+                with_block_timeout(config, process_computed_block(block, ctx, &summary)).await?;
+            } else {
+                with_block_timeout(config, process_sync_blocks(&index, block, ctx, &summary)).await?;
+            }
+            summary.log();
+            dashboard::record(&summary);
+            Ok(local_api::outcomes_json(&summary.outcomes()))
+        }
+    }
+}
+
+// [schedule] daemon mode: run a cycle every interval_secs until stopped
+async fn run_daemon(ctx: &AppContext<'_>, schedule: &ScheduleConfig) -> Result<()> {
+    let config = ctx.config;
+    let interval = Duration::from_secs(schedule.interval_secs.max(1));
+    let keep_session_open = schedule.keep_session_open.unwrap_or(false);
+    let keepalive = Duration::from_secs(schedule.keepalive_secs.unwrap_or(DEFAULT_KEEPALIVE_SECS).max(1));
+    let busy_retry = Duration::from_secs(schedule.busy_retry_secs.unwrap_or(DEFAULT_BUSY_RETRY_SECS).max(1));
+    let jitter_secs = schedule.jitter_secs.unwrap_or(0);
+    info!("[SCHEDULE] Running every {}s{}{}", interval.as_secs(),
+        if jitter_secs > 0 { format!(" plus up to {}s of jitter", jitter_secs) } else { String::new() },
+        if keep_session_open { " with the QuickBooks session kept open" } else { "" });
+    // looking for changes needs a session to ask on
+    let watch = match schedule.watch_changes_secs {
+        Some(_) if !keep_session_open => {
+            log::warn!("[SCHEDULE] watch_changes_secs needs keep_session_open = true; only the interval applies");
+            None
+        }
+        Some(secs) => {
+            info!("[SCHEDULE] Looking for changes in QuickBooks every {}s", secs.max(1));
+            Some(Duration::from_secs(secs.max(1)))
+        }
+        None => None,
+    };
+
+    // [local_api] and [dashboard] requests, answered between cycles
+    let (calls, queue) = local_api::queue();
+    if let Some(api_cfg) = &config.local_api {
+        local_api::start(api_cfg, calls.clone())?;
+    }
+    if let Some(dashboard_cfg) = &config.dashboard {
+        dashboard::start(dashboard_cfg, config, calls.clone()).await?;
+    }
+    let mut api = (config.local_api.is_some() || config.dashboard.is_some()).then_some(queue);
+    let mut session: Option<QbSession> = None;
+    let mut stale_watch = config.watchdog.as_ref().map(|w| {
+        info!("[WATCHDOG] Flagging {}!{} once data is over {} minutes old", w.sheet_name, w.cell_address, w.stale_after_mins);
+        watchdog::Watchdog::start()
+    });
+    let mut tray_icon = config.tray.as_ref().and_then(tray::Tray::start);
+    loop {
+        // changes made from here on, even during the cycle, are new to the watch
+        let cycle_started = chrono::Local::now();
+        let mut next_cycle = Instant::now() + interval + jitter::random_delay(jitter_secs);
+        // a close snapshot doesn't wait for the interval
+        if let Some(until_close) = close_snapshot::until_next(config) {
+            next_cycle = next_cycle.min(Instant::now() + until_close);
+        }
+
+        // someone else holding the company file isn't a failure; try again sooner than the interval,
+        // with a warning toast when [tray] is on
+        let busy = |e: &anyhow::Error| {
+            if !com_error::is_busy(e) {
+                return false;
+            }
+            log::warn!("[SCHEDULE] QuickBooks is busy, cycle postponed by {}s: {:#}", busy_retry.as_secs(), e);
+            true
+        };
+
+        let quickbooks_active = config.needs_quickbooks_at(window_now(config));
+        if !quickbooks_active {
+            // the company file may be locked for a backup now; let go of it until the blocks are back
+            if let Some(open) = session.take() {
+                info!("[SCHEDULE] Outside the active hours of every QuickBooks block; closing the session");
+                open.close();
+            }
+        }
+        let outcome = if keep_session_open && quickbooks_active {
+            // reuse the session from the last cycle, reconnecting if QuickBooks dropped it
+            let reopened = match session.take() {
+                Some(open) => open.reopen_if_dropped(config),
+                None => QbSession::open(config),
+            };
+            let outcome = match reopened {
+                Ok(open) => {
+                    let counts = run_cycle_until_stopped(Some(&open), ctx).await;
+                    session = Some(open);
+                    counts.map(tray::Outcome::Ran)
+                }
+                Err(e) if busy(&e) => {
+                    next_cycle = Instant::now() + busy_retry;
+                    Some(tray::Outcome::Busy(busy_reason(busy_retry)))
+                }
+                Err(e) => {
+                    eprintln!("[SCHEDULE] Could not open a QuickBooks session: {:#}", e);
+                    Some(tray::Outcome::Failed(format!("Could not open a QuickBooks session: {:#}", e)))
+                }
+            };
+            announce_open_circuit(config).await;
+            outcome
+        } else {
+            match run_qbxml(ctx).await {
+                Ok(counts) => counts.map(tray::Outcome::Ran),
+                Err(e) if busy(&e) => {
+                    next_cycle = Instant::now() + busy_retry;
+                    Some(tray::Outcome::Busy(busy_reason(busy_retry)))
+                }
+                Err(e) => {
+                    eprintln!("[SCHEDULE] Cycle failed: {:#}", e);
+                    Some(tray::Outcome::Failed(format!("{:#}", e)))
+                }
+            }
+        };
+        if let (Some(tray_icon), Some(outcome)) = (tray_icon.as_mut(), &outcome) {
+            tray_icon.after_cycle(outcome);
+        }
+        // after every attempt, so a cycle that couldn't even start still shows up as stale data
+        if let (Some(watch), Some(watchdog_cfg)) = (stale_watch.as_mut(), &config.watchdog) {
+            watch.check(watchdog_cfg, ctx).await;
+        }
+
+        // between cycles, poke the open session so QuickBooks doesn't time it out; when watching,
+        // the poke is the look for changes, and finding any starts the next cycle now
+        let mut running = !shutdown::is_requested();
+        if let Some(open) = &session {
+            let step = watch.map_or(keepalive, |watch| watch.min(keepalive));
+            while running && Instant::now() + step < next_cycle {
+                running = wait_until(Instant::now() + step, &mut api, Some(open), ctx).await;
+                if running {
+                    let poked = match watch {
+                        Some(_) => changes::any_since(open, &cycle_started),
+                        None => open.keep_alive().map(|()| false),
+                    };
+                    match poked {
+                        Ok(false) => {}
+                        Ok(true) => {
+                            info!("[SCHEDULE] QuickBooks data changed; starting the next cycle now");
+                            next_cycle = Instant::now();
+                            break;
+                        }
+                        Err(e) => {
+                            log::warn!("[SCHEDULE] Keep-alive failed, will reconnect next cycle: {:#}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        if running {
+            running = wait_until(next_cycle, &mut api, session.as_ref(), ctx).await;
+        }
+        if !running {
+            info!("[SCHEDULE] Stopping");
+            if let Some(open) = session.take() {
+                open.close();
+            }
+            return Ok(());
+        }
+    }
+}
+
+// Runs each tenant of a --config-dir in turn and logs a line per tenant at the end. Like a single
+// config's run, false only when a tenant could not be loaded or run, not for failed blocks.
+async fn run_tenants(dir: &std::path::Path, cli: &cli::Cli) -> bool {
+    let tenants = match tenants::discover(dir) {
+        Ok(tenants) => tenants,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            return false;
+        }
+    };
+    let env_name = cli.env.as_deref().filter(|e| !e.is_empty());
+    let mut results = Vec::new();
+    for tenant in &tenants {
+        if shutdown::is_requested() {
+            results.push((&tenant.name, Err(anyhow::anyhow!("not run, stopping"))));
+            continue;
+        }
+        info!("[TENANT] ===== {} ({}) =====", tenant.name, tenant.path.display());
+        let result = match tenants::load(tenant, env_name, &cli.config_overrides()) {
+            Err(e) => Err(e.context("config failed to load")),
+            Ok(config) => run_qbxml(&AppContext::new(&config)).await
+                .and_then(|counts| counts.ok_or_else(|| anyhow::anyhow!("cancelled"))),
+        };
+        results.push((&tenant.name, result));
+    }
+    for (name, result) in &results {
+        match result {
+            Ok((ok, 0, skipped)) => info!("[TENANT] {:<20} {} ok, 0 failed, {} skipped", name, ok, skipped),
+            Ok((ok, failed, skipped)) => log::warn!("[TENANT] {:<20} {} ok, {} failed, {} skipped", name, ok, failed, skipped),
+            Err(e) => log::error!("[TENANT] {:<20} {:#}", name, e),
+        }
+    }
+    results.iter().all(|(_, result)| result.is_ok())
+}
+
+fn load_config(cli: &cli::Cli) -> Result<Config> {
+    // --env prod (or QB_SYNC_ENV=prod) layers config/config.prod.toml over config/config.toml
+    let env_name = cli.env.as_deref().filter(|e| !e.is_empty());
+    let config = Config::load(&cli.config, env_name, &cli.config_overrides())?;
+    close_snapshot::validate(&config)?;
+    Ok(config)
+}
+
+// the text of the [tray] warning when a daemon cycle is postponed
+fn busy_reason(retry: Duration) -> String {
+    format!("The company file is in use, perhaps open in single-user mode; trying again in {}s", retry.as_secs())
+}
+
+// `qb_sync selftest`: a line per check, carrying on after a failure so one run shows everything
+// that needs fixing
+async fn selftest(cli: &cli::Cli) -> Result<()> {
+    let config = load_config(cli)?;
+    println!("[ OK ] Config {} loads, {} block(s)", cli.config.display(), config.block_labels().len());
+    let mut failed = 0;
+    if config.needs_quickbooks() {
+        match QbSession::open(&config) {
+            Ok(session) => {
+                println!("[ OK ] QuickBooks: {} with {} open", session.host.product_name, session.host.company_file);
+                session.close();
+            }
+            Err(e) => {
+                println!("[FAIL] QuickBooks: {:#}", e);
+                failed += 1;
+            }
+        }
+    } else {
+        println!("[SKIP] QuickBooks: no block needs it");
+    }
+    // a read changes nothing, and still needs the webapp URL, api_key and the tab to be right
+    let ctx = AppContext::new(&config);
+    let mut sheets: Vec<(&str, &str)> = config.sync_blocks.iter()
+        .filter(|b| !b.spreadsheet_id.is_empty() && !b.sheet_name.is_empty())
+        .map(|b| (b.spreadsheet_id.as_str(), b.sheet_name.as_str()))
+        .collect();
+    sheets.sort();
+    sheets.dedup();
+    for (spreadsheet_id, sheet_name) in sheets {
+        match ctx.sheets(spreadsheet_id).read_range(sheet_name, "A1").await {
+            Ok(_) => println!("[ OK ] Webapp: sheet '{}' of {}", sheet_name, spreadsheet_id),
+            Err(e) => {
+                println!("[FAIL] Webapp: sheet '{}' of {}: {:#}", sheet_name, spreadsheet_id, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} check(s) failed", failed);
+    }
     Ok(())
 }
 
+async fn run_command(command: &cli::Command, cli: &cli::Cli) -> Result<()> {
+    match command {
+        // handled in main: they are the sync itself
+        cli::Command::Run | cli::Command::Daemon => unreachable!("run and daemon aren't one-off commands"),
+        cli::Command::ValidateConfig => {
+            let Some(dir) = &cli.config_dir else {
+                let config = load_config(cli)?;
+                println!("{} is valid: {} block(s)", cli.config.display(), config.block_labels().len());
+                return Ok(());
+            };
+            let env_name = cli.env.as_deref().filter(|e| !e.is_empty());
+            let mut invalid = 0;
+            for tenant in tenants::discover(dir)? {
+                match tenants::load(&tenant, env_name, &cli.config_overrides()) {
+                    Ok(config) => println!("{} is valid: {} block(s)", tenant.path.display(), config.block_labels().len()),
+                    Err(e) => {
+                        println!("{} is invalid: {:#}", tenant.path.display(), e);
+                        invalid += 1;
+                    }
+                }
+            }
+            if invalid > 0 {
+                anyhow::bail!("{} tenant config(s) failed to load", invalid);
+            }
+            Ok(())
+        }
+        cli::Command::Selftest => selftest(cli).await,
+        cli::Command::Completions { shell } => {
+            clap_complete::generate(*shell, &mut cli::Cli::command(), "qb_sync", &mut std::io::stdout());
+            Ok(())
+        }
+        cli::Command::EncryptSecret { method, recipient, machine, value } => {
+            let plaintext = match value {
+                Some(value) => value.clone(),
+                None => {
+                    eprintln!("Enter the secret, then press Enter:");
+                    secrets::read_line_hidden()?
+                }
+            };
+            if plaintext.is_empty() {
+                anyhow::bail!("nothing to encrypt");
+            }
+            println!("{}", secrets::seal(&plaintext, *method, recipient.as_deref(), *machine)?);
+            Ok(())
+        }
+        cli::Command::Diff { min_change } => {
+            let config = load_config(cli)?;
+            let min_change = min_change
+                .or(config.balance_diff.as_ref().and_then(|d| d.min_change))
+                .unwrap_or(balance_diff::DEFAULT_MIN_CHANGE);
+            let history = History::load(&History::path(&config))?;
+            let changes = balance_diff::changes(&history, min_change);
+            if changes.is_empty() {
+                println!("No balance changes between the last two runs");
+            }
+            for line in balance_diff::lines(&changes) {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        cli::Command::Report { report: cli::Report::Reliability { days } } => {
+            let config = load_config(cli)?;
+            let history = History::load(&History::path(&config))?;
+            let first_day = chrono::Local::now().date_naive() - chrono::Days::new(u64::from(*days - 1));
+            let report = reliability::summarize(&history, first_day);
+            if report.is_empty() {
+                println!("No runs recorded in the last {} day(s)", days);
+            } else {
+                println!("Last {} day(s), since {}:", days, first_day);
+                for line in reliability::lines(&report) {
+                    println!("{}", line);
+                }
+            }
+            Ok(())
+        }
+        cli::Command::ImportBlocks { csv, from_sheet, tab, dry_run } => {
+            let rows = match (csv, from_sheet) {
+                (Some(path), _) => import_blocks::from_csv(path)?,
+                (None, Some(spreadsheet_id)) => {
+                    // only [google_sheets] is needed, but the whole config has to load to get it
+                    let config = load_config(cli)?;
+                    import_blocks::from_sheet(&AppContext::new(&config), spreadsheet_id, tab).await?
+                }
+                (None, None) => anyhow::bail!("give a CSV file or --from-sheet"),
+            };
+            // the base file itself, not the merged config: overlays and --set stay out of it
+            let text = std::fs::read_to_string(&cli.config)
+                .with_context(|| format!("Failed to read {}", cli.config.display()))?;
+            let (merged_text, merged) = import_blocks::merge(&text, &rows)?;
+            if *dry_run {
+                print!("{}", merged_text);
+            } else if merged.added + merged.updated > 0 {
+                import_blocks::save(&cli.config, &merged_text)?;
+            }
+            eprintln!("{} row(s): {} block(s) added, {} updated, {} already up to date{}",
+                rows.len(), merged.added, merged.updated, merged.unchanged,
+                if *dry_run { " (dry run, nothing saved)" } else { "" });
+            Ok(())
+        }
+        cli::Command::DeployWebapp { credentials, script_id, title, dry_run } => {
+            // like import-blocks, the base file as written; it needn't load yet on a new install
+            let text = std::fs::read_to_string(&cli.config)
+                .with_context(|| format!("Failed to read {}", cli.config.display()))?;
+            let (configured_id, webapp_url) = deploy_webapp::current(&text)?;
+            let script_id = script_id.as_deref().or(configured_id.as_deref());
+            if *dry_run {
+                // stop before signing in: every API call after that changes the project
+                println!("{}", deploy_webapp::plan(script_id, webapp_url.as_deref(), title)?);
+                eprintln!("Dry run: nothing deployed, config not saved");
+                return Ok(());
+            }
+            let http = reqwest::Client::new();
+            let token = deploy_webapp::sign_in(&http, credentials).await?;
+            let deployed = deploy_webapp::deploy(&http, &token, script_id, webapp_url.as_deref(), title).await?;
+            let updated = deploy_webapp::update_config(&text, &deployed)?;
+            if updated != text {
+                import_blocks::save(&cli.config, &updated)?;
+            }
+            eprintln!("Web app deployed: {}", deployed.url);
+            if deployed.created {
+                eprintln!("New project: open https://script.google.com/d/{}/edit, run setupPermissions() and then \
+                    setupQuickBooksIntegration(), and put the API key it logs in [google_sheets] api_key", deployed.script_id);
+            }
+            Ok(())
+        }
+        cli::Command::Dump { format, output } => {
+            let config = load_config(cli)?;
+            let session = QbSession::open(&config)?;
+            // close the session whatever the query returned
+            let response = session.processor.get_account_xml(&session.ticket);
+            session.close();
+            let response_xml = response?.context("No response to the account query, ticket probably invalid")?;
+            let mut index = accounts::index_accounts(&response_xml, config.quickbooks.number_format()?)?;
+            accounts::classify(&mut index, config.account_classification.as_ref());
+            dump::write_to(&dump::sorted(&index), *format, output.as_deref())?;
+            if let Some(path) = output {
+                eprintln!("Wrote {} accounts to {}", index.len(), path.display());
+            }
+            Ok(())
+        }
+        cli::Command::ListAccounts { account_type } => {
+            let config = load_config(cli)?;
+            let session = QbSession::open(&config)?;
+            let response = session.processor.get_account_xml(&session.ticket);
+            session.close();
+            let response_xml = response?.context("No response to the account query, ticket probably invalid")?;
+            let index = accounts::index_accounts(&response_xml, config.quickbooks.number_format()?)?;
+            let mut listed = dump::sorted(&index);
+            if let Some(account_type) = account_type {
+                listed.retain(|a| a.account_type.eq_ignore_ascii_case(account_type));
+            }
+            for line in dump::lines(&listed) {
+                println!("{}", line);
+            }
+            eprintln!("{} account(s)", listed.len());
+            Ok(())
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     // Parse arguments
-    let args: Vec<String> = env::args().collect();
-    let verbose = args.iter().any(|a| a == "--verbose" || a == "-v");
+    let cli = cli::Cli::parse();
 
-    if verbose {
+    let level = if cli.verbose {
         print_instructions();
-        env_logger::builder().filter_level(log::LevelFilter::Debug).init();
+        log::LevelFilter::Debug
+    } else if cli.quiet {
+        log::LevelFilter::Warn
     } else {
-        env_logger::builder().filter_level(log::LevelFilter::Info).init();
+        log::LevelFilter::Info
+    };
+    // the default env_logger layout plus the run / block correlation ids when there are any
+    env_logger::builder()
+        .filter_level(level)
+        // progress and totals lines stay on under --quiet
+        .filter_module(progress::TARGET, level.max(log::LevelFilter::Info))
+        .format(|buf, record| {
+            writeln!(buf, "[{} {:<5} {}{}] {}", buf.timestamp(), record.level(), record.target(), correlation::log_suffix(), record.args())
+        })
+        .init();
+
+    // from here on Ctrl+C / console close lets the current cycle wind down and close QuickBooks cleanly
+    shutdown::install();
+    if cli.resume {
+        resume::request();
+    }
+
+    // --record / --replay
+    let capture = match (&cli.record, &cli.replay) {
+        (Some(dir), _) => Some((capture::Mode::Record, dir)),
+        (None, Some(dir)) => Some((capture::Mode::Replay, dir)),
+        (None, None) => None,
+    };
+    if let Some((mode, dir)) = capture {
+        if !matches!(cli.command, None | Some(cli::Command::Run)) {
+            eprintln!("Error: --record and --replay are for a single sync run, not daemon or another subcommand");
+            std::process::exit(1);
+        }
+        if let Err(e) = capture::start(mode, dir) {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // run and daemon are the sync itself (Some(false) / Some(true); None without a subcommand leaves
+    // it to [schedule]). The rest are one-off utilities; dump, list-accounts and selftest talk to
+    // QuickBooks, and none of them sync
+    let daemon = match &cli.command {
+        None => None,
+        Some(cli::Command::Run) => Some(false),
+        Some(cli::Command::Daemon) => Some(true),
+        Some(command) => {
+            if let Err(e) = run_command(command, &cli).await {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+    };
+
+    // --config-dir: every tenant config in the directory, once each
+    if let Some(dir) = &cli.config_dir {
+        if daemon == Some(true) {
+            eprintln!("Error: --config-dir runs each tenant once; schedule it with Task Scheduler instead of daemon");
+            std::process::exit(1);
+        }
+        if !run_tenants(dir, &cli).await {
+            std::process::exit(1);
+        }
+        return;
     }
+
     // Load configuration
-    let config = match Config::load_from_file("config/config.toml") {
+    let config = match load_config(&cli) {
         Ok(cfg) => cfg,
         Err(e) => {
             eprintln!("Error: {:#}", e);
@@ -232,8 +1985,24 @@ async fn main() {
             std::process::exit(1);
         }
     };
-    // Do the work
-    match run_qbxml(&config).await {
+    // Do the work; a [schedule] section keeps running cycles instead of exiting after one, except
+    // when recording or replaying, which are about a single run, or with `run`
+    let schedule = match daemon {
+        Some(true) => match &config.schedule {
+            Some(schedule) => Some(schedule),
+            None => {
+                eprintln!("Error: daemon needs a [schedule] section with interval_secs in {}", cli.config.display());
+                std::process::exit(1);
+            }
+        },
+        Some(false) => None,
+        None => config.schedule.as_ref().filter(|_| capture.is_none()),
+    };
+    let result = match schedule {
+        Some(schedule) => run_daemon(&AppContext::new(&config), schedule).await,
+        None => run_qbxml(&AppContext::new(&config)).await.map(|_| ()),
+    };
+    match result {
       Err(e) => {
             eprintln!("Error: {:#}", e);
             std::process::exit(1);
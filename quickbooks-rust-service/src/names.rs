@@ -0,0 +1,109 @@
+// [name_mapping]: customer, vendor and payee names renamed on export from a two-column CSV
+// (from,to), so "ACME Corp" and "Acme Corporation" both reach the sheet as "Acme" and a pivot or
+// dashboard groups them as one
+//
+// Names match ignoring case and runs of spaces. A Customer:Job name whose customer is mapped keeps
+// its jobs ("ACME Corp:Roof" -> "Acme:Roof"), unless the full name has a mapping of its own. The
+// file is read at the start of each block, so edits apply from the next sync without a restart.
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::config::{Config, NameMappingConfig};
+use crate::lists;
+
+// the entity columns of list, transaction and aging exports, when `columns` isn't set
+pub const DEFAULT_COLUMNS: &[&str] = &["Name", "Full Name", "Parent", "Customer", "Vendor", "Payee", "Received From"];
+
+/// from -> to, keyed by `key`
+#[derive(Debug, Clone, Default)]
+pub struct NameMap {
+    names: HashMap<String, String>,
+}
+
+impl NameMap {
+    /// Reads [name_mapping] file; no [name_mapping] is an empty map
+    pub fn load(config: &Config) -> Result<Self> {
+        let Some(mapping) = &config.name_mapping else { return Ok(Self::default()) };
+        let text = std::fs::read_to_string(&mapping.file)
+            .with_context(|| format!("Failed to read [name_mapping] file {}", mapping.file))?;
+        Self::parse(&text).with_context(|| format!("[name_mapping] file {}", mapping.file))
+    }
+
+    /// A header row of from,to (any case) is skipped; blank lines are ignored
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).trim(csv::Trim::All).from_reader(text.as_bytes());
+        let mut names = HashMap::new();
+        for (line, record) in reader.records().enumerate() {
+            let record = record?;
+            let (from, to) = (record.get(0).unwrap_or_default(), record.get(1).unwrap_or_default());
+            if from.is_empty() && to.is_empty() || line == 0 && from.eq_ignore_ascii_case("from") && to.eq_ignore_ascii_case("to") {
+                continue;
+            }
+            if from.is_empty() || to.is_empty() || record.len() > 2 {
+                bail!("line {} should be two names, from,to", line + 1);
+            }
+            if let Some(earlier) = names.insert(key(from), to.to_string()).filter(|earlier| earlier != to) {
+                bail!("'{}' is mapped to both '{}' and '{}'", from, earlier, to);
+            }
+        }
+        Ok(Self { names })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The mapped name, or None when `name` has no mapping
+    pub fn rename(&self, name: &str) -> Option<String> {
+        if let Some(to) = self.names.get(&key(name)) {
+            return Some(to.clone());
+        }
+        let (customer, job) = name.split_once(':')?;
+        self.names.get(&key(customer)).map(|to| format!("{}:{}", to, job))
+    }
+}
+
+fn key(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// A NameMap resolved to the mapped columns of one export
+#[derive(Debug, Clone, Default)]
+pub struct Renamer {
+    map: NameMap,
+    columns: Vec<usize>,
+}
+
+impl Renamer {
+    /// The columns of `headers` named in [name_mapping] columns (or DEFAULT_COLUMNS); a configured
+    /// column the export doesn't have is skipped, as one file serves every block
+    pub fn new(map: NameMap, headers: &[&str], mapping: Option<&NameMappingConfig>) -> Self {
+        let wanted: Vec<String> = match mapping.filter(|m| !m.columns.is_empty()) {
+            Some(m) => m.columns.iter().map(|c| lists::field_key(c)).collect(),
+            None => DEFAULT_COLUMNS.iter().map(|c| lists::field_key(c)).collect(),
+        };
+        let columns = match map.is_empty() {
+            true => Vec::new(),
+            false => headers.iter().enumerate().filter(|(_, h)| wanted.contains(&lists::field_key(h))).map(|(i, _)| i).collect(),
+        };
+        Self { map, columns }
+    }
+
+    /// Renames one data row in place; indented Customer:Job names keep their indent
+    pub fn apply(&self, row: &mut [Value]) {
+        for index in &self.columns {
+            let Some(Value::String(text)) = row.get_mut(*index) else { continue };
+            let indent = text.len() - text.trim_start().len();
+            if let Some(to) = self.map.rename(text) {
+                *text = format!("{}{}", &text[..indent], to);
+            }
+        }
+    }
+}
+
+/// The renamer for an export with these headers, reading the mapping file afresh
+pub fn for_headers(headers: &[&str], config: &Config) -> Result<Renamer> {
+    Ok(Renamer::new(NameMap::load(config)?, headers, config.name_mapping.as_ref()))
+}
@@ -0,0 +1,173 @@
+// [odbc]: read-only SQL against the company file through the QODBC driver
+//
+// List blocks with backend = "odbc" are read this way instead of through the qbXML SDK session:
+// QODBC pages through a large table far faster than one huge qbXML response. The driver only reads
+// here. It keeps its own connection to QuickBooks, so these blocks run whether or not an SDK session
+// is open. Every value is fetched as
+// text, NULL as None; lists::odbc_row turns a row into the same cells the qbXML export writes.
+// --record keeps each query's rows and --replay hands them back without touching the driver.
+
+use anyhow::Result;
+
+use crate::capture;
+use crate::config::OdbcConfig;
+
+/// Runs `sql` and hands `each` every row, its columns in SELECT order; an error from `each` stops
+/// the fetch
+pub fn query(cfg: &OdbcConfig, sql: &str, mut each: impl FnMut(Vec<Option<String>>) -> Result<()>) -> Result<()> {
+    if let Some(rows) = capture::replayed_odbc(sql) {
+        return rows?.into_iter().try_for_each(each);
+    }
+    if !capture::recording_odbc() {
+        return driver::query(&cfg.connection_string, sql, each);
+    }
+    // only a query that finished is recorded
+    let mut rows = Vec::new();
+    driver::query(&cfg.connection_string, sql, |row| {
+        rows.push(row.clone());
+        each(row)
+    })?;
+    capture::record_odbc(sql, &rows);
+    Ok(())
+}
+
+#[cfg(feature = "qb-com")]
+mod driver {
+    use anyhow::{anyhow, bail, Context, Result};
+    use std::ptr;
+    use winapi::um::sql::{
+        SQLAllocHandle, SQLDisconnect, SQLFetch, SQLFreeHandle, SQLGetData, SQLNumResultCols, SQLSetEnvAttr, SQL_HANDLE_DBC,
+        SQL_HANDLE_ENV, SQL_HANDLE_STMT, SQL_NO_DATA, SQL_NULL_DATA, SQL_NULL_HANDLE, SQL_SUCCESS, SQL_SUCCESS_WITH_INFO,
+    };
+    use winapi::um::sqlext::{SQL_ATTR_ODBC_VERSION, SQL_DRIVER_NOPROMPT, SQL_OV_ODBC3};
+    use winapi::um::sqltypes::{SQLHANDLE, SQLINTEGER, SQLLEN, SQLRETURN, SQLSMALLINT, SQLWCHAR};
+    use winapi::um::sqlucode::{SQLDriverConnectW, SQLExecDirectW, SQLGetDiagRecW, SQL_C_WCHAR};
+
+    // freed (and disconnected) in reverse order however the query ends
+    struct Handle {
+        kind: SQLSMALLINT,
+        handle: SQLHANDLE,
+        connected: bool,
+    }
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            unsafe {
+                if self.connected {
+                    SQLDisconnect(self.handle);
+                }
+                SQLFreeHandle(self.kind, self.handle);
+            }
+        }
+    }
+
+    fn ok(code: SQLRETURN) -> bool {
+        code == SQL_SUCCESS || code == SQL_SUCCESS_WITH_INFO
+    }
+
+    fn wide(text: &str) -> Vec<SQLWCHAR> {
+        text.encode_utf16().collect()
+    }
+
+    // the driver's first diagnostic record, e.g. "[QODBC] Table not found"
+    fn diagnostic(handle: &Handle) -> String {
+        let mut state = [0 as SQLWCHAR; 6];
+        let mut native: SQLINTEGER = 0;
+        let mut message = [0 as SQLWCHAR; 1024];
+        let mut length: SQLSMALLINT = 0;
+        let code = unsafe {
+            SQLGetDiagRecW(handle.kind, handle.handle, 1, state.as_mut_ptr(), &mut native, message.as_mut_ptr(), message.len() as SQLSMALLINT, &mut length)
+        };
+        if !ok(code) {
+            return "no details from the driver".to_string();
+        }
+        let length = (length.max(0) as usize).min(message.len());
+        format!("{} (SQLSTATE {})", String::from_utf16_lossy(&message[..length]), String::from_utf16_lossy(&state[..5]))
+    }
+
+    fn check(code: SQLRETURN, handle: &Handle, what: &str) -> Result<()> {
+        if ok(code) {
+            Ok(())
+        } else {
+            bail!("{}: {}", what, diagnostic(handle))
+        }
+    }
+
+    fn alloc(kind: SQLSMALLINT, parent: Option<&Handle>) -> Result<Handle> {
+        let mut handle: SQLHANDLE = SQL_NULL_HANDLE;
+        let code = unsafe { SQLAllocHandle(kind, parent.map_or(SQL_NULL_HANDLE, |p| p.handle), &mut handle) };
+        if !ok(code) {
+            return Err(match parent {
+                Some(parent) => anyhow!("SQLAllocHandle failed: {}", diagnostic(parent)),
+                None => anyhow!("SQLAllocHandle failed; is the ODBC driver manager installed?"),
+            });
+        }
+        Ok(Handle { kind, handle, connected: false })
+    }
+
+    // one column of the current row as text; long values come in several pieces
+    fn column_text(statement: &Handle, column: u16) -> Result<Option<String>> {
+        let mut text: Vec<SQLWCHAR> = Vec::new();
+        let mut buffer = [0 as SQLWCHAR; 4096];
+        loop {
+            let mut indicator: SQLLEN = 0;
+            let code = unsafe {
+                SQLGetData(statement.handle, column, SQL_C_WCHAR, buffer.as_mut_ptr().cast(), std::mem::size_of_val(&buffer) as SQLLEN, &mut indicator)
+            };
+            if code == SQL_NO_DATA {
+                break;
+            }
+            check(code, statement, "SQLGetData failed")?;
+            if indicator == SQL_NULL_DATA {
+                return Ok(None);
+            }
+            // the piece fills the buffer but for its terminator when more is to come; a driver that
+            // can't tell the total length (SQL_NO_TOTAL) always has more
+            let available = usize::try_from(indicator).map_or(usize::MAX, |bytes| bytes / std::mem::size_of::<SQLWCHAR>());
+            let units = (buffer.len() - 1).min(available);
+            text.extend_from_slice(&buffer[..units]);
+            if code == SQL_SUCCESS {
+                break;
+            }
+        }
+        Ok(Some(String::from_utf16_lossy(&text)))
+    }
+
+    pub fn query(connection_string: &str, sql: &str, mut each: impl FnMut(Vec<Option<String>>) -> Result<()>) -> Result<()> {
+        let env = alloc(SQL_HANDLE_ENV, None)?;
+        check(unsafe { SQLSetEnvAttr(env.handle, SQL_ATTR_ODBC_VERSION, SQL_OV_ODBC3 as usize as _, 0) }, &env, "SQLSetEnvAttr failed")?;
+        let mut connection = alloc(SQL_HANDLE_DBC, Some(&env))?;
+        let text = wide(connection_string);
+        let code = unsafe {
+            SQLDriverConnectW(connection.handle, ptr::null_mut(), text.as_ptr(), text.len() as SQLSMALLINT, ptr::null_mut(), 0, ptr::null_mut(), SQL_DRIVER_NOPROMPT)
+        };
+        check(code, &connection, "Couldn't connect through [odbc] connection_string")?;
+        connection.connected = true;
+
+        let statement = alloc(SQL_HANDLE_STMT, Some(&connection))?;
+        let text = wide(sql);
+        check(unsafe { SQLExecDirectW(statement.handle, text.as_ptr(), text.len() as SQLINTEGER) }, &statement, "The ODBC query failed")
+            .with_context(|| format!("Query: {}", sql))?;
+        let mut columns: SQLSMALLINT = 0;
+        check(unsafe { SQLNumResultCols(statement.handle, &mut columns) }, &statement, "SQLNumResultCols failed")?;
+        loop {
+            let code = unsafe { SQLFetch(statement.handle) };
+            if code == SQL_NO_DATA {
+                return Ok(());
+            }
+            check(code, &statement, "SQLFetch failed")?;
+            let row = (1..=columns.max(0) as u16).map(|column| column_text(&statement, column)).collect::<Result<Vec<_>>>()?;
+            each(row)?;
+        }
+    }
+}
+
+// the ODBC driver manager is Windows only here
+#[cfg(not(feature = "qb-com"))]
+mod driver {
+    use anyhow::{bail, Result};
+
+    pub fn query(_connection_string: &str, _sql: &str, _each: impl FnMut(Vec<Option<String>>) -> Result<()>) -> Result<()> {
+        bail!("backend = \"odbc\" needs the Windows build")
+    }
+}
@@ -0,0 +1,162 @@
+// [google_sheets] offline_queue: append-mode rows that couldn't reach Google at all (no connection,
+// DNS failure, timeout after every retry) are kept in a journal file instead of being lost, and sent
+// in order at the start of the next cycle
+//
+// Only appends are queued: a cell or range write that missed a cycle is simply written again, with
+// newer data, by the next one, but a missed history row would leave a gap for good. While rows are
+// waiting, new appends join the end of the queue rather than overtaking them. A queued row the
+// webapp refuses once it can be reached (a deleted tab, a changed API key) is dropped with an error,
+// since it would block every row behind it.
+//
+// The journal holds one payload per line, without the API key, which is added back when it is sent.
+// Webhook-style destination headers aren't kept either; queued rows are sent with the
+// [google_sheets] headers only.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::config::Config;
+use crate::context::AppContext;
+
+// How long the webapp (Code.ts IDEMPOTENCY_KEEP_DAYS) remembers an append's idempotency key. A row
+// that timed out but did land, queued for longer than this, is added a second time when it is sent
+const KEY_KEPT_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub queued_at: DateTime<Local>,
+    pub spreadsheet_id: String,
+    // the payload as it would have been posted, minus apiKey
+    pub body: serde_json::Value,
+}
+
+/// The journal file; one per config, shared by every client through AppContext
+#[derive(Debug)]
+pub struct OfflineQueue {
+    path: PathBuf,
+    // pushes from concurrent blocks and the flush's rewrite go through here one at a time
+    lock: Mutex<()>,
+}
+
+impl OfflineQueue {
+    /// The queue configured for `config`, if any
+    pub fn open(config: &Config) -> Option<Arc<Self>> {
+        let path = config.google_sheets.offline_queue.as_ref()?;
+        Some(Arc::new(Self { path: PathBuf::from(path), lock: Mutex::new(()) }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether rows are waiting; new appends queue behind them
+    pub fn has_pending(&self) -> bool {
+        std::fs::metadata(&self.path).is_ok_and(|m| m.len() > 0)
+    }
+
+    /// Adds a payload at the end of the journal; returns how many are now waiting
+    pub fn push(&self, spreadsheet_id: &str, body: &serde_json::Value) -> Result<usize> {
+        let mut body = body.clone();
+        if let Some(fields) = body.as_object_mut() {
+            fields.remove("apiKey");
+        }
+        let entry = Entry { queued_at: Local::now(), spreadsheet_id: spreadsheet_id.to_string(), body };
+        let line = serde_json::to_string(&entry)?;
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        writeln!(file, "{}", line).with_context(|| format!("Failed to write {}", self.path.display()))?;
+        file.sync_all().ok();
+        Ok(read_entries(&self.path)?.len())
+    }
+
+    /// Everything waiting, oldest first
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        read_entries(&self.path)
+    }
+
+    // Drops the first `count` entries, keeping any pushed since they were read
+    fn remove_first(&self, count: usize) -> Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let rest: Vec<Entry> = read_entries(&self.path)?.into_iter().skip(count).collect();
+        let mut text = String::new();
+        for entry in &rest {
+            text.push_str(&serde_json::to_string(entry)?);
+            text.push('\n');
+        }
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, text).with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path).with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+// A line cut short by a crash mid-write can only be the last one; it is skipped
+fn read_entries(path: &Path) -> Result<Vec<Entry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                log::warn!("[QUEUE] Skipping an unreadable line of {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// Sends the queued rows in order, stopping at the first that still can't reach Google; what was
+/// sent (or refused) leaves the journal
+pub async fn flush(ctx: &AppContext<'_>) {
+    let Some(queue) = &ctx.offline_queue else { return };
+    let entries = match queue.entries() {
+        Ok(entries) if entries.is_empty() => return,
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!("[QUEUE] {:#}", e);
+            return;
+        }
+    };
+    log::info!("[QUEUE] Sending {} rows queued while Google was unreachable (oldest from {})",
+        entries.len(), entries[0].queued_at.format("%Y-%m-%d %H:%M"));
+    if Local::now() - entries[0].queued_at > chrono::Duration::days(KEY_KEPT_DAYS) {
+        log::warn!("[QUEUE] Rows queued more than {} days ago are past the webapp's duplicate check; \
+            any of them that did reach Google before timing out will be added again", KEY_KEPT_DAYS);
+    }
+    let mut done = 0;
+    for entry in &entries {
+        match ctx.sheets(&entry.spreadsheet_id).replay(&entry.body).await {
+            Ok(true) => done += 1,
+            Ok(false) => break,
+            Err(e) => {
+                log::error!("[QUEUE] Dropping a row queued at {} for spreadsheet {}: {:#}",
+                    entry.queued_at.format("%Y-%m-%d %H:%M"), entry.spreadsheet_id, e);
+                done += 1;
+            }
+        }
+    }
+    if done < entries.len() {
+        log::warn!("[QUEUE] Google is still unreachable; {} rows stay queued in {}", entries.len() - done, queue.path().display());
+    } else {
+        log::info!("[QUEUE] Every queued row has been sent");
+    }
+    if done > 0 {
+        if let Err(e) = queue.remove_first(done) {
+            // sent again next time; the webapp skips the ones whose idempotency key it still has
+            // (see KEY_KEPT_DAYS)
+            log::error!("[QUEUE] {:#}", e);
+        }
+    }
+}
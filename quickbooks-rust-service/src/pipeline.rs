@@ -0,0 +1,66 @@
+// [pipeline]: row exports stream from the parser to the destination instead of being parsed whole
+// before the first write
+//
+// The response is parsed on a blocking thread that hands rows over a bounded channel in batches of
+// batch_rows. Each batch is written as soon as it arrives, so the first rows are on their way while
+// the rest are still being parsed. At most queue_batches parsed batches wait for their write; when
+// the writes fall behind, the parser waits for them instead of holding the whole export in memory.
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::config::PipelineConfig;
+
+const DEFAULT_BATCH_ROWS: usize = 5_000;
+const DEFAULT_QUEUE_BATCHES: usize = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    pub batch_rows: usize,
+    pub queue_batches: usize,
+}
+
+impl Settings {
+    pub fn from_config(cfg: Option<&PipelineConfig>) -> Self {
+        Self {
+            batch_rows: cfg.and_then(|c| c.batch_rows).unwrap_or(DEFAULT_BATCH_ROWS).max(1),
+            queue_batches: cfg.and_then(|c| c.queue_batches).unwrap_or(DEFAULT_QUEUE_BATCHES).max(1),
+        }
+    }
+
+    /// Everything in one batch, for writes that have to cover all the rows at once
+    pub fn unbatched(self) -> Self {
+        Self { batch_rows: usize::MAX, ..self }
+    }
+}
+
+/// Batches of rows as `produce` passes them to its callback, which it runs on a blocking thread.
+/// A failed parse arrives as the last message. Dropping the receiver stops the parse at its next
+/// row.
+pub fn batches<F>(settings: Settings, produce: F) -> mpsc::Receiver<Result<Vec<Vec<Value>>>>
+where
+    F: FnOnce(&mut dyn FnMut(Vec<Value>) -> Result<()>) -> Result<()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(settings.queue_batches);
+    tokio::task::spawn_blocking(move || {
+        let mut batch = Vec::new();
+        let result = produce(&mut |row| {
+            batch.push(row);
+            if batch.len() >= settings.batch_rows {
+                // blocks here while queue_batches batches are already waiting
+                sender.blocking_send(Ok(std::mem::take(&mut batch)))
+                    .map_err(|_| anyhow!("the writer stopped"))?;
+            }
+            Ok(())
+        });
+        let last = match result {
+            Ok(()) if batch.is_empty() => return,
+            Ok(()) => Ok(batch),
+            Err(e) => Err(e),
+        };
+        // nobody to tell when the writer has already given up
+        let _ = sender.blocking_send(last);
+    });
+    receiver
+}
@@ -0,0 +1,56 @@
+// An open QuickBooks Point of Sale connection + session, for [[raw_qbxml_blocks]] with
+// point_of_sale = true
+//
+// Point of Sale has its own SDK (QBPOSXMLRPLib.RequestProcessor, qbposXML requests) but the same
+// open / begin / process / end / close sequence as the desktop one. The session is opened for the
+// POS blocks of a cycle and closed straight after them; it never stays open between cycles.
+
+use anyhow::{Context, Result};
+
+use crate::config::{Config, PointOfSaleConfig};
+use crate::file_mode::FileMode;
+use crate::qb_backend::{self, RequestProcessor};
+
+pub struct PosSession {
+    pub processor: Box<dyn RequestProcessor>,
+    pub ticket: String,
+}
+
+impl PosSession {
+    pub fn open(config: &Config, pos: &PointOfSaleConfig) -> Result<Self> {
+        let processor = qb_backend::connect_point_of_sale(config)?;
+        let app_name = pos.application_name.as_deref()
+            .or(config.quickbooks.application_name.as_deref())
+            .unwrap_or("QuickBooks Sync Service");
+        if let Err(e) = processor.open_connection("", app_name) {
+            Self::cleanup(processor.as_ref());
+            return Err(e.context("Failed to connect to QuickBooks Point of Sale"));
+        }
+        match processor.begin_session(&pos.connection_string, FileMode::DoNotCare)
+            .with_context(|| format!("Point of Sale refused the connection string '{}'", pos.connection_string))
+        {
+            Ok(ticket) => {
+                log::info!("[POS] Connected to Point of Sale");
+                Ok(Self { processor, ticket })
+            }
+            Err(e) => {
+                Self::cleanup(processor.as_ref());
+                Err(e)
+            }
+        }
+    }
+
+    pub fn close(self) {
+        if let Err(e) = self.processor.end_session(&self.ticket) {
+            eprintln!("[POS] end_session errored: {:#}", e)
+        }
+        Self::cleanup(self.processor.as_ref());
+    }
+
+    fn cleanup(processor: &dyn RequestProcessor) {
+        if let Err(e) = processor.close_connection() {
+            eprintln!("[POS] close_connection errored: {:#}", e);
+        }
+        qb_backend::release();
+    }
+}
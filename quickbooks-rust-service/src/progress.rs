@@ -0,0 +1,67 @@
+// Progress logging for large configs: with hundreds of blocks, a few info lines per block bury
+// everything else
+//
+// While a cycle runs, a [PROGRESS] line every [logging] progress_secs says how far it has got
+// ("120/200 blocks complete, 3 errors"). Configs with more than detail_max_blocks blocks log the
+// per-block lines (query row counts, balances, the OK lines of the summary, [TIMING]) at debug
+// level instead of info; errors, warnings and the totals are always logged. --quiet keeps only
+// warnings, errors and the progress / totals lines.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::summary::RunSummary;
+
+/// Log target of the progress and totals lines, which --quiet still shows
+pub const TARGET: &str = "qb_sync::progress";
+
+// a tick at most this often; the first one waits a full interval, so short cycles print none
+pub const DEFAULT_PROGRESS_SECS: u64 = 30;
+pub const DEFAULT_DETAIL_MAX_BLOCKS: usize = 50;
+
+// set at the start of each cycle; tenants run one after another, so one flag serves them all
+static DETAIL: AtomicBool = AtomicBool::new(true);
+
+/// The level of per-block detail lines in the current cycle: info, or debug for a large config
+pub fn detail() -> log::Level {
+    if DETAIL.load(Ordering::Relaxed) {
+        log::Level::Info
+    } else {
+        log::Level::Debug
+    }
+}
+
+/// Decides the detail level for a cycle of `config`
+pub fn start_cycle(config: &Config) -> usize {
+    let blocks = config.block_labels().len();
+    let max = config.logging.as_ref().and_then(|l| l.detail_max_blocks).unwrap_or(DEFAULT_DETAIL_MAX_BLOCKS);
+    DETAIL.store(blocks <= max, Ordering::Relaxed);
+    if blocks > max {
+        log::info!(target: TARGET, "[PROGRESS] {} blocks (more than detail_max_blocks = {}); per-block lines are logged at debug level", blocks, max);
+    }
+    blocks
+}
+
+/// Runs `blocks`, logging progress against `total` blocks every [logging] progress_secs until it ends
+pub async fn with_ticks<F: Future>(blocks: F, summary: &RunSummary, total: usize, config: &Config) -> F::Output {
+    let secs = config.logging.as_ref().and_then(|l| l.progress_secs).unwrap_or(DEFAULT_PROGRESS_SECS);
+    if secs == 0 || total == 0 {
+        return blocks.await;
+    }
+    let every = Duration::from_secs(secs);
+    tokio::pin!(blocks);
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+    loop {
+        tokio::select! {
+            output = &mut blocks => return output,
+            _ = ticks.tick() => log_progress(summary, total),
+        }
+    }
+}
+
+fn log_progress(summary: &RunSummary, total: usize) {
+    let (done, failed) = summary.block_progress();
+    log::info!(target: TARGET, "[PROGRESS] {}/{} blocks complete, {} {}", done, total, failed, if failed == 1 { "error" } else { "errors" });
+}
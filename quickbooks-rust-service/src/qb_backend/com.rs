@@ -0,0 +1,134 @@
+// The real backend: COM initialization, the QBXMLRP2 and Point of Sale request processors and the
+// process list
+
+use anyhow::{anyhow, bail, Result};
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::tlhelp32::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS};
+
+use super::RequestProcessor;
+use crate::file_mode::FileMode;
+use crate::qbxml_safe::qbxml_request_processor::QbxmlRequestProcessor;
+
+pub fn connect() -> Result<Box<dyn RequestProcessor>> {
+    initialize()?;
+    match QbxmlRequestProcessor::new() {
+        Ok(processor) => Ok(Box::new(processor)),
+        Err(e) => {
+            eprintln!("[QBXML]: Failed to create QBXML request processor: {:#}", e);
+            // YOLO - this is the only cleanup needed at this point in the function
+            release();
+            Err(e)
+        }
+    }
+}
+
+pub fn connect_point_of_sale() -> Result<Box<dyn RequestProcessor>> {
+    initialize()?;
+    match QbxmlRequestProcessor::point_of_sale() {
+        Ok(processor) => Ok(Box::new(PointOfSaleProcessor(processor))),
+        Err(e) => {
+            release();
+            Err(e.context("Is the QuickBooks Point of Sale SDK installed?"))
+        }
+    }
+}
+
+// CoInitializeEx may be called again on a thread that already has COM; each call pairs with a release()
+fn initialize() -> Result<()> {
+    unsafe {
+        let hr = winapi::um::combaseapi::CoInitializeEx(std::ptr::null_mut(), winapi::um::objbase::COINIT_APARTMENTTHREADED);
+        // We can bail out here if there is a failure because nothing will need to be cleaned up
+        if hr < 0 {
+            return Err(anyhow!("Failed to initialize COM system: HRESULT=0x{:08X}", hr));
+        }
+    }
+    Ok(())
+}
+
+pub fn release() {
+    /*
+    YOLO
+    */
+    unsafe { winapi::um::combaseapi::CoUninitialize(); }
+}
+
+impl RequestProcessor for QbxmlRequestProcessor {
+    fn open_connection(&self, app_id: &str, app_name: &str) -> Result<()> {
+        QbxmlRequestProcessor::open_connection(self, app_id, app_name)
+    }
+
+    fn begin_session(&self, company_file: &str, file_mode: FileMode) -> Result<String> {
+        QbxmlRequestProcessor::begin_session(self, company_file, file_mode)
+    }
+
+    fn process_request(&self, ticket: &str, request: &str) -> Result<String> {
+        QbxmlRequestProcessor::process_request(self, ticket, request)
+    }
+
+    fn end_session(&self, ticket: &str) -> Result<()> {
+        QbxmlRequestProcessor::end_session(self, ticket)
+    }
+
+    fn close_connection(&self) -> Result<()> {
+        QbxmlRequestProcessor::close_connection(self)
+    }
+
+    fn get_current_company_file_name(&self, ticket: &str) -> Result<String> {
+        QbxmlRequestProcessor::get_current_company_file_name(self, ticket)
+    }
+}
+
+// QBPOSXMLRPLib.RequestProcessor; begin_session's company_file is the connection string
+struct PointOfSaleProcessor(QbxmlRequestProcessor);
+
+impl RequestProcessor for PointOfSaleProcessor {
+    fn open_connection(&self, app_id: &str, app_name: &str) -> Result<()> {
+        self.0.open_connection(app_id, app_name)
+    }
+
+    fn begin_session(&self, connection_string: &str, _file_mode: FileMode) -> Result<String> {
+        self.0.begin_pos_session(connection_string)
+    }
+
+    fn process_request(&self, ticket: &str, request: &str) -> Result<String> {
+        self.0.process_request(ticket, request)
+    }
+
+    fn end_session(&self, ticket: &str) -> Result<()> {
+        self.0.end_session(ticket)
+    }
+
+    fn close_connection(&self) -> Result<()> {
+        self.0.close_connection()
+    }
+
+    // Point of Sale has no such method; nothing asks a POS session for it
+    fn get_current_company_file_name(&self, _ticket: &str) -> Result<String> {
+        Ok(String::new())
+    }
+}
+
+pub fn process_running(process_names: &[&str]) -> Result<bool> {
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            bail!("CreateToolhelp32Snapshot failed");
+        }
+        let mut entry: PROCESSENTRY32W = std::mem::zeroed();
+        entry.dwSize = std::mem::size_of::<PROCESSENTRY32W>() as u32;
+
+        let mut found = false;
+        let mut more = Process32FirstW(snapshot, &mut entry) != 0;
+        while more {
+            let len = entry.szExeFile.iter().position(|&c| c == 0).unwrap_or(entry.szExeFile.len());
+            let name = String::from_utf16_lossy(&entry.szExeFile[..len]);
+            if process_names.iter().any(|p| p.eq_ignore_ascii_case(&name)) {
+                found = true;
+                break;
+            }
+            more = Process32NextW(snapshot, &mut entry) != 0;
+        }
+        CloseHandle(snapshot);
+        Ok(found)
+    }
+}
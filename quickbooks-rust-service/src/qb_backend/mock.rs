@@ -0,0 +1,119 @@
+// The mock backend: answers each request element with a recorded response named after it
+//
+// AccountQueryRq is answered with AccountQueryRs.xml, CustomerQueryRq with CustomerQueryRs.xml and
+// so on, the naming fixtures/ already uses; a report query is answered by its report type
+// (ProfitAndLossStandardRs.xml) before its element name. A request with several elements gets
+// their responses in one envelope. Recorded responses come from [debug] mock_responses, fixtures/
+// by default, or any [debug] archive_dir of real responses renamed the same way; Point of Sale's
+// qbposXML responses from its pos/ subdirectory.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+use super::RequestProcessor;
+use crate::config::Config;
+use crate::file_mode::FileMode;
+use crate::qbxml;
+
+const DEFAULT_RESPONSES_DIR: &str = "fixtures";
+pub const POINT_OF_SALE_DIR: &str = "pos";
+const TICKET: &str = "mock-session";
+
+pub fn responses_dir(config: &Config) -> PathBuf {
+    PathBuf::from(config.debug.as_ref().and_then(|d| d.mock_responses.as_deref()).unwrap_or(DEFAULT_RESPONSES_DIR))
+}
+
+#[derive(Debug, Clone)]
+pub struct MockProcessor {
+    dir: PathBuf,
+}
+
+impl MockProcessor {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // the first recorded file among `names`, as (file name, text)
+    fn recorded(&self, names: &[String]) -> Option<(String, String)> {
+        names.iter().find_map(|name| {
+            let file = format!("{}.xml", name);
+            std::fs::read_to_string(self.dir.join(&file)).ok().map(|text| (file, text))
+        })
+    }
+}
+
+// "AccountQueryRq" -> ["AccountQueryRs"]; a report query -> [its report type + "Rs", its element + "Rs"]
+fn response_names(request: roxmltree::Node) -> Vec<String> {
+    let element = request.tag_name().name();
+    let mut names: Vec<String> = request.children()
+        .filter(|n| n.is_element() && n.tag_name().name().ends_with("ReportType"))
+        .filter_map(|n| n.text())
+        .map(|report_type| format!("{}Rs", report_type.trim()))
+        .collect();
+    names.push(format!("{}Rs", element.strip_suffix("Rq").unwrap_or(element)));
+    names
+}
+
+impl RequestProcessor for MockProcessor {
+    fn open_connection(&self, _app_id: &str, _app_name: &str) -> Result<()> {
+        if !self.dir.is_dir() {
+            bail!("The mock backend's responses directory {} doesn't exist; set [debug] mock_responses", self.dir.display());
+        }
+        Ok(())
+    }
+
+    fn begin_session(&self, _company_file: &str, _file_mode: FileMode) -> Result<String> {
+        Ok(TICKET.to_string())
+    }
+
+    fn process_request(&self, _ticket: &str, request: &str) -> Result<String> {
+        let doc = roxmltree::Document::parse(request).context("The mock backend couldn't parse the request")?;
+        // QBXMLMsgsRq, or QBPOSXMLMsgsRq for Point of Sale
+        let Some(msgs) = doc.root_element().children().find(|n| n.is_element() && n.tag_name().name().ends_with("MsgsRq")) else {
+            bail!("The mock backend found no request elements in the request");
+        };
+        let root = doc.root_element().tag_name().name();
+        let requests: Vec<roxmltree::Node> = msgs.children().filter(|n| n.is_element()).collect();
+        let mut responses = Vec::with_capacity(requests.len());
+        for request in &requests {
+            let names = response_names(*request);
+            let Some(found) = self.recorded(&names) else {
+                bail!("The mock backend has no recorded response to {}; add {}.xml to {}", request.tag_name().name(), names[names.len() - 1], self.dir.display());
+            };
+            responses.push(found);
+        }
+        if let [(_, text)] = responses.as_slice() {
+            return Ok(text.clone());
+        }
+        // several elements: each file's responses, in request order, under one QBXMLMsgsRs (QBPOSXMLMsgsRs)
+        let msgs_rs = format!("{}MsgsRs", root);
+        let mut body = String::new();
+        for (file, text) in &responses {
+            let doc = qbxml::parse_document(text).with_context(|| format!("Failed to parse {}", file))?;
+            let Some(msgs) = doc.descendants().find(|n| n.has_tag_name(msgs_rs.as_str())) else {
+                bail!("{} has no {} element", file, msgs_rs);
+            };
+            for rs in msgs.children().filter(|n| n.is_element()) {
+                body.push_str(&text[rs.range()]);
+                body.push('\n');
+            }
+        }
+        Ok(format!("<?xml version=\"1.0\" ?>\n<{root}>\n<{msgs_rs}>\n{body}</{msgs_rs}>\n</{root}>\n"))
+    }
+
+    fn end_session(&self, _ticket: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn close_connection(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_current_company_file_name(&self, _ticket: &str) -> Result<String> {
+        Ok(self.dir.join("mock.qbw").display().to_string())
+    }
+}
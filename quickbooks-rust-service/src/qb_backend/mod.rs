@@ -0,0 +1,124 @@
+// The QuickBooks end of a session, behind one trait so everything above it builds without COM
+//
+// With the qb-com feature (on by default; Windows only) requests go to the QBXMLRP2.RequestProcessor
+// COM object of the installed QuickBooks SDK, through the wrappers in qbxml_safe. Built without it
+// (cargo build --no-default-features), the mock backend answers every request with a recorded
+// response from a directory, so the crate compiles and runs a whole cycle on Linux or macOS for CI
+// and development.
+
+use anyhow::Result;
+
+use crate::capture;
+use crate::config::Config;
+use crate::file_mode::FileMode;
+
+#[cfg(feature = "qb-com")]
+mod com;
+// also used by the tests, whichever backend is built
+#[cfg_attr(feature = "qb-com", allow(dead_code))]
+pub mod mock;
+
+#[cfg(all(feature = "qb-com", not(windows)))]
+compile_error!("the qb-com feature needs Windows; build with --no-default-features to use the mock backend");
+
+/// What a session needs from QuickBooks; the method names follow QBXMLRP2.RequestProcessor
+pub trait RequestProcessor {
+    fn open_connection(&self, app_id: &str, app_name: &str) -> Result<()>;
+
+    /// Returns the session ticket every request is sent with
+    fn begin_session(&self, company_file: &str, file_mode: FileMode) -> Result<String>;
+
+    fn process_request(&self, ticket: &str, request: &str) -> Result<String>;
+
+    fn end_session(&self, ticket: &str) -> Result<()>;
+
+    fn close_connection(&self) -> Result<()>;
+
+    /// Full path of the company file the session has open
+    fn get_current_company_file_name(&self, ticket: &str) -> Result<String>;
+
+    /// The account query every sync block shares
+    fn get_account_xml(&self, ticket: &str) -> Result<Option<String>> {
+        // note: use xml version "1.0" and qbxml version "13.0" - changes to those versions generate errors
+        let qbxml_request = r#"<?xml version="1.0" encoding="utf-8"?>
+<?qbxml version="13.0"?>
+<QBXML>
+   <QBXMLMsgsRq onError="continueOnError">
+      <AccountQueryRq>
+        <IncludeRetElement>ListID</IncludeRetElement>
+        <IncludeRetElement>FullName</IncludeRetElement>
+        <IncludeRetElement>AccountNumber</IncludeRetElement>
+        <IncludeRetElement>AccountType</IncludeRetElement>
+        <IncludeRetElement>Balance</IncludeRetElement>
+        <IncludeRetElement>CurrencyRef</IncludeRetElement>
+        <IncludeRetElement>Desc</IncludeRetElement>
+        <IncludeRetElement>BankNumber</IncludeRetElement>
+      </AccountQueryRq>
+   </QBXMLMsgsRq>
+</QBXML>"#;
+        Ok(Some(self.process_request(ticket, qbxml_request)?))
+    }
+}
+
+/// Creates the request processor of the backend this build has; call `release` once it is closed
+/// (--record and --replay, see capture, apply to either)
+pub fn connect(config: &Config) -> Result<Box<dyn RequestProcessor>> {
+    if let Some(replay) = capture::replay_processor() {
+        return Ok(replay);
+    }
+    Ok(capture::wrap(connect_backend(config)?))
+}
+
+#[cfg(feature = "qb-com")]
+fn connect_backend(_config: &Config) -> Result<Box<dyn RequestProcessor>> {
+    com::connect()
+}
+
+#[cfg(not(feature = "qb-com"))]
+fn connect_backend(config: &Config) -> Result<Box<dyn RequestProcessor>> {
+    let processor = mock::MockProcessor::new(mock::responses_dir(config));
+    log::warn!("[QBXML] Built without qb-com; answering requests from the recorded responses in {}", processor.dir().display());
+    Ok(Box::new(processor))
+}
+
+/// The request processor of QuickBooks Point of Sale; sessions begin with [point_of_sale]
+/// connection_string in place of the company file. `release` it like `connect`'s
+pub fn connect_point_of_sale(config: &Config) -> Result<Box<dyn RequestProcessor>> {
+    if let Some(replay) = capture::replay_processor() {
+        return Ok(replay);
+    }
+    Ok(capture::wrap(connect_point_of_sale_backend(config)?))
+}
+
+#[cfg(feature = "qb-com")]
+fn connect_point_of_sale_backend(_config: &Config) -> Result<Box<dyn RequestProcessor>> {
+    com::connect_point_of_sale()
+}
+
+#[cfg(not(feature = "qb-com"))]
+fn connect_point_of_sale_backend(config: &Config) -> Result<Box<dyn RequestProcessor>> {
+    let processor = mock::MockProcessor::new(mock::responses_dir(config).join(mock::POINT_OF_SALE_DIR));
+    log::warn!("[POS] Built without qb-com; answering requests from the recorded responses in {}", processor.dir().display());
+    Ok(Box::new(processor))
+}
+
+/// Undoes what `connect` (or `connect_point_of_sale`) set up, COM initialization, after the
+/// connection has been closed
+pub fn release() {
+    // a replay never initialized COM
+    #[cfg(feature = "qb-com")]
+    if !capture::replaying() {
+        com::release();
+    }
+}
+
+/// Whether a QuickBooks Desktop process is running; the mock backend always is
+pub fn quickbooks_running(process_names: &[&str]) -> Result<bool> {
+    #[cfg(feature = "qb-com")]
+    return com::process_running(process_names);
+    #[cfg(not(feature = "qb-com"))]
+    {
+        let _ = process_names;
+        Ok(true)
+    }
+}
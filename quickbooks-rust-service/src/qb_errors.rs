@@ -0,0 +1,114 @@
+// What the QuickBooks SDK's error codes mean and what to do about them
+//
+// Failures reach us as a COM HRESULT (from QBXMLRP2 or from COM itself, e.g. when the SDK isn't
+// installed) or as a statusCode on a qbXML response element. Both come with QuickBooks' own message,
+// which says what went wrong but rarely what to change; the hints here fill that in wherever an
+// error is shown.
+
+/// One known code: what it means in plain words and what usually fixes it
+#[derive(Debug, Clone, Copy)]
+pub struct Explanation {
+    pub meaning: &'static str,
+    pub hint: &'static str,
+}
+
+const fn explain(meaning: &'static str, hint: &'static str) -> Explanation {
+    Explanation { meaning, hint }
+}
+
+// HRESULTs from COM activation and from QBXMLRP2 (the 0x800404xx range)
+const HRESULTS: &[(u32, Explanation)] = &[
+    (0x8004_0154, explain("the QuickBooks SDK's request processor is not registered",
+        "install the QuickBooks SDK (or repair the QuickBooks install) and run qb_sync with the same 32/64-bit build the SDK registered")),
+    (0x8004_01F3, explain("the QBXMLRP2.RequestProcessor ProgID is unknown",
+        "the QuickBooks SDK is not installed on this PC; install it (v16 or later)")),
+    (0x8007_0005, explain("Windows denied access to QuickBooks",
+        "run qb_sync as the same Windows user as QuickBooks, and either both elevated (as administrator) or neither")),
+    (0x8008_0005, explain("the QuickBooks process could not be started or reached",
+        "QuickBooks and qb_sync must run at the same elevation level; close QuickBooks and start both the same way")),
+    (0x8004_0400, explain("QuickBooks could not parse the qbXML request",
+        "check raw_qbxml requests for malformed XML; qbsdklog.txt in the QuickBooks program data folder names the line")),
+    (0x8004_0401, explain("QuickBooks could not be accessed",
+        "make sure QuickBooks is installed and can be opened by this Windows user")),
+    (0x8004_0402, explain("QuickBooks hit an unexpected error",
+        "see qbsdklog.txt in the QuickBooks program data folder for details")),
+    (0x8004_0403, explain("the company file could not be opened",
+        "check that [quickbooks] company_file names an existing .qbw file this user can read, or use \"AUTO\" with the file open in QuickBooks")),
+    (0x8004_0408, explain("QuickBooks could not be started",
+        "open QuickBooks manually once to clear any update or registration prompt, then try again")),
+    (0x8004_040D, explain("the session ticket is no longer valid",
+        "QuickBooks ended the session; it is reopened on the next cycle, or restart qb_sync")),
+    (0x8004_0410, explain("the company file is open in a different mode than requested",
+        "the company file is in use, probably open in single-user mode by another user; switch it to multi-user or set connection_mode = \"auto\"")),
+    (0x8004_0414, explain("a dialog is open in QuickBooks",
+        "close the open window or message in QuickBooks and the next cycle will go through")),
+    (0x8004_0416, explain("QuickBooks is not running and no company file was named",
+        "set [quickbooks] company_file to the .qbw path, or open the company file in QuickBooks before using \"AUTO\"")),
+    (0x8004_0417, explain("no company file is open and none was named",
+        "set [quickbooks] company_file to the .qbw path, or open the company file in QuickBooks before using \"AUTO\"")),
+    (0x8004_0418, explain("this application has not been authorised for the company file yet",
+        "log into the company file as the QuickBooks Admin in single-user mode and run qb_sync once to answer the authorisation prompt")),
+    (0x8004_041A, explain("this application is not allowed to access the company file",
+        "in QuickBooks go to Edit > Preferences > Integrated Applications > Company Preferences and allow application_name")),
+    (0x8004_041D, explain("this application may not log into the company file automatically",
+        "in Integrated Applications preferences, allow the application to log in when QuickBooks isn't running and pick a user")),
+    (0x8004_0420, explain("the QuickBooks user denied access",
+        "run qb_sync again and choose \"Yes, always\" in the QuickBooks authorisation prompt, as the Admin")),
+    (0x8004_0422, explain("another application is sharing the company file",
+        "the company file is in use by another integrated application; ask for multi-user access or wait for it to finish")),
+    (0x8004_0423, explain("QuickBooks does not support the qbXML version requested",
+        "the installed QuickBooks is older than the qbXML version used; update QuickBooks or lower the <?qbxml version?> in raw_qbxml requests")),
+    (0x8004_0424, explain("QuickBooks has not finished starting up",
+        "QuickBooks is still opening the file, running a backup or showing a startup dialog; it is retried after busy_retry_secs")),
+];
+
+// statusCode values on qbXML *Rs elements
+const STATUS_CODES: &[(u32, Explanation)] = &[
+    (1, explain("no matching objects were found",
+        "the query ran but returned nothing; check the names or filters in the request")),
+    (500, explain("the query was not fully completed; some matching records could not be returned",
+        "if the request is right, the company file may be damaged; run File > Utilities > Verify Data in QuickBooks")),
+    (3100, explain("the name is already in use",
+        "another list item already has this name; pick a different one")),
+    (3120, explain("an object named in the request does not exist",
+        "check the spelling of account_full_name or the FullName in the request against QuickBooks")),
+    (3140, explain("the request refers to something QuickBooks doesn't have",
+        "check the referenced account, customer, class or item name, including its parent (Parent:Child)")),
+    (3175, explain("the record is in use by another user",
+        "close the record in QuickBooks or wait, then try again")),
+    (3176, explain("the record is being edited by another user",
+        "close the record in QuickBooks or wait, then try again")),
+    (3180, explain("QuickBooks could not save the record",
+        "see the status message for which field was rejected")),
+    (3200, explain("the record changed since it was read",
+        "the edit sequence is out of date; query the record again before modifying it")),
+    (3250, explain("the feature is not enabled or not available in this QuickBooks edition",
+        "turn the feature on in QuickBooks preferences (e.g. multicurrency, classes, inventory; payroll reports need a payroll subscription) or leave the block out")),
+    (3260, explain("the QuickBooks user lacks permission for this",
+        "give the user the integrated application logs in as access to this area in QuickBooks")),
+    (3261, explain("the application may not read personal data",
+        "in Integrated Applications preferences, allow access to personal data such as Social Security numbers")),
+];
+
+/// The explanation for an HRESULT, if it is one QuickBooks users commonly run into
+pub fn for_hresult(code: i32) -> Option<Explanation> {
+    // bit-for-bit: HRESULTs are printed as unsigned hex
+    let code = code as u32;
+    HRESULTS.iter().find(|(c, _)| *c == code).map(|(_, e)| *e)
+}
+
+/// The explanation for a qbXML statusCode attribute
+pub fn for_status_code(code: &str) -> Option<Explanation> {
+    let code: u32 = code.trim().parse().ok()?;
+    STATUS_CODES.iter().find(|(c, _)| *c == code).map(|(_, e)| *e)
+}
+
+/// "; <meaning>: <hint>" to add to an error message, or nothing for an unknown HRESULT
+pub fn hresult_suffix(code: i32) -> String {
+    for_hresult(code).map(|e| format!("; {}: {}", e.meaning, e.hint)).unwrap_or_default()
+}
+
+/// The same for a statusCode
+pub fn status_code_suffix(code: &str) -> String {
+    for_status_code(code).map(|e| format!("; {}: {}", e.meaning, e.hint)).unwrap_or_default()
+}
@@ -0,0 +1,161 @@
+// An open QuickBooks connection + session
+//
+// A one-shot run opens and closes this once. In daemon mode with [schedule] keep_session_open = true
+// it stays open between cycles, kept warm with a cheap HostQuery and reopened if QuickBooks drops it,
+// since BeginSession alone takes several seconds on a big company file.
+
+use anyhow::Result;
+
+use crate::capture;
+use crate::circuit;
+use crate::config::Config;
+use crate::file_mode::FileMode;
+use crate::dates;
+use crate::host::{self, HostInfo};
+use crate::qbxml;
+use crate::qb_backend::{self, RequestProcessor};
+use crate::quickbooks_process;
+use crate::response_cache;
+
+pub struct QbSession {
+    pub processor: Box<dyn RequestProcessor>,
+    pub ticket: String,
+    // product and company details queried when the session opened
+    pub host: HostInfo,
+}
+
+impl QbSession {
+    pub fn open(config: &Config) -> Result<Self> {
+        // a QuickBooks the circuit breaker is keeping away from isn't started or connected to either
+        circuit::check(config)?;
+        // nothing to clean up yet, so a stopped QuickBooks can bail right here with a readable message
+        // (a --replay answers from its capture, whether QuickBooks is there or not)
+        let launched_quickbooks = !capture::replaying() && quickbooks_process::ensure_running(&config.quickbooks)?;
+
+        // COM (or the mock backend); connect cleans up after itself when it fails
+        // repeated queries answered from the cache don't count as calls to the circuit breaker
+        let processor = response_cache::wrap(circuit::guard(qb_backend::connect(config)?, config), config);
+
+        // AppID isn't used by the QBSDK, if a value is passed in config it is harmless but not used
+        let app_id = config.quickbooks.application_id.as_deref().unwrap_or("");
+
+        /*  If we ever change the name of the service we register with Quickbooks we'll have
+        to change this default too in order to ensure the program will work even if the config.toml loses this setting
+        */
+        let app_name = config.quickbooks.application_name.as_deref().unwrap_or("QuickBooks Sync Service");
+
+        if let Err(e) = processor.open_connection(app_id, app_name) {
+            Self::cleanup(processor.as_ref());
+            return Err(e);
+        }
+
+        // sets company_file to AUTO if blank, company file name if provided in config.toml
+        let company_file = match config.quickbooks.company_file.as_str() {
+            "AUTO" => "",
+            path => {
+                println!("[DEBUG] Company file: {}", path);
+                path }
+            };
+
+        // we could try to check to see if we have an apparenlty valid ticket here but ...
+        let ticket = if launched_quickbooks {
+            // we just started QuickBooks; it takes a while to load the company file
+            quickbooks_process::begin_session_when_ready(processor.as_ref(), company_file, FileMode::DoNotCare, &config.quickbooks)
+        } else {
+            processor.begin_session(company_file, FileMode::DoNotCare)
+        };
+        let mut session = match ticket {
+            Ok(ticket) => Self { processor, ticket, host: HostInfo::default() },
+            Err(e) => {
+                Self::cleanup(processor.as_ref());
+                return Err(e);
+            }
+        };
+
+        // the wrong edition or company file fails the run here, before any block has written anything
+        match session.check_host(config) {
+            Ok(info) => session.host = info,
+            Err(e) => {
+                session.close();
+                return Err(e);
+            }
+        }
+        Ok(session)
+    }
+
+    /// Logs the product, edition and company of the open QuickBooks and applies
+    /// require_edition / require_company
+    fn check_host(&self, config: &Config) -> Result<HostInfo> {
+        let response = self.processor.process_request(&self.ticket, &host::request())?;
+        let mut info = host::parse(&response)?;
+        info.company_file = self.processor.get_current_company_file_name(&self.ticket).unwrap_or_else(|e| {
+            log::warn!("[QBXML] Could not read the company file name: {:#}", e);
+            String::new()
+        });
+        log::info!(
+            "[QBXML] Connected to {} (version {}.{}, {} edition, {}), company '{}'",
+            info.product_name, info.major_version, info.minor_version, info.edition(), info.country, info.company_name
+        );
+        if !info.file_mode.is_empty() {
+            log::info!("[QBXML] Company file open in {} mode", info.file_mode);
+        }
+        if let Some(restriction) = info.restriction() {
+            log::warn!("[QBXML] Restricted company file: {}; some queries behave differently and changes are refused", restriction);
+        }
+        let settings = host::settings(&info);
+        if !settings.is_empty() {
+            let settings: Vec<String> = settings.iter().map(|(label, value)| format!("{} {}", label.to_lowercase(), value)).collect();
+            log::info!("[QBXML] Company preferences: {}", settings.join(", "));
+        }
+        host::check(&info, &config.quickbooks)?;
+        dates::set_fiscal_year_start(info.fiscal_year_start_month);
+        Ok(info)
+    }
+
+    /// Sends a HostQuery, the cheapest request there is, to check the session is still usable
+    pub fn keep_alive(&self) -> Result<()> {
+        let request = qbxml::envelope("<HostQueryRq></HostQueryRq>");
+        let response = self.processor.process_request(&self.ticket, &request)?;
+        let doc = qbxml::parse_document(&response)?;
+        qbxml::find_response(&doc, "HostQueryRs")?;
+        Ok(())
+    }
+
+    /// Keeps the session if it still answers, otherwise closes what's left of it and opens a new one
+    pub fn reopen_if_dropped(self, config: &Config) -> Result<Self> {
+        match self.keep_alive() {
+            Ok(()) => Ok(self),
+            Err(e) => {
+                log::warn!("[QBXML] Session dropped ({:#}); reconnecting", e);
+                self.close();
+                Self::open(config)
+            }
+        }
+    }
+
+    pub fn close(self) {
+        /*
+        The COM system has returned all sorts of values for tickets when the ticket fails to be created
+        so we can't just assume that we can detect an invalid ticket; we should attempt to close the
+        session regardless of what we got as a ticket.
+
+        We don't want to bail out here in the event of an error because there are still cleanup steps needed
+        */
+        if let Err(e) = self.processor.end_session(&self.ticket) {
+            eprintln!("[QBXML] end_session errored: {:#}", e)
+        }
+        Self::cleanup(self.processor.as_ref());
+    }
+
+    fn cleanup(processor: &dyn RequestProcessor) {
+        /*
+        We want to try to continue clean up even if this fails
+        I think this could happen if the connection was not open but the COM system was initialized
+        */
+        if let Err(e) = processor.close_connection() {
+            eprintln!("[QBXML] close_connection errored: {:#}", e);
+        }
+
+        qb_backend::release();
+    }
+}
@@ -0,0 +1,84 @@
+// Helpers shared by the qbXML query modules: request envelopes and response navigation
+// The COM plumbing lives in qbxml_safe; this module only deals with the XML text
+
+use anyhow::{Context, Result};
+use roxmltree::{Document, Node};
+
+use crate::qb_errors;
+
+// note: use xml version "1.0" and qbxml version "13.0" - changes to those versions generate errors
+pub const QBXML_VERSION: &str = "13.0";
+
+/// Wraps one or more request elements in the standard qbXML envelope
+pub fn envelope(body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<?qbxml version="{}"?>
+<QBXML>
+   <QBXMLMsgsRq onError="continueOnError">
+{}
+   </QBXMLMsgsRq>
+</QBXML>"#, QBXML_VERSION, body)
+}
+
+// qbposXML 3.0 is understood by Point of Sale 2013 (v11) and later
+pub const QBPOSXML_VERSION: &str = "3.0";
+
+/// The Point of Sale envelope (qbposXML); responses come back under QBPOSXML / QBPOSXMLMsgsRs
+pub fn pos_envelope(body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<?qbposxml version="{}"?>
+<QBPOSXML>
+   <QBPOSXMLMsgsRq onError="continueOnError">
+{}
+   </QBPOSXMLMsgsRq>
+</QBPOSXML>"#, QBPOSXML_VERSION, body)
+}
+
+/// Escapes text for use inside a request element
+pub fn escape(text: &str) -> String {
+    htmlescape::encode_minimal(text)
+}
+
+/// qbXML date-times are local time without an offset, e.g. 2024-01-31T13:45:00
+pub fn format_datetime(at: &chrono::DateTime<chrono::Local>) -> String {
+    at.format("%Y-%m-%dT%H:%M:%S").to_string()
+}
+
+pub fn parse_document(response_xml: &str) -> Result<Document<'_>> {
+    Document::parse(response_xml).context("Failed to parse qbXML response")
+}
+
+/// Finds the `rs_name` response element and checks its status attributes.
+/// statusCode 1 ("no matching objects") is not an error; the element just has no children.
+pub fn find_response<'a, 'input>(doc: &'a Document<'input>, rs_name: &str) -> Result<Node<'a, 'input>> {
+    let rs = doc.descendants()
+        .find(|n| n.has_tag_name(rs_name))
+        .with_context(|| format!("qbXML response has no {} element", rs_name))?;
+    if rs.attribute("statusSeverity") == Some("Error") {
+        anyhow::bail!(
+            "QuickBooks returned error {} for {}: {}{}",
+            rs.attribute("statusCode").unwrap_or("?"),
+            rs_name,
+            rs.attribute("statusMessage").unwrap_or("<no message>"),
+            qb_errors::status_code_suffix(rs.attribute("statusCode").unwrap_or_default())
+        );
+    }
+    Ok(rs)
+}
+
+/// Text of the first direct child called `name`
+pub fn child_text(node: Node, name: &str) -> Option<String> {
+    node.children()
+        .find(|n| n.has_tag_name(name))
+        .and_then(|n| n.text())
+        .map(|t| t.trim().to_string())
+}
+
+/// FullName of a reference child such as `<AccountRef><FullName>..</FullName></AccountRef>`
+pub fn ref_full_name(node: Node, ref_name: &str) -> Option<String> {
+    node.children()
+        .find(|n| n.has_tag_name(ref_name))
+        .and_then(|r| child_text(r, "FullName"))
+}
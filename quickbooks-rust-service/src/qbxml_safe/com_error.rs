@@ -0,0 +1,51 @@
+// Error raised by a failed QBXMLRP2 method call
+// QuickBooks reports its own errors through EXCEPINFO, so the SCODE (e.g. 0x80040410) is the code
+// that matters; the Invoke HRESULT is then just DISP_E_EXCEPTION.
+
+use std::fmt;
+
+use crate::qb_errors;
+
+// company file open in a mode other than the one requested, typically single-user by someone else
+const FILE_OPEN_IN_OTHER_MODE: i32 = 0x80040410u32 as i32;
+// another application is sharing the file and this one needs it in single-user mode
+const FILE_SHARED_BY_OTHER_APP: i32 = 0x80040422u32 as i32;
+// QuickBooks has not finished initializing (opening the file, a modal dialog, a backup, ...)
+const QUICKBOOKS_NOT_READY: i32 = 0x80040424u32 as i32;
+
+const BUSY_CODES: [i32; 3] = [FILE_OPEN_IN_OTHER_MODE, FILE_SHARED_BY_OTHER_APP, QUICKBOOKS_NOT_READY];
+
+#[derive(Debug, Clone)]
+pub struct ComError {
+    pub method: String,
+    pub hresult: i32,
+    // EXCEPINFO scode; 0 when the object didn't fill it in
+    pub scode: i32,
+    pub description: String,
+}
+
+impl ComError {
+    /// The code QuickBooks meant: the EXCEPINFO scode when there is one, the HRESULT otherwise
+    pub fn code(&self) -> i32 {
+        if self.scode != 0 { self.scode } else { self.hresult }
+    }
+
+    /// QuickBooks can't serve us right now because someone else holds the company file
+    pub fn is_busy(&self) -> bool {
+        BUSY_CODES.contains(&self.code())
+    }
+}
+
+impl fmt::Display for ComError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the busy codes' hints say the company file is in use
+        write!(f, "{} failed: {} (0x{:08X}){}", self.method, self.description, self.code() as u32, qb_errors::hresult_suffix(self.code()))
+    }
+}
+
+impl std::error::Error for ComError {}
+
+/// Whether any error in the chain is QuickBooks reporting the company file as busy / locked
+pub fn is_busy(error: &anyhow::Error) -> bool {
+    error.chain().any(|e| e.downcast_ref::<ComError>().is_some_and(ComError::is_busy))
+}
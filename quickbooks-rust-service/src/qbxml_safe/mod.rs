@@ -1,5 +1,9 @@
 // Type-safe wrappers for QBXML COM/OLE API using winapi
 // This module provides SafeVariant, SafeDispatch, and helpers for QBXMLRP2.RequestProcessor
 
+#[cfg(feature = "qb-com")]
 pub mod qbxml_safe_variant;
+#[cfg(feature = "qb-com")]
 pub mod qbxml_request_processor;
+// no COM in here; the busy checks work the same whichever backend is built
+pub mod com_error;
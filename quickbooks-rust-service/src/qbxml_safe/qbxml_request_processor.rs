@@ -4,6 +4,8 @@
 use winapi::shared::guiddef::{CLSID, IID_NULL};
 use winapi::um::oaidl::{IDispatch, VARIANT, EXCEPINFO};
 use crate::qbxml_safe::qbxml_safe_variant::SafeVariant;
+use crate::qbxml_safe::com_error::ComError;
+use crate::qb_errors;
 use crate::file_mode::FileMode;
 
 const DISPATCH_METHOD: u16 = 1;
@@ -24,7 +26,15 @@ pub const IID_IDispatch: winapi::shared::guiddef::GUID = winapi::shared::guiddef
 impl QbxmlRequestProcessor {
     pub fn new() -> Result<Self, anyhow::Error> {
         // Use the single QBXML ProgID for RequestProcessor
-        let prog_id = "QBXMLRP2.RequestProcessor";
+        Self::with_prog_id("QBXMLRP2.RequestProcessor")
+    }
+
+    // Point of Sale's request processor has the same methods apart from BeginSession
+    pub fn point_of_sale() -> Result<Self, anyhow::Error> {
+        Self::with_prog_id("QBPOSXMLRPLib.RequestProcessor")
+    }
+
+    fn with_prog_id(prog_id: &str) -> Result<Self, anyhow::Error> {
         log::info!("Trying QBXML ProgID: {}", prog_id);
         let prog_id_wide = widestring::U16CString::from_str(prog_id).unwrap();
         let mut clsid: CLSID = unsafe { std::mem::zeroed() };
@@ -36,7 +46,7 @@ impl QbxmlRequestProcessor {
         };
         if hr < 0 {
             log::error!("ProgID {} not found or CLSIDFromProgID failed: HRESULT=0x{:08X}", prog_id, hr as u32);
-            return Err(anyhow::anyhow!("Failed to find QBXML COM ProgID: {} (HRESULT=0x{:08X})", prog_id, hr as u32));
+            return Err(anyhow::anyhow!("Failed to find QBXML COM ProgID: {} (HRESULT=0x{:08X}){}", prog_id, hr as u32, qb_errors::hresult_suffix(hr)));
         }
         let mut dispatch_ptr: *mut IDispatch = std::ptr::null_mut();
         let hr = unsafe {
@@ -55,7 +65,7 @@ impl QbxmlRequestProcessor {
             Ok(instance)
         } else {
             log::error!("Failed to create COM instance for {}: HRESULT=0x{:08X}", prog_id, hr as u32);
-            Err(anyhow::anyhow!("Failed to create QBXML COM instance for ProgID: {} (HRESULT=0x{:08X})", prog_id, hr as u32))
+            Err(anyhow::anyhow!("Failed to create QBXML COM instance for ProgID: {} (HRESULT=0x{:08X}){}", prog_id, hr as u32, qb_errors::hresult_suffix(hr)))
         }
     }
 
@@ -73,7 +83,8 @@ impl QbxmlRequestProcessor {
                 for cause in e.chain().skip(1) {
                     log::error!("Caused by: {:#}", cause);
                 }
-                Err(anyhow::anyhow!("Failed to open QuickBooks connection. See error logs above for HRESULT, EXCEPINFO, and details."))
+                // keeps the ComError, and with it the explanation of its code, in the chain
+                Err(e.context("Failed to open QuickBooks connection"))
             }
         }
     }
@@ -97,6 +108,17 @@ impl QbxmlRequestProcessor {
         Ok(ticket)
     }
 
+    // POS opens the company data named in a connection string; there is no file mode
+    pub fn begin_pos_session(&self, connection_string: &str) -> Result<String, anyhow::Error> {
+        let connection_var = SafeVariant::from_string(connection_string);
+        let result = self.invoke_method("BeginSession", &[connection_var])?;
+        let ticket = result.to_string().unwrap_or_default();
+        if ticket.is_empty() {
+            log::warn!("BeginSession returned an empty ticket string!");
+        }
+        Ok(ticket)
+    }
+
     pub fn process_request(&self, ticket: &str, request: &str) -> Result<String, anyhow::Error> {
         let ticket_var = SafeVariant::from_string(ticket);
         let request_var = SafeVariant::from_string(request);
@@ -117,64 +139,13 @@ impl QbxmlRequestProcessor {
         Ok(())
     }
 
-    // currently not used but we may need it
-    pub fn get_current_company_file_name(&self) -> Result<String, anyhow::Error> {
-        let result = self.invoke_method("GetCurrentCompanyFileName", &[])?;
+    // full path of the company file the session has open
+    pub fn get_current_company_file_name(&self, ticket: &str) -> Result<String, anyhow::Error> {
+        let ticket_var = SafeVariant::from_string(ticket);
+        let result = self.invoke_method("GetCurrentCompanyFileName", &[ticket_var])?;
         Ok(result.to_string().unwrap_or_default())
     }
 
-    pub fn get_account_xml(&self, ticket: &str) -> Result<Option<String>, anyhow::Error> {
-        // Step 1: Build QBXML request for account query
-        // note: use xml version "1.0" and qbxml version "13.0" - changes to those versions generate errors
-        let qbxml_request = format!(
-            r#"<?xml version="1.0" encoding="utf-8"?>
-<?qbxml version="13.0"?>
-<QBXML>
-   <QBXMLMsgsRq onError="continueOnError">
-      <AccountQueryRq>
-        <IncludeRetElement>FullName</IncludeRetElement>
-        <IncludeRetElement>Balance</IncludeRetElement>
-      </AccountQueryRq>
-   </QBXMLMsgsRq>
-</QBXML>"#);        
-        // Step 2: Send request
-        let response_xml = match self.process_request(ticket, &qbxml_request) {
-            Ok(xml) => xml,
-            Err(e) => return Err(e),
-        };
-        Ok(Some(response_xml))
-    }
-
-    pub fn get_account_balance(&self, response_xml: &str, account_full_name: &str) -> Result<Option<f64>, anyhow::Error> {
-        let mut balance = 0.0;
-        let mut found = false;
-        let mut search_start = 0;
-        while let Some(ret_start) = response_xml[search_start..].find("<AccountRet>") {
-            let ret_start = ret_start + search_start;
-            let ret_end = match response_xml[ret_start..].find("</AccountRet>") {
-                Some(e) => ret_start + e + "</AccountRet>".len(),
-                None => break,
-            };
-            let account_block = &response_xml[ret_start..ret_end];
-            if let Some(full_name) = Self::extract_xml_field(account_block, "<FullName>", "</FullName>") {
-                let decoded_full_name = htmlescape::decode_html(&full_name).unwrap_or(full_name.clone());
-                if decoded_full_name == account_full_name {
-                    balance = Self::extract_xml_field(account_block, "<Balance>", "</Balance>")
-                        .and_then(|s| s.parse::<f64>().ok()).unwrap_or(0.0);
-                    found = true;
-                    break;
-                }
-            }
-            search_start = ret_end;
-        }
-        if found {
-            Ok(Some(balance))
-        } else {
-            log::warn!("No accounts found with the specified criteria");
-            Ok(None)
-        }
-    }
-
     fn invoke_method(&self, method_name: &str, params: &[SafeVariant]) -> Result<SafeVariant, anyhow::Error> {
         let method_name_wide = widestring::U16CString::from_str(method_name).unwrap();
         // Instead, use VARIANT zeroed and wrap as needed
@@ -217,7 +188,7 @@ impl QbxmlRequestProcessor {
         };
         if hr < 0 {
             // Log EXCEPINFO details if available
-            unsafe {
+            let error = unsafe {
                 let description = if !excepinfo.bstrDescription.is_null() {
                     let wide = widestring::U16CStr::from_ptr_str(excepinfo.bstrDescription);
                     wide.to_string_lossy()
@@ -231,17 +202,15 @@ impl QbxmlRequestProcessor {
                     "<no source>".to_string()
                 };
                 let scode = excepinfo.scode;
-                log::error!("COM Invoke failed: HRESULT=0x{:08X}, Source: {}, Description: {}, SCODE: 0x{:08X}", hr, source, description, scode);
-            }
-            return Err(anyhow::anyhow!("Invoke failed: HRESULT=0x{:08X}", hr));
+                let error = ComError { method: method_name.to_string(), hresult: hr, scode, description };
+                // a busy company file is expected now and then; the caller decides what to do about it
+                if !error.is_busy() {
+                    log::error!("COM Invoke failed: HRESULT=0x{:08X}, Source: {}, Description: {}, SCODE: 0x{:08X}", hr, source, error.description, scode);
+                }
+                error
+            };
+            return Err(anyhow::Error::new(error));
         }
         Ok(SafeVariant(result))
     }
-
-    // Helper function for minimal XML field extraction
-    fn extract_xml_field(xml: &str, start_tag: &str, end_tag: &str) -> Option<String> {
-        let start = xml.find(start_tag)? + start_tag.len();
-        let end = xml[start..].find(end_tag)? + start;
-        Some(xml[start..end].trim().to_string())
-    }
 }
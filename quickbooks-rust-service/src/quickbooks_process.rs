@@ -0,0 +1,107 @@
+// Detect whether QuickBooks Desktop is running and optionally start it
+//
+// BeginSession against a closed QuickBooks fails with an opaque COM error (or hangs while the SDK
+// tries to launch it in the background), so check the process list first and say what's wrong.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::config::QuickBooksConfig;
+use crate::file_mode::FileMode;
+use crate::qb_backend::{self, RequestProcessor};
+
+// QBW.EXE is the 64-bit executable (2022 and later), QBW32.EXE the older 32-bit one
+const PROCESS_NAMES: [&str; 2] = ["QBW.EXE", "QBW32.EXE"];
+const DEFAULT_STARTUP_TIMEOUT_SECS: u64 = 180;
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+pub fn is_running() -> Result<bool> {
+    qb_backend::quickbooks_running(&PROCESS_NAMES)
+}
+
+/// Makes sure QuickBooks is running before a session is opened.
+/// Returns true when this call launched it, so the caller knows to wait for it to become ready.
+pub fn ensure_running(qb: &QuickBooksConfig) -> Result<bool> {
+    if is_running()? {
+        return Ok(false);
+    }
+
+    let auto_file = qb.company_file == "AUTO";
+    if !qb.auto_start_quickbooks.unwrap_or(false) {
+        if auto_file {
+            // AUTO means "whatever file is open", which needs a running QuickBooks
+            bail!("QuickBooks Desktop is not running. Start it and open the company file, or set auto_start_quickbooks = true under [quickbooks]");
+        }
+        log::warn!("[QB] QuickBooks Desktop is not running; the SDK will try to open {} itself", qb.company_file);
+        return Ok(false);
+    }
+
+    let exe = match &qb.quickbooks_exe {
+        Some(path) => PathBuf::from(path),
+        None => find_quickbooks_exe().ok_or_else(|| anyhow!("auto_start_quickbooks is set but QBW.exe wasn't found under Program Files; set quickbooks_exe under [quickbooks]"))?,
+    };
+    let mut command = std::process::Command::new(&exe);
+    // without a file QuickBooks reopens the last company file, which is what AUTO expects
+    if !auto_file && !qb.company_file.is_empty() {
+        command.arg(&qb.company_file);
+    }
+    log::info!("[QB] QuickBooks Desktop is not running; starting {}", exe.display());
+    command.spawn().with_context(|| format!("Failed to start {}", exe.display()))?;
+
+    let deadline = Instant::now() + startup_timeout(qb);
+    while !is_running()? {
+        if crate::shutdown::is_requested() {
+            bail!("Stopped while waiting for QuickBooks to start");
+        }
+        if Instant::now() > deadline {
+            bail!("Started {} but the QuickBooks process never appeared", exe.display());
+        }
+        std::thread::sleep(Duration::from_secs(1));
+    }
+    Ok(true)
+}
+
+/// Retries BeginSession until QuickBooks has finished loading the company file
+pub fn begin_session_when_ready(processor: &dyn RequestProcessor, company_file: &str, file_mode: FileMode, qb: &QuickBooksConfig) -> Result<String> {
+    let timeout = startup_timeout(qb);
+    let deadline = Instant::now() + timeout;
+    loop {
+        match processor.begin_session(company_file, file_mode) {
+            Ok(ticket) => return Ok(ticket),
+            Err(e) if crate::shutdown::is_requested() => return Err(e.context("Stopped while waiting for QuickBooks")),
+            Err(e) if Instant::now() < deadline => {
+                log::info!("[QB] Waiting for QuickBooks to finish starting: {:#}", e);
+                std::thread::sleep(POLL_INTERVAL);
+            }
+            Err(e) => return Err(e.context(format!("QuickBooks did not become ready within {}s", timeout.as_secs()))),
+        }
+    }
+}
+
+fn startup_timeout(qb: &QuickBooksConfig) -> Duration {
+    Duration::from_secs(qb.startup_timeout_secs.unwrap_or(DEFAULT_STARTUP_TIMEOUT_SECS))
+}
+
+// Newest install wins: "QuickBooks Enterprise Solutions 24.0" sorts after "... 23.0"
+fn find_quickbooks_exe() -> Option<PathBuf> {
+    let roots = ["ProgramFiles", "ProgramFiles(x86)"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .map(|dir| Path::new(&dir).join("Intuit"));
+
+    let mut candidates = Vec::new();
+    for root in roots {
+        let Ok(entries) = std::fs::read_dir(&root) else { continue };
+        for entry in entries.flatten() {
+            for exe in ["QBW.exe", "QBW32.exe"] {
+                let path = entry.path().join(exe);
+                if path.is_file() {
+                    candidates.push(path);
+                }
+            }
+        }
+    }
+    candidates.sort();
+    candidates.pop()
+}
@@ -0,0 +1,58 @@
+// Per-spreadsheet write budget ([google_sheets] writes_per_minute)
+//
+// Google throttles a spreadsheet that gets too many writes in a short time, and once one write is
+// rejected the retries make it worse. Every post first takes a slot in its spreadsheet's sliding
+// one-minute window; when the window is full only that post waits, so blocks writing to other
+// spreadsheets carry on in the meantime.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+// the budget every config in the process writes through, so tenants sharing a spreadsheet share it
+static SHARED: OnceLock<Arc<Quota>> = OnceLock::new();
+
+#[derive(Default)]
+pub struct Quota {
+    // start times of the writes made in the last minute, per spreadsheet id
+    windows: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl Quota {
+    pub fn shared() -> Arc<Quota> {
+        SHARED.get_or_init(Default::default).clone()
+    }
+
+    /// Waits until `spreadsheet_id` has had fewer than `per_minute` writes in the last minute,
+    /// then counts this one
+    pub async fn acquire(&self, spreadsheet_id: &str, per_minute: u32) {
+        if per_minute == 0 {
+            return;
+        }
+        let mut logged = false;
+        loop {
+            let wait_until = {
+                let mut windows = self.windows.lock().unwrap();
+                let window = windows.entry(spreadsheet_id.to_string()).or_default();
+                let now = Instant::now();
+                while window.front().is_some_and(|&t| now.duration_since(t) >= WINDOW) {
+                    window.pop_front();
+                }
+                if window.len() < per_minute as usize {
+                    window.push_back(now);
+                    return;
+                }
+                window[0] + WINDOW
+            };
+            if !logged {
+                log::info!("[QUOTA] {} writes to spreadsheet {} in the last minute; waiting {}s",
+                    per_minute, spreadsheet_id, wait_until.saturating_duration_since(Instant::now()).as_secs());
+                logged = true;
+            }
+            tokio::time::sleep_until(wait_until).await;
+        }
+    }
+}
@@ -0,0 +1,64 @@
+// A small seeded random number generator (SplitMix64) for jitter delays and trace ids
+//
+// Nothing here needs to be unpredictable to an attacker, only different from one cycle (and one
+// machine) to the next, so a clock-seeded generator does instead of a rand dependency. Secrets and
+// tokens keep using uuid's OS randomness.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// generators seeded in the same nanosecond still get different seeds
+static SEEDS: AtomicU64 = AtomicU64::new(0);
+
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Seeded from the clock and the process id
+    pub fn from_clock() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default();
+        let count = SEEDS.fetch_add(1, Ordering::Relaxed);
+        Self::new(nanos ^ (u64::from(std::process::id()) << 32) ^ count.wrapping_mul(GAMMA))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GAMMA);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from 0 to `max`, both included; the modulo bias is far too small to matter here
+    pub fn up_to(&mut self, max: u64) -> u64 {
+        match max.checked_add(1) {
+            Some(bound) => self.next_u64() % bound,
+            None => self.next_u64(),
+        }
+    }
+
+    /// `bytes` random bytes as lowercase hex, e.g. an OTLP trace id (16) or span id (8); never all
+    /// zeros, which OTLP treats as no id
+    pub fn hex(&mut self, bytes: usize) -> String {
+        loop {
+            let mut hex = String::with_capacity(bytes * 2);
+            let mut word = 0;
+            for i in 0..bytes {
+                if i % 8 == 0 {
+                    word = self.next_u64();
+                }
+                hex.push_str(&format!("{:02x}", (word >> (8 * (i % 8))) as u8));
+            }
+            if bytes == 0 || hex.bytes().any(|b| b != b'0') {
+                return hex;
+            }
+        }
+    }
+}
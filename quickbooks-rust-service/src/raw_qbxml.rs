@@ -0,0 +1,59 @@
+// [[raw_qbxml_blocks]]: a user-supplied qbXML request whose response values are mapped to cells
+// with XPath (see extract.rs), for anything the SDK can answer that has no first-class block yet.
+// With point_of_sale = true the request is qbposXML and goes to QuickBooks Point of Sale instead.
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::RawQbxmlBlockConfig;
+use crate::qb_errors;
+use crate::qbxml;
+
+/// The request to send: the inline request or request_file, wrapped in the qbXML (or qbposXML)
+/// envelope unless it already is a full document
+pub fn request(block: &RawQbxmlBlockConfig) -> Result<String> {
+    let body = match (&block.request, &block.request_file) {
+        (Some(_), Some(_)) => bail!("Raw qbXML block '{}' sets both request and request_file", block.name),
+        (None, None) => bail!("Raw qbXML block '{}' needs request or request_file", block.name),
+        (Some(request), None) => request.clone(),
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read request_file {}", path))?,
+    };
+    let body = body.trim();
+    if body.starts_with("<?xml") || body.starts_with("<QBXML") || body.starts_with("<QBPOSXML") {
+        return Ok(body.to_string());
+    }
+    if block.is_point_of_sale() {
+        return Ok(qbxml::pos_envelope(body));
+    }
+    Ok(qbxml::envelope(body))
+}
+
+/// Fails when any *Rs in the response came back with statusSeverity="Error"; "no matching objects"
+/// is only Info and passes
+pub fn check_status(response_xml: &str) -> Result<()> {
+    let doc = qbxml::parse_document(response_xml)?;
+    let msgs = doc.descendants()
+        .find(|n| n.has_tag_name("QBXMLMsgsRs") || n.has_tag_name("QBPOSXMLMsgsRs"))
+        .context("qbXML response has no QBXMLMsgsRs element")?;
+    for rs in msgs.children().filter(|n| n.is_element()) {
+        if rs.attribute("statusSeverity") == Some("Error") {
+            bail!(
+                "QuickBooks returned error {} for {}: {}{}",
+                rs.attribute("statusCode").unwrap_or("?"),
+                rs.tag_name().name(),
+                rs.attribute("statusMessage").unwrap_or("<no message>"),
+                qb_errors::status_code_suffix(rs.attribute("statusCode").unwrap_or_default())
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Whether the request changes anything in QuickBooks: any *AddRq, *ModRq, *DelRq or *VoidRq
+/// element (TxnDelRq, ListDelRq, DataExtAddRq, ...)
+pub fn writes_to_quickbooks(request: &str) -> Result<bool> {
+    let doc = roxmltree::Document::parse(request).context("Failed to parse the raw qbXML request")?;
+    Ok(doc.descendants()
+        .filter(|n| n.is_element())
+        .any(|n| ["AddRq", "ModRq", "DelRq", "VoidRq"].iter().any(|suffix| n.tag_name().name().ends_with(suffix))))
+}
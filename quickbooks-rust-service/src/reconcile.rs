@@ -0,0 +1,144 @@
+// [[reconciliation_blocks]]: an account's QuickBooks balance checked against the same balance from
+// somewhere else, such as the bank's figure in a sheet cell, a CSV export or a JSON endpoint
+//
+// The difference is compared with the block's tolerance and MATCH or MISMATCH written to its cell.
+// A mismatch also fails the block in the run summary, so it reaches whoever watches for failures
+// (exit code, status sheet, tray notifications) the same way a failed write does.
+
+use anyhow::{bail, Context, Result};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::accounts::{self, AccountIndex};
+use crate::amounts::{self, NumberFormat};
+use crate::capture;
+use crate::config::{Config, ReconciliationConfig, ReconciliationSource};
+use crate::context::AppContext;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+const DEFAULT_MATCH_TEXT: &str = "MATCH";
+const DEFAULT_MISMATCH_TEXT: &str = "MISMATCH";
+const DEFAULT_MATCH_COLOR: &str = "#d9ead3";
+const DEFAULT_MISMATCH_COLOR: &str = "#f4cccc";
+
+/// Both sides of one reconciliation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reconciliation {
+    pub books: Decimal,
+    pub source: Decimal,
+    pub tolerance: Decimal,
+}
+
+impl Reconciliation {
+    /// QuickBooks less the source
+    pub fn difference(&self) -> Decimal {
+        self.books - self.source
+    }
+
+    pub fn matches(&self) -> bool {
+        self.difference().abs() <= self.tolerance
+    }
+}
+
+/// The block's account balance as sync blocks write it, sign-normalized when it says so
+pub fn books_balance(block: &ReconciliationConfig, accounts: &AccountIndex, config: &Config) -> Result<Decimal> {
+    let account = accounts::find_account(accounts, &block.account_full_name)
+        .with_context(|| format!("No account named '{}'", block.account_full_name))?;
+    Ok(match block.normalizes_signs(config) {
+        true => account.normalized_balance(),
+        false => account.balance,
+    })
+}
+
+/// Fetches the source's value and sets it against `books`
+pub async fn reconcile(block: &ReconciliationConfig, books: Decimal, ctx: &AppContext<'_>) -> Result<Reconciliation> {
+    let format = ctx.config.quickbooks.number_format()?;
+    let source = match &block.source {
+        ReconciliationSource::Sheet { spreadsheet_id, sheet_name, range } => {
+            let spreadsheet_id = spreadsheet_id.as_deref().unwrap_or(&block.spreadsheet_id);
+            let rows = ctx.sheets(spreadsheet_id).read_range(sheet_name, range).await?;
+            let text = rows.first().and_then(|row| row.first()).map(String::as_str).unwrap_or_default();
+            parse_amount(text, format).with_context(|| format!("{}!{} holds '{}', not an amount", sheet_name, range, text))?
+        }
+        ReconciliationSource::Csv { path, column } => csv_amount(path, column.as_deref(), format)?,
+        ReconciliationSource::Http { url, pointer, headers } => {
+            let mut request = ctx.http.get(url).timeout(TIMEOUT);
+            for (name, value) in headers {
+                request = request.header(name.as_str(), value.as_str());
+            }
+            let (status, text) = capture::get("reconciliation", url, request).await
+                .with_context(|| format!("Failed to GET {}", host(url)))?;
+            if !status.is_success() {
+                bail!("{} returned {} - {}", host(url), status, text);
+            }
+            json_amount(&text, pointer.as_deref().unwrap_or(""), format)?
+        }
+    };
+    let tolerance = Decimal::from_f64(block.tolerance.unwrap_or(0.0)).unwrap_or_default();
+    Ok(Reconciliation { books, source, tolerance })
+}
+
+/// Writes MATCH or MISMATCH (and with write_amounts the three amounts beside it)
+pub async fn write(block: &ReconciliationConfig, result: &Reconciliation, ctx: &AppContext<'_>) -> Result<()> {
+    let gs_client = ctx.sheets(&block.spreadsheet_id);
+    let (text, color) = match result.matches() {
+        true => (block.match_text.as_deref().unwrap_or(DEFAULT_MATCH_TEXT), block.match_color.as_deref().unwrap_or(DEFAULT_MATCH_COLOR)),
+        false => (block.mismatch_text.as_deref().unwrap_or(DEFAULT_MISMATCH_TEXT), block.mismatch_color.as_deref().unwrap_or(DEFAULT_MISMATCH_COLOR)),
+    };
+    gs_client.send_flag(text, Some(color), &block.sheet_name, &block.cell_address).await?;
+    if let Some(first) = block.amount_cells().first() {
+        let row = vec![vec![amounts::json(result.books), amounts::json(result.source), amounts::json(result.difference())]];
+        gs_client.write_range(&row, &block.sheet_name, &first.to_string(), false, None).await?;
+    }
+    Ok(())
+}
+
+// An amount as a sheet, bank file or API may show it: currency symbols and codes are dropped
+// before the configured separators are applied, so "$1,234.50" and "-USD 12.00" both read
+fn parse_amount(text: &str, format: NumberFormat) -> Option<Decimal> {
+    let plain: String = text.chars()
+        .filter(|c| c.is_ascii_digit() || matches!(c, '.' | ',' | '-' | '(' | ')' | '\'' | ' ' | '\u{00A0}' | '\u{202F}'))
+        .collect();
+    format.parse(&plain)
+}
+
+// The `column` amount (default the last column) of the last row of a CSV file with a header row
+fn csv_amount(path: &str, column: Option<&str>, format: NumberFormat) -> Result<Decimal> {
+    let mut reader = csv::Reader::from_path(path).with_context(|| format!("Failed to open {}", path))?;
+    let headers = reader.headers().with_context(|| format!("Failed to read the header row of {}", path))?.clone();
+    let index = match column {
+        Some(name) => headers.iter().position(|h| h.trim().eq_ignore_ascii_case(name.trim()))
+            .with_context(|| format!("{} has no '{}' column", path, name))?,
+        None => headers.len().checked_sub(1).with_context(|| format!("{} has no columns", path))?,
+    };
+    let mut last = None;
+    for record in reader.records() {
+        last = Some(record.with_context(|| format!("Failed to read {}", path))?);
+    }
+    let last = last.with_context(|| format!("{} has no rows below the header", path))?;
+    let text = last.get(index).unwrap_or_default();
+    parse_amount(text, format).with_context(|| format!("The last row of {} holds '{}' in '{}', not an amount", path, text, &headers[index]))
+}
+
+// The amount at `pointer` in a JSON document: a number, or a string holding one
+fn json_amount(text: &str, pointer: &str, format: NumberFormat) -> Result<Decimal> {
+    let doc: Value = serde_json::from_str(text).context("The response isn't JSON")?;
+    let value = doc.pointer(pointer).with_context(|| format!("The response has nothing at '{}'", pointer))?;
+    match value {
+        Value::Number(n) => Decimal::from_str(&n.to_string()).or_else(|_| Decimal::from_scientific(&n.to_string()))
+            .with_context(|| format!("{} isn't an amount", n)),
+        Value::String(s) => parse_amount(s, format).with_context(|| format!("'{}' isn't an amount", s)),
+        other => bail!("The response has {} at '{}', not an amount", other, pointer),
+    }
+}
+
+// URLs of such endpoints may carry a key, so only the host goes in errors
+fn host(url: &str) -> String {
+    reqwest::Url::parse(url).ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_else(|| "<invalid url>".to_string())
+}
@@ -0,0 +1,125 @@
+// redact = { ... } on list and transaction blocks: personal data taken out of exported rows before
+// they are written, column by column, for sheets shared beyond the people who may see it
+//
+// "hash" replaces a value with a short keyed hash, the same for the same value (ignoring case and
+// surrounding spaces) so a column still works as a lookup or join key; "mask" keeps enough to
+// recognise it (j***@example.com, ***-0142); "remove" leaves the cell blank. The header row is
+// never touched.
+
+use anyhow::{bail, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use crate::config::{Config, ListBlockConfig, TransactionBlockConfig};
+use crate::lists;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Redaction {
+    Hash,
+    Mask,
+    Remove,
+}
+
+// hex digits kept of the hash: 64 bits, plenty to keep a customer list's values apart
+const HASH_LEN: usize = 16;
+// phone numbers keep their last digits when masked
+const PHONE_DIGITS_KEPT: usize = 4;
+
+/// The redactions of one block, resolved to column positions
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    columns: Vec<(usize, Redaction)>,
+    key: Option<String>,
+}
+
+impl Redactor {
+    /// `rules` name columns as in `headers` (spaces and case don't matter); `key` is [redaction] hash_key
+    pub fn new(headers: &[&str], rules: &HashMap<String, Redaction>, key: Option<&str>) -> Result<Self> {
+        let mut columns = Vec::with_capacity(rules.len());
+        for (column, redaction) in rules {
+            let Some(index) = headers.iter().position(|h| lists::field_key(h) == lists::field_key(column)) else {
+                bail!("redact names '{}', which is not a column of this export; it has {}", column, headers.join(", "));
+            };
+            columns.push((index, *redaction));
+        }
+        Ok(Self { columns, key: key.map(str::to_string) })
+    }
+
+    /// Redacts one data row in place
+    pub fn apply(&self, row: &mut [Value]) {
+        for (index, redaction) in &self.columns {
+            let Some(value) = row.get_mut(*index) else { continue };
+            let text = match &*value {
+                Value::String(s) => s.clone(),
+                Value::Null => continue,
+                other => other.to_string(),
+            };
+            if text.trim().is_empty() {
+                continue;
+            }
+            *value = Value::from(match redaction {
+                Redaction::Hash => hash(&text, self.key.as_deref()),
+                Redaction::Mask => mask(&text),
+                Redaction::Remove => String::new(),
+            });
+        }
+    }
+}
+
+/// The redactor of a list block, for its columns as configured
+pub fn for_list_block(block: &ListBlockConfig, config: &Config) -> Result<Redactor> {
+    Redactor::new(&block.list_type.headers(block.line_items(), block.currency(), block.category(), block.class()), &block.redact, hash_key(config))
+}
+
+pub fn for_transaction_block(block: &TransactionBlockConfig, config: &Config) -> Result<Redactor> {
+    Redactor::new(&block.txn_type.headers(), &block.redact, hash_key(config))
+}
+
+fn hash_key(config: &Config) -> Option<&str> {
+    config.redaction.as_ref().and_then(|r| r.hash_key.as_deref())
+}
+
+// HMAC-SHA256 under the key when there is one; a bare SHA-256 of an e-mail address can be reversed
+// by hashing a list of likely addresses
+fn hash(text: &str, key: Option<&str>) -> String {
+    let normalized = text.trim().to_lowercase();
+    let digest: Vec<u8> = match key {
+        Some(key) => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+            mac.update(normalized.as_bytes());
+            mac.finalize().into_bytes().to_vec()
+        }
+        None => Sha256::digest(normalized.as_bytes()).to_vec(),
+    };
+    digest.iter().map(|b| format!("{:02x}", b)).collect::<String>()[..HASH_LEN].to_string()
+}
+
+// e-mail: first letter and the domain; phone number: the last four digits, punctuation kept;
+// anything else: the first character
+fn mask(text: &str) -> String {
+    let text = text.trim();
+    if let Some((local, domain)) = text.split_once('@') {
+        let first: String = local.chars().take(1).collect();
+        return format!("{}***@{}", first, domain);
+    }
+    let digits = text.chars().filter(char::is_ascii_digit).count();
+    let phone_like = text.chars().all(|c| c.is_ascii_digit() || " +-().x".contains(c));
+    if phone_like && digits > PHONE_DIGITS_KEPT {
+        let mut seen = 0;
+        return text.chars()
+            .map(|c| {
+                if !c.is_ascii_digit() {
+                    return c;
+                }
+                seen += 1;
+                if seen > digits - PHONE_DIGITS_KEPT { c } else { '*' }
+            })
+            .collect();
+    }
+    let first: String = text.chars().take(1).collect();
+    format!("{}***", first)
+}
@@ -0,0 +1,155 @@
+// Per-block reliability: every cycle adds each block's outcome, time and error kind to the history
+// file as daily counts, and `qb_sync report reliability --days N` sums them up, so the blocks that
+// fail most (and why) stand out when tuning retries and timeouts
+
+use anyhow::Result;
+use chrono::{Days, Local, NaiveDate};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::context::AppContext;
+use crate::history::History;
+use crate::summary::{RunSummary, Status};
+
+pub const DEFAULT_KEEP_DAYS: u32 = 90;
+// error kinds listed per block in the report
+const TOP_ERRORS: usize = 3;
+
+/// Adds this cycle's outcomes to the history file; a block with several destinations counts as
+/// failed when any of them failed
+pub fn record(summary: &RunSummary, ctx: &AppContext<'_>) -> Result<()> {
+    let config = ctx.config;
+    let keep_days = config.history.as_ref().and_then(|h| h.keep_days).unwrap_or(DEFAULT_KEEP_DAYS);
+    if keep_days == 0 {
+        return Ok(());
+    }
+    let mut statuses: Vec<(String, Status)> = Vec::new();
+    for outcome in summary.outcomes() {
+        match statuses.iter_mut().find(|(block, _)| *block == outcome.block) {
+            // an error outranks a skip, which outranks ok
+            Some((_, status)) => match (&status, &outcome.status) {
+                (Status::Error(_), _) | (Status::Skipped(_), Status::Ok) => {}
+                _ => *status = outcome.status,
+            },
+            None => statuses.push((outcome.block, outcome.status)),
+        }
+    }
+    if statuses.is_empty() {
+        return Ok(());
+    }
+    let timings: HashMap<String, f64> = summary.timings().into_iter()
+        .map(|(block, timing)| (block, timing.total().as_secs_f64()))
+        .collect();
+
+    let today = Local::now().date_naive();
+    ctx.history.update(|history| {
+        for (block, status) in statuses {
+            let stats = history.day_stats(&block, today);
+            match status {
+                Status::Ok => stats.ok += 1,
+                Status::Skipped(_) => stats.skipped += 1,
+                Status::Error(e) => {
+                    stats.failed += 1;
+                    *stats.errors.entry(error_kind(&e)).or_default() += 1;
+                }
+            }
+            if let Some(secs) = timings.get(&block) {
+                stats.secs += secs;
+                stats.timed += 1;
+            }
+        }
+        history.prune_runs(today - Days::new(u64::from(keep_days - 1)));
+    })
+}
+
+// Groups error messages into a handful of kinds worth counting; anything else is known by the
+// start of its message
+fn error_kind(message: &str) -> String {
+    let lower = message.to_ascii_lowercase();
+    let kind = if lower.contains("timed out") || lower.contains("timeout") || lower.contains("deadline") {
+        "timeout"
+    } else if lower.contains("429") || lower.contains("quota") || lower.contains("rate limit") {
+        "rate limited"
+    } else if lower.contains("failed to send") || lower.contains("connect") || lower.contains("dns") {
+        "network"
+    } else if lower.contains("contains the formula") {
+        "formula protected"
+    } else if lower.contains("web app returned error: 5") {
+        "webapp 5xx"
+    } else if lower.contains("web app") {
+        "webapp error"
+    } else if lower.contains("quickbooks") || lower.contains("qbxml") {
+        "QuickBooks"
+    } else {
+        let start = message.split(':').next().unwrap_or_default().trim();
+        return start.chars().take(40).collect();
+    };
+    kind.to_string()
+}
+
+pub struct BlockReliability {
+    pub block: String,
+    pub ok: u32,
+    pub failed: u32,
+    pub skipped: u32,
+    pub mean_secs: Option<f64>,
+    // most frequent first
+    pub errors: Vec<(String, u32)>,
+}
+
+impl BlockReliability {
+    pub fn runs(&self) -> u32 {
+        self.ok + self.failed + self.skipped
+    }
+
+    pub fn success_rate(&self) -> f64 {
+        if self.runs() == 0 { 0.0 } else { f64::from(self.ok) / f64::from(self.runs()) }
+    }
+}
+
+/// Every block's counts since `first_day`, least reliable first
+pub fn summarize(history: &History, first_day: NaiveDate) -> Vec<BlockReliability> {
+    let mut report: Vec<BlockReliability> = history.runs()
+        .filter_map(|(block, days)| {
+            let (mut ok, mut failed, mut skipped, mut secs, mut timed) = (0, 0, 0, 0.0, 0);
+            let mut errors: BTreeMap<&str, u32> = BTreeMap::new();
+            for stats in days.range(first_day..).map(|(_, stats)| stats) {
+                ok += stats.ok;
+                failed += stats.failed;
+                skipped += stats.skipped;
+                secs += stats.secs;
+                timed += stats.timed;
+                for (kind, count) in &stats.errors {
+                    *errors.entry(kind).or_default() += count;
+                }
+            }
+            if ok + failed + skipped == 0 {
+                return None;
+            }
+            let mut errors: Vec<(String, u32)> = errors.into_iter().map(|(k, n)| (k.to_string(), n)).collect();
+            errors.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+            let mean_secs = (timed > 0).then(|| secs / f64::from(timed));
+            Some(BlockReliability { block: block.clone(), ok, failed, skipped, mean_secs, errors })
+        })
+        .collect();
+    report.sort_by(|a, b| a.success_rate().total_cmp(&b.success_rate()).then_with(|| a.block.cmp(&b.block)));
+    report
+}
+
+/// The report as a table, one line per block
+pub fn lines(report: &[BlockReliability]) -> Vec<String> {
+    let width = report.iter().map(|r| r.block.chars().count()).max().unwrap_or(0).max("Block".len());
+    let mut lines = vec![format!(
+        "{:<width$}  {:>5}  {:>7}  {:>6}  {:>7}  {:>9}  Most common errors",
+        "Block", "Runs", "Success", "Failed", "Skipped", "Mean time"
+    )];
+    for r in report {
+        let mean = r.mean_secs.map(|s| format!("{:.1}s", s)).unwrap_or_else(|| "-".to_string());
+        let errors: Vec<String> = r.errors.iter().take(TOP_ERRORS).map(|(kind, n)| format!("{} ({})", kind, n)).collect();
+        let line = format!(
+            "{:<width$}  {:>5}  {:>6.1}%  {:>6}  {:>7}  {:>9}  {}",
+            r.block, r.runs(), r.success_rate() * 100.0, r.failed, r.skipped, mean, errors.join(", ")
+        );
+        lines.push(line.trim_end().to_string());
+    }
+    lines
+}
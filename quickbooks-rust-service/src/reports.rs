@@ -0,0 +1,422 @@
+// qbXML summary and detail reports (GeneralSummaryReportQueryRq / GeneralDetailReportQueryRq, and
+// their Payroll* counterparts) flattened into rows for range writes
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use roxmltree::Node;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use std::str::FromStr;
+
+use crate::amounts::{self, NumberFormat};
+use crate::config::{ReportBlockConfig, RoundingConfig};
+use crate::dates;
+use crate::qbxml;
+
+#[derive(Debug, Clone, Default)]
+pub struct ReportTable {
+    pub title: String,
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+    // the account each row is about (its RowData), blank for headings, totals and item rows; kept
+    // alongside `rows` so allowed_accounts / denied_accounts can drop them
+    pub accounts: Vec<String>,
+}
+
+impl ReportTable {
+    /// Rows ready for a range write, optionally led by the column titles
+    pub fn to_rows(&self, include_header: bool) -> Vec<Vec<Value>> {
+        let mut rows = Vec::with_capacity(self.rows.len() + 1);
+        if include_header {
+            rows.push(self.headers.iter().map(|h| Value::from(h.as_str())).collect());
+        }
+        rows.extend(self.rows.iter().cloned());
+        rows
+    }
+
+    /// Drops the rows about accounts `permitted` rejects; returns how many. Totals QuickBooks
+    /// computed still include them.
+    pub fn retain_accounts(&mut self, permitted: impl Fn(&str) -> bool) -> usize {
+        let before = self.rows.len();
+        let accounts = std::mem::take(&mut self.accounts).into_iter().chain(std::iter::repeat(String::new()));
+        (self.rows, self.accounts) = std::mem::take(&mut self.rows).into_iter()
+            .zip(accounts)
+            .filter(|(_, account)| account.is_empty() || permitted(account))
+            .unzip();
+        before - self.rows.len()
+    }
+
+    /// Rounds every amount in the table per `rounding`
+    pub fn round(&mut self, rounding: RoundingConfig) {
+        for cell in self.rows.iter_mut().flatten() {
+            rounding.apply_to_cell(cell);
+        }
+    }
+}
+
+/// The total of the first row labelled `label` (ignoring case and indentation): its last amount
+pub fn row_total(table: &ReportTable, label: &str) -> Option<Decimal> {
+    let row = table.rows.iter().find(|row| {
+        row.first().and_then(Value::as_str).is_some_and(|first| first.trim().eq_ignore_ascii_case(label.trim()))
+    })?;
+    row.iter().skip(1).rev().find_map(|cell| match cell {
+        Value::Number(n) => Decimal::from_str(&n.to_string()).ok(),
+        // amounts that would lose cents as a double are written as text
+        Value::String(text) => Decimal::from_str(text.trim()).ok(),
+        _ => None,
+    })
+}
+
+// GeneralDetailReportType values; every other report type is asked for as a summary report
+const DETAIL_REPORT_TYPES: &[&str] = &[
+    "1099Detail", "AuditTrail", "BalanceSheetDetail", "CheckDetail", "CustomerBalanceDetail", "DepositDetail",
+    "EstimatesByJob", "ExpenseByVendorDetail", "GeneralLedger", "IncomeByCustomerDetail", "IncomeTaxDetail",
+    "InventoryValuationDetail", "JobProgressInvoicesVsEstimates", "Journal", "MissingChecks", "OpenInvoices",
+    "OpenPOs", "OpenPOsByJob", "OpenSalesOrderByCustomer", "OpenSalesOrderByItem", "PendingSales",
+    "ProfitAndLossDetail", "PurchaseByItemDetail", "PurchaseByVendorDetail", "SalesByCustomerDetail",
+    "SalesByItemDetail", "SalesByRepDetail", "TxnDetailByAccount", "TxnListByCustomer", "TxnListByDate",
+    "TxnListByVendor", "UnbilledCostsByJob", "UnpaidBillsDetail", "VendorBalanceDetail",
+];
+
+// PayrollSummaryReportType and PayrollDetailReportType values; QuickBooks only answers them with a
+// payroll subscription, and otherwise with status 3250
+const PAYROLL_SUMMARY_REPORT_TYPES: &[&str] = &["EmployeeEarningsSummary", "PayrollLiabilityBalances", "PayrollSummary"];
+const PAYROLL_DETAIL_REPORT_TYPES: &[&str] = &[
+    "EmployeeStateTaxesDetail", "PayrollItemDetail", "PayrollReviewDetail", "PayrollTransactionDetail",
+    "PayrollTransactionsByPayee",
+];
+
+// "GeneralDetailReport", "GeneralSummaryReport" or a payroll one, the query element without Rq / Rs
+fn report_element(block: &ReportBlockConfig) -> &'static str {
+    let is = |types: &[&str]| types.iter().any(|t| t.eq_ignore_ascii_case(&block.report_type));
+    if is(PAYROLL_SUMMARY_REPORT_TYPES) {
+        "PayrollSummaryReport"
+    } else if is(PAYROLL_DETAIL_REPORT_TYPES) {
+        "PayrollDetailReport"
+    } else if is(DETAIL_REPORT_TYPES) {
+        "GeneralDetailReport"
+    } else {
+        "GeneralSummaryReport"
+    }
+}
+
+/// Builds the GeneralSummaryReportQueryRq (or GeneralDetailReportQueryRq, for detail report types,
+/// or the payroll equivalent) for the block's report type and period.
+/// Either an explicit date range or a qbXML ReportDateMacro (e.g. "LastMonth") may be given;
+/// with neither, QuickBooks uses the report's default period. Relative dates resolve against `today`.
+/// Summary reports can have a column per period with summarize_columns_by, e.g. "Month".
+pub fn report_request(block: &ReportBlockConfig, today: NaiveDate) -> Result<String> {
+    let element = report_element(block);
+    let mut body = format!(
+        "      <{0}QueryRq>\n        <{0}Type>{1}</{0}Type>\n",
+        element,
+        qbxml::escape(&block.report_type)
+    );
+    match (&block.date_from, &block.date_to, &block.date_macro) {
+        (Some(_), _, Some(_)) | (_, Some(_), Some(_)) => {
+            anyhow::bail!("Report block '{}' sets both a date range and date_macro", block.report_type);
+        }
+        (None, None, Some(date_macro)) => {
+            body.push_str(&format!("        <ReportDateMacro>{}</ReportDateMacro>\n", qbxml::escape(date_macro)));
+        }
+        (None, None, None) => {}
+        (from, to, None) => {
+            body.push_str("        <ReportPeriod>\n");
+            if let Some(from) = from {
+                body.push_str(&format!("          <FromReportDate>{}</FromReportDate>\n", dates::resolve_qbxml_date(from, today)?));
+            }
+            if let Some(to) = to {
+                body.push_str(&format!("          <ToReportDate>{}</ToReportDate>\n", dates::resolve_qbxml_date(to, today)?));
+            }
+            body.push_str("        </ReportPeriod>\n");
+        }
+    }
+    if let Some(columns) = &block.summarize_columns_by {
+        if !element.ends_with("SummaryReport") {
+            anyhow::bail!("Report block '{}': summarize_columns_by only applies to summary reports", block.report_type);
+        }
+        body.push_str(&format!("        <SummarizeColumnsBy>{}</SummarizeColumnsBy>\n", qbxml::escape(columns)));
+    }
+    body.push_str(&format!("      </{}QueryRq>", element));
+    Ok(qbxml::envelope(&body))
+}
+
+/// Parses a report block's response: the trial balance layout for trial balances (warning when
+/// debits and credits differ), the per-item one for inventory valuation, the per-customer one for
+/// unbilled costs, the general one for every other report
+pub fn parse_for_block(block: &ReportBlockConfig, response_xml: &str, format: NumberFormat) -> Result<ReportTable> {
+    if is_inventory_valuation(block) {
+        return parse_inventory_valuation(response_xml, format);
+    }
+    if is_unbilled_costs(block) {
+        return parse_unbilled_costs(response_xml, format);
+    }
+    if !is_trial_balance(block) {
+        return parse_report(response_xml, &format!("{}QueryRs", report_element(block)), format);
+    }
+    let table = parse_trial_balance(response_xml, format)?;
+    if let Some((debits, credits)) = trial_balance_totals(&table) {
+        if (debits - credits).abs() >= 0.005 {
+            log::warn!("[QBXML] Trial balance is out of balance: debits {:.2}, credits {:.2}", debits, credits);
+        }
+    }
+    Ok(table)
+}
+
+/// Parses a GeneralSummaryReportQueryRs or GeneralDetailReportQueryRs (`rs_name`) into a table.
+/// Text rows (section headings) land in the first column; data, subtotal and total rows are
+/// placed by colID. Cells in numeric columns are written as numbers so sheet math works.
+pub fn parse_report(response_xml: &str, rs_name: &str, format: NumberFormat) -> Result<ReportTable> {
+    let doc = qbxml::parse_document(response_xml)?;
+    let rs = qbxml::find_response(&doc, rs_name)?;
+    let report = match rs.children().find(|n| n.has_tag_name("ReportRet")) {
+        Some(report) => report,
+        None => return Ok(ReportTable::default()),
+    };
+
+    let mut table = ReportTable {
+        title: qbxml::child_text(report, "ReportTitle").unwrap_or_default(),
+        ..Default::default()
+    };
+    let numeric = column_headers(report, &mut table.headers);
+    let width = table.headers.len().max(1);
+
+    if let Some(data) = report.children().find(|n| n.has_tag_name("ReportData")) {
+        for row in data.children().filter(Node::is_element) {
+            let mut cells = vec![Value::from(""); width];
+            match row.tag_name().name() {
+                "TextRow" => {
+                    cells[0] = Value::from(row.attribute("value").unwrap_or(""));
+                }
+                "DataRow" | "SubtotalRow" | "TotalRow" => place_columns(row, &numeric, format, &mut cells),
+                _ => continue,
+            }
+            table.rows.push(cells);
+            table.accounts.push(row_account(row).unwrap_or_default().to_string());
+        }
+    }
+    Ok(table)
+}
+
+// the account a data or subtotal row is about, from its RowData
+fn row_account<'a>(row: Node<'a, '_>) -> Option<&'a str> {
+    row.children()
+        .find(|n| n.has_tag_name("RowData") && n.attribute("rowType") == Some("account"))
+        .and_then(|n| n.attribute("value"))
+}
+
+// column descriptions: pushes the titles, returns whether each column holds numbers
+fn column_headers(report: Node, headers: &mut Vec<String>) -> Vec<bool> {
+    let mut numeric = Vec::new();
+    for desc in report.children().filter(|n| n.has_tag_name("ColDesc")) {
+        let title = desc.children()
+            .filter(|n| n.has_tag_name("ColTitle"))
+            .filter_map(|n| n.attribute("value"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        headers.push(title);
+        numeric.push(!matches!(desc.attribute("dataType"), Some("STRTYPE") | Some("DATETYPE") | None));
+    }
+    numeric
+}
+
+// a row's ColData into `cells` by colID, numbers as numbers
+fn place_columns(row: Node, numeric: &[bool], format: NumberFormat, cells: &mut [Value]) {
+    for col in row.children().filter(|n| n.has_tag_name("ColData")) {
+        let idx = match col.attribute("colID").and_then(|c| c.parse::<usize>().ok()) {
+            Some(id) if id >= 1 && id <= cells.len() => id - 1,
+            _ => continue,
+        };
+        let raw = col.attribute("value").unwrap_or("");
+        cells[idx] = if numeric.get(idx).copied().unwrap_or(false) {
+            format.parse(raw).map(amounts::json).unwrap_or_else(|| Value::from(raw))
+        } else {
+            Value::from(raw)
+        };
+    }
+}
+
+/// Whether a report block is the unbilled costs by job report, written with each row's customer
+pub fn is_unbilled_costs(block: &ReportBlockConfig) -> bool {
+    block.report_type.eq_ignore_ascii_case("UnbilledCostsByJob")
+}
+
+/// Parses an UnbilledCostsByJob GeneralDetailReportQueryRs into the report's own columns led by
+/// Customer: every cost row names the customer:job it waits to be billed to (from RowData, else the
+/// headings above it), so the sheet can be filtered and pivoted by customer. Headings and per-job
+/// subtotals are dropped; the Total row stays.
+pub fn parse_unbilled_costs(response_xml: &str, format: NumberFormat) -> Result<ReportTable> {
+    let doc = qbxml::parse_document(response_xml)?;
+    let rs = qbxml::find_response(&doc, "GeneralDetailReportQueryRs")?;
+    let mut table = ReportTable {
+        headers: vec!["Customer".to_string()],
+        ..Default::default()
+    };
+    let Some(report) = rs.children().find(|n| n.has_tag_name("ReportRet")) else {
+        return Ok(table);
+    };
+    table.title = qbxml::child_text(report, "ReportTitle").unwrap_or_default();
+    let numeric = column_headers(report, &mut table.headers);
+    let width = numeric.len().max(1);
+
+    // the open customer / job headings; a subtotal closes the innermost one
+    let mut headings: Vec<String> = Vec::new();
+    if let Some(data) = report.children().find(|n| n.has_tag_name("ReportData")) {
+        for row in data.children().filter(Node::is_element) {
+            let customer = match row.tag_name().name() {
+                "TextRow" => {
+                    headings.push(row.attribute("value").unwrap_or("").trim().to_string());
+                    continue;
+                }
+                "SubtotalRow" => {
+                    headings.pop();
+                    continue;
+                }
+                "DataRow" => row.children()
+                    .find(|n| n.has_tag_name("RowData"))
+                    .and_then(|n| n.attribute("value"))
+                    .map(str::to_string)
+                    .unwrap_or_else(|| headings.join(":")),
+                "TotalRow" => "Total".to_string(),
+                _ => continue,
+            };
+            let mut cells = vec![Value::from(""); width];
+            place_columns(row, &numeric, format, &mut cells);
+            cells.insert(0, Value::from(customer));
+            table.rows.push(cells);
+            table.accounts.push(String::new());
+        }
+    }
+    Ok(table)
+}
+
+/// Whether a report block is a trial balance, which gets its own account / debit / credit layout
+pub fn is_trial_balance(block: &ReportBlockConfig) -> bool {
+    block.report_type.eq_ignore_ascii_case("TrialBalance")
+}
+
+/// Parses a TrialBalance GeneralSummaryReportQueryRs into Account / Debit / Credit rows: one per
+/// account (full name, taken from RowData so sub-accounts stay unambiguous), then the total row.
+/// Section headings and subtotals are dropped; they would only double count.
+pub fn parse_trial_balance(response_xml: &str, format: NumberFormat) -> Result<ReportTable> {
+    let doc = qbxml::parse_document(response_xml)?;
+    let rs = qbxml::find_response(&doc, "GeneralSummaryReportQueryRs")?;
+    let mut table = ReportTable {
+        headers: vec!["Account".to_string(), "Debit".to_string(), "Credit".to_string()],
+        ..Default::default()
+    };
+    let Some(report) = rs.children().find(|n| n.has_tag_name("ReportRet")) else {
+        return Ok(table);
+    };
+    table.title = qbxml::child_text(report, "ReportTitle").unwrap_or_default();
+
+    // the account label is column 1; a title row of the others is the date
+    let (Some(debit_col), Some(credit_col)) = (column_id(report, &["Debit"]), column_id(report, &["Credit"])) else {
+        anyhow::bail!("Trial balance report has no Debit / Credit columns");
+    };
+    let amount = |row: Node, col: &str| column_amount(row, col, format);
+
+    if let Some(data) = report.children().find(|n| n.has_tag_name("ReportData")) {
+        for row in data.children().filter(|n| n.has_tag_name("DataRow") || n.has_tag_name("TotalRow")) {
+            let account = if row.has_tag_name("TotalRow") {
+                "Total".to_string()
+            } else {
+                row_account(row)
+                    .or_else(|| {
+                        row.children()
+                            .find(|n| n.has_tag_name("ColData") && n.attribute("colID") == Some("1"))
+                            .and_then(|n| n.attribute("value"))
+                    })
+                    .unwrap_or("")
+                    .to_string()
+            };
+            table.rows.push(vec![Value::from(account.as_str()), amount(row, &debit_col), amount(row, &credit_col)]);
+            table.accounts.push(if row.has_tag_name("TotalRow") { String::new() } else { account });
+        }
+    }
+    Ok(table)
+}
+
+// colID of the first column whose ColType or one of whose title rows is one of `names`
+fn column_id(report: Node, names: &[&str]) -> Option<String> {
+    report.children().filter(|n| n.has_tag_name("ColDesc")).find(|desc| {
+        qbxml::child_text(*desc, "ColType").into_iter()
+            .chain(desc.children()
+                .filter(|n| n.has_tag_name("ColTitle"))
+                .filter_map(|n| n.attribute("value"))
+                .map(|v| v.trim().to_string()))
+            .any(|name| names.contains(&name.as_str()))
+    })?.attribute("colID").map(str::to_string)
+}
+
+// a row's amount in column `col`, blank when it has none
+fn column_amount(row: Node, col: &str, format: NumberFormat) -> Value {
+    row.children()
+        .filter(|n| n.has_tag_name("ColData"))
+        .find(|n| n.attribute("colID") == Some(col))
+        .and_then(|n| n.attribute("value"))
+        .and_then(|v| format.parse(v))
+        .map(amounts::json)
+        .unwrap_or_else(|| Value::from(""))
+}
+
+/// Whether a report block is an inventory valuation summary, written one row per item
+pub fn is_inventory_valuation(block: &ReportBlockConfig) -> bool {
+    block.report_type.eq_ignore_ascii_case("InventoryValuationSummary")
+}
+
+/// Parses an InventoryValuationSummary GeneralSummaryReportQueryRs into Item / On Hand / Avg Cost /
+/// Asset Value rows: one per item (full name from RowData, so sub-items stay unambiguous), then the
+/// total row. Headings and parent-item subtotals are dropped like the trial balance's; the
+/// percentage and retail columns are left out.
+pub fn parse_inventory_valuation(response_xml: &str, format: NumberFormat) -> Result<ReportTable> {
+    let doc = qbxml::parse_document(response_xml)?;
+    let rs = qbxml::find_response(&doc, "GeneralSummaryReportQueryRs")?;
+    let mut table = ReportTable {
+        headers: ["Item", "On Hand", "Avg Cost", "Asset Value"].map(String::from).to_vec(),
+        ..Default::default()
+    };
+    let Some(report) = rs.children().find(|n| n.has_tag_name("ReportRet")) else {
+        return Ok(table);
+    };
+    table.title = qbxml::child_text(report, "ReportTitle").unwrap_or_default();
+
+    let columns = [
+        column_id(report, &["QuantityOnHand", "On Hand"]),
+        column_id(report, &["AverageCost", "Avg Cost"]),
+        column_id(report, &["AssetValue", "Asset Value"]),
+    ];
+    let [Some(on_hand), Some(avg_cost), Some(asset_value)] = columns else {
+        anyhow::bail!("Inventory valuation report has no On Hand / Avg Cost / Asset Value columns");
+    };
+
+    if let Some(data) = report.children().find(|n| n.has_tag_name("ReportData")) {
+        for row in data.children().filter(|n| n.has_tag_name("DataRow") || n.has_tag_name("TotalRow")) {
+            let item = if row.has_tag_name("TotalRow") {
+                "Total".to_string()
+            } else {
+                row.children()
+                    .find(|n| n.has_tag_name("RowData") && n.attribute("rowType") == Some("item"))
+                    .and_then(|n| n.attribute("value"))
+                    .or_else(|| {
+                        row.children()
+                            .find(|n| n.has_tag_name("ColData") && n.attribute("colID") == Some("1"))
+                            .and_then(|n| n.attribute("value"))
+                    })
+                    .unwrap_or("")
+                    .to_string()
+            };
+            let mut cells = vec![Value::from(item)];
+            cells.extend([&on_hand, &avg_cost, &asset_value].map(|col| column_amount(row, col, format)));
+            table.rows.push(cells);
+            table.accounts.push(String::new());
+        }
+    }
+    Ok(table)
+}
+
+/// Debit and credit totals of a parsed trial balance, when it has a total row
+pub fn trial_balance_totals(table: &ReportTable) -> Option<(f64, f64)> {
+    let total = table.rows.iter().rev().find(|r| r.first().and_then(Value::as_str) == Some("Total"))?;
+    Some((total[1].as_f64().unwrap_or(0.0), total[2].as_f64().unwrap_or(0.0)))
+}
@@ -0,0 +1,105 @@
+// [quickbooks] cache_secs: answer a repeated qbXML query from the last response to it
+//
+// Blocks often send byte-for-byte the same request, such as two report blocks whose date macros
+// both come out as this month, or the same list read by two blocks. Each query request is hashed
+// and its response kept for cache_secs; an identical request within that time gets the kept
+// response without a call to QuickBooks. Only queries are kept, and only responses without an
+// error status, so a failed or half-answered request is always sent again. The cache is per
+// company file and lives as long as the process, so a daemon reuses responses across cycles.
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::file_mode::FileMode;
+use crate::qb_backend::RequestProcessor;
+use crate::qbxml;
+
+// (company file, SHA-256 of the request) -> when it was answered, and the response
+type Responses = HashMap<(String, [u8; 32]), (Instant, String)>;
+
+static RESPONSES: OnceLock<Mutex<Responses>> = OnceLock::new();
+
+fn with_responses<T>(f: impl FnOnce(&mut Responses) -> T) -> T {
+    let mut responses = RESPONSES.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut responses)
+}
+
+/// `processor` answering repeated queries from the cache when [quickbooks] cache_secs is set
+pub fn wrap(processor: Box<dyn RequestProcessor>, config: &Config) -> Box<dyn RequestProcessor> {
+    match config.quickbooks.cache_secs {
+        Some(secs) if secs > 0 => Box::new(Cached {
+            inner: processor,
+            company: config.quickbooks.company_file.to_ascii_lowercase(),
+            ttl: Duration::from_secs(secs),
+        }),
+        _ => processor,
+    }
+}
+
+pub struct Cached {
+    inner: Box<dyn RequestProcessor>,
+    company: String,
+    ttl: Duration,
+}
+
+// Whether every message of the request only reads: a *QueryRq, which includes the reports
+fn is_query(request: &str) -> bool {
+    let Ok(doc) = qbxml::parse_document(request) else { return false };
+    let mut messages = doc.descendants()
+        .filter(|n| n.parent().is_some_and(|p| p.tag_name().name().ends_with("MsgsRq")))
+        .filter(|n| n.is_element())
+        .peekable();
+    messages.peek().is_some() && messages.all(|n| n.tag_name().name().ends_with("QueryRq"))
+}
+
+/// Whether any response in it reports an error; warnings such as "no match" are fine to keep
+pub fn has_error(response: &str) -> bool {
+    let Ok(doc) = qbxml::parse_document(response) else { return true };
+    doc.descendants().any(|n| n.attribute("statusSeverity").is_some_and(|s| s.eq_ignore_ascii_case("error")))
+}
+
+impl RequestProcessor for Cached {
+    fn open_connection(&self, app_id: &str, app_name: &str) -> Result<()> {
+        self.inner.open_connection(app_id, app_name)
+    }
+
+    fn begin_session(&self, company_file: &str, file_mode: FileMode) -> Result<String> {
+        self.inner.begin_session(company_file, file_mode)
+    }
+
+    fn process_request(&self, ticket: &str, request: &str) -> Result<String> {
+        if !is_query(request) {
+            return self.inner.process_request(ticket, request);
+        }
+        let key = (self.company.clone(), Sha256::digest(request.as_bytes()).into());
+        let kept = with_responses(|responses| {
+            responses.retain(|_, (at, _)| at.elapsed() < self.ttl);
+            responses.get(&key).map(|(at, response)| (at.elapsed(), response.clone()))
+        });
+        if let Some((age, response)) = kept {
+            log::debug!("[QBXML] Reusing the response to an identical request from {}s ago", age.as_secs());
+            return Ok(response);
+        }
+        let response = self.inner.process_request(ticket, request)?;
+        if !has_error(&response) {
+            with_responses(|responses| responses.insert(key, (Instant::now(), response.clone())));
+        }
+        Ok(response)
+    }
+
+    fn end_session(&self, ticket: &str) -> Result<()> {
+        self.inner.end_session(ticket)
+    }
+
+    fn close_connection(&self) -> Result<()> {
+        self.inner.close_connection()
+    }
+
+    fn get_current_company_file_name(&self, ticket: &str) -> Result<String> {
+        self.inner.get_current_company_file_name(ticket)
+    }
+}
@@ -0,0 +1,109 @@
+// --resume: pick up a cycle that was cut short by a crash, a reboot or a stop request
+//
+// While a cycle runs, the blocks that have completed (finished, with every destination written) are
+// kept in a checkpoint file next to the history file, rewritten as each block finishes. A cycle that reaches its end removes it, so the file
+// only survives a cycle that never finished. With --resume, a cycle that finds one skips the blocks
+// it lists instead of writing them again, which for append-mode blocks would add their row twice.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::config::Config;
+use crate::history::History;
+
+const FILE_NAME: &str = "cycle.json";
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Called once from main for --resume
+pub fn request() {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CheckpointFile {
+    run_id: Option<String>,
+    started: DateTime<Local>,
+    completed: BTreeSet<String>,
+}
+
+/// The running cycle's checkpoint
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    run_id: Option<String>,
+    started: DateTime<Local>,
+    // blocks completed by the interrupted cycle this one resumes, and by this one
+    completed: Mutex<BTreeSet<String>>,
+}
+
+impl Checkpoint {
+    /// Starts the checkpoint for a new cycle. With --resume and an unfinished cycle on disk, also
+    /// returns the blocks that cycle completed, which this one should skip.
+    pub fn start(config: &Config, run_id: Option<String>) -> (Self, BTreeSet<String>) {
+        let path = History::path(config).with_file_name(FILE_NAME);
+        let mut carried = BTreeSet::new();
+        if REQUESTED.load(Ordering::Relaxed) {
+            match read(&path) {
+                Ok(Some(previous)) => {
+                    log::info!(
+                        "[RESUME] Resuming the cycle started {} (run {}): {} block(s) already completed",
+                        previous.started.format("%Y-%m-%d %H:%M:%S"),
+                        previous.run_id.as_deref().unwrap_or("-"),
+                        previous.completed.len()
+                    );
+                    carried = previous.completed;
+                }
+                Ok(None) => log::info!("[RESUME] No unfinished cycle to resume; running every block"),
+                Err(e) => log::warn!("[RESUME] Ignoring the unfinished cycle's checkpoint: {:#}", e),
+            }
+        }
+        let checkpoint = Self { path, run_id, started: Local::now(), completed: Mutex::new(carried.clone()) };
+        (checkpoint, carried)
+    }
+
+    /// Adds a block that finished with every outcome OK and saves the checkpoint
+    pub fn complete(&self, block: &str) {
+        // held while writing, so blocks finishing together save one after another
+        let mut completed = self.completed.lock().unwrap();
+        completed.insert(block.to_string());
+        let file = CheckpointFile { run_id: self.run_id.clone(), started: self.started, completed: completed.clone() };
+        if let Err(e) = write(&self.path, &file) {
+            log::warn!("[RESUME] Failed to save the cycle checkpoint: {:#}", e);
+        }
+    }
+
+    /// The cycle reached its end; nothing is left to resume
+    pub fn finish(&self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("[RESUME] Failed to remove {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Option<CheckpointFile>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let file = serde_json::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(file))
+}
+
+fn write(path: &Path, file: &CheckpointFile) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(file)?)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,78 @@
+// active_hours / active_days: when a block runs, so nothing touches QuickBooks while the company
+// file is locked for the nightly backup
+//
+// "07:00-19:00" runs from 07:00 up to, not including, 19:00; "22:00-06:00" runs overnight, and
+// then belongs to the day it starts on. Days are "Mon".."Sun" (full names too, any case) or a
+// range such as "Mon-Fri". A block with either setting uses its own window; a block with neither
+// uses the top-level one, which also covers [company_info], [kpis] and the other sections that
+// aren't blocks. Times are in the [quickbooks] timezone.
+
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    // None: all day
+    hours: Option<(NaiveTime, NaiveTime)>,
+    // empty: every day
+    days: Vec<Weekday>,
+}
+
+impl Window {
+    /// None when neither is set, meaning always
+    pub fn parse(active_hours: Option<&str>, active_days: &[String]) -> Result<Option<Window>> {
+        if active_hours.is_none() && active_days.is_empty() {
+            return Ok(None);
+        }
+        let hours = active_hours.map(parse_hours).transpose()?;
+        let mut days = Vec::new();
+        for entry in active_days {
+            days.extend(parse_days(entry)?);
+        }
+        Ok(Some(Window { hours, days }))
+    }
+
+    pub fn contains(&self, at: NaiveDateTime) -> bool {
+        let day = at.weekday();
+        let on = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+        let time = at.time();
+        match self.hours {
+            None => on(day),
+            Some((start, end)) if start < end => on(day) && time >= start && time < end,
+            // overnight: the early hours belong to the evening before
+            Some((start, end)) => (time >= start && on(day)) || (time < end && on(day.pred())),
+        }
+    }
+}
+
+// "07:00-19:00"
+fn parse_hours(text: &str) -> Result<(NaiveTime, NaiveTime)> {
+    let parse = |part: &str| NaiveTime::parse_from_str(part.trim(), "%H:%M")
+        .with_context(|| format!("active_hours '{}': '{}' isn't a time of day as HH:MM", text, part.trim()));
+    let Some((start, end)) = text.split_once('-') else {
+        bail!("active_hours '{}' should be a start and end time, e.g. \"07:00-19:00\"", text);
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start == end {
+        bail!("active_hours '{}' starts and ends at the same time; leave it out to run all day", text);
+    }
+    Ok((start, end))
+}
+
+// "Mon", "monday" or "Mon-Fri"
+fn parse_days(entry: &str) -> Result<Vec<Weekday>> {
+    let day = |name: &str| name.trim().parse::<Weekday>()
+        .map_err(|_| anyhow::anyhow!("active_days '{}': '{}' isn't a day of the week such as \"Mon\"", entry, name.trim()));
+    match entry.split_once('-') {
+        None => Ok(vec![day(entry)?]),
+        Some((first, last)) => {
+            let (mut day_at, last) = (day(first)?, day(last)?);
+            let mut days = vec![day_at];
+            while day_at != last {
+                day_at = day_at.succ();
+                days.push(day_at);
+            }
+            Ok(days)
+        }
+    }
+}
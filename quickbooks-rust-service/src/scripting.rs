@@ -0,0 +1,116 @@
+// scripts = ["scripts/x.rhai", ...]: small Rhai (https://rhai.rs) scripts for the transformations the
+// config can't express, run in order with each one's result passed to the next
+//
+// A sync block's scripts see `value` (the number or text about to be written) and `record` (the
+// account: account_full_name, balance, account_type, ...) and return the value to write: a number,
+// text, or () to write nothing this run. A list or transaction block's scripts see each data row as
+// `row`, a map from column header to cell, and return the row (a column left out is written blank)
+// or () to drop it. Scripts have no file or network access, and one that runs away is stopped after
+// MAX_OPERATIONS. print() goes to the log. The files are compiled when the config loads and read
+// again at the start of each block, like the name mapping.
+
+use anyhow::{bail, Context, Result};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use rust_decimal::Decimal;
+use serde_json::Value;
+use std::str::FromStr;
+
+use crate::amounts;
+use crate::destinations::SyncValue;
+
+// plenty for per-row arithmetic and string work; a loop that never ends fails the block instead
+const MAX_OPERATIONS: u64 = 1_000_000;
+// well past cents and exchange rates, well short of the noise of float arithmetic
+const FLOAT_DECIMALS: u32 = 9;
+
+pub struct Scripts {
+    engine: Engine,
+    scripts: Vec<(String, AST)>,
+}
+
+impl Scripts {
+    /// Reads and compiles `paths`; none is an empty chain that changes nothing
+    pub fn load(paths: &[String]) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| log::info!("[SCRIPT] {}", text));
+        engine.on_debug(|text, _, position| log::debug!("[SCRIPT] {} {}", position, text));
+        let mut scripts = Vec::with_capacity(paths.len());
+        for path in paths {
+            let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read script {}", path))?;
+            let ast = engine.compile(&text).with_context(|| format!("Script {} doesn't compile", path))?;
+            scripts.push((path.clone(), ast));
+        }
+        Ok(Self { engine, scripts })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    /// A sync block's value after its scripts; None when one returned ()
+    pub fn value(&self, value: SyncValue) -> Result<Option<SyncValue>> {
+        let record = rhai::serde::to_dynamic(&value.account)?;
+        let mut current = rhai::serde::to_dynamic(value.cell_value())?;
+        for (path, ast) in &self.scripts {
+            let mut scope = Scope::new();
+            scope.push("record", record.clone());
+            scope.push("value", current);
+            current = self.engine.eval_ast_with_scope::<Dynamic>(&mut scope, ast)
+                .with_context(|| format!("Script {} failed", path))?;
+            if current.is_unit() {
+                return Ok(None);
+            }
+        }
+        let mut value = value;
+        if current.is_string() {
+            value.rendered = Some(current.into_string().unwrap_or_default());
+        } else if let Some(balance) = number(&current) {
+            (value.account.balance, value.rendered) = (balance, None);
+        } else {
+            bail!("Script returned {}; a sync block's script should return a number, text or ()", current.type_name());
+        }
+        Ok(Some(value))
+    }
+
+    /// One data row after the scripts, its cells in `headers` order; None when one returned ()
+    pub fn row(&self, headers: &[&str], row: Vec<Value>) -> Result<Option<Vec<Value>>> {
+        let mut current = Map::new();
+        for (header, cell) in headers.iter().zip(row) {
+            current.insert((*header).into(), rhai::serde::to_dynamic(cell)?);
+        }
+        for (path, ast) in &self.scripts {
+            let mut scope = Scope::new();
+            scope.push("row", current);
+            let result = self.engine.eval_ast_with_scope::<Dynamic>(&mut scope, ast)
+                .with_context(|| format!("Script {} failed", path))?;
+            if result.is_unit() {
+                return Ok(None);
+            }
+            let type_name = result.type_name();
+            current = result.try_cast::<Map>()
+                .with_context(|| format!("Script {} returned {}; a row script should return the row (a map) or ()", path, type_name))?;
+        }
+        if let Some(unknown) = current.keys().find(|key| !headers.contains(&key.as_str())) {
+            bail!("Script returned a '{}' column, which the export doesn't have", unknown);
+        }
+        headers.iter()
+            .map(|header| match current.remove(*header) {
+                Some(cell) => Ok(rhai::serde::from_dynamic::<Value>(&cell)?),
+                None => Ok(Value::Null),
+            })
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+}
+
+// through text, as amounts does, then to FLOAT_DECIMALS, so 45210.37 / 1000.0 is 45.21037 and not
+// 45.210370000000005
+fn number(value: &Dynamic) -> Option<Decimal> {
+    if let Ok(int) = value.as_int() {
+        return Some(Decimal::from(int));
+    }
+    let text = value.as_float().ok()?.to_string();
+    let number = Decimal::from_str(&text).or_else(|_| Decimal::from_scientific(&text)).ok()?;
+    Some(amounts::round(number, FLOAT_DECIMALS).normalize())
+}
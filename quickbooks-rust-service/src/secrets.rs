@@ -0,0 +1,231 @@
+// Encrypted config values
+//
+// Sensitive values in config.toml can be stored as ciphertext instead of plaintext:
+//   enc:dpapi:<base64>  Windows DPAPI, only decryptable by the same Windows user (or machine with --machine)
+//   enc:age:<base64>    age (x25519), decryptable with the identity file from [secrets] or QB_SYNC_AGE_IDENTITY
+// Produce them with `qb_sync encrypt-secret`. Values without the enc: prefix are used as-is.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::io::{IsTerminal, Read};
+use std::str::FromStr;
+
+pub const PREFIX: &str = "enc:";
+pub const AGE_IDENTITY_ENV_VAR: &str = "QB_SYNC_AGE_IDENTITY";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Method {
+    Dpapi,
+    Age,
+}
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+/// Returns the plaintext for a config value, decrypting it if it carries the enc: prefix
+pub fn reveal(value: &str, age_identity_file: Option<&str>) -> Result<String> {
+    let Some(rest) = value.strip_prefix(PREFIX) else {
+        return Ok(value.to_string());
+    };
+    let (method, encoded) = rest
+        .split_once(':')
+        .ok_or_else(|| anyhow!("encrypted value must look like enc:<dpapi|age>:<base64>"))?;
+    let ciphertext = BASE64.decode(encoded.trim()).context("encrypted value is not valid base64")?;
+
+    let plaintext = match method {
+        "dpapi" => dpapi::unprotect(&ciphertext)?,
+        "age" => {
+            let identity_file = std::env::var(AGE_IDENTITY_ENV_VAR)
+                .ok()
+                .or_else(|| age_identity_file.map(str::to_string))
+                .ok_or_else(|| anyhow!("enc:age value found but no identity file; set [secrets] age_identity_file or {}", AGE_IDENTITY_ENV_VAR))?;
+            age_decrypt(&ciphertext, &identity_file)?
+        }
+        other => bail!("unknown encryption method '{}' (expected dpapi or age)", other),
+    };
+    String::from_utf8(plaintext).context("decrypted value is not valid UTF-8")
+}
+
+/// Encrypts a plaintext value and returns the enc:... string to paste into config.toml
+pub fn seal(plaintext: &str, method: Method, age_recipient: Option<&str>, machine_scope: bool) -> Result<String> {
+    let (name, ciphertext) = match method {
+        Method::Dpapi => ("dpapi", dpapi::protect(plaintext.as_bytes(), machine_scope)?),
+        Method::Age => {
+            let recipient = age_recipient.ok_or_else(|| anyhow!("--recipient <age1...> is required for age encryption"))?;
+            let recipient = age::x25519::Recipient::from_str(recipient.trim())
+                .map_err(|e| anyhow!("invalid age recipient: {}", e))?;
+            ("age", age::encrypt(&recipient, plaintext.as_bytes()).context("age encryption failed")?)
+        }
+    };
+    Ok(format!("{}{}:{}", PREFIX, name, BASE64.encode(ciphertext)))
+}
+
+fn age_decrypt(ciphertext: &[u8], identity_file: &str) -> Result<Vec<u8>> {
+    let identities = age::IdentityFile::from_file(identity_file.to_string())
+        .with_context(|| format!("Failed to read age identity file {}", identity_file))?
+        .into_identities()
+        .map_err(|e| anyhow!("Failed to load age identities from {}: {}", identity_file, e))?;
+
+    let decryptor = age::Decryptor::new(ciphertext).context("not a valid age ciphertext")?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|identity| identity.as_ref() as &dyn age::Identity))
+        .context("age decryption failed; wrong identity file?")?;
+    let mut plaintext = Vec::new();
+    reader.read_to_end(&mut plaintext)?;
+    Ok(plaintext)
+}
+
+/// One line from stdin without its line ending. When stdin is a terminal the typing isn't echoed,
+/// so the secret stays off the screen and out of terminal scrollback.
+pub fn read_line_hidden() -> Result<String> {
+    let stdin = std::io::stdin();
+    let terminal = stdin.is_terminal();
+    let echo = if terminal { Some(echo::Off::new()?) } else { None };
+    let mut line = String::new();
+    let read = stdin.read_line(&mut line);
+    drop(echo);
+    if terminal {
+        // the Enter wasn't echoed either
+        eprintln!();
+    }
+    read?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(unix)]
+mod echo {
+    use anyhow::Result;
+
+    // echo goes back on when this is dropped
+    pub struct Off(libc::termios);
+
+    impl Off {
+        pub fn new() -> Result<Self> {
+            unsafe {
+                let mut attrs: libc::termios = std::mem::zeroed();
+                if libc::tcgetattr(libc::STDIN_FILENO, &mut attrs) != 0 {
+                    return Err(std::io::Error::last_os_error().into());
+                }
+                let saved = attrs;
+                attrs.c_lflag &= !libc::ECHO;
+                if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &attrs) != 0 {
+                    return Err(std::io::Error::last_os_error().into());
+                }
+                Ok(Off(saved))
+            }
+        }
+    }
+
+    impl Drop for Off {
+        fn drop(&mut self) {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+        }
+    }
+}
+
+#[cfg(all(windows, feature = "qb-com"))]
+mod echo {
+    use anyhow::{bail, Result};
+    use winapi::um::consoleapi::{GetConsoleMode, SetConsoleMode};
+    use winapi::um::errhandlingapi::GetLastError;
+    use winapi::um::processenv::GetStdHandle;
+    use winapi::um::winbase::STD_INPUT_HANDLE;
+    use winapi::um::wincon::ENABLE_ECHO_INPUT;
+    use winapi::um::winnt::HANDLE;
+
+    // the console mode goes back when this is dropped
+    pub struct Off(HANDLE, u32);
+
+    impl Off {
+        pub fn new() -> Result<Self> {
+            unsafe {
+                let console = GetStdHandle(STD_INPUT_HANDLE);
+                let mut mode = 0;
+                if GetConsoleMode(console, &mut mode) == 0 || SetConsoleMode(console, mode & !ENABLE_ECHO_INPUT) == 0 {
+                    bail!("Failed to turn console echo off: error {}", GetLastError());
+                }
+                Ok(Off(console, mode))
+            }
+        }
+    }
+
+    impl Drop for Off {
+        fn drop(&mut self) {
+            unsafe { SetConsoleMode(self.0, self.1) };
+        }
+    }
+}
+
+// no console API without winapi (the qb-com feature); the secret is echoed as typed
+#[cfg(not(any(unix, all(windows, feature = "qb-com"))))]
+mod echo {
+    use anyhow::Result;
+
+    pub struct Off;
+
+    impl Off {
+        pub fn new() -> Result<Self> {
+            Ok(Off)
+        }
+    }
+}
+
+#[cfg(feature = "qb-com")]
+mod dpapi {
+    use anyhow::{bail, Result};
+    use std::ptr;
+    use winapi::um::dpapi::{CryptProtectData, CryptUnprotectData, CRYPTPROTECT_LOCAL_MACHINE, CRYPTPROTECT_UI_FORBIDDEN};
+    use winapi::um::errhandlingapi::GetLastError;
+    use winapi::um::winbase::LocalFree;
+    use winapi::um::wincrypt::DATA_BLOB;
+
+    // DPAPI hands back a LocalAlloc'd buffer; copy it out and free it
+    unsafe fn take_blob(blob: &DATA_BLOB) -> Vec<u8> {
+        let bytes = std::slice::from_raw_parts(blob.pbData, blob.cbData as usize).to_vec();
+        LocalFree(blob.pbData as _);
+        bytes
+    }
+
+    pub fn protect(plaintext: &[u8], machine_scope: bool) -> Result<Vec<u8>> {
+        let mut input = DATA_BLOB { cbData: plaintext.len() as u32, pbData: plaintext.as_ptr() as *mut u8 };
+        let mut output = DATA_BLOB { cbData: 0, pbData: ptr::null_mut() };
+        let mut flags = CRYPTPROTECT_UI_FORBIDDEN;
+        if machine_scope {
+            flags |= CRYPTPROTECT_LOCAL_MACHINE;
+        }
+        unsafe {
+            if CryptProtectData(&mut input, ptr::null(), ptr::null_mut(), ptr::null_mut(), ptr::null_mut(), flags, &mut output) == 0 {
+                bail!("CryptProtectData failed: error {}", GetLastError());
+            }
+            Ok(take_blob(&output))
+        }
+    }
+
+    pub fn unprotect(ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let mut input = DATA_BLOB { cbData: ciphertext.len() as u32, pbData: ciphertext.as_ptr() as *mut u8 };
+        let mut output = DATA_BLOB { cbData: 0, pbData: ptr::null_mut() };
+        unsafe {
+            if CryptUnprotectData(&mut input, ptr::null_mut(), ptr::null_mut(), ptr::null_mut(), ptr::null_mut(), CRYPTPROTECT_UI_FORBIDDEN, &mut output) == 0 {
+                // usually means the value was encrypted by a different Windows user or machine
+                bail!("CryptUnprotectData failed: error {} (was this value encrypted by another Windows user?)", GetLastError());
+            }
+            Ok(take_blob(&output))
+        }
+    }
+}
+
+// DPAPI is Windows only; age values still work in a build without qb-com
+#[cfg(not(feature = "qb-com"))]
+mod dpapi {
+    use anyhow::{bail, Result};
+
+    pub fn protect(_plaintext: &[u8], _machine_scope: bool) -> Result<Vec<u8>> {
+        bail!("DPAPI needs the Windows build (the qb-com feature); use --method age")
+    }
+
+    pub fn unprotect(_ciphertext: &[u8]) -> Result<Vec<u8>> {
+        bail!("enc:dpapi values can only be decrypted by the Windows build (the qb-com feature); re-encrypt with --method age")
+    }
+}
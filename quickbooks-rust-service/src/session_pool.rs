@@ -0,0 +1,133 @@
+// [quickbooks] parallel_sessions: extra QuickBooks sessions for report and transaction blocks
+//
+// QBXMLRP2.RequestProcessor is an apartment-threaded COM object. Created from a multithreaded
+// apartment, COM would host it in a single STA and marshal every call there, so the calls would
+// still go one at a time. Instead each pooled session lives on a thread of its own that
+// initializes COM as an STA, opens its own connection and session, and takes requests off a shared
+// queue until the pool is closed. Only the request and response text cross threads.
+//
+// How much runs at once is up to QuickBooks: it answers some requests against one company file in
+// turn whatever the number of sessions. Even then the blocks' parsing and Sheets writes overlap
+// the next query, which is most of a report-heavy cycle.
+
+use anyhow::{anyhow, Result};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tokio::sync::oneshot;
+
+use crate::capture;
+use crate::config::Config;
+use crate::qb_backend::RequestProcessor;
+use crate::qb_session::QbSession;
+
+struct Job {
+    request: String,
+    reply: oneshot::Sender<Result<String>>,
+}
+
+pub struct SessionPool {
+    jobs: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl SessionPool {
+    /// The pool for this cycle's `pending` report and transaction blocks: None when
+    /// parallel_sessions is 1, there is only one such block, or during --record / --replay, whose
+    /// capture has to stay in order. Also None, after a warning, when no pooled session opens.
+    pub fn for_cycle(config: &Config, pending: usize) -> Option<Self> {
+        let size = config.quickbooks.parallel_sessions.unwrap_or(1) as usize;
+        if size < 2 || pending < 2 || capture::active() {
+            return None;
+        }
+        Self::open(config, size.min(pending))
+    }
+
+    // Opens `size` sessions at once, since each BeginSession takes seconds, and waits for all of them
+    fn open(config: &Config, size: usize) -> Option<Self> {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        let (ready, opened) = mpsc::channel::<bool>();
+        let mut workers = Vec::with_capacity(size);
+        for number in 1..=size {
+            let (config, queue, ready) = (config.clone(), queue.clone(), ready.clone());
+            match std::thread::Builder::new().name(format!("qb-session-{}", number)).spawn(move || work(number, &config, &queue, ready)) {
+                Ok(handle) => workers.push(handle),
+                Err(e) => log::warn!("[QBXML] Failed to start pooled session {}: {}", number, e),
+            }
+        }
+        // every worker answers once, then drops its sender
+        drop(ready);
+        let opened = opened.iter().filter(|ok| *ok).count();
+        let pool = Self { jobs: Some(jobs), workers };
+        if opened == 0 {
+            log::warn!("[QBXML] No pooled session opened; report and transaction blocks use the cycle's session");
+            pool.close();
+            return None;
+        }
+        log::info!("[QBXML] {} pooled session(s) open for report and transaction blocks", opened);
+        Some(pool)
+    }
+
+    /// Queues `request` for the next idle session and waits for its response
+    pub async fn process_request(&self, request: &str) -> Result<String> {
+        let (reply, response) = oneshot::channel();
+        let jobs = self.jobs.as_ref().ok_or_else(|| anyhow!("The session pool is closed"))?;
+        jobs.send(Job { request: request.to_string(), reply })
+            .map_err(|_| anyhow!("Every pooled QuickBooks session has closed"))?;
+        response.await.map_err(|_| anyhow!("The pooled QuickBooks session closed before answering"))?
+    }
+
+    /// Lets each session finish its request, then ends the sessions and their threads
+    pub fn close(mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                log::error!("[QBXML] A pooled session's thread panicked");
+            }
+        }
+    }
+}
+
+// One pooled session: opened, and COM initialized, on this thread, which it never leaves
+fn work(number: usize, config: &Config, queue: &Mutex<mpsc::Receiver<Job>>, ready: mpsc::Sender<bool>) {
+    let session = match QbSession::open(config) {
+        Ok(session) => session,
+        Err(e) => {
+            log::warn!("[QBXML] Pooled session {} failed to open: {:#}", number, e);
+            let _ = ready.send(false);
+            return;
+        }
+    };
+    let _ = ready.send(true);
+    drop(ready);
+    loop {
+        // the lock is only held while waiting, so each job goes to exactly one idle session
+        let job = queue.lock().map_err(|_| ()).and_then(|queue| queue.recv().map_err(|_| ()));
+        let Ok(job) = job else { break };
+        // a block that timed out has stopped listening
+        let _ = job.reply.send(session.processor.process_request(&session.ticket, &job.request));
+    }
+    session.close();
+}
+
+/// Where a report or transaction block's query goes: the cycle's session, or the pool
+#[derive(Clone, Copy)]
+pub enum Querier<'a> {
+    Session(&'a dyn RequestProcessor, &'a str),
+    Pool(&'a SessionPool),
+}
+
+impl Querier<'_> {
+    pub async fn process_request(&self, request: &str) -> Result<String> {
+        match self {
+            Querier::Session(processor, ticket) => processor.process_request(ticket, request),
+            Querier::Pool(pool) => pool.process_request(request).await,
+        }
+    }
+
+    /// Whether blocks can send their queries at the same time
+    pub fn concurrent(&self) -> bool {
+        matches!(self, Querier::Pool(_))
+    }
+}
@@ -0,0 +1,67 @@
+// [google_sheets] isolate_after: one broken spreadsheet doesn't hold up the others (off by default)
+//
+// A spreadsheet whose share was revoked, or a block with a mistyped spreadsheet_id, fails every
+// write to it, each after its retries. Once `isolate_after` writes in a row to one spreadsheet have
+// failed, its remaining writes this cycle fail straight away with the reason, and no more time goes
+// on it; blocks writing to other spreadsheets carry on as usual. Only failures the webapp answered
+// count: Google being out of reach affects every spreadsheet alike (appends are queued for it), and
+// a write refused to protect a formula is about one cell. Every cycle starts with a clean slate.
+
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Default)]
+pub struct SheetFaults {
+    // per spreadsheet id
+    state: Mutex<HashMap<String, Faults>>,
+}
+
+#[derive(Default)]
+struct Faults {
+    in_a_row: u32,
+    // why the spreadsheet is left alone for the rest of the cycle
+    isolated: Option<String>,
+}
+
+impl SheetFaults {
+    /// Forgets every spreadsheet's failures, at the start of a cycle
+    pub fn reset(&self) {
+        self.state.lock().unwrap().clear();
+    }
+
+    /// Err with the reason when `spreadsheet_id` is isolated for the rest of the cycle
+    pub fn check(&self, spreadsheet_id: &str) -> Result<()> {
+        match self.state.lock().unwrap().get(spreadsheet_id).and_then(|f| f.isolated.as_deref()) {
+            Some(reason) => bail!("Spreadsheet {} is skipped for the rest of this cycle after failing: {}", spreadsheet_id, reason),
+            None => Ok(()),
+        }
+    }
+
+    pub fn succeeded(&self, spreadsheet_id: &str) {
+        if let Some(faults) = self.state.lock().unwrap().get_mut(spreadsheet_id) {
+            faults.in_a_row = 0;
+        }
+    }
+
+    /// Counts a failed write; the `isolate_after`th in a row isolates the spreadsheet (0: never)
+    pub fn failed(&self, spreadsheet_id: &str, reason: &anyhow::Error, isolate_after: u32) {
+        let mut state = self.state.lock().unwrap();
+        let faults = state.entry(spreadsheet_id.to_string()).or_default();
+        faults.in_a_row += 1;
+        if isolate_after > 0 && faults.in_a_row >= isolate_after && faults.isolated.is_none() {
+            log::error!("[SHEETS] Spreadsheet {} failed {} writes in a row; skipping its other writes this cycle", spreadsheet_id, faults.in_a_row);
+            faults.isolated = Some(format!("{:#}", reason));
+        }
+    }
+
+    /// The spreadsheets isolated this cycle, with why
+    pub fn isolated(&self) -> Vec<(String, String)> {
+        let state = self.state.lock().unwrap();
+        let mut isolated: Vec<(String, String)> = state.iter()
+            .filter_map(|(id, f)| f.isolated.clone().map(|reason| (id.clone(), reason)))
+            .collect();
+        isolated.sort();
+        isolated
+    }
+}
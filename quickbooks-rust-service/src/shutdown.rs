@@ -0,0 +1,70 @@
+// Graceful shutdown on Ctrl+C / Ctrl+Break, console close, logoff and system shutdown
+//
+// The first signal asks the current cycle to stop: pending Sheets posts are dropped at their next
+// await point, then the QuickBooks session and connection are closed and COM is uninitialized, so
+// the company file isn't left locked. A second signal exits immediately.
+
+use std::sync::OnceLock;
+use tokio::sync::watch;
+
+static REQUESTED: OnceLock<watch::Sender<bool>> = OnceLock::new();
+
+fn sender() -> &'static watch::Sender<bool> {
+    REQUESTED.get_or_init(|| watch::channel(false).0)
+}
+
+/// Starts listening for stop signals; call once from main inside the runtime
+pub fn install() {
+    tokio::spawn(async {
+        wait_for_signal().await;
+        log::warn!("[SHUTDOWN] Stop requested; finishing up and closing the QuickBooks session (press Ctrl+C again to force)");
+        sender().send_replace(true);
+
+        wait_for_signal().await;
+        eprintln!("[SHUTDOWN] Forced exit");
+        std::process::exit(130);
+    });
+}
+
+/// Asks the daemon to stop, as a first Ctrl+C does; for the tray icon's menu
+// only the Windows tray calls it
+#[cfg_attr(not(feature = "qb-com"), allow(dead_code))]
+pub fn request() {
+    log::warn!("[SHUTDOWN] Stop requested; finishing up and closing the QuickBooks session");
+    sender().send_replace(true);
+}
+
+pub fn is_requested() -> bool {
+    *sender().borrow()
+}
+
+/// Resolves once a stop has been requested (immediately if it already has)
+pub async fn requested() {
+    let mut rx = sender().subscribe();
+    // only errors if the sender is dropped, and it lives in a static
+    let _ = rx.wait_for(|stop| *stop).await;
+}
+
+#[cfg(windows)]
+async fn wait_for_signal() {
+    use tokio::signal::windows;
+    // Ctrl+C comes through ctrl_c; Ctrl+Break, closing the console window, logging off and
+    // shutting down Windows each have their own event
+    let listeners = (windows::ctrl_break(), windows::ctrl_close(), windows::ctrl_logoff(), windows::ctrl_shutdown());
+    let (Ok(mut ctrl_break), Ok(mut close), Ok(mut logoff), Ok(mut shutdown)) = listeners else {
+        let _ = tokio::signal::ctrl_c().await;
+        return;
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = ctrl_break.recv() => {},
+        _ = close.recv() => {},
+        _ = logoff.recv() => {},
+        _ = shutdown.recv() => {},
+    }
+}
+
+#[cfg(not(windows))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
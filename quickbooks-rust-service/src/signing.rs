@@ -0,0 +1,61 @@
+// HMAC-SHA256 request signing for the Apps Script webapp
+//
+// The signature covers "<timestamp>.<nonce>.<body>" so a captured request can't be replayed later
+// (timestamp window) or twice (nonce), and the body can't be altered without the shared secret.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub struct Signature {
+    pub timestamp: String,
+    pub nonce: String,
+    // lowercase hex
+    pub signature: String,
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> Signature {
+    let timestamp = chrono::Utc::now().timestamp().to_string();
+    let nonce = uuid::Uuid::new_v4().simple().to_string();
+    let signature = hmac_hex(secret, &timestamp, &nonce, body);
+    Signature { timestamp, nonce, signature }
+}
+
+fn hmac_hex(secret: &str, timestamp: &str, nonce: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(nonce.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // HMAC-SHA256 of "<timestamp>.<nonce>.<body>" in UTF-8, worked out independently the way
+    // Code.ts VERIFY_SIGNATURE builds its message (computeHmacSha256Signature, lowercase hex)
+    #[test]
+    fn signature_matches_the_webapp_check() {
+        let body = br#"{"apiKey":"test-key","cellAddress":"B2","floatValue":45210.37}"#;
+        assert_eq!(
+            hmac_hex("shared-secret", "1718000000", "6f1c2e3d4b5a69788796a5b4c3d2e1f0", body),
+            "9f9e845b3c55bb1caf09fa8bb81ff013e7f26722b85869d50c4b99af2fbd7288",
+        );
+        assert_eq!(
+            hmac_hex("shared-secret", "1718000000", "n", "Café".as_bytes()),
+            "6ddd2e0ef5431c9e3bda3fb6503e7c86d97f8e687fb58ba023c942ec2761679b",
+        );
+    }
+
+    #[test]
+    fn each_signature_has_its_own_nonce() {
+        let (first, second) = (sign("shared-secret", b"{}"), sign("shared-secret", b"{}"));
+        assert_ne!(first.nonce, second.nonce);
+        assert_eq!(first.nonce.len(), 32);
+        assert!(first.timestamp.parse::<i64>().is_ok());
+        assert_eq!(first.signature, hmac_hex("shared-secret", &first.timestamp, &first.nonce, b"{}"));
+        assert_ne!(first.signature, hmac_hex("other-secret", &first.timestamp, &first.nonce, b"{}"));
+    }
+}
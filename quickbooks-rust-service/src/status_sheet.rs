@@ -0,0 +1,55 @@
+// Optional [status_sheet]: the run summary written as a table into its own tab at the end of a
+// cycle, so whoever reads the spreadsheet can see what was refreshed and what failed without
+// opening the service logs
+
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::config::StatusSheetConfig;
+use crate::context::AppContext;
+use crate::summary::{RunSummary, Status};
+
+const HEADERS: [&str; 7] = ["Block", "Destination", "Value", "Time", "Status", "Detail", "Run"];
+
+/// A cell value as plain text; strings lose their JSON quotes
+pub fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+pub fn rows(summary: &RunSummary) -> Vec<Vec<Value>> {
+    let run_id = summary.run_id.clone().unwrap_or_default();
+    let mut rows = vec![HEADERS.iter().map(|h| Value::from(*h)).collect()];
+    for o in summary.outcomes() {
+        let (status, detail) = match o.status {
+            Status::Ok => ("OK", String::new()),
+            Status::Error(e) => ("ERROR", e),
+            Status::Skipped(reason) => ("SKIPPED", reason),
+        };
+        rows.push(vec![
+            Value::from(o.block),
+            Value::from(o.destination),
+            Value::from(o.value.unwrap_or_default()),
+            Value::from(o.at.format("%Y-%m-%d %H:%M:%S").to_string()),
+            Value::from(status),
+            Value::from(detail),
+            Value::from(run_id.as_str()),
+        ]);
+    }
+    rows
+}
+
+/// Replaces the status table; rows left over from a longer previous run are cleared
+pub async fn write(summary: &RunSummary, status_cfg: &StatusSheetConfig, ctx: &AppContext<'_>) -> Result<()> {
+    let gs_client = ctx.sheets(&status_cfg.spreadsheet_id);
+    gs_client.write_range(
+        &rows(summary),
+        &status_cfg.sheet_name,
+        status_cfg.cell_address.as_deref().unwrap_or("A1"),
+        true,
+        None,
+        ).await?;
+    Ok(())
+}
@@ -0,0 +1,332 @@
+// Per-run summary of every block/destination outcome, printed at the end of a cycle, and the values
+// blocks publish for the computed blocks that run after them
+
+use chrono::{DateTime, Local};
+use rust_decimal::Decimal;
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config::MaxFrequency;
+use crate::correlation;
+use crate::progress;
+use crate::resume::Checkpoint;
+
+#[derive(Debug, Clone)]
+pub enum Status {
+    Ok,
+    Error(String),
+    Skipped(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub block: String,
+    pub destination: String,
+    pub status: Status,
+    // correlation id of the block run that produced this outcome
+    pub block_id: Option<String>,
+    // what the block wrote (a balance, "12 rows", ...), when it got that far
+    pub value: Option<String>,
+    pub at: DateTime<Local>,
+}
+
+// Where a block spends its time: waiting for QuickBooks, parsing the response or writing to destinations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Query,
+    Parse,
+    Write,
+}
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Query => "query",
+            Phase::Parse => "parse",
+            Phase::Write => "write",
+        }
+    }
+}
+
+// One timed stretch of a block's work, as exported to [telemetry]
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub block: String,
+    pub phase: Phase,
+    pub end: SystemTime,
+    pub elapsed: Duration,
+}
+
+impl Span {
+    pub fn start(&self) -> SystemTime {
+        self.end - self.elapsed
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Timing {
+    pub query: Duration,
+    pub parse: Duration,
+    pub write: Duration,
+}
+
+impl Timing {
+    pub fn total(&self) -> Duration {
+        self.query + self.parse + self.write
+    }
+}
+
+// Blocks run concurrently, so outcomes are collected behind a mutex
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    pub run_id: Option<String>,
+    outcomes: Mutex<Vec<Outcome>>,
+    values: Mutex<HashMap<String, String>>,
+    // numbers blocks publish for computed blocks: account block balances, report rows, raw fields
+    variables: Mutex<HashMap<String, Decimal>>,
+    // in the order blocks first reported a time
+    timings: Mutex<Vec<(String, Timing)>>,
+    spans: Mutex<Vec<Span>>,
+    // --resume: saved as blocks complete, and the blocks an interrupted cycle already completed
+    checkpoint: Option<Checkpoint>,
+    completed_earlier: BTreeSet<String>,
+    // outside their active hours or days this cycle, or already run this period
+    inactive: BTreeSet<String>,
+    // the company preferences read when the session opened, as label / value pairs
+    settings: Vec<(&'static str, String)>,
+}
+
+impl RunSummary {
+    pub fn new() -> Self {
+        Self { run_id: correlation::run_id(), ..Self::default() }
+    }
+
+    /// Keeps `checkpoint` up to date with the blocks completed; `completed_earlier` are skipped
+    pub fn with_checkpoint(self, checkpoint: Checkpoint, completed_earlier: BTreeSet<String>) -> Self {
+        Self { checkpoint: Some(checkpoint), completed_earlier, ..self }
+    }
+
+    /// Carries the QuickBooks company preferences (host::settings) into the logged and exported summary
+    pub fn with_settings(self, settings: Vec<(&'static str, String)>) -> Self {
+        Self { settings, ..self }
+    }
+
+    pub fn settings(&self) -> &[(&'static str, String)] {
+        &self.settings
+    }
+
+    /// Blocks to leave out of this cycle for being outside their active hours; each is reported as
+    /// skipped
+    pub fn with_inactive(self, inactive: Vec<String>) -> Self {
+        for block in &inactive {
+            self.skipped(block, "-", "outside its active hours");
+        }
+        Self { inactive: inactive.into_iter().collect(), ..self }
+    }
+
+    /// Blocks to leave out of this cycle because max_frequency lets them run only once a day or
+    /// hour and they already have; each is reported as skipped
+    pub fn with_ran_this_period(mut self, ran: Vec<(String, MaxFrequency)>) -> Self {
+        for (block, frequency) in ran {
+            if self.inactive.contains(&block) {
+                continue;
+            }
+            self.skipped(&block, "-", &format!("already ran {} (max_frequency)", frequency.period()));
+            self.inactive.insert(block);
+        }
+        self
+    }
+
+    /// Whether the block sits this cycle out: the interrupted cycle this one resumes already
+    /// completed it, it is outside its active hours or it already ran as often as max_frequency allows
+    pub fn sits_out(&self, block: &str) -> bool {
+        self.completed_earlier.contains(block) || self.inactive.contains(block)
+    }
+
+    /// The cycle ran to its end, so it has nothing left to resume
+    pub fn finish_checkpoint(&self) {
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.finish();
+        }
+    }
+
+    /// The block finished this cycle; once every outcome it recorded is OK it goes into the
+    /// checkpoint, so --resume skips it. Call after the block's processing has returned, not per
+    /// destination: a block cut short after its first write has to run again.
+    pub fn complete(&self, block: &str) {
+        let Some(checkpoint) = &self.checkpoint else { return };
+        let completed = {
+            let outcomes = self.outcomes.lock().unwrap();
+            let mut statuses = outcomes.iter().filter(|o| o.block == block).map(|o| &o.status).peekable();
+            statuses.peek().is_some() && statuses.all(|status| matches!(status, Status::Ok))
+        };
+        if completed {
+            checkpoint.complete(block);
+        }
+    }
+
+    pub fn record(&self, block: &str, destination: &str, result: &anyhow::Result<()>) {
+        let status = match result {
+            Ok(()) => Status::Ok,
+            Err(e) => Status::Error(format!("{:#}", e)),
+        };
+        self.push(block, destination, status);
+    }
+
+    pub fn skipped(&self, block: &str, destination: &str, reason: &str) {
+        self.push(block, destination, Status::Skipped(reason.to_string()));
+    }
+
+    /// Remembers the value a block produced; call before recording its outcomes
+    pub fn set_value(&self, block: &str, value: impl Into<String>) {
+        self.values.lock().unwrap().insert(block.to_string(), value.into());
+    }
+
+    /// Makes `value` available to computed blocks' expressions as `name`
+    pub fn publish(&self, name: &str, value: Decimal) {
+        self.variables.lock().unwrap().insert(name.to_string(), value);
+    }
+
+    pub fn variable(&self, name: &str) -> Option<Decimal> {
+        self.variables.lock().unwrap().get(name).copied()
+    }
+
+    fn push(&self, block: &str, destination: &str, status: Status) {
+        let value = self.values.lock().unwrap().get(block).cloned();
+        let mut outcomes = self.outcomes.lock().unwrap();
+        outcomes.push(Outcome {
+            block: block.to_string(),
+            destination: destination.to_string(),
+            status,
+            block_id: correlation::block_id(),
+            value,
+            at: Local::now(),
+        });
+    }
+
+    /// Adds to the time a block spent in one phase, which ended just now; a block can report a
+    /// phase more than once
+    pub fn add_time(&self, block: &str, phase: Phase, elapsed: Duration) {
+        self.spans.lock().unwrap().push(Span { block: block.to_string(), phase, end: SystemTime::now(), elapsed });
+        let mut timings = self.timings.lock().unwrap();
+        let index = match timings.iter().position(|(b, _)| b == block) {
+            Some(index) => index,
+            None => {
+                timings.push((block.to_string(), Timing::default()));
+                timings.len() - 1
+            }
+        };
+        let timing = &mut timings[index].1;
+        match phase {
+            Phase::Query => timing.query += elapsed,
+            Phase::Parse => timing.parse += elapsed,
+            Phase::Write => timing.write += elapsed,
+        }
+    }
+
+    /// Runs `f` and counts how long it took towards the block's `phase`
+    pub fn timed<T>(&self, block: &str, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.add_time(block, phase, started.elapsed());
+        result
+    }
+
+    pub async fn timed_async<T>(&self, block: &str, phase: Phase, fut: impl Future<Output = T>) -> T {
+        let started = Instant::now();
+        let result = fut.await;
+        self.add_time(block, phase, started.elapsed());
+        result
+    }
+
+    pub fn timings(&self) -> Vec<(String, Timing)> {
+        self.timings.lock().unwrap().clone()
+    }
+
+    pub fn spans(&self) -> Vec<Span> {
+        self.spans.lock().unwrap().clone()
+    }
+
+    /// Whether anything was recorded for the block yet
+    pub fn has_block(&self, block: &str) -> bool {
+        self.outcomes.lock().unwrap().iter().any(|o| o.block == block)
+    }
+
+    pub fn outcomes(&self) -> Vec<Outcome> {
+        self.outcomes.lock().unwrap().clone()
+    }
+
+    /// (ok, failed, skipped) outcome counts
+    pub fn counts(&self) -> (usize, usize, usize) {
+        let outcomes = self.outcomes.lock().unwrap();
+        let ok = outcomes.iter().filter(|o| matches!(o.status, Status::Ok)).count();
+        let failed = outcomes.iter().filter(|o| matches!(o.status, Status::Error(_))).count();
+        (ok, failed, outcomes.len() - ok - failed)
+    }
+
+    /// (blocks with an outcome, blocks with a failed destination), for progress lines
+    pub fn block_progress(&self) -> (usize, usize) {
+        let outcomes = self.outcomes.lock().unwrap();
+        let done: BTreeSet<&str> = outcomes.iter().map(|o| o.block.as_str()).collect();
+        let failed: BTreeSet<&str> = outcomes.iter().filter(|o| matches!(o.status, Status::Error(_))).map(|o| o.block.as_str()).collect();
+        (done.len(), failed.len())
+    }
+
+    /// Logs one line per outcome plus a totals line; OK lines are per-block detail
+    pub fn log(&self) {
+        let outcomes = self.outcomes();
+        for o in &outcomes {
+            let id = o.block_id.as_deref().map(|id| format!(" [{}]", id)).unwrap_or_default();
+            match &o.status {
+                Status::Ok => log::log!(progress::detail(), "[SUMMARY] OK      {} -> {}{}", o.block, o.destination, id),
+                Status::Skipped(reason) => log::warn!("[SUMMARY] SKIPPED {} -> {}: {}{}", o.block, o.destination, reason, id),
+                Status::Error(e) => log::error!("[SUMMARY] ERROR   {} -> {}: {}{}", o.block, o.destination, e, id),
+            }
+        }
+        let (ok, failed, skipped) = self.counts();
+        log::info!(target: progress::TARGET, "[SUMMARY] {} ok, {} failed, {} skipped", ok, failed, skipped);
+        if !self.settings.is_empty() {
+            let settings: Vec<String> = self.settings.iter().map(|(label, value)| format!("{}: {}", label, value)).collect();
+            log::info!("[SUMMARY] QuickBooks {}", settings.join(", "));
+        }
+    }
+
+    /// Logs where each block's time went, warns about blocks slower than `slow_block`, and totals
+    /// QuickBooks time against destination time so a slow cycle can be pinned on one or the other
+    pub fn log_timings(&self, slow_block: Duration) {
+        let timings = self.timings();
+        for (block, t) in &timings {
+            log::log!(
+                progress::detail(),
+                "[TIMING] {}: query {:.2}s, parse {:.2}s, write {:.2}s",
+                block, t.query.as_secs_f64(), t.parse.as_secs_f64(), t.write.as_secs_f64()
+            );
+            if t.total() > slow_block {
+                log::warn!(
+                    "[TIMING] {} took {:.1}s (over {}s): {}",
+                    block, t.total().as_secs_f64(), slow_block.as_secs(), slowest_phase(t)
+                );
+            }
+        }
+        if !timings.is_empty() {
+            let quickbooks: Duration = timings.iter().map(|(_, t)| t.query + t.parse).sum();
+            let destinations: Duration = timings.iter().map(|(_, t)| t.write).sum();
+            log::info!(
+                "[TIMING] QuickBooks {:.1}s (queries and parsing), destinations {:.1}s (summed over blocks, some of which write in parallel)",
+                quickbooks.as_secs_f64(), destinations.as_secs_f64()
+            );
+        }
+    }
+}
+
+fn slowest_phase(t: &Timing) -> &'static str {
+    if t.query >= t.parse && t.query >= t.write {
+        "mostly waiting for QuickBooks"
+    } else if t.parse >= t.write {
+        "mostly parsing the response"
+    } else {
+        "mostly writing to destinations"
+    }
+}
@@ -0,0 +1,156 @@
+// [telemetry]: every cycle as an OpenTelemetry trace, sent to an OTLP/HTTP collector (Jaeger, Tempo,
+// the OpenTelemetry Collector, ...)
+//
+// The trace has one root span for the cycle, a span per block covering its work, and under each
+// block a span for every query, parse and write the run summary timed. Trace and span ids are
+// random; the root span carries the run's correlation id as qb_sync.run_id, so a trace can be found
+// from a log line or a webapp request and the other way round. Spans are sent once the cycle is over, as OTLP JSON; nothing is sent while it runs.
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::capture;
+use crate::config::TelemetryConfig;
+use crate::random::Rng;
+use crate::summary::{RunSummary, Span, Status};
+
+const DEFAULT_SERVICE_NAME: &str = "qb_sync";
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+
+// OTLP span kind and status codes
+const KIND_INTERNAL: u8 = 1;
+const STATUS_OK: u8 = 1;
+const STATUS_ERROR: u8 = 2;
+
+/// Sends the cycle that began at `started` as a trace; failures are only logged
+pub async fn export(http: &reqwest::Client, summary: &RunSummary, telemetry: &TelemetryConfig, started: SystemTime) {
+    let body = trace(summary, telemetry, started, SystemTime::now());
+    if let Err(e) = post(http, telemetry, &body).await {
+        log::warn!("[TELEMETRY] Failed to export the cycle's trace: {:#}", e);
+    }
+}
+
+fn trace(summary: &RunSummary, telemetry: &TelemetryConfig, started: SystemTime, ended: SystemTime) -> Value {
+    let mut rng = Rng::from_clock();
+    let trace_id = rng.hex(16);
+    let root_id = rng.hex(8);
+    let outcomes = summary.outcomes();
+    let (ok, failed, skipped) = summary.counts();
+
+    let mut root_attributes = vec![
+        attribute("qb_sync.blocks.ok", json!(ok)),
+        attribute("qb_sync.blocks.failed", json!(failed)),
+        attribute("qb_sync.blocks.skipped", json!(skipped)),
+    ];
+    if let Some(run_id) = &summary.run_id {
+        root_attributes.push(attribute("qb_sync.run_id", json!(run_id)));
+    }
+    // the company preferences, e.g. qb_sync.quickbooks.report_basis = "Accrual"
+    for (label, value) in summary.settings() {
+        root_attributes.push(attribute(&format!("qb_sync.quickbooks.{}", label.to_lowercase().replace(' ', "_")), json!(value)));
+    }
+    let mut spans = vec![span(&trace_id, &root_id, None, "sync cycle", started, ended,
+        root_attributes,
+        (failed > 0).then(|| format!("{} block destination(s) failed", failed)),
+    )];
+
+    // blocks in the order they first reported a time, each spanning its first start to last end
+    let mut by_block: Vec<(String, Vec<Span>)> = Vec::new();
+    for timed in summary.spans() {
+        match by_block.iter_mut().find(|(block, _)| *block == timed.block) {
+            Some((_, list)) => list.push(timed),
+            None => by_block.push((timed.block.clone(), vec![timed])),
+        }
+    }
+    for (block, timed) in by_block {
+        let block_id = rng.hex(8);
+        let first = timed.iter().map(Span::start).min().unwrap_or(started);
+        let last = timed.iter().map(|t| t.end).max().unwrap_or(ended);
+        let errors: Vec<&str> = outcomes.iter()
+            .filter(|o| o.block == block)
+            .filter_map(|o| match &o.status {
+                Status::Error(e) => Some(e.as_str()),
+                _ => None,
+            })
+            .collect();
+        let mut attributes = vec![attribute("qb_sync.block", json!(block))];
+        if let Some(id) = outcomes.iter().find(|o| o.block == block).and_then(|o| o.block_id.as_deref()) {
+            attributes.push(attribute("qb_sync.block_id", json!(id)));
+        }
+        let error = (!errors.is_empty()).then(|| errors.join("; "));
+        spans.push(span(&trace_id, &block_id, Some(&root_id), &block, first, last, attributes, error));
+        for t in &timed {
+            spans.push(span(&trace_id, &rng.hex(8), Some(&block_id), t.phase.name(), t.start(), t.end,
+                vec![attribute("qb_sync.block", json!(block))], None));
+        }
+    }
+
+    let service_name = telemetry.service_name.as_deref().unwrap_or(DEFAULT_SERVICE_NAME);
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", json!(service_name)),
+                    attribute("service.version", json!(env!("CARGO_PKG_VERSION"))),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "qb_sync" },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn span(trace_id: &str, span_id: &str, parent: Option<&str>, name: &str, start: SystemTime, end: SystemTime, attributes: Vec<Value>, error: Option<String>) -> Value {
+    let status = match error {
+        Some(message) => json!({ "code": STATUS_ERROR, "message": message }),
+        None => json!({ "code": STATUS_OK }),
+    };
+    let mut span = json!({
+        "traceId": trace_id,
+        "spanId": span_id,
+        "name": name,
+        "kind": KIND_INTERNAL,
+        "startTimeUnixNano": unix_nanos(start),
+        "endTimeUnixNano": unix_nanos(end),
+        "attributes": attributes,
+        "status": status,
+    });
+    if let Some(parent) = parent {
+        span["parentSpanId"] = json!(parent);
+    }
+    span
+}
+
+// OTLP JSON wraps every attribute value in its type
+fn attribute(key: &str, value: Value) -> Value {
+    let typed = match value {
+        Value::Number(n) if n.is_u64() || n.is_i64() => json!({ "intValue": n.to_string() }),
+        Value::Number(n) => json!({ "doubleValue": n }),
+        Value::Bool(b) => json!({ "boolValue": b }),
+        other => json!({ "stringValue": other.as_str().map(str::to_string).unwrap_or_else(|| other.to_string()) }),
+    };
+    json!({ "key": key, "value": typed })
+}
+
+// 64-bit nanosecond counts go as strings in OTLP JSON
+fn unix_nanos(at: SystemTime) -> String {
+    at.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
+
+async fn post(http: &reqwest::Client, telemetry: &TelemetryConfig, body: &Value) -> Result<()> {
+    let url = format!("{}/v1/traces", telemetry.endpoint.trim_end_matches('/'));
+    let timeout = Duration::from_secs(telemetry.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS).max(1));
+    let mut request = http.post(&url).timeout(timeout);
+    for (name, value) in &telemetry.headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    let (status, text) = capture::send("telemetry", &url, body, request).await.with_context(|| format!("Failed to POST to {}", url))?;
+    if !status.is_success() {
+        bail!("{} answered {}: {}", url, status, text);
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+// Minimal value templates such as "{account_name}: {balance:.2} {currency}"
+// Placeholders name a variable, optionally followed by ":.N" to fix the decimals of a number.
+// "{{" and "}}" produce literal braces.
+
+use anyhow::Result;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+use crate::accounts::AccountData;
+use crate::amounts;
+
+#[derive(Debug, Clone)]
+pub enum TemplateValue {
+    Text(String),
+    Number(Decimal),
+}
+
+pub type TemplateVars = HashMap<&'static str, TemplateValue>;
+
+// Variables available to a sync block's value_template
+pub fn account_vars(account: &AccountData) -> TemplateVars {
+    TemplateVars::from([
+        ("account_name", TemplateValue::Text(account.account_full_name.clone())),
+        ("account_number", TemplateValue::Text(account.number.clone())),
+        ("account_type", TemplateValue::Text(account.account_type.clone())),
+        ("balance", TemplateValue::Number(account.balance)),
+        ("currency", TemplateValue::Text(account.currency.clone())),
+        ("account_description", TemplateValue::Text(account.description.clone())),
+        ("bank_number", TemplateValue::Text(account.bank_number.clone())),
+        ("category", TemplateValue::Text(account.category.clone())),
+    ])
+}
+
+pub fn render(template: &str, vars: &TemplateVars) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => anyhow::bail!("Unclosed '{{' in template \"{}\"", template),
+                    }
+                }
+                out.push_str(&render_placeholder(&placeholder, vars, template)?);
+            }
+            '}' => anyhow::bail!("Unmatched '}}' in template \"{}\"", template),
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+fn render_placeholder(placeholder: &str, vars: &TemplateVars, template: &str) -> Result<String> {
+    let (name, spec) = match placeholder.split_once(':') {
+        Some((name, spec)) => (name.trim(), Some(spec.trim())),
+        None => (placeholder.trim(), None),
+    };
+    let value = vars.get(name).ok_or_else(|| {
+        let mut known: Vec<_> = vars.keys().copied().collect();
+        known.sort();
+        anyhow::anyhow!("Unknown placeholder '{{{}}}' in template \"{}\" (known: {})", name, template, known.join(", "))
+    })?;
+    let precision = match spec {
+        None => None,
+        Some(spec) => match spec.strip_prefix('.').and_then(|p| p.parse::<usize>().ok()) {
+            Some(p) => Some(p),
+            None => anyhow::bail!("Unsupported format '{}' for '{{{}}}'; only ':.N' is supported", spec, name),
+        },
+    };
+    Ok(match (value, precision) {
+        // Decimal's own formatting truncates to the precision
+        (TemplateValue::Number(n), Some(p)) => format!("{:.*}", p, amounts::round(*n, p as u32)),
+        (TemplateValue::Number(n), None) => n.to_string(),
+        (TemplateValue::Text(t), _) => t.clone(),
+    })
+}
+
+/// Renders every string of a JSON value as a template. A string that is just one numeric
+/// placeholder ("{balance}") becomes a JSON number instead of text.
+pub fn render_json(value: &serde_json::Value, vars: &TemplateVars) -> Result<serde_json::Value> {
+    use serde_json::Value;
+    Ok(match value {
+        Value::String(text) => {
+            let whole = text.strip_prefix('{').and_then(|t| t.strip_suffix('}')).map(str::trim);
+            match whole.and_then(|name| vars.get(name)) {
+                Some(TemplateValue::Number(n)) => amounts::json(*n),
+                _ => Value::from(render(text, vars)?),
+            }
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|v| render_json(v, vars)).collect::<Result<_>>()?),
+        Value::Object(fields) => Value::Object(
+            fields.iter()
+                .map(|(k, v)| Ok((k.clone(), render_json(v, vars)?)))
+                .collect::<Result<_>>()?,
+        ),
+        other => other.clone(),
+    })
+}
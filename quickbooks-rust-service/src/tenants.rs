@@ -0,0 +1,56 @@
+// --config-dir: one qb_sync serving several companies (tenants), e.g. a bookkeeper's clients
+//
+// Every *.toml directly in the directory is a complete, independent config with its own QuickBooks
+// company file and destinations, named after the file (acme.toml is tenant "acme"). Files with a
+// second dot in the name (acme.staging.toml) are --env overlays of their tenant, not tenants.
+// Tenants run one after another, in file name order, each in its own QuickBooks session.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::close_snapshot;
+use crate::config::{Config, HistoryConfig};
+
+pub struct Tenant {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// The tenant configs in `dir`, sorted by name
+pub fn discover(dir: &Path) -> Result<Vec<Tenant>> {
+    let entries = std::fs::read_dir(dir).with_context(|| format!("Failed to read config directory {}", dir.display()))?;
+    let mut tenants = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().and_then(|e| e.to_str()) != Some("toml") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+        // acme.staging.toml overlays acme.toml
+        if name.contains('.') {
+            continue;
+        }
+        tenants.push(Tenant { name: name.to_string(), path: path.clone() });
+    }
+    if tenants.is_empty() {
+        anyhow::bail!("No tenant configs (*.toml) found in {}", dir.display());
+    }
+    tenants.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(tenants)
+}
+
+/// Loads a tenant's config. Unless it names its own history file, each tenant keeps its history
+/// under state/<tenant>/ so their balances and run counts don't mix.
+pub fn load(tenant: &Tenant, env: Option<&str>, overrides: &[(String, String)]) -> Result<Config> {
+    let mut config = Config::load(&tenant.path, env, overrides)?;
+    let history = config.history.get_or_insert(HistoryConfig { path: None, keep_days: None });
+    if history.path.is_none() {
+        history.path = Some(format!("state/{}/history.json", tenant.name));
+    }
+    if config.schedule.is_some() {
+        log::warn!("[TENANT] {}: [schedule] is ignored with --config-dir; every tenant runs once per invocation", tenant.name);
+        config.schedule = None;
+    }
+    close_snapshot::validate(&config)?;
+    Ok(config)
+}
@@ -0,0 +1,141 @@
+// Transaction list queries (CheckQueryRq, DepositQueryRq, ...) flattened into rows for range writes
+// Cash out (checks, card charges), cash in (deposits, received payments) and customer credits
+// (credit memos, card refunds) share one row shape
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde_json::Value;
+
+use crate::amounts::{self, NumberFormat};
+use crate::config::{TransactionBlockConfig, TxnType};
+use crate::dates;
+use crate::qbxml;
+
+#[derive(Debug, Clone)]
+pub struct TxnRow {
+    pub date: String,
+    // payee for cash out, customer / received-from for cash in and credits
+    pub payee: String,
+    pub amount: Decimal,
+    pub account: String,
+}
+
+impl TxnRow {
+    pub fn to_values(&self) -> Vec<Value> {
+        vec![
+            Value::from(self.date.as_str()),
+            Value::from(self.payee.as_str()),
+            amounts::json(self.amount),
+            Value::from(self.account.as_str()),
+        ]
+    }
+}
+
+impl TxnType {
+    /// Credit memos and refunds, which take money back from sales
+    pub fn is_credit(&self) -> bool {
+        matches!(self, TxnType::CreditMemo | TxnType::ARRefundCreditCard)
+    }
+
+    pub fn headers(&self) -> [&'static str; 4] {
+        match self {
+            TxnType::Check | TxnType::CreditCardCharge => ["Date", "Payee", "Amount", "Account"],
+            TxnType::Deposit | TxnType::ReceivePayment => ["Date", "Received From", "Amount", "Deposit To"],
+            TxnType::CreditMemo => ["Date", "Customer", "Amount", "A/R Account"],
+            TxnType::ARRefundCreditCard => ["Date", "Customer", "Amount", "Refund From"],
+        }
+    }
+}
+
+/// Builds the query for the block's transaction type, filtered on transaction date
+pub fn query_request(block: &TransactionBlockConfig, today: NaiveDate) -> Result<String> {
+    let element = block.txn_type.element();
+    let mut body = format!("      <{}QueryRq>\n", element);
+    if block.date_from.is_some() || block.date_to.is_some() {
+        body.push_str("        <TxnDateRangeFilter>\n");
+        if let Some(from) = &block.date_from {
+            body.push_str(&format!("          <FromTxnDate>{}</FromTxnDate>\n", dates::resolve_qbxml_date(from, today)?));
+        }
+        if let Some(to) = &block.date_to {
+            body.push_str(&format!("          <ToTxnDate>{}</ToTxnDate>\n", dates::resolve_qbxml_date(to, today)?));
+        }
+        body.push_str("        </TxnDateRangeFilter>\n");
+    }
+    if block.txn_type == TxnType::Deposit {
+        // the depositor's name only appears on the deposit lines
+        body.push_str("        <IncludeLineItems>true</IncludeLineItems>\n");
+    }
+    body.push_str(&format!("      </{}QueryRq>", element));
+    Ok(qbxml::envelope(&body))
+}
+
+/// Parses the `<Type>Ret` elements of a query response in the order QuickBooks returned them
+pub fn parse_transactions(txn_type: TxnType, response_xml: &str, format: NumberFormat) -> Result<Vec<TxnRow>> {
+    let element = txn_type.element();
+    let doc = qbxml::parse_document(response_xml)?;
+    let rs = qbxml::find_response(&doc, &format!("{}QueryRs", element))?;
+    let ret_name = format!("{}Ret", element);
+
+    let mut rows = Vec::new();
+    for ret in rs.children().filter(|n| n.has_tag_name(ret_name.as_str())) {
+        let (payee, amount, account) = match txn_type {
+            TxnType::Check | TxnType::CreditCardCharge => (
+                qbxml::ref_full_name(ret, "PayeeEntityRef"),
+                qbxml::child_text(ret, "Amount"),
+                qbxml::ref_full_name(ret, "AccountRef"),
+            ),
+            TxnType::Deposit => (
+                // first named line; deposits of several customers' payments only show the first
+                ret.children()
+                    .filter(|n| n.has_tag_name("DepositLineRet"))
+                    .find_map(|line| qbxml::ref_full_name(line, "EntityRef")),
+                qbxml::child_text(ret, "DepositTotal"),
+                qbxml::ref_full_name(ret, "DepositToAccountRef"),
+            ),
+            TxnType::ReceivePayment => (
+                qbxml::ref_full_name(ret, "CustomerRef"),
+                qbxml::child_text(ret, "TotalAmount"),
+                // payments still in Undeposited Funds have no deposit-to account
+                qbxml::ref_full_name(ret, "DepositToAccountRef"),
+            ),
+            TxnType::CreditMemo => (
+                qbxml::ref_full_name(ret, "CustomerRef"),
+                qbxml::child_text(ret, "TotalAmount"),
+                qbxml::ref_full_name(ret, "ARAccountRef"),
+            ),
+            TxnType::ARRefundCreditCard => (
+                qbxml::ref_full_name(ret, "CustomerRef"),
+                qbxml::child_text(ret, "TotalAmount"),
+                qbxml::ref_full_name(ret, "RefundFromAccountRef"),
+            ),
+        };
+        let amount = amount.and_then(|a| format.parse(&a)).unwrap_or_default();
+        rows.push(TxnRow {
+            date: qbxml::child_text(ret, "TxnDate").unwrap_or_default(),
+            payee: payee.unwrap_or_default(),
+            // QuickBooks gives credits as positive totals; negative, a SUM with the sales nets them
+            amount: if txn_type.is_credit() { -amount } else { amount },
+            account: account.unwrap_or_default(),
+        });
+    }
+    Ok(rows)
+}
+
+/// Drops the transactions whose account `permitted` rejects; returns how many. Payments with no
+/// deposit-to account are kept.
+pub fn retain_accounts(txns: &mut Vec<TxnRow>, permitted: impl Fn(&str) -> bool) -> usize {
+    let before = txns.len();
+    txns.retain(|txn| txn.account.is_empty() || permitted(&txn.account));
+    before - txns.len()
+}
+
+/// Rows ready for a range write, optionally led by the column titles
+pub fn to_rows(txn_type: TxnType, txns: &[TxnRow], include_header: bool) -> Vec<Vec<Value>> {
+    let mut rows = Vec::with_capacity(txns.len() + 1);
+    if include_header {
+        rows.push(txn_type.headers().iter().map(|h| Value::from(*h)).collect());
+    }
+    rows.extend(txns.iter().map(TxnRow::to_values));
+    rows
+}
@@ -0,0 +1,244 @@
+// [tray]: a notification-area icon for the daemon on a bookkeeper's workstation
+//
+// The icon's tooltip shows how the last cycle went, and a toast (a balloon on older Windows) pops
+// up when syncing starts failing, and optionally when it works again, so a broken sync gets noticed
+// without a monitoring stack. One toast per change rather than per cycle; QuickBooks being busy
+// isn't a failure, but gets a warning toast of its own when it starts. Right-clicking the icon offers "Stop qb_sync", which stops the daemon the way
+// Ctrl+C does. Needs the Windows (qb-com) build and an interactive session; a service running as
+// SYSTEM has no desktop to show it on.
+
+use chrono::Local;
+
+use crate::config::TrayConfig;
+
+/// How a daemon cycle went
+pub enum Outcome {
+    // ok, failed and skipped blocks
+    Ran((usize, usize, usize)),
+    // why the cycle was postponed
+    Busy(String),
+    Failed(String),
+}
+
+// what a toast's icon shows
+#[derive(Clone, Copy)]
+enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+pub struct Tray {
+    cfg: TrayConfig,
+    failing: bool,
+    busy: bool,
+}
+
+impl Tray {
+    /// Adds the icon; None, with a warning, when it can't be shown
+    pub fn start(cfg: &TrayConfig) -> Option<Tray> {
+        match icon::add("qb_sync: waiting for the first cycle") {
+            Ok(()) => Some(Tray { cfg: cfg.clone(), failing: false, busy: false }),
+            Err(e) => {
+                log::warn!("[TRAY] No tray icon: {:#}", e);
+                None
+            }
+        }
+    }
+
+    pub fn after_cycle(&mut self, outcome: &Outcome) {
+        let at = Local::now().format("%H:%M");
+        let (tip, failure) = match outcome {
+            Outcome::Ran((ok, 0, skipped)) => (format!("qb_sync: {} ok, {} skipped at {}", ok, skipped, at), None),
+            Outcome::Ran((ok, failed, _)) => (
+                format!("qb_sync: {} failed, {} ok at {}", failed, ok, at),
+                Some(format!("{} block(s) failed at {}; see the log for why", failed, at)),
+            ),
+            Outcome::Busy(_) => (format!("qb_sync: QuickBooks busy at {}, retrying", at), None),
+            Outcome::Failed(error) => (format!("qb_sync: cycle failed at {}", at), Some(error.clone())),
+        };
+        icon::set_tip(&tip);
+        if let Outcome::Busy(reason) = outcome {
+            if !self.busy && self.cfg.notify_busy.unwrap_or(true) {
+                icon::toast("QuickBooks is busy", reason, Level::Warning);
+            }
+            self.busy = true;
+            return;
+        }
+        self.busy = false;
+        match &failure {
+            Some(text) if !self.failing && self.cfg.notify_failures.unwrap_or(true) => icon::toast("QuickBooks sync failed", text, Level::Error),
+            None if self.failing && self.cfg.notify_recovery.unwrap_or(false) => icon::toast("QuickBooks sync working again", &tip, Level::Info),
+            _ => {}
+        }
+        self.failing = failure.is_some();
+    }
+}
+
+impl Drop for Tray {
+    fn drop(&mut self) {
+        icon::remove();
+    }
+}
+
+#[cfg(feature = "qb-com")]
+mod icon {
+    use anyhow::{anyhow, Result};
+    use std::ptr;
+    use std::sync::atomic::{AtomicIsize, Ordering};
+    use super::Level;
+    use winapi::shared::minwindef::{LPARAM, LRESULT, UINT, WPARAM};
+    use winapi::shared::windef::{HWND, POINT};
+    use winapi::um::errhandlingapi::GetLastError;
+    use winapi::um::libloaderapi::GetModuleHandleW;
+    use winapi::um::shellapi::{
+        Shell_NotifyIconW, NIF_ICON, NIF_INFO, NIF_MESSAGE, NIF_TIP, NIIF_ERROR, NIIF_INFO, NIIF_WARNING, NIM_ADD, NIM_DELETE, NIM_MODIFY, NOTIFYICONDATAW,
+    };
+    use winapi::um::winuser::{
+        AppendMenuW, CreatePopupMenu, CreateWindowExW, DefWindowProcW, DestroyMenu, DispatchMessageW, GetCursorPos, GetMessageW, LoadIconW,
+        RegisterClassW, SetForegroundWindow, TrackPopupMenu, TranslateMessage, HWND_MESSAGE, IDI_APPLICATION, MF_STRING, MSG, TPM_NONOTIFY,
+        TPM_RETURNCMD, TPM_RIGHTBUTTON, WM_APP, WM_CONTEXTMENU, WM_RBUTTONUP, WNDCLASSW,
+    };
+
+    const CALLBACK_MESSAGE: UINT = WM_APP + 1;
+    const ICON_ID: UINT = 1;
+    const MENU_STOP: i32 = 1;
+
+    // the hidden window the icon reports to; 0 until it exists
+    static WINDOW: AtomicIsize = AtomicIsize::new(0);
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    // as much of `text` as fits, always 0-terminated
+    fn copy_into(field: &mut [u16], text: &str) {
+        let units: Vec<u16> = text.encode_utf16().take(field.len() - 1).collect();
+        field[..units.len()].copy_from_slice(&units);
+        field[units.len()] = 0;
+    }
+
+    fn data(hwnd: HWND) -> NOTIFYICONDATAW {
+        // SAFETY: a plain C struct for which all zeroes is valid
+        let mut data: NOTIFYICONDATAW = unsafe { std::mem::zeroed() };
+        data.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+        data.hWnd = hwnd;
+        data.uID = ICON_ID;
+        data
+    }
+
+    // Shell_NotifyIconW only needs the window and id, so updates can come from any thread
+    fn modify(fill: impl FnOnce(&mut NOTIFYICONDATAW)) {
+        let hwnd = WINDOW.load(Ordering::Acquire) as HWND;
+        if hwnd.is_null() {
+            return;
+        }
+        let mut data = data(hwnd);
+        fill(&mut data);
+        unsafe { Shell_NotifyIconW(NIM_MODIFY, &mut data) };
+    }
+
+    pub fn add(tip: &str) -> Result<()> {
+        let (ready, created) = std::sync::mpsc::channel();
+        let tip = tip.to_string();
+        // the window, and so the menu, lives on a thread of its own with a message loop
+        std::thread::spawn(move || unsafe { run(&tip, ready) });
+        created.recv().map_err(|_| anyhow!("the tray thread ended"))?
+    }
+
+    unsafe fn run(tip: &str, ready: std::sync::mpsc::Sender<Result<()>>) {
+        let class = wide("qb_sync_tray");
+        let instance = GetModuleHandleW(ptr::null());
+        let mut window_class: WNDCLASSW = std::mem::zeroed();
+        window_class.lpfnWndProc = Some(window_proc);
+        window_class.hInstance = instance;
+        window_class.lpszClassName = class.as_ptr();
+        RegisterClassW(&window_class);
+        let hwnd = CreateWindowExW(0, class.as_ptr(), class.as_ptr(), 0, 0, 0, 0, 0, HWND_MESSAGE, ptr::null_mut(), instance, ptr::null_mut());
+        if hwnd.is_null() {
+            let _ = ready.send(Err(anyhow!("CreateWindowExW failed: error {}", GetLastError())));
+            return;
+        }
+        let mut icon = data(hwnd);
+        icon.uFlags = NIF_ICON | NIF_MESSAGE | NIF_TIP;
+        icon.uCallbackMessage = CALLBACK_MESSAGE;
+        icon.hIcon = LoadIconW(ptr::null_mut(), IDI_APPLICATION);
+        copy_into(&mut icon.szTip, tip);
+        if Shell_NotifyIconW(NIM_ADD, &mut icon) == 0 {
+            let _ = ready.send(Err(anyhow!("Shell_NotifyIconW failed; is there a desktop session?")));
+            return;
+        }
+        WINDOW.store(hwnd as isize, Ordering::Release);
+        let _ = ready.send(Ok(()));
+
+        let mut msg: MSG = std::mem::zeroed();
+        while GetMessageW(&mut msg, ptr::null_mut(), 0, 0) > 0 {
+            TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+    }
+
+    unsafe extern "system" fn window_proc(hwnd: HWND, msg: UINT, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        let event = lparam as UINT;
+        if msg == CALLBACK_MESSAGE && (event == WM_RBUTTONUP || event == WM_CONTEXTMENU) {
+            let menu = CreatePopupMenu();
+            let label = wide("Stop qb_sync");
+            AppendMenuW(menu, MF_STRING, MENU_STOP as usize, label.as_ptr());
+            let mut point: POINT = std::mem::zeroed();
+            GetCursorPos(&mut point);
+            // without this the menu stays open when clicking elsewhere
+            SetForegroundWindow(hwnd);
+            let command = TrackPopupMenu(menu, TPM_RETURNCMD | TPM_NONOTIFY | TPM_RIGHTBUTTON, point.x, point.y, 0, hwnd, ptr::null());
+            DestroyMenu(menu);
+            if command == MENU_STOP {
+                crate::shutdown::request();
+            }
+            return 0;
+        }
+        DefWindowProcW(hwnd, msg, wparam, lparam)
+    }
+
+    pub fn set_tip(tip: &str) {
+        modify(|data| {
+            data.uFlags = NIF_TIP;
+            copy_into(&mut data.szTip, tip);
+        });
+    }
+
+    pub fn toast(title: &str, text: &str, level: Level) {
+        modify(|data| {
+            data.uFlags = NIF_INFO;
+            data.dwInfoFlags = match level {
+                Level::Info => NIIF_INFO,
+                Level::Warning => NIIF_WARNING,
+                Level::Error => NIIF_ERROR,
+            };
+            copy_into(&mut data.szInfoTitle, title);
+            copy_into(&mut data.szInfo, text);
+        });
+    }
+
+    pub fn remove() {
+        let hwnd = WINDOW.swap(0, Ordering::AcqRel) as HWND;
+        if !hwnd.is_null() {
+            let mut data = data(hwnd);
+            unsafe { Shell_NotifyIconW(NIM_DELETE, &mut data) };
+        }
+    }
+}
+
+// the notification area is Windows only
+#[cfg(not(feature = "qb-com"))]
+mod icon {
+    use anyhow::{bail, Result};
+
+    pub fn add(_tip: &str) -> Result<()> {
+        bail!("[tray] needs the Windows build")
+    }
+
+    pub fn set_tip(_tip: &str) {}
+
+    pub fn toast(_title: &str, _text: &str, _level: super::Level) {}
+
+    pub fn remove() {}
+}
@@ -0,0 +1,95 @@
+// [watchdog]: in daemon mode, a cell on the dashboard that reads "STALE" once any watched block has
+// gone longer than stale_after_mins without a clean run, so whoever looks at the numbers sees that
+// syncing broke rather than trusting old figures
+//
+// Every cycle records which blocks finished with all their destinations written; after each cycle
+// attempt (including one that couldn't open QuickBooks) the daemon compares those times with now.
+// The cell is only written when its state changes. A block that has never run cleanly counts from
+// when the daemon started.
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+
+use crate::config::WatchdogConfig;
+use crate::context::AppContext;
+use crate::summary::{RunSummary, Status};
+
+const DEFAULT_STALE_TEXT: &str = "STALE";
+const DEFAULT_FRESH_TEXT: &str = "OK";
+
+/// Remembers, in the history file, the blocks of this cycle that had no failed or skipped destination
+pub fn record(summary: &RunSummary, ctx: &AppContext<'_>) -> Result<()> {
+    let outcomes = summary.outcomes();
+    let mut clean: BTreeMap<&str, bool> = BTreeMap::new();
+    for o in &outcomes {
+        *clean.entry(&o.block).or_insert(true) &= matches!(o.status, Status::Ok);
+    }
+    if !clean.values().any(|ok| *ok) {
+        return Ok(());
+    }
+    let now = Local::now();
+    ctx.history.update(|history| {
+        for (block, _) in clean.into_iter().filter(|(_, ok)| *ok) {
+            history.mark_ok(block, now);
+        }
+    })
+}
+
+/// The watched blocks whose last clean run (or `since`, for one that never had any) is older than
+/// the threshold, with that time
+pub fn stale_blocks(watchdog: &WatchdogConfig, ctx: &AppContext<'_>, since: DateTime<Local>, now: DateTime<Local>) -> Vec<(String, DateTime<Local>)> {
+    let blocks = if watchdog.blocks.is_empty() { ctx.config.block_labels() } else { watchdog.blocks.clone() };
+    let threshold = chrono::Duration::minutes(watchdog.stale_after_mins as i64);
+    ctx.history.read(|history| {
+        blocks.into_iter()
+            .map(|block| {
+                let last = history.last_ok(&block).unwrap_or(since);
+                (block, last)
+            })
+            .filter(|(_, last)| now - *last > threshold)
+            .collect()
+    })
+}
+
+/// The daemon's view of the indicator cell
+pub struct Watchdog {
+    started: DateTime<Local>,
+    // what the cell was last set to; None until a write succeeds
+    shown_stale: Option<bool>,
+}
+
+impl Watchdog {
+    /// Watching from now; blocks without a clean run yet count from here
+    pub fn start() -> Self {
+        Self { started: Local::now(), shown_stale: None }
+    }
+
+    /// Sets the indicator cell when the data has gone stale or become fresh again
+    pub async fn check(&mut self, watchdog: &WatchdogConfig, ctx: &AppContext<'_>) {
+        let stale = stale_blocks(watchdog, ctx, self.started, Local::now());
+        let is_stale = !stale.is_empty();
+        if self.shown_stale == Some(is_stale) {
+            return;
+        }
+        if is_stale {
+            let blocks: Vec<String> = stale.iter()
+                .map(|(block, last)| format!("{} (since {})", block, last.format("%Y-%m-%d %H:%M")))
+                .collect();
+            log::warn!("[WATCHDOG] No clean run for over {} minutes: {}", watchdog.stale_after_mins, blocks.join(", "));
+        } else if self.shown_stale.is_some() {
+            log::info!("[WATCHDOG] Every watched block is up to date again");
+        }
+        let (text, color) = if is_stale {
+            (watchdog.stale_text.as_deref().unwrap_or(DEFAULT_STALE_TEXT), watchdog.stale_color.as_deref())
+        } else {
+            (watchdog.fresh_text.as_deref().unwrap_or(DEFAULT_FRESH_TEXT), watchdog.fresh_color.as_deref())
+        };
+        let gs_client = ctx.sheets(&watchdog.spreadsheet_id);
+        match gs_client.send_flag(text, color, &watchdog.sheet_name, &watchdog.cell_address).await {
+            Ok(()) => self.shown_stale = Some(is_stale),
+            // tried again after the next cycle
+            Err(e) => log::warn!("[WATCHDOG] Failed to write the indicator cell: {:#}", e),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+// Shared by the fixture tests: the recorded qbXML responses and a stand-in for the Apps Script
+// webapp (and any webhook receiver) that answers like the deployed Code.ts and keeps every request
+
+use std::path::{Path, PathBuf};
+
+use quickbooks_sheets_sync::config::Config;
+use serde_json::Value;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// where the mock webapp is "deployed", like an Apps Script .../exec URL
+pub const WEBAPP_PATH: &str = "/exec";
+
+/// A recorded response from fixtures/
+pub fn fixture(name: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(name);
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read fixture {}: {}", path.display(), e))
+}
+
+pub struct MockWebapp {
+    pub server: MockServer,
+    dir: PathBuf,
+}
+
+impl MockWebapp {
+    /// Accepts every POST with {"success": true}, as the webapp answers a range write
+    pub async fn start() -> Self {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "success": true })))
+            .mount(&server)
+            .await;
+        let dir = std::env::temp_dir().join(format!("qb_sync_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("temp dir");
+        Self { server, dir }
+    }
+
+    /// A file in the test's own temp directory, removed with it
+    pub fn temp_path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.server.uri(), path)
+    }
+
+    /// A config whose [google_sheets] is this webapp, with `blocks` (TOML tables) added, loaded the
+    /// way the service loads config.toml
+    pub fn config(&self, blocks: &str) -> Config {
+        Config::load(self.config_file(blocks), None, &[]).expect("test config should load")
+    }
+
+    /// The config file `config` loads, for tests expecting the load to fail
+    pub fn config_file(&self, blocks: &str) -> PathBuf {
+        let mut text = String::from("version = 2\n");
+        // both are required; an empty list when the test has none
+        for required in ["sync_blocks", "timestamp_blocks"] {
+            if !blocks.contains(&format!("[[{}]]", required)) {
+                text.push_str(&format!("{} = []\n", required));
+            }
+        }
+        // literal strings, so Windows paths need no escaping
+        text.push_str(&format!(
+            "\n[quickbooks]\ncompany_file = \"AUTO\"\n\n[google_sheets]\nwebapp_url = '{}'\napi_key = \"test-key\"\nretries = 0\n\n[history]\npath = '{}'\n\n{}\n",
+            self.url(WEBAPP_PATH),
+            self.dir.join("history.json").display(),
+            blocks,
+        ));
+        let path = self.dir.join("config.toml");
+        std::fs::write(&path, text).expect("write config");
+        path
+    }
+
+    /// JSON bodies POSTed to `path`, in the order they arrived
+    pub async fn posts(&self, path: &str) -> Vec<Value> {
+        self.server.received_requests().await.unwrap_or_default().iter()
+            .filter(|request| request.url.path() == path)
+            .map(|request| serde_json::from_slice(&request.body).expect("JSON body"))
+            .collect()
+    }
+
+    /// What the webapp received
+    pub async fn webapp_posts(&self) -> Vec<Value> {
+        self.posts(WEBAPP_PATH).await
+    }
+}
+
+impl Drop for MockWebapp {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
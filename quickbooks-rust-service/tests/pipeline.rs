@@ -0,0 +1,2125 @@
+// Recorded qbXML responses (fixtures/) through parsing, transforms and destination writes, checked
+// at the mock webapp. The QuickBooks query itself is the only part of a cycle not exercised.
+
+mod common;
+
+use common::{fixture, MockWebapp};
+use quickbooks_sheets_sync::config::{CellMap, Config, DestinationConfig, ListType, QuickBooksConfig};
+use quickbooks_sheets_sync::context::AppContext;
+use quickbooks_sheets_sync::destinations::jsonl::JsonlRows;
+use quickbooks_sheets_sync::file_mode::FileMode;
+use quickbooks_sheets_sync::qb_backend::mock::MockProcessor;
+use quickbooks_sheets_sync::qb_backend::RequestProcessor;
+use quickbooks_sheets_sync::{a1, accounts, aging, capture, circuit, correlation, dashboard, dates, deleted_txns, destinations, expr, extract, google_sheets, host, integrity, kpis, lists, local_api, names, offline_queue, qbxml, raw_qbxml, reconcile, redact, reports, response_cache, scripting, transactions};
+use serde_json::{json, Value};
+use wiremock::matchers::{body_partial_json, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn sync_block_writes_balance_to_its_cell() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[sync_blocks]]
+spreadsheet_id = "sheet-1"
+account_full_name = "Cash Accounts:Operating Checking"
+sheet_name = "Balances"
+cell_address = "B2"
+"#);
+    let ctx = AppContext::new(&config);
+    let index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    assert_eq!(index.len(), 5);
+
+    let block = &config.sync_blocks[0];
+    let account = accounts::find_account(&index, &block.account_full_name).expect("account in fixture");
+    let value = destinations::sync_value(block, account, &config).unwrap();
+    for destination in destinations::for_sync_block(block, &ctx).unwrap() {
+        destination.write_value(&value).await.unwrap();
+    }
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0]["spreadsheetId"], "sheet-1");
+    assert_eq!(posts[0]["sheetName"], "Balances");
+    assert_eq!(posts[0]["cellAddress"], "B2");
+    assert_eq!(posts[0]["floatValue"], json!(45210.37));
+    assert_eq!(posts[0]["apiKey"], "test-key");
+}
+
+#[tokio::test]
+async fn sync_block_follows_its_account_through_a_rename() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[sync_blocks]]
+spreadsheet_id = "sheet-1"
+account_full_name = "Cash Accounts:Operating Checking"
+sheet_name = "Balances"
+cell_address = "B2"
+"#);
+    let ctx = AppContext::new(&config);
+    let block = &config.sync_blocks[0];
+    let index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), Default::default()).unwrap();
+    let found = accounts::for_sync_block(&index, block, &config, None).unwrap();
+    assert_eq!(found.list_id, "80000002-1000000002");
+    ctx.history.update(|h| h.set_account_id(block.label(), &found.list_id, &block.account_full_name)).unwrap();
+
+    // renamed in QuickBooks; the remembered ListID still finds it
+    let renamed = fixture("AccountQueryRs.xml").replace("Cash Accounts:Operating Checking", "Cash Accounts:Main Checking");
+    let index = accounts::index_accounts(&renamed, Default::default()).unwrap();
+    assert!(accounts::for_sync_block(&index, block, &config, None).is_none());
+    let known = ctx.history.read(|h| h.account_id(block.label(), &block.account_full_name));
+    let found = accounts::for_sync_block(&index, block, &config, known.as_deref()).unwrap();
+    assert_eq!((found.account_full_name.as_str(), found.balance), ("Cash Accounts:Main Checking", "45210.37".parse().unwrap()));
+}
+
+#[tokio::test]
+async fn named_sync_block_pointed_at_another_account_drops_the_old_list_id() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[sync_blocks]]
+name = "Cash"
+spreadsheet_id = "sheet-1"
+account_full_name = "Cash Accounts:Operating Checking"
+sheet_name = "Balances"
+cell_address = "B2"
+"#);
+    let ctx = AppContext::new(&config);
+    let block = &config.sync_blocks[0];
+    let index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), Default::default()).unwrap();
+    let found = accounts::for_sync_block(&index, block, &config, None).unwrap();
+    ctx.history.update(|h| h.set_account_id(block.label(), &found.list_id, &block.account_full_name)).unwrap();
+
+    // the same block, now pointed at another account: the stored ListID no longer applies
+    let other = index.values().find(|a| a.list_id != found.list_id && !a.list_id.is_empty()).unwrap();
+    let mut repointed = block.clone();
+    repointed.account_full_name = other.account_full_name.clone();
+    assert_eq!(repointed.label(), "Cash");
+    let known = ctx.history.read(|h| h.account_id(repointed.label(), &repointed.account_full_name));
+    assert_eq!(known, None);
+    let now_found = accounts::for_sync_block(&index, &repointed, &config, known.as_deref()).unwrap();
+    assert_eq!(now_found.list_id, other.list_id);
+
+    ctx.history.update(|h| h.set_account_id(repointed.label(), &now_found.list_id, &repointed.account_full_name)).unwrap();
+    assert!(ctx.history.read(|h| h.remembers_account("Cash", &other.list_id, &other.account_full_name)));
+    assert_eq!(ctx.history.read(|h| h.account_id("Cash", &other.account_full_name)), Some(other.list_id.clone()));
+}
+
+#[tokio::test]
+async fn unreadable_history_is_moved_aside_before_anything_is_saved() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config("");
+    let path = webapp.temp_path("history.json");
+    std::fs::write(&path, "{\"blocks\": {\"Customers\": {\"last_success\": \"2024-06-01T09:00:00-07:00\"}").unwrap();
+
+    let ctx = AppContext::new(&config);
+    assert_eq!(ctx.history.read(|h| h.last_success("Customers")), None);
+    ctx.history.update(|h| h.mark_success("Invoices", chrono::Local::now())).unwrap();
+
+    // the damaged file is still there to recover from, under its own name
+    let aside: Vec<_> = std::fs::read_dir(path.parent().unwrap()).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("history.json.corrupt-"))
+        .collect();
+    assert_eq!(aside.len(), 1);
+    assert!(std::fs::read_to_string(webapp.temp_path(&aside[0])).unwrap().contains("2024-06-01T09:00:00"));
+    let saved = std::fs::read_to_string(&path).unwrap();
+    assert!(saved.contains("Invoices") && !saved.contains("Customers"));
+}
+
+#[tokio::test]
+async fn max_frequency_skips_blocks_that_already_ran_this_period() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[sync_blocks]]
+name = "Daily cash"
+max_frequency = "daily"
+spreadsheet_id = "sheet-1"
+account_full_name = "Cash Accounts:Operating Checking"
+sheet_name = "History"
+cell_address = "APPEND:A"
+
+[[timestamp_blocks]]
+name = "Hourly stamp"
+max_frequency = "hourly"
+spreadsheet_id = "sheet-1"
+sheet_name = "History"
+cell_address = "D1"
+"#);
+    let ctx = AppContext::new(&config);
+    let (now, local) = (quickbooks_sheets_sync::dates::now(&config).unwrap(), chrono::Local::now());
+    assert!(ctx.history.ran_this_period(&config, now).is_empty());
+
+    ctx.history.update(|h| {
+        h.mark_ok("Daily cash", local);
+        h.mark_ok("Hourly stamp", local - chrono::Duration::days(1));
+    }).unwrap();
+    let ran: Vec<String> = ctx.history.ran_this_period(&config, now).into_iter().map(|(block, _)| block).collect();
+    assert_eq!(ran, ["Daily cash"]);
+}
+
+#[tokio::test]
+async fn cell_note_carries_the_values_provenance() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[sync_blocks]]
+spreadsheet_id = "sheet-1"
+account_full_name = "Cash Accounts:Operating Checking"
+sheet_name = "Balances"
+cell_address = "B2"
+cell_note = true
+"#);
+    let ctx = AppContext::new(&config);
+    let index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    let block = &config.sync_blocks[0];
+    let value = destinations::sync_value(block, accounts::find_account(&index, &block.account_full_name).unwrap(), &config).unwrap();
+    for destination in destinations::for_sync_block(block, &ctx).unwrap() {
+        destination.write_value(&value).await.unwrap();
+    }
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts[0]["floatValue"], json!(45210.37));
+    let note = posts[0]["note"].as_str().unwrap();
+    assert!(note.starts_with(&format!("From QuickBooks at {}", value.queried_at.format("%Y-%m-%d %H:%M:%S"))), "{}", note);
+    assert!(note.contains("Account: Cash Accounts:Operating Checking (ListID 80000002-1000000002)"), "{}", note);
+}
+
+#[tokio::test]
+async fn on_missing_writes_a_placeholder_or_clears_the_cell() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[sync_blocks]]
+spreadsheet_id = "sheet-1"
+account_full_name = "Closed Account"
+sheet_name = "Balances"
+cell_address = "B2"
+on_missing = "text:N/A"
+
+[[sync_blocks]]
+spreadsheet_id = "sheet-1"
+account_full_name = "Closed Account"
+sheet_name = "Balances"
+cell_address = "B3"
+on_missing = "clear"
+
+[[sync_blocks]]
+spreadsheet_id = "sheet-1"
+account_full_name = "Closed Account"
+sheet_name = "Balances"
+cell_address = "B4"
+"#);
+    let ctx = AppContext::new(&config);
+    for block in &config.sync_blocks {
+        let Some(value) = destinations::missing_value(block, &config).unwrap() else { continue };
+        for destination in destinations::for_sync_block(block, &ctx).unwrap() {
+            destination.write_value(&value).await.unwrap();
+        }
+    }
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 2, "the default (skip) writes nothing");
+    assert_eq!(posts[0]["stringValue"], "N/A");
+    assert_eq!(posts[1]["cellAddress"], "B3");
+    assert_eq!(posts[1]["stringValue"], "");
+
+    let typo = webapp.config_file(r#"
+[[sync_blocks]]
+spreadsheet_id = "sheet-1"
+account_full_name = "Closed Account"
+sheet_name = "Balances"
+cell_address = "B2"
+on_missing = "blank"
+"#);
+    assert!(format!("{:#}", Config::load(&typo, None, &[]).unwrap_err()).contains("on_missing = \"blank\""));
+}
+
+#[tokio::test]
+async fn named_range_cell_is_passed_to_the_webapp() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[sync_blocks]]
+spreadsheet_id = "sheet-1"
+account_full_name = "Cash Accounts:Operating Checking"
+sheet_name = "Dashboard"
+cell_address = "named:MonthlyCash"
+"#);
+    let ctx = AppContext::new(&config);
+    let index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+
+    let block = &config.sync_blocks[0];
+    let account = accounts::find_account(&index, &block.account_full_name).expect("account in fixture");
+    let value = destinations::sync_value(block, account, &config).unwrap();
+    for destination in destinations::for_sync_block(block, &ctx).unwrap() {
+        destination.write_value(&value).await.unwrap();
+    }
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0]["cellAddress"], "named:MonthlyCash");
+    assert_eq!(posts[0]["floatValue"], json!(45210.37));
+}
+
+#[tokio::test]
+async fn cells_map_writes_name_value_and_time_in_one_post() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[sync_blocks]]
+spreadsheet_id = "sheet-1"
+account_full_name = "Cash Accounts:Operating Checking"
+sheet_name = "Dashboard"
+date_format = "%Y"
+cells = { value = "B2", name = "A2", queried_at = "C2" }
+"#);
+    let ctx = AppContext::new(&config);
+    let index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+
+    let block = &config.sync_blocks[0];
+    let account = accounts::find_account(&index, &block.account_full_name).expect("account in fixture");
+    let value = destinations::sync_value(block, account, &config).unwrap();
+    for destination in destinations::for_sync_block(block, &ctx).unwrap() {
+        assert_eq!(destination.describe(), "sheet Dashboard!A2:C2");
+        destination.write_value(&value).await.unwrap();
+    }
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0]["cellAddress"], "A2");
+    let year = value.queried_at.format("%Y").to_string();
+    assert_eq!(posts[0]["rows"], json!([["Cash Accounts:Operating Checking", 45210.37, year]]));
+
+    // a gap would need a second write
+    let apart = CellMap { value: Some("B2".into()), name: Some("A2".into()), queried_at: Some("D2".into()) };
+    assert!(apart.layout().unwrap_err().to_string().contains("aren't next to each other"));
+}
+
+#[tokio::test]
+async fn config_vars_are_filled_into_block_settings() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[vars]
+dashboard = "sheet-7"
+cash = "Cash Accounts"
+flip = true
+
+[[sync_blocks]]
+spreadsheet_id = "${dashboard}"
+account_full_name = "${cash}:Operating Checking"
+sheet_name = "$${cash} Balances"
+cell_address = "B2"
+normalize_signs = "${flip}"
+value_template = "${balance:.2}"
+"#);
+    let block = &config.sync_blocks[0];
+    assert_eq!(block.spreadsheet_id, "sheet-7");
+    assert_eq!(block.normalize_signs, Some(true));
+    let ctx = AppContext::new(&config);
+    let index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    let account = accounts::find_account(&index, &block.account_full_name).expect("account in fixture");
+    let value = destinations::sync_value(block, account, &config).unwrap();
+    for destination in destinations::for_sync_block(block, &ctx).unwrap() {
+        destination.write_value(&value).await.unwrap();
+    }
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts[0]["spreadsheetId"], "sheet-7");
+    // "$${" is a literal "${", and value_template keeps its own placeholders
+    assert_eq!(posts[0]["sheetName"], "${cash} Balances");
+    assert_eq!(posts[0]["stringValue"], "$45210.37");
+}
+
+#[tokio::test]
+async fn strict_config_names_the_misspelt_key_and_its_block() {
+    let webapp = MockWebapp::start().await;
+    let block = r#"
+[[sync_blocks]]
+name = "Checking"
+account_full_name = "Cash Accounts:Operating Checking"
+spreadsheet_id = "sheet-1"
+sheet_name = "Dashboard"
+cell_adress = "B2"
+"#;
+    let path = webapp.config_file(block);
+    let error = Config::load(&path, None, &[]).unwrap_err();
+    assert_eq!(format!("{:#}", error), "Unknown config key 'cell_adress' in sync block 'Checking'; \
+        check the spelling, or set strict = false to ignore unknown keys");
+
+    // strict = false drops the unknown key as before
+    let config = Config::load(&path, None, &[("strict".into(), "false".into())]).unwrap();
+    assert_eq!(config.sync_blocks[0].cell_address, "");
+}
+
+#[tokio::test]
+async fn normalized_template_value_fans_out_to_webhook() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(&format!(r#"
+[[sync_blocks]]
+name = "income"
+spreadsheet_id = "sheet-1"
+account_full_name = "Consulting Income"
+sheet_name = "Balances"
+cell_address = "B3"
+normalize_signs = true
+value_template = "{{account_name}}: {{balance:.2}}"
+
+[[sync_blocks.destinations]]
+type = "webhook"
+url = "{}"
+"#, webapp.url("/hook")));
+    let ctx = AppContext::new(&config);
+    let index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+
+    let block = &config.sync_blocks[0];
+    let value = destinations::sync_value(block, &index["Consulting Income"], &config).unwrap();
+    for destination in destinations::for_sync_block(block, &ctx).unwrap() {
+        destination.write_value(&value).await.unwrap();
+    }
+
+    // income is credit-normal: QuickBooks' -128450.00 reads positive once normalized
+    let cell = webapp.webapp_posts().await;
+    assert_eq!(cell.len(), 1);
+    assert_eq!(cell[0]["stringValue"], "Consulting Income: 128450.00");
+    let hook = webapp.posts("/hook").await;
+    assert_eq!(hook.len(), 1);
+    assert_eq!(hook[0]["account"], "Consulting Income");
+    assert_eq!(hook[0]["value"], "Consulting Income: 128450.00");
+    assert_eq!(hook[0]["balance"], json!(128450.0));
+}
+
+#[tokio::test]
+async fn destination_headers_are_added_to_the_webapp_ones() {
+    let webapp = MockWebapp::start().await;
+    let mut config = webapp.config(r#"
+[[sync_blocks]]
+spreadsheet_id = "sheet-1"
+account_full_name = "Company Card"
+sheet_name = "Balances"
+cell_address = "B4"
+
+[[sync_blocks.destinations]]
+type = "cell"
+spreadsheet_id = "sheet-9"
+cell_address = "C4"
+headers = { "CF-Access-Client-Id" = "partner.access", "X-Tenant" = "north" }
+"#);
+    config.google_sheets.headers.extend([
+        ("CF-Access-Client-Id".to_string(), "main.access".to_string()),
+        ("CF-Access-Client-Secret".to_string(), "main-secret".to_string()),
+    ]);
+    let ctx = AppContext::new(&config);
+    let index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    let block = &config.sync_blocks[0];
+    let value = destinations::sync_value(block, &index["Company Card"], &config).unwrap();
+    for destination in destinations::for_sync_block(block, &ctx).unwrap() {
+        destination.write_value(&value).await.unwrap();
+    }
+
+    let requests = webapp.server.received_requests().await.unwrap();
+    let header = |i: usize, name: &str| requests[i].headers.get(name).map(|v| v.to_str().unwrap().to_string());
+    assert_eq!(requests.len(), 2);
+    // the block's own cell gets only [google_sheets] headers; the destination's replace or add to them
+    assert_eq!(header(0, "cf-access-client-id").as_deref(), Some("main.access"));
+    assert_eq!(header(0, "x-tenant"), None);
+    assert_eq!(header(1, "cf-access-client-id").as_deref(), Some("partner.access"));
+    assert_eq!(header(1, "cf-access-client-secret").as_deref(), Some("main-secret"));
+    assert_eq!(header(1, "x-tenant").as_deref(), Some("north"));
+}
+
+#[tokio::test]
+async fn report_block_writes_its_rows_as_a_range() {
+    let webapp = MockWebapp::start().await;
+    let mut config = webapp.config(r#"
+[[report_blocks]]
+name = "pnl"
+report_type = "ProfitAndLossStandard"
+spreadsheet_id = "sheet-2"
+sheet_name = "P&L"
+cell_address = "A1"
+clear_below = true
+"#);
+    config.google_sheets.confirm_destructive = Some(true);
+    let ctx = AppContext::new(&config);
+    let block = &config.report_blocks[0];
+    let table = reports::parse_for_block(block, &fixture("ProfitAndLossStandardRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    assert_eq!(table.title, "Profit & Loss");
+    let rows = table.to_rows(true);
+    ctx.sheets(&block.spreadsheet_id).write_range(&rows, &block.sheet_name, &block.cell_address, true, None).await.unwrap();
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0]["mode"], "range");
+    assert_eq!(posts[0]["clearBelow"], true);
+    assert_eq!(posts[0]["rows"], json!([
+        ["", "May 24"],
+        ["Ordinary Income/Expense", ""],
+        ["Income", ""],
+        ["Consulting Income", 21450.0],
+        ["Total Income", 21450.0],
+        ["Expense", ""],
+        ["Rent Expense", 4200.0],
+        ["Net Income", 17250.0],
+    ]));
+}
+
+#[tokio::test]
+async fn payroll_summary_is_queried_as_a_payroll_report_by_period() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[report_blocks]]
+report_type = "PayrollSummary"
+spreadsheet_id = "budget"
+sheet_name = "Payroll"
+cell_address = "A1"
+date_macro = "ThisYearToDate"
+summarize_columns_by = "Month"
+publish = { gross_pay = "Total Gross Pay", employer_taxes = "Total Employer Taxes and Contributions" }
+"#);
+    let block = &config.report_blocks[0];
+    let request = reports::report_request(block, chrono::NaiveDate::from_ymd_opt(2024, 6, 1).unwrap()).unwrap();
+    assert!(request.contains("<PayrollSummaryReportQueryRq>"), "{}", request);
+    assert!(request.contains("<PayrollSummaryReportType>PayrollSummary</PayrollSummaryReportType>"), "{}", request);
+    assert!(request.contains("<SummarizeColumnsBy>Month</SummarizeColumnsBy>"), "{}", request);
+
+    let table = reports::parse_for_block(block, &fixture("PayrollSummaryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    assert_eq!(table.headers, ["", "Apr 24", "May 24", "TOTAL"]);
+    assert_eq!(table.rows[4], json!(["Total Gross Pay", 11120.0, 11380.0, 22500.0]).as_array().unwrap().clone());
+    // the totals column is what a budgeting workbook's computed blocks get
+    assert_eq!(reports::row_total(&table, "Total Gross Pay"), Some(rust_decimal::Decimal::new(22500, 0)));
+    assert_eq!(reports::row_total(&table, "Total Employer Taxes and Contributions"), Some(rust_decimal::Decimal::new(176325, 2)));
+
+    let mut detail = block.clone();
+    detail.report_type = "PayrollItemDetail".to_string();
+    let error = reports::report_request(&detail, chrono::NaiveDate::from_ymd_opt(2024, 6, 1).unwrap()).unwrap_err();
+    assert!(error.to_string().contains("only applies to summary reports"), "{}", error);
+}
+
+#[tokio::test]
+async fn published_report_total_feeds_a_computed_block() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[sync_blocks]]
+name = "Owner draw"
+expr = "round(max(net_income - rent, 0) / 3, 2)"
+spreadsheet_id = "sheet-1"
+sheet_name = "KPIs"
+cell_address = "B2"
+
+[[report_blocks]]
+report_type = "ProfitAndLossStandard"
+spreadsheet_id = "sheet-2"
+sheet_name = "P&L"
+cell_address = "A1"
+publish = { net_income = "Net Income", rent = "  rent expense" }
+"#);
+    let ctx = AppContext::new(&config);
+    let report = &config.report_blocks[0];
+    let table = reports::parse_for_block(report, &fixture("ProfitAndLossStandardRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    let published: Vec<_> = report.publish.iter().map(|(name, label)| (name.clone(), reports::row_total(&table, label).unwrap())).collect();
+
+    let block = &config.sync_blocks[0];
+    let result = expr::evaluate(block.expression.as_deref().unwrap(), |name| published.iter().find(|(n, _)| n == name).map(|(_, v)| *v)).unwrap();
+    // (17250.00 - 4200.00) / 3
+    assert_eq!(result, rust_decimal::Decimal::from(4350));
+    let value = destinations::computed_value(block, result, &config).unwrap();
+    for destination in destinations::for_sync_block(block, &ctx).unwrap() {
+        destination.write_value(&value).await.unwrap();
+    }
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts[0]["floatValue"], json!(4350.0));
+}
+
+#[tokio::test]
+async fn rounding_policy_is_applied_before_writing() {
+    let webapp = MockWebapp::start().await;
+    let mut config = webapp.config(r#"
+[[sync_blocks]]
+name = "banker"
+expression = "2.345 + 0.01 - 0.01"
+spreadsheet_id = "sheet-1"
+sheet_name = "Dashboard"
+cell_address = "B2"
+
+[[sync_blocks]]
+name = "half_up"
+expression = "2.345 + 0.01 - 0.01"
+spreadsheet_id = "sheet-1"
+sheet_name = "Dashboard"
+cell_address = "B3"
+rounding = { mode = "half_up" }
+"#);
+    // top-level settings, which the helper's TOML would put inside [history]
+    config.rounding = Some(serde_json::from_value(json!({ "mode": "half_even", "decimals": 2 })).unwrap());
+    let written: Vec<String> = config.sync_blocks.iter().map(|block| {
+        let result = expr::evaluate(block.expression.as_deref().unwrap(), |_| None).unwrap();
+        destinations::computed_value(block, result, &config).unwrap().account.balance.to_string()
+    }).collect();
+    assert_eq!(written, ["2.34", "2.35"]);
+
+    let mut table = reports::ReportTable {
+        title: "Inventory".into(),
+        headers: vec!["Item".into(), "Avg Cost".into()],
+        rows: vec![vec![json!("Widget"), json!(2.355)], vec![json!("Gadget"), json!(4.125)]],
+        ..Default::default()
+    };
+    table.round(config.rounding.unwrap());
+    assert_eq!(table.rows, [[json!("Widget"), json!(2.36)], [json!("Gadget"), json!(4.12)]]);
+
+    // list exports round their money columns only; quantities and rates keep their places
+    let amounts = |list_type: ListType, line_items: bool, currency: bool| -> Vec<&str> {
+        let headers = list_type.headers(line_items, currency, false, false);
+        lists::amount_columns(list_type, line_items, currency, false, false).into_iter().map(|i| headers[i]).collect()
+    };
+    assert_eq!(amounts(ListType::Invoice, true, false), ["Amount", "Balance Due", "Line Amount"]);
+    assert_eq!(amounts(ListType::Account, false, true), ["Balance", "Home Balance"]);
+    assert!(amounts(ListType::ItemInventory, false, false).is_empty());
+    let mut cells = [json!(2.345), json!("2.345")];
+    cells.iter_mut().for_each(|cell| config.rounding.unwrap().apply_to_cell(cell));
+    assert_eq!(cells, [json!(2.34), json!("2.345")]);
+}
+
+#[tokio::test]
+async fn trial_balance_keeps_accounts_and_total() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[report_blocks]]
+report_type = "TrialBalance"
+spreadsheet_id = "sheet-2"
+sheet_name = "TB"
+cell_address = "A1"
+"#);
+    let table = reports::parse_for_block(&config.report_blocks[0], &fixture("TrialBalanceRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    assert_eq!(table.to_rows(true), vec![
+        vec![json!("Account"), json!("Debit"), json!("Credit")],
+        vec![json!("Cash Accounts:Operating Checking"), json!(45210.37), json!("")],
+        vec![json!("Company Card"), json!(""), json!(1875.42)],
+        vec![json!("Opening Balance Equity"), json!(""), json!(43334.95)],
+        vec![json!("Total"), json!(45210.37), json!(45210.37)],
+    ]);
+    assert_eq!(reports::trial_balance_totals(&table), Some((45210.37, 45210.37)));
+}
+
+#[tokio::test]
+async fn inventory_valuation_lists_each_item_once() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[report_blocks]]
+report_type = "InventoryValuationSummary"
+spreadsheet_id = "sheet-2"
+sheet_name = "Costing"
+cell_address = "A1"
+"#);
+    let table = reports::parse_for_block(&config.report_blocks[0], &fixture("InventoryValuationSummaryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    assert_eq!(table.to_rows(true), vec![
+        vec![json!("Item"), json!("On Hand"), json!("Avg Cost"), json!("Asset Value")],
+        vec![json!("Paper:Copy Paper"), json!(120.0), json!(24.5), json!(2940.0)],
+        vec![json!("Toner Cartridge"), json!(16.0), json!(66.25), json!(1060.0)],
+        vec![json!("Total"), json!(136.0), json!(""), json!(4000.0)],
+    ]);
+}
+
+#[tokio::test]
+async fn unbilled_costs_name_the_customer_on_every_row() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[report_blocks]]
+report_type = "UnbilledCostsByJob"
+spreadsheet_id = "sheet-2"
+sheet_name = "WIP"
+cell_address = "A1"
+"#);
+    let block = &config.report_blocks[0];
+    let request = reports::report_request(block, chrono::Local::now().date_naive()).unwrap();
+    assert!(request.contains("<GeneralDetailReportType>UnbilledCostsByJob</GeneralDetailReportType>"), "{}", request);
+    let table = reports::parse_for_block(block, &fixture("UnbilledCostsByJobRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    let rows = table.to_rows(true);
+    assert_eq!(rows[0], vec![json!("Customer"), json!("Type"), json!("Date"), json!("Source Name"), json!("Memo"), json!("Account"), json!("Amount")]);
+    let customers: Vec<&Value> = rows[1..].iter().map(|r| &r[0]).collect();
+    assert_eq!(customers, [&json!("Blue Heron Dental:Office Remodel"), &json!("Blue Heron Dental:Office Remodel"), &json!("Cedar Ridge School District"), &json!("Total")]);
+    assert_eq!(rows[3][6], json!(240.0));
+    assert_eq!(reports::row_total(&table, "Total").map(|t| t.to_string()), Some("1202.4".to_string()));
+}
+
+#[tokio::test]
+async fn transaction_block_writes_one_row_per_check() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[transaction_blocks]]
+txn_type = "Check"
+spreadsheet_id = "sheet-3"
+sheet_name = "Checks"
+cell_address = "A2"
+"#);
+    let ctx = AppContext::new(&config);
+    let block = &config.transaction_blocks[0];
+    let txns = transactions::parse_transactions(block.txn_type, &fixture("CheckQueryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    let rows = transactions::to_rows(block.txn_type, &txns, false);
+    ctx.sheets(&block.spreadsheet_id).write_range(&rows, &block.sheet_name, &block.cell_address, false, None).await.unwrap();
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0]["cellAddress"], "A2");
+    assert_eq!(posts[0]["rows"], json!([
+        ["2024-05-01", "Harbor Street Properties", 4200.0, "Cash Accounts:Operating Checking"],
+        ["2024-05-23", "Northwind Office Supply", 312.18, "Cash Accounts:Operating Checking"],
+    ]));
+}
+
+#[tokio::test]
+async fn credit_memos_and_refunds_are_written_as_negative_amounts() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[transaction_blocks]]
+txn_type = "CreditMemo"
+spreadsheet_id = "revenue"
+sheet_name = "Credits"
+cell_address = "A1"
+date_from = "2024-05-01"
+
+[[transaction_blocks]]
+txn_type = "ARRefundCreditCard"
+spreadsheet_id = "revenue"
+sheet_name = "Refunds"
+cell_address = "A1"
+"#);
+    let format = config.quickbooks.number_format().unwrap();
+    let (memos, refunds) = (&config.transaction_blocks[0], &config.transaction_blocks[1]);
+    let request = transactions::query_request(memos, chrono::NaiveDate::from_ymd_opt(2024, 6, 1).unwrap()).unwrap();
+    assert!(request.contains("<CreditMemoQueryRq>") && request.contains("<FromTxnDate>2024-05-01</FromTxnDate>"), "{}", request);
+
+    let txns = transactions::parse_transactions(memos.txn_type, &fixture("CreditMemoQueryRs.xml"), format).unwrap();
+    assert_eq!(transactions::to_rows(memos.txn_type, &txns, true), [
+        vec![json!("Date"), json!("Customer"), json!("Amount"), json!("A/R Account")],
+        vec![json!("2024-05-24"), json!("Lakeside Dental"), json!(-350.0), json!("Accounts Receivable")],
+    ]);
+    let txns = transactions::parse_transactions(refunds.txn_type, &fixture("ARRefundCreditCardQueryRs.xml"), format).unwrap();
+    assert_eq!(transactions::to_rows(refunds.txn_type, &txns, false), [
+        vec![json!("2024-05-26"), json!("Pine Ridge Veterinary"), json!(-125.5), json!("Cash Accounts:Operating Checking")],
+    ]);
+}
+
+#[tokio::test]
+async fn name_mapping_merges_payees_before_rows_are_written() {
+    let webapp = MockWebapp::start().await;
+    let mapping = webapp.temp_path("names.csv");
+    std::fs::write(&mapping, "from,to\nHarbor Street Properties,Harbor Street\n northwind  office SUPPLY ,Northwind\nAcme Corp,Acme\n").unwrap();
+    let config = webapp.config(&format!(r#"
+[name_mapping]
+file = "{}"
+
+[[transaction_blocks]]
+txn_type = "Check"
+spreadsheet_id = "expenses"
+sheet_name = "Checks"
+cell_address = "A1"
+"#, mapping.display()));
+    let block = &config.transaction_blocks[0];
+    let txns = transactions::parse_transactions(block.txn_type, &fixture("CheckQueryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    let mut rows = transactions::to_rows(block.txn_type, &txns, false);
+    let renamer = names::for_headers(&block.txn_type.headers(), &config).unwrap();
+    rows.iter_mut().for_each(|row| renamer.apply(row));
+    let payees: Vec<&Value> = rows.iter().map(|row| &row[1]).collect();
+    assert_eq!(payees[..2], [&json!("Harbor Street"), &json!("Northwind")]);
+
+    // a mapped customer keeps its jobs; a name with a mapping of its own takes that instead
+    let map = names::NameMap::parse("Acme Corp,Acme\nacme corp:Roof,Acme Roofing\n").unwrap();
+    assert_eq!(map.rename("ACME CORP:Gutters").as_deref(), Some("Acme:Gutters"));
+    assert_eq!(map.rename("Acme Corp:Roof").as_deref(), Some("Acme Roofing"));
+    assert_eq!(map.rename("Acme Corporation"), None);
+    assert!(names::NameMap::parse("Acme Corp,Acme\nACME corp,Acme Inc\n").is_err());
+}
+
+#[tokio::test]
+async fn scripts_transform_values_and_rows_in_order() {
+    let webapp = MockWebapp::start().await;
+    let script = |name: &str, text: &str| {
+        let path = webapp.temp_path(name);
+        std::fs::write(&path, text).unwrap();
+        path.display().to_string()
+    };
+    let thousands = script("thousands.rhai", r#"if record.account_type == "Bank" { value / 1000.0 } else { () }"#);
+    let large = script("large.rhai", "if row.Amount < 1000.0 { () } else { row }");
+    let shout = script("shout.rhai", "row.Payee = row.Payee.to_upper(); row");
+    let config = webapp.config(&format!(r#"
+[[sync_blocks]]
+spreadsheet_id = "sheet-1"
+account_full_name = "Cash Accounts:Operating Checking"
+sheet_name = "Balances"
+cell_address = "B2"
+scripts = ["{}"]
+
+[[transaction_blocks]]
+txn_type = "Check"
+spreadsheet_id = "expenses"
+sheet_name = "Checks"
+cell_address = "A1"
+scripts = ["{}", "{}"]
+"#, thousands, large, shout));
+
+    let index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    let block = &config.sync_blocks[0];
+    let value = destinations::sync_value(block, accounts::find_account(&index, &block.account_full_name).unwrap(), &config).unwrap();
+    let value = scripting::Scripts::load(&block.scripts).unwrap().value(value).unwrap().expect("a Bank account has a value");
+    assert_eq!(value.cell_value(), json!(45.21037));
+
+    // the first script drops the small check, the second sees only what is left
+    let block = &config.transaction_blocks[0];
+    let txns = transactions::parse_transactions(block.txn_type, &fixture("CheckQueryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    let scripts = scripting::Scripts::load(&block.scripts).unwrap();
+    let rows: Vec<Vec<Value>> = transactions::to_rows(block.txn_type, &txns, false).into_iter()
+        .filter_map(|row| scripts.row(&block.txn_type.headers(), row).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0][1], json!("HARBOR STREET PROPERTIES"));
+    assert_eq!(rows[0][2], json!(4200.0));
+
+    // a script that doesn't compile fails the config load
+    let broken = script("broken.rhai", "if value > { 1 }");
+    let path = webapp.config_file(&format!(r#"
+[[sync_blocks]]
+spreadsheet_id = "sheet-1"
+account_full_name = "Cash Accounts:Operating Checking"
+sheet_name = "Balances"
+cell_address = "B2"
+scripts = ["{}"]
+"#, broken));
+    assert!(format!("{:#}", Config::load(&path, None, &[]).unwrap_err()).contains("doesn't compile"));
+}
+
+#[tokio::test]
+async fn list_block_writes_header_and_records() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[list_blocks]]
+list_type = "Customer"
+spreadsheet_id = "sheet-4"
+sheet_name = "Customers"
+cell_address = "A1"
+named_range = true
+"#);
+    let ctx = AppContext::new(&config);
+    let block = &config.list_blocks[0];
+    let mut rows = vec![lists::header_row(block.list_type, false, false, false, false)];
+    lists::for_each_row(block.list_type, &fixture("CustomerQueryRs.xml"), config.quickbooks.number_format().unwrap(), false, false, false, None, false, |row| {
+        rows.push(row);
+        Ok(())
+    }).unwrap();
+    ctx.sheets(&block.spreadsheet_id).write_range(&rows, &block.sheet_name, &block.cell_address, false, Some("Customers")).await.unwrap();
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0]["namedRange"], "Customers");
+    assert_eq!(posts[0]["rows"], json!([
+        ["Name", "Email", "Phone", "Balance", "Modified"],
+        ["Blue Heron Dental", "billing@blueheron.example", "555-0142", 6400.0, "2024-05-28T13:20:45-08:00"],
+        ["Cedar Ridge School District", "", "555-0199", 0.0, "2024-04-02T09:00:00-08:00"],
+    ]));
+
+    // an incremental run reads its table back to merge into, which mapped columns don't make
+    let path = webapp.config_file(r#"
+[[list_blocks]]
+list_type = "Customer"
+spreadsheet_id = "sheet-4"
+sheet_name = "Customers"
+cell_address = "A1"
+incremental = true
+columns = { "Name" = "A", "Balance" = "C" }
+"#);
+    assert!(format!("{:#}", Config::load(&path, None, &[]).unwrap_err()).contains("columns can't be used with incremental"));
+}
+
+// The mock webapp's range writes applied to a grid, shown the way the sheet displays the values
+fn apply_range_writes(grid: &mut Vec<Vec<String>>, posts: &[Value]) {
+    for post in posts.iter().filter(|p| p["mode"] == "range") {
+        let cell = a1::parse_cell(post["cellAddress"].as_str().unwrap()).unwrap();
+        for (i, row) in post["rows"].as_array().unwrap().iter().enumerate() {
+            let r = cell.row as usize - 1 + i;
+            if grid.len() <= r {
+                grid.resize(r + 1, Vec::new());
+            }
+            for (j, value) in row.as_array().unwrap().iter().enumerate() {
+                let c = cell.column as usize - 1 + j;
+                if grid[r].len() <= c {
+                    grid[r].resize(c + 1, String::new());
+                }
+                grid[r][c] = match value {
+                    Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                };
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn incremental_export_updates_changed_records_in_place() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[list_blocks]]
+list_type = "Customer"
+spreadsheet_id = "sheet-4"
+sheet_name = "Customers"
+cell_address = "B2"
+incremental = true
+"#);
+    let ctx = AppContext::new(&config);
+    let block = &config.list_blocks[0];
+    let client = ctx.sheets(&block.spreadsheet_id);
+    let anchor = a1::parse_cell(&block.cell_address).unwrap();
+    let header = lists::header_row(block.list_type, false, false, false, false);
+    let export = |xml: &str| {
+        let mut rows = Vec::new();
+        lists::for_each_row(block.list_type, xml, Default::default(), false, false, false, None, false, |row| {
+            rows.push(row);
+            Ok(())
+        }).unwrap();
+        rows
+    };
+
+    // the first run has no last success, so it writes every record
+    let mut grid = vec![vec!["Customer balances".to_string()]];
+    let mut rows = vec![header.clone()];
+    rows.extend(export(&fixture("CustomerQueryRs.xml")));
+    client.write_range(&rows, &block.sheet_name, &block.cell_address, false, None).await.unwrap();
+    apply_range_writes(&mut grid, &webapp.webapp_posts().await);
+    assert_eq!(grid.len(), 4);
+
+    // the second only gets Blue Heron (its balance changed) and a new customer
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "mode": "read" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "success": true, "rows": grid })))
+        .with_priority(1)
+        .mount(&webapp.server)
+        .await;
+    let changed = fixture("CustomerQueryRs.xml")
+        .replace("<TotalBalance>6400.00</TotalBalance>", "<TotalBalance>7100.00</TotalBalance>")
+        .replace("Cedar Ridge School District", "Alder Creek Farm")
+        .replace("555-0199", "555-0111");
+    let sheet = client.read_sheet(&block.sheet_name).await.unwrap();
+    let mut rows = vec![header.clone()];
+    rows.extend(lists::merge_changed(&sheet, anchor, header.len(), true, export(&changed), lists::key_column(block.list_type)));
+    let before = webapp.webapp_posts().await.len();
+    client.write_range(&rows, &block.sheet_name, &block.cell_address, false, None).await.unwrap();
+    apply_range_writes(&mut grid, &webapp.webapp_posts().await[before..]);
+
+    // each record once: Blue Heron updated where it was, Cedar Ridge kept, Alder Creek added below
+    let names: Vec<&str> = grid[2..].iter().map(|row| row[1].as_str()).collect();
+    assert_eq!(names, ["Blue Heron Dental", "Cedar Ridge School District", "Alder Creek Farm"]);
+    assert_eq!(grid[0], ["Customer balances"]);
+    assert_eq!(grid[1][1..], ["Name", "Email", "Phone", "Balance", "Modified"]);
+    assert_eq!(grid[2][4], "7100.0");
+    assert_eq!(grid[3][1..], ["Cedar Ridge School District", "", "555-0199", "0.0", "2024-04-02T09:00:00-08:00"]);
+    assert_eq!(grid[4][3], "555-0111");
+}
+
+#[tokio::test]
+async fn odbc_list_block_selects_the_same_columns() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[odbc]
+connection_string = "DSN=QuickBooks Data"
+
+[[list_blocks]]
+list_type = "SalesOrder"
+backend = "odbc"
+spreadsheet_id = "sheet-4"
+sheet_name = "Orders"
+cell_address = "A1"
+modified_from = "2024-05-01"
+"#);
+    let block = &config.list_blocks[0];
+    assert!(block.odbc());
+    let sql = lists::odbc_query(block, None, chrono::NaiveDate::from_ymd_opt(2024, 6, 1).unwrap()).unwrap();
+    assert!(sql.starts_with("SELECT RefNumber, CustomerRefFullName, TxnDate, "), "{}", sql);
+    assert!(sql.ends_with(" FROM SalesOrder WHERE TimeModified >= {ts '2024-05-01 00:00:00'} AND IsFullyInvoiced = 0 AND IsManuallyClosed = 0"), "{}", sql);
+
+    // plain decimals and 1 / 0 come out as the qbXML export's numbers and booleans
+    let headers = lists::header_row(ListType::Vendor, false, false, false, false);
+    let values: Vec<Option<String>> = headers.iter().map(|h| match h.as_str().unwrap() {
+        "Name" => Some("Northwind Paper".to_string()),
+        "Balance" => Some("1250.50".to_string()),
+        "1099 Eligible" => Some("1".to_string()),
+        _ => None,
+    }).collect();
+    let row = lists::odbc_row(ListType::Vendor, values);
+    let cell = |header: &str| row[headers.iter().position(|h| h == header).unwrap()].clone();
+    assert_eq!(cell("Name"), "Northwind Paper");
+    assert_eq!(cell("Balance"), json!(1250.5));
+    assert_eq!(cell("1099 Eligible"), true);
+
+    // line items have no ODBC counterpart here
+    let mut with_lines = block.clone();
+    with_lines.list_type = ListType::PurchaseOrder;
+    assert!(lists::check_odbc(&with_lines).is_err());
+}
+
+#[test]
+fn customer_contacts_include_the_billing_address() {
+    let mut rows = vec![lists::header_row(ListType::CustomerContacts, false, false, false, false)];
+    lists::for_each_row(ListType::CustomerContacts, &fixture("CustomerQueryRs.xml"), Default::default(), false, false, false, None, false, |row| {
+        rows.push(row);
+        Ok(())
+    }).unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(json!(rows[0][..9]), json!(["Name", "Company", "Contact", "Email", "Cc", "Phone", "Alt Phone", "Fax", "Address 1"]));
+    assert_eq!(json!(rows[1]), json!([
+        "Blue Heron Dental", "Blue Heron Dental LLC", "Dana Ruiz", "billing@blueheron.example", "office@blueheron.example",
+        "555-0142", "555-0143", "", "Blue Heron Dental LLC", "Attn: Accounts Payable", "410 Harbor Way, Suite 2", "", "",
+        "Anytown", "WA", "98000", "", "2024-05-28T13:20:45-08:00",
+    ]));
+    // no BillAddress at all leaves the address columns blank
+    assert!(rows[2][8..17].iter().all(|v| v == ""), "{:?}", rows[2]);
+}
+
+#[tokio::test]
+async fn relative_dates_apply_offsets_and_reject_bad_ones() {
+    let today = chrono::NaiveDate::from_ymd_opt(2024, 5, 31).unwrap();
+    let date = |y, m, d| chrono::NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    assert_eq!(dates::resolve("-7d", today).unwrap(), date(2024, 5, 24));
+    assert_eq!(dates::resolve("+2w", today).unwrap(), date(2024, 6, 14));
+    // month offsets clamp to the end of shorter months
+    assert_eq!(dates::resolve("end-of-month-3m", today).unwrap(), date(2024, 2, 29));
+    assert_eq!(dates::resolve("start-of-year-1y", today).unwrap(), date(2023, 1, 1));
+
+    for bad in ["7d", "-d", "-7x", "start-of-decade"] {
+        assert!(dates::resolve(bad, today).is_err(), "{}", bad);
+    }
+    // more years than fit in a month count, and more days than the calendar has
+    for huge in ["+400000000y", "-400000000y", "+4000000000d"] {
+        assert!(format!("{:#}", dates::resolve(huge, today).unwrap_err()).contains("out of range"), "{}", huge);
+    }
+
+
+    // a block date out of range fails the load rather than the block's run
+    let webapp = MockWebapp::start().await;
+    let path = webapp.config_file(r#"
+[[report_blocks]]
+report_type = "ProfitAndLossStandard"
+spreadsheet_id = "sheet-1"
+sheet_name = "P&L"
+cell_address = "A1"
+date_from = "start-of-year-400000000y"
+"#);
+    assert!(format!("{:#}", Config::load(&path, None, &[]).unwrap_err()).contains("out of range"));
+}
+
+#[test]
+fn customer_jobs_are_indented_under_their_parent() {
+    let mut rows = vec![lists::header_row(ListType::CustomerJobs, false, false, false, false)];
+    lists::for_each_row(ListType::CustomerJobs, &fixture("CustomerQueryRs-jobs.xml"), Default::default(), false, false, false, None, false, |row| {
+        rows.push(row);
+        Ok(())
+    }).unwrap();
+    assert_eq!(json!(rows), json!([
+        ["Name", "Full Name", "Parent", "Level", "Job Status", "Job Type", "Start Date", "Projected End", "End Date", "Balance"],
+        ["Blue Heron Dental", "Blue Heron Dental", "", 0.0, "None", "", "", "", "", 6400.0],
+        ["    Office Remodel", "Blue Heron Dental:Office Remodel", "Blue Heron Dental", 1.0, "InProgress", "Remodel", "2024-03-11", "2024-07-31", "", 6400.0],
+        ["        Phase 1", "Blue Heron Dental:Office Remodel:Phase 1", "Blue Heron Dental:Office Remodel", 2.0, "Closed", "", "2024-03-11", "2024-04-30", "2024-04-26", 0.0],
+    ]));
+}
+
+#[test]
+fn class_column_is_opt_in_and_last() {
+    let plain = lists::header_row(ListType::Invoice, true, false, false, false);
+    assert!(!plain.contains(&json!("Class")), "{:?}", plain);
+    let with_class = lists::header_row(ListType::Invoice, true, false, false, true);
+    assert_eq!(with_class[..plain.len()], plain[..]);
+    assert_eq!(with_class.last(), Some(&json!("Class")));
+    assert_eq!(lists::class_column(ListType::Invoice, true, true), Some(plain.len()));
+    assert_eq!(lists::class_column(ListType::Invoice, true, false), None);
+}
+
+#[tokio::test]
+async fn account_categories_total_and_tag_exported_rows() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[account_classification]
+types = { Bank = "Cash", CreditCard = "Debt" }
+accounts = { "Cash Accounts:Tax Reserve" = "Restricted" }
+
+[[sync_blocks]]
+name = "cash"
+category = "Cash"
+spreadsheet_id = "sheet-1"
+sheet_name = "Dashboard"
+cell_address = "B2"
+
+[[list_blocks]]
+list_type = "Account"
+spreadsheet_id = "sheet-1"
+sheet_name = "Accounts"
+cell_address = "A1"
+include_category = true
+"#);
+    let mut index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), Default::default()).unwrap();
+    accounts::classify(&mut index, config.account_classification.as_ref());
+    assert_eq!(index["Company Card"].category, "Debt");
+
+    // the reserve's override takes it out of Cash
+    let total = accounts::for_sync_block(&index, &config.sync_blocks[0], &config, None).unwrap();
+    assert_eq!(total.account_full_name, "Cash");
+    assert_eq!(total.balance.to_string(), "45210.37");
+
+    let block = &config.list_blocks[0];
+    let mut rows = vec![lists::header_row(block.list_type, false, false, block.category(), false)];
+    lists::for_each_row(block.list_type, &fixture("AccountQueryRs.xml"), Default::default(), false, false, false, config.account_classification.as_ref(), false, |row| {
+        rows.push(row);
+        Ok(())
+    }).unwrap();
+    let categories: Vec<(&str, &str)> = rows.iter().map(|r| (r[0].as_str().unwrap(), r.last().unwrap().as_str().unwrap())).collect();
+    assert_eq!(categories, [
+        ("Name", "Category"),
+        ("Cash Accounts", "Cash"),
+        ("Cash Accounts:Operating Checking", "Cash"),
+        ("Cash Accounts:Tax Reserve", "Restricted"),
+        ("Company Card", "Debt"),
+        ("Consulting Income", ""),
+    ]);
+}
+
+#[tokio::test]
+async fn account_lists_keep_accounts_off_every_destination() {
+    let webapp = MockWebapp::start().await;
+    let mut config = webapp.config("");
+    config.allowed_accounts = vec!["Cash Accounts".into(), "company card".into()];
+    config.denied_accounts = vec!["Cash Accounts:Tax Reserve".into()];
+    let mut index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), Default::default()).unwrap();
+    accounts::restrict(&mut index, &config);
+    let mut kept: Vec<&str> = index.keys().map(String::as_str).collect();
+    kept.sort();
+    assert_eq!(kept, ["Cash Accounts", "Cash Accounts:Operating Checking", "Company Card"]);
+
+    // a block naming a denied account stops the load
+    let path = webapp.temp_path("denied.toml");
+    std::fs::write(&path, format!(r#"version = 2
+denied_accounts = ["Payroll"]
+timestamp_blocks = []
+
+[quickbooks]
+company_file = "AUTO"
+
+[google_sheets]
+webapp_url = '{}'
+api_key = "test-key"
+
+[[sync_blocks]]
+account_full_name = "Payroll:Wages"
+spreadsheet_id = "sheet-1"
+sheet_name = "Dashboard"
+cell_address = "B2"
+"#, webapp.url("/exec"))).unwrap();
+    let error = Config::load(&path, None, &[]).unwrap_err();
+    assert!(format!("{:#}", error).contains("'Payroll:Wages' isn't permitted"), "{:#}", error);
+}
+
+#[tokio::test]
+async fn denied_accounts_are_left_out_of_report_transaction_and_raw_rows() {
+    let webapp = MockWebapp::start().await;
+    let mut config = webapp.config(r#"
+[[report_blocks]]
+report_type = "ProfitAndLossStandard"
+spreadsheet_id = "sheet-2"
+sheet_name = "P&L"
+cell_address = "A1"
+
+[[transaction_blocks]]
+txn_type = "Check"
+spreadsheet_id = "sheet-2"
+sheet_name = "Checks"
+cell_address = "A1"
+"#);
+    config.denied_accounts = vec!["rent expense".into(), "Cash Accounts:Operating Checking".into()];
+    let permitted = |account: &str| config.account_permitted(account);
+    let format = config.quickbooks.number_format().unwrap();
+
+    let mut table = reports::parse_for_block(&config.report_blocks[0], &fixture("ProfitAndLossStandardRs.xml"), format).unwrap();
+    assert_eq!(table.retain_accounts(permitted), 1);
+    let labels: Vec<&str> = table.rows.iter().filter_map(|row| row[0].as_str()).collect();
+    assert!(labels.contains(&"Consulting Income") && !labels.contains(&"Rent Expense"), "{:?}", labels);
+    assert_eq!(table.rows.len(), table.accounts.len());
+
+    let mut txns = transactions::parse_transactions(config.transaction_blocks[0].txn_type, &fixture("CheckQueryRs.xml"), format).unwrap();
+    assert_eq!(transactions::retain_accounts(&mut txns, permitted), 2);
+    assert!(txns.is_empty());
+
+    let mut response = extract::Response::parse(&fixture("AccountQueryRs.xml")).unwrap();
+    assert_eq!(response.drop_accounts(permitted), 1);
+    let names = response.values("AccountQueryRs/AccountRet/FullName").unwrap();
+    assert_eq!(names.len(), 4);
+    assert!(!names.contains(&json!("Cash Accounts:Operating Checking")));
+}
+
+#[tokio::test]
+async fn kpis_are_worked_out_from_the_classified_accounts() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[account_classification]
+types = { Bank = "Cash", AccountsPayable = "Payables" }
+accounts = { "Cash Accounts:Tax Reserve" = "Restricted" }
+
+[[report_blocks]]
+report_type = "ProfitAndLossStandard"
+spreadsheet_id = "sheet-1"
+sheet_name = "P&L"
+cell_address = "A1"
+publish = { expenses = "Total Expense" }
+
+[kpis]
+spreadsheet_id = "sheet-1"
+sheet_name = "Summary"
+current_assets = ["Cash", "Restricted"]
+quick_assets = ["Cash"]
+current_liabilities = ["Payables"]
+cash = ["Cash"]
+monthly_burn = "expenses / 3"
+cells = { current_ratio = "B2", quick_ratio = "B3", working_capital = "B4", runway_months = "B5" }
+"#);
+    let mut index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), Default::default()).unwrap();
+    index.insert("Accounts Payable".to_string(), accounts::AccountData {
+        account_full_name: "Accounts Payable".to_string(),
+        account_type: "AccountsPayable".to_string(),
+        balance: "-12000".parse().unwrap(),
+        ..Default::default()
+    });
+    accounts::classify(&mut index, config.account_classification.as_ref());
+    let kpis_cfg = config.kpis.as_ref().unwrap();
+
+    let values = kpis::compute(&index, kpis_cfg, Some("15000".parse().unwrap()));
+    let shown: Vec<(&str, String)> = values.iter().map(|(k, v)| (k.as_str(), v.unwrap().to_string())).collect();
+    assert_eq!(shown, [
+        ("current_ratio", "4.02".to_string()),
+        ("quick_ratio", "3.77".to_string()),
+        ("runway_months", "3.01".to_string()),
+        ("working_capital", "36210.37".to_string()),
+    ]);
+
+    // nothing owed and no burn: the ratios are left out rather than divided by zero
+    index.remove("Accounts Payable");
+    let values = kpis::compute(&index, kpis_cfg, None);
+    assert_eq!(values["current_ratio"], None);
+    assert_eq!(values["runway_months"], None);
+}
+
+#[tokio::test]
+async fn blocks_sit_out_cycles_outside_their_active_hours() {
+    let webapp = MockWebapp::start().await;
+    let mut config = webapp.config(r#"
+[[sync_blocks]]
+name = "cash"
+account_full_name = "Cash Accounts:Operating Checking"
+spreadsheet_id = "sheet-1"
+sheet_name = "Dashboard"
+cell_address = "B2"
+
+[[timestamp_blocks]]
+name = "heartbeat"
+spreadsheet_id = "sheet-1"
+sheet_name = "Dashboard"
+cell_address = "B1"
+active_hours = "22:00-06:00"
+
+[balance_diff]
+min_change = 1.0
+"#);
+    // top-level settings, which the helper's TOML would put inside [history]
+    config.active_hours = Some("07:00-19:00".to_string());
+    config.active_days = vec!["Mon-Fri".to_string()];
+    let at = |text: &str| chrono::NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+
+    // Wednesday morning: the heartbeat's own window overrides the top-level one
+    assert_eq!(config.inactive_blocks(at("2024-05-01 09:30")), ["heartbeat"]);
+    assert!(config.needs_quickbooks_at(at("2024-05-01 09:30")));
+    // Wednesday night: only the heartbeat runs, so no session is needed
+    assert_eq!(config.inactive_blocks(at("2024-05-01 23:00")), ["cash", "Balance diff"]);
+    assert!(!config.needs_quickbooks_at(at("2024-05-01 23:00")));
+    // Saturday midday: neither; the heartbeat's window has no days, so only its hours count
+    assert_eq!(config.inactive_blocks(at("2024-05-04 12:00")), ["cash", "heartbeat", "Balance diff"]);
+    assert_eq!(config.inactive_blocks(at("2024-05-04 02:00")), ["cash", "Balance diff"]);
+}
+
+#[tokio::test]
+async fn timestamp_iso_cell_sits_right_of_the_display_cell() {
+    let webapp = MockWebapp::start().await;
+    let timestamp = r#"
+[[timestamp_blocks]]
+name = "updated"
+spreadsheet_id = "sheet-1"
+sheet_name = "Dashboard"
+cell_address = "B1"
+display_format = "%m/%d/%Y %I:%M %p"
+iso_utc = true
+"#;
+    let config = webapp.config(timestamp);
+    assert_eq!(config.timestamp_blocks[0].iso_cell().unwrap().to_string(), "C1");
+
+    // the ISO cell counts when checking for blocks writing the same cell
+    let error = Config::load(webapp.config_file(&format!(r#"{}
+[[sync_blocks]]
+account_full_name = "Cash"
+spreadsheet_id = "sheet-1"
+sheet_name = "Dashboard"
+cell_address = "C1"
+"#, timestamp)), None, &[]).unwrap_err();
+    assert!(format!("{:#}", error).contains("Dashboard!C1"), "{:#}", error);
+
+    let error = Config::load(webapp.config_file(&timestamp.replace("B1", "named:Updated")), None, &[]).unwrap_err();
+    assert!(format!("{:#}", error).contains("named:Updated"), "{:#}", error);
+}
+
+#[tokio::test]
+async fn append_addresses_are_checked_wherever_they_are_given() {
+    let webapp = MockWebapp::start().await;
+    let destination = r#"
+[[sync_blocks]]
+account_full_name = "Cash"
+spreadsheet_id = "sheet-1"
+sheet_name = "Dashboard"
+cell_address = "B2"
+
+[[sync_blocks.destinations]]
+type = "cell"
+sheet_name = "History"
+cell_address = "APPEND:C"
+"#;
+    let config = webapp.config(destination);
+    assert!(config.sync_blocks[0].all_destinations().iter().any(|d| matches!(d, DestinationConfig::Append { append_column: Some(c), .. } if c == "C")));
+
+    let error = Config::load(webapp.config_file(&destination.replace("APPEND:C", "APPEND:C3")), None, &[]).unwrap_err();
+    assert!(format!("{:#}", error).contains("Invalid append column 'C3'"), "{:#}", error);
+
+    // blocks that write a cell of their own can't append
+    let error = Config::load(webapp.config_file(r#"
+[[timestamp_blocks]]
+spreadsheet_id = "sheet-1"
+sheet_name = "History"
+cell_address = "APPEND:A"
+"#), None, &[]).unwrap_err();
+    assert!(format!("{:#}", error).contains("'APPEND:A' appends a row"), "{:#}", error);
+}
+
+#[tokio::test]
+async fn redacted_columns_are_hashed_and_masked() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[redaction]
+hash_key = "test-hash-key"
+
+[[list_blocks]]
+list_type = "CustomerContacts"
+spreadsheet_id = "sheet-6"
+sheet_name = "Contacts"
+cell_address = "A1"
+redact = { "Email" = "hash", "phone" = "mask", "Cc" = "mask", "Address 3" = "remove" }
+"#);
+    let redactor = redact::for_list_block(&config.list_blocks[0], &config).unwrap();
+    let mut rows = Vec::new();
+    lists::for_each_row(ListType::CustomerContacts, &fixture("CustomerQueryRs.xml"), Default::default(), false, false, false, None, false, |row| {
+        rows.push(row);
+        Ok(())
+    }).unwrap();
+    let mut row = rows[0].clone();
+    redactor.apply(&mut row);
+
+    let hashed = row[3].as_str().unwrap();
+    assert_eq!(hashed.len(), 16);
+    assert_ne!(hashed, "billing@blueheron.example");
+    // the same address in other letter case hashes the same, so the column still joins
+    let mut upper = rows[0].clone();
+    upper[3] = json!("Billing@BlueHeron.example");
+    redactor.apply(&mut upper);
+    assert_eq!(upper[3], row[3]);
+    assert_eq!(row[4], "o***@blueheron.example");
+    assert_eq!(row[5], "***-0142");
+    assert_eq!(row[10], "");
+    // blank values stay blank and other columns are untouched
+    assert_eq!(row[7], "");
+    assert_eq!(row[0], "Blue Heron Dental");
+}
+
+#[tokio::test]
+async fn missing_sheet_is_created_with_the_mapped_headers() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[list_blocks]]
+list_type = "Customer"
+spreadsheet_id = "sheet-8"
+sheet_name = "New Customers"
+cell_address = "A2"
+include_header = false
+columns = { "name" = "A", "Balance" = "C" }
+create_sheet_if_missing = true
+"#);
+    let ctx = AppContext::new(&config);
+    let block = &config.list_blocks[0];
+    let headers = lists::mapped_headers(block.list_type, false, false, false, false, &block.columns);
+    let client = ctx.sheets(&block.spreadsheet_id).creating_sheet(true, headers);
+    client.write_range(&[vec![json!("Blue Heron Dental")]], &block.sheet_name, "A2", false, None).await.unwrap();
+    // reads never create anything
+    let _ = client.read_sheet(&block.sheet_name).await;
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts[0]["createSheet"], true);
+    assert_eq!(posts[0]["newSheetHeaders"], json!({ "A": "Name", "C": "Balance" }));
+    assert!(posts[1].get("createSheet").is_none(), "{}", posts[1]);
+}
+
+#[tokio::test]
+async fn range_is_read_through_the_webapp() {
+    let webapp = MockWebapp::start().await;
+    // ahead of the catch-all write answer
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "mode": "read" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "success": true, "rows": [["Blue Heron Dental", "1,250.00"]] })))
+        .with_priority(1)
+        .mount(&webapp.server)
+        .await;
+    let config = webapp.config("");
+    let client = AppContext::new(&config).sheets("sheet-9");
+
+    let rows = client.read_range("Customers", "A2:B2").await.unwrap();
+    assert_eq!(rows, [["Blue Heron Dental", "1,250.00"]]);
+    client.read_range("Customers", "named:CustomerKeys").await.unwrap();
+    // checked before anything is sent
+    assert!(client.read_range("Customers", "Customers!A2:B2").await.is_err());
+    assert!(client.read_range("Customers", "A:B").await.is_err());
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 2);
+    assert_eq!(posts[0]["sheetName"], "Customers");
+    assert_eq!(posts[0]["cellAddress"], "A2:B2");
+    assert_eq!(posts[1]["cellAddress"], "named:CustomerKeys");
+}
+
+#[tokio::test]
+async fn open_invoices_are_aged_per_customer() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[aging]
+spreadsheet_id = "sheet-4"
+sheet_name = "AR Aging"
+"#);
+    let aging_cfg = config.aging.as_ref().unwrap();
+    assert!(aging::request().contains("<PaidStatus>NotPaidOnly</PaidStatus>"));
+    let invoices = aging::parse(&fixture("InvoiceQueryRs-open.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+    assert_eq!(invoices.len(), 5);
+
+    // the job's invoice counts under its customer
+    let today = chrono::NaiveDate::from_ymd_opt(2024, 5, 31).unwrap();
+    let rows = aging::buckets(&invoices, aging_cfg, today);
+    assert_eq!(rows.iter().map(|r| r.customer.as_str()).collect::<Vec<_>>(), ["Blue Heron Dental", "Cedar Ridge Clinic", "Lakeside Pediatrics"]);
+    let ctx = AppContext::new(&config);
+    ctx.sheets(&aging_cfg.spreadsheet_id).write_range(&aging::to_rows(&rows, aging_cfg), &aging_cfg.sheet_name, "A1", true, None).await.unwrap();
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts[0]["rows"], json!([
+        ["Customer", "0-30", "31-60", "61-90", "90+", "Total"],
+        ["Blue Heron Dental", 3400.0, 1250.0, 0.0, 0.0, 4650.0],
+        ["Cedar Ridge Clinic", 0.0, 0.0, 780.5, 2200.0, 2980.5],
+        ["Lakeside Pediatrics", 0.0, 615.25, 0.0, 0.0, 615.25],
+        ["Total", 3400.0, 1865.25, 780.5, 2200.0, 8245.75],
+    ]));
+}
+
+#[tokio::test]
+async fn deleted_transactions_are_appended_in_the_order_deleted() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[deleted_transactions]
+spreadsheet_id = "sheet-3"
+sheet_name = "Audit"
+txn_types = ["check", "Invoice"]
+"#);
+    let audit = config.deleted_transactions.as_ref().unwrap();
+    let request = deleted_txns::request(&audit.txn_types, "2024-05-01T00:00:00").unwrap();
+    assert!(request.contains("<TxnDelType>Check</TxnDelType>"));
+    assert!(request.contains("<FromDeletedDate>2024-05-01T00:00:00</FromDeletedDate>"));
+
+    let rows = deleted_txns::parse(&fixture("TxnDeletedQueryRs.xml")).unwrap();
+    assert_eq!(rows.len(), 2);
+    let ctx = AppContext::new(&config);
+    let client = ctx.sheets(&audit.spreadsheet_id);
+    for row in &rows {
+        client.append_row(row, &audit.sheet_name, None).await.unwrap();
+    }
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 2);
+    assert_eq!(posts[0]["mode"], "append");
+    assert_eq!(posts[0]["rowValues"], json!(["2024-05-02 16:41:30", "Invoice", "INV-2291", "1A31-1714600000", "2024-04-29 11:02:45"]));
+    assert_eq!(posts[1]["rowValues"][1], "Check");
+}
+
+#[tokio::test]
+async fn writes_carry_an_idempotency_key_without_correlation_ids() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config("");
+    let client = AppContext::new(&config).sheets("sheet-1");
+    let row = [json!("2024-06-01"), json!(45210.37)];
+    correlation::in_run(false, async {
+        correlation::in_block(client.append_row(&row, "History", None)).await.unwrap();
+        correlation::in_block(client.append_row(&row, "History", None)).await.unwrap();
+    }).await;
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 2);
+    assert!(posts[0].get("runId").is_none() && posts[0].get("requestId").is_none());
+    let keys: Vec<Vec<&str>> = posts.iter().map(|p| p["idempotencyKey"].as_str().expect("idempotency key").split(':').collect()).collect();
+    // cycle:block:hash; the same row from two blocks of one cycle is two writes
+    assert_eq!(keys[0].len(), 3);
+    assert_eq!(keys[0][0], keys[1][0]);
+    assert_ne!(keys[0][1], keys[1][1]);
+    assert_eq!(keys[0][2], keys[1][2]);
+}
+
+#[tokio::test]
+async fn appends_queued_while_offline_are_sent_next_cycle() {
+    let webapp = MockWebapp::start().await;
+    let mut config = webapp.config("");
+    config.google_sheets.offline_queue = Some(webapp.temp_path("queue.jsonl").display().to_string());
+    // a port nothing listens on stands in for a dropped internet connection
+    let mut offline = config.clone();
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    offline.google_sheets.webapp_url = format!("http://{}/exec", closed);
+
+    let offline_ctx = AppContext::new(&offline);
+    let client = offline_ctx.sheets("sheet-9");
+    client.append_row(&[json!("2024-06-01"), json!(100.5)], "History", None).await.unwrap();
+    client.append_row(&[json!("2024-06-02"), json!(101.5)], "History", None).await.unwrap();
+    let queued = offline_ctx.offline_queue.as_ref().unwrap().entries().unwrap();
+    assert_eq!(queued.len(), 2);
+    assert!(queued[0].body.get("apiKey").is_none(), "the API key isn't written to disk");
+
+    let ctx = AppContext::new(&config);
+    offline_queue::flush(&ctx).await;
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 2);
+    assert_eq!(posts[0]["rowValues"], json!(["2024-06-01", 100.5]));
+    assert_eq!(posts[1]["rowValues"], json!(["2024-06-02", 101.5]));
+    assert_eq!(posts[0]["apiKey"], "test-key");
+    assert!(ctx.offline_queue.as_ref().unwrap().entries().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn append_retention_trims_and_thins_the_history_tab() {
+    let webapp = MockWebapp::start().await;
+    let day = |ago: u64| (chrono::Local::now().date_naive() - chrono::Days::new(ago)).format("%Y-%m-%d").to_string();
+    let history = json!([
+        ["Date", "Balance"],
+        [format!("{} 09:00", day(40)), "90"],
+        [format!("{} 08:00", day(10)), "100"],
+        [format!("{} 18:00", day(10)), "110.5"],
+        [format!("{} 08:00", day(2)), "120"],
+        [format!("{} 18:00", day(2)), "130"],
+    ]);
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "mode": "read" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "success": true, "rows": history })))
+        .with_priority(1)
+        .mount(&webapp.server)
+        .await;
+    let mut config = webapp.config(r#"
+[[sync_blocks]]
+account_full_name = "Cash Accounts:Operating Checking"
+spreadsheet_id = "sheet-1"
+sheet_name = "History"
+mode = "append"
+retention = { keep_days = 30, daily_after_days = 7 }
+"#);
+    config.google_sheets.confirm_destructive = Some(true);
+    let block = &config.sync_blocks[0];
+    let ctx = AppContext::new(&config);
+    let index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), Default::default()).unwrap();
+    let account = accounts::find_account(&index, &block.account_full_name).unwrap();
+    let value = destinations::sync_value(block, account, &config).unwrap();
+    for destination in destinations::for_sync_block(block, &ctx).unwrap() {
+        destination.write_value(&value).await.unwrap();
+    }
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.iter().map(|p| p["mode"].as_str().unwrap()).collect::<Vec<_>>(), ["append", "read", "range"]);
+    // the 40-day-old row is gone and the 10-day-old day keeps only its last row
+    assert_eq!(posts[2]["cellAddress"], "A2");
+    assert_eq!(posts[2]["clearBelow"], true);
+    assert_eq!(posts[2]["confirmDestructive"], true);
+    assert_eq!(posts[2]["rows"], json!([
+        [format!("{} 18:00", day(10)), 110.5],
+        [format!("{} 08:00", day(2)), 120],
+        [format!("{} 18:00", day(2)), 130],
+    ]));
+
+    let error = Config::load(webapp.config_file(r#"
+[[sync_blocks]]
+account_full_name = "Cash"
+spreadsheet_id = "sheet-1"
+sheet_name = "Dashboard"
+cell_address = "B2"
+retention = { keep_days = 30 }
+"#), None, &[]).unwrap_err();
+    assert!(format!("{:#}", error).contains("only applies to append mode"), "{:#}", error);
+}
+
+#[tokio::test]
+async fn jsonl_destination_writes_a_record_per_sync() {
+    let webapp = MockWebapp::start().await;
+    Mock::given(method("PUT")).respond_with(ResponseTemplate::new(200)).mount(&webapp.server).await;
+    let lake = webapp.temp_path("lake/balances.jsonl");
+    let config = webapp.config(&format!(r#"
+[s3]
+access_key_id = "AKIDEXAMPLE"
+secret_access_key = "secret"
+
+[[sync_blocks]]
+name = "Operating"
+account_full_name = "Cash Accounts:Operating Checking"
+destinations = [
+    {{ type = "jsonl", path = '{}', rotate = "never" }},
+    {{ type = "jsonl", url = "{}" }},
+]
+"#, lake.display(), webapp.url("/lake/qb/")));
+    let block = &config.sync_blocks[0];
+    let ctx = AppContext::new(&config);
+    let index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), Default::default()).unwrap();
+    let account = accounts::find_account(&index, &block.account_full_name).unwrap();
+    let value = destinations::sync_value(block, account, &config).unwrap();
+    for destination in destinations::for_sync_block(block, &ctx).unwrap() {
+        destination.write_value(&value).await.unwrap();
+    }
+
+    let text = std::fs::read_to_string(&lake).unwrap();
+    let lines: Vec<Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!((&lines[0]["entity_type"], &lines[0]["block"]), (&json!("account"), &json!("Operating")));
+    assert_eq!(lines[0]["account"], "Cash Accounts:Operating Checking");
+    // the bucket gets the same record as an object of its own, signed with [s3]
+    let requests = webapp.server.received_requests().await.unwrap();
+    let put = requests.iter().find(|r| r.method.as_str() == "PUT").expect("a PUT to the bucket");
+    assert!(put.url.path().starts_with("/lake/qb/") && put.url.path().ends_with("-Operating.jsonl"), "{}", put.url);
+    let authorization = put.headers.get("authorization").unwrap().to_str().unwrap();
+    assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"), "{}", authorization);
+    assert_eq!(serde_json::from_slice::<Value>(&put.body).unwrap(), lines[0]);
+}
+
+#[tokio::test]
+async fn jsonl_export_writes_a_record_per_list_row() {
+    let webapp = MockWebapp::start().await;
+    let lake = webapp.temp_path("lake/customers.jsonl");
+    let config = webapp.config(&format!(r#"
+[[list_blocks]]
+list_type = "Customer"
+spreadsheet_id = "sheet-4"
+sheet_name = "Customers"
+cell_address = "A1"
+jsonl = {{ path = '{}', rotate = "never" }}
+"#, lake.display()));
+    let block = &config.list_blocks[0];
+    let headers = block.list_type.headers(false, false, false, false);
+    let mut rows = Vec::new();
+    lists::for_each_row(block.list_type, &fixture("CustomerQueryRs.xml"), Default::default(), false, false, false, None, false, |row| {
+        rows.push(row);
+        Ok(())
+    }).unwrap();
+    let jsonl = JsonlRows::new(block.jsonl.as_ref().unwrap(), reqwest::Client::new(), None, block.label().to_string(), "Customer").unwrap();
+    jsonl.write(&headers, &rows).await.unwrap();
+
+    let text = std::fs::read_to_string(&lake).unwrap();
+    let lines: Vec<Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!((&lines[0]["entity_type"], &lines[0]["block"]), (&json!("Customer"), &json!("Customer")));
+    assert_eq!(lines[0]["record"]["Name"], "Blue Heron Dental");
+    assert_eq!(lines[1]["record"]["Phone"], "555-0199");
+
+    // a block export needs exactly one place to go
+    let path = webapp.config_file(r#"
+[[list_blocks]]
+list_type = "Customer"
+spreadsheet_id = "sheet-4"
+sheet_name = "Customers"
+cell_address = "A1"
+jsonl = { rotate = "never" }
+"#);
+    assert!(format!("{:#}", Config::load(&path, None, &[]).unwrap_err()).contains("jsonl needs either path or url"));
+}
+
+#[tokio::test]
+async fn large_range_is_split_across_posts() {
+    let webapp = MockWebapp::start().await;
+    let mut config = webapp.config("");
+    config.google_sheets.confirm_destructive = Some(true);
+    config.google_sheets.max_rows_per_write = Some(2);
+    let ctx = AppContext::new(&config);
+    let rows: Vec<Vec<Value>> = (1..=5).map(|i| vec![json!(i)]).collect();
+    ctx.sheets("sheet-5").write_range(&rows, "Data", "B3", true, Some("Data")).await.unwrap();
+
+    let posts = webapp.webapp_posts().await;
+    let anchors: Vec<&Value> = posts.iter().map(|p| &p["cellAddress"]).collect();
+    assert_eq!(anchors, [&json!("B3"), &json!("B5"), &json!("B7")]);
+    // only the first post clears and names the range, which spans all five rows
+    assert_eq!(posts[0]["clearBelow"], true);
+    assert_eq!(posts[0]["namedRangeRows"], 5);
+    assert_eq!(posts[1]["clearBelow"], false);
+    assert!(posts[1].get("namedRange").is_none());
+}
+
+#[tokio::test]
+async fn unconfirmed_clear_below_only_previews_what_it_would_remove() {
+    let webapp = MockWebapp::start().await;
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "previewClear": true })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "success": true, "wouldClear": 2, "clearPreview": [["old", 1]] })))
+        .with_priority(1)
+        .mount(&webapp.server)
+        .await;
+    // safe mode is the default for a version 2 file
+    let mut config = webapp.config("");
+    assert_eq!(config.google_sheets.confirm_destructive, None);
+    config.google_sheets.max_rows_per_write = Some(2);
+    let ctx = AppContext::new(&config);
+    let rows: Vec<Vec<Value>> = (1..=3).map(|i| vec![json!(i)]).collect();
+    ctx.sheets("sheet-5").write_range(&rows, "Data", "B3", true, None).await.unwrap();
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 2);
+    // nothing is cleared; the post ending the block asks what is below it
+    assert_eq!((&posts[0]["clearBelow"], posts[0].get("previewClear")), (&json!(false), None));
+    assert_eq!((&posts[1]["clearBelow"], &posts[1]["previewClear"]), (&json!(false), &json!(true)));
+    assert!(posts.iter().all(|post| post.get("confirmDestructive").is_none()));
+}
+
+#[tokio::test]
+async fn configs_from_before_safe_mode_keep_removing_rows() {
+    let webapp = MockWebapp::start().await;
+    let load = |version: &str, extra: &str| {
+        let path = webapp.temp_path("old.toml");
+        std::fs::write(&path, format!("{}sync_blocks = []\ntimestamp_blocks = []\n\n[quickbooks]\ncompany_file = \"AUTO\"\n\n[google_sheets]\nwebapp_url = '{}'\napi_key = \"test-key\"\n{}",
+            version, webapp.url("/exec"), extra)).unwrap();
+        Config::load(path, None, &[]).unwrap().google_sheets.confirm_destructive
+    };
+    assert_eq!(load("version = 1\n", ""), Some(true));
+    assert_eq!(load("", ""), Some(true));
+    assert_eq!(load("version = 1\n", "confirm_destructive = false\n"), Some(false));
+    assert_eq!(load("version = 2\n", ""), None);
+}
+
+#[tokio::test]
+async fn write_concurrency_caps_webapp_requests_in_flight() {
+    let webapp = MockWebapp::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "success": true })).set_delay(std::time::Duration::from_millis(200)))
+        .with_priority(1)
+        .mount(&webapp.server)
+        .await;
+    let config = webapp.config("\n[pipeline]\nwrite_concurrency = 2\n");
+    let ctx = AppContext::new(&config);
+    let sheets = ctx.sheets("sheet-1");
+    let cells = ["A1", "A2", "A3", "A4"];
+    let started = std::time::Instant::now();
+    let writes = cells.iter().map(|cell| sheets.send_string("x", "Data", cell));
+    for result in futures::future::join_all(writes).await {
+        result.unwrap();
+    }
+    // two at a time: two rounds of the webapp's 200ms rather than one
+    assert!(started.elapsed() >= std::time::Duration::from_millis(400), "{:?}", started.elapsed());
+    assert_eq!(webapp.webapp_posts().await.len(), 4);
+}
+
+#[tokio::test]
+async fn failing_spreadsheet_is_isolated_from_the_others() {
+    let webapp = MockWebapp::start().await;
+    Mock::given(method("POST"))
+        .and(body_partial_json(json!({ "spreadsheetId": "revoked" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "success": false, "error": "You do not have permission to access the requested document." })))
+        .with_priority(1)
+        .mount(&webapp.server)
+        .await;
+    let mut config = webapp.config("");
+    config.google_sheets.isolate_after = Some(2);
+    let ctx = AppContext::new(&config);
+    let (revoked, healthy) = (ctx.sheets("revoked"), ctx.sheets("sheet-1"));
+    for _ in 0..3 {
+        assert!(revoked.send_string("x", "Dashboard", "A1").await.is_err());
+        healthy.send_string("x", "Dashboard", "A1").await.unwrap();
+    }
+
+    // the third write to the revoked spreadsheet never went out
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.iter().filter(|p| p["spreadsheetId"] == "revoked").count(), 2);
+    assert_eq!(posts.iter().filter(|p| p["spreadsheetId"] == "sheet-1").count(), 3);
+    let error = revoked.send_string("x", "Dashboard", "A1").await.unwrap_err();
+    assert!(format!("{:#}", error).contains("skipped for the rest of this cycle"), "{:#}", error);
+    assert_eq!(ctx.sheet_faults.isolated().len(), 1);
+
+    // the next cycle tries it again
+    ctx.sheet_faults.reset();
+    assert!(revoked.send_string("x", "Dashboard", "A1").await.is_err());
+    assert_eq!(webapp.webapp_posts().await.len(), 6);
+}
+
+#[tokio::test]
+async fn staged_range_is_promoted_in_one_write() {
+    let webapp = MockWebapp::start().await;
+    let mut config = webapp.config("");
+    config.google_sheets.confirm_destructive = Some(true);
+    config.google_sheets.max_rows_per_write = Some(2);
+    let ctx = AppContext::new(&config);
+    let client = ctx.sheets("sheet-5").creating_sheet(true, Default::default());
+    let staging = google_sheets::staging_sheet_name("pnl", "P&L");
+    assert_eq!(staging, "_staging pnl P&L");
+    let rows: Vec<Vec<Value>> = vec![vec![json!("Income"), json!(100)], vec![json!("Rent")], vec![json!("Net"), json!(60)]];
+    client.stage_range(&rows, &staging, "A2").await.unwrap();
+    client.promote(&staging, "P&L", "A2", rows.len(), 2, true, Some("pnl")).await.unwrap();
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 3);
+    // the staging tab is the webapp's to add; create_sheet_if_missing is for the promoted sheet
+    for post in &posts[..2] {
+        assert_eq!(post["sheetName"], staging.as_str());
+        assert_eq!(post["staged"], true);
+        assert_eq!(post["clearBelow"], false);
+        assert!(post.get("createSheet").is_none() && post.get("namedRange").is_none());
+    }
+    assert_eq!(posts[2]["mode"], "promote");
+    assert_eq!(posts[2]["sheetName"], "P&L");
+    assert_eq!(posts[2]["stagingSheet"], staging.as_str());
+    assert_eq!((&posts[2]["rowCount"], &posts[2]["width"]), (&json!(3), &json!(2)));
+    assert_eq!((&posts[2]["clearBelow"], &posts[2]["namedRange"], &posts[2]["createSheet"]), (&json!(true), &json!("pnl"), &json!(true)));
+}
+
+#[test]
+fn company_preferences_are_read_with_the_host() {
+    let info = host::parse(&fixture("HostQueryRs.xml")).unwrap();
+    assert_eq!(info.company_name, "Example Builders");
+    assert_eq!(info.fiscal_year_start_month, 7);
+    let prefs = info.preferences.as_ref().expect("preferences in fixture");
+    assert!(!prefs.multicurrency);
+    assert_eq!(prefs.report_basis, "Accrual");
+    // home currency is only shown for multicurrency files
+    assert_eq!(host::settings(&info), vec![
+        ("Multicurrency", "Off".to_string()),
+        ("Report basis", "Accrual".to_string()),
+        ("Class tracking", "On".to_string()),
+        ("Account numbers", "On".to_string()),
+    ]);
+    assert!(host::rows(&info).contains(&vec![json!("Report basis"), json!("Accrual")]));
+}
+
+#[test]
+fn accountant_copy_restriction_is_detected_and_can_abort() {
+    let info = host::parse(&fixture("HostQueryRs-accountant-copy.xml")).unwrap();
+    assert_eq!(info.file_mode, "MultiUser");
+    assert_eq!(info.restriction(), Some(host::Restriction::DividingDate("2024-12-31".to_string())));
+    assert_eq!(host::parse(&fixture("HostQueryRs.xml")).unwrap().restriction(), None);
+
+    let qb_cfg = |mode: &str| serde_json::from_value::<QuickBooksConfig>(json!({ "company_file": "", "restricted_mode": mode })).unwrap();
+    assert!(host::check(&info, &qb_cfg("warn")).is_ok());
+    let err = host::check(&info, &qb_cfg("abort")).unwrap_err().to_string();
+    assert!(err.contains("2024-12-31"), "{}", err);
+
+    // skip_write_back leaves out requests that would change the file
+    assert!(raw_qbxml::writes_to_quickbooks(&qbxml::envelope("<JournalEntryAddRq></JournalEntryAddRq>")).unwrap());
+    assert!(!raw_qbxml::writes_to_quickbooks(&qbxml::envelope("<ItemQueryRq></ItemQueryRq>")).unwrap());
+}
+
+#[test]
+fn integrity_check_stops_on_an_incomplete_account_query() {
+    let error = integrity::check(&fixture("IntegrityCheckRs-damaged.xml"), Default::default()).unwrap_err();
+    let message = format!("{:#}", error);
+    assert!(message.contains("status 500"), "{}", message);
+    assert!(message.contains("Verify Data"), "{}", message);
+}
+
+#[test]
+fn quickbooks_error_status_is_reported_with_its_meaning() {
+    let error = accounts::index_accounts(&fixture("AccountQueryRs-permission.xml"), Default::default()).unwrap_err();
+    let message = format!("{:#}", error);
+    assert!(message.contains("error 3260"), "{}", message);
+    assert!(message.contains("lacks permission"), "{}", message);
+}
+
+#[tokio::test]
+async fn mock_backend_answers_from_the_fixtures() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[[report_blocks]]
+name = "pnl"
+report_type = "ProfitAndLossStandard"
+spreadsheet_id = "sheet-2"
+sheet_name = "P&L"
+cell_address = "A1"
+"#);
+    let processor = MockProcessor::new(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures"));
+    processor.open_connection("", "test").unwrap();
+    let ticket = processor.begin_session("", FileMode::DoNotCare).unwrap();
+
+    let response = processor.get_account_xml(&ticket).unwrap().unwrap();
+    let index = accounts::index_accounts(&response, Default::default()).unwrap();
+    assert_eq!(index.len(), accounts::index_accounts(&fixture("AccountQueryRs.xml"), Default::default()).unwrap().len());
+
+    // a report query is answered by its report type
+    let request = reports::report_request(&config.report_blocks[0], chrono::Local::now().date_naive()).unwrap();
+    let table = reports::parse_for_block(&config.report_blocks[0], &processor.process_request(&ticket, &request).unwrap(), Default::default()).unwrap();
+    assert_eq!(table.title, "Profit & Loss");
+
+    // several request elements get their responses in one envelope
+    let response = processor.process_request(&ticket, &qbxml::envelope("<HostQueryRq></HostQueryRq>\n<CustomerQueryRq></CustomerQueryRq>")).unwrap();
+    let doc = qbxml::parse_document(&response).unwrap();
+    qbxml::find_response(&doc, "HostQueryRs").unwrap();
+    qbxml::find_response(&doc, "CustomerQueryRs").unwrap();
+
+    let error = processor.process_request(&ticket, &qbxml::envelope("<InvoiceQueryRq></InvoiceQueryRq>")).unwrap_err();
+    assert!(format!("{:#}", error).contains("InvoiceQueryRs.xml"), "{:#}", error);
+}
+
+#[test]
+fn recorded_run_replays_without_quickbooks() {
+    let dir = std::env::temp_dir().join(format!("qb-capture-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let invoices = qbxml::envelope("<InvoiceQueryRq></InvoiceQueryRq>");
+
+    let recording: &'static capture::Capture = Box::leak(Box::new(capture::open(capture::Mode::Record, &dir).unwrap()));
+    let processor = recording.recorder(Box::new(MockProcessor::new("fixtures")));
+    let accounts = processor.get_account_xml("ticket").unwrap().unwrap();
+    processor.process_request("ticket", &qbxml::envelope("<CustomerQueryRq></CustomerQueryRq>")).unwrap();
+    assert!(processor.process_request("ticket", &invoices).is_err());
+    // a directory already holding a capture isn't recorded over
+    assert!(capture::open(capture::Mode::Record, &dir).is_err());
+
+    let replaying: &'static capture::Capture = Box::leak(Box::new(capture::open(capture::Mode::Replay, &dir).unwrap()));
+    let replay = replaying.replayer();
+    // answered by request element, so a request whose dates moved still finds its response
+    assert_eq!(replay.get_account_xml("replay").unwrap().unwrap(), accounts);
+    assert_eq!(replay.process_request("replay", &qbxml::envelope("<CustomerQueryRq>\n</CustomerQueryRq>")).unwrap(), fixture("CustomerQueryRs.xml"));
+    // each recorded response is used once; a failed request left nothing to replay
+    assert!(replay.get_account_xml("replay").is_err());
+    assert!(replay.process_request("replay", &invoices).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn circuit_breaker_opens_after_failures_in_a_row() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[quickbooks.circuit_breaker]
+failures = 2
+max_request_kb = 1
+"#);
+    let processor = circuit::guard(Box::new(MockProcessor::new(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures"))), &config);
+    let ticket = processor.begin_session("", FileMode::DoNotCare).unwrap();
+    let host = qbxml::envelope("<HostQueryRq></HostQueryRq>");
+
+    // an oversized request is refused without reaching QuickBooks or counting against it
+    let padded = qbxml::envelope(&format!("<HostQueryRq></HostQueryRq><!--{}-->", " ".repeat(2048)));
+    assert!(processor.process_request(&ticket, &padded).unwrap_err().to_string().contains("max_request_kb"));
+
+    // no InvoiceQueryRs fixture, so these fail; a success in between starts the count again
+    let missing = qbxml::envelope("<InvoiceQueryRq></InvoiceQueryRq>");
+    processor.process_request(&ticket, &missing).unwrap_err();
+    processor.process_request(&ticket, &host).unwrap();
+    processor.process_request(&ticket, &missing).unwrap_err();
+    assert!(circuit::take_opened(&config).is_none());
+    processor.process_request(&ticket, &missing).unwrap_err();
+
+    let refused = processor.process_request(&ticket, &host).unwrap_err().to_string();
+    assert!(refused.contains("circuit breaker is open"), "{}", refused);
+    assert!(circuit::take_opened(&config).unwrap().contains("2 failed calls in a row"));
+    // announced once per opening
+    assert!(circuit::take_opened(&config).is_none());
+}
+
+#[tokio::test]
+async fn repeated_query_is_answered_from_the_cache() {
+    let webapp = MockWebapp::start().await;
+    let mut config = webapp.config("");
+    config.quickbooks.cache_secs = Some(300);
+    let dir = webapp.temp_path("responses");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("HostQueryRs.xml"), fixture("HostQueryRs.xml")).unwrap();
+    let processor = response_cache::wrap(Box::new(MockProcessor::new(&dir)), &config);
+    let ticket = processor.begin_session("", FileMode::DoNotCare).unwrap();
+    let host = qbxml::envelope("<HostQueryRq></HostQueryRq>");
+    let first = processor.process_request(&ticket, &host).unwrap();
+
+    // with the recorded response gone, only the identical request still gets an answer
+    std::fs::remove_file(dir.join("HostQueryRs.xml")).unwrap();
+    assert_eq!(processor.process_request(&ticket, &host).unwrap(), first);
+    let other = qbxml::envelope("<HostQueryRq><IncludeRetElement>ProductName</IncludeRetElement></HostQueryRq>");
+    processor.process_request(&ticket, &other).unwrap_err();
+}
+
+#[tokio::test]
+async fn reconciliation_flags_a_balance_the_bank_disagrees_with() {
+    let webapp = MockWebapp::start().await;
+    Mock::given(method("GET"))
+        .and(path("/bank/balance"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "accounts": [{ "available": "$45,200.37" }] })))
+        .mount(&webapp.server)
+        .await;
+    let config = webapp.config(&format!(r#"
+[[reconciliation_blocks]]
+name = "Checking vs bank"
+account_full_name = "Cash Accounts:Operating Checking"
+spreadsheet_id = "sheet-1"
+sheet_name = "Recon"
+cell_address = "B2"
+write_amounts = true
+source = {{ type = "http", url = "{}", pointer = "/accounts/0/available" }}
+
+[[reconciliation_blocks]]
+name = "Checking within 10"
+account_full_name = "Cash Accounts:Operating Checking"
+tolerance = 10.0
+spreadsheet_id = "sheet-1"
+sheet_name = "Recon"
+cell_address = "B3"
+source = {{ type = "http", url = "{}", pointer = "/accounts/0/available" }}
+"#, webapp.url("/bank/balance"), webapp.url("/bank/balance")));
+    let ctx = AppContext::new(&config);
+    let index = accounts::index_accounts(&fixture("AccountQueryRs.xml"), config.quickbooks.number_format().unwrap()).unwrap();
+
+    let mut statuses = Vec::new();
+    for block in &config.reconciliation_blocks {
+        let books = reconcile::books_balance(block, &index, &config).unwrap();
+        let result = reconcile::reconcile(block, books, &ctx).await.unwrap();
+        assert_eq!(result.difference().to_string(), "10.00");
+        statuses.push(result.matches());
+        reconcile::write(block, &result, &ctx).await.unwrap();
+    }
+    assert_eq!(statuses, [false, true]);
+
+    let posts = webapp.webapp_posts().await;
+    assert_eq!(posts.len(), 3);
+    assert_eq!(posts[0]["stringValue"], "MISMATCH");
+    assert_eq!(posts[0]["background"], "#f4cccc");
+    assert_eq!(posts[1]["cellAddress"], "C2");
+    assert_eq!(posts[1]["rows"], json!([[45210.37, 45200.37, 10.0]]));
+    assert_eq!(posts[2]["stringValue"], "MATCH");
+    assert_eq!(posts[2]["cellAddress"], "B3");
+}
+
+#[tokio::test]
+async fn point_of_sale_block_reads_store_stock_and_sales() {
+    let webapp = MockWebapp::start().await;
+    let config = webapp.config(r#"
+[point_of_sale]
+connection_string = "Computer Name=STORE-PC;Company Data=Test Store;Version=12"
+
+[[raw_qbxml_blocks]]
+name = "store"
+point_of_sale = true
+request = "<ItemInventoryQueryRq></ItemInventoryQueryRq><SalesReceiptQueryRq></SalesReceiptQueryRq>"
+spreadsheet_id = "sheet-1"
+sheet_name = "Store"
+
+[[raw_qbxml_blocks.fields]]
+xpath = "sum(ItemInventoryQueryRs/ItemInventoryRet/OnHandStore01)"
+cell_address = "B1"
+
+[[raw_qbxml_blocks.fields]]
+xpath = "sum(SalesReceiptQueryRs/SalesReceiptRet/Total)"
+cell_address = "B2"
+"#);
+    assert!(!config.needs_quickbooks());
+    let block = &config.raw_qbxml_blocks[0];
+    let request = raw_qbxml::request(block).unwrap();
+    assert!(request.contains("<?qbposxml version="), "{}", request);
+
+    let processor = MockProcessor::new(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join("pos"));
+    let response_xml = processor.process_request("ticket", &request).unwrap();
+    raw_qbxml::check_status(&response_xml).unwrap();
+    let response = extract::Response::parse(&response_xml).unwrap();
+    let values: Vec<Value> = block.field_mappings().unwrap().iter().map(|m| response.values(&m.xpath).unwrap()[0].clone()).collect();
+    assert_eq!(values, vec![json!(61.0), json!(51.38)]);
+}
+
+// One raw HTTP/1.1 exchange with the dashboard, so the test controls Host and Origin
+async fn dashboard_exchange(address: std::net::SocketAddr, request: String) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+fn dashboard_post(host: &str, origin: Option<&str>, body: &str) -> String {
+    let origin = origin.map(|o| format!("Origin: {}\r\n", o)).unwrap_or_default();
+    format!(
+        "POST /run HTTP/1.1\r\nHost: {}\r\n{}Content-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+        host, origin, body.len(), body,
+    )
+}
+
+async fn start_dashboard(webapp: &MockWebapp) -> (std::net::SocketAddr, tokio::sync::mpsc::Receiver<local_api::Call>) {
+    let config = webapp.config(r#"
+[dashboard]
+listen = "127.0.0.1:0"
+
+[[sync_blocks]]
+name = "Cash"
+spreadsheet_id = "sheet-1"
+account_full_name = "Cash Accounts:Operating Checking"
+sheet_name = "Balances"
+cell_address = "B2"
+"#);
+    let (calls, queue) = local_api::queue();
+    let address = dashboard::start(config.dashboard.as_ref().unwrap(), &config, calls).await.unwrap();
+    (address, queue)
+}
+
+#[tokio::test]
+async fn dashboard_refuses_foreign_hosts_origins_and_tokens() {
+    let webapp = MockWebapp::start().await;
+    let (address, mut queue) = start_dashboard(&webapp).await;
+    let (local, evil) = (format!("127.0.0.1:{}", address.port()), format!("evil.example:{}", address.port()));
+
+    let page = dashboard_exchange(address, format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", local)).await;
+    assert!(page.starts_with("HTTP/1.1 200"));
+    let token = page.split(r#"name="token" value=""#).nth(1).and_then(|rest| rest.split('"').next()).expect("token in the page");
+    let localhost = format!("localhost:{}", address.port());
+    assert!(dashboard_exchange(address, format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", localhost)).await.starts_with("HTTP/1.1 200"));
+
+    // a rebound name: Host and Origin agree with each other but not with the dashboard
+    let rebound = dashboard_exchange(address, format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", evil)).await;
+    assert!(rebound.starts_with("HTTP/1.1 403") && !rebound.contains(token));
+    let valid = format!("token={}&block=Cash", token);
+    let local_origin = format!("http://{}", local);
+    for request in [
+        dashboard_post(&local, None, &valid),
+        dashboard_post(&local, Some("http://evil.example"), &valid),
+        dashboard_post(&local, Some(&local_origin), "block=Cash"),
+        dashboard_post(&local, Some(&local_origin), "token=0123&block=Cash"),
+        dashboard_post(&evil, Some(&format!("http://{}", evil)), &valid),
+    ] {
+        let response = dashboard_exchange(address, request.clone()).await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}\n=> {}", request, response);
+    }
+    assert!(queue.try_recv().is_err());
+
+    // a request line without an end is dropped once it passes the cap, unanswered
+    // (closing with unread input may surface as a reset rather than a clean end)
+    {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let _ = stream.write_all(format!("GET /{}", "a".repeat(64 * 1024)).as_bytes()).await;
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        assert!(response.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn dashboard_run_button_queues_the_sync_block() {
+    let webapp = MockWebapp::start().await;
+    let (address, mut queue) = start_dashboard(&webapp).await;
+    let local = format!("127.0.0.1:{}", address.port());
+    let page = dashboard_exchange(address, format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", local)).await;
+    let token = page.split(r#"name="token" value=""#).nth(1).and_then(|rest| rest.split('"').next()).unwrap().to_string();
+
+    let daemon = tokio::spawn(async move {
+        let call = queue.recv().await.expect("a queued call");
+        let name = match &call.request {
+            local_api::Request::RunSyncBlock { name } => name.clone(),
+            _ => panic!("expected RunSyncBlock"),
+        };
+        call.respond(Ok(json!([])));
+        name
+    });
+    let response = dashboard_exchange(address, dashboard_post(&local, Some(&format!("http://{}", local)), &format!("token={}&block=Cash", token))).await;
+    assert!(response.starts_with("HTTP/1.1 303"), "{}", response);
+    assert_eq!(daemon.await.unwrap(), "Cash");
+}